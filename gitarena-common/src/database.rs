@@ -4,11 +4,14 @@ use std::str::FromStr;
 use std::time::Duration;
 
 use anyhow::{anyhow, bail, Context, Result};
+use log::info;
 use once_cell::sync::OnceCell;
 use sqlx::{Executor, Postgres};
 use tokio::fs;
 
 pub mod models;
+pub mod privileges;
+pub mod quota;
 
 // These are all type aliased to allow for compile time switching of database backend in the future
 use sqlx::postgres::{PgConnectOptions, PgDatabaseError};
@@ -24,9 +27,28 @@ pub type PoolOptions = sqlx::pool::PoolOptions<Database>;
 pub async fn create_postgres_pool(module: &'static str, max_conns: Option<u32>) -> Result<Pool> {
     static ONCE: OnceCell<String> = OnceCell::new();
 
+    let max_connections = max_conns.ok_or(()).or_else(|_| get_max_connections())?;
+    let min_connections = read_env::<u32>("MIN_POOL_CONNECTIONS")?.unwrap_or(0).min(max_connections);
+    let acquire_timeout = Duration::from_secs(read_env::<u64>("POOL_ACQUIRE_TIMEOUT")?.unwrap_or(10));
+    let idle_timeout = optional_duration(read_env::<u64>("POOL_IDLE_TIMEOUT")?.unwrap_or(600));
+    let max_lifetime = optional_duration(read_env::<u64>("POOL_MAX_LIFETIME")?.unwrap_or(1800));
+
+    info!(
+        "Connecting to Postgres with {}-{} connections (acquire timeout: {}s, idle timeout: {}, max lifetime: {})",
+        min_connections,
+        max_connections,
+        acquire_timeout.as_secs(),
+        idle_timeout.map_or_else(|| "none".to_owned(), |timeout| format!("{}s", timeout.as_secs())),
+        max_lifetime.map_or_else(|| "none".to_owned(), |lifetime| format!("{}s", lifetime.as_secs()))
+    );
+
     Ok(PoolOptions::new()
-        .max_connections(max_conns.ok_or(()).or_else(|_| get_max_connections())?)
-        .connect_timeout(Duration::from_secs(10))
+        .max_connections(max_connections)
+        .min_connections(min_connections)
+        // sqlx uses the connect timeout as upper bound for acquiring a connection from the pool
+        .connect_timeout(acquire_timeout)
+        .idle_timeout(idle_timeout)
+        .max_lifetime(max_lifetime)
         .after_connect(move |connection| {
             Box::pin(async move {
                 // If setting the app name fails it's not a big deal if the connection is still fine so let's ignore the error
@@ -71,3 +93,17 @@ fn get_max_connections() -> Result<u32> {
         Err(VarError::NotUnicode(_)) => bail!("MAX_POOL_CONNECTIONS environment variable is not a valid unicode string")
     })
 }
+
+/// Reads an optional environment variable and parses it into `T`
+fn read_env<T: FromStr>(name: &'static str) -> Result<Option<T>> {
+    match env::var(name) {
+        Ok(env_str) => Ok(Some(env_str.parse::<T>().map_err(|_| anyhow!("Unable to parse {} environment variable", name))?)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => bail!("{} environment variable is not a valid unicode string", name)
+    }
+}
+
+/// Timeouts of zero seconds are disabled
+fn optional_duration(seconds: u64) -> Option<Duration> {
+    (seconds > 0).then(|| Duration::from_secs(seconds))
+}
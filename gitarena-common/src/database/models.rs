@@ -45,3 +45,65 @@ impl TryFrom<&str> for KeyType {
         })
     }
 }
+
+#[derive(Type, Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[sqlx(type_name = "import_state", rename_all = "lowercase")]
+#[serde(rename_all(serialize = "lowercase", deserialize = "lowercase"))]
+pub enum ImportState {
+    Queued,
+    Running,
+    Finished,
+    Failed
+}
+
+/// Access level of a collaborator, organization member or team in a repository.
+/// Variants are ordered from lowest to highest, so they can be compared to find the higher access level
+#[derive(Type, Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Deserialize, Serialize)]
+#[sqlx(type_name = "access_level", rename_all = "lowercase")]
+#[serde(rename_all(serialize = "lowercase", deserialize = "lowercase"))]
+pub enum AccessLevel {
+    Viewer,
+    Supporter,
+    Coder,
+    Manager,
+    Admin
+}
+
+impl Display for AccessLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use AccessLevel::*;
+
+        f.write_str(match self {
+            Viewer => "viewer",
+            Supporter => "supporter",
+            Coder => "coder",
+            Manager => "manager",
+            Admin => "admin"
+        })
+    }
+}
+
+// Currently all these methods are hard coded but in the future they will be configurable on a per repo/org basis
+impl AccessLevel {
+    pub fn can_view(&self) -> bool {
+        true
+    }
+
+    pub fn can_manage_issues(&self) -> bool {
+        match self {
+            AccessLevel::Viewer | AccessLevel::Coder => false,
+            AccessLevel::Supporter | AccessLevel::Manager | AccessLevel::Admin => true
+        }
+    }
+
+    pub fn can_push(&self) -> bool {
+        match self {
+            AccessLevel::Viewer | AccessLevel::Supporter => false,
+            AccessLevel::Coder | AccessLevel::Manager | AccessLevel::Admin => true
+        }
+    }
+
+    pub fn can_admin(&self) -> bool {
+        matches!(self, AccessLevel::Admin)
+    }
+}
@@ -0,0 +1,47 @@
+use crate::database::Database;
+use crate::database::models::AccessLevel;
+
+use anyhow::Result;
+use sqlx::Executor;
+
+/// Returns the highest access level user `user_id` has in repository `repo_id` owned by `repo_owner`, either granted directly
+/// or through membership in the organization owning it and its teams. Teams can only raise the access level, so the effective
+/// access level is the maximum of all of them.
+///
+/// Shared by the web server and `gitarena-ssh`, so Git over HTTPS and SSH always grant the same access
+pub async fn access_level<'e, E: Executor<'e, Database = Database>>(user_id: i32, repo_id: i32, repo_owner: i32, executor: E) -> Result<Option<AccessLevel>> {
+    // Enums are ordered by their declaration, so `greatest` and `max` pick the higher access level.
+    // Keep the organization role mapping in sync with the documentation of `OrganizationRole`
+    let (access_level,): (Option<AccessLevel>,) = sqlx::query_as("select greatest(\
+            (select access_level from privileges where user_id = $1 and repo_id = $2 limit 1), \
+            (select case role when 'owner' then 'admin'::access_level else 'viewer'::access_level end \
+                from organization_members where organization = $3 and member = $1 limit 1), \
+            (select max(teams.access_level) from teams \
+                inner join team_members on team_members.team = teams.id \
+                inner join team_repositories on team_repositories.team = teams.id \
+                where team_members.member = $1 and team_repositories.repo = $2)\
+        )")
+        .bind(&user_id)
+        .bind(&repo_id)
+        .bind(&repo_owner)
+        .fetch_one(executor)
+        .await?;
+
+    Ok(access_level)
+}
+
+/// Whether repositories owned by `repo_owner` are hidden from everyone but admins. This is the case if the owner has been
+/// disabled or banned and `users.hide_banned_repositories` is enabled.
+///
+/// Shared by the web server and `gitarena-ssh` for the same reason as [access_level]
+pub async fn is_owner_blocked<'e, E: Executor<'e, Database = Database>>(repo_owner: i32, executor: E) -> Result<bool> {
+    // Boolean settings are stored as `true`/`false` or `1`/`0`, both of which Postgres casts to boolean
+    let (blocked,): (bool,) = sqlx::query_as("select \
+            coalesce((select value from settings where key = 'users.hide_banned_repositories')::boolean, false) \
+            and coalesce((select disabled or coalesce(banned_until > now(), false) from users where id = $1), false)")
+        .bind(&repo_owner)
+        .fetch_one(executor)
+        .await?;
+
+    Ok(blocked)
+}
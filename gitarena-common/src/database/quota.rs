@@ -0,0 +1,68 @@
+use crate::database::Database;
+
+use anyhow::Result;
+use sqlx::Transaction;
+
+/// Storage used by a repository and its owner compared to their limits. Limits of zero or less are disabled
+#[derive(Debug)]
+pub struct QuotaUsage {
+    pub repo_limit: i64,
+    pub repo_size: u64,
+    pub owner_limit: i64,
+    pub owner_used: u64
+}
+
+impl QuotaUsage {
+    /// Returns why `incoming` additional bytes do not fit within the limits or `None` if they do
+    pub fn exceeded(&self, incoming: u64) -> Option<String> {
+        if self.repo_limit > 0 && self.repo_size + incoming > self.repo_limit as u64 {
+            return Some(format!("Push would exceed the repository size limit of {} bytes ({} bytes in use)", self.repo_limit, self.repo_size));
+        }
+
+        if self.owner_limit > 0 && self.owner_used + incoming > self.owner_limit as u64 {
+            return Some(format!("Push would exceed the storage quota of {} bytes ({} bytes in use)", self.owner_limit, self.owner_used));
+        }
+
+        None
+    }
+
+    /// Returns how many bytes may still be added to the repository or `None` if no limit applies
+    pub fn remaining(&self) -> Option<u64> {
+        let repo = (self.repo_limit > 0).then(|| (self.repo_limit as u64).saturating_sub(self.repo_size));
+        let owner = (self.owner_limit > 0).then(|| (self.owner_limit as u64).saturating_sub(self.owner_used));
+
+        match (repo, owner) {
+            (Some(repo), Some(owner)) => Some(repo.min(owner)),
+            (repo, owner) => repo.or(owner)
+        }
+    }
+}
+
+/// Reads the storage usage of repository `repo_id` owned by `owner`. Sizes are taken from the cached `repo_size_bytes`.
+///
+/// The row of the owner stays locked until `transaction` ends, so concurrent writes into repositories of the same owner
+/// are checked one after another instead of all passing against the same stale sizes. Update the cached size of the
+/// repository before committing `transaction`
+pub async fn usage(repo_id: i32, owner: i32, transaction: &mut Transaction<'_, Database>) -> Result<QuotaUsage> {
+    let (storage_quota,): (Option<i64>,) = sqlx::query_as("select storage_quota from users where id = $1 limit 1 for update")
+        .bind(&owner)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    let (user_max_size, repo_max_size, owner_used, repo_size): (i64, i64, i64, i64) = sqlx::query_as("select \
+            coalesce((select value from settings where key = 'quotas.user_max_size')::bigint, 0), \
+            coalesce((select value from settings where key = 'quotas.repo_max_size')::bigint, 0), \
+            (select coalesce(sum(repo_size_bytes), 0)::bigint from repositories where owner = $1), \
+            coalesce((select repo_size_bytes from repositories where id = $2), 0)::bigint")
+        .bind(&owner)
+        .bind(&repo_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    Ok(QuotaUsage {
+        repo_limit: repo_max_size,
+        repo_size: repo_size.max(0) as u64,
+        owner_limit: storage_quota.unwrap_or(user_max_size),
+        owner_used: owner_used.max(0) as u64
+    })
+}
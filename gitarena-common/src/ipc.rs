@@ -2,16 +2,19 @@ use std::io::{Read, Write};
 use std::{fmt, fs, mem};
 use std::fmt::{Debug, Display, Formatter};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bincode::config::{AllowTrailing, Bounded, LittleEndian, VarintEncoding, WithOtherEndian, WithOtherIntEncoding, WithOtherLimit, WithOtherTrailing};
 use bincode::{DefaultOptions, Options as _};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 // World's longest type, thank you
 pub type BincodeType = WithOtherTrailing<WithOtherIntEncoding<WithOtherEndian<WithOtherLimit<DefaultOptions, Bounded>, LittleEndian>, VarintEncoding>, AllowTrailing>;
 
-/// [Type-length-value](https://en.wikipedia.org/wiki/Type%E2%80%93length%E2%80%93value) packet to be used for GitArena IPC
+/// [Type-length-value](https://en.wikipedia.org/wiki/Type%E2%80%93length%E2%80%93value) packet to be used for GitArena IPC.
+///
+/// As the header is varint encoded, packets are sent over the wire prefixed with their serialized size as big endian `u64`
 #[derive(Deserialize, Serialize)]
 pub struct IpcPacket<T: ?Sized> {
     id: u64,
@@ -32,6 +35,17 @@ impl<T: Serialize + Sized + PacketId> IpcPacket<T> {
 }
 
 impl<T: Sized> IpcPacket<T> {
+    /// Packet id, can be read by deserializing into `IpcPacket<()>` to find out the actual type of a received packet
+    #[inline]
+    pub const fn id(&self) -> u64 {
+        self.id
+    }
+
+    #[inline]
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
     /// Maximum size that this struct can be serialized from (mem::size_of::<Self> + 1 MB)
     #[inline]
     pub const fn max_size() -> u64 {
@@ -95,6 +109,29 @@ pub trait PacketId {
     fn id(&self) -> u64;
 }
 
+/// Writes `data` to `writer` as a single packet, prefixed with its serialized size
+pub async fn write_packet<W: AsyncWrite + Unpin, T: Serialize + PacketId>(writer: &mut W, data: T) -> Result<()> {
+    let bytes = IpcPacket::new(data).serialize().context("Failed to serialize packet")?;
+
+    writer.write_u64(bytes.len() as u64).await.context("Failed to send packet")?;
+    writer.write_all(bytes.as_slice()).await.context("Failed to send packet")?;
+    writer.flush().await.context("Failed to send packet")
+}
+
+/// Reads a single packet written by [write_packet] from `reader`
+pub async fn read_packet<R: AsyncRead + Unpin, T: DeserializeOwned>(reader: &mut R) -> Result<T> {
+    let length = reader.read_u64().await.context("Failed to read length")?;
+
+    if length > IpcPacket::<T>::max_size() {
+        bail!("Received packet of {} bytes which exceeds the maximum of {} bytes", length, IpcPacket::<T>::max_size());
+    }
+
+    let mut payload = vec![0_u8; length as usize];
+    reader.read_exact(payload.as_mut_slice()).await.context("Failed to read payload")?;
+
+    Ok(IpcPacket::<T>::deserialize_from(payload.as_slice())?.into_data())
+}
+
 /// Cross-platform way to get the socket/pipe path.
 ///
 /// # Side effects
@@ -108,3 +145,13 @@ pub fn ipc_path() -> Result<&'static str> {
         "/run/gitarena/workhorse"
     })
 }
+
+/// Path of the Unix socket the main process listens on for Git hooks of `gitarena-ssh`.
+///
+/// # Side effects
+///
+/// This function exhibits side effects (creation of directory `/run/gitarena`)
+pub fn hooks_ipc_path() -> Result<&'static str> {
+    fs::create_dir_all("/run/gitarena").context("Failed to create directory")?;
+    Ok("/run/gitarena/hooks")
+}
@@ -1,5 +1,6 @@
 pub mod database;
 pub mod ipc;
 pub mod log;
+pub mod outbound_url;
 pub mod packets;
 pub mod prelude;
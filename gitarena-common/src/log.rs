@@ -49,18 +49,33 @@ pub fn init_logger(module: &str, directives: &'static [&str]) -> Result<Vec<Work
     Ok(guards)
 }
 
+/// Returns whenever logs written to stdout should be JSON lines instead of human readable text.
+/// Enabled by setting the `LOG_FORMAT` environment variable to `json`
+pub fn json_stdout() -> bool {
+    env::var("LOG_FORMAT").map_or(false, |format| format.eq_ignore_ascii_case("json"))
+}
+
 pub fn stdout<S: Subscriber + for<'a> LookupSpan<'a>>() -> Option<(impl layer::Layer<S>, WorkerGuard)> {
     if env::var_os("NO_STDOUT_LOG").is_some() {
         return None;
     }
 
     let (writer, guard) = tracing_appender::non_blocking(io::stdout());
+    let json = json_stdout();
 
-    let layer = Layer::new()
+    let text_layer = (!json).then(|| Layer::new()
         .with_thread_ids(true)
-        .with_writer(writer);
+        .with_writer(writer.clone()));
+
+    // Fields of the current span and all its parents (such as the ones of `#[instrument]`) are written as separate JSON objects
+    let json_layer = json.then(|| Layer::new()
+        .with_thread_ids(true)
+        .with_writer(writer)
+        .json()
+        .with_current_span(true)
+        .with_span_list(true));
 
-    Some((layer, guard))
+    Some((layer::Layer::and_then(text_layer, json_layer), guard))
 }
 
 pub fn log_file<S: Subscriber + for<'a> LookupSpan<'a>>(module: &str) -> Result<Option<(impl layer::Layer<S>, WorkerGuard)>> {
@@ -0,0 +1,81 @@
+//! Guards requests GitArena makes to user supplied urls (webhooks, repository imports and mirrors) against server-side request forgery.
+//!
+//! Hosts are resolved and rejected if any of their addresses is not publicly routable, so users can't make GitArena
+//! talk to loopback, private networks or cloud metadata endpoints. Callers need to check again right before connecting,
+//! as the records of a domain may have changed since it was saved, and must not follow redirects to unchecked hosts.
+//!
+//! Shared by the web server and `gitarena-workhorse`, which connects to the urls of imports and mirrors
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{anyhow, bail, Result};
+use tokio::net::lookup_host;
+use url::{Host, Url};
+
+/// Errors if the host of `url` resolves to an address which is [not global](is_global). The scheme needs to be checked by the caller
+pub async fn check(url: &Url) -> Result<()> {
+    // The port is irrelevant for the lookup itself
+    let port = url.port_or_known_default().unwrap_or(0);
+
+    let addresses: Vec<IpAddr> = match url.host().ok_or_else(|| anyhow!("Url needs to contain a host"))? {
+        Host::Ipv4(ip) => vec![IpAddr::V4(ip)],
+        Host::Ipv6(ip) => vec![IpAddr::V6(ip)],
+        Host::Domain(domain) => lookup_host((domain, port))
+            .await
+            .map_err(|_| anyhow!("Host {} could not be resolved", domain))?
+            .map(|address| address.ip())
+            .collect()
+    };
+
+    if addresses.is_empty() {
+        bail!("Host could not be resolved");
+    }
+
+    if addresses.into_iter().any(|ip| !is_global(ip)) {
+        bail!("Url may not point to a loopback, private or otherwise reserved address");
+    }
+
+    Ok(())
+}
+
+/// Whether `ip` is publicly routable. Follows the definition of the (unstable) `IpAddr::is_global`
+pub fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_global_v4(ip),
+            None => is_global_v6(ip)
+        }
+    }
+}
+
+fn is_global_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0 // "this network"
+        || (a == 100 && (b & 0b1100_0000) == 64) // shared address space (100.64.0.0/10)
+        || (a == 192 && b == 0 && c == 0) // IETF protocol assignments
+        || (a == 198 && (b & 0xfe) == 18) // benchmarking (198.18.0.0/15)
+        || a >= 240) // reserved
+}
+
+fn is_global_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || (segments[0] & 0xfe00) == 0xfc00 // unique local (fc00::/7)
+        || (segments[0] & 0xffc0) == 0xfe80 // link local (fe80::/10)
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8) // documentation
+        || (segments[0] == 0x0100 && segments[1..4] == [0, 0, 0]) // discard only (100::/64)
+        || (segments[0] == 0x0064 && segments[1] == 0xff9b) // IPv4/IPv6 translation, could reach any IPv4 address
+        || segments[0..6] == [0, 0, 0, 0, 0, 0]) // IPv4 compatible (deprecated)
+}
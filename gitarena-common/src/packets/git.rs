@@ -4,7 +4,13 @@ use gitarena_macros::IpcPacket;
 #[derive(Deserialize, Serialize, Debug, Default, IpcPacket)]
 #[ipc(packet = "Git", id = 1)] // = 1001
 pub struct GitImport {
+    /// Id of the `import_jobs` row to report progress to
+    pub job: i32,
     pub url: String,
     pub username: Option<String>,
-    pub password: Option<String>
+    pub password: Option<String>,
+    /// Path to the (already initialized) bare repository to import into
+    pub path: String,
+    /// Maximum size in bytes the repository may grow to during the import, the push size limit or the remaining storage quota
+    pub max_size: u64
 }
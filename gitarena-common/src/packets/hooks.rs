@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use gitarena_macros::IpcPacket;
+
+/// Sent by `gitarena-ssh` to the main process for Git operations over SSH, so they go through the same checks and
+/// side effects as the smart HTTP protocol does
+#[derive(Deserialize, Serialize, Debug, IpcPacket)]
+#[ipc(packet = "Hooks", id = 1)] // = 2001
+pub struct GitHook {
+    pub stage: HookStage,
+    pub repo: i32,
+    /// `None` if a deploy key is used
+    pub user: Option<i32>,
+    /// Directory Git stores the objects of a push in until `pre-receive` accepted it (`GIT_QUARANTINE_PATH`)
+    pub quarantine: Option<String>,
+    pub updates: Vec<HookRefUpdate>
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    /// Sent when the SSH session starts. The repository can't be moved until the connection of this stage is closed
+    Session { push: bool },
+    /// Checks the ref updates before Git applies them
+    PreReceive,
+    /// Runs everything that follows a push once the refs have been updated
+    PostReceive
+}
+
+/// Ref update as passed by Git to the `pre-receive` and `post-receive` hooks
+#[derive(Deserialize, Serialize, Debug)]
+pub struct HookRefUpdate {
+    pub old: String,
+    pub new: String,
+    pub target_ref: String
+}
+
+/// Response of the main process to a [GitHook]
+#[derive(Deserialize, Serialize, Debug, Default, IpcPacket)]
+#[ipc(packet = "Hooks", id = 2)] // = 2002
+pub struct GitHookResult {
+    pub accepted: bool,
+    /// Printed to the user, prefixed with `remote:` by Git
+    pub messages: Vec<String>
+}
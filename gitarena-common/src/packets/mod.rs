@@ -1,10 +1,12 @@
 use num_derive::{FromPrimitive, ToPrimitive};
 
 pub mod git; // 1xxx
+pub mod hooks; // 2xxx
 
 #[repr(u64)]
 pub enum PacketCategory {
-    Git = 1000
+    Git = 1000,
+    Hooks = 2000
 }
 
 // TODO: Find a way to automatically generate this
@@ -13,5 +15,7 @@ pub enum PacketCategory {
 #[repr(u64)]
 #[derive(FromPrimitive, ToPrimitive)]
 pub enum PacketId {
-    GitImport = 1001
+    GitImport = 1001,
+    GitHook = 2001,
+    GitHookResult = 2002
 }
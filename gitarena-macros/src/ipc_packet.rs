@@ -85,8 +85,6 @@ pub(crate) fn ipc_packet(input: TokenStream) -> TokenStream {
             let enum_identifier = Ident::new(uppercased_category.as_str(), Span::call_site());
 
             TokenStream::from(quote! {
-                use gitarena_macros::ipc;
-
                 impl crate::ipc::PacketId for #identifier {
                     #[inline]
                     fn id(&self) -> u64 {
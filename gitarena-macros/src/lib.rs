@@ -47,13 +47,9 @@ pub fn from_optional_config(input: TokenStream) -> TokenStream {
     internal_from_optional_config(input)
 }
 
-#[proc_macro_derive(IpcPacket)]
+// `ipc` is an inert helper attribute, so multiple packets can be derived in the same module
+#[proc_macro_derive(IpcPacket, attributes(ipc))]
 #[proc_macro_error]
 pub fn derive_ipc_packet(input: TokenStream) -> TokenStream {
     internal_ipc_packet(input)
 }
-
-#[proc_macro_attribute]
-pub fn ipc(_: TokenStream, item: TokenStream) -> TokenStream {
-    item
-}
@@ -0,0 +1,106 @@
+use std::env;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process;
+
+use anyhow::{anyhow, bail, Context, Result};
+use gitarena_common::ipc::{hooks_ipc_path, read_packet, write_packet};
+use gitarena_common::packets::hooks::{GitHook, GitHookResult, HookRefUpdate, HookStage};
+use gitarena_common::prelude::*;
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::net::UnixStream;
+
+const HOOKS: [&str; 2] = ["pre-receive", "post-receive"];
+
+/// Sends `hook` to the main process and returns its response as well as the connection, which stays open for [HookStage::Session]
+pub(crate) async fn call(hook: GitHook) -> Result<(GitHookResult, UnixStream)> {
+    let path = hooks_ipc_path()?;
+    let mut stream = UnixStream::connect(path).await.with_context(|| format!("Failed to connect to GitArena at {}", path))?;
+
+    write_packet(&mut stream, hook).await?;
+    let result = read_packet(&mut stream).await?;
+
+    Ok((result, stream))
+}
+
+/// Writes scripts for all hooks GitArena handles which invoke `gitarena-ssh hook <name>` and returns their directory.
+/// It is passed to Git as `core.hooksPath`, so hooks placed into repositories by hand are ignored
+pub(crate) fn install() -> Result<PathBuf> {
+    let executable = env::current_exe()?;
+    let dir = PathBuf::from("/run/gitarena/ssh-hooks");
+
+    fs::create_dir_all(&dir).context("Failed to create hooks directory")?;
+
+    for name in HOOKS {
+        let path = dir.join(name);
+        let script = format!("#!/bin/sh\nexec '{}' hook {}\n", executable.display(), name);
+
+        if fs::read_to_string(&path).map_or(false, |existing| existing == script) {
+            continue;
+        }
+
+        // Written next to the hook and renamed afterwards, so concurrent pushes never execute a half written script
+        let temp_path = dir.join(format!(".{}.{}", name, process::id()));
+
+        fs::write(&temp_path, script)?;
+        fs::set_permissions(&temp_path, Permissions::from_mode(0o755))?;
+        fs::rename(&temp_path, &path)?;
+    }
+
+    Ok(dir)
+}
+
+/// Executed by Git as `pre-receive` or `post-receive` hook. Forwards the ref updates read from stdin to the main process
+/// and prints its messages, which Git relays to the user. Exits with a non-zero status if the push has been rejected
+pub(crate) async fn run(name: &str) -> Result<()> {
+    let stage = match name {
+        "pre-receive" => HookStage::PreReceive,
+        "post-receive" => HookStage::PostReceive,
+        _ => bail!("Unknown hook: {}", name)
+    };
+
+    let repo = env::var("GITARENA_REPO_ID")
+        .context("Hook has not been invoked by gitarena-ssh")?
+        .parse::<i32>()?;
+    let user = match env::var("GITARENA_USER_ID") {
+        Ok(id) => Some(id.parse::<i32>()?),
+        Err(_) => None
+    };
+
+    let mut updates = Vec::new();
+    let mut lines = BufReader::new(io::stdin()).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let mut split = line.split_whitespace();
+
+        let (old, new, target_ref) = match (split.next(), split.next(), split.next()) {
+            (Some(old), Some(new), Some(target_ref)) => (old, new, target_ref),
+            _ => return Err(anyhow!("Failed to parse ref update: {}", line))
+        };
+
+        updates.push(HookRefUpdate {
+            old: old.to_owned(),
+            new: new.to_owned(),
+            target_ref: target_ref.to_owned()
+        });
+    }
+
+    let (result, _) = call(GitHook {
+        stage,
+        repo,
+        user,
+        quarantine: env::var("GIT_QUARANTINE_PATH").ok(),
+        updates
+    }).await?;
+
+    for message in result.messages.iter() {
+        eprintln!("{}", message);
+    }
+
+    if !result.accepted {
+        process::exit(1);
+    }
+
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use std::env;
+
 use anyhow::Result;
 use futures::TryStreamExt;
 use gitarena_common::database::Database;
@@ -6,15 +8,26 @@ use gitarena_common::prelude::*;
 use sqlx::{Executor, Row};
 
 pub(crate) async fn print_all<'e, E: Executor<'e, Database = Database>>(executor: E) -> Result<()> {
+    let executable = env::current_exe()?;
+
     let mut stream = sqlx::query(
-        "select algorithm, key from ssh_keys where expires_at is null or expires_at < now()"
+        "select id, algorithm, key, false as deploy_key from ssh_keys where expires_at is null or expires_at > now() \
+        union all select id, algorithm, key, true as deploy_key from deploy_keys"
     ).fetch(executor);
 
     while let Some(row) = stream.try_next().await? {
+        let id: i32 = row.try_get("id")?;
         let algorithm: KeyType = row.try_get("algorithm")?;
         let key: &[u8] = row.try_get("key")?;
+        let deploy_key: bool = row.try_get("deploy_key")?;
+
+        let flag = if deploy_key { "--deploy-key " } else { "" };
 
-        println!("{} {}", algorithm, base64::encode(key));
+        // Force every connection to go through `gitarena-ssh serve` so the key can be resolved to its owner
+        println!(
+            "command=\"{} serve {}{}\",no-port-forwarding,no-X11-forwarding,no-agent-forwarding,no-pty {} {}",
+            executable.display(), flag, id, algorithm, base64::encode(key)
+        );
     }
 
     Ok(())
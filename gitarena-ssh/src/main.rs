@@ -3,21 +3,34 @@ use clap::{Subcommand, Parser};
 use gitarena_common::database::create_postgres_pool;
 use gitarena_common::prelude::*;
 
+mod hook;
 mod keys;
+mod serve;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args: Args = Args::try_parse()?;
 
+    // Hooks only talk to the main process, so they do not need a database connection
+    if let Some(Command::Hook { name }) = &args.command {
+        return hook::run(name.as_str()).await;
+    }
+
     let db_pool = create_postgres_pool("gitarena-ssh", Some(1)).await?;
-    let mut transaction = db_pool.begin().await?;
 
     // Subcommand execution
     use Command::*;
 
+    // Serving runs Git for as long as the client is connected, so it should not hold a transaction open
+    if let Some(Serve { key_id, deploy_key }) = &args.command {
+        return serve::serve(*key_id, *deploy_key, &db_pool).await;
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
     match &args.command {
         Some(AuthorizedKeys) => keys::print_all(&mut transaction).await?,
-        _ => bail!("GitArena does currently not provide SSH access")
+        _ => bail!("GitArena does not provide shell access")
     }
 
     transaction.commit().await?;
@@ -26,9 +39,21 @@ async fn main() -> Result<()> {
 
 #[derive(Subcommand, Debug)]
 enum Command {
-    /// Prints out all non-expired SSH keys added by all GitArena users.
+    /// Prints out all non-expired SSH keys added by all GitArena users as well as all deploy keys.
     /// This command should be invoked by the OpenSSH server via [`AuthorizedKeysCommand`](https://man.openbsd.org/sshd_config#AuthorizedKeysCommand)
-    AuthorizedKeys
+    AuthorizedKeys,
+    /// Serves `git-upload-pack` and `git-receive-pack` requests for the owner of the SSH key with the provided id.
+    /// This command is set as forced command for every key printed by `authorized-keys` and should not be invoked manually
+    Serve {
+        key_id: i32,
+        /// Treats `key_id` as the id of a deploy key, which only grants access to the repository it belongs to
+        #[clap(long)]
+        deploy_key: bool
+    },
+    /// Forwards a Git hook to the main process. Invoked by Git during pushes served by `serve` and should not be invoked manually
+    Hook {
+        name: String
+    }
 }
 
 #[derive(Parser, Debug)]
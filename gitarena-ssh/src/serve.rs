@@ -0,0 +1,252 @@
+use crate::hook;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use anyhow::{anyhow, bail, Context, Result};
+use gitarena_common::database::Database;
+use gitarena_common::database::privileges;
+use gitarena_common::packets::hooks::{GitHook, HookStage};
+use gitarena_common::prelude::*;
+use sqlx::Executor;
+use tokio::process::Command;
+
+/// Serves a Git request for the user owning the SSH key with id `key_id` or, if `deploy_key` is set, for the deploy key with that id.
+/// The requested command is read from `SSH_ORIGINAL_COMMAND` which is set by the OpenSSH server when a forced command is used.
+///
+/// Pushes run `pre-receive` and `post-receive` hooks (see [hook]) calling back into the main process, so they go through the
+/// same checks and post push steps as pushes over smart HTTP. Exits with the status of Git if it did not succeed
+pub(crate) async fn serve<'e, E: Executor<'e, Database = Database> + Copy>(key_id: i32, deploy_key: bool, executor: E) -> Result<()> {
+    let original_command = env::var("SSH_ORIGINAL_COMMAND").unwrap_or_default();
+
+    let (service, path) = parse_command(original_command.as_str())?;
+    let (owner, name) = path.trim_start_matches('/')
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Invalid repository path: {}", path))?;
+
+    let principal = if deploy_key {
+        find_deploy_key(key_id, executor).await?
+    } else {
+        find_key_owner(key_id, executor).await?
+    };
+
+    let repo_option: Option<(i32, i32, String, String, bool, bool, bool, String)> = sqlx::query_as(
+        "select repositories.id, repositories.owner, repositories.name, repositories.visibility::text, repositories.archived, repositories.disabled, \
+        repositories.mirrored_from is not null, users.username \
+        from repositories inner join users on users.id = repositories.owner \
+        where lower(users.username) = lower($1) and lower(repositories.name) = lower($2) limit 1"
+    )
+        .bind(owner)
+        .bind(name)
+        .fetch_optional(executor)
+        .await?;
+
+    let repo = repo_option.map(|(id, owner, name, visibility, archived, disabled, mirror, owner_name)| SshRepository {
+        id,
+        owner,
+        name,
+        visibility,
+        archived,
+        disabled,
+        mirror,
+        owner_name
+    });
+
+    // Do not leak the existence of internal/private repositories
+    let repo = match repo {
+        Some(repo) if check_access(&repo, &principal, executor).await? => repo,
+        _ => bail!("Repository not found")
+    };
+
+    if service == "git-receive-pack" {
+        if !check_push(&repo, &principal, executor).await? {
+            bail!("No permission to push into this repo");
+        }
+
+        if repo.archived {
+            bail!("Repository is archived and thus read-only");
+        }
+
+        if repo.mirror {
+            bail!("Repository is a mirror and thus read-only");
+        }
+    }
+
+    let (base_dir,): (String,) = sqlx::query_as("select value from settings where key = 'repositories.base_dir' limit 1")
+        .fetch_one(executor)
+        .await?;
+
+    let repo_path = Path::new(base_dir.as_str()).join(repo.owner_name.as_str()).join(repo.name.as_str());
+    let sub_command = service.trim_start_matches("git-");
+
+    let push = service == "git-receive-pack";
+    let user_id = match &principal {
+        KeyPrincipal::User(user) => Some(user.id),
+        KeyPrincipal::Deploy { .. } => None
+    };
+
+    // Held until Git exits, so the repository does not get moved away (and the server does not shut down mid push) while it is in use
+    let (session, _session_stream) = hook::call(GitHook {
+        stage: HookStage::Session { push },
+        repo: repo.id,
+        user: user_id,
+        quarantine: None,
+        updates: Vec::new()
+    }).await?;
+
+    if !session.accepted {
+        bail!("Repository is currently unavailable");
+    }
+
+    let mut command = Command::new("git");
+
+    if push {
+        let hooks_dir = hook::install()?;
+        command.arg("-c").arg(format!("core.hooksPath={}", hooks_dir.display()));
+    }
+
+    command.arg(sub_command).arg(repo_path).env("GITARENA_REPO_ID", repo.id.to_string());
+
+    if let Some(user_id) = user_id {
+        command.env("GITARENA_USER_ID", user_id.to_string());
+    }
+
+    let status = command.status().await.with_context(|| format!("Failed to execute git {}", sub_command))?;
+
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+
+    Ok(())
+}
+
+async fn find_key_owner<'e, E: Executor<'e, Database = Database>>(key_id: i32, executor: E) -> Result<KeyPrincipal> {
+    let (user_id, disabled, banned, admin): (i32, bool, bool, bool) = sqlx::query_as(
+        "select users.id, users.disabled, coalesce(users.banned_until > now(), false), users.admin from users \
+        inner join ssh_keys on ssh_keys.owner = users.id \
+        where ssh_keys.id = $1 and (ssh_keys.expires_at is null or ssh_keys.expires_at > now()) limit 1"
+    )
+        .bind(&key_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| anyhow!("SSH key does not exist or has expired"))?;
+
+    if disabled {
+        bail!("Account has been disabled. Please contact support.");
+    }
+
+    if banned {
+        bail!("Account has been banned. Please contact support.");
+    }
+
+    Ok(KeyPrincipal::User(KeyOwner {
+        id: user_id,
+        admin
+    }))
+}
+
+async fn find_deploy_key<'e, E: Executor<'e, Database = Database>>(key_id: i32, executor: E) -> Result<KeyPrincipal> {
+    let (repo, read_only): (i32, bool) = sqlx::query_as("select repo, read_only from deploy_keys where id = $1 limit 1")
+        .bind(&key_id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| anyhow!("SSH key does not exist"))?;
+
+    Ok(KeyPrincipal::Deploy {
+        repo,
+        read_only
+    })
+}
+
+/// Parses a command such as `git-upload-pack 'owner/repo.git'` into its service and path
+fn parse_command(command: &str) -> Result<(&str, &str)> {
+    let (service, path) = command.trim()
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("GitArena does not provide shell access"))?;
+
+    if service != "git-upload-pack" && service != "git-receive-pack" {
+        bail!("GitArena does not provide shell access");
+    }
+
+    let path = path.trim().trim_matches('\'').trim_matches('"');
+
+    if path.is_empty() || path.contains("..") {
+        bail!("Invalid repository path: {}", path);
+    }
+
+    Ok((service, path))
+}
+
+/// Mirrors `privilege::check_access` of the web server, so Git over SSH grants the same access as Git over HTTPS
+async fn check_access<'e, E: Executor<'e, Database = Database> + Copy>(repo: &SshRepository, principal: &KeyPrincipal, executor: E) -> Result<bool> {
+    let admin = matches!(principal, KeyPrincipal::User(user) if user.admin);
+
+    // Disabled repositories are only accessible to admins, not even their owner
+    if repo.disabled {
+        return Ok(admin);
+    }
+
+    if !admin && privileges::is_owner_blocked(repo.owner, executor).await? {
+        return Ok(false);
+    }
+
+    let user = match principal {
+        KeyPrincipal::User(user) => user,
+        KeyPrincipal::Deploy { repo: repo_id, .. } => return Ok(*repo_id == repo.id)
+    };
+
+    if user.admin || user.id == repo.owner || repo.visibility != "private" {
+        return Ok(true);
+    }
+
+    let access_level = privileges::access_level(user.id, repo.id, repo.owner, executor).await?;
+
+    Ok(access_level.map_or(false, |access_level| access_level.can_view()))
+}
+
+async fn check_push<'e, E: Executor<'e, Database = Database>>(repo: &SshRepository, principal: &KeyPrincipal, executor: E) -> Result<bool> {
+    let user = match principal {
+        KeyPrincipal::User(user) => user,
+        KeyPrincipal::Deploy { repo: repo_id, read_only } => return Ok(*repo_id == repo.id && !read_only)
+    };
+
+    if user.admin || user.id == repo.owner {
+        return Ok(true);
+    }
+
+    let access_level = privileges::access_level(user.id, repo.id, repo.owner, executor).await?;
+
+    Ok(access_level.map_or(false, |access_level| access_level.can_push()))
+}
+
+/// Whoever the presented SSH key belongs to
+#[derive(Debug)]
+enum KeyPrincipal {
+    User(KeyOwner),
+    /// Deploy keys only ever grant access to the single repository they have been added to
+    Deploy {
+        repo: i32,
+        read_only: bool
+    }
+}
+
+#[derive(Debug)]
+struct KeyOwner {
+    id: i32,
+    admin: bool
+}
+
+#[derive(Debug)]
+struct SshRepository {
+    id: i32,
+    owner: i32,
+    name: String,
+    visibility: String,
+    archived: bool,
+    disabled: bool,
+    mirror: bool,
+    owner_name: String
+}
@@ -0,0 +1,286 @@
+//! Imports a remote repository by fetching all of its branches and tags into the bare repository created by the main process.
+//!
+//! State and progress are written into the `import_jobs` table, which the main process exposes to the user.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use gitarena_common::database::models::ImportState;
+use gitarena_common::database::Pool;
+use gitarena_common::outbound_url;
+use gitarena_common::packets::git::GitImport;
+use gitarena_common::prelude::*;
+use log::{error, info, warn};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::task;
+use tokio::time;
+use url::Url;
+
+/// Only the tail of Git's output is kept, which is enough to find the last progress update and error message
+const OUTPUT_TAIL: usize = 8192;
+
+pub(crate) async fn run(packet: GitImport, db_pool: Pool) {
+    let job = packet.job;
+
+    let result = match set_state(job, ImportState::Running, None, &db_pool).await {
+        Ok(()) => import(&packet, &db_pool).await,
+        Err(err) => Err(err)
+    };
+
+    let (state, error) = match result {
+        Ok(()) => {
+            info!("Import job {} finished", job);
+            (ImportState::Finished, None)
+        }
+        Err(err) => {
+            warn!("Import job {} failed: {}", job, err);
+            (ImportState::Failed, Some(err.to_string()))
+        }
+    };
+
+    if let Err(err) = set_state(job, state, error.as_deref(), &db_pool).await {
+        error!("Failed to update state of import job {}: {}", job, err);
+    }
+}
+
+async fn import(packet: &GitImport, db_pool: &Pool) -> Result<()> {
+    let path = Path::new(packet.path.as_str());
+
+    // The main process checked the host when the import was requested, but its records may have changed since
+    outbound_url::check(&Url::parse(packet.url.as_str())?).await?;
+
+    let mut child = git(path, packet.username.as_deref(), packet.password.as_deref())
+        .args(&["fetch", "--progress", "--", packet.url.as_str(), "+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stderr = child.stderr.take().ok_or_else(|| anyhow!("Failed to capture Git output"))?;
+
+    let mut output = Vec::<u8>::new();
+    let mut buffer = [0_u8; 4096];
+    let mut progress = 0;
+    let mut size_check = time::interval(Duration::from_secs(2));
+
+    loop {
+        tokio::select! {
+            read = stderr.read(&mut buffer) => {
+                let read = read?;
+
+                if read == 0 {
+                    break;
+                }
+
+                output.extend_from_slice(&buffer[..read]);
+
+                if output.len() > OUTPUT_TAIL {
+                    output.drain(..output.len() - OUTPUT_TAIL);
+                }
+
+                if let Some(current) = parse_progress(String::from_utf8_lossy(&output).as_ref()) {
+                    if current != progress {
+                        progress = current;
+                        set_progress(packet.job, progress, db_pool).await?;
+                    }
+                }
+            }
+            _ = size_check.tick() => check_size(path, packet.max_size).await?
+        }
+    }
+
+    let status = child.wait().await?;
+
+    if !status.success() {
+        bail!("{}", last_line(&output).unwrap_or_else(|| format!("Git exited with {}", status)));
+    }
+
+    check_size(path, packet.max_size).await?;
+
+    if let Some(branch) = remote_head(packet, path).await? {
+        set_head(packet, path, branch.as_str(), db_pool).await?;
+    }
+
+    // Quotas rely on the cached size, which is still zero from when the repository was created
+    sqlx::query("update repositories set repo_size_bytes = $1 where id = (select repo from import_jobs where id = $2)")
+        .bind(&(repo_size(path).await? as i64))
+        .bind(&packet.job)
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Prepares a Git command running in `path`, authenticating using the provided credentials.
+/// These are passed using environment variables, as arguments are visible to other users on the system.
+///
+/// Redirects are never followed: Git would send the credentials to the redirect target as well and only the host of the
+/// original url is checked to be a public address
+pub(crate) fn git(path: &Path, username: Option<&str>, password: Option<&str>) -> Command {
+    let mut command = Command::new("git");
+
+    command.current_dir(path)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_ALLOW_PROTOCOL", "http:https:git")
+        .env("GIT_CONFIG_KEY_0", "http.followRedirects")
+        .env("GIT_CONFIG_VALUE_0", "false");
+
+    match (username, password) {
+        (None, None) => {
+            command.env("GIT_CONFIG_COUNT", "1");
+        }
+        (username, password) => {
+            let credentials = format!("{}:{}", username.unwrap_or_default(), password.unwrap_or_default());
+
+            command.env("GIT_CONFIG_COUNT", "2")
+                .env("GIT_CONFIG_KEY_1", "http.extraHeader")
+                .env("GIT_CONFIG_VALUE_1", format!("Authorization: Basic {}", base64::encode(credentials)));
+        }
+    }
+
+    command
+}
+
+/// Returns the last non-empty line Git printed, which usually is the reason why it failed
+pub(crate) fn last_line(output: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(output)
+        .split(|c| c == '\r' || c == '\n')
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .last()
+        .map(str::to_owned)
+}
+
+/// Maps Git's `Receiving objects` progress to 0 - 90% and `Resolving deltas` to the remaining 10%
+fn parse_progress(output: &str) -> Option<i16> {
+    let percentage = |phase: &str| -> Option<i16> {
+        let start = output.rfind(phase)? + phase.len();
+        let rest = output[start..].trim_start();
+
+        rest[..rest.find('%')?].parse::<i16>().ok()
+    };
+
+    match percentage("Resolving deltas:") {
+        Some(deltas) => Some(90 + deltas.min(100) / 10),
+        None => percentage("Receiving objects:").map(|objects| objects.min(100) * 9 / 10)
+    }
+}
+
+async fn check_size(path: &Path, max_size: u64) -> Result<()> {
+    let size = dir_size_async(path).await?;
+
+    if size > max_size {
+        bail!("Repository exceeds the maximum size of {} bytes", max_size);
+    }
+
+    Ok(())
+}
+
+/// Size of the directory at `path` in bytes, computed on the blocking thread pool
+pub(crate) async fn dir_size_async(path: &Path) -> Result<u64> {
+    let path = path.to_owned();
+
+    Ok(task::spawn_blocking(move || dir_size(path.as_path())).await??)
+}
+
+/// Size of the repository at `path` as computed by the main process: objects, LFS objects and release assets
+pub(crate) async fn repo_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+
+    for dir in ["objects", "lfs", "releases"] {
+        let dir = path.join(dir);
+
+        if dir.is_dir() {
+            size += dir_size_async(dir.as_path()).await?;
+        }
+    }
+
+    Ok(size)
+}
+
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        size += if metadata.is_dir() {
+            dir_size(entry.path().as_path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(size)
+}
+
+/// Returns the branch `HEAD` points to on the remote
+async fn remote_head(packet: &GitImport, path: &Path) -> Result<Option<String>> {
+    outbound_url::check(&Url::parse(packet.url.as_str())?).await?;
+
+    let output = git(path, packet.username.as_deref(), packet.password.as_deref())
+        .args(&["ls-remote", "--symref", "--", packet.url.as_str(), "HEAD"])
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("ref: refs/heads/"))
+        .and_then(|line| line.split('\t').next())
+        .map(str::to_owned))
+}
+
+/// Points the local `HEAD` to `branch` and makes it the default branch of the repository
+async fn set_head(packet: &GitImport, path: &Path, branch: &str, db_pool: &Pool) -> Result<()> {
+    let status = git(path, packet.username.as_deref(), packet.password.as_deref())
+        .args(&["symbolic-ref", "HEAD", format!("refs/heads/{}", branch).as_str()])
+        .stdin(Stdio::null())
+        .status()
+        .await?;
+
+    if !status.success() {
+        bail!("Failed to set HEAD to {}", branch);
+    }
+
+    sqlx::query("update repositories set default_branch = $1 where id = (select repo from import_jobs where id = $2)")
+        .bind(branch)
+        .bind(&packet.job)
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn set_state(job: i32, state: ImportState, error: Option<&str>, db_pool: &Pool) -> Result<()> {
+    sqlx::query("update import_jobs set state = $1, error = $2, \
+        progress = case when $1 = 'finished'::import_state then 100 else progress end, updated_at = now() where id = $3")
+        .bind(state)
+        .bind(error)
+        .bind(&job)
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn set_progress(job: i32, progress: i16, db_pool: &Pool) -> Result<()> {
+    sqlx::query("update import_jobs set progress = $1, updated_at = now() where id = $2")
+        .bind(&progress)
+        .bind(&job)
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
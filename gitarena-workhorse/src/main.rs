@@ -1,27 +1,38 @@
 use std::io;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use futures::stream::StreamExt;
+use gitarena_common::database::{create_postgres_pool, Pool};
 use gitarena_common::ipc::{ipc_path, IpcPacket};
 use gitarena_common::log::init_logger;
 use gitarena_common::packets::git::GitImport;
 use gitarena_common::packets::PacketId;
 use gitarena_common::prelude::*;
-use log::{debug, error, info, warn};
+use log::{error, info};
 use num_traits::cast::FromPrimitive;
 use parity_tokio_ipc::Endpoint;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 use tracing_unwrap::ResultExt;
 
+mod import;
+mod mirror;
+
+/// Upper limit for the size of a single packet, anything larger is most likely a corrupted length prefix
+const MAX_PACKET_SIZE: u64 = 1_000_000;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let _log_guards = init_logger("gitarena-workhorse", &[])?;
 
+    let db_pool = create_postgres_pool("gitarena-workhorse", None).await?;
+
+    mirror::spawn_scheduler(db_pool.clone());
+
     Endpoint::new(ipc_path()?.to_owned())
         .incoming()
         .with_context(|| format!("Failed to create endpoint at {}", ipc_path().unwrap_or_log()))? // .unwrap_or_log() is safe as it would've excited early two lines above if this errors
         .for_each(|connection| async {
-            if let Err(err) = handle(connection).await {
+            if let Err(err) = handle(connection, &db_pool).await {
                 error!("Error occurred while reading stream: {}", err);
             }
         })
@@ -32,33 +43,38 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn handle<T: AsyncRead + AsyncWrite + Unpin + 'static>(connection: Result<T, io::Error>) -> Result<()> {
+async fn handle<T: AsyncRead + AsyncWrite + Unpin + 'static>(connection: Result<T, io::Error>, db_pool: &Pool) -> Result<()> {
     let mut connection = connection?;
 
-    let type_ = connection.read_u64().await.context("Failed to read type")?;
-    let length = connection.read_u64().await.context("Failed to read length")?;
+    // The main process keeps its connection open and sends all packets over it, so keep reading until it disconnects
+    loop {
+        let length = match connection.read_u64().await {
+            Ok(length) => length,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err).context("Failed to read length")
+        };
 
-    let id: PacketId = PacketId::from_u64(type_).with_context(|| format!("Received unknown packet id: {}", type_))?;
+        if length > MAX_PACKET_SIZE {
+            bail!("Received packet of {} bytes which exceeds the maximum of {} bytes", length, MAX_PACKET_SIZE);
+        }
 
-    let mut buffer: Vec<u8> = Vec::with_capacity(length as usize);
-    let read_length = connection.read(buffer.as_mut_slice()).await.context("Failed to read payload")? as u64;
+        let mut payload = vec![0_u8; length as usize];
+        connection.read_exact(payload.as_mut_slice()).await.context("Failed to read payload")?;
 
-    if read_length != length {
-        warn!("Failed to read correct payload size, expected: {} read: {}", length, read_length);
-    }
+        let type_ = IpcPacket::<()>::deserialize(payload.as_slice())?.id();
+        let id: PacketId = PacketId::from_u64(type_).with_context(|| format!("Received unknown packet id: {}", type_))?;
 
-    // Bincode is configured in gitarena-common/src/ipc.rs to use little endianness
-    let ser_type = &type_.to_le_bytes();
-    let ser_length = &length.to_le_bytes();
-    let payload = &[ser_type, ser_length, buffer.as_slice()].concat();
+        match id {
+            PacketId::GitImport => {
+                let packet = IpcPacket::<GitImport>::deserialize(payload.as_slice())?;
+                let packet = packet.into_data();
 
-    match id {
-        PacketId::GitImport => {
-            let packet = IpcPacket::<GitImport>::deserialize(payload)?;
+                info!("Received import job {} for {}", packet.job, packet.url);
 
-            debug!("got {:?}", packet);
+                let db_pool = db_pool.clone();
+                tokio::spawn(async move { import::run(packet, db_pool).await });
+            }
+            PacketId::GitHook | PacketId::GitHookResult => bail!("Received hook packet {} which is handled by the main process", type_)
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,130 @@
+//! Syncs pull mirrors. Every minute, all mirrors whose `next_sync_at` has passed fetch all branches and tags from upstream,
+//! pruning refs which have been deleted there.
+
+use crate::import::{dir_size_async, git, last_line, repo_size};
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use gitarena_common::database::Pool;
+use gitarena_common::database::quota;
+use gitarena_common::outbound_url;
+use gitarena_common::prelude::*;
+use log::{debug, error, warn};
+use tokio::time;
+use url::Url;
+
+/// Syncs taking longer than this are aborted, so a hanging upstream can't block all other mirrors
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+pub(crate) fn spawn_scheduler(db_pool: Pool) {
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = sync_due(&db_pool).await {
+                error!("Failed to sync mirrors: {}", err);
+            }
+        }
+    });
+}
+
+async fn sync_due(db_pool: &Pool) -> Result<()> {
+    // Claims the due mirrors by scheduling their next sync right away, so a sync is never started twice
+    let due: Vec<(i32, i32, String, String)> = sqlx::query_as("update mirror_settings \
+        set next_sync_at = now() + mirror_settings.interval * interval '1 second' \
+        from repositories inner join users on users.id = repositories.owner \
+        where repositories.id = mirror_settings.repo and mirror_settings.next_sync_at <= now() \
+        and repositories.mirrored_from is not null and not repositories.archived \
+        returning mirror_settings.repo, repositories.owner, repositories.mirrored_from, \
+        (select value from settings where key = 'repositories.base_dir') || '/' || users.username || '/' || repositories.name")
+        .fetch_all(db_pool)
+        .await?;
+
+    for (repo, owner, url, path) in due {
+        debug!("Syncing mirror {} from {}", repo, url);
+
+        let result = sync_within_quota(repo, owner, url.as_str(), Path::new(path.as_str()), db_pool).await;
+
+        if let Err(err) = &result {
+            warn!("Failed to sync mirror {} from {}: {}", repo, url, err);
+        }
+
+        sqlx::query("update mirror_settings set last_sync_at = now(), last_sync_success = $1, last_error = $2 where repo = $3")
+            .bind(result.is_ok())
+            .bind(result.err().map(|err| err.to_string()))
+            .bind(&repo)
+            .execute(db_pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Syncs the mirror without letting it grow past the storage quota of its owner, updating its cached size afterwards
+async fn sync_within_quota(repo: i32, owner: i32, url: &str, path: &Path, db_pool: &Pool) -> Result<()> {
+    // The owner is only locked while reading the usage, as a sync may take a long time
+    let mut transaction = db_pool.begin().await?;
+    let remaining = quota::usage(repo, owner, &mut transaction).await?.remaining();
+    transaction.commit().await?;
+
+    let result = sync(url, path, remaining).await;
+
+    let size = repo_size(path).await?;
+
+    sqlx::query("update repositories set repo_size_bytes = $1 where id = $2")
+        .bind(&(size as i64))
+        .bind(&repo)
+        .execute(db_pool)
+        .await?;
+
+    result
+}
+
+/// Fetches from `url`, aborting once more than `max_growth` bytes of objects have been received
+async fn sync(url: &str, path: &Path, max_growth: Option<u64>) -> Result<()> {
+    // The host has only been checked when the mirror was created, its records may point somewhere else by now
+    outbound_url::check(&Url::parse(url)?).await?;
+
+    let objects = path.join("objects");
+    let initial_size = dir_size_async(objects.as_path()).await?;
+
+    let mut command = git(path, None, None);
+
+    command.args(&["fetch", "--prune", "--quiet", "--", url, "+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"])
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    let output = command.output();
+
+    let deadline = time::sleep(SYNC_TIMEOUT);
+    let mut size_check = time::interval(Duration::from_secs(2));
+
+    tokio::pin!(output);
+    tokio::pin!(deadline);
+
+    // Returning early drops the output future, which kills Git
+    let output = loop {
+        tokio::select! {
+            output = &mut output => break output?,
+            _ = &mut deadline => bail!("Sync did not finish within {} minutes", SYNC_TIMEOUT.as_secs() / 60),
+            _ = size_check.tick() => {
+                if let Some(max_growth) = max_growth {
+                    if dir_size_async(objects.as_path()).await?.saturating_sub(initial_size) > max_growth {
+                        bail!("Sync would exceed the storage quota of the repository owner");
+                    }
+                }
+            }
+        }
+    };
+
+    if !output.status.success() {
+        bail!("{}", last_line(&output.stderr).unwrap_or_else(|| format!("Git exited with {}", output.status)));
+    }
+
+    Ok(())
+}
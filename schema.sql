@@ -11,15 +11,25 @@ create table if not exists users
         constraint users_pk
             primary key,
     username   varchar(32)                                                          not null,
-    password   char(96)                                                             not null,
+    password   varchar(256)                                                         not null,
     disabled   boolean                  default false                               not null,
+    banned_until timestamp with time zone default null,
+    storage_quota bigint                default null,
     admin      boolean                  default false                               not null,
+    totp_secret varchar(32)             default null,
+    totp_last_step bigint               default null,
+    private_email boolean               default false                               not null,
+    login_notifications boolean         default true                                not null,
+    organization boolean                default false                               not null,
     created_at timestamp with time zone default current_timestamp                   not null
 );
 
 create unique index if not exists users_username_uindex
     on users (username);
 
+create unique index if not exists users_username_lower_uindex
+    on users (lower(username));
+
 -- Emails
 
 create table emails
@@ -43,6 +53,9 @@ create table emails
 create unique index emails_email_uindex
     on emails (email);
 
+create unique index emails_email_lower_uindex
+    on emails (lower(email));
+
 create index emails_owner_index
     on emails (owner);
 
@@ -50,20 +63,103 @@ create index emails_owner_index
 
 create table if not exists user_verifications
 (
-    id      serial                   not null
+    id       serial                                 not null
         constraint user_verifications_pk
             primary key,
-    user_id integer                  not null,
-    hash    char(32)                 not null,
-    expires timestamp with time zone not null
+    user_id  integer                                not null
+        constraint user_verifications_users_id_fk
+            references users
+            on delete cascade,
+    email_id integer                                not null
+        constraint user_verifications_emails_id_fk
+            references emails
+            on delete cascade,
+    hash     char(64)                               not null,
+    expires  timestamp with time zone               not null,
+    sent_at  timestamp with time zone default now() not null
 );
 
+comment on column user_verifications.hash is 'SHA-256 of the token sent to the user';
+
 create unique index if not exists user_verifications_hash_uindex
     on user_verifications (hash);
 
-create unique index if not exists user_verifications_user_id_uindex
+create unique index if not exists user_verifications_email_id_uindex
+    on user_verifications (email_id);
+
+create index if not exists user_verifications_user_id_index
     on user_verifications (user_id);
 
+create table if not exists email_changes
+(
+    user_id     integer                                not null
+        constraint email_changes_pk
+            primary key
+        constraint email_changes_users_id_fk
+            references users
+            on delete cascade,
+    email       varchar(256)                           not null,
+    hash        char(64)                               not null,
+    revoke_hash char(64)                               not null,
+    expires     timestamp with time zone               not null,
+    created_at  timestamp with time zone default now() not null
+);
+
+comment on table email_changes is 'Pending changes of the primary email, applied once the link sent to the new address has been clicked';
+comment on column email_changes.hash is 'SHA-256 of the token sent to the user';
+comment on column email_changes.revoke_hash is 'SHA-256 of the token sent to the current address to cancel the change';
+
+create unique index if not exists email_changes_hash_uindex
+    on email_changes (hash);
+
+create unique index if not exists email_changes_revoke_hash_uindex
+    on email_changes (revoke_hash);
+
+create table if not exists password_resets
+(
+    id         serial                                 not null
+        constraint password_resets_pk
+            primary key,
+    user_id    integer                                not null
+        constraint password_resets_users_id_fk
+            references users
+            on delete cascade,
+    hash       char(64)                               not null,
+    expires    timestamp with time zone               not null,
+    created_at timestamp with time zone default now() not null
+);
+
+comment on column password_resets.hash is 'SHA-256 of the token sent to the user';
+
+create unique index if not exists password_resets_hash_uindex
+    on password_resets (hash);
+
+create index if not exists password_resets_user_id_index
+    on password_resets (user_id);
+
+-- Organizations
+
+create type organization_role as enum ('member', 'owner');
+
+create table if not exists organization_members
+(
+    organization integer                                            not null
+        constraint organization_members_organization_fk
+            references users
+            on delete cascade,
+    member       integer                                            not null
+        constraint organization_members_member_fk
+            references users
+            on delete cascade,
+    role         organization_role default 'member'                 not null,
+    created_at   timestamp with time zone default current_timestamp not null,
+    constraint organization_members_pk
+        primary key (organization, member)
+);
+
+create index if not exists organization_members_member_index
+    on organization_members (member);
+
 -- Repositories
 
 create type repo_visibility as enum ('public', 'internal', 'private');
@@ -85,9 +181,58 @@ create table if not exists repositories
     forked_from    integer,
     mirrored_from  varchar(256) default NULL::character varying,
     archived       boolean default false                                not null,
-    disabled       boolean default false                                not null
+    disabled       boolean default false                                not null,
+    issue_counter  integer default 0                                    not null,
+    repo_size_bytes bigint default 0                                    not null
+);
+
+comment on column repositories.issue_counter is 'Last allocated issue or pull request #, incremented within the transaction creating the issue or pull request';
+comment on column repositories.repo_size_bytes is 'Size of the object database, updated after every push as walking it on every request is too slow';
+
+-- Repository redirects
+
+create table repository_redirects
+(
+    owner      integer                                not null
+        constraint repository_redirects_users_id_fk
+            references users
+            on delete cascade,
+    name       varchar(32)                            not null,
+    repo       integer                                not null
+        constraint repository_redirects_repositories_id_fk
+            references repositories
+            on delete cascade,
+    created_at timestamp with time zone default now() not null
 );
 
+comment on table repository_redirects is 'Previous owner and name of renamed or transferred repositories. Ignored once a repository with that owner and name exists again';
+
+create unique index repository_redirects_owner_name_uindex
+    on repository_redirects (owner, lower(name));
+
+-- Repository transfers
+
+create table repository_transfers
+(
+    repo       integer                                not null
+        constraint repository_transfers_pk
+            primary key
+        constraint repository_transfers_repositories_id_fk
+            references repositories
+            on delete cascade,
+    target     integer                                not null
+        constraint repository_transfers_target_users_id_fk
+            references users
+            on delete cascade,
+    initiator  integer
+        constraint repository_transfers_initiator_users_id_fk
+            references users
+            on delete set null,
+    created_at timestamp with time zone default now() not null
+);
+
+comment on table repository_transfers is 'Pending transfers, the repository keeps its current owner until the target accepts';
+
 -- Privileges
 
 create type access_level as enum ('viewer', 'supporter', 'coder', 'manager', 'admin');
@@ -108,6 +253,64 @@ create table if not exists privileges
     access_level access_level default 'viewer'::access_level not null
 );
 
+create unique index if not exists privileges_user_id_repo_id_uindex
+    on privileges (user_id, repo_id);
+
+comment on table privileges is 'Collaborators of a repository. The owner is not listed here as it always has admin access';
+
+-- Teams
+
+create table if not exists teams
+(
+    id           serial
+        constraint teams_pk
+            primary key,
+    organization integer                                            not null
+        constraint teams_organization_fk
+            references users
+            on delete cascade,
+    name         varchar(32)                                        not null,
+    access_level access_level default 'viewer'::access_level        not null,
+    created_at   timestamp with time zone default current_timestamp not null
+);
+
+create unique index if not exists teams_organization_name_uindex
+    on teams (organization, lower(name));
+
+create table if not exists team_members
+(
+    team   integer not null
+        constraint team_members_team_fk
+            references teams
+            on delete cascade,
+    member integer not null
+        constraint team_members_member_fk
+            references users
+            on delete cascade,
+    constraint team_members_pk
+        primary key (team, member)
+);
+
+create index if not exists team_members_member_index
+    on team_members (member);
+
+create table if not exists team_repositories
+(
+    team integer not null
+        constraint team_repositories_team_fk
+            references teams
+            on delete cascade,
+    repo integer not null
+        constraint team_repositories_repo_fk
+            references repositories
+            on delete cascade,
+    constraint team_repositories_pk
+        primary key (team, repo)
+);
+
+create index if not exists team_repositories_repo_index
+    on team_repositories (repo);
+
 -- Sessions
 
 create table sessions
@@ -119,10 +322,13 @@ create table sessions
     hash                varchar(32) default md5((random())::text)           not null,
     ip_address          inet                                                not null,
     user_agent          varchar(256)                                        not null,
+    pending_2fa         boolean default false                               not null,
     created_at          timestamp with time zone default current_timestamp  not null,
     updated_at          timestamp with time zone default current_timestamp  not null
 );
 
+comment on column sessions.pending_2fa is 'Session has been created using the password but the second factor has not been entered yet';
+
 create unique index sessions_hash_uindex
     on sessions (hash);
 
@@ -146,18 +352,19 @@ create table stars
     repo        integer         not null
         constraint stars_repositories_id_fk
             references repositories
-            on delete cascade
+            on delete cascade,
+    created_at  timestamp with time zone default now() not null
 );
 
 create index stars_repo_index
     on stars (repo);
 
-create index stars_stargazer_index
-    on stars (stargazer);
+create unique index stars_stargazer_repo_uindex
+    on stars (stargazer, repo);
 
 -- SSO
 
-create type sso_provider as enum ('github', 'gitlab', 'bitbucket');
+create type sso_provider as enum ('github', 'gitlab', 'bitbucket', 'oidc');
 
 create table sso
 (
@@ -166,12 +373,15 @@ create table sso
             references users
             on delete cascade,
     provider    sso_provider not null,
-    provider_id varchar(64)  not null
+    provider_id varchar(256) not null
 );
 
 create index sso_provider_id_index
     on sso (provider_id);
 
+create unique index sso_provider_provider_id_uindex
+    on sso (provider, provider_id);
+
 create index sso_provider_index
     on sso (provider);
 
@@ -190,11 +400,12 @@ create table issues
             references repositories
             on delete cascade,
     index        integer                                              not null,
-    author       integer                                              not null
+    author       integer
         constraint issues_users_id_fk
             references users
-            on delete cascade,
+            on delete set null,
     title        varchar(256)                                         not null,
+    body         text                     default ''                  not null,
     labels       integer[]                default ARRAY []::integer[] not null,
     milestone    integer,
     assignees    integer[]                default ARRAY []::integer[] not null,
@@ -205,9 +416,168 @@ create table issues
     updated_at   timestamp with time zone default CURRENT_TIMESTAMP   not null
 );
 
-comment on table issues is 'Contains issues and their corresponding data; Does *not* contain the actual text content';
+comment on table issues is 'Contains issues and their corresponding data; Comments are stored separately';
 comment on column issues.index is 'Issue # per repository (not global instance)';
 
+create unique index issues_repo_index_uindex
+    on issues (repo, index);
+
+create table issue_comments
+(
+    id         serial
+        constraint issue_comments_pk
+            primary key,
+    issue      integer                                            not null
+        constraint issue_comments_issues_id_fk
+            references issues
+            on delete cascade,
+    author     integer
+        constraint issue_comments_users_id_fk
+            references users
+            on delete set null,
+    body       text                                               not null,
+    mentions   integer[]                default ARRAY []::integer[] not null,
+    created_at timestamp with time zone default CURRENT_TIMESTAMP not null,
+    edited_at  timestamp with time zone,
+    deleted_at timestamp with time zone
+);
+
+comment on column issue_comments.mentions is 'Ids of the users mentioned using @username in the body';
+comment on column issue_comments.deleted_at is 'Deleted comments are kept as tombstones with an empty body so the thread stays intact';
+
+create index issue_comments_issue_index
+    on issue_comments (issue);
+
+-- Pull requests
+
+create type pull_request_state as enum ('open', 'closed', 'merged');
+
+create table pull_requests
+(
+    id            serial
+        constraint pull_requests_pk
+            primary key,
+    repo          integer                                            not null
+        constraint pull_requests_repositories_id_fk
+            references repositories
+            on delete cascade,
+    index         integer                                            not null,
+    author        integer
+        constraint pull_requests_users_id_fk
+            references users
+            on delete set null,
+    title         varchar(256)                                       not null,
+    body          text                     default ''                not null,
+    source_repo   integer
+        constraint pull_requests_source_repositories_id_fk
+            references repositories
+            on delete set null,
+    source_branch varchar(256)                                       not null,
+    target_branch varchar(256)                                       not null,
+    head_sha      char(40)                                           not null,
+    base_sha      char(40)                                           not null,
+    state         pull_request_state       default 'open'            not null,
+    merge_commit  char(40),
+    merged_by     integer
+        constraint pull_requests_merged_by_users_id_fk
+            references users
+            on delete set null,
+    created_at    timestamp with time zone default CURRENT_TIMESTAMP not null,
+    updated_at    timestamp with time zone default CURRENT_TIMESTAMP not null,
+    merged_at     timestamp with time zone
+);
+
+comment on column pull_requests.index is 'Shares the issue # counter of the repository, so issues and pull requests never have the same #';
+comment on column pull_requests.source_repo is 'Repository the changes come from, either the target repository itself or a fork of it. Null if the fork has been deleted';
+comment on column pull_requests.head_sha is 'Commit of the source branch last fetched into refs/pull/<index>/head';
+comment on column pull_requests.base_sha is 'Merge base of head_sha and the target branch at the time head_sha was fetched';
+
+create unique index pull_requests_repo_index_uindex
+    on pull_requests (repo, index);
+
+-- Releases
+
+create table releases
+(
+    id         serial
+        constraint releases_pk
+            primary key,
+    repo       integer                                            not null
+        constraint releases_repositories_id_fk
+            references repositories
+            on delete cascade,
+    tag        varchar(256)                                       not null,
+    title      varchar(256)                                       not null,
+    body       text                     default ''                not null,
+    draft      boolean                  default false             not null,
+    prerelease boolean                  default false             not null,
+    author     integer
+        constraint releases_users_id_fk
+            references users
+            on delete set null,
+    created_at timestamp with time zone default CURRENT_TIMESTAMP not null
+);
+
+comment on column releases.tag is 'Name of the tag without refs/tags/';
+
+create unique index releases_repo_tag_uindex
+    on releases (repo, tag);
+
+create table release_assets
+(
+    id           serial
+        constraint release_assets_pk
+            primary key,
+    release      integer                                            not null
+        constraint release_assets_releases_id_fk
+            references releases
+            on delete cascade,
+    name         varchar(256)                                       not null,
+    content_type varchar(256)                                       not null,
+    size         bigint                                             not null,
+    uploader     integer
+        constraint release_assets_users_id_fk
+            references users
+            on delete set null,
+    created_at   timestamp with time zone default CURRENT_TIMESTAMP not null
+);
+
+comment on table release_assets is 'Files are stored inside the repository directory at releases/<release id>/<asset id>';
+
+create unique index release_assets_release_name_uindex
+    on release_assets (release, name);
+
+-- Commit statuses
+
+create type commit_state as enum ('success', 'pending', 'failure', 'error');
+
+create table commit_statuses
+(
+    id          serial
+        constraint commit_statuses_pk
+            primary key,
+    repo        integer                                            not null
+        constraint commit_statuses_repositories_id_fk
+            references repositories
+            on delete cascade,
+    sha         char(40)                                           not null,
+    context     varchar(256)             default 'default'         not null,
+    state       commit_state                                       not null,
+    target_url  varchar(2048),
+    description varchar(1024),
+    creator     integer
+        constraint commit_statuses_users_id_fk
+            references users
+            on delete set null,
+    created_at  timestamp with time zone default CURRENT_TIMESTAMP not null
+);
+
+comment on table commit_statuses is 'Statuses are never updated, the latest status of a context replaces all previous ones';
+comment on type commit_state is 'Ordered from best to worst, the combined state of a commit is the worst state of all contexts';
+
+create index commit_statuses_repo_sha_index
+    on commit_statuses (repo, sha);
+
 -- SSH keys
 
 create type ssh_key_type as enum (
@@ -228,7 +598,7 @@ create table ssh_keys
             references users
             on delete cascade,
     title       varchar(64)                            not null,
-    fingerprint char(47)                               not null,
+    fingerprint varchar(64)                            not null,
     algorithm   ssh_key_type                           not null,
     key         bytea                                  not null,
     created_at  timestamp with time zone default now() not null,
@@ -241,6 +611,496 @@ create unique index ssh_keys_fingerprint_uindex
 create unique index ssh_keys_key_uindex
     on ssh_keys (key);
 
+create table deploy_keys
+(
+    id          serial
+        constraint deploy_keys_pk
+            primary key,
+    repo        integer                                not null
+        constraint deploy_keys_repositories_id_fk
+            references repositories
+            on delete cascade,
+    title       varchar(64)                            not null,
+    fingerprint varchar(64)                            not null,
+    algorithm   ssh_key_type                           not null,
+    key         bytea                                  not null,
+    read_only   boolean default true                   not null,
+    created_at  timestamp with time zone default now() not null
+);
+
+comment on table deploy_keys is 'SSH keys granting access to a single repository, see `gitarena-ssh serve --deploy-key`';
+
+create unique index deploy_keys_fingerprint_uindex
+    on deploy_keys (fingerprint);
+
+create unique index deploy_keys_key_uindex
+    on deploy_keys (key);
+
+-- Access tokens
+
+create table access_tokens
+(
+    id          serial
+        constraint access_tokens_pk
+            primary key,
+    owner       integer                                not null
+        constraint access_tokens_users_id_fk
+            references users
+            on delete cascade,
+    name        varchar(64)                            not null,
+    prefix      char(8)                                not null,
+    hash        char(96)                               not null,
+    scopes      integer default 0                      not null,
+    created_at  timestamp with time zone default now() not null,
+    expires_at  timestamp with time zone
+);
+
+comment on column access_tokens.prefix is 'First characters of the token after `gitarena_`, used for lookup and display';
+comment on column access_tokens.scopes is 'Bitflag of granted scopes, see `TokenScopes`';
+
+create index access_tokens_prefix_index
+    on access_tokens (prefix);
+
+create index access_tokens_owner_index
+    on access_tokens (owner);
+
+-- GPG keys
+
+create table gpg_keys
+(
+    id          serial
+        constraint gpg_keys_pk
+            primary key,
+    owner       integer                                not null
+        constraint gpg_keys_users_id_fk
+            references users
+            on delete cascade,
+    key_id      char(16)                               not null,
+    subkey_ids  char(16)[] default '{}'                not null,
+    armored     text                                   not null,
+    created_at  timestamp with time zone default now() not null
+);
+
+comment on column gpg_keys.key_id is 'Long key id of the primary key in upper case hex';
+
+create unique index gpg_keys_key_id_uindex
+    on gpg_keys (key_id);
+
+create index gpg_keys_owner_index
+    on gpg_keys (owner);
+
+-- Recovery codes
+
+create table recovery_codes
+(
+    id          serial
+        constraint recovery_codes_pk
+            primary key,
+    owner       integer                                not null
+        constraint recovery_codes_users_id_fk
+            references users
+            on delete cascade,
+    hash        char(96)                               not null,
+    used_at     timestamp with time zone
+);
+
+create index recovery_codes_owner_index
+    on recovery_codes (owner);
+
+-- LFS locks
+
+create table lfs_locks
+(
+    id          serial
+        constraint lfs_locks_pk
+            primary key,
+    repo        integer                                not null
+        constraint lfs_locks_repositories_id_fk
+            references repositories
+            on delete cascade,
+    path        varchar(1024)                          not null,
+    owner       integer                                not null
+        constraint lfs_locks_users_id_fk
+            references users
+            on delete cascade,
+    created_at  timestamp with time zone default now() not null
+);
+
+create unique index lfs_locks_repo_path_uindex
+    on lfs_locks (repo, path);
+
+-- Branch protections
+
+create table branch_protections
+(
+    id                     serial
+        constraint branch_protections_pk
+            primary key,
+    repo                   integer                                not null
+        constraint branch_protections_repositories_id_fk
+            references repositories
+            on delete cascade,
+    pattern                varchar(256)                           not null,
+    no_force_push          boolean                  default true  not null,
+    no_delete              boolean                  default true  not null,
+    require_signed         boolean                  default false not null,
+    allow_admin_force_push boolean                  default false not null,
+    created_at             timestamp with time zone default now() not null
+);
+
+create unique index branch_protections_repo_pattern_uindex
+    on branch_protections (repo, pattern);
+
+-- Webhooks
+
+create table webhooks
+(
+    id         serial
+        constraint webhooks_pk
+            primary key,
+    repo       integer                                not null
+        constraint webhooks_repositories_id_fk
+            references repositories
+            on delete cascade,
+    url        varchar(2048)                          not null,
+    secret     varchar(256)                           not null,
+    events     integer                  default 1     not null,
+    created_at timestamp with time zone default now() not null
+);
+
+create index webhooks_repo_index
+    on webhooks (repo);
+
+create table webhook_deliveries
+(
+    id           serial
+        constraint webhook_deliveries_pk
+            primary key,
+    webhook      integer                                not null
+        constraint webhook_deliveries_webhooks_id_fk
+            references webhooks
+            on delete cascade,
+    event        varchar(32)                            not null,
+    status_code  integer,
+    success      boolean                                not null,
+    error        text,
+    delivered_at timestamp with time zone default now() not null
+);
+
+create index webhook_deliveries_webhook_index
+    on webhook_deliveries (webhook);
+
+-- Language statistics
+
+create table repository_languages
+(
+    repo        integer                                not null
+        constraint repository_languages_pk
+            primary key
+        constraint repository_languages_repositories_id_fk
+            references repositories
+            on delete cascade,
+    tree        char(40)                               not null,
+    languages   jsonb                                  not null,
+    computed_at timestamp with time zone default now() not null
+);
+
+-- Blame
+
+create table blame_cache
+(
+    repo        integer                                not null
+        constraint blame_cache_repositories_id_fk
+            references repositories
+            on delete cascade,
+    blob        char(40)                               not null,
+    path        text                                   not null,
+    hunks       jsonb                                  not null,
+    computed_at timestamp with time zone default now() not null,
+    constraint blame_cache_pk
+        primary key (repo, blob, path)
+);
+
+-- Imports
+
+create type import_state as enum ('queued', 'running', 'finished', 'failed');
+
+create table import_jobs
+(
+    id         serial
+        constraint import_jobs_pk
+            primary key,
+    repo       integer                                not null
+        constraint import_jobs_repositories_id_fk
+            references repositories
+            on delete cascade,
+    url        varchar(2048)                          not null,
+    state      import_state default 'queued'          not null,
+    progress   smallint     default 0                 not null,
+    error      text,
+    created_at timestamp with time zone default now() not null,
+    updated_at timestamp with time zone default now() not null
+);
+
+comment on table import_jobs is 'Imports are run by the workhorse, which updates state and progress (0 - 100) while cloning';
+comment on column import_jobs.url is 'Never contains credentials, these are only passed to the workhorse';
+
+create index import_jobs_repo_index
+    on import_jobs (repo);
+
+-- Pull mirrors
+
+create table mirror_settings
+(
+    repo              integer                                not null
+        constraint mirror_settings_pk
+            primary key
+        constraint mirror_settings_repositories_id_fk
+            references repositories
+            on delete cascade,
+    interval          integer      default 3600              not null,
+    next_sync_at      timestamp with time zone default now() not null,
+    last_sync_at      timestamp with time zone,
+    last_sync_success boolean,
+    last_error        text
+);
+
+comment on table mirror_settings is 'Mirrors fetch from repositories.mirrored_from every interval seconds, synced by the workhorse';
+
+-- Background jobs
+
+create table jobs
+(
+    id         serial
+        constraint jobs_pk
+            primary key,
+    kind       varchar(32)                            not null,
+    payload    jsonb                                  not null,
+    attempts   integer      default 0                 not null,
+    run_at     timestamp with time zone default now() not null,
+    last_error text,
+    failed_at  timestamp with time zone,
+    created_at timestamp with time zone default now() not null
+);
+
+comment on table jobs is 'Work done out of band by the job worker, such as sending emails. Finished jobs are deleted';
+comment on column jobs.failed_at is 'Set once all attempts have been used up, the job is not retried afterwards';
+
+create index jobs_run_at_index
+    on jobs (run_at)
+    where failed_at is null;
+
+-- Contributions
+
+create table contributions
+(
+    user_id integer not null
+        constraint contributions_users_id_fk
+            references users
+            on delete cascade,
+    repo_id integer not null
+        constraint contributions_repositories_id_fk
+            references repositories
+            on delete cascade,
+    day     date    not null,
+    count   integer not null,
+    constraint contributions_pk
+        primary key (user_id, repo_id, day)
+);
+
+comment on table contributions is 'Commits per author and day of the trailing year, recomputed per repository after every push';
+
+-- Follows and activities
+
+create table follows
+(
+    follower   integer                                not null
+        constraint follows_follower_fk
+            references users
+            on delete cascade,
+    followee   integer                                not null
+        constraint follows_followee_fk
+            references users
+            on delete cascade,
+    created_at timestamp with time zone default now() not null,
+    constraint follows_pk
+        primary key (follower, followee),
+    constraint follows_not_self_check
+        check (follower <> followee)
+);
+
+create index follows_followee_index
+    on follows (followee);
+
+create type activity_kind as enum ('push', 'release', 'repository');
+
+create table activities
+(
+    id         serial
+        constraint activities_pk
+            primary key,
+    actor      integer                                not null
+        constraint activities_users_id_fk
+            references users
+            on delete cascade,
+    repo       integer                                not null
+        constraint activities_repositories_id_fk
+            references repositories
+            on delete cascade,
+    kind       activity_kind                          not null,
+    payload    jsonb                    default '{}'  not null,
+    created_at timestamp with time zone default now() not null
+);
+
+create index activities_actor_id_index
+    on activities (actor, id desc);
+
+comment on table activities is 'Pushes, releases and new repositories shown in the feed of followers';
+
+-- Invite codes
+
+create table invite_codes
+(
+    id         serial
+        constraint invite_codes_pk
+            primary key,
+    hash       char(64)                               not null,
+    single_use boolean                  default true  not null,
+    uses       integer                  default 0     not null,
+    expires_at timestamp with time zone default null,
+    created_by integer
+        constraint invite_codes_users_id_fk
+            references users
+            on delete set null,
+    created_at timestamp with time zone default now() not null
+);
+
+comment on table invite_codes is 'Codes required to register if registrations.mode is invite_only';
+comment on column invite_codes.hash is 'SHA-256 of the code handed out by the admin';
+
+create unique index invite_codes_hash_uindex
+    on invite_codes (hash);
+
+-- Topics
+
+create table repo_topics
+(
+    repo  integer     not null
+        constraint repo_topics_repositories_id_fk
+            references repositories
+            on delete cascade,
+    topic varchar(35) not null,
+    constraint repo_topics_pk
+        primary key (repo, topic)
+);
+
+create index repo_topics_topic_index
+    on repo_topics (topic);
+
+-- Watches and notifications
+
+create type watch_level as enum ('all', 'participating', 'ignore');
+
+create table watches
+(
+    user_id    integer                                not null
+        constraint watches_users_id_fk
+            references users
+            on delete cascade,
+    repo       integer                                not null
+        constraint watches_repositories_id_fk
+            references repositories
+            on delete cascade,
+    level      watch_level                            not null,
+    created_at timestamp with time zone default now() not null,
+    constraint watches_pk
+        primary key (user_id, repo)
+);
+
+create index watches_repo_index
+    on watches (repo);
+
+comment on column watches.level is 'all: every new issue and release, participating: only issues mentioning the user, ignore: nothing';
+
+create type notification_kind as enum ('issue', 'release');
+
+create table notifications
+(
+    id         serial
+        constraint notifications_pk
+            primary key,
+    recipient  integer                                not null
+        constraint notifications_recipient_fk
+            references users
+            on delete cascade,
+    actor      integer
+        constraint notifications_actor_fk
+            references users
+            on delete set null,
+    repo       integer                                not null
+        constraint notifications_repositories_id_fk
+            references repositories
+            on delete cascade,
+    kind       notification_kind                      not null,
+    payload    jsonb                    default '{}'  not null,
+    read_at    timestamp with time zone default null,
+    created_at timestamp with time zone default now() not null
+);
+
+create index notifications_recipient_id_index
+    on notifications (recipient, id desc);
+
+-- Audit log
+
+create type audit_event as enum ('login_success', 'login_failure', 'token_created', 'token_revoked', 'sso_linked',
+    'permission_changed', 'repo_deleted', 'account_deleted');
+
+create table audit_log
+(
+    id         serial
+        constraint audit_log_pk
+            primary key,
+    event      audit_event                            not null,
+    actor      integer
+        constraint audit_log_users_id_fk
+            references users
+            on delete set null,
+    target     varchar(256),
+    ip_address inet,
+    details    jsonb                    default '{}'  not null,
+    created_at timestamp with time zone default now() not null
+);
+
+create index audit_log_actor_index
+    on audit_log (actor);
+
+create index audit_log_event_index
+    on audit_log (event);
+
+comment on table audit_log is 'Security-relevant events, only readable by admins';
+
+-- Known logins
+
+create table known_logins
+(
+    user_id     integer                                not null
+        constraint known_logins_users_id_fk
+            references users
+            on delete cascade,
+    ip_address  inet                                   not null,
+    user_agent  varchar(256)                           not null,
+    revoke_hash varchar(64)              default null,
+    first_seen  timestamp with time zone default now() not null,
+    last_seen   timestamp with time zone default now() not null,
+    constraint known_logins_pk
+        primary key (user_id, ip_address)
+);
+
+create unique index known_logins_revoke_hash_uindex
+    on known_logins (revoke_hash);
+
+comment on column known_logins.revoke_hash is 'SHA-256 of the token in the "this wasn''t me" link of the new login email, cleared once used';
+
 -- Settings
 -- CONTRIBUTING: This table always needs to be the last in this file. Please add new tables above this section.
 
@@ -261,10 +1121,29 @@ create unique index settings_key_uindex
 -- CONTRIBUTING: If adding new settings, please add key, default value (or null) and type below
 
 insert into settings (key, value, type) values ('domain', null, 'string');
+insert into settings (key, value, type) values ('instance.name', 'GitArena', 'string');
+insert into settings (key, value, type) values ('instance.signature.name', 'GitArena', 'string');
+insert into settings (key, value, type) values ('instance.signature.email', 'git@gitarena.com', 'string');
 insert into settings (key, value, type) values ('secret', md5((random())::text), 'string');
-insert into settings (key, value, type) values ('allow_registrations', null, 'boolean');
+insert into settings (key, value, type) values ('registrations.mode', 'open', 'string');
+insert into settings (key, value, type) values ('registrations.rate_limit.max', 10, 'int');
+insert into settings (key, value, type) values ('registrations.rate_limit.window', 3600, 'int');
+insert into settings (key, value, type) values ('registrations.rate_limit.exempt_localhost', false, 'boolean');
+insert into settings (key, value, type) values ('api.max_page_size', 100, 'int');
 insert into settings (key, value, type) values ('repositories.base_dir', null, 'string');
+insert into settings (key, value, type) values ('repositories.default_branch', 'main', 'string');
 insert into settings (key, value, type) values ('repositories.importing_enabled', true, 'boolean');
+insert into settings (key, value, type) values ('repositories.mirror_min_interval', 600, 'int');
+insert into settings (key, value, type) values ('repositories.readme_names', 'README.md,README.markdown,README.rst,README.txt,README', 'string');
+insert into settings (key, value, type) values ('repositories.raw_stream_threshold', 1048576, 'int');
+insert into settings (key, value, type) values ('repositories.max_diff_size', 524288, 'int');
+insert into settings (key, value, type) values ('repositories.highlight_max_size', 1048576, 'int');
+insert into settings (key, value, type) values ('repositories.max_push_size', 1073741824, 'int');
+insert into settings (key, value, type) values ('repositories.feed_max_entries', 50, 'int');
+insert into settings (key, value, type) values ('repositories.max_topics', 20, 'int');
+insert into settings (key, value, type) values ('quotas.user_max_size', 0, 'int');
+insert into settings (key, value, type) values ('quotas.repo_max_size', 0, 'int');
+insert into settings (key, value, type) values ('releases.max_asset_size', 536870912, 'int');
 insert into settings (key, value, type) values ('hcaptcha.enabled', null, 'boolean');
 insert into settings (key, value, type) values ('hcaptcha.site_key', null, 'string');
 insert into settings (key, value, type) values ('hcaptcha.secret', null, 'string');
@@ -275,18 +1154,45 @@ insert into settings (key, value, type) values ('smtp.tls', null, 'boolean');
 insert into settings (key, value, type) values ('smtp.address', null, 'string');
 insert into settings (key, value, type) values ('smtp.username', null, 'string');
 insert into settings (key, value, type) values ('smtp.password', null, 'string');
+insert into settings (key, value, type) values ('email.templates_dir', null, 'string');
 insert into settings (key, value, type) values ('integrations.sentry.enabled', 'false', 'boolean');
 insert into settings (key, value, type) values ('integrations.sentry.dsn', null, 'string');
+insert into settings (key, value, type) values ('users.promote_first_user', true, 'boolean');
+insert into settings (key, value, type) values ('users.hide_banned_repositories', false, 'boolean');
+insert into settings (key, value, type) values ('users.reserved_names', 'about,admin,api,avatars,explore,login,logout,metrics,new,raw,register,settings,sso,static', 'string');
+insert into settings (key, value, type) values ('users.login_notifications', true, 'boolean');
+insert into settings (key, value, type) values ('passwords.min_length', 8, 'int');
+insert into settings (key, value, type) values ('passwords.hibp.enabled', false, 'boolean');
+insert into settings (key, value, type) values ('passwords.reset_expiry', 3600, 'int');
+insert into settings (key, value, type) values ('passwords.argon2.memory', 4096, 'int');
+insert into settings (key, value, type) values ('passwords.argon2.iterations', 3, 'int');
+insert into settings (key, value, type) values ('passwords.argon2.parallelism', 4, 'int');
+insert into settings (key, value, type) values ('metrics.enabled', false, 'boolean');
+insert into settings (key, value, type) values ('metrics.token', null, 'string');
 insert into settings (key, value, type) values ('sessions.log_ip', true, 'boolean');
 insert into settings (key, value, type) values ('sessions.log_user_agent', true, 'boolean');
+insert into settings (key, value, type) values ('sessions.max_age', 864000, 'int');
+insert into settings (key, value, type) values ('sessions.same_site', 'lax', 'string');
+insert into settings (key, value, type) values ('shutdown.timeout', 60, 'int');
+insert into settings (key, value, type) values ('security.csp', 'default-src ''self''; script-src ''self'' ''nonce-{nonce}'' https://hcaptcha.com https://*.hcaptcha.com; style-src ''self'' ''unsafe-inline'' https://hcaptcha.com https://*.hcaptcha.com; img-src ''self'' data: https:; frame-src https://hcaptcha.com https://*.hcaptcha.com; connect-src ''self'' https://hcaptcha.com https://*.hcaptcha.com; object-src ''none''; base-uri ''self''', 'string');
+insert into settings (key, value, type) values ('security.frame_options', 'DENY', 'string');
+insert into settings (key, value, type) values ('security.referrer_policy', 'strict-origin-when-cross-origin', 'string');
+insert into settings (key, value, type) values ('security.hsts_max_age', 0, 'int');
+insert into settings (key, value, type) values ('security.trusted_proxies', null, 'string');
 insert into settings (key, value, type) values ('avatars.gravatar', true, 'boolean');
 insert into settings (key, value, type) values ('avatars.dir', 'avatars', 'string');
+insert into settings (key, value, type) values ('avatars.max_upload_size', 2097152, 'int');
 insert into settings (key, value, type) values ('sso.github.enabled', false, 'boolean');
 insert into settings (key, value, type) values ('sso.github.client_id', null, 'string');
 insert into settings (key, value, type) values ('sso.github.client_secret', null, 'string');
 insert into settings (key, value, type) values ('sso.gitlab.enabled', false, 'boolean');
+insert into settings (key, value, type) values ('sso.gitlab.base_url', 'https://gitlab.com', 'string');
 insert into settings (key, value, type) values ('sso.gitlab.app_id', null, 'string');
 insert into settings (key, value, type) values ('sso.gitlab.client_secret', null, 'string');
 insert into settings (key, value, type) values ('sso.bitbucket.enabled', false, 'boolean');
 insert into settings (key, value, type) values ('sso.bitbucket.key', null, 'string');
 insert into settings (key, value, type) values ('sso.bitbucket.secret', null, 'string');
+insert into settings (key, value, type) values ('sso.oidc.enabled', false, 'boolean');
+insert into settings (key, value, type) values ('sso.oidc.issuer_url', null, 'string');
+insert into settings (key, value, type) values ('sso.oidc.client_id', null, 'string');
+insert into settings (key, value, type) values ('sso.oidc.client_secret', null, 'string');
@@ -0,0 +1,110 @@
+//! Personal access tokens allow authenticating Git over HTTPS and API requests without using the account password.
+//!
+//! Tokens have the format `gitarena_<prefix><secret>`. The prefix is stored in plain text in order to look up the token
+//! and to display it to the user. The full token is only stored as a hash and thus only shown once upon creation.
+
+use crate::crypto;
+use crate::user::User;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, FromRow, Postgres};
+
+pub(crate) const TOKEN_PREFIX: &str = "gitarena_";
+
+const LOOKUP_PREFIX_LENGTH: usize = 8;
+const SECRET_LENGTH: usize = 32;
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", name)]
+pub(crate) struct PersonalAccessToken {
+    pub(crate) id: i32,
+    pub(crate) owner: i32,
+    pub(crate) name: String,
+    pub(crate) prefix: String,
+    #[serde(skip_serializing)]
+    pub(crate) hash: String,
+    pub(crate) scopes: i32,
+    pub(crate) created_at: DateTime<Local>,
+    pub(crate) expires_at: Option<DateTime<Local>>
+}
+
+impl PersonalAccessToken {
+    /// Creates a new access token for `user` and returns it alongside the plain text token.
+    /// The plain text token cannot be recovered afterwards
+    pub(crate) async fn create<'e, E: Executor<'e, Database = Postgres>>(user: &User, name: &str, scopes: TokenScopes, expires_at: Option<DateTime<Local>>, executor: E) -> Result<(PersonalAccessToken, String)> {
+        let prefix = crypto::random_numeric_ascii_string(LOOKUP_PREFIX_LENGTH);
+        let secret = crypto::random_numeric_ascii_string(SECRET_LENGTH);
+        let token = format!("{}{}{}", TOKEN_PREFIX, prefix, secret);
+
+        let hash = crypto::hash_password(token.as_str())?;
+
+        let access_token = sqlx::query_as::<_, PersonalAccessToken>("insert into access_tokens (owner, name, prefix, hash, scopes, expires_at) values ($1, $2, $3, $4, $5, $6) returning *")
+            .bind(&user.id)
+            .bind(name)
+            .bind(prefix.as_str())
+            .bind(hash.as_str())
+            .bind(scopes.bits())
+            .bind(&expires_at)
+            .fetch_one(executor)
+            .await?;
+
+        Ok((access_token, token))
+    }
+
+    /// Finds the access token matching the plain text `token`. Expired tokens are returned as well, use [is_expired](PersonalAccessToken::is_expired) to check them
+    pub(crate) async fn find_using_token<'e, E: Executor<'e, Database = Postgres>>(token: &str, executor: E) -> Result<Option<PersonalAccessToken>> {
+        let prefix = match token.strip_prefix(TOKEN_PREFIX) {
+            Some(stripped) if stripped.len() == LOOKUP_PREFIX_LENGTH + SECRET_LENGTH && stripped.is_char_boundary(LOOKUP_PREFIX_LENGTH) => &stripped[..LOOKUP_PREFIX_LENGTH],
+            _ => return Ok(None)
+        };
+
+        let candidates: Vec<PersonalAccessToken> = sqlx::query_as::<_, PersonalAccessToken>("select * from access_tokens where prefix = $1")
+            .bind(prefix)
+            .fetch_all(executor)
+            .await?;
+
+        for candidate in candidates {
+            if crypto::check_token(&candidate, token)? {
+                return Ok(Some(candidate));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expires_at.map_or_else(|| false, |expires_at| expires_at < Local::now())
+    }
+
+    pub(crate) fn has_scope(&self, scope: TokenScopes) -> bool {
+        TokenScopes::from_bits(self.scopes).contains(scope)
+    }
+}
+
+/// Bitflag of permissions granted to an access token
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub(crate) struct TokenScopes(i32);
+
+impl TokenScopes {
+    pub(crate) const READ_REPOSITORY: TokenScopes = TokenScopes(1 << 0);
+    pub(crate) const WRITE_REPOSITORY: TokenScopes = TokenScopes(1 << 1);
+
+    pub(crate) const ALL: TokenScopes = TokenScopes((1 << 2) - 1);
+
+    /// Creates scopes from raw bits, dropping any unknown bits
+    pub(crate) fn from_bits(bits: i32) -> TokenScopes {
+        TokenScopes(bits & TokenScopes::ALL.0)
+    }
+
+    pub(crate) fn bits(&self) -> i32 {
+        self.0
+    }
+
+    pub(crate) fn contains(&self, other: TokenScopes) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
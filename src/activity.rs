@@ -0,0 +1,107 @@
+//! Activities of users shown in the feed of their followers.
+//!
+//! Activities are recorded regardless of the visibility of the repository they happened in. Visibility is checked when
+//! the feed is read instead, so changing the visibility of a repository (or the access of a viewer) applies to past activities as well.
+
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::User;
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{Executor, FromRow, Postgres, Transaction, Type};
+use tracing::instrument;
+
+#[derive(Type, Debug, Clone, Copy, Serialize)]
+#[sqlx(type_name = "activity_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ActivityKind {
+    Push,
+    Release,
+    Repository
+}
+
+/// Single entry of a feed, already joined with the names required to link to the actor and repository
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct FeedEntry {
+    pub(crate) id: i32,
+    pub(crate) actor: i32,
+    pub(crate) actor_username: String,
+    pub(crate) repo: i32,
+    pub(crate) repo_owner: String,
+    pub(crate) repo_name: String,
+    pub(crate) kind: ActivityKind,
+    /// Kind specific details: the pushed refs for pushes, tag and title for releases and the forked repository id for new repositories
+    pub(crate) payload: Value,
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>
+}
+
+#[instrument(err, skip(executor))]
+pub(crate) async fn record<'e, E: Executor<'e, Database = Postgres>>(actor: &User, repo: &Repository, kind: ActivityKind, payload: Value, executor: E) -> Result<()> {
+    sqlx::query("insert into activities (actor, repo, kind, payload) values ($1, $2, $3, $4)")
+        .bind(&actor.id)
+        .bind(&repo.id)
+        .bind(&kind)
+        .bind(&payload)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns up to `limit` activities of users followed by `viewer`, newest first. If `after` is set, only activities older than it are returned.
+/// Activities in repositories `viewer` has no access to are skipped, so more rows are fetched until the page is full
+pub(crate) async fn feed(viewer: &User, after: Option<i32>, limit: i64, transaction: &mut Transaction<'_, Postgres>) -> Result<Vec<FeedEntry>> {
+    let mut entries = Vec::with_capacity(limit as usize);
+    let mut access = HashMap::<i32, bool>::new();
+    let mut cursor = after;
+
+    loop {
+        let batch: Vec<FeedEntry> = sqlx::query_as::<_, FeedEntry>("select a.id, a.actor, actors.username as actor_username, a.repo, \
+            owners.username as repo_owner, r.name as repo_name, a.kind, a.payload, a.created_at from activities a \
+            inner join follows f on f.followee = a.actor and f.follower = $1 \
+            inner join users actors on actors.id = a.actor \
+            inner join repositories r on r.id = a.repo \
+            inner join users owners on owners.id = r.owner \
+            where ($2::integer is null or a.id < $2) order by a.id desc limit $3")
+            .bind(&viewer.id)
+            .bind(&cursor)
+            .bind(&limit)
+            .fetch_all(&mut *transaction)
+            .await?;
+
+        let exhausted = (batch.len() as i64) < limit;
+        cursor = batch.last().map(|entry| entry.id).or(cursor);
+
+        for entry in batch {
+            let visible = match access.get(&entry.repo) {
+                Some(visible) => *visible,
+                None => {
+                    let repo: Repository = sqlx::query_as::<_, Repository>("select * from repositories where id = $1 limit 1")
+                        .bind(&entry.repo)
+                        .fetch_one(&mut *transaction)
+                        .await?;
+
+                    let visible = privilege::check_access(&repo, Some(viewer), &mut *transaction).await?;
+
+                    access.insert(entry.repo, visible);
+                    visible
+                }
+            };
+
+            if visible && (entries.len() as i64) < limit {
+                entries.push(entry);
+            }
+        }
+
+        if exhausted || entries.len() as i64 >= limit {
+            return Ok(entries);
+        }
+    }
+}
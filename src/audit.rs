@@ -0,0 +1,97 @@
+//! Audit trail of security-relevant events such as logins, access tokens and permission changes.
+//!
+//! Entries are written using their own connection instead of the transaction of the request, so failed logins are
+//! recorded even though the request's transaction is rolled back. Failing to write an entry never fails the request
+//! itself, errors are only logged.
+
+use crate::session;
+use crate::user::User;
+
+use actix_web::HttpRequest;
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use ipnetwork::IpNetwork;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Executor, FromRow, PgPool, Postgres, Type};
+
+#[derive(Type, Display, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[sqlx(type_name = "audit_event", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AuditEvent {
+    LoginSuccess,
+    LoginFailure,
+    TokenCreated,
+    TokenRevoked,
+    SsoLinked,
+    PermissionChanged,
+    RepoDeleted,
+    AccountDeleted
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct AuditLogEntry {
+    pub(crate) id: i32,
+    pub(crate) event: AuditEvent,
+    pub(crate) actor: Option<i32>,
+    pub(crate) actor_username: Option<String>,
+    /// What the event happened to, e.g. `user:mellowagain`, `repo:mellowagain/gitarena` or `token:12`
+    pub(crate) target: Option<String>,
+    pub(crate) ip_address: Option<IpNetwork>,
+    /// Event specific details such as the login method or the old and new role
+    pub(crate) details: Value,
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Records `event` caused by `actor` (if known) from the client of `request`.
+/// Never fails, errors while writing the entry are logged as warning
+pub(crate) async fn record<T: ToString>(request: &HttpRequest, event: AuditEvent, actor: Option<&User>, target: Option<T>, details: Value) {
+    let db_pool = match request.app_data::<Data<PgPool>>() {
+        Some(db_pool) => db_pool,
+        None => {
+            warn!("Unable to write audit log entry for {}: No database pool registered", event);
+            return;
+        }
+    };
+
+    let (ip_address, _) = session::extract_ip_and_ua(request);
+    let target = target.map(|target| target.to_string());
+
+    if let Err(err) = insert(event, actor.map(|user| user.id), target.as_deref(), &ip_address, &details, db_pool.get_ref()).await {
+        warn!("Failed to write audit log entry for {} (actor: {:?}, target: {:?}): {}", event, actor.map(|user| user.id), target, err);
+    }
+}
+
+async fn insert<'e, E: Executor<'e, Database = Postgres>>(event: AuditEvent, actor: Option<i32>, target: Option<&str>, ip_address: &IpNetwork, details: &Value, executor: E) -> Result<()> {
+    sqlx::query("insert into audit_log (event, actor, target, ip_address, details) values ($1, $2, $3, $4, $5)")
+        .bind(&event)
+        .bind(&actor)
+        .bind(target)
+        .bind(ip_address)
+        .bind(details)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns up to `limit` entries, newest first, optionally filtered by actor and event.
+/// If `after` is set, only entries older than it are returned
+pub(crate) async fn query<'e, E: Executor<'e, Database = Postgres>>(actor: Option<i32>, event: Option<AuditEvent>, after: Option<i32>, limit: i64, executor: E) -> Result<Vec<AuditLogEntry>> {
+    Ok(sqlx::query_as::<_, AuditLogEntry>("select audit_log.id, audit_log.event, audit_log.actor, users.username as actor_username, \
+        audit_log.target, audit_log.ip_address, audit_log.details, audit_log.created_at from audit_log \
+        left join users on users.id = audit_log.actor \
+        where ($1::integer is null or audit_log.actor = $1) and ($2::audit_event is null or audit_log.event = $2) \
+        and ($3::integer is null or audit_log.id < $3) order by audit_log.id desc limit $4")
+        .bind(&actor)
+        .bind(&event)
+        .bind(&after)
+        .bind(&limit)
+        .fetch_all(executor)
+        .await?)
+}
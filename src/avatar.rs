@@ -0,0 +1,112 @@
+//! Avatar resolution for users who have not uploaded an avatar.
+//!
+//! If the `avatars.gravatar` setting is enabled, avatars are looked up on Gravatar using the SHA256 hash of the primary email.
+//! Otherwise an identicon is generated locally, so the email hash never leaves the instance.
+
+use crate::config::get_setting;
+use crate::mail::Email;
+use crate::user::User;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use sha2::{Digest, Sha256};
+use sqlx::{Postgres, Transaction};
+
+/// Sizes in pixels uploaded avatars are stored in, the last one is the largest
+pub(crate) const UPLOAD_SIZES: [u32; 3] = [40, 100, 500];
+
+/// Uploads larger than this in either dimension are rejected before being decoded, as small files may still decompress into huge images
+pub(crate) const MAX_UPLOAD_DIMENSION: u32 = 4096;
+
+/// Amount of cells per row and column of identicons
+const IDENTICON_CELLS: u32 = 5;
+/// Size in pixels of a single cell, including the margin this results in 250x250 pixel images
+const IDENTICON_CELL_SIZE: u32 = 40;
+const IDENTICON_MARGIN: u32 = 25;
+
+/// Builds the Gravatar url for an email address, falling back to a Gravatar generated identicon if no Gravatar exists.
+/// Gravatar accepts SHA256 hashes of the trimmed, lowercase address
+pub(crate) fn gravatar_url(email: &str, size: u32) -> String {
+    let hash = hex::encode(Sha256::digest(email.trim().to_lowercase().as_bytes()));
+
+    format!("https://www.gravatar.com/avatar/{}?s={}&r=pg&d=identicon", hash, size)
+}
+
+/// Url of the avatar for `user_id` served by GitArena itself
+pub(crate) fn local_url(user_id: i32) -> String {
+    format!("/api/avatar/{}", user_id)
+}
+
+/// Url of the avatar uploaded by `user_id`
+pub(crate) fn upload_url(user_id: i32) -> String {
+    format!("/avatars/{}", user_id)
+}
+
+/// Path of the uploaded avatar of `user_id` in a specific size, which needs to be one of [UPLOAD_SIZES]
+pub(crate) fn upload_path(avatars_dir: &str, user_id: i32, size: u32) -> PathBuf {
+    Path::new(avatars_dir).join(user_id.to_string()).join(format!("{}.jpg", size))
+}
+
+/// Returns whenever `user_id` has uploaded an avatar
+pub(crate) fn has_upload(avatars_dir: &str, user_id: i32) -> bool {
+    upload_path(avatars_dir, user_id, UPLOAD_SIZES[UPLOAD_SIZES.len() - 1]).is_file()
+}
+
+/// Resolves the url clients should load the avatar of `user` from: Uploaded avatars are preferred over Gravatar,
+/// which is used directly if enabled. Otherwise the identicon generated by GitArena is used
+pub(crate) async fn resolve_url(user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<String> {
+    let avatars_dir = get_setting::<String, _>("avatars.dir", &mut *transaction).await?;
+
+    if has_upload(avatars_dir.as_str(), user.id) {
+        return Ok(upload_url(user.id));
+    }
+
+    if !get_setting::<bool, _>("avatars.gravatar", &mut *transaction).await? {
+        return Ok(local_url(user.id));
+    }
+
+    Ok(match Email::find_primary_email(user, &mut *transaction).await? {
+        Some(email) => gravatar_url(email.email.as_str(), 500),
+        None => local_url(user.id)
+    })
+}
+
+/// Generates a PNG encoded, horizontally symmetric identicon. The same `seed` always results in the same image
+pub(crate) fn identicon(seed: &[u8]) -> Result<Vec<u8>> {
+    let hash = Sha256::digest(seed);
+
+    // First three bytes are used for the color, the following ones decide which cells are filled
+    let color = Rgb([hash[0] / 2 + 64, hash[1] / 2 + 64, hash[2] / 2 + 64]);
+    let background = Rgb([240, 240, 240]);
+
+    let half = (IDENTICON_CELLS + 1) / 2;
+    let filled = |column: u32, row: u32| -> bool {
+        let column = column.min(IDENTICON_CELLS - 1 - column);
+        hash[3 + (row * half + column) as usize] % 2 == 0
+    };
+
+    let size = IDENTICON_CELLS * IDENTICON_CELL_SIZE + IDENTICON_MARGIN * 2;
+    let image = RgbImage::from_fn(size, size, |x, y| {
+        let inner = IDENTICON_MARGIN..size - IDENTICON_MARGIN;
+
+        if !inner.contains(&x) || !inner.contains(&y) {
+            return background;
+        }
+
+        let column = (x - IDENTICON_MARGIN) / IDENTICON_CELL_SIZE;
+        let row = (y - IDENTICON_MARGIN) / IDENTICON_CELL_SIZE;
+
+        if filled(column, row) {
+            color
+        } else {
+            background
+        }
+    });
+
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(image).write_to(&mut bytes, ImageOutputFormat::Png)?;
+
+    Ok(bytes)
+}
@@ -0,0 +1,142 @@
+//! Branch protection rules which restrict what kind of ref updates are accepted in receive-pack.
+//!
+//! Rules apply to all branches matching their pattern. If multiple rules match a branch, the most restrictive combination applies.
+
+use crate::git::ref_update::{RefUpdate, RefUpdateType};
+use crate::git::signature::{self, VerificationStatus};
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::User;
+use crate::utils::glob;
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use derive_more::Display;
+use git2::{Oid, Repository as Git2Repository};
+use serde::Serialize;
+use sqlx::{Executor, FromRow, Postgres, Transaction};
+use tracing::instrument;
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", pattern)]
+pub(crate) struct BranchProtection {
+    pub(crate) id: i32,
+    pub(crate) repo: i32,
+    pub(crate) pattern: String,
+    pub(crate) no_force_push: bool,
+    pub(crate) no_delete: bool,
+    pub(crate) require_signed: bool,
+    pub(crate) allow_admin_force_push: bool,
+    pub(crate) created_at: DateTime<Local>
+}
+
+impl BranchProtection {
+    pub(crate) async fn all<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, executor: E) -> Result<Vec<BranchProtection>> {
+        Ok(sqlx::query_as::<_, BranchProtection>("select * from branch_protections where repo = $1 order by id")
+            .bind(&repo.id)
+            .fetch_all(executor)
+            .await?)
+    }
+
+    /// Checks if `branch` (without `refs/heads/`) matches the pattern of this rule. `*` matches any amount of characters
+    pub(crate) fn matches(&self, branch: &str) -> bool {
+        glob::matches(self.pattern.as_str(), branch)
+    }
+}
+
+/// Checks `update` against the branch protection rules of `repo`.
+/// Returns `None` if the update is allowed or the reason why it was rejected. `user` is `None` for pushes using a deploy key.
+///
+/// New objects sent by the client are needed to detect force pushes and unsigned commits. They're read from `quarantine`,
+/// an objects directory outside of the repository, so objects of rejected pushes never end up in the repository itself
+#[instrument(err, skip(transaction))]
+pub(crate) async fn check_update(update: &RefUpdate, repo: &Repository, user: Option<&User>, quarantine: Option<&Path>, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<String>> {
+    let branch = match update.target_ref.strip_prefix("refs/heads/") {
+        Some(branch) => branch,
+        None => return Ok(None)
+    };
+
+    let rules = BranchProtection::all(repo, &mut *transaction).await?
+        .into_iter()
+        .filter(|rule| rule.matches(branch))
+        .collect::<Vec<_>>();
+
+    if rules.is_empty() {
+        return Ok(None);
+    }
+
+    let no_delete = rules.iter().any(|rule| rule.no_delete);
+    let no_force_push = rules.iter().any(|rule| rule.no_force_push);
+    let require_signed = rules.iter().any(|rule| rule.require_signed);
+    let allow_admin_force_push = rules.iter().filter(|rule| rule.no_force_push).all(|rule| rule.allow_admin_force_push);
+
+    let is_admin = privilege::check_admin(repo, user, &mut *transaction).await?;
+
+    let update_type = RefUpdateType::determinate(&update.old, &update.new).await?;
+
+    if matches!(update_type, RefUpdateType::Delete) {
+        return Ok(if no_delete && !is_admin {
+            Some(format!("branch {} is protected against deletion", branch))
+        } else {
+            None
+        });
+    }
+
+    if !(no_force_push && !(is_admin && allow_admin_force_push)) && !require_signed {
+        return Ok(None);
+    }
+
+    let git2_repo = repo.libgit2(&mut *transaction).await?;
+
+    // The odb is shared with `git2_repo`, which is only opened for this check, so the alternate doesn't outlive it
+    if let Some(quarantine) = quarantine {
+        let path = quarantine.to_str().ok_or_else(|| anyhow!("Quarantine path is not valid unicode"))?;
+        git2_repo.odb()?.add_disk_alternate(path)?;
+    }
+
+    let new_oid = Oid::from_str(update.new.as_deref().unwrap_or_default())?;
+
+    if git2_repo.find_object(new_oid, None).is_err() {
+        return Ok(Some("missing necessary objects".to_owned()));
+    }
+
+    // The old oid sent by the client can't be trusted, a client claiming to create a branch which already exists would otherwise skip the checks below
+    let old_oid = git2_repo.refname_to_id(update.target_ref.as_str()).ok();
+
+    if let Some(old_oid) = old_oid {
+        if no_force_push && !(is_admin && allow_admin_force_push) && old_oid != new_oid && !git2_repo.graph_descendant_of(new_oid, old_oid)? {
+            return Ok(Some(format!("force pushes are not allowed on protected branch {}", branch)));
+        }
+    }
+
+    if require_signed {
+        if let Some(oid) = first_unsigned_commit(&git2_repo, new_oid, old_oid, &mut *transaction).await? {
+            return Ok(Some(format!("commit {} is not signed with a verified signature", oid)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Walks all commits which are new in this push and returns the first one without a valid signature
+async fn first_unsigned_commit(repo: &Git2Repository, new_oid: Oid, old_oid: Option<Oid>, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<Oid>> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(new_oid)?;
+
+    match old_oid {
+        Some(old_oid) => revwalk.hide(old_oid)?,
+        None => revwalk.hide_glob("refs/heads/*")? // New branch, only check commits which are not on any other branch yet
+    }
+
+    for oid in revwalk {
+        let oid = oid?;
+
+        if signature::verify_commit(repo, oid, &mut *transaction).await? != Some(VerificationStatus::Valid) {
+            return Ok(Some(oid));
+        }
+    }
+
+    Ok(None)
+}
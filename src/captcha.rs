@@ -1,4 +1,4 @@
-use crate::config::get_optional_setting;
+use crate::config::{get_optional_setting, get_setting};
 use crate::err;
 use crate::prelude::AwcExtensions;
 
@@ -6,17 +6,21 @@ use anyhow::Result;
 use awc::Client;
 use log::{error, warn};
 use serde::{Deserialize, Serialize};
-use sqlx::{Executor, Postgres};
+use sqlx::{Executor, Postgres, Transaction};
 
-pub(crate) async fn verify_captcha<'e, E: Executor<'e, Database = Postgres>>(token: &String, executor: E) -> Result<bool> {
-    let api_key = match get_optional_setting::<String, _>("hcaptcha.site_key", executor).await? {
-        Some(api_key) => api_key,
-        None => return Ok(true)
-    };
+/// Returns whenever registrations have to solve a captcha. Disabled unless `hcaptcha.enabled` is set, so development setups work without hCaptcha keys
+pub(crate) async fn is_enabled<'e, E: Executor<'e, Database = Postgres>>(executor: E) -> Result<bool> {
+    Ok(get_optional_setting::<bool, _>("hcaptcha.enabled", executor).await?.unwrap_or(false))
+}
+
+/// Verifies `token` with hCaptcha. Callers need to check [is_enabled] first, this function always contacts hCaptcha
+pub(crate) async fn verify_captcha(token: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<bool> {
+    let secret = get_setting::<String, _>("hcaptcha.secret", &mut *transaction).await?;
+    let site_key = get_setting::<String, _>("hcaptcha.site_key", &mut *transaction).await?;
 
     let response: HCaptchaResponse = Client::gitarena()
         .post("https://hcaptcha.com/siteverify")
-        .send_form(&[("response", token), ("secret", &api_key)])
+        .send_form(&[("response", token), ("secret", secret.as_str()), ("sitekey", site_key.as_str())])
         .await
         .map_err(|err| err!(BAD_GATEWAY, "Unable to verify hCaptcha captcha token: {}", err))?
         .json()
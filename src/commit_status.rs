@@ -0,0 +1,76 @@
+//! Statuses reported by external services such as CI systems for a commit.
+//!
+//! Each service reports under its own context (for example `ci/build`). Statuses are only ever inserted,
+//! the latest one of every context is the current one.
+
+use crate::repository::Repository;
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, FromRow, Postgres, Type};
+
+/// Ordered from best to worst, so the combined state is the [maximum](Iterator::max) of all states
+#[derive(Type, Display, Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Deserialize, Serialize)]
+#[sqlx(type_name = "commit_state", rename_all = "lowercase")]
+#[serde(rename_all(serialize = "lowercase", deserialize = "lowercase"))]
+pub(crate) enum CommitState {
+    Success,
+    Pending,
+    Failure,
+    Error
+}
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}: {}", context, state)]
+pub(crate) struct CommitStatus {
+    pub(crate) id: i32,
+    pub(crate) repo: i32,
+    pub(crate) sha: String,
+    pub(crate) context: String,
+    pub(crate) state: CommitState,
+    pub(crate) target_url: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) creator: Option<i32>,
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>
+}
+
+impl CommitStatus {
+    /// Returns the latest status of every context reported for `sha`, ordered by context
+    pub(crate) async fn latest<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, sha: &str, executor: E) -> Result<Vec<CommitStatus>> {
+        Ok(sqlx::query_as::<_, CommitStatus>("select distinct on (context) * from commit_statuses where repo = $1 and sha = $2 order by context, id desc")
+            .bind(&repo.id)
+            .bind(sha)
+            .fetch_all(executor)
+            .await?)
+    }
+}
+
+/// Combines the statuses of a commit into a single state. Returns `None` if no status has been reported
+pub(crate) fn combined_state(statuses: &[CommitStatus]) -> Option<CommitState> {
+    statuses.iter().map(|status| status.state).max()
+}
+
+/// Looks up the combined state of multiple commits at once. Commits without any status are not part of the result
+pub(crate) async fn combined_states<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, shas: &[String], executor: E) -> Result<HashMap<String, CommitState>> {
+    let latest: Vec<(String, CommitState)> = sqlx::query_as("select distinct on (sha, context) sha, state from commit_statuses \
+        where repo = $1 and sha = any($2) order by sha, context, id desc")
+        .bind(&repo.id)
+        .bind(shas)
+        .fetch_all(executor)
+        .await?;
+
+    let mut states = HashMap::with_capacity(shas.len());
+
+    for (sha, state) in latest {
+        let combined = states.entry(sha).or_insert(state);
+        *combined = state.max(*combined);
+    }
+
+    Ok(states)
+}
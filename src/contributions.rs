@@ -0,0 +1,167 @@
+//! Daily commit counts shown as contribution calendar on user profiles.
+//!
+//! Counts are stored per repository in the `contributions` table and recomputed for the trailing year after every push,
+//! so visibility can be checked per repository when they are read. Commits are attributed using all verified emails
+//! (and the noreply alias) of an user, just like [try_disassemble](crate::prelude::LibGit2SignatureExtensions::try_disassemble).
+
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::User;
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+use git2::Sort;
+use serde::Serialize;
+use sqlx::{Postgres, Transaction};
+use tracing::instrument;
+
+/// Amount of days shown in the calendar
+const DAYS: i64 = 365;
+
+/// A single day in the calendar. `level` ranges from 0 (no contributions) to 4 and determines the color of the cell
+#[derive(Debug, Serialize)]
+pub(crate) struct CalendarDay {
+    pub(crate) date: NaiveDate,
+    pub(crate) count: i64,
+    pub(crate) level: u8
+}
+
+/// Recounts the commits of the trailing year reachable from any branch of `repo` and replaces the stored counts
+#[instrument(err, skip(transaction))]
+pub(crate) async fn refresh(repo: &Repository, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    let since = (Utc::now() - Duration::days(DAYS)).timestamp();
+    let libgit2_repo = repo.libgit2(&mut *transaction).await?;
+
+    let mut walk = libgit2_repo.revwalk()?;
+    walk.set_sorting(Sort::TIME)?;
+    walk.push_glob("refs/heads/*")?;
+
+    let mut per_email = HashMap::<(String, NaiveDate), i64>::new();
+
+    for oid in walk {
+        let commit = libgit2_repo.find_commit(oid?)?;
+        let time = commit.time().seconds();
+
+        // Commits are sorted by time, so everything that follows is older as well
+        if time < since {
+            break;
+        }
+
+        let author = commit.author();
+        let email = match author.email() {
+            Some(email) => email.to_lowercase(),
+            None => continue
+        };
+
+        let date = NaiveDateTime::from_timestamp(time, 0).date();
+
+        *per_email.entry((email, date)).or_default() += 1;
+    }
+
+    let emails = per_email.keys().map(|(email, _)| email.as_str()).collect::<Vec<_>>();
+    let users = User::find_using_emails(emails.as_slice(), &mut *transaction).await?;
+
+    let mut per_user = HashMap::<(i32, NaiveDate), i64>::new();
+
+    for ((email, date), count) in per_email.iter() {
+        if let Some(user) = users.get(email) {
+            *per_user.entry((user.id, *date)).or_default() += count;
+        }
+    }
+
+    sqlx::query("delete from contributions where repo_id = $1")
+        .bind(&repo.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    let (user_ids, (days, counts)): (Vec<i32>, (Vec<NaiveDate>, Vec<i64>)) = per_user.into_iter()
+        .map(|((user_id, date), count)| (user_id, (date, count)))
+        .unzip();
+
+    sqlx::query("insert into contributions (user_id, repo_id, day, count) select user_id, $1, day, count from unnest($2, $3, $4) as t(user_id, day, count)")
+        .bind(&repo.id)
+        .bind(&user_ids)
+        .bind(&days)
+        .bind(&counts)
+        .execute(&mut *transaction)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the contributions of `user` during the trailing year per day, only counting repositories `viewer` has access to
+pub(crate) async fn for_viewer(user: &User, viewer: Option<&User>, transaction: &mut Transaction<'_, Postgres>) -> Result<BTreeMap<NaiveDate, i64>> {
+    let since = Utc::now().date().naive_utc() - Duration::days(DAYS);
+
+    let repos: Vec<Repository> = sqlx::query_as::<_, Repository>("select * from repositories where id in \
+        (select distinct repo_id from contributions where user_id = $1 and day > $2)")
+        .bind(&user.id)
+        .bind(&since)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    let mut visible = Vec::with_capacity(repos.len());
+
+    for repo in repos {
+        if privilege::check_access(&repo, viewer, &mut *transaction).await? {
+            visible.push(repo.id);
+        }
+    }
+
+    let rows: Vec<(NaiveDate, i64)> = sqlx::query_as("select day, sum(count)::bigint from contributions \
+        where user_id = $1 and day > $2 and repo_id = any($3) group by day")
+        .bind(&user.id)
+        .bind(&since)
+        .bind(&visible)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    Ok(rows.into_iter().collect())
+}
+
+/// Arranges `contributions` into weeks (columns) starting on Sunday, as displayed on the profile
+pub(crate) fn calendar(contributions: &BTreeMap<NaiveDate, i64>) -> Vec<Vec<CalendarDay>> {
+    let today = Utc::now().date().naive_utc();
+    let start = today - Duration::days(DAYS);
+    let start = start - Duration::days(start.weekday().num_days_from_sunday() as i64);
+
+    let max = contributions.values().copied().max().unwrap_or(0);
+
+    let mut weeks = Vec::new();
+    let mut date = start;
+
+    while date <= today {
+        let mut week = Vec::with_capacity(7);
+
+        for _ in 0..7 {
+            if date > today {
+                break;
+            }
+
+            let count = contributions.get(&date).copied().unwrap_or(0);
+
+            week.push(CalendarDay {
+                date,
+                count,
+                level: level(count, max)
+            });
+
+            date = date.succ();
+        }
+
+        weeks.push(week);
+    }
+
+    weeks
+}
+
+/// Buckets `count` into quarters of the busiest day
+fn level(count: i64, max: i64) -> u8 {
+    if count <= 0 || max <= 0 {
+        return 0;
+    }
+
+    (((count * 4) + max - 1) / max).clamp(1, 4) as u8
+}
@@ -1,21 +1,76 @@
+use crate::access_token::PersonalAccessToken;
 use crate::user::User;
 
+#[cfg(test)]
+use std::cell::Cell;
+
 use anyhow::{Context, Result};
 use argon2::{Config, ThreadMode, Variant, Version};
+use once_cell::sync::{Lazy, OnceCell};
 use rand::distributions::Distribution;
 use rand::distributions::Uniform;
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, Postgres};
+
+/// Argon2id parameters used for new hashes. Set on startup from the `passwords.argon2.*` settings.
+/// Existing hashes keep working after changing these as their parameters are part of the encoded (PHC) string
+static ARGON_PARAMS: OnceCell<ArgonParams> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ArgonParams {
+    /// Memory cost in KiB
+    pub(crate) memory: u32,
+    pub(crate) iterations: u32,
+    pub(crate) parallelism: u32
+}
+
+impl Default for ArgonParams {
+    fn default() -> ArgonParams {
+        ArgonParams {
+            memory: 4096,
+            iterations: 3,
+            parallelism: 4
+        }
+    }
+}
+
+pub(crate) fn init_argon_params(params: ArgonParams) {
+    let parallelism = params.parallelism.max(1);
+
+    let _ = ARGON_PARAMS.set(ArgonParams {
+        memory: params.memory.max(8 * parallelism), // Minimum required by Argon2
+        iterations: params.iterations.max(1),
+        parallelism
+    });
+}
 
-const ARGON_CONFIG: Config = Config {
-    ad: &[],
-    hash_length: 32,
-    lanes: 4,
-    mem_cost: 4096,
-    secret: &[],
-    thread_mode: ThreadMode::Parallel,
-    time_cost: 3,
-    variant: Variant::Argon2id,
-    version: Version::Version13
-};
+fn argon_config() -> Config<'static> {
+    let params = ARGON_PARAMS.get().copied().unwrap_or_default();
+
+    Config {
+        ad: &[],
+        hash_length: 32,
+        lanes: params.parallelism,
+        mem_cost: params.memory,
+        secret: &[],
+        thread_mode: ThreadMode::Parallel,
+        time_cost: params.iterations,
+        variant: Variant::Argon2id,
+        version: Version::Version13
+    }
+}
+
+/// Hash of a random password using the same parameters as real password hashes.
+/// Used to make authentication attempts for non-existent users take as long as ones for existing users
+static DECOY_HASH: Lazy<String> = Lazy::new(|| {
+    hash_password(random_string(32).as_str()).unwrap_or_default()
+});
+
+#[cfg(test)]
+thread_local! {
+    /// Amount of password hashes verified on the current thread, so tests can check that code paths do the same amount of work
+    pub(crate) static PASSWORD_CHECKS: Cell<usize> = Cell::new(0);
+}
 
 pub(crate) fn random_string_charset(length: usize, charset: &'static [u8]) -> String {
     let mut rng = rand::thread_rng();
@@ -45,16 +100,79 @@ pub(crate) fn random_hex_string(length: usize) -> String {
     random_string_charset(length, CHARSET)
 }
 
+/// Hashes a random, high entropy token (such as email verification tokens) for storage in the database.
+/// As these can not be brute forced a fast hash is sufficient, which also allows looking up tokens by their hash
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
 pub(crate) fn hash_password(password: &str) -> Result<String> {
     let salt = random_string(16);
 
     argon2::hash_encoded(
-        password.as_bytes(), salt.as_bytes(), &ARGON_CONFIG
+        password.as_bytes(), salt.as_bytes(), &argon_config()
     ).context("Failed to hash password")
 }
 
+/// Returns whenever `hash` has been created with weaker parameters (or another Argon2 variant) than the currently configured ones
+pub(crate) fn needs_rehash(hash: &str) -> bool {
+    // $argon2id$v=19$m=4096,t=3,p=4$<salt>$<hash>
+    let mut parts = hash.trim_end().split('$').skip(1);
+
+    if parts.next() != Some("argon2id") || parts.next() != Some("v=19") {
+        return true;
+    }
+
+    let current = ARGON_PARAMS.get().copied().unwrap_or_default();
+    let mut params = (0, 0, 0);
+
+    for param in parts.next().unwrap_or_default().split(',') {
+        match param.split_once('=') {
+            Some(("m", value)) => params.0 = value.parse().unwrap_or(0),
+            Some(("t", value)) => params.1 = value.parse().unwrap_or(0),
+            Some(("p", value)) => params.2 = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+
+    params.0 < current.memory || params.1 < current.iterations || params.2 < current.parallelism
+}
+
+/// Rehashes the password of `user` if it has been hashed with weaker parameters than the currently configured ones.
+/// Needs to be called with the plain text password right after it has been verified using [check_password]
+pub(crate) async fn upgrade_password_hash<'e, E: Executor<'e, Database = Postgres>>(user: &User, password: &str, executor: E) -> Result<()> {
+    if !needs_rehash(user.password.as_str()) {
+        return Ok(());
+    }
+
+    sqlx::query("update users set password = $1 where id = $2")
+        .bind(hash_password(password)?)
+        .bind(&user.id)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
 pub(crate) fn check_password(user: &User, password: &str) -> Result<bool> {
+    verify_password(user.password.as_str(), password).with_context(|| format!("Failed to check password for user #{}", user.id))
+}
+
+/// Runs a password check against a decoy hash and discards the result.
+/// Call this when the user does not exist in order to not leak the existence of usernames through response timing
+pub(crate) fn check_password_decoy(password: &str) {
+    let _ = verify_password(DECOY_HASH.as_str(), password);
+}
+
+fn verify_password(hash: &str, password: &str) -> Result<bool, argon2::Error> {
+    #[cfg(test)]
+    PASSWORD_CHECKS.with(|checks| checks.set(checks.get() + 1));
+
+    argon2::verify_encoded(hash, password.as_bytes())
+}
+
+pub(crate) fn check_token(access_token: &PersonalAccessToken, token: &str) -> Result<bool> {
     argon2::verify_encoded(
-        user.password.as_str(), password.as_bytes()
-    ).with_context(|| format!("Failed to check password for user #{}", user.id))
+        access_token.hash.as_str(), token.as_bytes()
+    ).with_context(|| format!("Failed to check access token #{}", access_token.id))
 }
@@ -0,0 +1,212 @@
+//! Cross-site request forgery protection for requests authenticated using the session cookie.
+//!
+//! The token is a HMAC of the session identity, so it's tied to the session and doesn't need to be stored anywhere.
+//! It's handed to the browser in the `gitarena-csrf` cookie, which (unlike the session cookie) is readable by JavaScript
+//! so it can be sent back in the `X-CSRF-Token` header. Plain HTML forms send it in the `csrf_token` field instead.
+//!
+//! Requests without a session (such as API clients using access tokens or Basic auth) can't be forged by another site
+//! and are therefore exempt, as are the Git smart HTTP endpoints which authenticate every request on their own.
+
+use crate::error::{ErrorDisplayType, GitArenaError};
+use crate::err;
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_http::h1::Payload as H1Payload;
+use actix_identity::RequestIdentity;
+use actix_web::body::BoxBody;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{CONTENT_TYPE, HeaderValue, SET_COOKIE};
+use actix_web::http::Method;
+use actix_web::web::BytesMut;
+use actix_web::Error as ActixError;
+use futures::future::{ready, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use log::warn;
+use once_cell::sync::OnceCell;
+use qstring::QString;
+use ring::constant_time::verify_slices_are_equal;
+use ring::hmac;
+use tracing_unwrap::OptionExt;
+
+pub(crate) const CSRF_COOKIE: &str = "gitarena-csrf";
+pub(crate) const CSRF_HEADER: &str = "x-csrf-token";
+pub(crate) const CSRF_FORM_FIELD: &str = "csrf_token";
+
+/// Forms are small, anything bigger isn't read to look for the token
+const MAX_FORM_SIZE: usize = 65536;
+
+static KEY: OnceCell<hmac::Key> = OnceCell::new();
+static SECURE: OnceCell<bool> = OnceCell::new();
+
+pub(crate) fn init(secret: &str, secure: bool) {
+    let _ = KEY.set(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()));
+    let _ = SECURE.set(secure);
+}
+
+/// Returns the token which needs to be sent along state-changing requests made using the session `identity`
+pub(crate) fn token(identity: &str) -> String {
+    let tag = hmac::sign(KEY.get().unwrap_or_log(), format!("csrf:{}", identity).as_bytes());
+
+    hex::encode(tag.as_ref())
+}
+
+/// Middleware rejecting state-changing requests made using the session cookie without a valid token.
+/// Needs to be wrapped *inside* of the identity service, as it reads the session identity
+pub(crate) struct CsrfProtection;
+
+impl<S> Transform<S, ServiceRequest> for CsrfProtection
+    where S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = ActixError> + 'static,
+          S::Future: 'static
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = ActixError;
+    type Transform = CsrfMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfMiddleware {
+            service: Rc::new(service)
+        }))
+    }
+}
+
+pub(crate) struct CsrfMiddleware<S> {
+    service: Rc<S>
+}
+
+impl<S> Service<ServiceRequest> for CsrfMiddleware<S>
+    where S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = ActixError> + 'static,
+          S::Future: 'static
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut request: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let expected = match request.get_identity() {
+                Some(identity) => token(identity.as_str()),
+                None => return service.call(request).await
+            };
+
+            if requires_token(&request) {
+                let provided = match header_token(&request) {
+                    Some(provided) => Some(provided),
+                    None => form_token(&mut request).await
+                };
+
+                let valid = provided.map_or(false, |provided| verify_slices_are_equal(provided.as_bytes(), expected.as_bytes()).is_ok());
+
+                if !valid {
+                    return Ok(reject(request));
+                }
+            }
+
+            let cookie_matches = request.cookie(CSRF_COOKIE).map_or(false, |cookie| cookie.value() == expected);
+            let mut response = service.call(request).await?;
+
+            if !cookie_matches {
+                let cookie = Cookie::build(CSRF_COOKIE, expected)
+                    .path("/")
+                    .same_site(SameSite::Strict)
+                    .secure(*SECURE.get().unwrap_or(&false))
+                    .finish();
+
+                match HeaderValue::from_str(cookie.to_string().as_str()) {
+                    Ok(value) => {
+                        response.headers_mut().append(SET_COOKIE, value);
+                    }
+                    Err(err) => warn!("Failed to set CSRF cookie: {}", err)
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+fn requires_token(request: &ServiceRequest) -> bool {
+    if matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE) {
+        return false;
+    }
+
+    !is_git_endpoint(request.path())
+}
+
+/// Git smart HTTP (`/{username}/{repository}.git/git-upload-pack` etc.) and LFS endpoints authenticate using Basic auth
+/// on every request, so they don't need a token. Other paths which merely contain `.git/` are not exempt
+fn is_git_endpoint(path: &str) -> bool {
+    let mut split = path.trim_start_matches('/').splitn(3, '/');
+
+    let (username, repository, rest) = match (split.next(), split.next(), split.next()) {
+        (Some(username), Some(repository), Some(rest)) => (username, repository, rest),
+        _ => return false
+    };
+
+    if username.is_empty() || repository.strip_suffix(".git").map_or(true, str::is_empty) {
+        return false;
+    }
+
+    matches!(rest, "git-upload-pack" | "git-receive-pack" | "info/refs") || rest.starts_with("info/lfs/")
+}
+
+fn header_token(request: &ServiceRequest) -> Option<String> {
+    request.headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Reads the token from an url encoded form body. The body is put back afterwards, so the route can still read it
+async fn form_token(request: &mut ServiceRequest) -> Option<String> {
+    let is_form = request.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("application/x-www-form-urlencoded"));
+
+    if !is_form {
+        return None;
+    }
+
+    let mut payload = request.take_payload();
+    let mut body = BytesMut::new();
+
+    while let Some(chunk) = payload.next().await {
+        match chunk {
+            Ok(chunk) if body.len() + chunk.len() <= MAX_FORM_SIZE => body.extend_from_slice(&chunk),
+            _ => return None
+        }
+    }
+
+    let body = body.freeze();
+    let token = QString::from(String::from_utf8_lossy(&body).as_ref()).get(CSRF_FORM_FIELD).map(str::to_owned);
+
+    let (_, mut restored) = H1Payload::create(true);
+    restored.unread_data(body);
+    request.set_payload(restored.into());
+
+    token
+}
+
+fn reject(request: ServiceRequest) -> ServiceResponse<BoxBody> {
+    let display_type = if request.path().starts_with("/api") {
+        ErrorDisplayType::Json
+    } else {
+        ErrorDisplayType::Html
+    };
+
+    let error = GitArenaError {
+        source: Arc::new(err!(FORBIDDEN, "Missing or invalid CSRF token, please reload the page and try again").into()),
+        display_type
+    };
+
+    request.error_response(error)
+}
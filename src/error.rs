@@ -1,6 +1,8 @@
 use crate::git::io::band::Band;
 use crate::git::io::writer::GitWriter;
 use crate::templates;
+use crate::utils::request_span::RequestId;
+use crate::utils::security_headers;
 
 use std::error::Error as StdError;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
@@ -20,7 +22,7 @@ use actix_web::{HttpResponse, HttpResponseBuilder, ResponseError};
 use anyhow::{Error, Result};
 use derive_more::{Display, Error};
 use log::error;
-use serde_json::json;
+use serde_json::{json, Value};
 use tera::Context;
 
 /// Returns early with an error. This macro is similar to the `bail!` macro which can be found in `anyhow`.
@@ -150,13 +152,19 @@ impl GitArenaError {
     fn status_code(&self) -> StatusCode {
         match self.source.downcast_ref::<WithStatusCode>() {
             Some(with_code) => with_code.code,
+            None if self.is_pool_timeout() => StatusCode::SERVICE_UNAVAILABLE,
             None => StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 
+    /// Whenever no database connection could be acquired from the pool within the configured acquire timeout
+    fn is_pool_timeout(&self) -> bool {
+        matches!(self.source.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::PoolTimedOut))
+    }
+
     /// Whenever this error should be printed to the console
     fn should_print(&self) -> bool {
-        self.status_code() == StatusCode::INTERNAL_SERVER_ERROR && !self.should_display_message()
+        (self.status_code() == StatusCode::INTERNAL_SERVER_ERROR && !self.should_display_message()) || self.is_pool_timeout()
     }
 
     /// Whenever this error should be displayed to the end user
@@ -167,10 +175,32 @@ impl GitArenaError {
     fn message(&self) -> String {
         if self.should_display_message() {
             self.source.to_string()
+        } else if self.is_pool_timeout() {
+            "GitArena is currently overloaded, please try again later".to_owned()
         } else {
             self.status_code().canonical_reason().map_or_else(String::new, str::to_owned)
         }
     }
+
+    /// Machine readable identifier of the status code, such as `not_found` or `too_many_requests`
+    fn code(&self) -> String {
+        self.status_code()
+            .canonical_reason()
+            .unwrap_or("unknown")
+            .to_lowercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    }
+
+    /// Envelope every JSON API error is sent in: `{ "error": { "code": ..., "message": ..., "request_id": ... } }`
+    fn json_body(&self, request_id: Option<&str>) -> Value {
+        json!({
+            "error": {
+                "code": self.code(),
+                "message": self.message(),
+                "request_id": request_id
+            }
+        })
+    }
 }
 
 impl Debug for GitArenaError {
@@ -213,15 +243,18 @@ impl ResponseError for GitArenaError {
 
                 error.error_response()
             }
-            ErrorDisplayType::Json => builder.json(json!({
-                "error": self.message()
-            })),
+            ErrorDisplayType::Json => {
+                // The middleware adds the request id to the body, as it isn't accessible from here
+                builder.extensions_mut().insert::<GitArenaError>(self.clone());
+
+                builder.json(self.json_body(None))
+            },
             ErrorDisplayType::Plain => builder.body(self.message())
         }
     }
 }
 
-/// Middleware which renders HTML and Git errors
+/// Middleware which renders HTML and Git errors and adds the [request id](RequestId) to JSON errors
 pub(crate) fn error_renderer_middleware<S, B>(request: ServiceRequest, service: &S) -> impl Future<Output = ActixResult<ServiceResponse<impl MessageBody>>> + 'static
     where S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
           S::Future: 'static,
@@ -232,11 +265,12 @@ pub(crate) fn error_renderer_middleware<S, B>(request: ServiceRequest, service:
     async {
         let mut response = future.await?.map_into_boxed_body();
         let gitarena_error = response.response_mut().extensions_mut().remove::<GitArenaError>();
+        let request_id = response.request().extensions().get::<RequestId>().map(|id| id.0.clone());
 
         Ok(if let Some(error) = gitarena_error {
             match error.display_type {
                 ErrorDisplayType::Html => {
-                    let result = render_html_error(&error).await;
+                    let result = render_html_error(&error, request_id.as_deref()).await;
 
                     response.map_body(|head, _| {
                         head.headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
@@ -245,7 +279,7 @@ pub(crate) fn error_renderer_middleware<S, B>(request: ServiceRequest, service:
                     })
                 },
                 ErrorDisplayType::Git => {
-                    let result = render_git_error(&error).await;
+                    let result = render_git_error(&error, request_id.as_deref()).await;
 
                     response.map_body(|head, _| {
                         match result {
@@ -260,7 +294,12 @@ pub(crate) fn error_renderer_middleware<S, B>(request: ServiceRequest, service:
                         }
                     })
                 }
-                _ => unreachable!("Only html and Git error responses are handled in the async middleware")
+                ErrorDisplayType::Json => {
+                    let body = error.json_body(request_id.as_deref());
+
+                    response.map_body(|_, _| BoxBody::new(body.to_string()))
+                }
+                _ => unreachable!("Only html, Git and JSON error responses are handled in the async middleware")
             }
         } else {
             response
@@ -268,10 +307,16 @@ pub(crate) fn error_renderer_middleware<S, B>(request: ServiceRequest, service:
     }
 }
 
-async fn render_html_error(renderer: &GitArenaError) -> Result<BoxBody> {
+async fn render_html_error(renderer: &GitArenaError, request_id: Option<&str>) -> Result<BoxBody> {
     let mut context = Context::new();
     context.try_insert("error", renderer.message().as_str())?;
 
+    if let Some(request_id) = request_id {
+        context.try_insert("request_id", request_id)?;
+    }
+
+    context.try_insert("csp_nonce", &security_headers::nonce())?;
+
     if cfg!(debug_assertions) {
         context.try_insert("debug", &true)?;
     }
@@ -282,9 +327,14 @@ async fn render_html_error(renderer: &GitArenaError) -> Result<BoxBody> {
     Ok(BoxBody::new(template))
 }
 
-async fn render_git_error(renderer: &GitArenaError) -> Result<BoxBody> {
+async fn render_git_error(renderer: &GitArenaError, request_id: Option<&str>) -> Result<BoxBody> {
+    let message = match request_id {
+        Some(request_id) => format!("error: {} (request id: {})", renderer.message(), request_id),
+        None => format!("error: {}", renderer.message())
+    };
+
     let mut writer = GitWriter::new();
-    writer.write_text_sideband(Band::Error, format!("error: {}", renderer.message())).await?;
+    writer.write_text_sideband(Band::Error, message).await?;
 
     Ok(BoxBody::new(writer.serialize().await?))
 }
@@ -1,95 +1,129 @@
-use crate::{crypto, die, err};
+use crate::access_token::{PersonalAccessToken, TokenScopes, TOKEN_PREFIX};
+use crate::audit::{self, AuditEvent};
+use crate::{config, crypto, die, err, metrics, session};
+use crate::mail::Email;
 use crate::prelude::*;
 use crate::privileges::repo_visibility::RepoVisibility;
 use crate::repository::Repository;
 use crate::user::User;
+use crate::utils::rate_limit::RateLimiter;
+
+use std::time::Duration;
 
 use actix_web::http::header::{CONTENT_TYPE, WWW_AUTHENTICATE};
 use actix_web::{Either, HttpRequest, HttpResponse};
 use anyhow::Result;
-use sqlx::{Executor, Postgres};
+use log::warn;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 use tracing::instrument;
-use tracing_unwrap::OptionExt;
 
-#[instrument(skip(request, executor), err)]
-pub(crate) async fn validate_repo_access<'e, E>(repo: Option<Repository>, content_type: &str, request: &HttpRequest, executor: E) -> Result<Either<(Option<User>, Repository), HttpResponse>>
-    where E: Executor<'e, Database = Postgres>
-{
+#[instrument(skip(request, transaction), err)]
+pub(crate) async fn validate_repo_access(repo: Option<Repository>, content_type: &str, scope: TokenScopes, request: &HttpRequest, transaction: &mut Transaction<'_, Postgres>) -> Result<Either<(Option<User>, Repository), HttpResponse>> {
     match repo {
-        Some(repo) => {
-            if repo.visibility != RepoVisibility::Public {
-                return match login_flow(request, executor, content_type).await? {
-                    Either::Left(user) => Ok(Either::Left((Some(user), repo))),
-                    Either::Right(response) => Ok(Either::Right(response))
-                }
+        Some(repo) => match repo.visibility {
+            RepoVisibility::Public => Ok(Either::Left((None, repo))),
+            // Internal repositories are readable by every authenticated user, so they require a login just like private ones.
+            // Callers still need to check access afterwards using `privilege::check_access`
+            RepoVisibility::Internal | RepoVisibility::Private => match login_flow(request, transaction, content_type, scope).await? {
+                Either::Left(user) => Ok(Either::Left((Some(user), repo))),
+                Either::Right(response) => Ok(Either::Right(response))
             }
-
-            Ok(Either::Left((None, repo)))
         },
         None => {
             // Prompt for authentication even if the repo does not exist to prevent leakage of private repositories
-            let _ = login_flow(request, executor, content_type).await?;
+            let _ = login_flow(request, transaction, content_type, scope).await?;
 
             die!(NOT_FOUND, "Repository not found");
         }
     }
 }
 
-#[instrument(skip(request, executor), err)]
-pub(crate) async fn login_flow<'e, E>(request: &HttpRequest, executor: E, content_type: &str) -> Result<Either<User, HttpResponse>>
-    where E: Executor<'e, Database = Postgres>
-{
+#[instrument(skip(request, transaction), err)]
+pub(crate) async fn login_flow(request: &HttpRequest, transaction: &mut Transaction<'_, Postgres>, content_type: &str, scope: TokenScopes) -> Result<Either<User, HttpResponse>> {
     if !is_present(request).await {
-        return Ok(Either::Right(prompt(content_type).await));
+        return Ok(Either::Right(prompt(content_type, transaction).await?));
     }
 
-    Ok(Either::Left(authenticate(request, executor).await?))
+    Ok(Either::Left(authenticate(request, scope, transaction).await?))
 }
 
-#[instrument]
-pub(crate) async fn prompt(content_type: &str) -> HttpResponse {
-    HttpResponse::Unauthorized()
+/// Asks the Git client for credentials. The realm is the configured instance name (or `GitArena` if unset)
+#[instrument(skip(transaction), err)]
+pub(crate) async fn prompt(content_type: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<HttpResponse> {
+    let instance_name = config::get_optional_setting::<String, _>("instance.name", &mut *transaction).await?;
+    let realm = instance_name.as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("GitArena")
+        .replace('\\', "")
+        .replace('"', "");
+
+    Ok(HttpResponse::Unauthorized()
         .append_header((CONTENT_TYPE, content_type))
-        .append_header((WWW_AUTHENTICATE, "Basic realm=\"GitArena\", charset=\"UTF-8\""))
-        .finish()
+        .append_header((WWW_AUTHENTICATE, format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm)))
+        .append_header(("Git-Protocol", "version=2"))
+        .finish())
 }
 
+/// Failed authentication attempts allowed per username and per ip address within the window below
+const MAX_FAILED_ATTEMPTS: usize = 10;
+const FAILED_ATTEMPTS_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+static FAILED_ATTEMPTS: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(MAX_FAILED_ATTEMPTS, FAILED_ATTEMPTS_WINDOW));
+
+/// Authenticates the request using either username and password, or an access token.
+/// Access tokens may be sent as Bearer token or in the password field of Basic auth and are required to have been granted `scope`
 #[instrument(skip_all, err)]
-pub(crate) async fn authenticate<'e, E>(request: &HttpRequest, transaction: E) -> Result<User>
-    where E: Executor<'e, Database = Postgres>
-{
+pub(crate) async fn authenticate(request: &HttpRequest, scope: TokenScopes, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
     // TODO: Add more verbose logging to this function similar to frontend login (for usage by fail2ban)
 
     match request.get_header("authorization") {
         Some(auth_header) => {
-            let (username, password) = parse_basic_auth(auth_header).await?;
+            let credentials = parse_authorization(auth_header).await?;
 
-            if username.is_empty() || password.is_empty() {
-                die!(UNAUTHORIZED, "Username and password cannot be empty");
+            let (ip_address, _) = session::extract_ip_and_ua(request);
+            let ip_key = format!("ip:{}", ip_address.ip());
+            let user_key = match &credentials {
+                Credentials::Basic(username, _) if !username.is_empty() => Some(format!("user:{}", username.to_lowercase())),
+                _ => None
+            };
+
+            // Misconfigured credential helpers may retry dozens of times a minute, so stop checking passwords early
+            if FAILED_ATTEMPTS.is_limited(ip_key.as_str()) || user_key.as_deref().map_or(false, |key| FAILED_ATTEMPTS.is_limited(key)) {
+                warn!("Rejecting Git authentication from {} due to too many failed attempts", ip_address.ip());
+                die!(TOO_MANY_REQUESTS, "Too many failed authentication attempts. Please try again later.");
             }
 
-            let option: Option<User> = sqlx::query_as::<_, User>("select * from users where username = $1 limit 1")
-                .bind(&username)
-                .fetch_optional(transaction)
-                .await?;
+            let result = verify_credentials(credentials, scope, transaction).await;
+            metrics::record_auth("basic", result.is_ok());
+
+            for key in Some(ip_key).iter().chain(user_key.iter()) {
+                match result {
+                    Ok(_) => FAILED_ATTEMPTS.reset(key.as_str()),
+                    Err(_) => FAILED_ATTEMPTS.hit(key.as_str())
+                }
+            }
 
-            if option.is_none() {
-                die!(UNAUTHORIZED, "User does not exist");
+            if result.is_err() {
+                audit::record(request, AuditEvent::LoginFailure, None, user_key.as_deref(), json!({ "method": "basic" })).await;
             }
 
-            let user = option.unwrap_or_log();
+            let user = result?;
 
-            if !crypto::check_password(&user, &password)? {
-                die!(UNAUTHORIZED, "Incorrect password");
+            if let Some(message) = user.blocked_message() {
+                audit::record(request, AuditEvent::LoginFailure, Some(&user), Some(format!("user:{}", &user.username)), json!({ "method": "basic", "reason": "blocked" })).await;
+
+                die!(FORBIDDEN, "{}", message);
             }
 
-            // TODO: Check for allowed login
-            /*let primary_email = Email::find_primary_email(&user, transaction)
+            let primary_email = Email::find_primary_email(&user, &mut *transaction)
                 .await?
-                .ok_or_else(|| anyhow!("No primary email".to_owned()))?;*/
+                .ok_or_else(|| err!(UNAUTHORIZED, "No primary email"))?;
 
-            if user.disabled/* || !primary_email.is_allowed_login()*/ {
-                die!(UNAUTHORIZED, "Account has been disabled. Please contact support.");
+            if !primary_email.is_allowed_login() {
+                die!(FORBIDDEN, "Account has been disabled. Please contact support.");
             }
 
             Ok(user)
@@ -98,21 +132,147 @@ pub(crate) async fn authenticate<'e, E>(request: &HttpRequest, transaction: E) -
     }
 }
 
-#[instrument(skip(auth_header), err)]
-pub(crate) async fn parse_basic_auth(auth_header: &str) -> Result<(String, String)> {
-    let (auth_type, base64_credentials) = auth_header.split_once(' ').ok_or_else(|| err!(BAD_REQUEST))?;
+async fn verify_credentials(credentials: Credentials, scope: TokenScopes, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    match credentials {
+        Credentials::Basic(_, password) if password.starts_with(TOKEN_PREFIX) => authenticate_token(password.as_str(), scope, transaction).await,
+        Credentials::Basic(username, password) => {
+            if username.is_empty() || password.is_empty() {
+                die!(UNAUTHORIZED, "Username and password cannot be empty");
+            }
+
+            // Git clients may be configured to use an email address as username, which works with any verified address
+            let option: Option<User> = sqlx::query_as::<_, User>("select * from users where username = $1 \
+                or id = (select owner from emails where lower(email) = lower($1) and verified_at is not null limit 1) limit 1")
+                .bind(&username)
+                .fetch_optional(&mut *transaction)
+                .await?;
+
+            let user = match (check_user_password(option.as_ref(), &password)?, option) {
+                (true, Some(user)) => user,
+                _ => die!(UNAUTHORIZED, "Incorrect username or password")
+            };
+
+            // A password alone does not satisfy two-factor authentication, so these users need to use an access token
+            if user.totp_secret.is_some() {
+                die!(UNAUTHORIZED, "Two-factor authentication is enabled for this account. Please use an access token instead of your password");
+            }
+
+            crypto::upgrade_password_hash(&user, &password, &mut *transaction).await?;
 
-    if auth_type != "Basic" {
-        die!(UNAUTHORIZED, "Unsupported authentication type, only Basic auth allowed");
+            Ok(user)
+        }
+        Credentials::Bearer(token) => authenticate_token(token.as_str(), scope, transaction).await
     }
+}
 
-    let credentials = String::from_utf8(base64::decode(base64_credentials)?)?;
+/// Checks `password` against the hash of `user` or, if the user does not exist, against a decoy hash.
+/// Both cases run exactly one password check so response timing does not reveal whenever the user exists
+fn check_user_password(user: Option<&User>, password: &str) -> Result<bool> {
+    match user {
+        Some(user) => crypto::check_password(user, password),
+        None => {
+            crypto::check_password_decoy(password);
+            Ok(false)
+        }
+    }
+}
+
+async fn authenticate_token(token: &str, scope: TokenScopes, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    let access_token = PersonalAccessToken::find_using_token(token, &mut *transaction)
+        .await?
+        .ok_or_else(|| err!(UNAUTHORIZED, "Invalid access token"))?;
+
+    if access_token.is_expired() {
+        die!(UNAUTHORIZED, "Access token has expired");
+    }
 
-    Ok(credentials.split_once(':')
-        .map(|(username, password)| (username.to_owned(), password.to_owned()))
-        .ok_or_else(|| err!(UNAUTHORIZED, "Both username and password is required"))?)
+    if !access_token.has_scope(scope) {
+        die!(UNAUTHORIZED, "Access token has not been granted the required scope");
+    }
+
+    let user: User = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+        .bind(&access_token.owner)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    Ok(user)
+}
+
+#[instrument(skip(auth_header), err)]
+pub(crate) async fn parse_authorization(auth_header: &str) -> Result<Credentials> {
+    let (auth_type, value) = auth_header.split_once(' ').ok_or_else(|| err!(UNAUTHORIZED, "Malformed authorization header"))?;
+    let value = value.trim();
+
+    match auth_type {
+        "Basic" => {
+            let decoded = base64::decode(value).map_err(|_| err!(UNAUTHORIZED, "Malformed Basic auth credentials"))?;
+            let credentials = String::from_utf8(decoded).map_err(|_| err!(UNAUTHORIZED, "Malformed Basic auth credentials"))?;
+
+            Ok(credentials.split_once(':')
+                .map(|(username, password)| Credentials::Basic(username.to_owned(), password.to_owned()))
+                .ok_or_else(|| err!(UNAUTHORIZED, "Both username and password is required"))?)
+        }
+        "Bearer" => {
+            if !value.starts_with(TOKEN_PREFIX) || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                die!(UNAUTHORIZED, "Malformed Bearer token");
+            }
+
+            Ok(Credentials::Bearer(value.to_owned()))
+        }
+        _ => die!(UNAUTHORIZED, "Unsupported authentication type, only Basic and Bearer auth allowed")
+    }
 }
 
 pub(crate) async fn is_present(request: &HttpRequest) -> bool {
     request.get_header("authorization").is_some()
 }
+
+pub(crate) enum Credentials {
+    /// Username and password
+    Basic(String, String),
+    /// Access token
+    Bearer(String)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_user_password;
+
+    use crate::crypto::{self, PASSWORD_CHECKS};
+    use crate::user::User;
+
+    use std::cell::Cell;
+
+    use chrono::Utc;
+
+    fn count_password_checks<F: FnOnce()>(function: F) -> usize {
+        let before = PASSWORD_CHECKS.with(Cell::get);
+        function();
+
+        PASSWORD_CHECKS.with(Cell::get) - before
+    }
+
+    #[test]
+    fn unknown_users_take_as_many_password_checks_as_existing_ones() {
+        let user = User {
+            id: 1,
+            username: "octocat".to_owned(),
+            password: crypto::hash_password("correct horse battery staple").unwrap(),
+            disabled: false,
+            banned_until: None,
+            storage_quota: None,
+            admin: false,
+            totp_secret: None,
+            private_email: false,
+            login_notifications: true,
+            organization: false,
+            created_at: Utc::now()
+        };
+
+        let existing = count_password_checks(|| assert!(!check_user_password(Some(&user), "hunter2").unwrap()));
+        let unknown = count_password_checks(|| assert!(!check_user_password(None, "hunter2").unwrap()));
+
+        assert_eq!(existing, 1);
+        assert_eq!(existing, unknown);
+    }
+}
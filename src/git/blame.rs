@@ -0,0 +1,142 @@
+//! Blame of single files.
+//!
+//! Blaming has to walk the history of a file which can take a long time for large files with many commits.
+//! Results are cached per blob and path. Requests stop waiting after [BLAME_TIMEOUT], but the computation keeps running
+//! in the background and caches its result, so reloading the page later shows it. Only one blame per file runs at a time.
+
+use crate::repository::Repository;
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use actix_web::web;
+use anyhow::Result;
+use git2::{BlameOptions, Oid, Repository as Git2Repository};
+use log::warn;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::PgPool;
+use tracing::instrument;
+
+pub(crate) const BLAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Repository id, blob and path of every blame currently being computed
+static RUNNING: Lazy<Mutex<HashSet<(i32, Oid, String)>>> = Lazy::new(Default::default);
+
+/// Consecutive lines which were last changed by the same commit
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct BlameHunk {
+    pub(crate) commit: String,
+    /// 1-based line number of the first line of this hunk in the blamed file
+    pub(crate) start_line: usize,
+    pub(crate) lines: usize,
+
+    pub(crate) author_name: String,
+    pub(crate) author_email: String,
+    pub(crate) time: i64, // Unix timestamp
+    pub(crate) offset: i32 // Offset from UTC in minutes
+}
+
+/// Blames `path` as of `commit_oid`. `blob_oid` is the blob found at `path` in that commit and is used as cache key.
+/// Returns `None` if blaming did not finish within [BLAME_TIMEOUT] or is still running for an earlier request.
+///
+/// Takes the pool instead of a transaction, as none may be held open while waiting for the result
+#[instrument(err, skip(db_pool))]
+pub(crate) async fn blame(repo: &Repository, commit_oid: Oid, blob_oid: Oid, path: &str, db_pool: &PgPool) -> Result<Option<Vec<BlameHunk>>> {
+    let cached: Option<(Json<Vec<BlameHunk>>,)> = sqlx::query_as("select hunks from blame_cache where repo = $1 and blob = $2 and path = $3 limit 1")
+        .bind(&repo.id)
+        .bind(blob_oid.to_string())
+        .bind(path)
+        .fetch_optional(db_pool)
+        .await?;
+
+    if let Some((Json(hunks),)) = cached {
+        return Ok(Some(hunks));
+    }
+
+    let key = (repo.id, blob_oid, path.to_owned());
+
+    if !RUNNING.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(key.clone()) {
+        return Ok(None);
+    }
+
+    let fs_path = match repo.get_fs_path(db_pool).await {
+        Ok(fs_path) => fs_path,
+        Err(err) => {
+            RUNNING.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&key);
+            return Err(err);
+        }
+    };
+    let db_pool = db_pool.clone();
+
+    // Spawned so the result still gets cached once the request stopped waiting for it, libgit2 can not cancel a running blame
+    let handle = actix_web::rt::spawn(async move {
+        let result = compute_and_cache(fs_path, commit_oid, &key, &db_pool).await;
+        RUNNING.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&key);
+
+        result
+    });
+
+    match actix_web::rt::time::timeout(BLAME_TIMEOUT, handle).await {
+        Ok(result) => Ok(Some(result??)),
+        Err(_) => Ok(None)
+    }
+}
+
+async fn compute_and_cache(fs_path: String, commit_oid: Oid, (repo_id, blob_oid, path): &(i32, Oid, String), db_pool: &PgPool) -> Result<Vec<BlameHunk>> {
+    let owned_path = path.clone();
+
+    let hunks = web::block(move || compute(fs_path.as_str(), commit_oid, owned_path.as_str())).await??;
+
+    // A failure to cache should not hide the result from a request still waiting for it
+    if let Err(err) = sqlx::query("insert into blame_cache (repo, blob, path, hunks) values ($1, $2, $3, $4) on conflict do nothing")
+        .bind(repo_id)
+        .bind(blob_oid.to_string())
+        .bind(path.as_str())
+        .bind(Json(&hunks))
+        .execute(db_pool)
+        .await {
+        warn!("Failed to cache blame of {} in repo id {}: {}", path, repo_id, err);
+    }
+
+    Ok(hunks)
+}
+
+fn compute(fs_path: &str, commit_oid: Oid, path: &str) -> Result<Vec<BlameHunk>> {
+    let repo = Git2Repository::open(fs_path)?;
+
+    let mut options = BlameOptions::new();
+    options.newest_commit(commit_oid);
+
+    let blame = repo.blame_file(Path::new(path), Some(&mut options))?;
+    let mut hunks = Vec::<BlameHunk>::with_capacity(blame.len());
+
+    for hunk in blame.iter() {
+        let commit = hunk.final_commit_id().to_string();
+
+        // libgit2 may split changes of the same commit into multiple adjacent hunks
+        if let Some(previous) = hunks.last_mut() {
+            if previous.commit == commit && previous.start_line + previous.lines == hunk.final_start_line() {
+                previous.lines += hunk.lines_in_hunk();
+                continue;
+            }
+        }
+
+        let signature = hunk.final_signature();
+
+        hunks.push(BlameHunk {
+            commit,
+            start_line: hunk.final_start_line(),
+            lines: hunk.lines_in_hunk(),
+            author_name: signature.name().unwrap_or("Ghost").to_owned(),
+            author_email: signature.email().unwrap_or_default().to_owned(),
+            time: signature.when().seconds(),
+            offset: signature.when().offset_minutes()
+        });
+    }
+
+    Ok(hunks)
+}
@@ -0,0 +1,190 @@
+//! Structured diffs of commits against their first parent or of two arbitrary commits.
+
+use anyhow::Result;
+use git2::{Delta, DiffFindOptions, DiffOptions, Oid, Patch, Repository as Git2Repository, Tree};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub(crate) struct CommitDiff {
+    pub(crate) commit: String,
+    /// `None` for root commits, which are diffed against the empty tree
+    pub(crate) parent: Option<String>,
+    pub(crate) files: Vec<FileDiff>,
+    pub(crate) additions: usize,
+    pub(crate) deletions: usize
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileDiff {
+    pub(crate) old_path: Option<String>,
+    pub(crate) new_path: Option<String>,
+    pub(crate) status: FileStatus,
+    pub(crate) binary: bool,
+    /// Files exceeding the maximum diff size are collapsed: `hunks` is empty and the line counts are zero
+    pub(crate) too_large: bool,
+    pub(crate) additions: usize,
+    pub(crate) deletions: usize,
+    pub(crate) hunks: Vec<DiffHunk>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FileStatus {
+    Added,
+    Modified,
+    Renamed,
+    Copied,
+    Deleted,
+    TypeChanged
+}
+
+#[derive(Serialize)]
+pub(crate) struct DiffHunk {
+    pub(crate) header: String,
+    pub(crate) old_start: u32,
+    pub(crate) old_lines: u32,
+    pub(crate) new_start: u32,
+    pub(crate) new_lines: u32,
+    pub(crate) lines: Vec<DiffLine>
+}
+
+#[derive(Serialize)]
+pub(crate) struct DiffLine {
+    /// `+` for added, `-` for removed and ` ` for context lines
+    pub(crate) origin: char,
+    pub(crate) old_line: Option<u32>,
+    pub(crate) new_line: Option<u32>,
+    pub(crate) content: String
+}
+
+/// Diffs `commit_oid` against its first parent. Files where either side is larger than `max_file_size` bytes are not diffed
+pub(crate) fn commit_diff(repo: &Git2Repository, commit_oid: Oid, max_file_size: usize) -> Result<CommitDiff> {
+    let commit = repo.find_commit(commit_oid)?;
+    let parent = commit.parents().next();
+
+    let old_tree = parent.as_ref().map(|parent| parent.tree()).transpose()?;
+    let files = diff_trees(repo, old_tree.as_ref(), &commit.tree()?, max_file_size)?;
+
+    Ok(CommitDiff {
+        commit: commit_oid.to_string(),
+        parent: parent.map(|parent| parent.id().to_string()),
+        additions: files.iter().map(|file| file.additions).sum(),
+        deletions: files.iter().map(|file| file.deletions).sum(),
+        files
+    })
+}
+
+/// Diffs `head_oid` against `base_oid`, for example all changes of a branch since its merge base.
+/// The result is structured the same way as a [commit diff](commit_diff), with `base_oid` as parent
+pub(crate) fn range_diff(repo: &Git2Repository, base_oid: Oid, head_oid: Oid, max_file_size: usize) -> Result<CommitDiff> {
+    let base_tree = repo.find_commit(base_oid)?.tree()?;
+    let head_tree = repo.find_commit(head_oid)?.tree()?;
+
+    let files = diff_trees(repo, Some(&base_tree), &head_tree, max_file_size)?;
+
+    Ok(CommitDiff {
+        commit: head_oid.to_string(),
+        parent: Some(base_oid.to_string()),
+        additions: files.iter().map(|file| file.additions).sum(),
+        deletions: files.iter().map(|file| file.deletions).sum(),
+        files
+    })
+}
+
+fn diff_trees(repo: &Git2Repository, old_tree: Option<&Tree<'_>>, new_tree: &Tree<'_>, max_file_size: usize) -> Result<Vec<FileDiff>> {
+    let mut options = DiffOptions::new();
+    options.ignore_submodules(true);
+
+    let mut diff = repo.diff_tree_to_tree(old_tree, Some(new_tree), Some(&mut options))?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+
+    let odb = repo.odb()?;
+    let mut files = Vec::with_capacity(diff.deltas().len());
+
+    for (index, delta) in diff.deltas().enumerate() {
+        let status = match delta.status() {
+            Delta::Added | Delta::Untracked => FileStatus::Added,
+            Delta::Deleted => FileStatus::Deleted,
+            Delta::Renamed => FileStatus::Renamed,
+            Delta::Copied => FileStatus::Copied,
+            Delta::Typechange => FileStatus::TypeChanged,
+            _ => FileStatus::Modified
+        };
+
+        let old_path = (!matches!(status, FileStatus::Added)).then(|| delta.old_file().path().map(|path| path.to_string_lossy().into_owned())).flatten();
+        let new_path = (!matches!(status, FileStatus::Deleted)).then(|| delta.new_file().path().map(|path| path.to_string_lossy().into_owned())).flatten();
+
+        // Sizes are read from the object headers, so huge blobs never get loaded into memory
+        let mut too_large = false;
+
+        for oid in [delta.old_file().id(), delta.new_file().id()] {
+            if !oid.is_zero() && odb.read_header(oid)?.0 > max_file_size {
+                too_large = true;
+            }
+        }
+
+        let mut file = FileDiff {
+            old_path,
+            new_path,
+            status,
+            binary: delta.flags().is_binary(),
+            too_large,
+            additions: 0,
+            deletions: 0,
+            hunks: Vec::new()
+        };
+
+        if too_large {
+            files.push(file);
+            continue;
+        }
+
+        let patch = match Patch::from_diff(&diff, index)? {
+            Some(patch) => patch,
+            None => {
+                files.push(file);
+                continue;
+            }
+        };
+
+        // Binary detection requires the content to be loaded, which only happens while generating the patch
+        if patch.delta().flags().is_binary() {
+            file.binary = true;
+            files.push(file);
+            continue;
+        }
+
+        let (_, additions, deletions) = patch.line_stats()?;
+        file.additions = additions;
+        file.deletions = deletions;
+
+        for hunk_index in 0..patch.num_hunks() {
+            let (hunk, line_count) = patch.hunk(hunk_index)?;
+            let mut lines = Vec::with_capacity(line_count);
+
+            for line_index in 0..line_count {
+                let line = patch.line_in_hunk(hunk_index, line_index)?;
+
+                lines.push(DiffLine {
+                    origin: line.origin(),
+                    old_line: line.old_lineno(),
+                    new_line: line.new_lineno(),
+                    content: String::from_utf8_lossy(line.content()).into_owned()
+                });
+            }
+
+            file.hunks.push(DiffHunk {
+                header: String::from_utf8_lossy(hunk.header()).trim_end().to_owned(),
+                old_start: hunk.old_start(),
+                old_lines: hunk.old_lines(),
+                new_start: hunk.new_start(),
+                new_lines: hunk.new_lines(),
+                lines
+            });
+        }
+
+        files.push(file);
+    }
+
+    Ok(files)
+}
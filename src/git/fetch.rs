@@ -3,9 +3,10 @@ use crate::git::io::progress_writer::ProgressWriter;
 use crate::git::io::writer::GitWriter;
 
 use actix_web::web::Bytes;
+use std::collections::{HashSet, VecDeque};
+
 use anyhow::Result;
-use async_recursion::async_recursion;
-use git2::{Buf, Commit, ObjectType, Oid, PackBuilder, Repository as Git2Repository};
+use git2::{Buf, ObjectType, Oid, Repository as Git2Repository};
 use log::warn;
 use tracing::instrument;
 
@@ -41,27 +42,14 @@ pub(crate) async fn fetch(input: Vec<Vec<u8>>, repo: &Git2Repository) -> Result<
             options.want.push(stripped.to_owned());
         }
 
-        /*if line.starts_with("shallow ") {
-            options.shallow.push(line[8..].to_owned());
-        }
-
-        if line.starts_with("deepen ") {
-            options.deepen = Some(line[7..].parse::<i32>()?);
+        if let Some(stripped) = line.strip_prefix("shallow ") {
+            options.shallow.push(stripped.to_owned());
         }
 
-        if line == "deepen-relative" {
-            options.deepen_relative = true;
+        if let Some(stripped) = line.strip_prefix("deepen ") {
+            options.deepen = Some(stripped.parse::<u32>()?);
         }
 
-        if line.starts_with("deepen-since ") && options.deepen.is_none() {
-            let timestamp_str = &line[13..];
-            //parse timestamp_str to DateTime<Utc>
-        }
-
-        if line.starts_with("deepen-not ") && options.deepen.is_none() {
-            options.deepen_not = Some(line[11..].to_owned());
-        }*/
-
         if line == "done" {
             break;
         }
@@ -73,10 +61,6 @@ pub(crate) async fn fetch(input: Vec<Vec<u8>>, repo: &Git2Repository) -> Result<
         writer.append(wants).await?;
     }
 
-    /*if let Some(mut shallows) = process_shallows(&repo, &options).await? {
-        writer = writer.append(&mut shallows);
-    }*/
-
     writer.flush().await?;
     writer.serialize().await
 }
@@ -115,8 +99,89 @@ pub(crate) async fn process_haves(repo: &Git2Repository, options: &Fetch) -> Res
 #[instrument(err, skip(repo))]
 pub(crate) async fn process_wants(repo: &Git2Repository, options: &Fetch) -> Result<Option<GitWriter>> {
     let mut writer = GitWriter::new();
+    let history = walk_history(repo, options)?;
+
+    // https://git-scm.com/docs/protocol-v2#_fetch (shallow-info section)
+    if options.is_shallow_request() {
+        writer.write_text("shallow-info").await?;
+        writer.append(write_shallow_info(&history).await?).await?;
+        writer.delimiter().await?;
+    }
+
     writer.write_text("packfile").await?;
 
+    writer.append(write_pack(repo, options, &history).await?).await?;
+
+    Ok(Some(writer))
+}
+
+/// Walks the history of all wanted commits. If the client requested a `deepen`, commits which are `deepen` generations
+/// away from a wanted commit are not followed further and become the new shallow boundary.
+/// A deepen which reaches the root commit simply results in the whole history being walked
+#[instrument(err, skip(repo))]
+pub(crate) fn walk_history(repo: &Git2Repository, options: &Fetch) -> Result<History> {
+    let mut history = History::default();
+    let mut seen = HashSet::<Oid>::new();
+    let mut queue = VecDeque::<(Oid, u32)>::new();
+
+    for wanted_obj in &options.want {
+        let oid = Oid::from_str(wanted_obj.as_str())?;
+
+        if let Ok(commit) = repo.find_commit(oid) {
+            if seen.insert(commit.id()) {
+                queue.push_back((commit.id(), 1));
+            }
+        }
+    }
+
+    // Breadth first, so every commit is visited with the shortest distance to a wanted commit first
+    while let Some((oid, depth)) = queue.pop_front() {
+        let commit = repo.find_commit(oid)?;
+        history.commits.push(oid);
+
+        if commit.parent_count() == 0 {
+            continue;
+        }
+
+        if matches!(options.deepen, Some(deepen) if depth >= deepen) {
+            history.shallow.push(oid);
+            continue;
+        }
+
+        // The client only had this commit but not its parents, which we're now going to send
+        if options.shallow.iter().any(|shallow| shallow == &oid.to_string()) {
+            history.unshallow.push(oid);
+        }
+
+        for parent_id in commit.parent_ids() {
+            if seen.insert(parent_id) {
+                queue.push_back((parent_id, depth + 1));
+            }
+        }
+    }
+
+    Ok(history)
+}
+
+pub(crate) async fn write_shallow_info(history: &History) -> Result<GitWriter> {
+    let mut writer = GitWriter::new();
+
+    for oid in &history.shallow {
+        writer.write_text(format!("shallow {}", oid)).await?;
+    }
+
+    for oid in &history.unshallow {
+        writer.write_text(format!("unshallow {}", oid)).await?;
+    }
+
+    Ok(writer)
+}
+
+/// Builds a packfile containing all objects wanted by the client and writes it (alongside progress messages) using side-band
+#[instrument(err, skip(repo, history))]
+pub(crate) async fn write_pack(repo: &Git2Repository, options: &Fetch, history: &History) -> Result<GitWriter> {
+    let mut writer = GitWriter::new();
+
     writer.write_text_sideband(Band::Progress, format!("Enumerating objects: {}, done.", options.want.len())).await?;
 
     let mut progress_writer = ProgressWriter::new();
@@ -132,12 +197,7 @@ pub(crate) async fn process_wants(repo: &Git2Repository, options: &Fetch) -> Res
                 Ok(object) => {
                     if let Some(kind) = object.kind() {
                         match kind {
-                            ObjectType::Commit => {
-                                // Can be simplified with if let guards: https://github.com/rust-lang/rust/issues/51114
-                                if let Some(commit) = object.as_commit() {
-                                    insert_commit_with_parents(commit, &mut pack_builder).await?;
-                                }
-                            },
+                            ObjectType::Commit => { /* Inserted below using the walked history */ },
                             ObjectType::Tree => pack_builder.insert_tree(object.id())?,
                             _ => pack_builder.insert_object(object.id(), Some(wanted_obj.as_str()))?
                         }
@@ -151,6 +211,10 @@ pub(crate) async fn process_wants(repo: &Git2Repository, options: &Fetch) -> Res
             }
         }
 
+        for oid in &history.commits {
+            pack_builder.insert_commit(*oid)?;
+        }
+
         let mut buf = Buf::new();
         pack_builder.write_buf(&mut buf)?;
 
@@ -177,33 +241,91 @@ pub(crate) async fn process_wants(repo: &Git2Repository, options: &Fetch) -> Res
         total, total_delta, reused, reused_delta, pack_reused
     )).await?;
 
-    Ok(Some(writer))
+    Ok(writer)
 }
 
-#[instrument(err, skip(pack_builder))]
-#[async_recursion(?Send)]
-async fn insert_commit_with_parents(commit: &Commit<'_>, pack_builder: &mut PackBuilder<'_>) -> Result<()> {
-    pack_builder.insert_commit(commit.id())?;
+/// Handles an `upload-pack` request using the legacy protocol (v0/v1) for clients which did not negotiate protocol v2.
+/// `wants` contains the lines before the first flush (including `shallow` and `deepen`), `haves` the lines after it.
+///
+/// As we do not advertise `multi_ack`, a single `ACK` for the first common object (or `NAK`) is sent. The packfile is only
+/// sent once the client is `done` with negotiation, as stateless clients send multiple requests until then
+#[instrument(err, skip(repo))]
+pub(crate) async fn fetch_v0(wants: Vec<Vec<u8>>, haves: Vec<Vec<u8>>, repo: &Git2Repository) -> Result<Bytes> {
+    let mut options = Fetch::default();
+    let mut writer = GitWriter::new();
+    let mut done = false;
+
+    for raw_line in wants.iter() {
+        let line = String::from_utf8(raw_line.to_vec())?;
+
+        // The first want line additionally contains the capabilities requested by the client
+        if let Some(stripped) = line.strip_prefix("want ") {
+            let mut split = stripped.split(' ');
+
+            if let Some(oid) = split.next() {
+                options.want.push(oid.to_owned());
+            }
+
+            for capability in split {
+                match capability {
+                    "thin-pack" => options.thin_pack = true,
+                    "no-progress" => options.no_progress = true,
+                    "include-tag" => options.include_tag = true,
+                    "ofs-delta" => options.ofs_delta = true,
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(stripped) = line.strip_prefix("shallow ") {
+            options.shallow.push(stripped.to_owned());
+        }
 
-    for parent in commit.parents() {
-        insert_commit_with_parents(&parent, pack_builder).await?;
+        if let Some(stripped) = line.strip_prefix("deepen ") {
+            options.deepen = Some(stripped.parse::<u32>()?);
+        }
     }
 
-    Ok(())
-}
+    for raw_line in haves.iter() {
+        let line = String::from_utf8(raw_line.to_vec())?;
 
-/*pub(crate) async fn process_shallows(repo: &Git2Repository, options: &Fetch) -> Result<Option<GitWriter>> {
-    if !repo.is_shallow() || options.shallow.is_empty() {
-        return Ok(None);
+        if let Some(stripped) = line.strip_prefix("have ") {
+            options.have.push(stripped.to_owned());
+        }
+
+        if line == "done" {
+            done = true;
+        }
     }
 
-    let mut writer = GitWriter::new();
-    writer = writer.write_text("shallow-info")?;
+    let history = if done || options.is_shallow_request() {
+        Some(walk_history(repo, &options)?)
+    } else {
+        None
+    };
 
-    // ...
+    // Stateless clients repeat their shallow request with every round, so the shallow update is sent every time as well
+    if let Some(history) = history.as_ref().filter(|_| options.is_shallow_request()) {
+        writer.append(write_shallow_info(history).await?).await?;
+        writer.flush().await?;
+    }
 
-    Ok(Some(writer))
-}*/
+    let common = options.have.iter()
+        .filter_map(|have| Oid::from_str(have.as_str()).ok())
+        .find(|oid| repo.find_commit(*oid).is_ok());
+
+    match common {
+        Some(oid) => writer.write_text(format!("ACK {}", oid)).await?,
+        None => writer.write_text("NAK").await?
+    };
+
+    if let Some(history) = history.as_ref().filter(|_| done) {
+        writer.append(write_pack(repo, &options, history).await?).await?;
+        writer.flush().await?;
+    }
+
+    writer.serialize().await
+}
 
 #[derive(Debug, Default)]
 pub(crate) struct Fetch {
@@ -213,9 +335,19 @@ pub(crate) struct Fetch {
     pub(crate) ofs_delta: bool, // PACKv2
     pub(crate) have: Vec<String>,
     pub(crate) want: Vec<String>,
-    /*pub(crate) shallow: Vec<String>,
-    pub(crate) deepen: Option<i32>,
-    pub(crate) deepen_relative: bool,
-    pub(crate) deepen_since: Option<DateTime<Utc>>,
-    pub(crate) deepen_not: Option<String>*/
+    pub(crate) shallow: Vec<String>,
+    pub(crate) deepen: Option<u32>
+}
+
+impl Fetch {
+    pub(crate) fn is_shallow_request(&self) -> bool {
+        self.deepen.is_some() || !self.shallow.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    pub(crate) commits: Vec<Oid>,
+    pub(crate) shallow: Vec<Oid>,
+    pub(crate) unshallow: Vec<Oid>
 }
@@ -0,0 +1,186 @@
+//! Language statistics of repositories, similar to the language bar on GitHub.
+//!
+//! Statistics are computed for the tree of the default branch and cached keyed by the tree oid,
+//! so they only get recomputed after the content of the default branch actually changed.
+
+use crate::repository::Repository;
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use git2::{ObjectType, Oid, Repository as Git2Repository, TreeWalkMode, TreeWalkResult};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Postgres, Transaction};
+use tracing::instrument;
+
+/// File extension (lower case, without dot), language name and color
+const LANGUAGES: &[(&str, &str, &str)] = &[
+    ("c", "C", "#555555"),
+    ("h", "C", "#555555"),
+    ("cc", "C++", "#f34b7d"),
+    ("cpp", "C++", "#f34b7d"),
+    ("cxx", "C++", "#f34b7d"),
+    ("hpp", "C++", "#f34b7d"),
+    ("cs", "C#", "#178600"),
+    ("css", "CSS", "#563d7c"),
+    ("scss", "SCSS", "#c6538c"),
+    ("dart", "Dart", "#00b4ab"),
+    ("ex", "Elixir", "#6e4a7e"),
+    ("exs", "Elixir", "#6e4a7e"),
+    ("erl", "Erlang", "#b83998"),
+    ("go", "Go", "#00add8"),
+    ("hs", "Haskell", "#5e5086"),
+    ("html", "HTML", "#e34c26"),
+    ("htm", "HTML", "#e34c26"),
+    ("java", "Java", "#b07219"),
+    ("js", "JavaScript", "#f1e05a"),
+    ("mjs", "JavaScript", "#f1e05a"),
+    ("jsx", "JavaScript", "#f1e05a"),
+    ("kt", "Kotlin", "#a97bff"),
+    ("kts", "Kotlin", "#a97bff"),
+    ("lua", "Lua", "#000080"),
+    ("m", "Objective-C", "#438eff"),
+    ("ml", "OCaml", "#3be133"),
+    ("php", "PHP", "#4f5d95"),
+    ("pl", "Perl", "#0298c3"),
+    ("ps1", "PowerShell", "#012456"),
+    ("py", "Python", "#3572a5"),
+    ("r", "R", "#198ce7"),
+    ("rb", "Ruby", "#701516"),
+    ("rs", "Rust", "#dea584"),
+    ("scala", "Scala", "#c22d40"),
+    ("sh", "Shell", "#89e051"),
+    ("bash", "Shell", "#89e051"),
+    ("zsh", "Shell", "#89e051"),
+    ("sql", "SQL", "#e38c00"),
+    ("swift", "Swift", "#f05138"),
+    ("tex", "TeX", "#3d6117"),
+    ("ts", "TypeScript", "#3178c6"),
+    ("tsx", "TypeScript", "#3178c6"),
+    ("vue", "Vue", "#41b883"),
+    ("zig", "Zig", "#ec915c")
+];
+
+/// Path segments of directories which contain vendored or generated code
+const IGNORED_DIRECTORIES: &[&str] = &["node_modules", "vendor", "third_party", "dist", "build", "target", ".git"];
+
+/// File name suffixes of generated or minified files
+const IGNORED_SUFFIXES: &[&str] = &[".min.js", ".min.css", ".lock", "-lock.json", ".pb.go", ".generated.rs"];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct LanguageStat {
+    pub(crate) name: String,
+    pub(crate) color: String,
+    pub(crate) bytes: u64,
+    pub(crate) percentage: f64
+}
+
+/// Returns the language statistics of the default branch of `repo`, ordered by size descending.
+/// Returns an empty list if the default branch does not exist (yet)
+#[instrument(err, skip(transaction))]
+pub(crate) async fn languages(repo: &Repository, transaction: &mut Transaction<'_, Postgres>) -> Result<Vec<LanguageStat>> {
+    let git2_repo = repo.libgit2(&mut *transaction).await?;
+
+    let tree_oid = match git2_repo.find_reference(format!("refs/heads/{}", repo.default_branch).as_str()).and_then(|reference| reference.peel_to_tree()) {
+        Ok(tree) => tree.id(),
+        Err(_) => return Ok(Vec::new())
+    };
+
+    let cached: Option<(Json<Vec<LanguageStat>>,)> = sqlx::query_as("select languages from repository_languages where repo = $1 and tree = $2 limit 1")
+        .bind(&repo.id)
+        .bind(tree_oid.to_string())
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+    if let Some((Json(languages),)) = cached {
+        return Ok(languages);
+    }
+
+    let languages = compute(&git2_repo, tree_oid)?;
+
+    sqlx::query("insert into repository_languages (repo, tree, languages) values ($1, $2, $3) \
+        on conflict (repo) do update set tree = excluded.tree, languages = excluded.languages, computed_at = now()")
+        .bind(&repo.id)
+        .bind(tree_oid.to_string())
+        .bind(Json(&languages))
+        .execute(&mut *transaction)
+        .await?;
+
+    Ok(languages)
+}
+
+fn compute(repo: &Git2Repository, tree_oid: Oid) -> Result<Vec<LanguageStat>> {
+    let tree = repo.find_tree(tree_oid)?;
+    let odb = repo.odb()?;
+
+    let mut sizes = HashMap::<&'static str, (&'static str, u64)>::new();
+    let mut walk_error = None;
+
+    tree.walk(TreeWalkMode::PreOrder, |_, entry| {
+        let name = match entry.name() {
+            Some(name) => name,
+            None => return TreeWalkResult::Skip
+        };
+
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                return if IGNORED_DIRECTORIES.contains(&name) {
+                    TreeWalkResult::Skip
+                } else {
+                    TreeWalkResult::Ok
+                };
+            }
+            Some(ObjectType::Blob) => {}
+            _ => return TreeWalkResult::Ok
+        }
+
+        if IGNORED_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+            return TreeWalkResult::Ok;
+        }
+
+        let (language, color) = match language_for(name) {
+            Some(language) => language,
+            None => return TreeWalkResult::Ok
+        };
+
+        // Only reads the object header instead of inflating the whole blob
+        match odb.read_header(entry.id()) {
+            Ok((size, _)) => sizes.entry(language).or_insert((color, 0)).1 += size as u64,
+            Err(err) => {
+                walk_error = Some(err);
+                return TreeWalkResult::Abort;
+            }
+        }
+
+        TreeWalkResult::Ok
+    })?;
+
+    if let Some(err) = walk_error {
+        return Err(err.into());
+    }
+
+    let total = sizes.values().map(|(_, bytes)| bytes).sum::<u64>();
+
+    let mut languages = sizes.into_iter()
+        .map(|(name, (color, bytes))| LanguageStat {
+            name: name.to_owned(),
+            color: color.to_owned(),
+            bytes,
+            percentage: if total > 0 { bytes as f64 * 100.0 / total as f64 } else { 0.0 }
+        })
+        .collect::<Vec<_>>();
+
+    languages.sort_by(|lhs, rhs| rhs.bytes.cmp(&lhs.bytes).then_with(|| lhs.name.cmp(&rhs.name)));
+
+    Ok(languages)
+}
+
+fn language_for(file_name: &str) -> Option<(&'static str, &'static str)> {
+    let (_, extension) = file_name.rsplit_once('.')?;
+    let extension = extension.to_lowercase();
+
+    LANGUAGES.iter()
+        .find(|(candidate, _, _)| *candidate == extension.as_str())
+        .map(|(_, name, color)| (*name, *color))
+}
@@ -0,0 +1,202 @@
+//! Storage of [Git LFS](https://github.com/git-lfs/git-lfs/blob/main/docs/api/README.md) objects.
+//!
+//! Objects are stored inside the bare repository at `lfs/objects/<oid[0..2]>/<oid[2..4]>/<oid>`, the same layout the
+//! Git LFS client uses locally. Uploads are first written into `lfs/tmp` and only moved into place once their hash matches.
+//!
+//! The transfers themselves are authenticated using short-lived [action tokens](action_token) handed out by the batch API,
+//! so the credentials of the batch request (a password or long-lived access token) are never echoed back to the client.
+
+use crate::repository::Repository;
+use crate::user::User;
+use crate::{crypto, die};
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
+use derive_more::Display;
+use futures::{Stream, StreamExt};
+use once_cell::sync::OnceCell;
+use ring::hmac;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, FromRow, Postgres};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+use tracing_unwrap::OptionExt;
+
+pub(crate) const LFS_CONTENT_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// Authorization scheme of action tokens, so they're never mistaken for the credentials of Basic or Bearer auth
+pub(crate) const ACTION_AUTH_SCHEME: &str = "LfsAction";
+
+/// Seconds an action token is valid for. The client requests new actions from the batch API once they expired
+pub(crate) const ACTION_TOKEN_TTL: i64 = 15 * 60;
+
+static KEY: OnceCell<hmac::Key> = OnceCell::new();
+
+pub(crate) fn init(secret: &str) {
+    let _ = KEY.set(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()));
+}
+
+/// Creates a token authorizing `user` to download objects of `repo` (and to upload them, if `upload` is set)
+/// for the next [ACTION_TOKEN_TTL] seconds
+pub(crate) fn action_token(user: &User, repo: &Repository, upload: bool) -> String {
+    let expires_at = Utc::now().timestamp() + ACTION_TOKEN_TTL;
+    let payload = format!("{}.{}.{}.{}", user.id, repo.id, if upload { "upload" } else { "download" }, expires_at);
+    let tag = hmac::sign(KEY.get().unwrap_or_log(), format!("lfs:{}", payload).as_bytes());
+
+    format!("{}.{}", payload, hex::encode(tag.as_ref()))
+}
+
+/// Verifies a token created by [action_token] for `repo` and returns the id of the user it has been issued to.
+/// Returns `None` if the token is invalid, expired or does not grant `upload`
+pub(crate) fn verify_action_token(token: &str, repo: &Repository, upload: bool) -> Option<i32> {
+    let (payload, tag) = token.rsplit_once('.')?;
+    let tag = hex::decode(tag).ok()?;
+
+    hmac::verify(KEY.get().unwrap_or_log(), format!("lfs:{}", payload).as_bytes(), tag.as_slice()).ok()?;
+
+    let mut split = payload.split('.');
+    let user_id = split.next()?.parse::<i32>().ok()?;
+    let repo_id = split.next()?.parse::<i32>().ok()?;
+    let operation = split.next()?;
+    let expires_at = split.next()?.parse::<i64>().ok()?;
+
+    if repo_id != repo.id || expires_at < Utc::now().timestamp() || (upload && operation != "upload") {
+        return None;
+    }
+
+    Some(user_id)
+}
+
+/// Checks if `oid` is a valid SHA-256 object id (64 lowercase hex characters)
+pub(crate) fn is_valid_oid(oid: &str) -> bool {
+    oid.len() == 64 && oid.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Returns the path of the object `oid` in `repo`. The object is not required to exist.
+/// `oid` has to be [a valid object id](is_valid_oid)
+pub(crate) async fn object_path<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, oid: &str, executor: E) -> Result<PathBuf> {
+    let repo_path = repo.get_fs_path(executor).await?;
+
+    Ok(Path::new(repo_path.as_str()).join("lfs").join("objects").join(&oid[0..2]).join(&oid[2..4]).join(oid))
+}
+
+/// Returns the size of the object at `path` or `None` if it does not exist
+pub(crate) async fn object_size(path: &Path) -> Option<u64> {
+    fs::metadata(path).await.ok().filter(|metadata| metadata.is_file()).map(|metadata| metadata.len())
+}
+
+/// Writes `stream` into the object at `path` if its SHA-256 hash matches `oid`. Errors if the stream is larger than `max_size` bytes.
+/// Returns `false` (and does not store anything) if the hash does not match, for example due to a truncated transfer
+#[instrument(err, skip(stream))]
+pub(crate) async fn store_object<S, B, E>(oid: &str, path: &Path, max_size: u64, mut stream: S) -> Result<bool>
+    where S: Stream<Item = Result<B, E>> + Unpin,
+          B: AsRef<[u8]>,
+          E: std::error::Error + Send + Sync + 'static
+{
+    // lfs/objects/xx/yy/oid -> lfs/tmp
+    let tmp_dir = path.ancestors().nth(4).map(|lfs_dir| lfs_dir.join("tmp")).unwrap_or_else(|| PathBuf::from("tmp"));
+    fs::create_dir_all(&tmp_dir).await?;
+
+    let tmp_path = tmp_dir.join(format!("{}-{}", oid, crypto::random_numeric_ascii_string(8)));
+    let mut file = fs::File::create(&tmp_path).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0_u64;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path).await;
+                return Err(err.into());
+            }
+        };
+
+        size += chunk.as_ref().len() as u64;
+
+        if size > max_size {
+            drop(file);
+            let _ = fs::remove_file(&tmp_path).await;
+            die!(PAYLOAD_TOO_LARGE, "Object may only be up to {} bytes large", max_size);
+        }
+
+        hasher.update(chunk.as_ref());
+        file.write_all(chunk.as_ref()).await?;
+    }
+
+    file.flush().await?;
+    drop(file);
+
+    if hex::encode(hasher.finalize()) != oid {
+        fs::remove_file(&tmp_path).await?;
+        return Ok(false);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    fs::rename(&tmp_path, path).await?;
+
+    Ok(true)
+}
+
+/// Lock on a file in a repository, preventing other users from pushing changes to it.
+/// `owner_name` is not part of the `lfs_locks` table and needs to be joined from `users`
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", path)]
+pub(crate) struct LfsLock {
+    pub(crate) id: i32,
+    pub(crate) repo: i32,
+    pub(crate) path: String,
+    pub(crate) owner: i32,
+    pub(crate) owner_name: String,
+    pub(crate) created_at: DateTime<Local>
+}
+
+impl LfsLock {
+    pub(crate) async fn find<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, id: i32, executor: E) -> Result<Option<LfsLock>> {
+        Ok(sqlx::query_as::<_, LfsLock>(
+            "select lfs_locks.*, users.username as owner_name from lfs_locks \
+            inner join users on users.id = lfs_locks.owner \
+            where lfs_locks.repo = $1 and lfs_locks.id = $2 limit 1"
+        )
+            .bind(&repo.id)
+            .bind(&id)
+            .fetch_optional(executor)
+            .await?)
+    }
+
+    pub(crate) async fn find_by_path<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, path: &str, executor: E) -> Result<Option<LfsLock>> {
+        Ok(sqlx::query_as::<_, LfsLock>(
+            "select lfs_locks.*, users.username as owner_name from lfs_locks \
+            inner join users on users.id = lfs_locks.owner \
+            where lfs_locks.repo = $1 and lfs_locks.path = $2 limit 1"
+        )
+            .bind(&repo.id)
+            .bind(path)
+            .fetch_optional(executor)
+            .await?)
+    }
+
+    /// Returns up to `limit` locks of `repo` with an id greater than `after`, ordered by id.
+    /// Optionally only returns locks on `path` or owned by `owner`
+    pub(crate) async fn list<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, path: Option<&str>, owner: Option<i32>, after: i32, limit: i64, executor: E) -> Result<Vec<LfsLock>> {
+        Ok(sqlx::query_as::<_, LfsLock>(
+            "select lfs_locks.*, users.username as owner_name from lfs_locks \
+            inner join users on users.id = lfs_locks.owner \
+            where lfs_locks.repo = $1 and ($2::varchar is null or lfs_locks.path = $2) and ($3::integer is null or lfs_locks.owner = $3) and lfs_locks.id > $4 \
+            order by lfs_locks.id limit $5"
+        )
+            .bind(&repo.id)
+            .bind(path)
+            .bind(owner)
+            .bind(&after)
+            .bind(&limit)
+            .fetch_all(executor)
+            .await?)
+    }
+}
@@ -163,6 +163,69 @@ pub(crate) async fn ls_refs_all(repo: &Git2Repository) -> Result<Bytes> {
     writer.serialize().await
 }
 
+// Used by git-upload-pack ref discovery for clients which do not speak protocol v2
+#[instrument(err, skip(repo))]
+pub(crate) async fn ls_refs_upload_pack(repo: &Git2Repository) -> Result<Bytes> {
+    let mut writer = GitWriter::new();
+
+    writer.write_text("# service=git-upload-pack").await?;
+    writer.flush().await?;
+
+    let mut capabilities = upload_pack_capabilities().to_owned();
+
+    if let Ok(head) = repo.find_reference("HEAD") {
+        if let Some(target) = head.symbolic_target() {
+            capabilities.push_str(format!(" symref=HEAD:{}", target).as_str());
+        }
+    }
+
+    let mut lines = Vec::<String>::new();
+
+    // HEAD has to be advertised first if it exists
+    if let Ok(oid) = repo.refname_to_id("HEAD") {
+        lines.push(format!("{} HEAD", oid));
+    }
+
+    for result in repo.references()? {
+        match result {
+            Ok(reference) => {
+                if let (Some(name), Ok(resolved)) = (reference.name(), reference.resolve()) {
+                    if let Some(oid) = resolved.target() {
+                        lines.push(format!("{} {}", oid, name));
+
+                        if let Some(peeled) = resolved.target_peel() {
+                            lines.push(format!("{} {}^{{}}", peeled, name));
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Failed to grab repository references for {}: {}", repo.path().display(), e);
+            }
+        }
+    }
+
+    // Git ignores capabilities written after the first line. If there are no refs at all, send a null ref with them
+    match lines.first_mut() {
+        Some(first) => {
+            first.push('\x00');
+            first.push_str(capabilities.as_str());
+        }
+        None => lines.push(format!("0000000000000000000000000000000000000000 capabilities^{{}}\x00{}", capabilities))
+    }
+
+    for line in lines {
+        writer.write_text(line).await?;
+    }
+
+    writer.flush().await?;
+    writer.serialize().await
+}
+
+const fn upload_pack_capabilities() -> &'static str {
+    concat!("side-band-64k ofs-delta shallow object-format=sha1 agent=git/gitarena-", env!("CARGO_PKG_VERSION"))
+}
+
 const fn receive_pack_capabilities() -> &'static str {
     concat!("\x00report-status report-status-v2 delete-refs side-band-64k quiet object-format=sha1 agent=git/gitarena-", env!("CARGO_PKG_VERSION"))
 }
@@ -0,0 +1,76 @@
+//! Merges of pull requests. Everything happens in memory using the object database, as repositories are bare and have no working tree.
+
+use crate::{die, err};
+
+use anyhow::Result;
+use git2::{Oid, Repository as Git2Repository, Signature};
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MergeStrategy {
+    /// Moves the target branch to the head commit, only possible if the target branch has not diverged
+    FastForward,
+    /// Creates a merge commit with the target branch and the head commit as parents
+    Merge,
+    /// Creates a single commit containing all changes on top of the target branch
+    Squash
+}
+
+/// Resolves `branch` in `source` and makes it available as `pull_ref` in `target`. If both are different repositories
+/// (pull requests from forks), the branch gets fetched from the file system path of `source`
+pub(crate) fn fetch_head(target: &Git2Repository, source: &Git2Repository, branch: &str, pull_ref: &str) -> Result<Oid> {
+    let head = source.refname_to_id(format!("refs/heads/{}", branch).as_str()).map_err(|_| err!(NOT_FOUND, "Source branch {} not found", branch))?;
+
+    if target.path() == source.path() {
+        target.reference(pull_ref, head, true, "pull request head")?;
+    } else {
+        let source_path = source.path().to_str().ok_or_else(|| err!(INTERNAL_SERVER_ERROR, "Source repository path is not valid UTF-8"))?;
+        let refspec = format!("+refs/heads/{}:{}", branch, pull_ref);
+
+        target.remote_anonymous(source_path)?.fetch(&[refspec.as_str()], None, None)?;
+    }
+
+    Ok(target.refname_to_id(pull_ref)?)
+}
+
+/// Checks whenever merging `head` into `target` would result in conflicts, without writing anything to the repository
+pub(crate) fn has_conflicts(repo: &Git2Repository, target: Oid, head: Oid) -> Result<bool> {
+    let target_commit = repo.find_commit(target)?;
+    let head_commit = repo.find_commit(head)?;
+
+    Ok(repo.merge_commits(&target_commit, &head_commit, None)?.has_conflicts())
+}
+
+/// Merges `head` into `target` using `strategy` and returns the commit the target branch should point to afterwards.
+/// Created commits are not referenced by anything yet, updating the branch is up to the caller
+pub(crate) fn merge(repo: &Git2Repository, strategy: MergeStrategy, target: Oid, head: Oid, message: &str, author: &Signature<'_>, committer: &Signature<'_>) -> Result<Oid> {
+    if target == head || repo.graph_descendant_of(target, head)? {
+        die!(CONFLICT, "Target branch already contains all commits of the pull request");
+    }
+
+    if let MergeStrategy::FastForward = strategy {
+        if !repo.graph_descendant_of(head, target)? {
+            die!(CONFLICT, "Target branch has diverged, fast-forward is not possible");
+        }
+
+        return Ok(head);
+    }
+
+    let target_commit = repo.find_commit(target)?;
+    let head_commit = repo.find_commit(head)?;
+
+    let mut index = repo.merge_commits(&target_commit, &head_commit, None)?;
+
+    if index.has_conflicts() {
+        die!(CONFLICT, "Pull request has merge conflicts");
+    }
+
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    let parents = match strategy {
+        MergeStrategy::Squash => vec![&target_commit],
+        _ => vec![&target_commit, &head_commit]
+    };
+
+    Ok(repo.commit(None, author, committer, message, &tree, parents.as_slice())?)
+}
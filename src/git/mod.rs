@@ -1,15 +1,24 @@
 use git_repository::hash::Kind;
 
 pub(crate) mod basic_auth;
+pub(crate) mod blame;
 pub(crate) mod capabilities;
+pub(crate) mod diff;
 pub(crate) mod fetch;
 pub(crate) mod history;
 pub(crate) mod hooks;
 pub(crate) mod io;
+pub(crate) mod languages;
+pub(crate) mod lfs;
 pub(crate) mod ls_refs;
+pub(crate) mod merge;
 pub(crate) mod pack;
+pub(crate) mod push;
 pub(crate) mod receive_pack;
 pub(crate) mod ref_update;
+pub(crate) mod search;
+pub(crate) mod signature;
+pub(crate) mod sshsig;
 pub(crate) mod utils;
 pub(crate) mod write;
 
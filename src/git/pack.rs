@@ -1,12 +1,16 @@
 use crate::git::GIT_HASH_KIND;
 use crate::repository::Repository;
+use crate::{die, err};
 
+use std::fs::File;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 
+use actix_web::web::{Bytes, BytesMut};
 use anyhow::{anyhow, Result};
+use futures::{Stream, StreamExt};
 use git_repository::odb::pack::bundle::write::Options as GitPackWriteOptions;
 use git_repository::odb::pack::data::input::{Mode as PackIterationMode};
 use git_repository::odb::pack::index::Version as PackVersion;
@@ -14,13 +18,125 @@ use git_repository::odb::pack::{Bundle, FindExt};
 use git_repository::progress;
 use sqlx::{Executor, Postgres};
 use tempfile::{Builder, TempDir};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use tracing::instrument;
 
+/// Upper limit for the ref update commands preceding the pack. Even pushes of thousands of refs stay well below this
+const MAX_COMMANDS_SIZE: usize = 1024 * 1024;
+
+/// Body of a receive-pack request. The pack is spooled to a temporary file instead of being kept in memory
+pub(crate) struct ReceivedPush {
+    /// Ref update commands as pkt-lines, including the terminating flush packet
+    pub(crate) commands: Bytes,
+    /// `None` if the client didn't send a pack, which is the case if it only deletes refs
+    pub(crate) pack_path: Option<PathBuf>,
+    /// Total size of the request body in bytes
+    pub(crate) size: u64,
+    _temp_dir: TempDir
+}
+
+/// Reads a receive-pack request body from `stream`, writing the pack to disk as it arrives.
+/// Fails as soon as the body turns out to be larger than `max_size` bytes
+pub(crate) async fn receive<S, B, E>(mut stream: S, max_size: u64) -> Result<ReceivedPush>
+    where S: Stream<Item = Result<B, E>> + Unpin,
+          B: AsRef<[u8]>,
+          E: std::error::Error + Send + Sync + 'static
+{
+    let temp_dir = Builder::new().prefix("gitarena_").tempdir()?;
+    let pack_path = temp_dir.path().join("incoming.pack");
+
+    let mut buffer = BytesMut::new();
+    let mut commands = None;
+    let mut pack_file = None;
+    let mut size = 0_u64;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        let chunk = chunk.as_ref();
+
+        size += chunk.len() as u64;
+
+        if size > max_size {
+            die!(PAYLOAD_TOO_LARGE, "Push exceeds the maximum size of {} bytes", max_size);
+        }
+
+        let data = if commands.is_none() {
+            buffer.extend_from_slice(chunk);
+
+            match command_section_len(&buffer)? {
+                Some(length) => {
+                    let rest = buffer.split_off(length);
+                    commands = Some(buffer.split().freeze());
+
+                    rest
+                }
+                None if buffer.len() > MAX_COMMANDS_SIZE => die!(PAYLOAD_TOO_LARGE, "Ref update list exceeds {} bytes", MAX_COMMANDS_SIZE),
+                None => continue
+            }
+        } else {
+            BytesMut::from(chunk)
+        };
+
+        if data.is_empty() {
+            continue;
+        }
+
+        if pack_file.is_none() {
+            pack_file = Some(fs::File::create(&pack_path).await?);
+        }
+
+        if let Some(file) = pack_file.as_mut() {
+            file.write_all(&data).await?;
+        }
+    }
+
+    let commands = commands.ok_or_else(|| err!(BAD_REQUEST, "Ref update list is incomplete"))?;
+
+    let pack_path = match pack_file {
+        Some(mut file) => {
+            file.flush().await?;
+            Some(pack_path)
+        }
+        None => None
+    };
+
+    Ok(ReceivedPush {
+        commands,
+        pack_path,
+        size,
+        _temp_dir: temp_dir
+    })
+}
+
+/// Returns the length of the pkt-lines up to and including the first flush packet or `None` if it hasn't been received yet
+fn command_section_len(buffer: &[u8]) -> Result<Option<usize>> {
+    let mut position = 0;
+
+    while let Some(prefix) = buffer.get(position..position + 4) {
+        let length = std::str::from_utf8(prefix)
+            .ok()
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| err!(BAD_REQUEST, "Received invalid pkt-line"))?;
+
+        match length {
+            0 => return Ok(Some(position + 4)),
+            1..=3 => die!(BAD_REQUEST, "Received invalid pkt-line"),
+            _ => position += length
+        }
+    }
+
+    Ok(None)
+}
+
 /// Returns path to index file, pack file and temporary dir.
 /// Ensure that the third tuple argument, the temporary dir, is alive for the whole duration of your usage.
-/// It being dropped results in the index and pack file to be deleted and thus the paths becoming invalid
-#[instrument(err, skip(data, executor))]
-pub(crate) async fn read<'e, E: Executor<'e, Database = Postgres>>(data: &[u8], repo: &Repository, executor: E) -> Result<(Option<PathBuf>, Option<PathBuf>, TempDir)> {
+/// It being dropped results in the index and pack file to be deleted and thus the paths becoming invalid.
+///
+/// The temporary dir is laid out like an objects directory (the pack is written into its `pack` subdirectory), so it can
+/// be used as quarantine: the objects of the push can be inspected without writing them into the repository first
+#[instrument(err, skip(executor))]
+pub(crate) async fn read<'e, E: Executor<'e, Database = Postgres>>(data: &Path, repo: &Repository, executor: E) -> Result<(Option<PathBuf>, Option<PathBuf>, TempDir)> {
     let temp_dir = Builder::new().prefix("gitarena_").tempdir()?;
 
     match write_to_fs(data, &temp_dir, repo, executor).await {
@@ -32,8 +148,8 @@ pub(crate) async fn read<'e, E: Executor<'e, Database = Postgres>>(data: &[u8],
     }
 }
 
-#[instrument(err, skip(data, executor))]
-pub(crate) async fn write_to_fs<'e, E: Executor<'e, Database = Postgres>>(data: &[u8], temp_dir: &TempDir, repo: &Repository, executor: E) -> Result<(PathBuf, PathBuf)> {
+#[instrument(err, skip(executor))]
+pub(crate) async fn write_to_fs<'e, E: Executor<'e, Database = Postgres>>(data: &Path, temp_dir: &TempDir, repo: &Repository, executor: E) -> Result<(PathBuf, PathBuf)> {
     let options = GitPackWriteOptions {
         thread_limit: Some(num_cpus::get()),
         iteration_mode: PackIterationMode::Verify,
@@ -44,11 +160,14 @@ pub(crate) async fn write_to_fs<'e, E: Executor<'e, Database = Postgres>>(data:
     let repo = repo.gitoxide(executor).await?;
     let objects = Arc::new(repo.objects);
 
-    let buf_reader = BufReader::new(data);
+    let buf_reader = BufReader::new(File::open(data)?);
+
+    let pack_dir = temp_dir.path().join("pack");
+    fs::create_dir_all(&pack_dir).await?;
 
     let bundle = Bundle::write_to_directory(
         buf_reader,
-        Some(&temp_dir),
+        Some(&pack_dir),
         progress::Discard,
         &AtomicBool::new(false), // The Actix runtime (+ tokio) handles timeouts for us
         Some(Box::new(move |oid, buffer| {
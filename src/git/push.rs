@@ -0,0 +1,70 @@
+//! Steps shared by every way of pushing into a repository, smart HTTP (`git_receive_pack`) as well as SSH (`ssh_hooks`).
+
+use crate::activity::{self, ActivityKind};
+use crate::contributions;
+use crate::git::hooks::post_update;
+use crate::repository::Repository;
+use crate::user::User;
+use crate::webhook::{self, PushPayload, PushedRef, WebhookEvents};
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use async_process::{Command, Stdio};
+use log::warn;
+use serde_json::json;
+use sqlx::{Connection, PgPool, Postgres, Transaction};
+
+/// Runs everything that follows a push once its refs have been updated: garbage collection, updating the cached size,
+/// post update hooks, contributions and activity. Commits `transaction` and delivers webhooks afterwards.
+///
+/// `pusher` is `None` for pushes using a deploy key, which are neither recorded as activity nor attributed in webhooks
+pub(crate) async fn finish(repo: &mut Repository, owner_name: &str, pusher: Option<&User>, pushed_refs: Vec<PushedRef>, db_pool: &PgPool, mut transaction: Transaction<'_, Postgres>) -> Result<()> {
+    let repo_dir_str = repo.get_fs_path(&mut transaction).await?;
+    let repo_dir = Path::new(&repo_dir_str);
+
+    // Let Git collect garbage to optimize repo size
+    match Command::new("git").args(&["gc", "--auto", "--quiet"]).current_dir(repo_dir).stdout(Stdio::null()).stderr(Stdio::null()).status().await {
+        Ok(status) => if !status.success() {
+            warn!("Git garbage collector exited with non-zero status: {}", status);
+        }
+        Err(err) => warn!("Failed to execute Git garbage collector: {}", err)
+    }
+
+    // Quotas and the repository page rely on the cached size, so it needs to be updated after every push
+    repo.update_size(&mut transaction).await?;
+
+    let store = repo.gitoxide(&mut transaction).await?.objects.clone();
+
+    // Run post update hooks
+    post_update::run(store, repo, &mut transaction)
+        .await
+        .with_context(|| format!("Failed to run post update hook for newest commit in {}/{}", owner_name, repo.name))?;
+
+    // Contributions are only derived data, so a failure should not fail the push. The savepoint is rolled back if it does
+    let mut savepoint = transaction.begin().await?;
+
+    match contributions::refresh(repo, &mut savepoint).await {
+        Ok(()) => savepoint.commit().await?,
+        Err(err) => warn!("Failed to refresh contributions for repo id {}: {}", &repo.id, err)
+    }
+
+    sqlx::query("update repositories set license = $1 where id = $2")
+        .bind(&repo.license)
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    if let (Some(pusher), false) = (pusher, pushed_refs.is_empty()) {
+        activity::record(pusher, repo, ActivityKind::Push, json!({ "refs": &pushed_refs }), &mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
+    if !pushed_refs.is_empty() {
+        let payload = PushPayload::new(repo, owner_name, pusher, pushed_refs);
+        webhook::deliver_in_background(repo.id, WebhookEvents::PUSH, &payload, db_pool.clone());
+    }
+
+    Ok(())
+}
@@ -8,8 +8,9 @@ use crate::utils::oid;
 use crate::{die, err};
 
 use std::convert::TryInto;
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
@@ -23,11 +24,11 @@ use git_repository::odb::pack::{cache, FindExt};
 use git_repository::odb::Store;
 use git_repository::refs::Target;
 use git_repository::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
-use sqlx::{Executor, PgPool, Postgres};
+use sqlx::{PgPool, Postgres, Transaction};
 use tracing::instrument;
 
 #[instrument(err, skip(writer, store))]
-pub(crate) async fn process_create_update(ref_update: &RefUpdate, repo: &Repository, store: Arc<Store>, db_pool: &PgPool, writer: &mut GitWriter, index_path: Option<&PathBuf>, pack_path: Option<&PathBuf>, raw_pack: &[u8]) -> Result<()> {
+pub(crate) async fn process_create_update(ref_update: &RefUpdate, repo: &Repository, store: Arc<Store>, db_pool: &PgPool, writer: &mut GitWriter, index_path: Option<&PathBuf>, pack_path: Option<&PathBuf>, raw_pack: &Path) -> Result<()> {
     assert!(ref_update.new.is_some());
 
     let mut transaction = db_pool.begin().await?;
@@ -86,9 +87,9 @@ pub(crate) async fn process_create_update(ref_update: &RefUpdate, repo: &Reposit
             let previous_oid = oid::from_hex_str(Some(previous_oid_str.as_str()))?;
             let previous_target = Target::Peeled(previous_oid);
 
-            PreviousValue::ExistingMustMatch(previous_target)
+            PreviousValue::MustExistAndMatch(previous_target)
         } else {
-            PreviousValue::Any
+            PreviousValue::MustNotExist
         };
 
         let edits = vec![
@@ -123,7 +124,7 @@ pub(crate) async fn process_create_update(ref_update: &RefUpdate, repo: &Reposit
         let odb = git2_repo.odb()?;
         let mut pack_writer = odb.packwriter()?;
 
-        pack_writer.write_all(raw_pack)?;
+        io::copy(&mut File::open(raw_pack)?, &mut pack_writer)?;
         pack_writer.commit()?;
     }
 
@@ -134,12 +135,12 @@ pub(crate) async fn process_create_update(ref_update: &RefUpdate, repo: &Reposit
     Ok(())
 }
 
-#[instrument(err, skip(writer))]
-pub(crate) async fn process_delete<'e, E: Executor<'e, Database = Postgres>>(ref_update: &RefUpdate, repo: &Repository, executor: E, writer: &mut GitWriter) -> Result<()> {
+#[instrument(err, skip(transaction, writer))]
+pub(crate) async fn process_delete(ref_update: &RefUpdate, repo: &Repository, transaction: &mut Transaction<'_, Postgres>, writer: &mut GitWriter) -> Result<()> {
     assert!(ref_update.old.is_some());
     assert!(ref_update.new.is_none());
 
-    let gitoxide_repo = repo.gitoxide(executor).await?;
+    let gitoxide_repo = repo.gitoxide(&mut *transaction).await?;
 
     let object_id = oid::from_hex_str(ref_update.old.as_deref()).map_err(|_| err!(NOT_FOUND, "Ref does not exist"))?;
 
@@ -157,7 +158,7 @@ pub(crate) async fn process_delete<'e, E: Executor<'e, Database = Postgres>>(ref
     gitoxide_repo.refs.transaction()
         .prepare(edits, Fail::Immediately)
         .map_err(|err| err!(INTERNAL_SERVER_ERROR, "Failed to commit transaction: {}", err))?
-        .commit(&Signature::gitarena_default())?;
+        .commit(&Signature::gitarena_default(&mut *transaction).await?)?;
 
     if ref_update.report_status || ref_update.report_status_v2 {
         writer.write_text_sideband_pktline(Band::Data, format!("ok {}", ref_update.target_ref)).await?;
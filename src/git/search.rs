@@ -0,0 +1,126 @@
+//! Searching file contents of a repository.
+//!
+//! Searches are delegated to `git grep` running against the tree of a commit, so nothing needs to be checked out.
+//! As patterns are user provided, Git is killed after [SEARCH_TIMEOUT] or once [MAX_RESULTS] matching lines have been found.
+
+use crate::err;
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time;
+use tracing::instrument;
+
+pub(crate) const SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+pub(crate) const MAX_RESULTS: usize = 100;
+
+/// Matching lines longer than this are cut off, as minified files would otherwise blow up the result page
+const MAX_LINE_LENGTH: usize = 256;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SearchMatch {
+    pub(crate) path: String,
+    /// 1-based line number
+    pub(crate) line: usize,
+    pub(crate) content: String
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct SearchResults {
+    pub(crate) matches: Vec<SearchMatch>,
+    /// Whether the search was stopped early because it took too long or found too many matches
+    pub(crate) truncated: bool
+}
+
+/// Searches all text files in `tree` (such as a branch or commit) for lines containing `query`.
+/// If `regex` is set, `query` is interpreted as extended regular expression, otherwise it is matched literally
+#[instrument(err, skip(repo_path))]
+pub(crate) async fn search(repo_path: &Path, tree: &str, query: &str, regex: bool, ignore_case: bool) -> Result<SearchResults> {
+    let mut command = Command::new("git");
+
+    command.current_dir(repo_path)
+        .args(&["grep", "--null", "--line-number", "-I", "--no-color"])
+        .arg(if regex { "--extended-regexp" } else { "--fixed-strings" });
+
+    if ignore_case {
+        command.arg("--ignore-case");
+    }
+
+    let mut child = command.args(&["-e", query, tree, "--"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdout = match child.stdout.take() {
+        Some(stdout) => stdout,
+        None => bail!("Failed to read output of git grep")
+    };
+
+    let mut lines = BufReader::new(stdout).split(b'\n');
+    let mut results = SearchResults::default();
+    let prefix = format!("{}:", tree);
+
+    let read = async {
+        while let Some(line) = lines.next_segment().await? {
+            if let Some(search_match) = parse_line(String::from_utf8_lossy(&line).as_ref(), prefix.as_str()) {
+                results.matches.push(search_match);
+            }
+
+            if results.matches.len() >= MAX_RESULTS {
+                results.truncated = true;
+                break;
+            }
+        }
+
+        Ok::<_, anyhow::Error>(())
+    };
+
+    match time::timeout(SEARCH_TIMEOUT, read).await {
+        Ok(result) => result?,
+        Err(_) => results.truncated = true
+    }
+
+    if results.truncated {
+        let _ = child.kill().await;
+        return Ok(results);
+    }
+
+    // Git exits with 1 if nothing matched and with 128 if the pattern is invalid
+    match child.wait().await?.code() {
+        Some(0) | Some(1) => Ok(results),
+        _ => Err(err!(BAD_REQUEST, "Invalid search query").into())
+    }
+}
+
+/// Parses a line of `git grep --null --line-number <tree>` which looks like `<tree>:<path>\0<line>\0<content>`
+fn parse_line(line: &str, prefix: &str) -> Option<SearchMatch> {
+    let mut parts = line.strip_prefix(prefix)?.splitn(3, '\0');
+
+    let path = parts.next()?.to_owned();
+    let line = parts.next()?.parse::<usize>().ok()?;
+    let mut content = parts.next()?.to_owned();
+
+    if content.len() > MAX_LINE_LENGTH {
+        let mut end = MAX_LINE_LENGTH;
+
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        content.truncate(end);
+        content.push('…');
+    }
+
+    Some(SearchMatch {
+        path,
+        line,
+        content
+    })
+}
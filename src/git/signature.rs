@@ -0,0 +1,150 @@
+use crate::git::sshsig::SshSignature;
+use crate::gpg;
+
+use anyhow::Result;
+use derive_more::Display;
+use git2::{Oid, Repository as Git2Repository};
+use serde::Serialize;
+use sqlx::{Executor, Postgres};
+use tracing::instrument;
+
+#[derive(Display, Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all(serialize = "lowercase"))]
+pub(crate) enum VerificationStatus {
+    /// Signature is valid and was made by a key registered to the committer
+    Valid,
+    /// Signature could not be verified or was made by a key belonging to a different user
+    Invalid,
+    /// Signature was made by a key unknown to this instance
+    Unknown
+}
+
+/// Verifies the signature of commit `oid`. Returns `None` if the commit is not signed
+#[instrument(err, skip(repo, executor))]
+pub(crate) async fn verify_commit<'e, E: Executor<'e, Database = Postgres>>(repo: &Git2Repository, oid: Oid, executor: E) -> Result<Option<VerificationStatus>> {
+    let (signature, signed_data) = match repo.extract_signature(&oid, None) {
+        Ok(tuple) => tuple,
+        Err(_) => return Ok(None) // libgit2 returns `NotFound` if the commit has no signature
+    };
+
+    let commit = repo.find_commit(oid)?;
+    let committer_email = commit.committer().email().unwrap_or_default().to_owned();
+
+    let signature = String::from_utf8_lossy(signature.as_ref());
+
+    if signature.starts_with("-----BEGIN PGP SIGNATURE-----") {
+        return Ok(Some(verify_gpg(signature.as_ref(), signed_data.as_ref(), committer_email.as_str(), executor).await?));
+    }
+
+    if signature.starts_with("-----BEGIN SSH SIGNATURE-----") {
+        return Ok(Some(verify_ssh(signature.as_ref(), signed_data.as_ref(), committer_email.as_str(), executor).await?));
+    }
+
+    Ok(Some(VerificationStatus::Unknown))
+}
+
+async fn verify_gpg<'e, E: Executor<'e, Database = Postgres>>(signature: &str, data: &[u8], committer_email: &str, executor: E) -> Result<VerificationStatus> {
+    let (signature, issuer) = match gpg::parse_signature(signature) {
+        Ok(tuple) => tuple,
+        Err(_) => return Ok(VerificationStatus::Invalid)
+    };
+
+    // The key needs to belong to the user who owns the verified committer email address
+    let key_option: Option<(String, bool)> = sqlx::query_as(
+        "select gpg_keys.armored, exists(select 1 from emails where emails.owner = gpg_keys.owner and lower(emails.email) = lower($2) and emails.verified_at is not null) \
+        from gpg_keys where gpg_keys.key_id = $1 or $1 = any(gpg_keys.subkey_ids) limit 1"
+    )
+        .bind(issuer.as_str())
+        .bind(committer_email)
+        .fetch_optional(executor)
+        .await?;
+
+    let (armored, owns_email) = match key_option {
+        Some(tuple) => tuple,
+        None => return Ok(VerificationStatus::Unknown)
+    };
+
+    let public_key = gpg::parse_public_key(armored.as_str())?;
+
+    Ok(if owns_email && gpg::verify(&public_key, &signature, data) {
+        VerificationStatus::Valid
+    } else {
+        VerificationStatus::Invalid
+    })
+}
+
+async fn verify_ssh<'e, E: Executor<'e, Database = Postgres>>(signature: &str, data: &[u8], committer_email: &str, executor: E) -> Result<VerificationStatus> {
+    let signature = match SshSignature::parse(signature) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(VerificationStatus::Invalid)
+    };
+
+    // SSH signatures embed the full public key, so it can be looked up directly in the SSH keys registered by users
+    let owns_email_option: Option<(bool,)> = sqlx::query_as(
+        "select exists(select 1 from emails where emails.owner = ssh_keys.owner and lower(emails.email) = lower($2) and emails.verified_at is not null) \
+        from ssh_keys where ssh_keys.key = $1 and (ssh_keys.expires_at is null or ssh_keys.expires_at > now()) limit 1"
+    )
+        .bind(signature.public_key.as_slice())
+        .bind(committer_email)
+        .fetch_optional(executor)
+        .await?;
+
+    let owns_email = match owns_email_option {
+        Some((owns_email,)) => owns_email,
+        None => return Ok(VerificationStatus::Unknown)
+    };
+
+    Ok(if owns_email && signature.verify(data).unwrap_or(false) {
+        VerificationStatus::Valid
+    } else {
+        VerificationStatus::Invalid
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::git::sshsig::SshSignature;
+
+    use git2::{ObjectType, Repository as Git2Repository};
+
+    /// Created using `git commit -S` with `gpg.format = ssh` and the ed25519 key below
+    const SIGNED_COMMIT: &str = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+author GitArena <test@gitarena.com> 1650000000 +0000
+committer GitArena <test@gitarena.com> 1650000000 +0000
+gpgsig -----BEGIN SSH SIGNATURE-----
+ U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAg8a9GOyeP8MIKk0UDJMIkMbRKyn
+ I7kmIl896nfHgTz18AAAADZ2l0AAAAAAAAAAZzaGE1MTIAAABTAAAAC3NzaC1lZDI1NTE5
+ AAAAQIKnmtAXnc/G7MMRLZWUNzccedNPgaKVADqC6ZIDaZSRjxOlPxe+5+4L+rm86JD3qk
+ d3HQvNS3V42ozi8a++TwU=
+ -----END SSH SIGNATURE-----
+
+Signed commit
+";
+
+    const PUBLIC_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIPGvRjsnj/DCCpNFAyTCJDG0SspyO5JiJfPep3x4E89f";
+
+    /// Writes `raw` into a new repository and verifies it the same way [verify_commit](super::verify_commit) does
+    fn verify_raw_commit(raw: &str) -> bool {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Git2Repository::init_bare(dir.path()).unwrap();
+        let oid = repo.odb().unwrap().write(ObjectType::Commit, raw.as_bytes()).unwrap();
+
+        let (signature, signed_data) = repo.extract_signature(&oid, None).unwrap();
+        let signature = SshSignature::parse(String::from_utf8_lossy(signature.as_ref()).as_ref()).unwrap();
+
+        assert_eq!(signature.public_key, base64::decode(PUBLIC_KEY).unwrap());
+
+        signature.verify(signed_data.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn ssh_signature_is_valid() {
+        assert!(verify_raw_commit(SIGNED_COMMIT));
+    }
+
+    #[test]
+    fn tampered_ssh_signed_commit_is_invalid() {
+        assert!(!verify_raw_commit(SIGNED_COMMIT.replace("Signed commit", "Tampered commit").as_str()));
+        assert!(!verify_raw_commit(SIGNED_COMMIT.replace("test@gitarena.com> 1650000000", "evil@gitarena.com> 1650000000").as_str()));
+    }
+}
@@ -0,0 +1,175 @@
+//! Parsing and verification of SSH signatures as created by `ssh-keygen -Y sign` and thus `git commit -S` with `gpg.format = ssh`.
+//!
+//! The format is described in [PROTOCOL.sshsig](https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.sshsig).
+
+use anyhow::{anyhow, bail, Result};
+use ring::signature::{self as ring_signature, RsaPublicKeyComponents, UnparsedPublicKey};
+use sha2::{Digest, Sha256, Sha512};
+
+const MAGIC_PREAMBLE: &[u8] = b"SSHSIG";
+const SIG_VERSION: u32 = 1;
+
+/// Namespace used by Git for commit and tag signatures
+const GIT_NAMESPACE: &[u8] = b"git";
+
+#[derive(Debug)]
+pub(crate) struct SshSignature {
+    /// Public key in SSH wire format, the same format as stored in `ssh_keys.key`
+    pub(crate) public_key: Vec<u8>,
+    namespace: Vec<u8>,
+    reserved: Vec<u8>,
+    hash_algorithm: String,
+    signature: Vec<u8>
+}
+
+impl SshSignature {
+    /// Parses an armored (`-----BEGIN SSH SIGNATURE-----`) signature
+    pub(crate) fn parse(armored: &str) -> Result<SshSignature> {
+        let encoded = armored.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+            .collect::<String>();
+
+        let blob = base64::decode(encoded)?;
+        let mut reader = WireReader::new(blob.as_slice());
+
+        if reader.read_bytes(MAGIC_PREAMBLE.len())? != MAGIC_PREAMBLE {
+            bail!("SSH signature does not start with magic preamble");
+        }
+
+        if reader.read_u32()? != SIG_VERSION {
+            bail!("Unsupported SSH signature version");
+        }
+
+        Ok(SshSignature {
+            public_key: reader.read_string()?.to_vec(),
+            namespace: reader.read_string()?.to_vec(),
+            reserved: reader.read_string()?.to_vec(),
+            hash_algorithm: String::from_utf8(reader.read_string()?.to_vec())?,
+            signature: reader.read_string()?.to_vec()
+        })
+    }
+
+    /// Checks if this is a valid signature of `message` in the `git` namespace made by [the embedded public key](SshSignature::public_key)
+    pub(crate) fn verify(&self, message: &[u8]) -> Result<bool> {
+        if self.namespace != GIT_NAMESPACE {
+            return Ok(false);
+        }
+
+        let hash = match self.hash_algorithm.as_str() {
+            "sha256" => Sha256::digest(message).to_vec(),
+            "sha512" => Sha512::digest(message).to_vec(),
+            _ => bail!("Unsupported SSH signature hash algorithm: {}", self.hash_algorithm)
+        };
+
+        // The signature is not made over the message itself but over this structure containing its hash
+        let mut signed_data = MAGIC_PREAMBLE.to_vec();
+        write_string(&mut signed_data, self.namespace.as_slice());
+        write_string(&mut signed_data, self.reserved.as_slice());
+        write_string(&mut signed_data, self.hash_algorithm.as_bytes());
+        write_string(&mut signed_data, hash.as_slice());
+
+        let mut signature_reader = WireReader::new(self.signature.as_slice());
+        let signature_type = signature_reader.read_string()?;
+        let signature_blob = signature_reader.read_string()?;
+
+        let mut key_reader = WireReader::new(self.public_key.as_slice());
+        let key_type = key_reader.read_string()?;
+
+        Ok(match (key_type, signature_type) {
+            (b"ssh-ed25519", b"ssh-ed25519") => {
+                let key = key_reader.read_string()?;
+
+                UnparsedPublicKey::new(&ring_signature::ED25519, key).verify(signed_data.as_slice(), signature_blob).is_ok()
+            }
+            (b"ssh-rsa", b"rsa-sha2-256" | b"rsa-sha2-512") => {
+                let e = strip_mpint(key_reader.read_string()?);
+                let n = strip_mpint(key_reader.read_string()?);
+
+                let algorithm = if signature_type == b"rsa-sha2-256" {
+                    &ring_signature::RSA_PKCS1_2048_8192_SHA256
+                } else {
+                    &ring_signature::RSA_PKCS1_2048_8192_SHA512
+                };
+
+                RsaPublicKeyComponents { n, e }.verify(algorithm, signed_data.as_slice(), signature_blob).is_ok()
+            }
+            (b"ecdsa-sha2-nistp256", b"ecdsa-sha2-nistp256") | (b"ecdsa-sha2-nistp384", b"ecdsa-sha2-nistp384") => {
+                let _curve = key_reader.read_string()?;
+                let point = key_reader.read_string()?;
+
+                let (algorithm, length): (&ring_signature::EcdsaVerificationAlgorithm, usize) = if key_type == b"ecdsa-sha2-nistp256" {
+                    (&ring_signature::ECDSA_P256_SHA256_FIXED, 32)
+                } else {
+                    (&ring_signature::ECDSA_P384_SHA384_FIXED, 48)
+                };
+
+                // ECDSA signatures are encoded as two mpints (r and s), ring expects them as fixed size big endian integers
+                let mut blob_reader = WireReader::new(signature_blob);
+                let r = left_pad(strip_mpint(blob_reader.read_string()?), length)?;
+                let s = left_pad(strip_mpint(blob_reader.read_string()?), length)?;
+
+                UnparsedPublicKey::new(algorithm, point).verify(signed_data.as_slice(), [r, s].concat().as_slice()).is_ok()
+            }
+            _ => false
+        })
+    }
+}
+
+/// Reader for the SSH wire format (RFC 4251, section 5)
+struct WireReader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> WireReader<'a> {
+    fn new(data: &'a [u8]) -> WireReader<'a> {
+        WireReader { data }
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.data.len() < length {
+            bail!("Unexpected end of SSH wire data");
+        }
+
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.read_bytes(4)?;
+
+        Ok(u32::from_be_bytes(bytes.try_into().map_err(|_| anyhow!("Unexpected end of SSH wire data"))?))
+    }
+
+    fn read_string(&mut self) -> Result<&'a [u8]> {
+        let length = self.read_u32()? as usize;
+
+        self.read_bytes(length)
+    }
+}
+
+fn write_string(buffer: &mut Vec<u8>, data: &[u8]) {
+    buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(data);
+}
+
+/// Strips the leading zero byte mpints have if the most significant bit is set
+fn strip_mpint(mpint: &[u8]) -> &[u8] {
+    match mpint {
+        [0, rest @ ..] => rest,
+        _ => mpint
+    }
+}
+
+fn left_pad(data: &[u8], length: usize) -> Result<Vec<u8>> {
+    if data.len() > length {
+        bail!("Integer too large for curve");
+    }
+
+    let mut padded = vec![0_u8; length - data.len()];
+    padded.extend_from_slice(data);
+
+    Ok(padded)
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use async_recursion::async_recursion;
+use git2::Reference as Git2Reference;
 use git_repository::hash::oid;
 use git_repository::objs::TreeRef;
 use git_repository::odb::pack::FindExt;
@@ -54,3 +55,8 @@ pub(crate) async fn read_blob_content(oid: &oid, store: Arc<Store>) -> Result<St
 
     Ok(file_content.to_owned())
 }
+
+/// Checks if `name` can be used as the name of a branch, e.g. as the default branch of a repository
+pub(crate) fn is_valid_branch_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 256 && Git2Reference::is_valid_name(format!("refs/heads/{}", name).as_str())
+}
@@ -1,41 +1,54 @@
 use crate::mail::Email;
+use crate::prelude::GitoxideSignatureExtensions;
 use crate::user::User;
-use crate::{err, mail};
+use crate::err;
 
 use anyhow::{Context, Result};
 use git2::{Repository as LibGit2Repo, Signature};
-use sqlx::{Pool, Postgres};
+use git_repository::actor::Signature as GitoxideSignature;
+use sqlx::{Postgres, Transaction};
 
-/// Writes and commits a file into the repository
-pub(crate) async fn write_file(repo: &LibGit2Repo, user: &User, branch: Option<&str>, file_name: &str, content: &[u8], db_pool: &Pool<Postgres>) -> Result<()> {
-    let mut transaction = db_pool.begin().await?;
-
-    let author_email = Email::find_commit_email(user, &mut transaction)
-        .await?
-        .ok_or_else(|| err!(BAD_REQUEST, "User has no commit email"))?;
-    let author_signature = Signature::now(user.username.as_str(), author_email.email.as_str())?;
-
-    let root_email = mail::get_root_email(db_pool).await?;
-    let root_signature = Signature::now("GitArena", root_email.as_str())?;
-
-    let blob = repo.blob(content).context("Failed to create blob")?;
+/// Creates the initial commit of an empty repository on `branch`, containing `files` (file name and content) at the root.
+/// The commit is authored and committed by the [server signature](server_signature)
+pub(crate) async fn write_initial_commit(repo: &LibGit2Repo, branch: &str, files: &[(&str, &[u8])], transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    let signature = server_signature(&mut *transaction).await?;
 
     let mut tree_builder = repo.treebuilder(None).context("Failed to acquire tree builder")?;
-    tree_builder.insert(file_name, blob, 0o100644).context("Failed to create blob")?;
+
+    for (file_name, content) in files {
+        let blob = repo.blob(content).context("Failed to create blob")?;
+        tree_builder.insert(file_name, blob, 0o100644).context("Failed to insert blob into tree")?;
+    }
 
     let tree_oid = tree_builder.write().context("Failed to write tree")?;
     let tree = repo.find_tree(tree_oid)?;
 
     repo.commit(
-        branch,
-        &author_signature,
-        &root_signature,
+        Some(format!("refs/heads/{}", branch).as_str()),
+        &signature,
+        &signature,
         "Initial commit",
         &tree,
         &[]
     ).context("Failed to commit")?;
 
-    transaction.commit().await?;
-
     Ok(())
 }
+
+/// Builds the signature commits authored by `user` through the web interface are made with.
+/// Users with a private email are attributed using their noreply address instead of their commit email
+pub(crate) async fn user_signature(user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<Signature<'static>> {
+    let email = Email::find_commit_email(user, &mut *transaction)
+        .await?
+        .ok_or_else(|| err!(BAD_REQUEST, "User has no commit email"))?;
+    let address = if user.private_email { user.noreply_email() } else { email.email };
+
+    Ok(Signature::now(user.username.as_str(), address.as_str())?)
+}
+
+/// Signature of GitArena itself, used as committer of merges and as tagger of tags created through the web interface
+pub(crate) async fn server_signature(transaction: &mut Transaction<'_, Postgres>) -> Result<Signature<'static>> {
+    let signature = GitoxideSignature::gitarena_default(transaction).await?;
+
+    Ok(Signature::now(String::from_utf8_lossy(&signature.name).as_ref(), String::from_utf8_lossy(&signature.email).as_ref())?)
+}
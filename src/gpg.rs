@@ -0,0 +1,51 @@
+//! OpenPGP public keys registered by users in order to verify signed commits.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use pgp::types::{KeyId, KeyTrait};
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+use serde::Serialize;
+use sqlx::FromRow;
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", key_id)]
+pub(crate) struct GpgKey {
+    pub(crate) id: i32,
+    pub(crate) owner: i32,
+    pub(crate) key_id: String,
+    pub(crate) subkey_ids: Vec<String>,
+    #[serde(skip_serializing)]
+    pub(crate) armored: String,
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Parses an ASCII armored public key and checks its self-signatures
+pub(crate) fn parse_public_key(armored: &str) -> Result<SignedPublicKey> {
+    let (public_key, _) = SignedPublicKey::from_string(armored)?;
+    public_key.verify()?;
+
+    Ok(public_key)
+}
+
+/// Returns the long key id (16 upper case hex characters) used to look up keys
+pub(crate) fn format_key_id(key_id: &KeyId) -> String {
+    hex::encode_upper(key_id.as_ref())
+}
+
+pub(crate) fn subkey_ids(public_key: &SignedPublicKey) -> Vec<String> {
+    public_key.public_subkeys.iter().map(|subkey| format_key_id(&subkey.key_id())).collect()
+}
+
+/// Parses an ASCII armored signature and returns it alongside the id of the key which issued it
+pub(crate) fn parse_signature(armored: &str) -> Result<(StandaloneSignature, String)> {
+    let (signature, _) = StandaloneSignature::from_string(armored)?;
+    let issuer = signature.signature.issuer().map(format_key_id).ok_or_else(|| anyhow!("Signature does not specify an issuer"))?;
+
+    Ok((signature, issuer))
+}
+
+/// Checks if `signature` is a valid signature of `data` made by `public_key` or one of its subkeys
+pub(crate) fn verify(public_key: &SignedPublicKey, signature: &StandaloneSignature, data: &[u8]) -> bool {
+    signature.verify(public_key, data).is_ok() || public_key.public_subkeys.iter().any(|subkey| signature.verify(subkey, data).is_ok())
+}
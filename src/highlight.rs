@@ -0,0 +1,76 @@
+//! Server-side syntax highlighting for the blob view.
+//!
+//! The syntax is picked using the file name and falls back to the first line (shebangs, modelines). Output is one HTML
+//! fragment per line so the template can add line numbers and anchors. As blobs are immutable, results are cached by blob id.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use git_repository::hash::ObjectId;
+use once_cell::sync::Lazy;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{IncludeBackground, styled_line_to_highlighted_html};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+use tokio::task;
+
+/// Amount of highlighted blobs kept in memory. Once full, an arbitrary entry is evicted
+const CACHE_CAPACITY: usize = 256;
+
+/// Dark theme matching the background of `.code-block`
+const THEME_NAME: &str = "base16-ocean.dark";
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+static THEME: Lazy<Theme> = Lazy::new(|| ThemeSet::load_defaults().themes.remove(THEME_NAME).unwrap_or_default());
+
+static CACHE: Lazy<Mutex<HashMap<ObjectId, Arc<Vec<String>>>>> = Lazy::new(Default::default);
+
+/// Highlights `content` of the blob `oid` found at `path` and returns the HTML of every line (without line breaks).
+/// Highlighting runs on the blocking thread pool as it may take a while for large files
+pub(crate) async fn highlight(oid: ObjectId, path: &str, content: &str) -> Result<Arc<Vec<String>>> {
+    if let Some(lines) = CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&oid) {
+        return Ok(lines.clone());
+    }
+
+    let path = path.to_owned();
+    let content = content.to_owned();
+
+    let lines = Arc::new(task::spawn_blocking(move || highlight_lines(path.as_str(), content.as_str())).await??);
+
+    let mut cache = CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if cache.len() >= CACHE_CAPACITY {
+        if let Some(key) = cache.keys().next().copied() {
+            cache.remove(&key);
+        }
+    }
+
+    cache.insert(oid, lines.clone());
+
+    Ok(lines)
+}
+
+fn highlight_lines(path: &str, content: &str) -> Result<Vec<String>> {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let extension = file_name.rsplit_once('.').map_or(file_name, |(_, extension)| extension);
+
+    let syntax = SYNTAXES.find_syntax_by_extension(extension)
+        .or_else(|| content.lines().next().and_then(|line| SYNTAXES.find_syntax_by_first_line(line)))
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text());
+
+    let mut highlighter = HighlightLines::new(syntax, &THEME);
+    let mut lines = Vec::new();
+
+    for line in LinesWithEndings::from(content) {
+        let regions = highlighter.highlight_line(line, &SYNTAXES)?
+            .into_iter()
+            .map(|(style, text)| (style, text.trim_end_matches(&['\r', '\n'][..])))
+            .collect::<Vec<_>>();
+
+        lines.push(styled_line_to_highlighted_html(&regions[..], IncludeBackground::No)?);
+    }
+
+    Ok(lines)
+}
@@ -45,11 +45,10 @@ impl Ipc {
         let packet = IpcPacket::new(packet);
         let bytes = packet.serialize().context("Failed to serialize packet")?;
 
-        self.connection.as_mut()
-            .ok_or_else(|| anyhow!("Not connected to workhorse"))?
-            .write_all(bytes.as_slice())
-            .await
-            .context("Failed to send packet to workhorse")
+        let connection = self.connection.as_mut().ok_or_else(|| anyhow!("Not connected to workhorse"))?;
+
+        connection.write_u64(bytes.len() as u64).await.context("Failed to send packet to workhorse")?;
+        connection.write_all(bytes.as_slice()).await.context("Failed to send packet to workhorse")
     }
 
     pub(crate) fn is_connected(&self) -> bool {
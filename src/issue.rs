@@ -1,31 +1,130 @@
+use crate::repository::Repository;
+use crate::user::User;
+
+use anyhow::Result;
 use chrono::serde::ts_seconds;
 use chrono::{DateTime, Utc};
 use derive_more::Display;
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::Serialize;
-use sqlx::FromRow;
+use sqlx::{Executor, FromRow, Postgres, Transaction};
+use tracing_unwrap::ResultExt;
 
-/// Contains issues and their corresponding data; Does *not* contain the actual text content
+/// Contains issues and their corresponding data; Comments are stored separately
 #[derive(FromRow, Display, Debug, Serialize)]
 #[display(fmt = "{}", title)]
 pub(crate) struct Issue {
     pub(crate) id: i32,
 
-    repo: i32,
-    index: i32, // Issue # per repository (not global instance)
+    pub(crate) repo: i32,
+    pub(crate) index: i32, // Issue # per repository (not global instance)
 
-    pub(crate) author: i32,
-    title: String,
+    /// `None` if the author deleted their account
+    pub(crate) author: Option<i32>,
+    pub(crate) title: String,
+    /// Markdown source, needs to be rendered using [markdown::render](crate::markdown::render) before being displayed
+    pub(crate) body: String,
 
     pub(crate) milestone: Option<i32>,
     pub(crate) labels: Vec<i32>,
     pub(crate) assignees: Vec<i32>,
 
-    closed: bool,
-    confidential: bool,
-    locked: bool,
+    pub(crate) closed: bool,
+    pub(crate) confidential: bool,
+    pub(crate) locked: bool,
 
     #[serde(with = "ts_seconds")]
-    created_at: DateTime<Utc>,
+    pub(crate) created_at: DateTime<Utc>,
     #[serde(with = "ts_seconds")]
     updated_at: DateTime<Utc>
 }
+
+impl Issue {
+    /// Creates a new issue with the next issue # of `repo`
+    pub(crate) async fn create(repo: &Repository, author: &User, title: &str, body: &str, confidential: bool, transaction: &mut Transaction<'_, Postgres>) -> Result<Issue> {
+        let index = next_index(repo, &mut *transaction).await?;
+
+        Ok(sqlx::query_as::<_, Issue>("insert into issues (repo, index, author, title, body, confidential) values ($1, $2, $3, $4, $5, $6) returning *")
+            .bind(&repo.id)
+            .bind(&index)
+            .bind(&author.id)
+            .bind(title)
+            .bind(body)
+            .bind(&confidential)
+            .fetch_one(&mut *transaction)
+            .await?)
+    }
+
+    pub(crate) async fn find<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, index: i32, executor: E) -> Result<Option<Issue>> {
+        Ok(sqlx::query_as::<_, Issue>("select * from issues where repo = $1 and index = $2 limit 1")
+            .bind(&repo.id)
+            .bind(&index)
+            .fetch_optional(executor)
+            .await?)
+    }
+
+    pub(crate) async fn set_closed<'e, E: Executor<'e, Database = Postgres>>(&mut self, closed: bool, executor: E) -> Result<()> {
+        sqlx::query("update issues set closed = $1, updated_at = current_timestamp where id = $2")
+            .bind(&closed)
+            .bind(&self.id)
+            .execute(executor)
+            .await?;
+
+        self.closed = closed;
+
+        Ok(())
+    }
+}
+
+/// Allocates the next # of `repo`, which is shared between issues and pull requests.
+///
+/// The counter is incremented using an `update` which locks the repository row until `transaction` is committed or rolled back,
+/// so concurrently created issues wait for each other instead of getting the same number
+pub(crate) async fn next_index(repo: &Repository, transaction: &mut Transaction<'_, Postgres>) -> Result<i32> {
+    let (index,): (i32,) = sqlx::query_as("update repositories set issue_counter = issue_counter + 1 where id = $1 returning issue_counter")
+        .bind(&repo.id)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    Ok(index)
+}
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", id)]
+pub(crate) struct IssueComment {
+    pub(crate) id: i32,
+    pub(crate) issue: i32,
+    /// `None` if the author deleted their account
+    pub(crate) author: Option<i32>,
+    /// Markdown source, empty for deleted comments
+    pub(crate) body: String,
+    pub(crate) mentions: Vec<i32>,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) edited_at: Option<DateTime<Utc>>,
+    pub(crate) deleted_at: Option<DateTime<Utc>>
+}
+
+/// Matches `@username` unless it is part of a word or an email address
+static MENTION_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:^|[^\w@.\-/])@([A-Za-z0-9_\-]{3,32})").unwrap_or_log());
+
+/// Resolves all `@username` mentions in `body` to user ids. Mentions of users that don't exist and of organizations are ignored
+pub(crate) async fn resolve_mentions<'e, E: Executor<'e, Database = Postgres>>(body: &str, executor: E) -> Result<Vec<i32>> {
+    let usernames = MENTION_REGEX.captures_iter(body)
+        .filter_map(|captures| captures.get(1))
+        .map(|username| username.as_str().to_lowercase())
+        .unique()
+        .collect::<Vec<_>>();
+
+    if usernames.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<(i32,)> = sqlx::query_as("select id from users where lower(username) = any($1) and organization = false")
+        .bind(&usernames)
+        .fetch_all(executor)
+        .await?;
+
+    Ok(ids.into_iter().map(|(id,)| id).collect())
+}
@@ -0,0 +1,160 @@
+//! Work that shouldn't delay HTTP responses, such as sending emails, is stored in the `jobs` table and run by a worker task.
+//!
+//! Jobs are claimed using `for update skip locked`, so multiple GitArena instances can share the same table.
+//! Failed jobs are retried with exponential backoff until [MAX_ATTEMPTS] is reached, after which they are kept with `failed_at`
+//! set so admins can see (and retry) them.
+
+use crate::mail;
+
+use std::cmp::min;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Executor, FromRow, PgPool, Postgres};
+use tokio::sync::Notify;
+use tokio::time;
+
+pub(crate) const MAX_ATTEMPTS: i32 = 8;
+
+/// How often the worker looks for due jobs if it hasn't been woken up by [enqueue]
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Claimed jobs are hidden from other workers for this long, so a crashed worker's jobs are picked up again eventually
+const LEASE_SECONDS: i64 = 600;
+
+const BASE_BACKOFF_SECONDS: i64 = 30;
+const MAX_BACKOFF_SECONDS: i64 = 6 * 60 * 60;
+
+static WAKE_UP: Lazy<Notify> = Lazy::new(Notify::new);
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Job {
+    Email {
+        /// Recipient mailbox, such as `username <user@example.com>`
+        to: String,
+        subject: String,
+        body: String,
+        /// Alternative HTML part, if the template has one
+        #[serde(default)]
+        html: Option<String>
+    }
+}
+
+impl Job {
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Job::Email { .. } => "email"
+        }
+    }
+
+    async fn run(self, db_pool: &PgPool) -> Result<()> {
+        match self {
+            Job::Email { to, subject, body, html } => mail::deliver(to.as_str(), subject.as_str(), body, html, db_pool).await
+        }
+    }
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct JobRow {
+    pub(crate) id: i32,
+    pub(crate) kind: String,
+    #[serde(skip_serializing)]
+    payload: Json<Job>,
+    pub(crate) attempts: i32,
+    #[serde(with = "ts_seconds")]
+    pub(crate) run_at: DateTime<Utc>,
+    pub(crate) last_error: Option<String>,
+    #[serde(with = "ts_seconds_option")]
+    pub(crate) failed_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Adds `job` to the queue. If `executor` is a transaction, the job only becomes visible to the worker once it has been committed
+pub(crate) async fn enqueue<'e, E: Executor<'e, Database = Postgres>>(job: Job, executor: E) -> Result<()> {
+    sqlx::query("insert into jobs (kind, payload) values ($1, $2)")
+        .bind(job.kind())
+        .bind(Json(&job))
+        .execute(executor)
+        .await?;
+
+    // If the job hasn't been committed yet when the worker wakes up, it gets picked up during the next poll
+    WAKE_UP.notify_one();
+
+    Ok(())
+}
+
+/// Spawns the worker task, which runs until the process exits
+pub(crate) fn spawn_worker(db_pool: PgPool) {
+    tokio::spawn(async move {
+        loop {
+            match run_next(&db_pool).await {
+                Ok(true) => continue,
+                Ok(false) => {},
+                Err(err) => warn!("Failed to run background job: {}", err)
+            }
+
+            let _ = time::timeout(POLL_INTERVAL, WAKE_UP.notified()).await;
+        }
+    });
+}
+
+/// Claims and runs the next due job. Returns `false` if there was nothing to do
+async fn run_next(db_pool: &PgPool) -> Result<bool> {
+    let row: Option<JobRow> = sqlx::query_as::<_, JobRow>("update jobs set attempts = attempts + 1, run_at = now() + make_interval(secs => $1) \
+        where id = (select id from jobs where failed_at is null and run_at <= now() order by run_at limit 1 for update skip locked) returning *")
+        .bind(LEASE_SECONDS as f64)
+        .fetch_optional(db_pool)
+        .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(false)
+    };
+
+    let (id, kind, attempts) = (row.id, row.kind, row.attempts);
+
+    match row.payload.0.run(db_pool).await {
+        Ok(()) => {
+            sqlx::query("delete from jobs where id = $1")
+                .bind(&id)
+                .execute(db_pool)
+                .await?;
+
+            debug!("Finished {} job {}", kind, id);
+        }
+        Err(err) => {
+            let error = format!("{:#}", err);
+
+            if attempts >= MAX_ATTEMPTS {
+                warn!("Giving up on {} job {} after {} attempts: {}", kind, id, attempts, error);
+
+                sqlx::query("update jobs set last_error = $1, failed_at = now() where id = $2")
+                    .bind(error.as_str())
+                    .bind(&id)
+                    .execute(db_pool)
+                    .await?;
+            } else {
+                let backoff = min(BASE_BACKOFF_SECONDS << (attempts - 1), MAX_BACKOFF_SECONDS);
+
+                warn!("{} job {} failed (attempt {}), retrying in {} seconds: {}", kind, id, attempts, backoff, error);
+
+                sqlx::query("update jobs set last_error = $1, run_at = now() + make_interval(secs => $2) where id = $3")
+                    .bind(error.as_str())
+                    .bind(backoff as f64)
+                    .bind(&id)
+                    .execute(db_pool)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(true)
+}
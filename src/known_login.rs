@@ -0,0 +1,97 @@
+//! Remembers the ip addresses users logged in from and warns them by email about logins from new ones.
+//!
+//! The email contains a single use link which signs the user out everywhere (see [revoke](crate::routes::user::user_login_revoke::revoke)).
+//! The very first login of an account is only remembered, there is nothing to compare it against yet.
+
+use crate::config::get_setting;
+use crate::mail::{self, Email};
+use crate::session;
+use crate::templates::plain;
+use crate::user::User;
+use crate::{crypto, template_context};
+
+use actix_web::HttpRequest;
+use actix_web::web::Data;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use ipnetwork::IpNetwork;
+use log::{debug, warn};
+use sqlx::PgPool;
+
+/// Length of the token in the link of the email. Only its hash gets stored in the database
+pub(crate) const TOKEN_LENGTH: usize = 64;
+
+/// Remembers the ip address of `request` for `user` and queues a new login email if it hasn't been seen before.
+/// Runs after the login has been committed and never fails it, errors are only logged as warning
+pub(crate) async fn check(request: &HttpRequest, user: &User) {
+    let db_pool = match request.app_data::<Data<PgPool>>() {
+        Some(db_pool) => db_pool,
+        None => {
+            warn!("Unable to check login of {} (id {}) for a new ip address: No database pool registered", &user.username, &user.id);
+            return;
+        }
+    };
+
+    let (ip_address, user_agent) = session::extract_ip_and_ua(request);
+    let user_agent = user_agent.chars().take(256).collect::<String>();
+
+    if let Err(err) = remember(user, &ip_address, user_agent.as_str(), db_pool.get_ref()).await {
+        warn!("Failed to check login of {} (id {}) for a new ip address: {}", &user.username, &user.id, err);
+    }
+}
+
+async fn remember(user: &User, ip_address: &IpNetwork, user_agent: &str, db_pool: &PgPool) -> Result<()> {
+    let mut transaction = db_pool.begin().await?;
+
+    let updated = sqlx::query("update known_logins set last_seen = now(), user_agent = $3 where user_id = $1 and ip_address = $2")
+        .bind(&user.id)
+        .bind(ip_address)
+        .bind(user_agent)
+        .execute(&mut transaction)
+        .await?;
+
+    if updated.rows_affected() > 0 {
+        transaction.commit().await?;
+        return Ok(());
+    }
+
+    let (first_login,): (bool,) = sqlx::query_as("select not exists(select 1 from known_logins where user_id = $1)")
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    let notify = !first_login && user.login_notifications && get_setting::<bool, _>("users.login_notifications", &mut transaction).await?;
+    let token = notify.then(|| crypto::random_hex_string(TOKEN_LENGTH));
+
+    sqlx::query("insert into known_logins (user_id, ip_address, user_agent, revoke_hash) values ($1, $2, $3, $4) on conflict do nothing")
+        .bind(&user.id)
+        .bind(ip_address)
+        .bind(user_agent)
+        .bind(token.as_deref().map(crypto::hash_token))
+        .execute(&mut transaction)
+        .await?;
+
+    if let Some(token) = token {
+        let email = Email::find_notification_email(user, &mut transaction)
+            .await?
+            .ok_or_else(|| anyhow!("User {} has no notification email address", user))?;
+
+        let domain = get_setting::<String, _>("domain", &mut transaction).await?;
+
+        let context = template_context!([
+            ("username".to_owned(), user.username.to_owned()),
+            ("ip_address".to_owned(), ip_address.ip().to_string()),
+            ("user_agent".to_owned(), user_agent.to_owned()),
+            ("time".to_owned(), Utc::now().format("%Y-%m-%d %H:%M UTC").to_string()),
+            ("link".to_owned(), format!("{}/login/revoke/{}", domain, token))
+        ]);
+
+        mail::send_template_to(user, &email, plain::NEW_LOGIN, &context, &mut transaction).await?;
+
+        debug!("{} (id {}) logged in from new ip address {}, sent notification", &user.username, &user.id, ip_address.ip());
+    }
+
+    transaction.commit().await?;
+
+    Ok(())
+}
@@ -6,6 +6,8 @@
 //! - The **public email** is displayed on the user profile.
 //! - All emails will be used to identify Git commits and incoming emails (e.g. issue creation by email).
 
+use crate::jobs::{self, Job};
+use crate::templates::plain::{self, TemplateContext};
 use crate::user::User;
 
 use std::fmt::{Debug, Formatter, Result as FmtResult, Write};
@@ -14,13 +16,14 @@ use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Local};
 use derive_more::Display;
 use gitarena_macros::from_config;
-use lettre::message::Mailbox;
+use lettre::message::{Mailbox, MultiPart};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
 use serde::Serialize;
 use sqlx::{Executor, FromRow, Pool, Postgres};
+use utoipa::Component;
 
-#[derive(FromRow, Display, Serialize)]
+#[derive(FromRow, Display, Serialize, Component)]
 #[display(fmt = "{}", email)]
 pub(crate) struct Email {
     pub(crate) id: i32,
@@ -127,27 +130,52 @@ pub(crate) async fn get_root_mailbox(db_pool: &Pool<Postgres>) -> Result<Mailbox
     Ok(Mailbox::new(Some("GitArena".to_owned()), address.parse()?))
 }
 
-pub(crate) async fn send_user_mail(user: &User, subject: &str, body: String, db_pool: &Pool<Postgres>) -> Result<()> {
-    // This is in an extra block so `transaction` gets dropped early
-    let email = {
-        let mut transaction = db_pool.begin().await?;
+/// Queues the email template `template` (such as [PASSWORD_RESET](crate::templates::plain::PASSWORD_RESET)) to the notification email of `user`
+pub(crate) async fn send_user_template(user: &User, template: &str, context: &TemplateContext, db_pool: &Pool<Postgres>) -> Result<()> {
+    let mut transaction = db_pool.begin().await?;
 
-        // Every *valid* user has a notification email address in the database, so .unwrap is fine
-        let email = Email::find_notification_email(user, &mut transaction)
-            .await?
-            .ok_or_else(|| anyhow!("User {} has no notification email address", user))?;
+    // Every *valid* user has a notification email address in the database
+    let email = Email::find_notification_email(user, &mut transaction)
+        .await?
+        .ok_or_else(|| anyhow!("User {} has no notification email address", user))?;
 
-        transaction.commit().await?;
+    send_template_to(user, &email, template, context, &mut transaction).await?;
 
-        email
-    };
+    transaction.commit().await?;
 
-    let message = Message::builder()
+    Ok(())
+}
+
+/// Queues the email template `template` to a specific address of `user` instead of their notification email (e.g. for verifying that address).
+/// It is sent in the background by the [job worker](crate::jobs)
+pub(crate) async fn send_template_to<'e, E: Executor<'e, Database = Postgres>>(user: &User, email: &Email, template: &str, context: &TemplateContext, executor: E) -> Result<()> {
+    send_template_to_address(user, email.email.as_str(), template, context, executor).await
+}
+
+/// Queues the email template `template` to `address`, which doesn't need to belong to `user` yet (e.g. when changing the primary address)
+pub(crate) async fn send_template_to_address<'e, E: Executor<'e, Database = Postgres>>(user: &User, address: &str, template: &str, context: &TemplateContext, executor: E) -> Result<()> {
+    let rendered = plain::get(template)?.render(context)?;
+    let to = Mailbox::new(Some(user.username.to_owned()), address.parse()?);
+
+    jobs::enqueue(Job::Email {
+        to: to.to_string(),
+        subject: rendered.subject,
+        body: rendered.text,
+        html: rendered.html
+    }, executor).await
+}
+
+/// Sends a mail right away. Called by the job worker, everything else should queue mails using [send_template_to] or [send_user_template]
+pub(crate) async fn deliver(to: &str, subject: &str, body: String, html: Option<String>, db_pool: &Pool<Postgres>) -> Result<()> {
+    let builder = Message::builder()
         .from(get_root_mailbox(db_pool).await?)
-        .to(email.as_mailbox(Some(user.username.to_owned()))?)
-        .subject(subject)
-        .body(body)
-        .context("Unable to build email.")?;
+        .to(to.parse().context("Invalid recipient")?)
+        .subject(subject);
+
+    let message = match html {
+        Some(html) => builder.multipart(MultiPart::alternative_plain_html(body, html)),
+        None => builder.body(body)
+    }.context("Unable to build email.")?;
 
     send_mail(message, db_pool).await
 }
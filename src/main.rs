@@ -1,13 +1,22 @@
 #![forbid(unsafe_code)]
 
+use crate::crypto::ArgonParams;
+use crate::csrf::CsrfProtection;
 use crate::error::error_renderer_middleware;
 use crate::ipc::Ipc;
+use crate::metrics::metrics_middleware;
 use crate::sse::Broadcaster;
 use crate::utils::admin_panel_layer::AdminPanelLayer;
+use crate::utils::compression::compression_middleware;
+use crate::utils::rate_limit::{self, IpRateLimiter};
+use crate::utils::repo_redirect::repo_redirect_middleware;
+use crate::utils::request_span::request_span_middleware;
+use crate::utils::security_headers::{self, security_headers_middleware, SecurityHeaders};
 
 use std::env::VarError;
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 
 use actix_files::Files;
 use actix_identity::{CookieIdentityPolicy, IdentityService};
@@ -16,7 +25,7 @@ use actix_web::cookie::SameSite;
 use actix_web::dev::{Service, ServiceResponse};
 use actix_web::http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CACHE_CONTROL, HeaderValue, LOCATION};
 use actix_web::http::Method;
-use actix_web::middleware::{NormalizePath, TrailingSlash};
+use actix_web::middleware::{Compress, NormalizePath, TrailingSlash};
 use actix_web::web::{Data, route, to};
 use actix_web::{App, HttpResponse, HttpServer};
 use anyhow::{anyhow, Context, Result};
@@ -24,7 +33,7 @@ use futures_locks::RwLock;
 use gitarena_common::database::create_postgres_pool;
 use gitarena_common::log::{default_env, log_file, stdout, tokio_console};
 use gitarena_macros::from_optional_config;
-use log::info;
+use log::{info, warn};
 use magic::{Cookie, CookieFlags};
 use time::Duration as TimeDuration;
 use tracing_appender::non_blocking::WorkerGuard;
@@ -33,27 +42,54 @@ use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Registry};
 use tracing_unwrap::ResultExt;
 
+mod access_token;
+mod activity;
+mod audit;
+mod avatar;
+mod branch_protection;
 mod captcha;
+mod commit_status;
 mod config;
+mod contributions;
 mod crypto;
+mod csrf;
 mod error;
 mod git;
+mod gpg;
+mod highlight;
 mod ipc;
 mod issue;
+mod jobs;
+mod known_login;
 mod licenses;
 mod mail;
+mod markdown;
+mod metrics;
+mod mirror;
+mod notification;
+mod organization;
+mod password;
 mod prelude;
 mod privileges;
+mod pull_request;
+mod quota;
+mod registration;
+mod release;
 mod repository;
 mod routes;
 mod session;
+mod shutdown;
 mod sse;
+mod ssh_hooks;
 mod ssh;
 mod sso;
 mod templates;
+mod topics;
+mod totp;
 mod user;
 mod utils;
 mod verification;
+mod webhook;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -66,13 +102,68 @@ async fn main() -> Result<()> {
 
     licenses::init().await;
 
-    let _watcher = templates::init().await?;
+    let email_templates_dir = config::get_optional_setting::<String, _>("email.templates_dir", &db_pool).await?;
+    let _watcher = templates::init(email_templates_dir.as_deref()).await?;
 
     let bind_address = env::var("BIND_ADDRESS").context("Unable to read mandatory BIND_ADDRESS environment variable")?;
 
-    let (secret, domain): (Option<String>, Option<String>) = from_optional_config!("secret" => String, "domain" => String);
+    let (secret, domain, session_max_age, session_same_site): (Option<String>, Option<String>, Option<i64>, Option<String>) = from_optional_config!(
+        "secret" => String,
+        "domain" => String,
+        "sessions.max_age" => i64,
+        "sessions.same_site" => String
+    );
     let secret = secret.ok_or_else(|| anyhow!("Unable to read secret from database"))?;
+    user::init_noreply_host(domain.as_deref());
     let secure = domain.map_or_else(|| false, |d| d.starts_with("https"));
+    let session_max_age = session_max_age.unwrap_or(864000);
+    let session_same_site = parse_same_site(session_same_site.as_deref());
+    csrf::init(secret.as_str(), secure);
+    git::lfs::init(secret.as_str());
+
+    let (registration_limit, registration_window, registration_exempt_localhost): (Option<i32>, Option<i32>, Option<bool>) = from_optional_config!(
+        "registrations.rate_limit.max" => i32,
+        "registrations.rate_limit.window" => i32,
+        "registrations.rate_limit.exempt_localhost" => bool
+    );
+    let _ = rate_limit::REGISTRATIONS.set(IpRateLimiter::new(
+        registration_limit.unwrap_or(10).max(1) as usize,
+        Duration::from_secs(registration_window.unwrap_or(3600).max(1) as u64),
+        registration_exempt_localhost.unwrap_or(false)
+    ));
+
+    let (argon_memory, argon_iterations, argon_parallelism): (Option<i32>, Option<i32>, Option<i32>) = from_optional_config!(
+        "passwords.argon2.memory" => i32,
+        "passwords.argon2.iterations" => i32,
+        "passwords.argon2.parallelism" => i32
+    );
+    let default_params = ArgonParams::default();
+    crypto::init_argon_params(ArgonParams {
+        memory: argon_memory.map_or(default_params.memory, |memory| memory.max(0) as u32),
+        iterations: argon_iterations.map_or(default_params.iterations, |iterations| iterations.max(0) as u32),
+        parallelism: argon_parallelism.map_or(default_params.parallelism, |parallelism| parallelism.max(0) as u32)
+    });
+
+    let (csp, frame_options, referrer_policy, hsts_max_age, trusted_proxies): (Option<String>, Option<String>, Option<String>, Option<i32>, Option<String>) = from_optional_config!(
+        "security.csp" => String,
+        "security.frame_options" => String,
+        "security.referrer_policy" => String,
+        "security.hsts_max_age" => i32,
+        "security.trusted_proxies" => String
+    );
+    session::init_trusted_proxies(trusted_proxies.as_deref());
+    security_headers::init(SecurityHeaders {
+        csp: csp.unwrap_or_default(),
+        frame_options: frame_options.unwrap_or_default(),
+        referrer_policy: referrer_policy.unwrap_or_default(),
+        hsts_max_age: hsts_max_age.unwrap_or(0)
+    });
+
+    let shutdown_timeout = config::get_optional_setting::<i32, _>("shutdown.timeout", &db_pool).await?;
+    let shutdown_timeout = Duration::from_secs(shutdown_timeout.unwrap_or(60).max(1) as u64);
+
+    jobs::spawn_worker(db_pool.clone());
+    ssh_hooks::spawn_listener(db_pool.clone());
 
     let ipc = RwLock::new(Ipc::new().await?);
 
@@ -84,9 +175,9 @@ async fn main() -> Result<()> {
         let identity_service = IdentityService::new(
             CookieIdentityPolicy::new(secret.as_bytes())
                 .name("gitarena-auth")
-                .max_age(TimeDuration::days(10))
+                .max_age(TimeDuration::seconds(session_max_age))
                 .http_only(true)
-                .same_site(SameSite::Lax)
+                .same_site(session_same_site)
                 .secure(secure)
         );
 
@@ -98,6 +189,7 @@ async fn main() -> Result<()> {
             .app_data(Data::new(ipc.clone()))
             .app_data(broadcaster.clone())
             .wrap(NormalizePath::new(TrailingSlash::Trim))
+            .wrap(CsrfProtection)
             .wrap(identity_service)
             .wrap_fn(|req, srv| {
                 let fut = srv.call(req);
@@ -121,7 +213,13 @@ async fn main() -> Result<()> {
                     Ok(res)
                 }
             })
+            .wrap_fn(repo_redirect_middleware)
             .wrap_fn(error_renderer_middleware)
+            .wrap_fn(security_headers_middleware)
+            .wrap_fn(compression_middleware)
+            .wrap(Compress::default())
+            .wrap_fn(metrics_middleware)
+            .wrap_fn(request_span_middleware)
             .default_service(route().method(Method::GET).to(routes::not_found::default_handler))
             .service(routes::admin::all())
             .configure(routes::init)
@@ -130,7 +228,8 @@ async fn main() -> Result<()> {
             .configure(routes::repository::init) // Repository routes need to be always last
             .route("/favicon.ico", to(|| async {
                 HttpResponse::MovedPermanently().append_header((LOCATION, "/static/img/favicon.ico")).finish()
-            }));
+            }))
+            .service(routes::user::profile::view_profile); // Matches every single segment path, so it needs to be registered last
 
         let debug_mode = cfg!(debug_assertions);
         let serve_static = matches!(env::var("SERVE_STATIC_FILES"), Ok(_) | Err(VarError::NotUnicode(_))) || debug_mode;
@@ -145,15 +244,32 @@ async fn main() -> Result<()> {
         }
 
         app
-    }).bind(bind_address.as_str()).context("Unable to bind HTTP server.")?;
+    }).bind(bind_address.as_str()).context("Unable to bind HTTP server.")?
+        .disable_signals()
+        .shutdown_timeout(shutdown_timeout.as_secs())
+        .run();
 
-    server.run().await.context("Unable to start HTTP server.")?;
+    tokio::spawn(shutdown::listen(server.handle(), shutdown_timeout));
+
+    server.await.context("Unable to start HTTP server.")?;
 
     info!("Thank you and goodbye.");
 
     Ok(())
 }
 
+/// Parses the `sessions.same_site` setting. Only `lax` and `strict` are accepted, as `none` would allow other sites to use the session
+fn parse_same_site(value: Option<&str>) -> SameSite {
+    match value.map(|value| value.to_lowercase()).as_deref() {
+        Some("strict") => SameSite::Strict,
+        Some("lax") | None => SameSite::Lax,
+        Some(other) => {
+            warn!("Unknown sessions.same_site value `{}`, falling back to `lax`", other);
+            SameSite::Lax
+        }
+    }
+}
+
 // This method is basically the same as `gitarena_common::log::init_logger` except it additionally adds the AdminPanelLayer at the end
 // Please keep this in sync with it
 fn init_logger(broadcaster: Data<RwLock<Broadcaster>>) -> Result<Vec<WorkerGuard>> {
@@ -0,0 +1,140 @@
+//! Server side rendering of Markdown files stored in repositories.
+//!
+//! Repository content is untrusted, so the rendered HTML is always passed through an allowlist based sanitizer.
+
+use std::collections::{HashMap, HashSet};
+
+use ammonia::Builder as SanitizerBuilder;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+/// Where relative links and images inside a rendered file point to
+pub(crate) struct LinkTarget<'a> {
+    pub(crate) username: &'a str,
+    pub(crate) repository: &'a str,
+    pub(crate) tree: &'a str,
+    /// Directory of the rendered file relative to the repository root, without leading or trailing slash (empty for the root)
+    pub(crate) directory: &'a str
+}
+
+impl LinkTarget<'_> {
+    fn blob_url(&self, path: &str) -> String {
+        format!("/{}/{}/tree/{}/blob/{}", self.username, self.repository, self.tree, path)
+    }
+
+    fn raw_url(&self, path: &str) -> String {
+        format!("/{}/{}/tree/{}/~blob/{}", self.username, self.repository, self.tree, path)
+    }
+}
+
+/// Whenever the file called `name` is rendered as Markdown instead of being displayed as text
+pub(crate) fn is_markdown(name: &str) -> bool {
+    let lowered_name = name.to_lowercase();
+
+    lowered_name.ends_with(".md") || lowered_name.ends_with(".markdown")
+}
+
+/// Renders `content` as sanitized HTML. Relative links are rewritten to point at the blob view, relative images at the raw view
+/// of the same ref. External images are routed through the image proxy to not leak the IP addresses of visitors
+pub(crate) fn render(content: &str, target: &LinkTarget<'_>) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+
+    let parser = Parser::new_ext(content, options).map(|event| match event {
+        Event::Start(Tag::Link(link_type, destination, title)) => Event::Start(Tag::Link(link_type, rewrite_link(destination, target, false), title)),
+        Event::Start(Tag::Image(link_type, destination, title)) => Event::Start(Tag::Image(link_type, rewrite_link(destination, target, true), title)),
+        _ => event
+    });
+
+    let mut unsafe_html = String::with_capacity(content.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, parser);
+
+    sanitize(unsafe_html.as_str())
+}
+
+fn sanitize(html: &str) -> String {
+    let mut tags = HashSet::new();
+    tags.extend([
+        "a", "blockquote", "br", "code", "del", "details", "em", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "img", "input", "kbd",
+        "li", "ol", "p", "pre", "s", "strong", "sub", "summary", "sup", "table", "tbody", "td", "th", "thead", "tr", "ul"
+    ]);
+
+    let mut tag_attributes = HashMap::new();
+    tag_attributes.insert("a", ["href", "title"].into_iter().collect::<HashSet<_>>());
+    tag_attributes.insert("img", ["src", "alt", "title", "width", "height"].into_iter().collect::<HashSet<_>>());
+    tag_attributes.insert("input", ["type", "checked", "disabled"].into_iter().collect::<HashSet<_>>()); // Task lists
+    tag_attributes.insert("code", ["class"].into_iter().collect::<HashSet<_>>()); // Language hint for highlight.js
+    tag_attributes.insert("td", ["align"].into_iter().collect::<HashSet<_>>());
+    tag_attributes.insert("th", ["align"].into_iter().collect::<HashSet<_>>());
+
+    SanitizerBuilder::default()
+        .tags(tags)
+        .tag_attributes(tag_attributes)
+        .url_schemes(["http", "https", "mailto"].into_iter().collect())
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(html)
+        .to_string()
+}
+
+fn rewrite_link<'a>(destination: CowStr<'a>, target: &LinkTarget<'_>, image: bool) -> CowStr<'a> {
+    let url = destination.as_ref();
+
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return if image {
+            CowStr::from(format!("/api/proxy/{}", hex::encode(url)))
+        } else {
+            destination
+        };
+    }
+
+    // Anchors, protocol relative urls and other schemes (e.g. `mailto:`) are kept as-is
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") || url.contains(':') {
+        return destination;
+    }
+
+    let (path, fragment) = match url.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (url, None)
+    };
+
+    let resolved = match resolve_path(target.directory, path) {
+        Some(resolved) => resolved,
+        None => return destination
+    };
+
+    let mut rewritten = if image {
+        target.raw_url(resolved.as_str())
+    } else {
+        target.blob_url(resolved.as_str())
+    };
+
+    if let Some(fragment) = fragment {
+        rewritten.push('#');
+        rewritten.push_str(fragment);
+    }
+
+    CowStr::from(rewritten)
+}
+
+/// Resolves `path` relative to `directory`. Paths starting with a slash are relative to the repository root.
+/// Returns `None` if the path tries to escape the repository root
+fn resolve_path(directory: &str, path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+
+    if !path.starts_with('/') {
+        segments.extend(directory.split('/').filter(|segment| !segment.is_empty()));
+    }
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => {
+                segments.pop()?;
+            }
+            _ => segments.push(segment)
+        }
+    }
+
+    Some(segments.join("/"))
+}
@@ -0,0 +1,97 @@
+//! Prometheus metrics exposed at `/metrics` if the `metrics.enabled` setting is turned on.
+//!
+//! Metrics are collected regardless of the setting, as recording them is cheap and the setting can be changed at runtime.
+
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::Error as ActixError;
+use actix_web::Result as ActixResult;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use sqlx::PgPool;
+use tracing_unwrap::ResultExt;
+
+static REGISTRY: Lazy<Registry> = Lazy::new(|| Registry::new_custom(Some("gitarena".to_owned()), None).unwrap_or_log());
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("http_requests_total", "HTTP requests by route pattern, method and status"),
+    &["route", "method", "status"]
+)));
+
+static HTTP_DURATION: Lazy<HistogramVec> = Lazy::new(|| register(HistogramVec::new(
+    HistogramOpts::new("http_request_duration_seconds", "Time it took to respond to HTTP requests by route pattern and method"),
+    &["route", "method"]
+)));
+
+static GIT_OPERATIONS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("git_operations_total", "Git smart HTTP requests by service"),
+    &["service"]
+)));
+
+static GIT_BYTES: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("git_bytes_total", "Bytes transferred by Git smart HTTP requests by service and direction (received or sent)"),
+    &["service", "direction"]
+)));
+
+static AUTH_ATTEMPTS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("auth_attempts_total", "Authentication attempts by method (web or basic) and result"),
+    &["method", "result"]
+)));
+
+static DB_POOL_SIZE: Lazy<IntGauge> = Lazy::new(|| register(IntGauge::new("db_pool_connections", "Open database connections")));
+static DB_POOL_IDLE: Lazy<IntGauge> = Lazy::new(|| register(IntGauge::new("db_pool_idle_connections", "Idle database connections")));
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<M>) -> M {
+    let metric = metric.unwrap_or_log();
+    REGISTRY.register(Box::new(metric.clone())).unwrap_or_log();
+    metric
+}
+
+/// Records a Git smart HTTP request. `service` is either `upload-pack` or `receive-pack`
+pub(crate) fn record_git_transfer(service: &str, received: usize, sent: usize) {
+    GIT_OPERATIONS.with_label_values(&[service]).inc();
+    GIT_BYTES.with_label_values(&[service, "received"]).inc_by(received as u64);
+    GIT_BYTES.with_label_values(&[service, "sent"]).inc_by(sent as u64);
+}
+
+pub(crate) fn record_auth(method: &str, success: bool) {
+    AUTH_ATTEMPTS.with_label_values(&[method, if success { "success" } else { "failure" }]).inc();
+}
+
+/// Renders all metrics in the Prometheus text format
+pub(crate) fn render(db_pool: &PgPool) -> Result<String> {
+    DB_POOL_SIZE.set(db_pool.size() as i64);
+    DB_POOL_IDLE.set(db_pool.num_idle() as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Middleware recording count and duration of all requests. Requests are labeled by their route pattern
+/// (such as `/{username}/{repository}`) instead of their path to keep the amount of label values bounded
+pub(crate) fn metrics_middleware<S, B>(request: ServiceRequest, service: &S) -> impl Future<Output = ActixResult<ServiceResponse<B>>> + 'static
+    where S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+          S::Future: 'static,
+          B: 'static
+{
+    let start = Instant::now();
+    let future = service.call(request);
+
+    async move {
+        let response = future.await?;
+
+        let route = response.request().match_pattern().unwrap_or_else(|| "unmatched".to_owned());
+        let method = response.request().method().as_str().to_owned();
+        let status = response.status().as_u16().to_string();
+
+        HTTP_REQUESTS.with_label_values(&[route.as_str(), method.as_str(), status.as_str()]).inc();
+        HTTP_DURATION.with_label_values(&[route.as_str(), method.as_str()]).observe(start.elapsed().as_secs_f64());
+
+        Ok(response)
+    }
+}
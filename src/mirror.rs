@@ -0,0 +1,34 @@
+//! Pull mirrors periodically fetch all branches and tags from `repositories.mirrored_from`.
+//!
+//! Syncing is done by the workhorse, this process only manages the settings and exposes the sync status.
+
+use crate::repository::Repository;
+
+use anyhow::Result;
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Executor, FromRow, Postgres};
+
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct MirrorSettings {
+    pub(crate) repo: i32,
+    /// Seconds between two syncs
+    pub(crate) interval: i32,
+    #[serde(with = "ts_seconds")]
+    pub(crate) next_sync_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds_option")]
+    pub(crate) last_sync_at: Option<DateTime<Utc>>,
+    /// `None` if the mirror has not been synced yet
+    pub(crate) last_sync_success: Option<bool>,
+    pub(crate) last_error: Option<String>
+}
+
+impl MirrorSettings {
+    pub(crate) async fn find<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, executor: E) -> Result<Option<MirrorSettings>> {
+        Ok(sqlx::query_as::<_, MirrorSettings>("select * from mirror_settings where repo = $1 limit 1")
+            .bind(&repo.id)
+            .fetch_optional(executor)
+            .await?)
+    }
+}
@@ -0,0 +1,162 @@
+//! In-app notifications for users watching a repository.
+//!
+//! Watchers are notified about new issues and releases depending on their [watch level](WatchLevel). Access is checked
+//! for every watcher when a notification is created, so watches on repositories a user lost access to stop delivering.
+
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::User;
+
+use anyhow::Result;
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Executor, FromRow, Postgres, Transaction, Type};
+use tracing::instrument;
+use utoipa::Component;
+
+#[derive(Type, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Component)]
+#[sqlx(type_name = "watch_level", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WatchLevel {
+    /// Notified about every new issue and release
+    All,
+    /// Only notified about issues mentioning the user
+    Participating,
+    /// Never notified, even if mentioned
+    Ignore
+}
+
+#[derive(Type, Debug, Clone, Copy, Serialize)]
+#[sqlx(type_name = "notification_kind", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum NotificationKind {
+    Issue,
+    Release
+}
+
+/// Single notification, already joined with the names required to link to the repository
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct Notification {
+    pub(crate) id: i32,
+    pub(crate) actor: Option<i32>,
+    pub(crate) actor_username: Option<String>,
+    pub(crate) repo: i32,
+    pub(crate) repo_owner: String,
+    pub(crate) repo_name: String,
+    pub(crate) kind: NotificationKind,
+    /// Kind specific details: index and title for issues, tag and title for releases
+    pub(crate) payload: Value,
+    #[serde(with = "ts_seconds_option")]
+    pub(crate) read_at: Option<DateTime<Utc>>,
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Returns the watch level of `user` for `repo` or `None` if the user is not watching it
+pub(crate) async fn watch_level<'e, E: Executor<'e, Database = Postgres>>(user: &User, repo: &Repository, executor: E) -> Result<Option<WatchLevel>> {
+    let level: Option<(WatchLevel,)> = sqlx::query_as("select level from watches where user_id = $1 and repo = $2 limit 1")
+        .bind(&user.id)
+        .bind(&repo.id)
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(level.map(|(level,)| level))
+}
+
+pub(crate) async fn watch<'e, E: Executor<'e, Database = Postgres>>(user: &User, repo: &Repository, level: WatchLevel, executor: E) -> Result<()> {
+    sqlx::query("insert into watches (user_id, repo, level) values ($1, $2, $3) on conflict (user_id, repo) do update set level = excluded.level")
+        .bind(&user.id)
+        .bind(&repo.id)
+        .bind(&level)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Removes the watch of `user` for `repo`. Returns `false` if the user was not watching it
+pub(crate) async fn unwatch<'e, E: Executor<'e, Database = Postgres>>(user: &User, repo: &Repository, executor: E) -> Result<bool> {
+    let result = sqlx::query("delete from watches where user_id = $1 and repo = $2")
+        .bind(&user.id)
+        .bind(&repo.id)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Notifies all watchers of `repo` except `actor` itself. Watchers with the `participating` level are only notified if
+/// their id is in `participants`. If `restricted` is set (e.g. for confidential issues), only watchers which can manage
+/// issues are notified
+#[instrument(err, skip(payload, transaction))]
+pub(crate) async fn notify_watchers(repo: &Repository, actor: &User, kind: NotificationKind, payload: Value, participants: &[i32], restricted: bool, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    let watchers: Vec<User> = sqlx::query_as::<_, User>("select users.* from watches inner join users on users.id = watches.user_id \
+        where watches.repo = $1 and watches.user_id <> $2 and not users.disabled \
+        and (watches.level = 'all' or (watches.level = 'participating' and watches.user_id = any($3)))")
+        .bind(&repo.id)
+        .bind(&actor.id)
+        .bind(participants)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    for watcher in watchers {
+        if !privilege::check_access(repo, Some(&watcher), &mut *transaction).await? {
+            continue;
+        }
+
+        if restricted && !privilege::check_manage_issues(repo, Some(&watcher), &mut *transaction).await? {
+            continue;
+        }
+
+        sqlx::query("insert into notifications (recipient, actor, repo, kind, payload) values ($1, $2, $3, $4, $5)")
+            .bind(&watcher.id)
+            .bind(&actor.id)
+            .bind(&repo.id)
+            .bind(&kind)
+            .bind(&payload)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Returns up to `limit` notifications of `recipient`, newest first. If `after` is set, only notifications older than it are returned
+pub(crate) async fn list<'e, E: Executor<'e, Database = Postgres>>(recipient: &User, unread_only: bool, after: Option<i32>, limit: i64, executor: E) -> Result<Vec<Notification>> {
+    Ok(sqlx::query_as::<_, Notification>("select n.id, n.actor, actors.username as actor_username, n.repo, \
+        owners.username as repo_owner, r.name as repo_name, n.kind, n.payload, n.read_at, n.created_at from notifications n \
+        left join users actors on actors.id = n.actor \
+        inner join repositories r on r.id = n.repo \
+        inner join users owners on owners.id = r.owner \
+        where n.recipient = $1 and (not $2 or n.read_at is null) and ($3::integer is null or n.id < $3) \
+        order by n.id desc limit $4")
+        .bind(&recipient.id)
+        .bind(&unread_only)
+        .bind(&after)
+        .bind(&limit)
+        .fetch_all(executor)
+        .await?)
+}
+
+/// Marks a single notification as read. Returns `false` if `recipient` has no notification with this id
+pub(crate) async fn mark_read<'e, E: Executor<'e, Database = Postgres>>(recipient: &User, id: i32, executor: E) -> Result<bool> {
+    let result = sqlx::query("update notifications set read_at = coalesce(read_at, now()) where id = $1 and recipient = $2")
+        .bind(&id)
+        .bind(&recipient.id)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks all notifications of `recipient` as read and returns how many were unread
+pub(crate) async fn mark_all_read<'e, E: Executor<'e, Database = Postgres>>(recipient: &User, executor: E) -> Result<u64> {
+    let result = sqlx::query("update notifications set read_at = now() where recipient = $1 and read_at is null")
+        .bind(&recipient.id)
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,84 @@
+//! Organizations are stored as users with the `organization` flag set, so they share the namespace of users and own
+//! repositories the same way. Nobody can log into an organization; users get access to its repositories through membership.
+
+use crate::privileges::repo_access::AccessLevel;
+use crate::user::User;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, FromRow, Postgres, Type};
+
+#[derive(Type, Display, Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Deserialize, Serialize)]
+#[sqlx(type_name = "organization_role", rename_all = "lowercase")]
+#[serde(rename_all(serialize = "lowercase", deserialize = "lowercase"))]
+pub(crate) enum OrganizationRole {
+    /// Has [viewer](crate::privileges::repo_access::AccessLevel::Viewer) access to all repositories of the organization,
+    /// anything above that is granted through [teams](Team)
+    Member,
+    /// Has [admin](crate::privileges::repo_access::AccessLevel::Admin) access to all repositories and manages members
+    Owner
+}
+
+/// Member of an organization joined with their username
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct OrganizationMember {
+    pub(crate) id: i32,
+    pub(crate) username: String,
+    pub(crate) role: OrganizationRole,
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Named group of organization members which grants its access level to all repositories assigned to it
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct Team {
+    pub(crate) id: i32,
+    pub(crate) organization: i32,
+    pub(crate) name: String,
+    pub(crate) access_level: AccessLevel,
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Finds an organization by name. Returns `None` if no user with that name exists or if it is not an organization
+pub(crate) async fn find_organization<'e, E: Executor<'e, Database = Postgres>>(name: &str, executor: E) -> Result<Option<User>> {
+    Ok(sqlx::query_as::<_, User>("select * from users where lower(username) = lower($1) and organization = true limit 1")
+        .bind(name)
+        .fetch_optional(executor)
+        .await?)
+}
+
+/// Returns the role of `user` in `organization` or `None` if they are not a member
+pub(crate) async fn find_role<'e, E: Executor<'e, Database = Postgres>>(organization: &User, user: &User, executor: E) -> Result<Option<OrganizationRole>> {
+    let role: Option<(OrganizationRole,)> = sqlx::query_as("select role from organization_members where organization = $1 and member = $2 limit 1")
+        .bind(&organization.id)
+        .bind(&user.id)
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(role.map(|(role,)| role))
+}
+
+pub(crate) async fn all_members<'e, E: Executor<'e, Database = Postgres>>(organization: &User, executor: E) -> Result<Vec<OrganizationMember>> {
+    Ok(sqlx::query_as::<_, OrganizationMember>("select users.id, users.username, organization_members.role, organization_members.created_at \
+        from organization_members inner join users on users.id = organization_members.member \
+        where organization_members.organization = $1 order by organization_members.role desc, lower(users.username)")
+        .bind(&organization.id)
+        .fetch_all(executor)
+        .await?)
+}
+
+pub(crate) async fn find_team<'e, E: Executor<'e, Database = Postgres>>(organization: &User, name: &str, executor: E) -> Result<Option<Team>> {
+    Ok(sqlx::query_as::<_, Team>("select * from teams where organization = $1 and lower(name) = lower($2) limit 1")
+        .bind(&organization.id)
+        .bind(name)
+        .fetch_optional(executor)
+        .await?)
+}
+
+pub(crate) async fn all_teams<'e, E: Executor<'e, Database = Postgres>>(organization: &User, executor: E) -> Result<Vec<Team>> {
+    Ok(sqlx::query_as::<_, Team>("select * from teams where organization = $1 order by lower(name)")
+        .bind(&organization.id)
+        .fetch_all(executor)
+        .await?)
+}
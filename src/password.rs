@@ -0,0 +1,110 @@
+//! Password policy applied upon registration.
+//!
+//! We don't implement any strict composition rules according to NIST 2017 Guidelines. Instead passwords need to have a
+//! configurable minimum length and may not be one of the most commonly used passwords.
+//! Optionally, passwords are also checked against the breach corpus of [Have I Been Pwned](https://haveibeenpwned.com/Passwords).
+
+use crate::prelude::AwcExtensions;
+use crate::{config, die};
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use awc::Client;
+use log::warn;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use sqlx::{Executor, Postgres};
+use zxcvbn::Entropy;
+
+const HIBP_RANGE_API: &str = "https://api.pwnedpasswords.com/range/";
+const HIBP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most commonly used passwords according to public breach compilations, all lowercase
+const COMMON_PASSWORDS: [&str; 64] = [
+    "123456", "123456789", "12345678", "1234567890", "12345", "1234567", "123123", "111111", "000000", "654321",
+    "666666", "121212", "112233", "123321", "987654321", "1q2w3e4r", "1q2w3e4r5t", "1qaz2wsx", "qwerty", "qwerty123",
+    "qwertyuiop", "asdfghjkl", "zxcvbnm", "password", "password1", "password123", "passw0rd", "p@ssw0rd", "iloveyou", "abc123",
+    "abcd1234", "admin", "admin123", "administrator", "welcome", "welcome1", "letmein", "monkey", "dragon", "football",
+    "baseball", "superman", "batman", "master", "sunshine", "princess", "shadow", "michael", "jennifer", "trustno1",
+    "starwars", "whatever", "freedom", "qazwsx", "ashley", "mustang", "access", "hello123", "charlie", "donald",
+    "changeme", "secret", "gitarena", "github"
+];
+
+/// Checks if `password` satisfies the password policy configured for this instance.
+/// Returns `Ok` on success and an error with a user-facing message on failure.
+pub(crate) async fn validate_password<'e, E: Executor<'e, Database = Postgres>>(password: &str, executor: E) -> Result<()> {
+    if password.trim().is_empty() {
+        die!(BAD_REQUEST, "Password cannot be empty");
+    }
+
+    let min_length = config::get_setting::<i32, _>("passwords.min_length", executor).await?;
+
+    if password.chars().count() < min_length.max(1) as usize {
+        die!(BAD_REQUEST, "Password must be at least {} characters", min_length);
+    }
+
+    if is_common_password(password) {
+        die!(BAD_REQUEST, "Password is too common, please choose a different one");
+    }
+
+    Ok(())
+}
+
+/// Rejects `password` if it appears in a known data breach. Does nothing unless `passwords.hibp.enabled` is set.
+///
+/// This is kept separate from [validate_password] as it contacts an external service, which shouldn't happen on every
+/// keystroke of the strength meter. If Have I Been Pwned is unreachable the password is allowed, so an outage doesn't block signups
+pub(crate) async fn check_breached<'e, E: Executor<'e, Database = Postgres>>(password: &str, executor: E) -> Result<()> {
+    if !config::get_optional_setting::<bool, _>("passwords.hibp.enabled", executor).await?.unwrap_or(false) {
+        return Ok(());
+    }
+
+    match is_breached(password).await {
+        Ok(true) => die!(BAD_REQUEST, "This password has appeared in a data breach and can't be used, please choose a different one"),
+        Ok(false) => Ok(()),
+        Err(err) => {
+            warn!("Unable to check password against Have I Been Pwned, allowing it: {}", err);
+            Ok(())
+        }
+    }
+}
+
+/// Queries the range API using k-anonymity: Only the first 5 characters of the SHA-1 hash are sent and
+/// the remaining suffix is looked up in the (padded) list of all suffixes sharing that prefix
+async fn is_breached(password: &str) -> Result<bool> {
+    let hash = hex::encode_upper(digest(&SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+
+    let mut response = Client::gitarena()
+        .get(format!("{}{}", HIBP_RANGE_API, prefix))
+        .insert_header(("Add-Padding", "true"))
+        .timeout(HIBP_TIMEOUT)
+        .send()
+        .await
+        .map_err(|err| anyhow!("{}", err))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!("Received status {}", response.status()));
+    }
+
+    let body = response.body().await.map_err(|err| anyhow!("{}", err))?;
+    let body = String::from_utf8_lossy(&body);
+
+    // Each line looks like `<suffix>:<count>`, padding entries have a count of zero
+    Ok(body.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .any(|(candidate, count)| candidate.eq_ignore_ascii_case(suffix) && count != "0"))
+}
+
+pub(crate) fn is_common_password(password: &str) -> bool {
+    let lower_case = password.to_lowercase();
+    COMMON_PASSWORDS.contains(&lower_case.as_str())
+}
+
+/// Estimates the strength of `password` using zxcvbn. `user_inputs` should contain user specific data such as the
+/// username or email address as passwords containing those are considered weaker.
+///
+/// Returns `None` if the password is empty.
+pub(crate) fn estimate_strength(password: &str, user_inputs: &[&str]) -> Option<Entropy> {
+    zxcvbn::zxcvbn(password, user_inputs).ok()
+}
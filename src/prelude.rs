@@ -1,7 +1,10 @@
+use crate::config::get_optional_setting;
 use crate::user::{User, WebUser};
 
+use std::collections::HashMap;
+
 use actix_web::HttpRequest;
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use awc::http::header::USER_AGENT;
 use awc::{Client, ClientBuilder};
@@ -9,9 +12,8 @@ use bstr::BString;
 use chrono::{DateTime, FixedOffset, LocalResult, TimeZone, Utc};
 use git2::{Signature as LibGit2Signature, Time as LibGit2Time};
 use git_repository::actor::{Sign, Signature as GitoxideSignature, Time as GitoxideTime};
-use log::warn;
 use qstring::QString;
-use sqlx::{Executor, Postgres};
+use sqlx::{Executor, Postgres, Transaction};
 use tera::Context;
 
 pub(crate) trait HttpRequestExtensions {
@@ -68,8 +70,8 @@ pub(crate) trait LibGit2TimeExtensions {
     /// Tries to convert from `git2` [Time][time] into `chrono` [DateTime][datetime].
     ///
     /// The returned [DateTime][datetime] timezone is [FixedOffset](chrono::FixedOffset) with
-    /// the offset provided by [Time][time]. In case the conversation yields an [ambiguous result](chrono::offset::LocalResult::Ambiguous)
-    /// a warning is logged and the smaller of the two ambiguous results is returned.
+    /// the offset provided by [Time][time]. Git stores the offset the commit was made with rather than a named time zone,
+    /// so the result always describes a single instant, even for commits made while daylight saving time ended.
     ///
     /// This method will fail and return an [Error](anyhow::Error) if `offset` is out of bounds (>24 hours) or
     /// if `seconds` is out of bounds (>[i64::MAX](i64::MAX)).
@@ -96,10 +98,33 @@ pub(crate) trait LibGit2TimeExtensions {
     /// [time]: git2::Time
     /// [datetime]: chrono::DateTime
     fn try_as_chrono(&self) -> Result<DateTime<FixedOffset>>;
+
+    /// Tries to convert from `git2` [Time][time] into a `chrono` [LocalResult](chrono::offset::LocalResult).
+    ///
+    /// As the offset is fixed, the result is never [ambiguous](chrono::offset::LocalResult::Ambiguous): A Unix timestamp maps to
+    /// exactly one local time for a given offset. Commits made during a daylight saving time fall-back carry the offset in effect
+    /// at that moment (e.g. `+0200` before and `+0100` after), so they are already told apart by their offset.
+    /// [None](chrono::offset::LocalResult::None) is returned if `seconds` is out of the range `chrono` supports.
+    ///
+    /// This method will fail and return an [Error](anyhow::Error) if `offset` is out of bounds (>24 hours).
+    ///
+    /// # Panics
+    ///
+    /// This function panics under the same conditions as [try_as_chrono](LibGit2TimeExtensions::try_as_chrono).
+    ///
+    /// [time]: git2::Time
+    /// [datetime]: chrono::DateTime
+    fn try_as_chrono_all(&self) -> Result<LocalResult<DateTime<FixedOffset>>>;
 }
 
 impl LibGit2TimeExtensions for LibGit2Time {
     fn try_as_chrono(&self) -> Result<DateTime<FixedOffset>> {
+        self.try_as_chrono_all()?
+            .single()
+            .ok_or_else(|| anyhow!("Cannot convert to UNIX time {} to DateTime with offset {} minutes", self.seconds(), self.offset_minutes()))
+    }
+
+    fn try_as_chrono_all(&self) -> Result<LocalResult<DateTime<FixedOffset>>> {
         let abs_offset_minutes = self.offset_minutes().abs();
         let abs_offset_seconds = abs_offset_minutes * 60;
 
@@ -109,14 +134,7 @@ impl LibGit2TimeExtensions for LibGit2Time {
             _ => unreachable!("unexpected sign: {}", self.sign())
         };
 
-        match offset.timestamp_opt(self.seconds(), 0) {
-            LocalResult::Single(date_time) => Ok(date_time),
-            LocalResult::Ambiguous(min, max) => {
-                warn!("Received ambiguous result for commit: {} and {}", &min, &max);
-                Ok(min)
-            },
-            LocalResult::None => bail!("Cannot convert to UNIX time {} to DateTime<{}>", self.seconds(), offset)
-        }
+        Ok(offset.timestamp_opt(self.seconds(), 0))
     }
 }
 
@@ -129,12 +147,18 @@ pub(crate) trait LibGit2SignatureExtensions {
     ///
     /// If an entry is found, the registered `username` and `user id` from the database will be returned.
     /// If no entry is found, this [Signature][signature]s `name()` and `None` will be returned.
+    /// This is also the case for deleted users, as their emails are deleted together with their account.
     ///
     /// If this [Signature][signature]'s name is not valid utf-8, `Ghost` will be returned instead for `username`.
     /// This behaviour is subject to change.
     ///
     /// If this [Signature][signature]'s email is not valid utf-8, `None` will be returned instead of an user id.
     ///
+    /// If the resolved user [keeps their email private](User::private_email), their noreply alias is returned instead of the email.
+    ///
+    /// When disassembling many signatures (e.g. a list of commits), resolve all emails at once using
+    /// [User::find_using_emails] and use [disassemble_with](LibGit2SignatureExtensions::disassemble_with) instead.
+    ///
     /// # Example
     ///
     /// ```ignore
@@ -147,42 +171,60 @@ pub(crate) trait LibGit2SignatureExtensions {
     ///
     /// [signature]: git2::Signature
     async fn try_disassemble<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> (String, Option<i32>, String);
+
+    /// Disassembles this [Signature][signature] the same way as [try_disassemble](LibGit2SignatureExtensions::try_disassemble),
+    /// but looks up the email in `users` (as returned by [User::find_using_emails]) instead of querying the database.
+    ///
+    /// [signature]: git2::Signature
+    fn disassemble_with(&self, users: &HashMap<String, User>) -> (String, Option<i32>, String);
 }
 
 #[async_trait(?Send)]
 impl LibGit2SignatureExtensions for LibGit2Signature<'_> {
     async fn try_disassemble<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> (String, Option<i32>, String) {
         let email = self.email().unwrap_or("Invalid email address");
+        let users = User::find_using_emails(&[email], executor).await.unwrap_or_default();
 
-        User::find_using_email(email, executor)
-            .await
-            .map_or_else(
-                || (self.name().unwrap_or("Ghost").to_owned(), None, email.to_owned()),
-                |user| (user.username, Some(user.id), email.to_owned())
-            )
+        self.disassemble_with(&users)
+    }
+
+    fn disassemble_with(&self, users: &HashMap<String, User>) -> (String, Option<i32>, String) {
+        let email = self.email().unwrap_or("Invalid email address");
+
+        users.get(&email.to_lowercase()).map_or_else(
+            || (self.name().unwrap_or("Ghost").to_owned(), None, email.to_owned()),
+            |user| (user.username.clone(), Some(user.id), if user.private_email { user.noreply_email() } else { email.to_owned() })
+        )
     }
 }
 
+#[async_trait(?Send)]
 pub(crate) trait GitoxideSignatureExtensions {
-    /// Returns the default signature for GitArena.
-    /// This is at the moment hardcoded but is subject to change in the future.
-    fn gitarena_default() -> GitoxideSignature;
+    /// Returns the signature used for commits and ref updates authored by GitArena itself.
+    ///
+    /// Name and email are read from the `instance.signature.name` and `instance.signature.email` settings,
+    /// falling back to `GitArena <git@gitarena.com>` if they are not set. The time is always the current time in UTC.
+    async fn gitarena_default(transaction: &mut Transaction<'_, Postgres>) -> Result<GitoxideSignature>;
 }
 
+#[async_trait(?Send)]
 impl GitoxideSignatureExtensions for GitoxideSignature {
-    fn gitarena_default() -> GitoxideSignature {
-        let now = Utc::now();
-        let naive = now.naive_utc();
+    async fn gitarena_default(transaction: &mut Transaction<'_, Postgres>) -> Result<GitoxideSignature> {
+        let name = get_optional_setting::<String, _>("instance.signature.name", &mut *transaction).await?;
+        let email = get_optional_setting::<String, _>("instance.signature.email", &mut *transaction).await?;
+
+        // Gitoxide stores the time as an unsigned 32 bit integer, so fail instead of silently wrapping around
+        let time = u32::try_from(Utc::now().timestamp()).map_err(|_| anyhow!("Current time is not representable as Git signature time"))?;
 
-        GitoxideSignature {
-            name: BString::from("GitArena"), // TODO: Allow administrators to edit this
-            email: BString::from("git@gitarena.com"), // as well as this
+        Ok(GitoxideSignature {
+            name: BString::from(name.unwrap_or_else(|| "GitArena".to_owned())),
+            email: BString::from(email.unwrap_or_else(|| "git@gitarena.com".to_owned())),
             time: GitoxideTime {
-                time: naive.timestamp() as u32,
+                time,
                 offset: 0,
                 sign: Sign::Plus
             }
-        }
+        })
     }
 }
 
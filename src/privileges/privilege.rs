@@ -4,25 +4,18 @@ use crate::repository::Repository;
 use crate::user::User;
 
 use anyhow::{Context, Result};
-use sqlx::{Executor, FromRow, Postgres};
-
-#[derive(FromRow)]
-pub(crate) struct Privilege {
-    pub(crate) id: i32,
-    pub(crate) user_id: i32,
-    pub(crate) repo_id: i32,
-    pub(crate) access_level: AccessLevel
-}
+use gitarena_common::database::privileges;
+use sqlx::{Executor, Postgres, Transaction};
 
 macro_rules! generate_check {
     ($name:ident, $target:ident) => {
         pub(crate) async fn $name<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, user: Option<&User>, executor: E) -> Result<bool> {
             Ok(if let Some(user) = user {
                 if &user.id != &repo.owner && !user.admin {
-                    get_repo_privilege(repo, user, executor)
+                    get_access_level(repo, user, executor)
                         .await
                         .with_context(|| format!("Unable to get repo privileges for user {} in repo {}", &user.id, &repo.id))?
-                        .map_or_else(|| false, |privilege| privilege.access_level.$target())
+                        .map_or_else(|| false, |access_level| access_level.$target())
                 } else {
                     true
                 }
@@ -33,19 +26,23 @@ macro_rules! generate_check {
     }
 }
 
-pub(crate) async fn check_access<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, user: Option<&User>, executor: E) -> Result<bool> {
+pub(crate) async fn check_access(repo: &Repository, user: Option<&User>, transaction: &mut Transaction<'_, Postgres>) -> Result<bool> {
     if repo.disabled {
         return Ok(user.map_or_else(|| false, |user| user.admin));
     }
 
+    if !user.map_or(false, |user| user.admin) && privileges::is_owner_blocked(repo.owner, &mut *transaction).await? {
+        return Ok(false);
+    }
+
     Ok(match repo.visibility {
         RepoVisibility::Private => {
             if let Some(user) = user {
                 if user.id != repo.owner && !user.admin {
-                    get_repo_privilege(repo, user, executor)
+                    get_access_level(repo, user, &mut *transaction)
                         .await
                         .with_context(|| format!("Unable to get repo privileges for user {} in repo {}", &user.id, &repo.id))?
-                        .map_or_else(|| false, |privilege| privilege.access_level.can_view())
+                        .map_or_else(|| false, |access_level| access_level.can_view())
                 } else {
                     true
                 }
@@ -62,10 +59,7 @@ generate_check!(check_manage_issues, can_manage_issues);
 generate_check!(check_push, can_push);
 generate_check!(check_admin, can_admin);
 
-async fn get_repo_privilege<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, user: &User, executor: E) -> Result<Option<Privilege>> {
-    Ok(sqlx::query_as::<_, Privilege>("select * from privileges where user_id = $1 and repo_id = $2 limit 1")
-        .bind(&user.id)
-        .bind(&repo.id)
-        .fetch_optional(executor)
-        .await?)
+/// Returns the highest access level `user` has in `repo`, see [access_level](gitarena_common::database::privileges::access_level)
+async fn get_access_level<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, user: &User, executor: E) -> Result<Option<AccessLevel>> {
+    privileges::access_level(user.id, repo.id, repo.owner, executor).await
 }
@@ -1,10 +1,11 @@
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 use sqlx::Type;
+use utoipa::Component;
 
-#[derive(Type, Display, Debug, Ord, PartialOrd, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Type, Display, Debug, Ord, PartialOrd, Eq, PartialEq, Deserialize, Serialize, Component)]
 #[sqlx(type_name = "repo_visibility", rename_all = "lowercase")]
-#[serde(rename_all(serialize = "lowercase", deserialize = "lowercase"))]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum RepoVisibility {
     Public,
     Internal,
@@ -0,0 +1,137 @@
+//! Pull requests propose merging a branch into a branch of the same repository or, for forks, of the repository it was forked from.
+//!
+//! The source branch is fetched into `refs/pull/<index>/head` of the target repository, so the commits stay available
+//! even if the fork is deleted and merges only ever need to touch a single repository.
+
+use crate::issue;
+use crate::repository::Repository;
+use crate::user::User;
+
+use anyhow::Result;
+use chrono::serde::{ts_seconds, ts_seconds_option};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, FromRow, Postgres, Transaction, Type};
+
+#[derive(Type, Display, Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[sqlx(type_name = "pull_request_state", rename_all = "lowercase")]
+#[serde(rename_all(serialize = "lowercase", deserialize = "lowercase"))]
+pub(crate) enum PullRequestState {
+    Open,
+    Closed,
+    Merged
+}
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", title)]
+pub(crate) struct PullRequest {
+    pub(crate) id: i32,
+
+    pub(crate) repo: i32,
+    pub(crate) index: i32, // Shares the issue # counter of the repository
+
+    /// `None` if the author deleted their account
+    pub(crate) author: Option<i32>,
+    pub(crate) title: String,
+    /// Markdown source, needs to be rendered using [markdown::render](crate::markdown::render) before being displayed
+    pub(crate) body: String,
+
+    pub(crate) source_repo: Option<i32>,
+    pub(crate) source_branch: String,
+    pub(crate) target_branch: String,
+    pub(crate) head_sha: String,
+    pub(crate) base_sha: String,
+
+    pub(crate) state: PullRequestState,
+    pub(crate) merge_commit: Option<String>,
+    pub(crate) merged_by: Option<i32>,
+
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub(crate) updated_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds_option")]
+    pub(crate) merged_at: Option<DateTime<Utc>>
+}
+
+impl PullRequest {
+    /// Creates a new pull request with the next # of `repo`. The caller needs to fetch `head_sha` into [head_ref](PullRequest::head_ref) afterwards
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn create(repo: &Repository, author: &User, title: &str, body: &str, source_repo: &Repository, source_branch: &str, target_branch: &str, head_sha: &str, base_sha: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<PullRequest> {
+        let index = issue::next_index(repo, &mut *transaction).await?;
+
+        Ok(sqlx::query_as::<_, PullRequest>("insert into pull_requests (repo, index, author, title, body, source_repo, source_branch, target_branch, head_sha, base_sha) \
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) returning *")
+            .bind(&repo.id)
+            .bind(&index)
+            .bind(&author.id)
+            .bind(title)
+            .bind(body)
+            .bind(&source_repo.id)
+            .bind(source_branch)
+            .bind(target_branch)
+            .bind(head_sha)
+            .bind(base_sha)
+            .fetch_one(&mut *transaction)
+            .await?)
+    }
+
+    pub(crate) async fn find<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, index: i32, executor: E) -> Result<Option<PullRequest>> {
+        Ok(sqlx::query_as::<_, PullRequest>("select * from pull_requests where repo = $1 and index = $2 limit 1")
+            .bind(&repo.id)
+            .bind(&index)
+            .fetch_optional(executor)
+            .await?)
+    }
+
+    /// Ref in the target repository the source branch gets fetched into
+    pub(crate) fn head_ref(&self) -> String {
+        format!("refs/pull/{}/head", self.index)
+    }
+
+    pub(crate) async fn update_head<'e, E: Executor<'e, Database = Postgres>>(&mut self, head_sha: &str, base_sha: &str, executor: E) -> Result<()> {
+        sqlx::query("update pull_requests set head_sha = $1, base_sha = $2, updated_at = current_timestamp where id = $3")
+            .bind(head_sha)
+            .bind(base_sha)
+            .bind(&self.id)
+            .execute(executor)
+            .await?;
+
+        self.head_sha = head_sha.to_owned();
+        self.base_sha = base_sha.to_owned();
+
+        Ok(())
+    }
+
+    /// Opens or closes the pull request. Use [set_merged](PullRequest::set_merged) for merges
+    pub(crate) async fn set_state<'e, E: Executor<'e, Database = Postgres>>(&mut self, state: PullRequestState, executor: E) -> Result<()> {
+        sqlx::query("update pull_requests set state = $1, updated_at = current_timestamp where id = $2")
+            .bind(&state)
+            .bind(&self.id)
+            .execute(executor)
+            .await?;
+
+        self.state = state;
+
+        Ok(())
+    }
+
+    pub(crate) async fn set_merged<'e, E: Executor<'e, Database = Postgres>>(&mut self, merge_commit: &str, merged_by: &User, executor: E) -> Result<()> {
+        let (merged_at,): (DateTime<Utc>,) = sqlx::query_as("update pull_requests set state = 'merged', merge_commit = $1, merged_by = $2, \
+            merged_at = current_timestamp, updated_at = current_timestamp where id = $3 returning merged_at")
+            .bind(merge_commit)
+            .bind(&merged_by.id)
+            .bind(&self.id)
+            .fetch_one(executor)
+            .await?;
+
+        self.state = PullRequestState::Merged;
+        self.merge_commit = Some(merge_commit.to_owned());
+        self.merged_by = Some(merged_by.id);
+        self.merged_at = Some(merged_at);
+
+        Ok(())
+    }
+}
+
@@ -0,0 +1,62 @@
+//! Storage quotas limiting how much disk space repositories may use.
+//!
+//! `quotas.user_max_size` limits the combined size of all repositories owned by an user or organization and
+//! `quotas.repo_max_size` the size of every single repository. Admins can override the former per user using `users.storage_quota`.
+//! All limits are in bytes, zero disables them.
+//!
+//! Every check locks the row of the repository owner until the transaction ends, so concurrent writes are checked one after
+//! another. Callers need to update [repo_size_bytes](Repository::repo_size_bytes) before committing the transaction, or
+//! [reserve] the incoming size if the write takes long enough that the owner should not stay locked for all of it.
+
+use crate::repository::Repository;
+use crate::die;
+
+use anyhow::Result;
+use gitarena_common::database::quota;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::instrument;
+
+/// Returns why `repo` would exceed a quota once `incoming` bytes have been added to it or `None` if it would not.
+/// The size of the received data is used as estimate, as objects are stored as sent by the client
+#[instrument(err, skip(transaction))]
+pub(crate) async fn exceeded(repo: &Repository, incoming: u64, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<String>> {
+    let usage = quota::usage(repo.id, repo.owner, &mut *transaction).await?;
+
+    Ok(usage.exceeded(incoming))
+}
+
+/// Checks `incoming` bytes against the quotas of `repo` like [exceeded] and, if they fit, reserves them by adding them to the
+/// cached size right away. Runs in its own short transaction, so the owner is only locked for the check itself instead of for
+/// the whole push. Concurrent pushes are checked against the reservation, which is replaced with the actual size once the
+/// push has finished (see [update_size](Repository::update_size))
+pub(crate) async fn reserve(repo: &Repository, incoming: u64, db_pool: &PgPool) -> Result<Option<String>> {
+    let mut transaction = db_pool.begin().await?;
+
+    if let Some(reason) = exceeded(repo, incoming, &mut transaction).await? {
+        return Ok(Some(reason));
+    }
+
+    sqlx::query("update repositories set repo_size_bytes = repo_size_bytes + $1 where id = $2")
+        .bind(&(incoming as i64))
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(None)
+}
+
+/// Rejects the request with `413 Payload Too Large` if `repo` would exceed a quota once `incoming` bytes have been added to it
+pub(crate) async fn check_push(repo: &Repository, incoming: u64, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    if let Some(reason) = exceeded(repo, incoming, transaction).await? {
+        die!(PAYLOAD_TOO_LARGE, "{}", reason);
+    }
+
+    Ok(())
+}
+
+/// Returns how many bytes may still be added to `repo` or `None` if no quota applies
+pub(crate) async fn remaining(repo: &Repository, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<u64>> {
+    Ok(quota::usage(repo.id, repo.owner, &mut *transaction).await?.remaining())
+}
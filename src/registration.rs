@@ -0,0 +1,87 @@
+//! Controls who may create new accounts, configured using the `registrations.mode` setting.
+//!
+//! In `invite_only` mode, new accounts require an invite code generated by an admin. Only the hash of a code is stored,
+//! so codes can't be looked up again after they have been created, similar to access tokens.
+
+use crate::config::get_setting;
+use crate::crypto;
+use crate::die;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+use sqlx::{Executor, FromRow, Postgres};
+
+/// Length of newly generated invite codes
+const CODE_LENGTH: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RegistrationMode {
+    Open,
+    InviteOnly,
+    Closed
+}
+
+impl RegistrationMode {
+    pub(crate) async fn current<'e, E: Executor<'e, Database = Postgres>>(executor: E) -> Result<RegistrationMode> {
+        let value = get_setting::<String, _>("registrations.mode", executor).await?;
+
+        Ok(match value.to_lowercase().as_str() {
+            "open" => RegistrationMode::Open,
+            "invite_only" => RegistrationMode::InviteOnly,
+            "closed" => RegistrationMode::Closed,
+            other => {
+                // Falling back to open would allow everybody to register on a misconfigured private instance
+                warn!("Unknown registrations.mode value `{}`, treating registrations as closed", other);
+                RegistrationMode::Closed
+            }
+        })
+    }
+}
+
+#[derive(FromRow, Debug, Serialize)]
+pub(crate) struct InviteCode {
+    pub(crate) id: i32,
+    #[serde(skip_serializing)]
+    pub(crate) hash: String,
+    pub(crate) single_use: bool,
+    pub(crate) uses: i32,
+    pub(crate) expires_at: Option<DateTime<Utc>>,
+    pub(crate) created_by: Option<i32>,
+    pub(crate) created_at: DateTime<Utc>
+}
+
+impl InviteCode {
+    /// Creates a new invite code and returns it alongside the plain code, which is only available at this point
+    pub(crate) async fn create<'e, E: Executor<'e, Database = Postgres>>(created_by: i32, single_use: bool, expires_at: Option<DateTime<Utc>>, executor: E) -> Result<(InviteCode, String)> {
+        let code = crypto::random_numeric_ascii_string(CODE_LENGTH);
+
+        let invite_code = sqlx::query_as::<_, InviteCode>("insert into invite_codes (hash, single_use, expires_at, created_by) values ($1, $2, $3, $4) returning *")
+            .bind(crypto::hash_token(code.as_str()))
+            .bind(&single_use)
+            .bind(&expires_at)
+            .bind(&created_by)
+            .fetch_one(executor)
+            .await?;
+
+        Ok((invite_code, code))
+    }
+
+    /// Marks `code` as used. Fails if the code does not exist, has expired or has already been used up.
+    /// Checking and counting the use happen in a single statement, so a single use code can't be redeemed twice concurrently
+    pub(crate) async fn redeem<'e, E: Executor<'e, Database = Postgres>>(code: &str, executor: E) -> Result<()> {
+        let redeemed: Option<(i32,)> = sqlx::query_as("update invite_codes set uses = uses + 1 where hash = $1 \
+            and (not single_use or uses = 0) and (expires_at is null or expires_at > now()) returning id")
+            .bind(crypto::hash_token(code.trim()))
+            .fetch_optional(executor)
+            .await?;
+
+        if redeemed.is_none() {
+            die!(FORBIDDEN, "Invite code is invalid, expired or has already been used");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! Releases attach a title, notes and downloadable files to a tag.
+//!
+//! Assets are stored inside the bare repository at `releases/<release id>/<asset id>`, so they move along with the repository
+//! when it gets renamed or transferred and are removed together with it.
+
+use crate::die;
+use crate::repository::Repository;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use sqlx::{Executor, FromRow, Postgres};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", title)]
+pub(crate) struct Release {
+    pub(crate) id: i32,
+    pub(crate) repo: i32,
+    pub(crate) tag: String,
+    pub(crate) title: String,
+    /// Markdown source, needs to be rendered using [markdown::render](crate::markdown::render) before being displayed
+    pub(crate) body: String,
+    pub(crate) draft: bool,
+    pub(crate) prerelease: bool,
+    pub(crate) author: Option<i32>,
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>
+}
+
+impl Release {
+    pub(crate) async fn find<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, tag: &str, executor: E) -> Result<Option<Release>> {
+        Ok(sqlx::query_as::<_, Release>("select * from releases where repo = $1 and tag = $2 limit 1")
+            .bind(&repo.id)
+            .bind(tag)
+            .fetch_optional(executor)
+            .await?)
+    }
+
+    pub(crate) async fn assets<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<Vec<ReleaseAsset>> {
+        Ok(sqlx::query_as::<_, ReleaseAsset>("select * from release_assets where release = $1 order by name")
+            .bind(&self.id)
+            .fetch_all(executor)
+            .await?)
+    }
+}
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", name)]
+pub(crate) struct ReleaseAsset {
+    pub(crate) id: i32,
+    pub(crate) release: i32,
+    pub(crate) name: String,
+    pub(crate) content_type: String,
+    pub(crate) size: i64,
+    pub(crate) uploader: Option<i32>,
+    #[serde(with = "ts_seconds")]
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Path the file of an asset is stored at, `repo_path` being the [file system path](Repository::get_fs_path) of the repository
+pub(crate) fn asset_path(repo_path: &str, release_id: i32, asset_id: i32) -> PathBuf {
+    Path::new(repo_path).join("releases").join(release_id.to_string()).join(asset_id.to_string())
+}
+
+/// Writes `stream` to `path` and returns the amount of bytes written. Fails without leaving a partial file behind if the stream
+/// errors or turns out to be larger than `max_size` bytes
+pub(crate) async fn store_asset<S, B, E>(path: &Path, max_size: u64, mut stream: S) -> Result<u64>
+    where S: Stream<Item = Result<B, E>> + Unpin,
+          B: AsRef<[u8]>,
+          E: std::error::Error + Send + Sync + 'static
+{
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let mut file = fs::File::create(path).await?;
+    let mut size = 0_u64;
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                drop(file);
+                let _ = fs::remove_file(path).await;
+                return Err(err.into());
+            }
+        };
+
+        size += chunk.as_ref().len() as u64;
+
+        if size > max_size {
+            drop(file);
+            let _ = fs::remove_file(path).await;
+            die!(PAYLOAD_TOO_LARGE, "Asset may only be up to {} bytes large", max_size);
+        }
+
+        file.write_all(chunk.as_ref()).await?;
+    }
+
+    file.flush().await?;
+
+    Ok(size)
+}
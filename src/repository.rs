@@ -2,11 +2,14 @@ use crate::error::{ErrorDisplayType, GitArenaError};
 use crate::privileges::privilege;
 use crate::privileges::repo_visibility::RepoVisibility;
 use crate::user::{User, WebUser};
+use crate::utils::repo_redirect;
 use crate::{die, err};
 
+use std::collections::HashMap;
 use std::future::Future;
+use std::path::Path;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use actix_web::dev::Payload;
 use actix_web::web::Data;
@@ -18,11 +21,20 @@ use git2::{Repository as Git2Repository, RepositoryInitOptions};
 use git_repository::refs::file::find::existing::Error as GitoxideFindError;
 use git_repository::refs::file::loose::Reference;
 use git_repository::Repository as GitoxideRepository;
+use log::error;
+use once_cell::sync::Lazy;
 use serde::Serialize;
-use sqlx::{Executor, FromRow, PgPool, Postgres};
+use sqlx::{Executor, FromRow, PgPool, Postgres, Transaction};
+use tokio::fs;
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
 use tracing_unwrap::OptionExt;
+use utoipa::Component;
 
-#[derive(FromRow, Display, Debug, Serialize)]
+/// Guards the directory of every repository which has been accessed through Git since startup. Git operations hold it shared,
+/// moving the directory requires it exclusively so clones and pushes never observe a half-moved repository
+static FS_LOCKS: Lazy<Mutex<HashMap<i32, Arc<RwLock<()>>>>> = Lazy::new(Default::default);
+
+#[derive(FromRow, Display, Debug, Serialize, Component)]
 #[display(fmt = "{}", name)]
 pub(crate) struct Repository {
     pub(crate) id: i32,
@@ -40,7 +52,15 @@ pub(crate) struct Repository {
     pub(crate) mirrored_from: Option<String>,
 
     pub(crate) archived: bool,
-    pub(crate) disabled: bool
+    pub(crate) disabled: bool,
+
+    /// Cached result of [compute_size](Repository::compute_size), updated by [update_size](Repository::update_size)
+    pub(crate) repo_size_bytes: i64,
+
+    /// Amount of stars, only loaded by endpoints which return it
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stars: Option<i64>
 }
 
 impl Repository {
@@ -97,9 +117,148 @@ impl Repository {
         Ok(format!("{}/{}/{}", base_dir, username, &self.name))
     }
 
-    pub(crate) async fn repo_size<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<u64> {
-        Ok(dir::get_size(self.get_fs_path(executor).await?)?)
+    /// Walks the object database (loose objects and packs), LFS objects and release assets and returns their combined size in bytes.
+    /// This is slow for large repositories, so use the cached [repo_size_bytes](Repository::repo_size_bytes) where possible
+    pub(crate) async fn compute_size<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<u64> {
+        let repo_path = self.get_fs_path(executor).await?;
+        let mut size = 0;
+
+        for dir in ["objects", "lfs", "releases"] {
+            let path = Path::new(repo_path.as_str()).join(dir);
+
+            if path.is_dir() {
+                size += dir::get_size(path)?;
+            }
+        }
+
+        Ok(size)
+    }
+
+    /// Recomputes the size of the repository and stores it in [repo_size_bytes](Repository::repo_size_bytes)
+    pub(crate) async fn update_size(&mut self, transaction: &mut Transaction<'_, Postgres>) -> Result<i64> {
+        let size = self.compute_size(&mut *transaction).await? as i64;
+
+        sqlx::query("update repositories set repo_size_bytes = $1 where id = $2")
+            .bind(&size)
+            .bind(&self.id)
+            .execute(&mut *transaction)
+            .await?;
+
+        self.repo_size_bytes = size;
+
+        Ok(size)
+    }
+
+    /// Prevents the repository directory from being moved until the returned guard is dropped.
+    /// Needs to be acquired before locking the repository row, as [relocate](Repository::relocate) locks in that order
+    pub(crate) async fn lock_fs_shared(&self) -> OwnedRwLockReadGuard<()> {
+        fs_lock(self.id).read_owned().await
+    }
+
+    /// Waits for running Git operations to finish and blocks new ones until the returned guard is dropped.
+    /// Needed when moving the directory outside of [relocate](Repository::relocate), the same ordering rules apply
+    pub(crate) async fn lock_fs(&self) -> OwnedRwLockWriteGuard<()> {
+        fs_lock(self.id).write_owned().await
     }
+
+    /// Moves the repository to `name` owned by `owner`, both in the database and on disk, and redirects its previous location.
+    ///
+    /// Waits for running Git operations to finish and blocks new ones until the directory has been moved, so they never see
+    /// a half-moved repository. Consumes the transaction as the directory needs to be moved back if committing fails
+    pub(crate) async fn relocate(&self, owner: i32, name: &str, mut transaction: Transaction<'_, Postgres>) -> Result<Repository> {
+        let _fs_lock = fs_lock(self.id).write_owned().await;
+
+        let repo = sqlx::query_as::<_, Repository>("select * from repositories where id = $1 limit 1 for update")
+            .bind(&self.id)
+            .fetch_optional(&mut transaction)
+            .await?
+            .ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+        let (exists,): (bool,) = sqlx::query_as("select exists(select 1 from repositories where owner = $1 and lower(name) = lower($2) and id != $3 limit 1)")
+            .bind(&owner)
+            .bind(name)
+            .bind(&repo.id)
+            .fetch_one(&mut transaction)
+            .await?;
+
+        if exists {
+            die!(CONFLICT, "Repository name already in use for this account");
+        }
+
+        let old_path = repo.get_fs_path(&mut transaction).await?;
+
+        repo_redirect::add_redirect(repo.id, &mut transaction).await?;
+
+        let repo = sqlx::query_as::<_, Repository>("update repositories set owner = $1, name = $2 where id = $3 returning *")
+            .bind(&owner)
+            .bind(name)
+            .bind(&repo.id)
+            .fetch_one(&mut transaction)
+            .await?;
+
+        let new_path = repo.get_fs_path(&mut transaction).await?;
+
+        if new_path != old_path {
+            // On case-insensitive file systems, the new path of a case-only rename already "exists" as it's the same directory
+            if Path::new(new_path.as_str()).exists() && !old_path.eq_ignore_ascii_case(new_path.as_str()) {
+                die!(CONFLICT, "Repository directory already exists");
+            }
+
+            if let Some(parent) = Path::new(new_path.as_str()).parent() {
+                fs::create_dir_all(parent).await?;
+            }
+
+            fs::rename(old_path.as_str(), new_path.as_str()).await?;
+        }
+
+        if let Err(err) = transaction.commit().await {
+            if let Err(move_err) = fs::rename(new_path.as_str(), old_path.as_str()).await {
+                error!("Failed to move repo {} back to {}: {}", &repo.id, &old_path, move_err);
+            }
+
+            return Err(err.into());
+        }
+
+        Ok(repo)
+    }
+
+    /// Deletes the repository including all rows referencing it and afterwards its directory.
+    ///
+    /// The directory is only removed once the transaction has been committed, so a failed commit never leaves rows
+    /// pointing to a missing directory. Like [relocate](Repository::relocate), running Git operations are waited for first
+    pub(crate) async fn delete(self, mut transaction: Transaction<'_, Postgres>) -> Result<()> {
+        let _fs_lock = fs_lock(self.id).write_owned().await;
+
+        let path = self.get_fs_path(&mut transaction).await?;
+
+        // Forks only reference the repository they were forked from by id without a foreign key
+        sqlx::query("update repositories set forked_from = null where forked_from = $1")
+            .bind(&self.id)
+            .execute(&mut transaction)
+            .await?;
+
+        let result = sqlx::query("delete from repositories where id = $1")
+            .bind(&self.id)
+            .execute(&mut transaction)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            die!(NOT_FOUND, "Repository not found");
+        }
+
+        transaction.commit().await?;
+
+        if let Err(err) = fs::remove_dir_all(path.as_str()).await {
+            error!("Failed to remove directory {} of deleted repo {}: {}", &path, &self.id, err);
+        }
+
+        Ok(())
+    }
+}
+
+fn fs_lock(repo: i32) -> Arc<RwLock<()>> {
+    let mut locks = FS_LOCKS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    locks.entry(repo).or_default().clone()
 }
 
 impl FromRequest for Repository {
@@ -0,0 +1,43 @@
+use crate::audit::{self, AuditEvent};
+use crate::user::{AdminUser, User};
+use crate::utils::pagination::{Page, PageQuery};
+use crate::{die, err};
+
+use actix_web::{HttpRequest, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+/// Audit log entries, newest first, optionally filtered by the username of the actor and the event type.
+/// Paginated using the id of the last entry as cursor
+#[route("/audit", method = "GET", err = "json")]
+pub(crate) async fn audit_log(page: web::Query<PageQuery>, filter: web::Query<AuditFilter>, _admin: AdminUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let after = match page.after.as_deref().map(str::parse::<i32>) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => die!(BAD_REQUEST, "Invalid cursor"),
+        None => None
+    };
+
+    let mut transaction = db_pool.begin().await?;
+
+    let actor = match filter.actor.as_deref() {
+        Some(username) => Some(User::find_using_name(username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "User not found"))?.id),
+        None => None
+    };
+
+    let limit = page.limit(&mut transaction).await?;
+    let entries = audit::query(actor, filter.event, after, limit as i64 + 1, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let page = Page::new(entries, limit, |entry| entry.id.to_string());
+
+    Ok(page.respond(&request, limit))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AuditFilter {
+    actor: Option<String>,
+    event: Option<AuditEvent>
+}
@@ -1,7 +1,7 @@
 use crate::prelude::ContextExtensions;
 use crate::repository::Repository;
-use crate::user::{User, WebUser};
-use crate::{die, render_template};
+use crate::user::{AdminUser, User};
+use crate::render_template;
 
 use actix_web::{Responder, web};
 use anyhow::Result;
@@ -15,12 +15,8 @@ use sqlx::PgPool;
 use tera::Context;
 
 #[route("/", method = "GET", err = "html")]
-pub(crate) async fn dashboard(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
-    let user = web_user.into_user()?;
-
-    if !user.admin {
-        die!(FORBIDDEN, "Not allowed");
-    }
+pub(crate) async fn dashboard(admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = admin.into_user();
 
     let mut context = Context::new();
 
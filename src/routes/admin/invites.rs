@@ -0,0 +1,75 @@
+use crate::registration::InviteCode;
+use crate::user::AdminUser;
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+
+#[route("/invites", method = "GET", err = "json")]
+pub(crate) async fn list_invites(_admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let invites: Vec<InviteCode> = sqlx::query_as::<_, InviteCode>("select * from invite_codes order by created_at desc")
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+/// Creates an invite code. The code is only part of this response, afterwards only its metadata can be listed
+#[route("/invites", method = "POST", err = "json")]
+pub(crate) async fn create_invite(body: web::Json<CreateInviteJsonRequest>, admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    if body.expires_at.map_or(false, |expires_at| expires_at <= Utc::now()) {
+        die!(BAD_REQUEST, "Expiration date needs to be in the future");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let (invite, code) = InviteCode::create(admin.id, body.single_use, body.expires_at, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) created invite code {} (single use: {}, expires: {:?})", &admin.username, &admin.id, &invite.id, &invite.single_use, &invite.expires_at);
+
+    Ok(HttpResponse::Created().json(json!({
+        "id": invite.id,
+        "code": code,
+        "url": format!("/register?invite={}", code)
+    })))
+}
+
+#[route("/invites/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_invite(id: web::Path<i32>, admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let (deleted_id,): (i32,) = sqlx::query_as("delete from invite_codes where id = $1 returning id")
+        .bind(id.into_inner())
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Invite code not found"))?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) deleted invite code {}", &admin.username, &admin.id, deleted_id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreateInviteJsonRequest {
+    #[serde(default = "default_single_use")]
+    single_use: bool,
+    expires_at: Option<DateTime<Utc>>
+}
+
+fn default_single_use() -> bool {
+    true
+}
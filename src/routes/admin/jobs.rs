@@ -0,0 +1,34 @@
+use crate::jobs::JobRow;
+use crate::user::AdminUser;
+use crate::err;
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use sqlx::PgPool;
+
+/// Lists jobs which have failed at least once, such as emails the SMTP server refused. Jobs which used up all attempts have `failed_at` set
+#[route("/jobs", method = "GET", err = "json")]
+pub(crate) async fn failed_jobs(admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = admin.into_user();
+
+    let jobs: Vec<JobRow> = sqlx::query_as::<_, JobRow>("select * from jobs where last_error is not null order by created_at desc limit 100")
+        .fetch_all(db_pool.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+/// Schedules a job to be run again right away, resetting its attempts
+#[route("/jobs/{id}/retry", method = "POST", err = "json")]
+pub(crate) async fn retry_job(id: web::Path<i32>, admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = admin.into_user();
+
+    sqlx::query("update jobs set attempts = 0, run_at = now(), failed_at = null where id = $1 returning id")
+        .bind(id.into_inner())
+        .fetch_optional(db_pool.get_ref())
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Job not found"))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,7 +1,7 @@
 use crate::prelude::ContextExtensions;
 use crate::sse::{Broadcaster, Category};
-use crate::user::WebUser;
-use crate::{die, render_template};
+use crate::user::AdminUser;
+use crate::render_template;
 
 use std::collections::HashMap;
 use std::fs;
@@ -19,12 +19,8 @@ use serde_json::Value;
 use tera::Context;
 
 #[route("/log", method = "GET", err = "html")]
-pub(crate) async fn log(web_user: WebUser) -> Result<impl Responder> {
-    let user = web_user.into_user()?;
-
-    if !user.admin {
-        die!(FORBIDDEN, "Not allowed");
-    }
+pub(crate) async fn log(admin: AdminUser) -> Result<impl Responder> {
+    let user = admin.into_user();
 
     static LOG_FILE: Lazy<String> = Lazy::new(get_log_file_path);
 
@@ -55,12 +51,8 @@ pub(crate) async fn log(web_user: WebUser) -> Result<impl Responder> {
 }
 
 #[route("/log/sse", method = "GET", err = "html")]
-pub(crate) async fn log_sse(web_user: WebUser, broadcaster: Data<RwLock<Broadcaster>>) -> Result<impl Responder> {
-    let user = web_user.into_user()?;
-
-    if !user.admin {
-        die!(FORBIDDEN, "Not allowed");
-    }
+pub(crate) async fn log_sse(admin: AdminUser, broadcaster: Data<RwLock<Broadcaster>>) -> Result<impl Responder> {
+    let user = admin.into_user();
 
     let tx = broadcaster.write().await.new_client(Category::AdminLog).await?;
 
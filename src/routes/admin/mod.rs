@@ -1,15 +1,32 @@
 use actix_web::Scope;
 use actix_web::web::scope;
 
+mod audit;
 mod dashboard;
+mod invites;
+mod jobs;
 mod log;
+mod repositories;
 mod settings;
+pub(crate) mod users;
 
 pub(crate) fn all() -> Scope {
     scope("/admin")
+        .service(audit::audit_log)
         .service(dashboard::dashboard)
+        .service(invites::list_invites)
+        .service(invites::create_invite)
+        .service(invites::delete_invite)
+        .service(jobs::failed_jobs)
+        .service(jobs::retry_job)
         .service(log::log)
         .service(log::log_sse)
+        .service(repositories::recalculate_size)
         .service(settings::get_settings)
         .service(settings::patch_settings)
+        .service(users::disable_user)
+        .service(users::enable_user)
+        .service(users::ban_user)
+        .service(users::unban_user)
+        .service(users::set_quota)
 }
@@ -0,0 +1,33 @@
+use crate::repository::Repository;
+use crate::user::AdminUser;
+use crate::err;
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::info;
+use serde_json::json;
+use sqlx::PgPool;
+
+/// Recomputes the cached size of a repository, for example after it has been modified on disk outside of GitArena
+#[route("/repositories/{id}/size", method = "POST", err = "json")]
+pub(crate) async fn recalculate_size(id: web::Path<i32>, admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let mut repo: Repository = sqlx::query_as::<_, Repository>("select * from repositories where id = $1 limit 1 for update")
+        .bind(id.into_inner())
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    let previous = repo.repo_size_bytes;
+    let size = repo.update_size(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) recalculated the size of repo id {}: {} -> {} bytes", &admin.username, &admin.id, &repo.id, previous, size);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "repo_size_bytes": size
+    })))
+}
@@ -1,6 +1,6 @@
 use crate::config::{Setting, TypeConstraint};
 use crate::prelude::{ContextExtensions, HttpRequestExtensions};
-use crate::user::WebUser;
+use crate::user::AdminUser;
 use crate::{config, die, err, render_template};
 
 use std::collections::HashMap;
@@ -14,12 +14,8 @@ use sqlx::PgPool;
 use tera::Context;
 
 #[route("/settings", method = "GET", err = "html")]
-pub(crate) async fn get_settings(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
-    let user = web_user.into_user()?;
-
-    if !user.admin {
-        die!(FORBIDDEN, "Not allowed");
-    }
+pub(crate) async fn get_settings(admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = admin.into_user();
 
     let mut context = Context::new();
     context.insert_user(&user)?;
@@ -44,12 +40,8 @@ pub(crate) async fn get_settings(web_user: WebUser, db_pool: web::Data<PgPool>)
 }
 
 #[route("/settings", method = "PATCH", err = "htmx+text")]
-pub(crate) async fn patch_settings(data: web::Form<HashMap<String, String>>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
-    let user = web_user.into_user()?;
-
-    if !user.admin {
-        die!(FORBIDDEN, "Not allowed");
-    }
+pub(crate) async fn patch_settings(data: web::Form<HashMap<String, String>>, admin: AdminUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = admin.into_user();
 
     let mut transaction = db_pool.begin().await?;
     let once = Once::new();
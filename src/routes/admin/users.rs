@@ -0,0 +1,199 @@
+use crate::audit::{self, AuditEvent};
+use crate::user::{AdminUser, User};
+use crate::{die, err};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::{PgPool, Postgres, Transaction};
+
+/// Maximum amount of users returned by [list_users] at once
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// Lists all users, newest first, for moderation. Paginated using `offset` and `limit` query parameters.
+/// Registered outside of the `/admin` scope as it's part of the API
+#[route("/api/admin/users", method = "GET", err = "json")]
+pub(crate) async fn list_users(query: web::Query<UserListQuery>, _admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let mut transaction = db_pool.begin().await?;
+
+    let (total,): (i64,) = sqlx::query_as("select count(*) from users")
+        .fetch_one(&mut transaction)
+        .await?;
+
+    let users: Vec<User> = sqlx::query_as::<_, User>("select * from users order by id desc offset $1 limit $2")
+        .bind(&offset)
+        .bind(&limit)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "users": users,
+        "total": total,
+        "offset": offset,
+        "limit": limit
+    })))
+}
+
+/// Disables an account indefinitely. All its sessions are destroyed, so it gets logged out everywhere right away
+#[route("/users/{username}/disable", method = "POST", err = "json")]
+pub(crate) async fn disable_user(username: web::Path<String>, admin: AdminUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_target(username.as_str(), &admin, &mut transaction).await?;
+    let sessions = update_user(&target, true, None, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) disabled {} (id {}), revoking {} sessions", &admin.username, &admin.id, &target.username, &target.id, sessions);
+    record_change(&request, &admin, &target, json!({ "action": "disable" })).await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "disabled": true
+    })))
+}
+
+#[route("/users/{username}/enable", method = "POST", err = "json")]
+pub(crate) async fn enable_user(username: web::Path<String>, admin: AdminUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_target(username.as_str(), &admin, &mut transaction).await?;
+    update_user(&target, false, target.banned_until, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) re-enabled {} (id {})", &admin.username, &admin.id, &target.username, &target.id);
+    record_change(&request, &admin, &target, json!({ "action": "enable" })).await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "disabled": false
+    })))
+}
+
+/// Bans an account until the given point in time, after which it can be used again without further action
+#[route("/users/{username}/ban", method = "POST", err = "json")]
+pub(crate) async fn ban_user(username: web::Path<String>, body: web::Json<BanJsonRequest>, admin: AdminUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    if body.until <= Utc::now() {
+        die!(BAD_REQUEST, "Ban needs to end in the future");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_target(username.as_str(), &admin, &mut transaction).await?;
+    let sessions = update_user(&target, target.disabled, Some(body.until), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) banned {} (id {}) until {}, revoking {} sessions", &admin.username, &admin.id, &target.username, &target.id, &body.until, sessions);
+    record_change(&request, &admin, &target, json!({ "action": "ban", "until": body.until })).await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "banned_until": body.until
+    })))
+}
+
+#[route("/users/{username}/unban", method = "POST", err = "json")]
+pub(crate) async fn unban_user(username: web::Path<String>, admin: AdminUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_target(username.as_str(), &admin, &mut transaction).await?;
+    update_user(&target, target.disabled, None, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) unbanned {} (id {})", &admin.username, &admin.id, &target.username, &target.id);
+    record_change(&request, &admin, &target, json!({ "action": "unban" })).await;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "banned_until": null
+    })))
+}
+
+/// Overrides the storage quota of an user or organization in bytes. Zero allows unlimited storage, `null` uses the instance default
+#[route("/users/{username}/quota", method = "PUT", err = "json")]
+pub(crate) async fn set_quota(username: web::Path<String>, body: web::Json<QuotaJsonRequest>, admin: AdminUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    if body.quota.map_or(false, |quota| quota < 0) {
+        die!(BAD_REQUEST, "Quota cannot be negative");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let target = User::find_using_name(username.as_str(), &mut transaction)
+        .await
+        .ok_or_else(|| err!(NOT_FOUND, "User not found"))?;
+
+    sqlx::query("update users set storage_quota = $1 where id = $2")
+        .bind(&body.quota)
+        .bind(&target.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) set the storage quota of {} (id {}) to {:?}", &admin.username, &admin.id, &target.username, &target.id, &body.quota);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "quota": body.quota
+    })))
+}
+
+async fn find_target(username: &str, admin: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    let target = User::find_using_name(username, &mut *transaction)
+        .await
+        .ok_or_else(|| err!(NOT_FOUND, "User not found"))?;
+
+    if target.id == admin.id {
+        die!(BAD_REQUEST, "Admins cannot disable or ban themselves");
+    }
+
+    Ok(target)
+}
+
+async fn record_change(request: &HttpRequest, admin: &User, target: &User, details: Value) {
+    audit::record(request, AuditEvent::PermissionChanged, Some(admin), Some(format!("user:{}", &target.username)), details).await;
+}
+
+/// Updates the account and destroys all its sessions if it can no longer be used. Returns the amount of destroyed sessions
+async fn update_user(user: &User, disabled: bool, banned_until: Option<DateTime<Utc>>, transaction: &mut Transaction<'_, Postgres>) -> Result<u64> {
+    sqlx::query("update users set disabled = $1, banned_until = $2 where id = $3")
+        .bind(&disabled)
+        .bind(&banned_until)
+        .bind(&user.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    if !disabled && banned_until.map_or(true, |until| until <= Utc::now()) {
+        return Ok(0);
+    }
+
+    let result = sqlx::query("delete from sessions where user_id = $1")
+        .bind(&user.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct UserListQuery {
+    offset: Option<i64>,
+    limit: Option<i64>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BanJsonRequest {
+    until: DateTime<Utc>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct QuotaJsonRequest {
+    quota: Option<i64>
+}
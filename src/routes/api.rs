@@ -9,6 +9,7 @@ pub(crate) async fn api() -> Result<impl Responder> {
         "app": "GitArena",
         "version": env!("CARGO_PKG_VERSION"),
         "documentation": "https://gitarena.com/docs/api",
+        "openapi": "/api/openapi.json",
         "repository": env!("CARGO_PKG_REPOSITORY"),
         "commit": env!("VERGEN_GIT_SHA")
     })))
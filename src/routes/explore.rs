@@ -1,5 +1,6 @@
 use crate::prelude::{ContextExtensions, HttpRequestExtensions};
 use crate::privileges::repo_visibility::RepoVisibility;
+use crate::topics::is_valid_topic;
 use crate::user::WebUser;
 use crate::{err, render_template};
 
@@ -86,6 +87,7 @@ struct ExploreOptions<'a> {
     mirrored: bool,
     internal: bool,
     disabled: bool,
+    topic: Option<&'a str>,
     sort: &'a str,
     order: Order,
     offset: u32,
@@ -102,6 +104,7 @@ impl ExploreOptions<'_> {
             mirrored: query_string.get("mirror").map_or_else(|| true, |value| value == "1"),
             internal,
             disabled,
+            topic: query_string.get("topic").filter(|topic| is_valid_topic(topic)),
             sort,
             order,
             offset: query_string.get("offset").map_or_else(|| 0, |value| value.parse::<u32>().unwrap_or(0)),
@@ -134,6 +137,11 @@ impl Display for ExploreOptions<'_> {
             f.write_str("repositories.disabled is false and ")?;
         }
 
+        // Only valid topics are accepted while parsing, which consist of identifier characters and are therefore safe to embed
+        if let Some(topic) = self.topic {
+            write!(f, "exists(select 1 from repo_topics where repo_topics.repo = repositories.id and repo_topics.topic = '{}') and ", topic)?;
+        }
+
         // Private repositories are hidden in the public explore page
         // TODO: Display them if the logged in user has permission to view them
         f.write_str("repositories.visibility != 'private' group by repositories.id, users.id order by ")?;
@@ -0,0 +1,37 @@
+use crate::config::{get_optional_setting, get_setting};
+use crate::prelude::*;
+use crate::{die, metrics};
+
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use ring::constant_time;
+use sqlx::PgPool;
+
+/// Exposes metrics in the Prometheus text format. Disabled by default; if `metrics.token` is set, scrapers need to send it as Bearer token
+#[route("/metrics", method = "GET", err = "text")]
+pub(crate) async fn metrics(request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let enabled = get_setting::<bool, _>("metrics.enabled", &mut transaction).await?;
+    let token = get_optional_setting::<String, _>("metrics.token", &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    if !enabled {
+        die!(NOT_FOUND, "Not found");
+    }
+
+    if let Some(token) = token.as_deref().filter(|token| !token.is_empty()) {
+        let provided = request.get_header("authorization").and_then(|header| header.strip_prefix("Bearer ")).unwrap_or_default();
+
+        if constant_time::verify_slices_are_equal(provided.trim().as_bytes(), token.as_bytes()).is_err() {
+            die!(UNAUTHORIZED, "Invalid metrics token");
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8"))
+        .body(metrics::render(db_pool.get_ref())?))
+}
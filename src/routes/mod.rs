@@ -2,6 +2,9 @@ use actix_web::web::ServiceConfig;
 
 mod api;
 mod explore;
+mod metrics;
+mod openapi;
+mod organization;
 pub(crate) mod admin;
 pub(crate) mod not_found;
 pub(crate) mod proxy;
@@ -10,5 +13,11 @@ pub(crate) mod user;
 
 pub(crate) fn init(config: &mut ServiceConfig) {
     config.service(api::api);
+    config.service(openapi::docs);
+    config.service(openapi::swagger_ui());
+    config.service(admin::users::list_users);
     config.service(explore::explore);
+    config.service(metrics::metrics);
+
+    organization::init(config);
 }
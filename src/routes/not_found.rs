@@ -1,7 +1,7 @@
 use crate::error::{ErrorDisplayType, GitArenaError};
 use crate::prelude::ContextExtensions;
-use crate::render_template;
 use crate::user::WebUser;
+use crate::{die, render_template};
 
 use std::sync::Arc;
 
@@ -10,16 +10,12 @@ use actix_web::Result as ActixResult;
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use log::debug;
-use serde_json::json;
 use sqlx::PgPool;
 use tera::Context;
 use tracing::instrument;
 
 async fn api_not_found() -> Result<HttpResponse> {
-    Ok(HttpResponse::NotFound().json(json!({
-        "error": "Not found",
-        "documentation": "https://gitarena.com/docs/api"
-    })))
+    die!(NOT_FOUND, "Not found")
 }
 
 async fn web_not_found(request: HttpRequest, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse> {
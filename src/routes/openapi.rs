@@ -0,0 +1,134 @@
+use crate::mail::Email;
+use crate::notification::WatchLevel;
+use crate::privileges::repo_visibility::RepoVisibility;
+use crate::repository::Repository;
+use crate::routes::repository::api::{create_repo, deploy_keys, repo_meta, star, topics, transfer, watch, webhooks, CreateJsonResponse};
+use crate::routes::user::api::{account, emails, follows, gpg_keys, profile, sessions, ssh_keys, tokens, two_factor};
+use crate::routes::user::user_create;
+
+use actix_web::http::header::LOCATION;
+use actix_web::{HttpResponse, Responder};
+use anyhow::Result;
+use gitarena_macros::route;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+/// OpenAPI document of the JSON API, served at `/api/openapi.json`. Handlers and structs need to be listed here
+/// after annotating them with `#[utoipa::path]` and deriving `Component` respectively
+#[derive(OpenApi)]
+#[openapi(
+    handlers(
+        user_create::post_register,
+        profile::get_profile,
+        account::delete_account,
+        emails::list_emails,
+        emails::add_email,
+        emails::set_primary_email,
+        emails::delete_email,
+        emails::set_email_privacy,
+        sessions::delete_sessions,
+        two_factor::enroll,
+        two_factor::verify,
+        follows::follow,
+        follows::unfollow,
+        follows::list_followers,
+        follows::list_following,
+        tokens::create_token,
+        tokens::list_tokens,
+        tokens::delete_token,
+        ssh_keys::add_ssh_key,
+        ssh_keys::list_ssh_keys,
+        ssh_keys::delete_ssh_key,
+        gpg_keys::add_gpg_key,
+        gpg_keys::list_gpg_keys,
+        gpg_keys::delete_gpg_key,
+        create_repo::create,
+        repo_meta::meta,
+        repo_meta::update_visibility,
+        repo_meta::rename,
+        repo_meta::update_description,
+        repo_meta::update_default_branch,
+        repo_meta::delete,
+        topics::get_topics,
+        topics::put_topics,
+        star::get_star,
+        star::post_star,
+        star::delete_star,
+        watch::get_watch,
+        watch::put_watch,
+        watch::delete_watch,
+        transfer::get_transfer,
+        transfer::create_transfer,
+        transfer::accept_transfer,
+        transfer::delete_transfer,
+        deploy_keys::list_deploy_keys,
+        deploy_keys::add_deploy_key,
+        deploy_keys::delete_deploy_key,
+        webhooks::list_webhooks,
+        webhooks::create_webhook,
+        webhooks::delete_webhook,
+        webhooks::list_deliveries
+    ),
+    components(
+        user_create::RegisterJsonRequest,
+        user_create::RegisterJsonResponse,
+        profile::ProfileJsonResponse,
+        account::DeleteAccountJsonRequest,
+        Email,
+        emails::AddEmailJsonRequest,
+        emails::EmailPrivacyJsonRequest,
+        emails::EmailPrivacyJsonResponse,
+        sessions::DeleteSessionsJsonResponse,
+        two_factor::EnrollJsonResponse,
+        two_factor::VerifyJsonRequest,
+        two_factor::VerifyJsonResponse,
+        follows::FollowJsonEntry,
+        tokens::CreateTokenJsonRequest,
+        tokens::CreateTokenJsonResponse,
+        tokens::TokenJsonResponse,
+        ssh_keys::AddKeyJsonRequest,
+        ssh_keys::AddKeyJsonResponse,
+        gpg_keys::AddGpgKeyJsonRequest,
+        gpg_keys::AddGpgKeyJsonResponse,
+        create_repo::CreateJsonRequest,
+        CreateJsonResponse,
+        Repository,
+        RepoVisibility,
+        repo_meta::VisibilityJsonRequest,
+        repo_meta::RenameJsonRequest,
+        repo_meta::RenameJsonResponse,
+        repo_meta::DescriptionJsonRequest,
+        repo_meta::DefaultBranchJsonRequest,
+        repo_meta::DeleteJsonRequest,
+        topics::TopicsJson,
+        WatchLevel,
+        watch::WatchJsonRequest,
+        watch::WatchJson,
+        transfer::TransferJsonRequest,
+        transfer::TransferredJsonResponse,
+        deploy_keys::AddDeployKeyJsonRequest,
+        deploy_keys::AddDeployKeyJsonResponse,
+        webhooks::CreateWebhookJsonRequest,
+        webhooks::CreateWebhookJsonResponse
+    ),
+    tags(
+        (name = "user", description = "Accounts and profiles"),
+        (name = "tokens", description = "Personal access tokens"),
+        (name = "keys", description = "SSH and GPG keys"),
+        (name = "repository", description = "Repository management")
+    )
+)]
+pub(crate) struct ApiDoc;
+
+/// Swagger UI at `/api/docs/` which also serves the document itself
+pub(crate) fn swagger_ui() -> SwaggerUi {
+    SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi())
+}
+
+/// Swagger UI only handles paths below `/api/docs/`, so the path without trailing slash needs to be redirected
+#[route("/api/docs", method = "GET", err = "html")]
+pub(crate) async fn docs() -> Result<impl Responder> {
+    Ok(HttpResponse::MovedPermanently()
+        .append_header((LOCATION, "/api/docs/"))
+        .finish())
+}
@@ -0,0 +1,142 @@
+use crate::audit::{self, AuditEvent};
+use crate::organization::{all_members, find_organization, find_role, OrganizationRole};
+use crate::routes::organization::{find_managed_organization, find_member_organization, OrganizationRequest};
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[route("/api/orgs/{name}/members", method = "GET", err = "json")]
+pub(crate) async fn list_members(uri: web::Path<OrganizationRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_member_organization(uri.name.as_str(), &user, &mut transaction).await?;
+
+    let members = all_members(&organization, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(members))
+}
+
+/// Adds a member to the organization or changes the role of an existing member
+#[route("/api/orgs/{name}/members", method = "PUT", err = "json")]
+pub(crate) async fn put_member(uri: web::Path<OrganizationRequest>, body: web::Json<MemberJsonRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_managed_organization(uri.name.as_str(), &user, &mut transaction).await?;
+
+    let member: User = sqlx::query_as::<_, User>("select * from users where lower(username) = lower($1) and organization = false limit 1")
+        .bind(&body.username)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "User not found"))?;
+
+    if body.role != OrganizationRole::Owner && member.id == user.id {
+        ensure_other_owner(&organization, &member, &mut transaction).await?;
+    }
+
+    sqlx::query("insert into organization_members (organization, member, role) values ($1, $2, $3) \
+        on conflict (organization, member) do update set role = excluded.role")
+        .bind(&organization.id)
+        .bind(&member.id)
+        .bind(&body.role)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) set role of {} (id {}) in organization {} (id {}) to {}", &user.username, &user.id, &member.username, &member.id, &organization.username, &organization.id, &body.role);
+    audit::record(&request, AuditEvent::PermissionChanged, Some(&user), Some(format!("org:{}", &organization.username)), json!({
+        "member": &member.username,
+        "role": &body.role
+    })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[route("/api/orgs/{name}/members/{username}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_member(uri: web::Path<MemberRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_organization(uri.name.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Organization not found"))?;
+
+    // Members may leave on their own, everybody else has to be removed by an owner
+    match find_role(&organization, &user, &mut transaction).await? {
+        Some(OrganizationRole::Owner) => {}
+        Some(_) if user.username.eq_ignore_ascii_case(uri.username.as_str()) => {}
+        Some(_) => die!(FORBIDDEN, "Only owners can manage members"),
+        None => die!(NOT_FOUND, "Organization not found")
+    }
+
+    let member: User = sqlx::query_as::<_, User>("select * from users where lower(username) = lower($1) limit 1")
+        .bind(uri.username.as_str())
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "User not found"))?;
+
+    ensure_other_owner(&organization, &member, &mut transaction).await?;
+
+    let result = sqlx::query("delete from organization_members where organization = $1 and member = $2")
+        .bind(&organization.id)
+        .bind(&member.id)
+        .execute(&mut transaction)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        die!(NOT_FOUND, "User is not a member of this organization");
+    }
+
+    // Otherwise the teams would keep granting access to the repositories of the organization
+    sqlx::query("delete from team_members where member = $1 and team in (select id from teams where organization = $2)")
+        .bind(&member.id)
+        .bind(&organization.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) removed {} (id {}) from organization {} (id {})", &user.username, &user.id, &member.username, &member.id, &organization.username, &organization.id);
+    audit::record(&request, AuditEvent::PermissionChanged, Some(&user), Some(format!("org:{}", &organization.username)), json!({
+        "member": &member.username,
+        "role": null
+    })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Errors if `member` is the last owner of `organization`, as that would leave it without anyone able to manage it
+async fn ensure_other_owner(organization: &User, member: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    let (last_owner,): (bool,) = sqlx::query_as("select coalesce(bool_or(member = $2) and count(*) = 1, false) from organization_members where organization = $1 and role = 'owner'")
+        .bind(&organization.id)
+        .bind(&member.id)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    if last_owner {
+        die!(BAD_REQUEST, "Organizations need at least one owner");
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MemberRequest {
+    name: String,
+    username: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MemberJsonRequest {
+    username: String,
+    role: OrganizationRole
+}
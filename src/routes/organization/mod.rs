@@ -0,0 +1,56 @@
+use crate::organization::{find_organization, find_role, OrganizationRole};
+use crate::user::User;
+use crate::{die, err};
+
+use actix_web::web::ServiceConfig;
+use anyhow::Result;
+use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
+
+mod members;
+mod organizations;
+mod teams;
+
+pub(crate) fn init(config: &mut ServiceConfig) {
+    config.service(organizations::create_organization);
+    config.service(organizations::list_organizations);
+
+    config.service(members::list_members);
+    config.service(members::put_member);
+    config.service(members::delete_member);
+
+    config.service(teams::list_teams);
+    config.service(teams::create_team);
+    config.service(teams::delete_team);
+    config.service(teams::put_team_member);
+    config.service(teams::delete_team_member);
+    config.service(teams::put_team_repository);
+    config.service(teams::delete_team_repository);
+}
+
+/// Finds an organization `user` is a member of. Membership is private, so non-members are told the organization does not exist
+pub(crate) async fn find_member_organization(name: &str, user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    let organization = find_organization(name, &mut *transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Organization not found"))?;
+
+    if find_role(&organization, user, &mut *transaction).await?.is_none() {
+        die!(NOT_FOUND, "Organization not found");
+    }
+
+    Ok(organization)
+}
+
+/// Finds an organization `user` is an owner of and thus allowed to manage its members and teams
+pub(crate) async fn find_managed_organization(name: &str, user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    let organization = find_organization(name, &mut *transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Organization not found"))?;
+
+    match find_role(&organization, user, &mut *transaction).await? {
+        Some(OrganizationRole::Owner) => Ok(organization),
+        Some(_) => die!(FORBIDDEN, "Only owners can manage this organization"),
+        None => die!(NOT_FOUND, "Organization not found")
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OrganizationRequest {
+    name: String
+}
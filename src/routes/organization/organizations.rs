@@ -0,0 +1,88 @@
+use crate::organization::OrganizationRole;
+use crate::user::{User, WebUser};
+use crate::utils::identifiers::{is_username_taken, validate_new_username};
+use crate::utils::is_unique_violation;
+use crate::{crypto, die};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+#[route("/api/orgs", method = "POST", err = "json")]
+pub(crate) async fn create_organization(body: web::Json<CreateOrganizationJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let name = body.name.as_str();
+
+    let mut transaction = db_pool.begin().await?;
+
+    validate_new_username(name, &mut transaction).await?;
+
+    if is_username_taken(name, &mut transaction).await? {
+        die!(CONFLICT, "Name already in use");
+    }
+
+    // Organizations can't be logged into, so their password is random and never handed out
+    let password = crypto::hash_password(crypto::random_string(32).as_str())?;
+
+    let organization: User = match sqlx::query_as::<_, User>("insert into users (username, password, organization) values ($1, $2, true) returning *")
+        .bind(name)
+        .bind(&password)
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(organization) => organization,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "Name already in use"),
+        Err(err) => return Err(err.into())
+    };
+
+    sqlx::query("insert into organization_members (organization, member, role) values ($1, $2, 'owner')")
+        .bind(&organization.id)
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("New organization created: {} (id {}) by {} (id {})", &organization.username, &organization.id, &user.username, &user.id);
+
+    Ok(HttpResponse::Created().json(OrganizationJsonResponse {
+        id: organization.id,
+        name: organization.username,
+        role: OrganizationRole::Owner,
+        created_at: organization.created_at
+    }))
+}
+
+/// Lists all organizations the current user is a member of
+#[route("/api/orgs", method = "GET", err = "json")]
+pub(crate) async fn list_organizations(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organizations: Vec<OrganizationJsonResponse> = sqlx::query_as::<_, OrganizationJsonResponse>("select users.id, users.username as name, organization_members.role, users.created_at \
+        from organization_members inner join users on users.id = organization_members.organization \
+        where organization_members.member = $1 order by lower(users.username)")
+        .bind(&user.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(organizations))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreateOrganizationJsonRequest {
+    name: String
+}
+
+#[derive(FromRow, Serialize)]
+pub(crate) struct OrganizationJsonResponse {
+    id: i32,
+    name: String,
+    role: OrganizationRole,
+    created_at: DateTime<Utc>
+}
@@ -0,0 +1,225 @@
+use crate::audit::{self, AuditEvent};
+use crate::organization::{all_teams, find_role, find_team, Team};
+use crate::privileges::repo_access::AccessLevel;
+use crate::repository::Repository;
+use crate::routes::organization::{find_managed_organization, find_member_organization, OrganizationRequest};
+use crate::user::{User, WebUser};
+use crate::utils::identifiers::is_valid;
+use crate::utils::is_unique_violation;
+use crate::{die, err};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+#[route("/api/orgs/{name}/teams", method = "GET", err = "json")]
+pub(crate) async fn list_teams(uri: web::Path<OrganizationRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_member_organization(uri.name.as_str(), &user, &mut transaction).await?;
+    let teams = all_teams(&organization, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(teams))
+}
+
+#[route("/api/orgs/{name}/teams", method = "POST", err = "json")]
+pub(crate) async fn create_team(uri: web::Path<OrganizationRequest>, body: web::Json<CreateTeamJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let name = body.name.as_str();
+
+    if name.is_empty() || name.len() > 32 || !name.chars().all(|c| is_valid(&c)) {
+        die!(BAD_REQUEST, "Team name must be between 1 and 32 characters long and may only contain a-z, 0-9, _ or -");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_managed_organization(uri.name.as_str(), &user, &mut transaction).await?;
+
+    let team: Team = match sqlx::query_as::<_, Team>("insert into teams (organization, name, access_level) values ($1, $2, $3) returning *")
+        .bind(&organization.id)
+        .bind(name)
+        .bind(&body.access_level)
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(team) => team,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "Team name already in use"),
+        Err(err) => return Err(err.into())
+    };
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) created team {} (id {}) in organization {} (id {})", &user.username, &user.id, &team.name, &team.id, &organization.username, &organization.id);
+
+    Ok(HttpResponse::Created().json(team))
+}
+
+#[route("/api/orgs/{name}/teams/{team}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_team(uri: web::Path<TeamRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_managed_organization(uri.name.as_str(), &user, &mut transaction).await?;
+    let team = find_team(&organization, uri.team.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Team not found"))?;
+
+    sqlx::query("delete from teams where id = $1")
+        .bind(&team.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) deleted team {} (id {}) in organization {} (id {})", &user.username, &user.id, &team.name, &team.id, &organization.username, &organization.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[route("/api/orgs/{name}/teams/{team}/members", method = "PUT", err = "json")]
+pub(crate) async fn put_team_member(uri: web::Path<TeamRequest>, body: web::Json<TeamMemberJsonRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_managed_organization(uri.name.as_str(), &user, &mut transaction).await?;
+    let team = find_team(&organization, uri.team.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Team not found"))?;
+
+    let member: User = User::find_using_name(body.username.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "User not found"))?;
+
+    if find_role(&organization, &member, &mut transaction).await?.is_none() {
+        die!(BAD_REQUEST, "Only members of the organization can be added to its teams");
+    }
+
+    sqlx::query("insert into team_members (team, member) values ($1, $2) on conflict do nothing")
+        .bind(&team.id)
+        .bind(&member.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    record_change(&request, &user, &organization, &team, json!({ "added_member": &member.username })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[route("/api/orgs/{name}/teams/{team}/members/{username}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_team_member(uri: web::Path<TeamMemberRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_managed_organization(uri.name.as_str(), &user, &mut transaction).await?;
+    let team = find_team(&organization, uri.team.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Team not found"))?;
+
+    let result = sqlx::query("delete from team_members where team = $1 and member = (select id from users where lower(username) = lower($2) limit 1)")
+        .bind(&team.id)
+        .bind(uri.username.as_str())
+        .execute(&mut transaction)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        die!(NOT_FOUND, "User is not a member of this team");
+    }
+
+    transaction.commit().await?;
+
+    record_change(&request, &user, &organization, &team, json!({ "removed_member": uri.username.as_str() })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Grants the team its access level on a repository of the organization
+#[route("/api/orgs/{name}/teams/{team}/repositories", method = "PUT", err = "json")]
+pub(crate) async fn put_team_repository(uri: web::Path<TeamRequest>, body: web::Json<TeamRepositoryJsonRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_managed_organization(uri.name.as_str(), &user, &mut transaction).await?;
+    let team = find_team(&organization, uri.team.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Team not found"))?;
+
+    // Teams can only be granted access to repositories owned by their organization
+    let repo = Repository::open(organization.id, body.repository.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    sqlx::query("insert into team_repositories (team, repo) values ($1, $2) on conflict do nothing")
+        .bind(&team.id)
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) granted team {} (id {}) access to {}/{} (id {})", &user.username, &user.id, &team.name, &team.id, &organization.username, &repo.name, &repo.id);
+    record_change(&request, &user, &organization, &team, json!({ "added_repository": &repo.name })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[route("/api/orgs/{name}/teams/{team}/repositories/{repository}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_team_repository(uri: web::Path<TeamRepositoryRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let organization = find_managed_organization(uri.name.as_str(), &user, &mut transaction).await?;
+    let team = find_team(&organization, uri.team.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Team not found"))?;
+    let repo = Repository::open(organization.id, uri.repository.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    let result = sqlx::query("delete from team_repositories where team = $1 and repo = $2")
+        .bind(&team.id)
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        die!(NOT_FOUND, "Team has no access to this repository");
+    }
+
+    transaction.commit().await?;
+
+    record_change(&request, &user, &organization, &team, json!({ "removed_repository": &repo.name })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn record_change(request: &HttpRequest, user: &User, organization: &User, team: &Team, details: Value) {
+    audit::record(request, AuditEvent::PermissionChanged, Some(user), Some(format!("team:{}/{}", &organization.username, &team.name)), details).await;
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TeamRequest {
+    name: String,
+    team: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TeamMemberRequest {
+    name: String,
+    team: String,
+    username: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TeamRepositoryRequest {
+    name: String,
+    team: String,
+    repository: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreateTeamJsonRequest {
+    name: String,
+    access_level: AccessLevel
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TeamMemberJsonRequest {
+    username: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TeamRepositoryJsonRequest {
+    repository: String
+}
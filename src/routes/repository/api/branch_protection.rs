@@ -0,0 +1,127 @@
+use crate::branch_protection::BranchProtection;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[route("/api/repo/{username}/{repository}/protections", method = "GET", err = "json")]
+pub(crate) async fn list_protections(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    let protections = BranchProtection::all(&repo, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(protections))
+}
+
+#[route("/api/repo/{username}/{repository}/protections", method = "POST", err = "json")]
+pub(crate) async fn create_protection(uri: web::Path<GitRequest>, body: web::Json<CreateProtectionJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to manage branch protections");
+    }
+
+    let pattern = body.pattern.trim();
+
+    if pattern.is_empty() || pattern.len() > 256 {
+        die!(BAD_REQUEST, "Pattern needs to be between 1 and 256 characters long");
+    }
+
+    let (exists,): (bool,) = sqlx::query_as("select exists(select 1 from branch_protections where repo = $1 and pattern = $2 limit 1)")
+        .bind(&repo.id)
+        .bind(pattern)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if exists {
+        die!(CONFLICT, "Branch protection for this pattern already exists");
+    }
+
+    let protection = sqlx::query_as::<_, BranchProtection>("insert into branch_protections (repo, pattern, no_force_push, no_delete, require_signed, allow_admin_force_push) values ($1, $2, $3, $4, $5, $6) returning *")
+        .bind(&repo.id)
+        .bind(pattern)
+        .bind(body.no_force_push.unwrap_or(true))
+        .bind(body.no_delete.unwrap_or(true))
+        .bind(body.require_signed.unwrap_or(false))
+        .bind(body.allow_admin_force_push.unwrap_or(false))
+        .fetch_one(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("Branch protection {} ({}) created for repo {} by user {}", &protection.id, &protection.pattern, &repo.id, &user.id);
+
+    Ok(HttpResponse::Created().json(protection))
+}
+
+#[route("/api/repo/{username}/{repository}/protections/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_protection(uri: web::Path<ProtectionRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to manage branch protections");
+    }
+
+    sqlx::query("delete from branch_protections where id = $1 and repo = $2 returning id")
+        .bind(&uri.id)
+        .bind(&repo.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Branch protection not found"))?;
+
+    transaction.commit().await?;
+
+    debug!("Branch protection {} removed from repo {} by user {}", &uri.id, &repo.id, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ProtectionRequest {
+    username: String,
+    repository: String,
+    id: i32
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreateProtectionJsonRequest {
+    pattern: String,
+    no_force_push: Option<bool>,
+    no_delete: Option<bool>,
+    require_signed: Option<bool>,
+    allow_admin_force_push: Option<bool>
+}
@@ -0,0 +1,152 @@
+use crate::audit::{self, AuditEvent};
+use crate::privileges::privilege;
+use crate::privileges::repo_access::AccessLevel;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+
+/// Lists the collaborators of a repository. The owner is not part of the list as it always has admin access implicitly
+#[route("/api/repo/{username}/{repository}/collaborators", method = "GET", err = "json")]
+pub(crate) async fn list_collaborators(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let (_, repo) = find_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    if !privilege::check_push(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only collaborators with push access are allowed to view the collaborators");
+    }
+
+    let collaborators: Vec<Collaborator> = sqlx::query_as::<_, Collaborator>("select users.username, privileges.access_level from privileges \
+        inner join users on users.id = privileges.user_id where privileges.repo_id = $1 order by lower(users.username)")
+        .bind(&repo.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(collaborators))
+}
+
+/// Adds a collaborator to the repository or changes the access level of an existing one
+#[route("/api/repo/{username}/{repository}/collaborators/{collaborator}", method = "PUT", err = "json")]
+pub(crate) async fn put_collaborator(uri: web::Path<CollaboratorRequest>, body: web::Json<CollaboratorJsonRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let (repo_owner, repo) = find_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to manage collaborators");
+    }
+
+    let collaborator = find_collaborator(uri.collaborator.as_str(), &repo, &mut transaction).await?;
+
+    if collaborator.organization {
+        die!(BAD_REQUEST, "Organizations cannot be added as collaborators");
+    }
+
+    sqlx::query("insert into privileges (user_id, repo_id, access_level) values ($1, $2, $3) \
+        on conflict (user_id, repo_id) do update set access_level = excluded.access_level")
+        .bind(&collaborator.id)
+        .bind(&repo.id)
+        .bind(&body.access_level)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) set access level of {} (id {}) in repo {} to {}", &user.username, &user.id, &collaborator.username, &collaborator.id, &repo.id, &body.access_level);
+    audit::record(&request, AuditEvent::PermissionChanged, Some(&user), Some(format!("repo:{}/{}", &repo_owner.username, &repo.name)), json!({
+        "collaborator": &collaborator.username,
+        "access_level": &body.access_level
+    })).await;
+
+    Ok(HttpResponse::Ok().json(Collaborator {
+        username: collaborator.username,
+        access_level: body.into_inner().access_level
+    }))
+}
+
+/// Removes a collaborator from the repository. Collaborators may remove themselves, everybody else has to be removed by an admin
+#[route("/api/repo/{username}/{repository}/collaborators/{collaborator}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_collaborator(uri: web::Path<CollaboratorRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let (repo_owner, repo) = find_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+    let collaborator = find_collaborator(uri.collaborator.as_str(), &repo, &mut transaction).await?;
+
+    if collaborator.id != user.id && !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to manage collaborators");
+    }
+
+    let result = sqlx::query("delete from privileges where user_id = $1 and repo_id = $2")
+        .bind(&collaborator.id)
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        die!(NOT_FOUND, "User is not a collaborator of this repository");
+    }
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) removed {} (id {}) from repo {}", &user.username, &user.id, &collaborator.username, &collaborator.id, &repo.id);
+    audit::record(&request, AuditEvent::PermissionChanged, Some(&user), Some(format!("repo:{}/{}", &repo_owner.username, &repo.name)), json!({
+        "collaborator": &collaborator.username,
+        "access_level": null
+    })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn find_repo(username: &str, repository: &str, user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<(User, Repository)> {
+    let repo_owner = User::find_using_name(username, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner.id, repository, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(user), &mut *transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    Ok((repo_owner, repo))
+}
+
+/// Looks up the user `username` refers to. The owner always has admin access, so it can't be added, demoted or removed
+async fn find_collaborator(username: &str, repo: &Repository, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    let collaborator = User::find_using_name(username, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "User not found"))?;
+
+    if collaborator.id == repo.owner {
+        die!(BAD_REQUEST, "The owner always has admin access to the repository");
+    }
+
+    Ok(collaborator)
+}
+
+#[derive(FromRow, Serialize)]
+struct Collaborator {
+    username: String,
+    access_level: AccessLevel
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CollaboratorRequest {
+    username: String,
+    repository: String,
+    collaborator: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CollaboratorJsonRequest {
+    access_level: AccessLevel
+}
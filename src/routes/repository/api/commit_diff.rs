@@ -0,0 +1,44 @@
+use crate::config::get_setting;
+use crate::git::diff;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[route("/api/repo/{username}/{repository}/commits/{sha}/diff", method = "GET", err = "json")]
+pub(crate) async fn get_commit_diff(uri: web::Path<CommitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    let max_diff_size = get_setting::<i32, _>("repositories.max_diff_size", &mut transaction).await?;
+    let libgit2_repo = repo.libgit2(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let commit = libgit2_repo.revparse_single(uri.sha.as_str())
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| err!(NOT_FOUND, "Commit not found"))?;
+
+    let diff = diff::commit_diff(&libgit2_repo, commit.id(), max_diff_size.max(0) as usize)?;
+
+    Ok(HttpResponse::Ok().json(diff))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CommitRequest {
+    username: String,
+    repository: String,
+    sha: String
+}
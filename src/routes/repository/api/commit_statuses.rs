@@ -0,0 +1,115 @@
+use crate::access_token::TokenScopes;
+use crate::commit_status::{self, CommitState, CommitStatus};
+use crate::git::basic_auth;
+use crate::privileges::privilege;
+use crate::routes::repository::api::issues::find_repo;
+use crate::user::WebUser;
+use crate::{die, err};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use git2::Oid;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+#[route("/api/repo/{username}/{repository}/statuses/{sha}", method = "GET", err = "json")]
+pub(crate) async fn get_statuses(uri: web::Path<CommitStatusRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let statuses = CommitStatus::latest(&repo, uri.sha.to_lowercase().as_str(), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(CombinedStatusJsonResponse {
+        state: commit_status::combined_state(statuses.as_slice()),
+        statuses
+    }))
+}
+
+/// Reports a status for a commit. Meant to be called by CI systems, so besides a session an access token with the
+/// `write_repository` scope is accepted using Basic or Bearer auth
+#[route("/api/repo/{username}/{repository}/statuses/{sha}", method = "POST", err = "json")]
+pub(crate) async fn create_status(uri: web::Path<CommitStatusRequest>, body: web::Json<CreateStatusJsonRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let user = match web_user {
+        WebUser::Authenticated(user) => user,
+        WebUser::Anonymous => basic_auth::authenticate(&request, TokenScopes::WRITE_REPOSITORY, &mut transaction).await?
+    };
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !privilege::check_push(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Reporting commit statuses requires push access");
+    }
+
+    let sha = uri.sha.to_lowercase();
+    let oid = Oid::from_str(sha.as_str()).ok().filter(|_| sha.len() == 40).ok_or_else(|| err!(BAD_REQUEST, "Commit needs to be a full 40 character SHA"))?;
+
+    if repo.libgit2(&mut transaction).await?.find_commit(oid).is_err() {
+        die!(NOT_FOUND, "Commit not found");
+    }
+
+    let context = body.context.as_deref().map(str::trim).filter(|context| !context.is_empty()).unwrap_or("default");
+
+    if context.len() > 256 {
+        die!(BAD_REQUEST, "Context may only be up to 256 characters long");
+    }
+
+    if let Some(target_url) = body.target_url.as_deref() {
+        if target_url.len() > 2048 || !(target_url.starts_with("https://") || target_url.starts_with("http://")) {
+            die!(BAD_REQUEST, "Target url needs to be a http(s) url of up to 2048 characters");
+        }
+    }
+
+    if body.description.as_deref().map_or(false, |description| description.len() > 1024) {
+        die!(BAD_REQUEST, "Description may only be up to 1024 characters long");
+    }
+
+    let status = sqlx::query_as::<_, CommitStatus>("insert into commit_statuses (repo, sha, context, state, target_url, description, creator) \
+        values ($1, $2, $3, $4, $5, $6, $7) returning *")
+        .bind(&repo.id)
+        .bind(sha.as_str())
+        .bind(context)
+        .bind(&body.state)
+        .bind(body.target_url.as_deref())
+        .bind(body.description.as_deref())
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("Status {} reported for commit {} in repo {} by user {}", &status, &status.sha, &repo.id, &user.id);
+
+    Ok(HttpResponse::Created().json(status))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CommitStatusRequest {
+    username: String,
+    repository: String,
+    sha: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreateStatusJsonRequest {
+    state: CommitState,
+    /// Defaults to `default`
+    #[serde(default)]
+    context: Option<String>,
+    #[serde(default)]
+    target_url: Option<String>,
+    #[serde(default)]
+    description: Option<String>
+}
+
+#[derive(Serialize)]
+pub(crate) struct CombinedStatusJsonResponse {
+    /// Worst state of all contexts, `null` if no status has been reported
+    state: Option<CommitState>,
+    statuses: Vec<CommitStatus>
+}
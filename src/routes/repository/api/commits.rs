@@ -0,0 +1,91 @@
+use crate::commit_status;
+use crate::git::history::all_commits;
+use crate::prelude::*;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::templates::web::GitCommit;
+use crate::user::{User, WebUser};
+use crate::utils::pagination::{Page, PageQuery};
+use crate::{die, err};
+
+use actix_web::{HttpRequest, Responder, web};
+use anyhow::Result;
+use git2::Oid;
+use gitarena_macros::route;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+/// Commit history of `ref` (the default branch if omitted), newest first. The cursor is the SHA of the last commit of
+/// the previous page, so pages stay the same even if new commits are pushed while paginating
+#[route("/api/repo/{username}/{repository}/commits", method = "GET", err = "json")]
+pub(crate) async fn list_commits(uri: web::Path<GitRequest>, page: web::Query<PageQuery>, query: web::Query<CommitListQuery>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    let limit = page.limit(&mut transaction).await?;
+    let libgit2_repo = repo.libgit2(&mut transaction).await?;
+
+    let start = match page.after.as_deref() {
+        Some(after) => Oid::from_str(after)
+            .ok()
+            .and_then(|oid| libgit2_repo.find_commit(oid).ok())
+            .ok_or_else(|| err!(BAD_REQUEST, "Invalid cursor"))?
+            .id(),
+        None => {
+            let target = query.reference.as_deref().unwrap_or(repo.default_branch.as_str());
+
+            libgit2_repo.revparse_single(target)
+                .and_then(|object| object.peel_to_commit())
+                .map_err(|_| err!(NOT_FOUND, "Ref {} not found", target))?
+                .id()
+        }
+    };
+
+    // The commit the cursor points to has already been part of the previous page
+    let skip = page.after.is_some() as usize;
+
+    let commit_ids = all_commits(&libgit2_repo, start.to_string().as_str(), skip + limit + 1).await?;
+    let git2_commits = commit_ids.into_iter().skip(skip).map(|oid| libgit2_repo.find_commit(oid)).collect::<Result<Vec<_>, _>>()?;
+
+    let author_emails = git2_commits.iter().filter_map(|commit| commit.author().email().map(str::to_owned)).collect::<Vec<_>>();
+    let authors = User::find_using_emails(author_emails.as_slice(), &mut transaction).await?;
+
+    let shas = git2_commits.iter().map(|commit| commit.id().to_string()).collect::<Vec<_>>();
+    let statuses = commit_status::combined_states(&repo, shas.as_slice(), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let commits = git2_commits.into_iter().map(|commit| {
+        let oid = commit.id().to_string();
+        let (name, uid, email) = commit.author().disassemble_with(&authors);
+
+        GitCommit {
+            status: statuses.get(&oid).copied(),
+            oid,
+            message: commit.message().unwrap_or_default().to_owned(),
+            time: commit.time().seconds(),
+            date: None,
+            author_name: name,
+            author_uid: uid,
+            author_email: email,
+            verification: None
+        }
+    }).collect::<Vec<_>>();
+
+    let page = Page::new(commits, limit, |commit| commit.oid.clone());
+
+    Ok(page.respond(&request, limit))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CommitListQuery {
+    #[serde(rename = "ref")]
+    reference: Option<String>
+}
@@ -1,22 +1,41 @@
-use crate::config::get_optional_setting;
-use crate::die;
+use crate::activity::{self, ActivityKind};
+use crate::config::{get_optional_setting, get_setting};
 use crate::git::write;
+use crate::git::utils::is_valid_branch_name;
+use crate::organization::{find_organization, find_role};
 use crate::prelude::HttpRequestExtensions;
 use crate::privileges::repo_visibility::RepoVisibility;
 use crate::repository::Repository;
-use crate::routes::repository::api::CreateJsonResponse;
-use crate::user::{User, WebUser};
+use crate::routes::repository::api::{validate_description, CreateJsonResponse};
+use crate::templates::init;
+use crate::user::WebUser;
 use crate::utils::identifiers::{is_fs_legal, is_reserved_repo_name, is_valid};
+use crate::{die, err};
 
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
-use sqlx::{PgPool, Pool, Postgres};
+use sqlx::PgPool;
 use anyhow::Result;
 use gitarena_macros::route;
 use serde::Deserialize;
+use serde_json::json;
 use log::info;
+use utoipa::Component;
 
 // This whole handler is very similar to `import_repo.rs` so at some point this should be consolidated into one
 
+#[utoipa::path(
+    post,
+    path = "/api/repo",
+    request_body = CreateJsonRequest,
+    responses(
+        (status = 200, description = "Repository has been created", body = CreateJsonResponse),
+        (status = 400, description = "Invalid name or description"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Organization not found"),
+        (status = 409, description = "Name already in use")
+    ),
+    tag = "repository"
+)]
 #[route("/api/repo", method = "POST", err = "json")]
 pub(crate) async fn create(web_user: WebUser, body: web::Json<CreateJsonRequest>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let mut transaction = db_pool.begin().await?;
@@ -39,41 +58,92 @@ pub(crate) async fn create(web_user: WebUser, body: web::Json<CreateJsonRequest>
 
     let description = &body.description;
 
-    if description.len() > 256 {
-        die!(BAD_REQUEST, "Description may only be up to 256 characters long");
+    validate_description(description.as_str())?;
+
+    let default_branch = match body.default_branch.as_deref().map(str::trim).filter(|branch| !branch.is_empty()) {
+        Some(branch) => branch.to_owned(),
+        None => get_setting::<String, _>("repositories.default_branch", &mut transaction).await?
+    };
+
+    if !is_valid_branch_name(default_branch.as_str()) {
+        die!(BAD_REQUEST, "Invalid default branch name");
     }
 
+    let gitignore = match body.gitignore.as_deref().filter(|name| !name.is_empty()) {
+        Some(name) => Some(init::gitignore(name).await?.ok_or_else(|| err!(BAD_REQUEST, "Unknown .gitignore template {}", name))?),
+        None => None
+    };
+
+    // Repositories can be created in organizations by all of its members, otherwise they're owned by the creator
+    let organization = match &body.owner {
+        Some(owner_name) => {
+            let organization = find_organization(owner_name.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Organization not found"))?;
+
+            if find_role(&organization, &user, &mut transaction).await?.is_none() {
+                die!(NOT_FOUND, "Organization not found");
+            }
+
+            Some(organization)
+        }
+        None => None
+    };
+    let owner = organization.as_ref().unwrap_or(&user);
+
     let (exists,): (bool,) = sqlx::query_as("select exists(select 1 from repositories where owner = $1 and lower(name) = lower($2) limit 1)")
-        .bind(&user.id)
+        .bind(&owner.id)
         .bind(&name)
         .fetch_one(&mut transaction)
         .await?;
 
     if exists {
-        die!(CONFLICT, "Repository name already in use for your account");
+        die!(CONFLICT, "Repository name already in use for this account");
     }
 
-    let repo: Repository = sqlx::query_as::<_, Repository>("insert into repositories (owner, name, description, visibility) values ($1, $2, $3, $4) returning *")
-        .bind(&user.id)
+    // The license is filled in with the name of the owner, so it can only be looked up once the owner is known
+    let license = match body.license.as_deref().filter(|spdx_id| !spdx_id.is_empty()) {
+        Some(spdx_id) => Some(init::license(spdx_id, owner.username.as_str()).await?.ok_or_else(|| err!(BAD_REQUEST, "Unknown license {}", spdx_id))?),
+        None => None
+    };
+
+    let repo: Repository = sqlx::query_as::<_, Repository>("insert into repositories (owner, name, description, visibility, default_branch) values ($1, $2, $3, $4, $5) returning *")
+        .bind(&owner.id)
         .bind(name)
         .bind(description)
         .bind(&body.visibility)
+        .bind(&default_branch)
         .fetch_one(&mut transaction)
         .await?;
 
     repo.create_fs(&mut transaction).await?;
 
-    // Can be simplified once let chains are implemented: https://github.com/rust-lang/rust/issues/53667
-    if body.readme.is_some() {
-        create_readme(&repo, &user, &db_pool).await?;
+    activity::record(&user, &repo, ActivityKind::Repository, json!({}), &mut transaction).await?;
+
+    let readme = body.readme.is_some().then(|| format!("# {}\n\n{}\n", repo.name.as_str(), repo.description.as_str()));
+    let mut files = Vec::<(&str, &[u8])>::new();
+
+    if let Some(readme) = readme.as_deref() {
+        files.push(("README.md", readme.as_bytes()));
+    }
+
+    if let Some(gitignore) = gitignore.as_deref() {
+        files.push((".gitignore", gitignore.as_bytes()));
+    }
+
+    if let Some(license) = license.as_deref() {
+        files.push(("LICENSE", license.as_bytes()));
+    }
+
+    if !files.is_empty() {
+        let libgit2_repo = repo.libgit2(&mut transaction).await?;
+        write::write_initial_commit(&libgit2_repo, repo.default_branch.as_str(), files.as_slice(), &mut transaction).await?;
     }
 
     let domain = get_optional_setting::<String, _>("domain", &mut transaction).await?.unwrap_or_default();
-    let path = format!("/{}/{}", &user.username, &repo.name);
+    let path = format!("/{}/{}", &owner.username, &repo.name);
 
     transaction.commit().await?;
 
-    info!("New repository created: {}/{} (id {})", &user.username, &repo.name, &repo.id);
+    info!("New repository created: {}/{} (id {}) by {} (id {})", &owner.username, &repo.name, &repo.id, &user.username, &user.id);
 
     Ok(if request.get_header("hx-request").is_some() {
         HttpResponse::Ok().append_header(("hx-redirect", path)).append_header(("hx-refresh", "true")).finish()
@@ -87,21 +157,23 @@ pub(crate) async fn create(web_user: WebUser, body: web::Json<CreateJsonRequest>
     })
 }
 
-async fn create_readme(repo: &Repository, user: &User, db_pool: &Pool<Postgres>) -> Result<()> {
-    let mut transaction = db_pool.begin().await?;
-    let libgit2_repo = repo.libgit2(&mut transaction).await?;
-    let readme = format!("# {}\n\n{}\n", repo.name.as_str(), repo.description.as_str());
-
-    transaction.commit().await?;
-
-    write::write_file(&libgit2_repo, user, Some("HEAD"), "README.md", readme.as_bytes(), db_pool).await
-}
-
-#[derive(Deserialize)]
+#[derive(Deserialize, Component)]
 pub(crate) struct CreateJsonRequest {
     name: String,
     description: String,
     visibility: RepoVisibility,
     #[serde(default)]
-    readme: Option<String>
+    readme: Option<String>,
+    /// Name of the `.gitignore` template to initialize the repository with
+    #[serde(default)]
+    gitignore: Option<String>,
+    /// SPDX identifier of the license to initialize the repository with
+    #[serde(default)]
+    license: Option<String>,
+    /// Defaults to the `repositories.default_branch` setting
+    #[serde(default)]
+    default_branch: Option<String>,
+    /// Name of the organization the repository should be created in
+    #[serde(default)]
+    owner: Option<String>
 }
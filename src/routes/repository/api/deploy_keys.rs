@@ -0,0 +1,181 @@
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::ssh::{self, DeployKey, ParsedKey};
+use crate::user::{User, WebUser};
+use crate::utils::is_unique_violation;
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use utoipa::Component;
+
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}/deploy_keys",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Deploy keys of the repository, newest first"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not a repository admin"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/deploy_keys", method = "GET", err = "json")]
+pub(crate) async fn list_deploy_keys(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_administrated_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    let keys: Vec<DeployKey> = sqlx::query_as::<_, DeployKey>("select * from deploy_keys where repo = $1 order by created_at desc")
+        .bind(&repo.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/repo/{username}/{repository}/deploy_keys",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = AddDeployKeyJsonRequest,
+    responses(
+        (status = 201, description = "Deploy key has been added", body = AddDeployKeyJsonResponse),
+        (status = 400, description = "Invalid SSH public key"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not a repository admin"),
+        (status = 404, description = "Repository not found"),
+        (status = 409, description = "SSH key already exists")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/deploy_keys", method = "POST", err = "json")]
+pub(crate) async fn add_deploy_key(uri: web::Path<GitRequest>, body: web::Json<AddDeployKeyJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let parsed = ParsedKey::parse(body.key.as_str(), body.title.as_str())?;
+
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_administrated_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    if ssh::key_in_use(parsed.fingerprint.as_str(), &mut transaction).await? {
+        die!(CONFLICT, "SSH key already exists");
+    }
+
+    let key = match sqlx::query_as::<_, DeployKey>("insert into deploy_keys (repo, title, fingerprint, algorithm, key, read_only) values ($1, $2, $3, $4, $5, $6) returning *")
+        .bind(&repo.id)
+        .bind(parsed.title.as_str())
+        .bind(parsed.fingerprint.as_str())
+        .bind(parsed.algorithm)
+        .bind(parsed.key.as_slice())
+        .bind(&body.read_only)
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(key) => key,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "SSH key already exists"),
+        Err(err) => return Err(err.into())
+    };
+
+    transaction.commit().await?;
+
+    debug!("Deploy key {} added to repo {} by user {} (fingerprint: {}, read only: {})", &key.id, &repo.id, &user.id, &key.fingerprint, &key.read_only);
+
+    Ok(HttpResponse::Created().json(AddDeployKeyJsonResponse {
+        id: key.id,
+        fingerprint: key.fingerprint
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/repo/{username}/{repository}/deploy_keys/{id}",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository"),
+        ("id" = i32, path, description = "Id of the deploy key")
+    ),
+    responses(
+        (status = 204, description = "Deploy key has been removed"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not a repository admin"),
+        (status = 404, description = "Repository or deploy key not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/deploy_keys/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_deploy_key(uri: web::Path<DeployKeyRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_administrated_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    sqlx::query("delete from deploy_keys where id = $1 and repo = $2 returning id")
+        .bind(&uri.id)
+        .bind(&repo.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Deploy key not found"))?;
+
+    transaction.commit().await?;
+
+    debug!("Deploy key {} removed from repo {} by user {}", &uri.id, &repo.id, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn find_administrated_repo(username: &str, repository: &str, user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<Repository> {
+    let repo_owner = User::find_using_name(username, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, repository, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(user), &mut *transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(user), &mut *transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to manage deploy keys");
+    }
+
+    Ok(repo)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeployKeyRequest {
+    username: String,
+    repository: String,
+    id: i32
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct AddDeployKeyJsonRequest {
+    #[serde(default)]
+    title: String,
+    key: String,
+    /// Keys are read-only unless explicitly allowed to push
+    #[serde(default = "default_read_only")]
+    read_only: bool
+}
+
+fn default_read_only() -> bool {
+    true
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct AddDeployKeyJsonResponse {
+    id: i32,
+    fingerprint: String
+}
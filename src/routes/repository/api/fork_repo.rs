@@ -1,6 +1,8 @@
+use crate::activity::{self, ActivityKind};
 use crate::config::get_optional_setting;
 use crate::prelude::HttpRequestExtensions;
 use crate::privileges::privilege;
+use crate::quota;
 use crate::repository::Repository;
 use crate::routes::repository::api::CreateJsonResponse;
 use crate::routes::repository::GitRequest;
@@ -12,6 +14,7 @@ use std::path::Path;
 
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::{Context, Result};
+use git2::build::{CloneLocal, RepoBuilder};
 use gitarena_macros::route;
 use log::info;
 use serde_json::json;
@@ -83,19 +86,39 @@ pub(crate) async fn create_fork(uri: web::Path<GitRequest>, web_user: WebUser, r
         die!(CONFLICT, "Repository name already in use for your account");
     }
 
-    let new_repo = sqlx::query_as::<_, Repository>("insert into repositories (owner, name, description, visibility, forked_from) values ($1, $2, $3, $4, $5) returning *")
+    // The clone keeps the HEAD of the original, so the default branch needs to match it
+    let mut new_repo = sqlx::query_as::<_, Repository>("insert into repositories (owner, name, description, visibility, forked_from, default_branch) values ($1, $2, $3, $4, $5, $6) returning *")
         .bind(&user.id)
         .bind(&repo.name)
         .bind(&repo.description)
         .bind(&repo.visibility)
         .bind(&repo.id)
+        .bind(&repo.default_branch)
         .fetch_one(&mut transaction)
         .await?;
 
+    // Keeps the original from being moved while it is being cloned
+    let _fs_lock = repo.lock_fs_shared().await;
+
+    // The fork starts out as large as the original, which counts towards the quota of the user forking it
+    quota::check_push(&new_repo, repo.repo_size_bytes.max(0) as u64, &mut transaction).await?;
+
     let old_path = repo.get_fs_path(&mut transaction).await?;
     let new_path = new_repo.get_fs_path(&mut transaction).await?;
 
-    copy_dir_all(Path::new(old_path.as_str()), Path::new(new_path.as_str())).await.context("Failed to copy repository")?;
+    let (source, destination) = (old_path.clone(), new_path.clone());
+    web::block(move || clone_local(Path::new(source.as_str()), Path::new(destination.as_str()))).await?.context("Failed to clone repository")?;
+
+    // LFS objects are not part of the Git object database and thus need to be copied separately
+    let old_lfs_path = Path::new(old_path.as_str()).join("lfs").join("objects");
+
+    if old_lfs_path.is_dir() {
+        copy_dir_all(old_lfs_path, Path::new(new_path.as_str()).join("lfs").join("objects")).await.context("Failed to copy LFS objects")?;
+    }
+
+    new_repo.update_size(&mut transaction).await?;
+
+    activity::record(&user, &new_repo, ActivityKind::Repository, json!({ "forked_from": &repo.id }), &mut transaction).await?;
 
     let domain = get_optional_setting::<String, _>("domain", &mut transaction).await?.unwrap_or_default();
     let url = format!("{}/{}/{}", domain, user.username, new_repo.name);
@@ -114,3 +137,20 @@ pub(crate) async fn create_fork(uri: web::Path<GitRequest>, web_user: WebUser, r
         })
     })
 }
+
+/// Clones the bare repository at `source` into `destination` without going through the Git transport.
+/// Objects are hard linked if possible so forks don't duplicate every object on disk
+fn clone_local(source: &Path, destination: &Path) -> Result<()> {
+    let mut builder = RepoBuilder::new();
+
+    builder.bare(true);
+    builder.clone_local(CloneLocal::Local);
+
+    // Mirror all refs (branches, tags, notes...) instead of creating remote tracking branches
+    builder.remote_create(|repo, name, url| repo.remote_with_fetch(name, url, "+refs/*:refs/*"));
+
+    let fork = builder.clone(source.to_string_lossy().as_ref(), destination)?;
+    fork.remote_delete("origin")?;
+
+    Ok(())
+}
@@ -2,19 +2,24 @@ use crate::config::{get_optional_setting, get_setting};
 use crate::prelude::HttpRequestExtensions;
 use crate::privileges::repo_visibility::RepoVisibility;
 use crate::repository::Repository;
-use crate::routes::repository::api::CreateJsonResponse;
+use crate::routes::repository::api::{validate_description, CreateJsonResponse};
+use crate::routes::repository::api::issues::find_repo;
 use crate::user::WebUser;
 use crate::utils::identifiers::{is_fs_legal, is_reserved_repo_name, is_valid};
-use crate::{die, err, Ipc};
+use crate::utils::outbound_url;
+use crate::{die, err, quota, Ipc};
 
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::{Context, Result};
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
 use futures_locks::RwLock;
+use gitarena_common::database::models::ImportState;
 use gitarena_common::packets::git::GitImport;
 use gitarena_macros::route;
-use log::info;
-use serde::Deserialize;
-use sqlx::PgPool;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
 use url::Url;
 
 // This whole handler is very similar to `create_repo.rs` so at some point this should be consolidated into one
@@ -46,16 +51,46 @@ pub(crate) async fn import(web_user: WebUser, body: web::Json<ImportJsonRequest>
 
     let description = &body.description;
 
-    if description.len() > 256 {
-        die!(BAD_REQUEST, "Description may only be up to 256 characters long");
-    }
+    validate_description(description.as_str())?;
 
-    let url = Url::parse(body.import_url.as_str()).map_err(|_| err!(BAD_REQUEST, "Unable to parse import url"))?;
+    let mut url = Url::parse(body.import_url.as_str()).map_err(|_| err!(BAD_REQUEST, "Unable to parse import url"))?;
 
-    if body.mirror.is_some() {
-        die!(NOT_IMPLEMENTED, "Mirroring is not yet implemented");
+    if !matches!(url.scheme(), "http" | "https" | "git") {
+        die!(BAD_REQUEST, "Only http, https and git urls can be imported");
     }
 
+    outbound_url::check(&url).await?;
+
+    // Credentials embedded into the url would be stored in the import job, so move them out of it
+    let username = body.username.clone().or_else(|| Some(url.username().to_owned()).filter(|username| !username.is_empty()));
+    let password = body.password.clone().or_else(|| url.password().map(str::to_owned));
+
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+
+    let mirror_interval = match body.mirror {
+        Some(_) => {
+            // Credentials are only passed to the workhorse for the initial import and never stored
+            if username.is_some() || password.is_some() {
+                die!(BAD_REQUEST, "Mirroring repositories which require credentials is not supported");
+            }
+
+            if url.as_str().len() > 256 {
+                die!(BAD_REQUEST, "Mirror url may only be up to 256 characters long");
+            }
+
+            let min_interval = get_setting::<i32, _>("repositories.mirror_min_interval", &mut transaction).await?;
+            let interval = body.mirror_interval.unwrap_or(3600);
+
+            if interval < min_interval {
+                die!(BAD_REQUEST, "Mirror interval needs to be at least {} seconds", min_interval);
+            }
+
+            Some(interval)
+        }
+        None => None
+    };
+
     let (exists,): (bool,) = sqlx::query_as("select exists(select 1 from repositories where owner = $1 and lower(name) = lower($2) limit 1)")
         .bind(&user.id)
         .bind(&name)
@@ -66,31 +101,66 @@ pub(crate) async fn import(web_user: WebUser, body: web::Json<ImportJsonRequest>
         die!(CONFLICT, "Repository name already in use for your account");
     }
 
-    let repo: Repository = sqlx::query_as::<_, Repository>("insert into repositories (owner, name, description, visibility) values ($1, $2, $3, $4) returning *")
+    let default_branch = get_setting::<String, _>("repositories.default_branch", &mut transaction).await?;
+
+    let repo: Repository = sqlx::query_as::<_, Repository>("insert into repositories (owner, name, description, visibility, mirrored_from, default_branch) values ($1, $2, $3, $4, $5, $6) returning *")
         .bind(&user.id)
         .bind(name)
         .bind(description)
         .bind(&body.visibility)
+        .bind(mirror_interval.map(|_| url.as_str()))
+        .bind(&default_branch)
         .fetch_one(&mut transaction)
         .await?;
 
     repo.create_fs(&mut transaction).await?;
 
+    let (job,): (i32,) = sqlx::query_as("insert into import_jobs (repo, url) values ($1, $2) returning id")
+        .bind(&repo.id)
+        .bind(url.as_str())
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if let Some(interval) = mirror_interval {
+        // The import itself is the first sync
+        sqlx::query("insert into mirror_settings (repo, interval, next_sync_at) values ($1, $2, now() + $2 * interval '1 second')")
+            .bind(&repo.id)
+            .bind(&interval)
+            .execute(&mut transaction)
+            .await?;
+    }
+
+    let max_size = get_setting::<i32, _>("repositories.max_push_size", &mut transaction).await?.max(0) as u64;
+    let max_size = quota::remaining(&repo, &mut transaction).await?.map_or(max_size, |remaining| remaining.min(max_size));
+
     // Currently, only Git importing is supported. TODO: Support other VCS as well as GitLab export
     // At some point it is also planned to import issues and such, requiring support for specific hosters such as GitHub, GitLab, BitBucket and Gitea
     let packet = GitImport {
+        job,
         url: url.to_string(),
-        username: body.username.clone(),
-        password: body.password.clone()
+        username,
+        password,
+        path: repo.get_fs_path(&mut transaction).await?,
+        max_size
     };
 
-    ipc.write().await.send(packet).await.context("Failed to send import packet to workhorse")?;
-
     let domain = get_optional_setting::<String, _>("domain", &mut transaction).await?.unwrap_or_default();
     let path = format!("/{}/{}", &user.username, &repo.name);
 
     transaction.commit().await?;
 
+    // The workhorse updates the job, so it may only be sent after it has been committed
+    if let Err(err) = ipc.write().await.send(packet).await.context("Failed to send import packet to workhorse") {
+        warn!("{:?}", err);
+
+        sqlx::query("update import_jobs set state = 'failed', error = 'Failed to start import' where id = $1")
+            .bind(&job)
+            .execute(db_pool.get_ref())
+            .await?;
+
+        return Err(err);
+    }
+
     info!("New repository created for importing: {}/{} (id {}) (source: {})", &user.username, &repo.name, &repo.id, url);
 
     Ok(if request.get_header("hx-request").is_some() {
@@ -105,6 +175,45 @@ pub(crate) async fn import(web_user: WebUser, body: web::Json<ImportJsonRequest>
     })
 }
 
+/// Returns the latest import of a repository, allowing to poll its progress
+#[route("/api/repo/{username}/{repository}/import", method = "GET", err = "json")]
+pub(crate) async fn import_status(uri: web::Path<ImportStatusRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+
+    let job = sqlx::query_as::<_, ImportJob>("select * from import_jobs where repo = $1 order by id desc limit 1")
+        .bind(&repo.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Repository has not been imported"))?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(job))
+}
+
+#[derive(FromRow, Serialize)]
+pub(crate) struct ImportJob {
+    id: i32,
+    repo: i32,
+    url: String,
+    state: ImportState,
+    /// Percentage from 0 to 100
+    progress: i16,
+    error: Option<String>,
+    #[serde(with = "ts_seconds")]
+    created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    updated_at: DateTime<Utc>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ImportStatusRequest {
+    username: String,
+    repository: String
+}
+
 #[derive(Deserialize)]
 pub(crate) struct ImportJsonRequest {
     //owner: String,
@@ -112,8 +221,12 @@ pub(crate) struct ImportJsonRequest {
     description: String,
     #[serde(rename = "url")]
     import_url: String,
+    /// Imports the repository as pull mirror if set, regardless of the value (as HTML checkboxes send `on`)
     #[serde(default)]
     mirror: Option<String>,
+    /// Seconds between two mirror syncs, defaults to one hour
+    #[serde(default)]
+    mirror_interval: Option<i32>,
     visibility: RepoVisibility,
 
     #[serde(default)]
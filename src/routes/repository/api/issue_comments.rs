@@ -0,0 +1,226 @@
+use crate::issue::{self, Issue, IssueComment};
+use crate::markdown::{self, LinkTarget};
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::api::issues::{find_repo, find_visible_issue};
+use crate::user::{WebUser, GHOST_USERNAME};
+use crate::{die, err};
+
+use std::collections::HashMap;
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[route("/api/repo/{username}/{repository}/issues/{index}/comments", method = "GET", err = "json")]
+pub(crate) async fn list_comments(uri: web::Path<IssueCommentsRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let issue = find_visible_issue(&repo, uri.index, web_user.as_ref(), &mut transaction).await?;
+
+    let comments: Vec<IssueComment> = sqlx::query_as::<_, IssueComment>("select * from issue_comments where issue = $1 order by id")
+        .bind(&issue.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    let author_ids = comments.iter().filter_map(|comment| comment.author).collect::<Vec<_>>();
+    let authors: HashMap<i32, String> = sqlx::query_as::<_, (i32, String)>("select id, username from users where id = any($1)")
+        .bind(&author_ids)
+        .fetch_all(&mut transaction)
+        .await?
+        .into_iter()
+        .collect();
+
+    transaction.commit().await?;
+
+    let comments = comments.into_iter()
+        .map(|comment| {
+            let author_name = comment.author
+                .and_then(|author| authors.get(&author).cloned())
+                .unwrap_or_else(|| GHOST_USERNAME.to_owned());
+            CommentJsonResponse::new(comment, author_name, &repo, uri.username.as_str())
+        })
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[route("/api/repo/{username}/{repository}/issues/{index}/comments", method = "POST", err = "json")]
+pub(crate) async fn create_comment(uri: web::Path<IssueCommentsRequest>, body: web::Json<CommentJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+    let issue = find_visible_issue(&repo, uri.index, Some(&user), &mut transaction).await?;
+
+    if repo.archived {
+        die!(FORBIDDEN, "Repository is archived");
+    }
+
+    // Locked issues can only be commented on by users which can manage issues
+    if issue.locked && !privilege::check_manage_issues(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Issue has been locked");
+    }
+
+    let text = validate_body(body.body.as_str())?;
+    let mentions = issue::resolve_mentions(text, &mut transaction).await?;
+
+    let comment = sqlx::query_as::<_, IssueComment>("insert into issue_comments (issue, author, body, mentions) values ($1, $2, $3, $4) returning *")
+        .bind(&issue.id)
+        .bind(&user.id)
+        .bind(text)
+        .bind(&mentions)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    touch_issue(&issue, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("Comment {} created on issue {} by user {}", &comment.id, &issue.id, &user.id);
+
+    Ok(HttpResponse::Created().json(CommentJsonResponse::new(comment, user.username, &repo, uri.username.as_str())))
+}
+
+#[route("/api/repo/{username}/{repository}/issues/{index}/comments/{id}", method = "PUT", err = "json")]
+pub(crate) async fn edit_comment(uri: web::Path<IssueCommentRequest>, body: web::Json<CommentJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+    let issue = find_visible_issue(&repo, uri.index, Some(&user), &mut transaction).await?;
+    let comment = find_comment(&issue, uri.id, &mut transaction).await?;
+
+    // Only authors can edit their comments, as edits by others would be attributed to the author
+    if comment.author != Some(user.id) {
+        die!(FORBIDDEN, "Only the author can edit this comment");
+    }
+
+    let text = validate_body(body.body.as_str())?;
+    let mentions = issue::resolve_mentions(text, &mut transaction).await?;
+
+    let comment = sqlx::query_as::<_, IssueComment>("update issue_comments set body = $1, mentions = $2, edited_at = current_timestamp where id = $3 returning *")
+        .bind(text)
+        .bind(&mentions)
+        .bind(&comment.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(CommentJsonResponse::new(comment, user.username, &repo, uri.username.as_str())))
+}
+
+#[route("/api/repo/{username}/{repository}/issues/{index}/comments/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_comment(uri: web::Path<IssueCommentRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+    let issue = find_visible_issue(&repo, uri.index, Some(&user), &mut transaction).await?;
+    let comment = find_comment(&issue, uri.id, &mut transaction).await?;
+
+    if comment.author != Some(user.id) && !privilege::check_manage_issues(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only the author and users which can manage issues can delete this comment");
+    }
+
+    // Comments are kept as tombstones so replies referring to them still make sense
+    sqlx::query("update issue_comments set body = '', mentions = ARRAY []::integer[], deleted_at = current_timestamp where id = $1")
+        .bind(&comment.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("Comment {} on issue {} deleted by user {}", &comment.id, &issue.id, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn find_comment(issue: &Issue, id: i32, transaction: &mut Transaction<'_, Postgres>) -> Result<IssueComment> {
+    let comment = sqlx::query_as::<_, IssueComment>("select * from issue_comments where id = $1 and issue = $2 limit 1")
+        .bind(&id)
+        .bind(&issue.id)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Comment not found"))?;
+
+    if comment.deleted_at.is_some() {
+        die!(GONE, "Comment has been deleted");
+    }
+
+    Ok(comment)
+}
+
+fn validate_body(body: &str) -> Result<&str> {
+    let body = body.trim();
+
+    if body.is_empty() || body.len() > 65536 {
+        die!(BAD_REQUEST, "Comment must be between 1 and 65536 characters long");
+    }
+
+    Ok(body)
+}
+
+/// Bumps `updated_at` of the issue so recently discussed issues can be found
+async fn touch_issue(issue: &Issue, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    sqlx::query("update issues set updated_at = current_timestamp where id = $1")
+        .bind(&issue.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IssueCommentsRequest {
+    username: String,
+    repository: String,
+    index: i32
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IssueCommentRequest {
+    username: String,
+    repository: String,
+    index: i32,
+    id: i32
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CommentJsonRequest {
+    body: String
+}
+
+#[derive(Serialize)]
+pub(crate) struct CommentJsonResponse {
+    #[serde(flatten)]
+    comment: IssueComment,
+    author_name: String,
+    body_html: String,
+    edited: bool,
+    deleted: bool
+}
+
+impl CommentJsonResponse {
+    fn new(comment: IssueComment, author_name: String, repo: &Repository, owner_name: &str) -> CommentJsonResponse {
+        let body_html = markdown::render(comment.body.as_str(), &LinkTarget {
+            username: owner_name,
+            repository: repo.name.as_str(),
+            tree: repo.default_branch.as_str(),
+            directory: ""
+        });
+
+        CommentJsonResponse {
+            author_name,
+            body_html,
+            edited: comment.edited_at.is_some(),
+            deleted: comment.deleted_at.is_some(),
+            comment
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use crate::issue::{self, Issue};
+use crate::markdown::{self, LinkTarget};
+use crate::notification::{self, NotificationKind};
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[route("/api/repo/{username}/{repository}/issues", method = "GET", err = "json")]
+pub(crate) async fn list_issues(uri: web::Path<GitRequest>, query: web::Query<ListIssuesQuery>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let can_manage = privilege::check_manage_issues(&repo, web_user.as_ref(), &mut transaction).await?;
+
+    let closed = match query.state.as_deref() {
+        None | Some("open") => Some(false),
+        Some("closed") => Some(true),
+        Some("all") => None,
+        Some(_) => die!(BAD_REQUEST, "State needs to be either open, closed or all")
+    };
+
+    // Confidential issues are only visible to their author and users which can manage issues
+    let issues: Vec<Issue> = sqlx::query_as::<_, Issue>("select * from issues where repo = $1 and ($2::boolean is null or closed = $2) \
+        and (not confidential or $3 or author = $4) order by index desc")
+        .bind(&repo.id)
+        .bind(&closed)
+        .bind(&can_manage)
+        .bind(web_user.as_ref().map(|user| user.id))
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[route("/api/repo/{username}/{repository}/issues", method = "POST", err = "json")]
+pub(crate) async fn create_issue(uri: web::Path<GitRequest>, body: web::Json<CreateIssueJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if repo.archived {
+        die!(FORBIDDEN, "Repository is archived");
+    }
+
+    let title = body.title.trim();
+
+    if title.is_empty() || title.len() > 256 {
+        die!(BAD_REQUEST, "Title must be between 1 and 256 characters long");
+    }
+
+    if body.body.len() > 65536 {
+        die!(BAD_REQUEST, "Body may only be up to 65536 characters long");
+    }
+
+    let issue = Issue::create(&repo, &user, title, body.body.as_str(), body.confidential, &mut transaction).await?;
+
+    let mentions = issue::resolve_mentions(issue.body.as_str(), &mut transaction).await?;
+    let payload = json!({ "index": &issue.index, "title": &issue.title });
+    notification::notify_watchers(&repo, &user, NotificationKind::Issue, payload, mentions.as_slice(), issue.confidential, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("Issue #{} (id {}) created in repo {} by user {}", &issue.index, &issue.id, &repo.id, &user.id);
+
+    Ok(HttpResponse::Created().json(issue))
+}
+
+#[route("/api/repo/{username}/{repository}/issues/{index}", method = "GET", err = "json")]
+pub(crate) async fn get_issue(uri: web::Path<IssueRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let issue = find_visible_issue(&repo, uri.index, web_user.as_ref(), &mut transaction).await?;
+
+    let author_name = User::username_or_ghost(issue.author, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    // Relative links in issues point to the default branch, the same way they would from the README
+    let body_html = markdown::render(issue.body.as_str(), &LinkTarget {
+        username: uri.username.as_str(),
+        repository: uri.repository.as_str(),
+        tree: repo.default_branch.as_str(),
+        directory: ""
+    });
+
+    Ok(HttpResponse::Ok().json(IssueJsonResponse {
+        issue,
+        author_name,
+        body_html
+    }))
+}
+
+#[route("/api/repo/{username}/{repository}/issues/{index}/state", method = "PUT", err = "json")]
+pub(crate) async fn update_issue_state(uri: web::Path<IssueRequest>, body: web::Json<IssueStateJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+    let mut issue = find_visible_issue(&repo, uri.index, Some(&user), &mut transaction).await?;
+
+    // Authors may close and reopen their own issues, unless they have been locked
+    let is_author = issue.author == Some(user.id) && !issue.locked;
+
+    if !is_author && !privilege::check_manage_issues(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only the author and users which can manage issues can change the state of this issue");
+    }
+
+    let closed = match body.state.as_str() {
+        "open" => false,
+        "closed" => true,
+        _ => die!(BAD_REQUEST, "State needs to be either open or closed")
+    };
+
+    issue.set_closed(closed, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("Issue #{} (id {}) in repo {} {} by user {}", &issue.index, &issue.id, &repo.id, if closed { "closed" } else { "reopened" }, &user.id);
+
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+pub(crate) async fn find_repo(username: &str, repository: &str, user: Option<&User>, transaction: &mut Transaction<'_, Postgres>) -> Result<Repository> {
+    let repo_owner = User::find_using_name(username, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, repository, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, user, &mut *transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    Ok(repo)
+}
+
+/// Finds an issue by its # and checks whenever `user` is allowed to see it
+pub(crate) async fn find_visible_issue(repo: &Repository, index: i32, user: Option<&User>, transaction: &mut Transaction<'_, Postgres>) -> Result<Issue> {
+    let issue = Issue::find(repo, index, &mut *transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Issue not found"))?;
+
+    if issue.confidential && user.map_or(true, |user| Some(user.id) != issue.author) && !privilege::check_manage_issues(repo, user, &mut *transaction).await? {
+        die!(NOT_FOUND, "Issue not found");
+    }
+
+    Ok(issue)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IssueRequest {
+    username: String,
+    repository: String,
+    index: i32
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ListIssuesQuery {
+    state: Option<String>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreateIssueJsonRequest {
+    title: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    confidential: bool
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IssueStateJsonRequest {
+    state: String
+}
+
+#[derive(Serialize)]
+pub(crate) struct IssueJsonResponse {
+    #[serde(flatten)]
+    issue: Issue,
+    author_name: String,
+    body_html: String
+}
@@ -0,0 +1,29 @@
+use crate::git::languages;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use sqlx::PgPool;
+
+#[route("/api/repo/{username}/{repository}/languages", method = "GET", err = "json")]
+pub(crate) async fn get_languages(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    let languages = languages::languages(&repo, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(languages))
+}
@@ -0,0 +1,99 @@
+use crate::config::get_setting;
+use crate::mirror::MirrorSettings;
+use crate::privileges::privilege;
+use crate::routes::repository::GitRequest;
+use crate::routes::repository::api::issues::find_repo;
+use crate::user::WebUser;
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::Utc;
+use gitarena_macros::route;
+use log::debug;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+#[route("/api/repo/{username}/{repository}/mirror", method = "GET", err = "json")]
+pub(crate) async fn get_mirror(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let settings = MirrorSettings::find(&repo, &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Repository is not a mirror"))?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+#[route("/api/repo/{username}/{repository}/mirror", method = "PUT", err = "json")]
+pub(crate) async fn update_mirror(uri: web::Path<GitRequest>, body: web::Json<UpdateMirrorJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Changing mirror settings requires admin access");
+    }
+
+    let min_interval = get_setting::<i32, _>("repositories.mirror_min_interval", &mut transaction).await?;
+
+    if body.interval < min_interval {
+        die!(BAD_REQUEST, "Interval needs to be at least {} seconds", min_interval);
+    }
+
+    // Reschedules the next sync relative to the last one, so shortening the interval takes effect right away
+    let settings = sqlx::query_as::<_, MirrorSettings>("update mirror_settings set interval = $1, \
+        next_sync_at = coalesce(last_sync_at, now()) + $1 * interval '1 second' where repo = $2 returning *")
+        .bind(&body.interval)
+        .bind(&repo.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Repository is not a mirror"))?;
+
+    transaction.commit().await?;
+
+    debug!("Mirror interval of repo {} changed to {} seconds by user {}", &repo.id, &settings.interval, &user.id);
+
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// Schedules the mirror to be synced the next time the workhorse checks for due mirrors, which happens every minute
+#[route("/api/repo/{username}/{repository}/mirror/sync", method = "POST", err = "json")]
+pub(crate) async fn sync_mirror(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !privilege::check_push(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Syncing a mirror requires push access");
+    }
+
+    let settings = MirrorSettings::find(&repo, &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Repository is not a mirror"))?;
+
+    // Manual syncs are rate limited by the same minimum interval to not hammer upstream
+    let min_interval = get_setting::<i32, _>("repositories.mirror_min_interval", &mut transaction).await?;
+
+    if let Some(last_sync_at) = settings.last_sync_at {
+        if (Utc::now() - last_sync_at).num_seconds() < i64::from(min_interval) {
+            die!(TOO_MANY_REQUESTS, "Mirror has been synced less than {} seconds ago", min_interval);
+        }
+    }
+
+    sqlx::query("update mirror_settings set next_sync_at = now() where repo = $1")
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct UpdateMirrorJsonRequest {
+    /// Seconds between two syncs
+    interval: i32
+}
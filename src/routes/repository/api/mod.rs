@@ -1,19 +1,61 @@
+use crate::die;
+
 use actix_web::web::ServiceConfig;
+use anyhow::Result;
 use serde::Serialize;
+use utoipa::Component;
 
-mod create_repo;
+mod branch_protection;
+mod collaborators;
+mod commit_diff;
+mod commit_statuses;
+mod commits;
+pub(crate) mod create_repo;
+pub(crate) mod deploy_keys;
 mod fork_repo;
 mod import_repo;
-mod repo_meta;
+mod issue_comments;
+mod issues;
+mod languages;
+mod mirror;
+mod pull_requests;
+mod releases;
+pub(crate) mod repo_meta;
 mod repo_readme;
-mod star;
+pub(crate) mod star;
+pub(crate) mod topics;
+pub(crate) mod transfer;
+pub(crate) mod watch;
+pub(crate) mod webhooks;
 
 pub(crate) fn init(config: &mut ServiceConfig) {
     // import_repo needs to be always above create_repo
     config.service(import_repo::import);
+    config.service(import_repo::import_status);
     config.service(create_repo::create);
     config.service(repo_meta::meta);
+    config.service(repo_meta::update_visibility);
+    config.service(repo_meta::rename);
+    config.service(repo_meta::update_description);
+    config.service(repo_meta::update_default_branch);
+    config.service(repo_meta::delete);
+    config.service(topics::get_topics);
+    config.service(topics::put_topics);
+    config.service(topics::list_topic_repositories);
     config.service(repo_readme::readme);
+    config.service(languages::get_languages);
+    config.service(commits::list_commits);
+    config.service(commit_diff::get_commit_diff);
+    config.service(commit_statuses::get_statuses);
+    config.service(commit_statuses::create_status);
+    config.service(mirror::get_mirror);
+    config.service(mirror::update_mirror);
+    config.service(mirror::sync_mirror);
+
+    config.service(transfer::get_transfer);
+    config.service(transfer::create_transfer);
+    config.service(transfer::accept_transfer);
+    config.service(transfer::delete_transfer);
 
     config.service(fork_repo::get_fork_amount);
     config.service(fork_repo::create_fork);
@@ -22,10 +64,66 @@ pub(crate) fn init(config: &mut ServiceConfig) {
     config.service(star::post_star);
     config.service(star::delete_star);
     config.service(star::put_star);
+
+    config.service(watch::get_watch);
+    config.service(watch::put_watch);
+    config.service(watch::delete_watch);
+
+    config.service(branch_protection::list_protections);
+    config.service(branch_protection::create_protection);
+    config.service(branch_protection::delete_protection);
+
+    config.service(collaborators::list_collaborators);
+    config.service(collaborators::put_collaborator);
+    config.service(collaborators::delete_collaborator);
+
+    config.service(deploy_keys::list_deploy_keys);
+    config.service(deploy_keys::add_deploy_key);
+    config.service(deploy_keys::delete_deploy_key);
+
+    config.service(webhooks::list_webhooks);
+    config.service(webhooks::create_webhook);
+    config.service(webhooks::delete_webhook);
+    config.service(webhooks::list_deliveries);
+
+    config.service(issues::list_issues);
+    config.service(issues::create_issue);
+    config.service(issues::get_issue);
+    config.service(issues::update_issue_state);
+
+    config.service(issue_comments::list_comments);
+    config.service(issue_comments::create_comment);
+    config.service(issue_comments::edit_comment);
+    config.service(issue_comments::delete_comment);
+
+    config.service(pull_requests::list_pull_requests);
+    config.service(pull_requests::create_pull_request);
+    config.service(pull_requests::get_pull_request);
+    config.service(pull_requests::get_pull_request_diff);
+    config.service(pull_requests::update_pull_request_state);
+    config.service(pull_requests::merge_pull_request);
+
+    config.service(releases::list_releases);
+    config.service(releases::create_release);
+    config.service(releases::upload_asset);
+    config.service(releases::get_release);
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Component)]
 pub(crate) struct CreateJsonResponse {
     pub(crate) id: i32,
     pub(crate) url: String
 }
+
+/// Checks if the string is a valid repository description. Descriptions are a single line of plain text of up to 256 characters
+pub(crate) fn validate_description(description: &str) -> Result<()> {
+    if description.chars().count() > 256 {
+        die!(BAD_REQUEST, "Description may only be up to 256 characters long");
+    }
+
+    if description.chars().any(char::is_control) {
+        die!(BAD_REQUEST, "Description may not contain line breaks or control characters");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,393 @@
+use crate::branch_protection;
+use crate::commit_status::{self, CommitState, CommitStatus};
+use crate::config::get_setting;
+use crate::git::diff;
+use crate::git::merge::{self, MergeStrategy};
+use crate::git::push;
+use crate::git::ref_update::RefUpdate;
+use crate::git::write;
+use crate::markdown::{self, LinkTarget};
+use crate::privileges::privilege;
+use crate::pull_request::{PullRequest, PullRequestState};
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::routes::repository::api::issues::find_repo;
+use crate::shutdown;
+use crate::user::{User, WebUser};
+use crate::webhook::PushedRef;
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use git2::{Oid, Repository as Git2Repository};
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[route("/api/repo/{username}/{repository}/pulls", method = "GET", err = "json")]
+pub(crate) async fn list_pull_requests(uri: web::Path<GitRequest>, query: web::Query<ListPullRequestsQuery>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+
+    let state = match query.state.as_deref() {
+        None | Some("open") => Some(PullRequestState::Open),
+        Some("closed") => Some(PullRequestState::Closed),
+        Some("merged") => Some(PullRequestState::Merged),
+        Some("all") => None,
+        Some(_) => die!(BAD_REQUEST, "State needs to be either open, closed, merged or all")
+    };
+
+    let pull_requests: Vec<PullRequest> = sqlx::query_as::<_, PullRequest>("select * from pull_requests where repo = $1 and ($2::pull_request_state is null or state = $2) order by index desc")
+        .bind(&repo.id)
+        .bind(&state)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(pull_requests))
+}
+
+#[route("/api/repo/{username}/{repository}/pulls", method = "POST", err = "json")]
+pub(crate) async fn create_pull_request(uri: web::Path<GitRequest>, body: web::Json<CreatePullRequestJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if repo.archived {
+        die!(FORBIDDEN, "Repository is archived");
+    }
+
+    let title = body.title.trim();
+
+    if title.is_empty() || title.len() > 256 {
+        die!(BAD_REQUEST, "Title must be between 1 and 256 characters long");
+    }
+
+    if body.body.len() > 65536 {
+        die!(BAD_REQUEST, "Body may only be up to 65536 characters long");
+    }
+
+    let fork = match body.source_repository.as_deref() {
+        Some(full_name) => {
+            let (owner, name) = full_name.split_once('/').ok_or_else(|| err!(BAD_REQUEST, "Source repository needs to be in the format owner/name"))?;
+            let source = find_repo(owner, name, Some(&user), &mut transaction).await?;
+
+            if source.id != repo.id && source.forked_from != Some(repo.id) {
+                die!(BAD_REQUEST, "Source repository needs to be a fork of this repository");
+            }
+
+            Some(source)
+        }
+        None => None
+    };
+    let source_repo = fork.as_ref().unwrap_or(&repo);
+
+    let source_branch = body.source_branch.trim();
+    let target_branch = body.target_branch.as_deref().map(str::trim).unwrap_or(repo.default_branch.as_str());
+
+    if source_repo.id == repo.id && source_branch == target_branch {
+        die!(BAD_REQUEST, "Source and target branch need to be different");
+    }
+
+    let git2_repo = repo.libgit2(&mut transaction).await?;
+    let source_git2_repo = source_repo.libgit2(&mut transaction).await?;
+
+    let target = target_tip(&git2_repo, target_branch)?;
+    let head = source_git2_repo.refname_to_id(format!("refs/heads/{}", source_branch).as_str()).map_err(|_| err!(NOT_FOUND, "Source branch {} not found", source_branch))?;
+
+    let mut pull_request = PullRequest::create(&repo, &user, title, body.body.as_str(), source_repo, source_branch, target_branch, head.to_string().as_str(), Oid::zero().to_string().as_str(), &mut transaction).await?;
+
+    // The head needs to be fetched before the merge base can be calculated, as commits of forks are not in this repository yet
+    let head = merge::fetch_head(&git2_repo, &source_git2_repo, source_branch, pull_request.head_ref().as_str())?;
+    let base = merge_base(&git2_repo, target, head)?;
+
+    pull_request.update_head(head.to_string().as_str(), base.to_string().as_str(), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("Pull request #{} (id {}) created in repo {} by user {}", &pull_request.index, &pull_request.id, &repo.id, &user.id);
+
+    Ok(HttpResponse::Created().json(pull_request))
+}
+
+#[route("/api/repo/{username}/{repository}/pulls/{index}", method = "GET", err = "json")]
+pub(crate) async fn get_pull_request(uri: web::Path<PullRequestRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let mut pull_request = find_pull_request(&repo, uri.index, &mut transaction).await?;
+
+    let git2_repo = repo.libgit2(&mut transaction).await?;
+    refresh_head(&mut pull_request, &git2_repo, &mut transaction).await?;
+
+    let author_name = User::username_or_ghost(pull_request.author, &mut transaction).await?;
+
+    let statuses = CommitStatus::latest(&repo, pull_request.head_sha.as_str(), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    // Only open pull requests can be merged, for everything else the result would be meaningless
+    let mergeable = match pull_request.state {
+        PullRequestState::Open => match target_tip(&git2_repo, pull_request.target_branch.as_str()) {
+            Ok(target) => Some(!merge::has_conflicts(&git2_repo, target, Oid::from_str(pull_request.head_sha.as_str())?)?),
+            Err(_) => Some(false)
+        },
+        _ => None
+    };
+
+    let body_html = markdown::render(pull_request.body.as_str(), &LinkTarget {
+        username: uri.username.as_str(),
+        repository: uri.repository.as_str(),
+        tree: pull_request.target_branch.as_str(),
+        directory: ""
+    });
+
+    Ok(HttpResponse::Ok().json(PullRequestJsonResponse {
+        pull_request,
+        author_name,
+        body_html,
+        mergeable,
+        status: commit_status::combined_state(statuses.as_slice())
+    }))
+}
+
+/// Diff of all changes of the pull request since the source branch diverged from the target branch
+#[route("/api/repo/{username}/{repository}/pulls/{index}/diff", method = "GET", err = "json")]
+pub(crate) async fn get_pull_request_diff(uri: web::Path<PullRequestRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let mut pull_request = find_pull_request(&repo, uri.index, &mut transaction).await?;
+
+    let git2_repo = repo.libgit2(&mut transaction).await?;
+    refresh_head(&mut pull_request, &git2_repo, &mut transaction).await?;
+
+    let max_diff_size = get_setting::<i32, _>("repositories.max_diff_size", &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let base = Oid::from_str(pull_request.base_sha.as_str())?;
+    let head = Oid::from_str(pull_request.head_sha.as_str())?;
+
+    let diff = diff::range_diff(&git2_repo, base, head, max_diff_size.max(0) as usize)?;
+
+    Ok(HttpResponse::Ok().json(diff))
+}
+
+#[route("/api/repo/{username}/{repository}/pulls/{index}/state", method = "PUT", err = "json")]
+pub(crate) async fn update_pull_request_state(uri: web::Path<PullRequestRequest>, body: web::Json<PullRequestStateJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+    let mut pull_request = find_pull_request(&repo, uri.index, &mut transaction).await?;
+
+    if pull_request.author != Some(user.id) && !privilege::check_push(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only the author and users with push access can change the state of this pull request");
+    }
+
+    if pull_request.state == PullRequestState::Merged {
+        die!(CONFLICT, "Pull request has already been merged");
+    }
+
+    let state = match body.state.as_str() {
+        "open" => PullRequestState::Open,
+        "closed" => PullRequestState::Closed,
+        _ => die!(BAD_REQUEST, "State needs to be either open or closed")
+    };
+
+    pull_request.set_state(state, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("Pull request #{} (id {}) in repo {} set to {} by user {}", &pull_request.index, &pull_request.id, &repo.id, &state, &user.id);
+
+    Ok(HttpResponse::Ok().json(pull_request))
+}
+
+#[route("/api/repo/{username}/{repository}/pulls/{index}/merge", method = "POST", err = "json")]
+pub(crate) async fn merge_pull_request(uri: web::Path<PullRequestRequest>, body: web::Json<MergeJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let mut repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !privilege::check_push(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Merging pull requests requires push access");
+    }
+
+    if repo.archived {
+        die!(FORBIDDEN, "Repository is archived");
+    }
+
+    if repo.mirrored_from.is_some() {
+        die!(FORBIDDEN, "Repository is a mirror and thus read-only");
+    }
+
+    let mut pull_request = find_pull_request(&repo, uri.index, &mut transaction).await?;
+
+    if pull_request.state != PullRequestState::Open {
+        die!(CONFLICT, "Only open pull requests can be merged");
+    }
+
+    // Merging writes into the repository just like a push does
+    let _push = shutdown::track_push();
+    let _fs_lock = repo.lock_fs_shared().await;
+
+    let git2_repo = repo.libgit2(&mut transaction).await?;
+    refresh_head(&mut pull_request, &git2_repo, &mut transaction).await?;
+
+    // Commits pushed to the source branch after the user reviewed the pull request must not be merged unseen
+    if pull_request.head_sha != body.head_sha.trim() {
+        die!(CONFLICT, "Source branch has been updated to {}, please review the changes and try again", &pull_request.head_sha);
+    }
+
+    let target_ref = format!("refs/heads/{}", pull_request.target_branch);
+    let target = target_tip(&git2_repo, pull_request.target_branch.as_str())?;
+    let head = Oid::from_str(pull_request.head_sha.as_str())?;
+
+    let message = match body.message.as_deref().map(str::trim).filter(|message| !message.is_empty()) {
+        Some(message) => message.to_owned(),
+        None => match body.strategy {
+            MergeStrategy::Squash => format!("{} (#{})", &pull_request.title, &pull_request.index),
+            _ => format!("Merge pull request #{} from {}\n\n{}", &pull_request.index, &pull_request.source_branch, &pull_request.title)
+        }
+    };
+
+    let author = write::user_signature(&user, &mut transaction).await?;
+    let committer = write::server_signature(&mut transaction).await?;
+
+    let new = merge::merge(&git2_repo, body.strategy, target, head, message.as_str(), &author, &committer)?;
+
+    let update = RefUpdate {
+        old: Some(target.to_string()),
+        new: Some(new.to_string()),
+        target_ref: target_ref.clone(),
+        ..Default::default()
+    };
+
+    if let Some(reason) = branch_protection::check_update(&update, &repo, Some(&user), None, &mut transaction).await? {
+        die!(FORBIDDEN, "Merge rejected: {}", reason);
+    }
+
+    // Only move the branch if nobody pushed to it in the meantime, otherwise their commits would be lost
+    git2_repo.reference_matching(target_ref.as_str(), new, true, target, format!("merge pull request #{}", &pull_request.index).as_str())
+        .map_err(|_| err!(CONFLICT, "Target branch has been updated while merging, please try again"))?;
+
+    pull_request.set_merged(new.to_string().as_str(), &user, &mut transaction).await?;
+
+    // Records the activity, delivers push webhooks and commits the transaction
+    let pushed_refs = vec![PushedRef::new(target_ref.as_str(), Some(target.to_string().as_str()), Some(new.to_string().as_str()))];
+    push::finish(&mut repo, uri.username.as_str(), Some(&user), pushed_refs, db_pool.get_ref(), transaction).await?;
+
+    debug!("Pull request #{} (id {}) in repo {} merged into {} by user {}", &pull_request.index, &pull_request.id, &repo.id, &new, &user.id);
+
+    Ok(HttpResponse::Ok().json(pull_request))
+}
+
+async fn find_pull_request(repo: &Repository, index: i32, transaction: &mut Transaction<'_, Postgres>) -> Result<PullRequest> {
+    PullRequest::find(repo, index, &mut *transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Pull request not found"))
+}
+
+/// Fetches the latest commit of the source branch of open pull requests. If the source repository or branch has been deleted,
+/// the last fetched commit is kept so the pull request can still be merged
+async fn refresh_head(pull_request: &mut PullRequest, git2_repo: &Git2Repository, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    if pull_request.state != PullRequestState::Open {
+        return Ok(());
+    }
+
+    let source_repo = match pull_request.source_repo {
+        Some(id) => sqlx::query_as::<_, Repository>("select * from repositories where id = $1 limit 1")
+            .bind(&id)
+            .fetch_optional(&mut *transaction)
+            .await?,
+        None => None
+    };
+
+    let source_repo = match source_repo {
+        Some(source_repo) => source_repo,
+        None => return Ok(())
+    };
+
+    let source_git2_repo = source_repo.libgit2(&mut *transaction).await?;
+
+    if source_git2_repo.refname_to_id(format!("refs/heads/{}", &pull_request.source_branch).as_str()).is_err() {
+        return Ok(());
+    }
+
+    let head = merge::fetch_head(git2_repo, &source_git2_repo, pull_request.source_branch.as_str(), pull_request.head_ref().as_str())?;
+    let base = match target_tip(git2_repo, pull_request.target_branch.as_str()) {
+        Ok(target) => merge_base(git2_repo, target, head)?,
+        Err(_) => return Ok(())
+    };
+
+    if head.to_string() != pull_request.head_sha || base.to_string() != pull_request.base_sha {
+        pull_request.update_head(head.to_string().as_str(), base.to_string().as_str(), &mut *transaction).await?;
+    }
+
+    Ok(())
+}
+
+fn target_tip(repo: &Git2Repository, branch: &str) -> Result<Oid> {
+    repo.refname_to_id(format!("refs/heads/{}", branch).as_str()).map_err(|_| err!(NOT_FOUND, "Target branch {} not found", branch))
+}
+
+fn merge_base(repo: &Git2Repository, target: Oid, head: Oid) -> Result<Oid> {
+    repo.merge_base(target, head).map_err(|_| err!(BAD_REQUEST, "Source and target branch do not share any history"))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PullRequestRequest {
+    username: String,
+    repository: String,
+    index: i32
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ListPullRequestsQuery {
+    state: Option<String>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreatePullRequestJsonRequest {
+    title: String,
+    #[serde(default)]
+    body: String,
+    /// `owner/name` of the fork the changes come from. If not set, the source branch is in the repository itself
+    #[serde(default)]
+    source_repository: Option<String>,
+    source_branch: String,
+    /// Defaults to the default branch of the repository
+    #[serde(default)]
+    target_branch: Option<String>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PullRequestStateJsonRequest {
+    state: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MergeJsonRequest {
+    strategy: MergeStrategy,
+    /// Head commit the user reviewed, the merge is rejected if the source branch has moved on since
+    head_sha: String,
+    #[serde(default)]
+    message: Option<String>
+}
+
+#[derive(Serialize)]
+pub(crate) struct PullRequestJsonResponse {
+    #[serde(flatten)]
+    pull_request: PullRequest,
+    author_name: String,
+    body_html: String,
+    /// Whenever the pull request can be merged without conflicts, `null` if the pull request is not open
+    mergeable: Option<bool>,
+    /// Combined state of the statuses reported for the head commit
+    status: Option<CommitState>
+}
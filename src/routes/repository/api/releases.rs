@@ -0,0 +1,267 @@
+use crate::activity::{self, ActivityKind};
+use crate::config::get_setting;
+use crate::git::write;
+use crate::markdown::{self, LinkTarget};
+use crate::notification::{self, NotificationKind};
+use crate::prelude::*;
+use crate::privileges::privilege;
+use crate::release::{self, Release, ReleaseAsset};
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::routes::repository::api::issues::find_repo;
+use crate::user::{User, WebUser};
+use crate::utils::is_unique_violation;
+use crate::{die, err, quota};
+
+use std::path::Path;
+
+use actix_files::file_extension_to_mime;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use git2::Reference;
+use gitarena_macros::route;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::fs;
+
+#[route("/api/repo/{username}/{repository}/releases", method = "GET", err = "json")]
+pub(crate) async fn list_releases(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let show_drafts = privilege::check_push(&repo, web_user.as_ref(), &mut transaction).await?;
+
+    let releases: Vec<Release> = sqlx::query_as::<_, Release>("select * from releases where repo = $1 and (not draft or $2) order by created_at desc")
+        .bind(&repo.id)
+        .bind(&show_drafts)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(releases))
+}
+
+/// Creates a release for `tag`. If the tag does not exist yet, it gets created as an annotated tag on `target` signed by the instance
+#[route("/api/repo/{username}/{repository}/releases", method = "POST", err = "json")]
+pub(crate) async fn create_release(uri: web::Path<GitRequest>, body: web::Json<CreateReleaseJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !privilege::check_push(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Creating releases requires push access");
+    }
+
+    if repo.archived {
+        die!(FORBIDDEN, "Repository is archived");
+    }
+
+    let tag = body.tag.trim();
+    let tag_ref = format!("refs/tags/{}", tag);
+
+    if tag.is_empty() || tag.len() > 256 || !Reference::is_valid_name(tag_ref.as_str()) {
+        die!(BAD_REQUEST, "Invalid tag name");
+    }
+
+    let title = body.title.as_deref().map(str::trim).filter(|title| !title.is_empty()).unwrap_or(tag);
+
+    if title.len() > 256 {
+        die!(BAD_REQUEST, "Title may only be up to 256 characters long");
+    }
+
+    if body.body.len() > 65536 {
+        die!(BAD_REQUEST, "Body may only be up to 65536 characters long");
+    }
+
+    let git2_repo = repo.libgit2(&mut transaction).await?;
+
+    if git2_repo.refname_to_id(tag_ref.as_str()).is_err() {
+        let target = body.target.as_deref().unwrap_or(repo.default_branch.as_str());
+        let object = git2_repo.revparse_single(target)
+            .and_then(|object| object.peel_to_commit())
+            .map_err(|_| err!(NOT_FOUND, "Target {} not found", target))?;
+
+        let tagger = write::server_signature(&mut transaction).await?;
+        git2_repo.tag(tag, object.as_object(), &tagger, title, false)?;
+
+        debug!("Tag {} created on {} in repo {} for a new release", tag, object.id(), &repo.id);
+    }
+
+    let release = match sqlx::query_as::<_, Release>("insert into releases (repo, tag, title, body, draft, prerelease, author) values ($1, $2, $3, $4, $5, $6, $7) returning *")
+        .bind(&repo.id)
+        .bind(tag)
+        .bind(title)
+        .bind(body.body.as_str())
+        .bind(&body.draft)
+        .bind(&body.prerelease)
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(release) => release,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "A release for tag {} already exists", tag),
+        Err(err) => return Err(err.into())
+    };
+
+    // Drafts can only be seen by users with push access, so they're kept out of feeds
+    if !release.draft {
+        let payload = json!({ "tag": &release.tag, "title": &release.title });
+
+        activity::record(&user, &repo, ActivityKind::Release, payload.clone(), &mut transaction).await?;
+        notification::notify_watchers(&repo, &user, NotificationKind::Release, payload, &[], false, &mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
+    debug!("Release {} (id {}) created in repo {} by user {}", &release.tag, &release.id, &repo.id, &user.id);
+
+    Ok(HttpResponse::Created().json(release))
+}
+
+#[route("/api/repo/{username}/{repository}/releases/{tag:.+}", method = "GET", err = "json")]
+pub(crate) async fn get_release(uri: web::Path<ReleaseRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let release = find_visible_release(&repo, uri.tag.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let assets = release.assets(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let body_html = markdown::render(release.body.as_str(), &LinkTarget {
+        username: uri.username.as_str(),
+        repository: uri.repository.as_str(),
+        tree: release.tag.as_str(),
+        directory: ""
+    });
+
+    Ok(HttpResponse::Ok().json(ReleaseJsonResponse {
+        release,
+        body_html,
+        assets
+    }))
+}
+
+/// Uploads the request body as asset `name` of the release. The content type is taken from the request or guessed from the file extension
+#[route("/api/repo/{username}/{repository}/releases/{tag:.+}/assets", method = "POST", err = "json")]
+pub(crate) async fn upload_asset(uri: web::Path<ReleaseRequest>, query: web::Query<UploadAssetQuery>, body: web::Payload, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let mut repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !privilege::check_push(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Uploading release assets requires push access");
+    }
+
+    let release = Release::find(&repo, uri.tag.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Release not found"))?;
+
+    let name = query.name.trim();
+
+    if name.is_empty() || name.len() > 256 || name.contains(|c: char| c == '/' || c == '\\' || c.is_control()) {
+        die!(BAD_REQUEST, "Asset name must be between 1 and 256 characters long and may not contain slashes");
+    }
+
+    let content_type = match request.get_header("content-type").filter(|value| !value.is_empty()) {
+        Some(value) => value.to_owned(),
+        None => {
+            let extension = Path::new(name).extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+            file_extension_to_mime(extension).to_string()
+        }
+    };
+
+    // The declared length gives a clear error up front, the actual size is capped to the remaining quota while storing the asset
+    if let Some(length) = request.get_header("content-length").and_then(|length| length.parse::<u64>().ok()) {
+        quota::check_push(&repo, length, &mut transaction).await?;
+    }
+
+    let max_size = get_setting::<i32, _>("releases.max_asset_size", &mut transaction).await?.max(0) as u64;
+    let max_size = quota::remaining(&repo, &mut transaction).await?.map_or(max_size, |remaining| remaining.min(max_size));
+    let repo_path = repo.get_fs_path(&mut transaction).await?;
+
+    let asset = match sqlx::query_as::<_, ReleaseAsset>("insert into release_assets (release, name, content_type, size, uploader) values ($1, $2, $3, 0, $4) returning *")
+        .bind(&release.id)
+        .bind(name)
+        .bind(content_type.as_str())
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(asset) => asset,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "Release already has an asset named {}", name),
+        Err(err) => return Err(err.into())
+    };
+
+    let path = release::asset_path(repo_path.as_str(), release.id, asset.id);
+    let size = release::store_asset(path.as_path(), max_size, body).await?;
+
+    let asset = sqlx::query_as::<_, ReleaseAsset>("update release_assets set size = $1 where id = $2 returning *")
+        .bind(&(size as i64))
+        .bind(&asset.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    repo.update_size(&mut transaction).await?;
+
+    if let Err(err) = transaction.commit().await {
+        if let Err(err) = fs::remove_file(&path).await {
+            warn!("Failed to remove asset {} after failing to save it: {}", path.display(), err);
+        }
+
+        return Err(err.into());
+    }
+
+    debug!("Asset {} (id {}) uploaded to release {} (id {}) by user {}", &asset.name, &asset.id, &release.tag, &release.id, &user.id);
+
+    Ok(HttpResponse::Created().json(asset))
+}
+
+/// Finds the release for `tag`. Drafts are only visible to users which can push to the repository
+pub(crate) async fn find_visible_release(repo: &Repository, tag: &str, user: Option<&User>, transaction: &mut Transaction<'_, Postgres>) -> Result<Release> {
+    let release = Release::find(repo, tag, &mut *transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Release not found"))?;
+
+    if release.draft && !privilege::check_push(repo, user, &mut *transaction).await? {
+        die!(NOT_FOUND, "Release not found");
+    }
+
+    Ok(release)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ReleaseRequest {
+    username: String,
+    repository: String,
+    tag: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CreateReleaseJsonRequest {
+    tag: String,
+    /// Defaults to the tag name
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    /// Branch or commit the tag gets created on if it does not exist yet. Defaults to the default branch
+    #[serde(default)]
+    target: Option<String>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct UploadAssetQuery {
+    name: String
+}
+
+#[derive(Serialize)]
+pub(crate) struct ReleaseJsonResponse {
+    #[serde(flatten)]
+    release: Release,
+    body_html: String,
+    assets: Vec<ReleaseAsset>
+}
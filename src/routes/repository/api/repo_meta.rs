@@ -1,14 +1,38 @@
+use crate::audit::{self, AuditEvent};
+use crate::git::utils::is_valid_branch_name;
+use crate::organization::{find_role, OrganizationRole};
 use crate::privileges::privilege;
+use crate::privileges::repo_visibility::RepoVisibility;
 use crate::repository::Repository;
 use crate::routes::repository::GitRequest;
-use crate::user::WebUser;
+use crate::routes::repository::api::star::get_star_count;
+use crate::routes::repository::api::validate_description;
+use crate::user::{User, WebUser};
+use crate::utils::identifiers::{is_fs_legal, is_reserved_repo_name, is_valid};
 use crate::{die, err};
 
-use actix_web::{HttpResponse, Responder, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use gitarena_macros::route;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::PgPool;
+use utoipa::Component;
 
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Repository metadata", body = Repository),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
 #[route("/api/repo/{username}/{repository}", method = "GET", err = "json")]
 pub(crate) async fn meta(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let mut transaction = db_pool.begin().await?;
@@ -19,7 +43,7 @@ pub(crate) async fn meta(uri: web::Path<GitRequest>, web_user: WebUser, db_pool:
         .await?
         .ok_or_else(|| err!(NOT_FOUND, "Not found"))?;
 
-    let repo: Repository = sqlx::query_as::<_, Repository>("select * from repositories where owner = $1 and lower(name) = lower($2) limit 1")
+    let mut repo: Repository = sqlx::query_as::<_, Repository>("select * from repositories where owner = $1 and lower(name) = lower($2) limit 1")
         .bind(&user_id)
         .bind(&uri.repository)
         .fetch_optional(&mut transaction)
@@ -30,7 +54,319 @@ pub(crate) async fn meta(uri: web::Path<GitRequest>, web_user: WebUser, db_pool:
         die!(NOT_FOUND, "Not found");
     }
 
+    repo.stars = Some(get_star_count(&repo, &mut transaction).await?);
+
     transaction.commit().await?;
 
     Ok(HttpResponse::Ok().json(repo))
 }
+
+#[utoipa::path(
+    put,
+    path = "/api/repo/{username}/{repository}/visibility",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = VisibilityJsonRequest,
+    responses(
+        (status = 204, description = "Visibility has been changed"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not an admin of the repository"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/visibility", method = "PUT", err = "json")]
+pub(crate) async fn update_visibility(uri: web::Path<GitRequest>, body: web::Json<VisibilityJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to change the visibility");
+    }
+
+    sqlx::query("update repositories set visibility = $1 where id = $2")
+        .bind(&body.visibility)
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("Visibility of repo {} changed to {} by user {}", &repo.id, &body.visibility, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Renames a repository. Its previous name keeps redirecting to the new one until another repository takes it
+#[utoipa::path(
+    post,
+    path = "/api/repo/{username}/{repository}/rename",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = RenameJsonRequest,
+    responses(
+        (status = 200, description = "Repository has been renamed", body = RenameJsonResponse),
+        (status = 400, description = "Invalid name"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not an admin of the repository"),
+        (status = 404, description = "Repository not found"),
+        (status = 409, description = "Name already in use")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/rename", method = "POST", err = "json")]
+pub(crate) async fn rename(uri: web::Path<GitRequest>, body: web::Json<RenameJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner.id, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to rename the repository");
+    }
+
+    let name = &body.name;
+
+    if name.is_empty() || name.len() > 32 || !name.chars().all(|c| is_valid(&c)) {
+        die!(BAD_REQUEST, "Repository name must be between 1 and 32 characters long and may only contain a-z, 0-9, _ or -");
+    }
+
+    if is_reserved_repo_name(name.as_str()) {
+        die!(BAD_REQUEST, "Repository name is a reserved identifier");
+    }
+
+    if !is_fs_legal(name) {
+        die!(BAD_REQUEST, "Repository name is illegal");
+    }
+
+    if name == &repo.name {
+        die!(BAD_REQUEST, "Repository is already named {}", name);
+    }
+
+    let old_name = repo.name.clone();
+    let repo = repo.relocate(repo.owner, name.as_str(), transaction).await?;
+
+    info!("Repo {} renamed from {} to {} by user {}", &repo.id, &old_name, &repo.name, &user.id);
+
+    Ok(HttpResponse::Ok().json(RenameJsonResponse {
+        owner: repo_owner.username,
+        name: repo.name
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/repo/{username}/{repository}/description",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = DescriptionJsonRequest,
+    responses(
+        (status = 204, description = "Description has been changed"),
+        (status = 400, description = "Invalid description"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not an admin of the repository"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/description", method = "PUT", err = "json")]
+pub(crate) async fn update_description(uri: web::Path<GitRequest>, body: web::Json<DescriptionJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner.id, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to change the description");
+    }
+
+    let description = body.description.trim();
+
+    validate_description(description)?;
+
+    sqlx::query("update repositories set description = $1 where id = $2")
+        .bind(description)
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("Description of repo {} changed by user {}", &repo.id, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Changes the default branch of the repository, which is also where `HEAD` points to. The branch needs to exist already
+#[utoipa::path(
+    put,
+    path = "/api/repo/{username}/{repository}/default-branch",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = DefaultBranchJsonRequest,
+    responses(
+        (status = 204, description = "Default branch has been changed"),
+        (status = 400, description = "Invalid branch name"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not an admin of the repository"),
+        (status = 404, description = "Repository or branch not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/default-branch", method = "PUT", err = "json")]
+pub(crate) async fn update_default_branch(uri: web::Path<GitRequest>, body: web::Json<DefaultBranchJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner.id, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to change the default branch");
+    }
+
+    let branch = body.branch.trim();
+
+    if !is_valid_branch_name(branch) {
+        die!(BAD_REQUEST, "Invalid branch name");
+    }
+
+    let branch_ref = format!("refs/heads/{}", branch);
+    let libgit2_repo = repo.libgit2(&mut transaction).await?;
+
+    if libgit2_repo.find_reference(branch_ref.as_str()).is_err() {
+        die!(NOT_FOUND, "Branch {} does not exist", branch);
+    }
+
+    libgit2_repo.set_head(branch_ref.as_str())?;
+
+    sqlx::query("update repositories set default_branch = $1 where id = $2")
+        .bind(branch)
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("Default branch of repo {} changed from {} to {} by user {}", &repo.id, &repo.default_branch, branch, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Deletes the repository. Requires the full name of the repository (`owner/name`) to be passed as confirmation
+#[utoipa::path(
+    delete,
+    path = "/api/repo/{username}/{repository}",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = DeleteJsonRequest,
+    responses(
+        (status = 204, description = "Repository has been deleted"),
+        (status = 400, description = "Confirmation does not match the repository name"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not the owner of the repository"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}", method = "DELETE", err = "json")]
+pub(crate) async fn delete(uri: web::Path<GitRequest>, body: web::Json<DeleteJsonRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner.id, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    // Unlike other settings, deletion is not available to collaborators with admin access
+    let is_owner = if repo_owner.organization {
+        find_role(&repo_owner, &user, &mut transaction).await? == Some(OrganizationRole::Owner)
+    } else {
+        repo_owner.id == user.id
+    };
+
+    if !is_owner {
+        die!(FORBIDDEN, "Only the owner is allowed to delete the repository");
+    }
+
+    let full_name = format!("{}/{}", &repo_owner.username, &repo.name);
+
+    if body.confirm.trim() != full_name {
+        die!(BAD_REQUEST, "Please type {} to confirm the deletion", full_name);
+    }
+
+    let repo_id = repo.id;
+    repo.delete(transaction).await?;
+
+    info!("Repo {} (id {}) deleted by {} (id {})", &full_name, &repo_id, &user.username, &user.id);
+    audit::record(&request, AuditEvent::RepoDeleted, Some(&user), Some(format!("repo:{}", &full_name)), json!({ "id": repo_id })).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct VisibilityJsonRequest {
+    visibility: RepoVisibility
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct RenameJsonRequest {
+    name: String
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct RenameJsonResponse {
+    owner: String,
+    name: String
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct DescriptionJsonRequest {
+    /// Single line of plain text, up to 256 characters. An empty string removes the description
+    description: String
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct DefaultBranchJsonRequest {
+    branch: String
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct DeleteJsonRequest {
+    /// Full name of the repository (`owner/name`)
+    confirm: String
+}
@@ -1,4 +1,6 @@
+use crate::config::get_setting;
 use crate::git::utils::{read_blob_content, repo_files_at_ref};
+use crate::markdown::{self, LinkTarget};
 use crate::privileges::privilege;
 use crate::repository::Repository;
 use crate::routes::repository::GitTreeRequest;
@@ -39,17 +41,35 @@ pub(crate) async fn readme(uri: web::Path<GitTreeRequest>, web_user: WebUser, db
     let tree_ref = repo_files_at_ref(&loose_ref, store.clone(), &gitoxide_repo, &mut buffer).await?;
     let tree = Tree::from(tree_ref);
 
-    let entry = tree.entries
-        .iter()
-        .find(|e| e.filename.to_lowercase().starts_with(b"readme"))
+    let readme_names = get_setting::<String, _>("repositories.readme_names", &mut transaction).await?;
+
+    // Files are matched case-insensitively, the configured order determines which one wins if there are multiple
+    let entry = readme_names.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .find_map(|name| tree.entries.iter().find(|entry| entry.filename.to_str().map_or(false, |filename| filename.eq_ignore_ascii_case(name))))
         .ok_or_else(|| err!(NOT_FOUND, "No readme file found"))?;
 
     let name = entry.filename.to_str().unwrap_or("Invalid file name");
 
     let content = read_blob_content(entry.oid.as_ref(), store).await?;
 
+    transaction.commit().await?;
+
+    let html = if markdown::is_markdown(name) {
+        Some(markdown::render(content.as_str(), &LinkTarget {
+            username: uri.username.as_str(),
+            repository: uri.repository.as_str(),
+            tree: uri.tree.as_str(),
+            directory: ""
+        }))
+    } else {
+        None
+    };
+
     Ok(HttpResponse::Ok().json(json!({
         "file_name": name,
-        "content": content
+        "content": content,
+        "html": html
     })))
 }
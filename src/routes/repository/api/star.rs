@@ -12,6 +12,19 @@ use log::debug;
 use serde_json::json;
 use sqlx::{Executor, PgPool, Postgres};
 
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}/star",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Star count of the repository and whenever the current user starred it. Only the count is returned as plain text for htmx requests"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
 #[route("/api/repo/{username}/{repository}/star", method = "GET", err = "htmx+json")]
 pub(crate) async fn get_star(uri: web::Path<GitRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let mut transaction = db_pool.begin().await?;
@@ -44,6 +57,21 @@ pub(crate) async fn get_star(uri: web::Path<GitRequest>, web_user: WebUser, requ
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/repo/{username}/{repository}/star",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 201, description = "Repository has been starred"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Repository not found"),
+        (status = 409, description = "Repository is already starred")
+    ),
+    tag = "repository"
+)]
 #[route("/api/repo/{username}/{repository}/star", method = "POST", err = "json")]
 pub(crate) async fn post_star(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let user = web_user.into_user()?;
@@ -68,6 +96,21 @@ pub(crate) async fn post_star(uri: web::Path<GitRequest>, web_user: WebUser, db_
     Ok(HttpResponse::Created().finish())
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/repo/{username}/{repository}/star",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 204, description = "Star has been removed"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Repository not found"),
+        (status = 409, description = "Repository is not starred")
+    ),
+    tag = "repository"
+)]
 #[route("/api/repo/{username}/{repository}/star", method = "DELETE", err = "json")]
 pub(crate) async fn delete_star(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let user = web_user.into_user()?;
@@ -122,7 +165,7 @@ pub(crate) async fn put_star(uri: web::Path<GitRequest>, web_user: WebUser, db_p
     Ok(response.body(count.to_string()))
 }
 
-async fn get_star_count<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, executor: E) -> Result<i64> {
+pub(crate) async fn get_star_count<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, executor: E) -> Result<i64> {
     let (count,): (i64,) = sqlx::query_as("select count(*) from stars where repo = $1")
         .bind(repo.id)
         .fetch_optional(executor)
@@ -133,7 +176,7 @@ async fn get_star_count<'e, E: Executor<'e, Database = Postgres>>(repo: &Reposit
 }
 
 async fn add_star<'e, E: Executor<'e, Database = Postgres>>(user: &User, repo: &Repository, executor: E) -> Result<()> {
-    sqlx::query("insert into stars (stargazer, repo) values ($1, $2)")
+    sqlx::query("insert into stars (stargazer, repo) values ($1, $2) on conflict do nothing")
         .bind(user.id)
         .bind(repo.id)
         .execute(executor)
@@ -0,0 +1,129 @@
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::topics::{self, is_valid_topic};
+use crate::user::{User, WebUser};
+use crate::utils::pagination::{Page, PageQuery};
+use crate::{die, err};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use utoipa::Component;
+
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}/topics",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Topics of the repository", body = TopicsJson),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/topics", method = "GET", err = "json")]
+pub(crate) async fn get_topics(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(&uri, web_user.as_ref(), &mut transaction).await?;
+    let topics = topics::for_repo(&repo, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(TopicsJson {
+        topics
+    }))
+}
+
+/// Replaces all topics of the repository. Topics are lowercased and duplicates are removed
+#[utoipa::path(
+    put,
+    path = "/api/repo/{username}/{repository}/topics",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = TopicsJson,
+    responses(
+        (status = 200, description = "Topics have been changed", body = TopicsJson),
+        (status = 400, description = "Invalid topic or too many topics"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not an admin of the repository"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/topics", method = "PUT", err = "json")]
+pub(crate) async fn put_topics(uri: web::Path<GitRequest>, body: web::Json<TopicsJson>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(&uri, Some(&user), &mut transaction).await?;
+
+    if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to change the topics");
+    }
+
+    let topics = topics::normalize(body.topics.as_slice(), &mut transaction).await?;
+    topics::set(&repo, topics.as_slice(), &mut transaction).await?;
+
+    let topics = topics::for_repo(&repo, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("Topics of repo {} changed by user {}", &repo.id, &user.id);
+
+    Ok(HttpResponse::Ok().json(TopicsJson {
+        topics
+    }))
+}
+
+/// Lists the repositories tagged with `topic` the current user has access to, newest first.
+/// Paginated using the id of the last repository as cursor
+#[route("/api/topics/{topic}", method = "GET", err = "json")]
+pub(crate) async fn list_topic_repositories(topic: web::Path<String>, page: web::Query<PageQuery>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let topic = topic.to_lowercase();
+
+    if !is_valid_topic(topic.as_str()) {
+        die!(BAD_REQUEST, "Invalid topic");
+    }
+
+    let after = match page.after.as_deref().map(str::parse::<i32>) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => die!(BAD_REQUEST, "Invalid cursor"),
+        None => None
+    };
+
+    let mut transaction = db_pool.begin().await?;
+
+    let limit = page.limit(&mut transaction).await?;
+    let repositories = topics::repositories(topic.as_str(), web_user.as_ref(), after, limit as i64 + 1, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let page = Page::new(repositories, limit, |repo| repo.id.to_string());
+
+    Ok(page.respond(&request, limit))
+}
+
+async fn find_repo(uri: &GitRequest, user: Option<&User>, transaction: &mut Transaction<'_, Postgres>) -> Result<Repository> {
+    let repo_owner = User::find_using_name(&uri.username, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner.id, &uri.repository, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, user, &mut *transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    Ok(repo)
+}
+
+#[derive(Deserialize, Serialize, Component)]
+pub(crate) struct TopicsJson {
+    topics: Vec<String>
+}
@@ -0,0 +1,271 @@
+use crate::config::get_setting;
+use crate::organization::OrganizationRole;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::routes::repository::api::issues::find_repo;
+use crate::templates::plain;
+use crate::user::{User, WebUser};
+use crate::{die, err, mail, template_context};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::serde::ts_seconds;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, FromRow, PgPool, Postgres, Transaction};
+use utoipa::Component;
+
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}/transfer",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Pending transfer of the repository"),
+        (status = 404, description = "Repository not found or no transfer pending")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/transfer", method = "GET", err = "json")]
+pub(crate) async fn get_transfer(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), web_user.as_ref(), &mut transaction).await?;
+    let transfer = PendingTransfer::find(&repo, &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "No transfer pending"))?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(transfer))
+}
+
+/// Starts transferring a repository. Only the owner (or an owner of the owning organization) may do this and needs to confirm
+/// by repeating the repository name. The repository stays with its current owner until the target [accepts](accept_transfer)
+#[utoipa::path(
+    post,
+    path = "/api/repo/{username}/{repository}/transfer",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = TransferJsonRequest,
+    responses(
+        (status = 201, description = "Transfer has been started and awaits acceptance by the new owner"),
+        (status = 400, description = "Transfer has not been confirmed or the new owner is invalid"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Only the owner of a repository may transfer it"),
+        (status = 404, description = "Repository or new owner not found"),
+        (status = 409, description = "New owner already has a repository with the same name")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/transfer", method = "POST", err = "json")]
+pub(crate) async fn create_transfer(uri: web::Path<GitRequest>, body: web::Json<TransferJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !is_owner(repo.owner, &user, &mut transaction).await? {
+        die!(FORBIDDEN, "Only the owner of a repository may transfer it");
+    }
+
+    if body.confirm != repo.name {
+        die!(BAD_REQUEST, "Please confirm the transfer by entering the repository name");
+    }
+
+    let target = User::find_using_name(body.new_owner.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "New owner not found"))?;
+
+    if target.id == repo.owner {
+        die!(BAD_REQUEST, "Repository is already owned by {}", &target.username);
+    }
+
+    if target.disabled {
+        die!(BAD_REQUEST, "New owner is disabled");
+    }
+
+    if Repository::open(target.id, repo.name.as_str(), &mut transaction).await.is_some() {
+        die!(CONFLICT, "{} already has a repository named {}", &target.username, &repo.name);
+    }
+
+    sqlx::query("insert into repository_transfers (repo, target, initiator) values ($1, $2, $3) \
+        on conflict (repo) do update set target = excluded.target, initiator = excluded.initiator, created_at = now()")
+        .bind(&repo.id)
+        .bind(&target.id)
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    let transfer = PendingTransfer::find(&repo, &mut transaction).await?.ok_or_else(|| err!(INTERNAL_SERVER_ERROR, "Failed to create transfer"))?;
+
+    let domain = get_setting::<String, _>("domain", &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("User {} started transferring repo {} to {}", &user.id, &repo.id, &target.id);
+
+    // Organizations may not have a notification address, so failing to notify doesn't fail the transfer
+    let context = template_context!([
+        ("username".to_owned(), target.username.to_owned()),
+        ("initiator".to_owned(), user.username.to_owned()),
+        ("repository".to_owned(), format!("{}/{}", &uri.username, &repo.name)),
+        ("target".to_owned(), target.username.to_owned()),
+        ("link".to_owned(), format!("{}/{}/{}", domain, &uri.username, &repo.name))
+    ]);
+
+    if let Err(err) = mail::send_user_template(&target, plain::REPO_TRANSFER, &context, &db_pool).await {
+        warn!("Failed to notify {} (id {}) about transfer of repo {}: {}", &target.username, &target.id, &repo.id, err);
+    }
+
+    Ok(HttpResponse::Created().json(transfer))
+}
+
+/// Accepts a pending transfer. Needs to be done by the target user or, if the target is an organization, one of its owners
+#[utoipa::path(
+    post,
+    path = "/api/repo/{username}/{repository}/transfer/accept",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Repository has been transferred", body = TransferredJsonResponse),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Repository not found or no transfer pending")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/transfer/accept", method = "POST", err = "json")]
+pub(crate) async fn accept_transfer(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let owner = User::find_using_name(uri.username.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Not found"))?;
+    let repo = Repository::open(owner.id, uri.repository.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Not found"))?;
+
+    let transfer = PendingTransfer::find(&repo, &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "No transfer pending"))?;
+
+    if !is_owner(transfer.target, &user, &mut transaction).await? {
+        die!(NOT_FOUND, "No transfer pending");
+    }
+
+    // Teams belong to the previous owner and don't carry over
+    sqlx::query("delete from team_repositories where repo = $1")
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query("delete from repository_transfers where repo = $1")
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    let repo = repo.relocate(transfer.target, repo.name.as_str(), transaction).await?;
+
+    info!("Repo {} transferred from {} to {} (accepted by user {})", &repo.id, &owner.id, &repo.owner, &user.id);
+
+    Ok(HttpResponse::Ok().json(TransferredJsonResponse {
+        owner: transfer.target_username,
+        name: repo.name
+    }))
+}
+
+/// Cancels a pending transfer. Can be done by both sides, the current owner cancelling or the target declining it
+#[utoipa::path(
+    delete,
+    path = "/api/repo/{username}/{repository}/transfer",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 204, description = "Transfer has been cancelled"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Only the owner or the new owner may cancel a transfer"),
+        (status = 404, description = "Repository not found or no transfer pending")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/transfer", method = "DELETE", err = "json")]
+pub(crate) async fn delete_transfer(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let owner = User::find_using_name(uri.username.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Not found"))?;
+    let repo = Repository::open(owner.id, uri.repository.as_str(), &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Not found"))?;
+
+    let transfer = PendingTransfer::find(&repo, &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "No transfer pending"))?;
+
+    let allowed = is_owner(repo.owner, &user, &mut transaction).await? || is_owner(transfer.target, &user, &mut transaction).await?;
+
+    if !allowed {
+        // The target may not be able to see the repository yet, so only check access after the target has been ruled out
+        if !privilege::check_access(&repo, Some(&user), &mut transaction).await? {
+            die!(NOT_FOUND, "Not found");
+        }
+
+        die!(FORBIDDEN, "Only the owner or the new owner may cancel a transfer");
+    }
+
+    sqlx::query("delete from repository_transfers where repo = $1")
+        .bind(&repo.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Whether `user` is `owner` or an owner of the organization `owner`. Instance admins are treated as owners of everything
+async fn is_owner(owner: i32, user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<bool> {
+    if user.id == owner || user.admin {
+        return Ok(true);
+    }
+
+    let role: Option<(OrganizationRole,)> = sqlx::query_as("select role from organization_members where organization = $1 and member = $2 limit 1")
+        .bind(&owner)
+        .bind(&user.id)
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+    Ok(matches!(role, Some((OrganizationRole::Owner,))))
+}
+
+#[derive(FromRow, Serialize)]
+pub(crate) struct PendingTransfer {
+    repo: i32,
+    target: i32,
+    target_username: String,
+    initiator: Option<i32>,
+    #[serde(with = "ts_seconds")]
+    created_at: DateTime<Utc>
+}
+
+impl PendingTransfer {
+    async fn find<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, executor: E) -> Result<Option<PendingTransfer>> {
+        Ok(sqlx::query_as::<_, PendingTransfer>("select repository_transfers.*, users.username as target_username from repository_transfers \
+            inner join users on users.id = repository_transfers.target where repository_transfers.repo = $1 limit 1")
+            .bind(&repo.id)
+            .fetch_optional(executor)
+            .await?)
+    }
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct TransferJsonRequest {
+    /// Username of the user or name of the organization to transfer the repository to
+    new_owner: String,
+    /// Needs to be the repository name
+    confirm: String
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct TransferredJsonResponse {
+    owner: String,
+    name: String
+}
@@ -0,0 +1,119 @@
+use crate::notification::{self, WatchLevel};
+use crate::routes::repository::GitRequest;
+use crate::routes::repository::api::issues::find_repo;
+use crate::user::WebUser;
+use crate::die;
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::Component;
+
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}/watch",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Watch level of the current user", body = WatchJson),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/watch", method = "GET", err = "json")]
+pub(crate) async fn get_watch(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+    let level = notification::watch_level(&user, &repo, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(WatchJson {
+        level
+    }))
+}
+
+/// Starts watching the repository or changes the level of an existing watch
+#[utoipa::path(
+    put,
+    path = "/api/repo/{username}/{repository}/watch",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = WatchJsonRequest,
+    responses(
+        (status = 200, description = "Repository is being watched", body = WatchJson),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/watch", method = "PUT", err = "json")]
+pub(crate) async fn put_watch(uri: web::Path<GitRequest>, body: web::Json<WatchJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    notification::watch(&user, &repo, body.level, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("{} (id {}) is now watching repository id {} ({:?})", &user.username, &user.id, &repo.id, &body.level);
+
+    Ok(HttpResponse::Ok().json(WatchJson {
+        level: Some(body.level)
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/repo/{username}/{repository}/watch",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 204, description = "Repository is no longer watched"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Repository not found or not watched")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/watch", method = "DELETE", err = "json")]
+pub(crate) async fn delete_watch(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(uri.username.as_str(), uri.repository.as_str(), Some(&user), &mut transaction).await?;
+
+    if !notification::unwatch(&user, &repo, &mut transaction).await? {
+        die!(NOT_FOUND, "Not watching this repository");
+    }
+
+    transaction.commit().await?;
+
+    debug!("{} (id {}) stopped watching repository id {}", &user.username, &user.id, &repo.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct WatchJsonRequest {
+    level: WatchLevel
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct WatchJson {
+    /// `None` if the user is not watching the repository
+    level: Option<WatchLevel>
+}
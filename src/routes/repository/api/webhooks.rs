@@ -0,0 +1,224 @@
+use crate::crypto;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::user::{User, WebUser};
+use crate::utils::outbound_url;
+use crate::webhook::{Webhook, WebhookDelivery, WebhookEvents};
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use url::Url;
+use utoipa::Component;
+
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}/webhooks",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    responses(
+        (status = 200, description = "Webhooks of the repository"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not a repository admin"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/webhooks", method = "GET", err = "json")]
+pub(crate) async fn list_webhooks(uri: web::Path<GitRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_administrated_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    let webhooks = sqlx::query_as::<_, Webhook>("select * from webhooks where repo = $1 order by id")
+        .bind(&repo.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/repo/{username}/{repository}/webhooks",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository")
+    ),
+    request_body = CreateWebhookJsonRequest,
+    responses(
+        (status = 201, description = "Webhook has been created", body = CreateWebhookJsonResponse),
+        (status = 400, description = "Invalid url or events"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not a repository admin"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/webhooks", method = "POST", err = "json")]
+pub(crate) async fn create_webhook(uri: web::Path<GitRequest>, body: web::Json<CreateWebhookJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_administrated_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    let url = Url::parse(body.url.trim()).map_err(|_| err!(BAD_REQUEST, "Url is not valid"))?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        die!(BAD_REQUEST, "Url needs to use either http or https");
+    }
+
+    outbound_url::check(&url).await?;
+
+    let secret = match &body.secret {
+        Some(secret) if !secret.is_empty() => secret.to_owned(),
+        _ => crypto::random_numeric_ascii_string(32)
+    };
+
+    let events = body.events.map_or(WebhookEvents::PUSH, WebhookEvents::from_bits);
+
+    if events.bits() == 0 {
+        die!(BAD_REQUEST, "Webhook needs to be subscribed to at least one event");
+    }
+
+    let webhook = sqlx::query_as::<_, Webhook>("insert into webhooks (repo, url, secret, events) values ($1, $2, $3, $4) returning *")
+        .bind(&repo.id)
+        .bind(url.as_str())
+        .bind(secret.as_str())
+        .bind(events.bits())
+        .fetch_one(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("Webhook {} created for repo {} by user {}", &webhook.id, &repo.id, &user.id);
+
+    Ok(HttpResponse::Created().json(CreateWebhookJsonResponse {
+        id: webhook.id,
+        secret
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/repo/{username}/{repository}/webhooks/{id}",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository"),
+        ("id" = i32, path, description = "Id of the webhook")
+    ),
+    responses(
+        (status = 204, description = "Webhook has been deleted"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not a repository admin"),
+        (status = 404, description = "Repository or webhook not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/webhooks/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_webhook(uri: web::Path<WebhookRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_administrated_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    sqlx::query("delete from webhooks where id = $1 and repo = $2 returning id")
+        .bind(&uri.id)
+        .bind(&repo.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Webhook not found"))?;
+
+    transaction.commit().await?;
+
+    debug!("Webhook {} removed from repo {} by user {}", &uri.id, &repo.id, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/repo/{username}/{repository}/webhooks/{id}/deliveries",
+    params(
+        ("username" = String, path, description = "Owner of the repository"),
+        ("repository" = String, path, description = "Name of the repository"),
+        ("id" = i32, path, description = "Id of the webhook")
+    ),
+    responses(
+        (status = 200, description = "Last 50 deliveries of the webhook, newest first"),
+        (status = 401, description = "Not logged in"),
+        (status = 403, description = "Not a repository admin"),
+        (status = 404, description = "Repository not found")
+    ),
+    tag = "repository"
+)]
+#[route("/api/repo/{username}/{repository}/webhooks/{id}/deliveries", method = "GET", err = "json")]
+pub(crate) async fn list_deliveries(uri: web::Path<WebhookRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_administrated_repo(uri.username.as_str(), uri.repository.as_str(), &user, &mut transaction).await?;
+
+    let deliveries = sqlx::query_as::<_, WebhookDelivery>(
+        "select webhook_deliveries.* from webhook_deliveries \
+        inner join webhooks on webhooks.id = webhook_deliveries.webhook \
+        where webhooks.id = $1 and webhooks.repo = $2 \
+        order by webhook_deliveries.delivered_at desc limit 50"
+    )
+        .bind(&uri.id)
+        .bind(&repo.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+/// Webhook secrets are sensitive, so all webhook routes are only available to repository admins
+async fn find_administrated_repo(username: &str, repository: &str, user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<Repository> {
+    let repo_owner = User::find_using_name(username, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, repository, &mut *transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, Some(user), &mut *transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    if !privilege::check_admin(&repo, Some(user), &mut *transaction).await? {
+        die!(FORBIDDEN, "Only repository admins are allowed to manage webhooks");
+    }
+
+    Ok(repo)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct WebhookRequest {
+    username: String,
+    repository: String,
+    id: i32
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct CreateWebhookJsonRequest {
+    url: String,
+    /// Used to sign deliveries. A random secret is generated if not set
+    secret: Option<String>,
+    /// Bitflags of the subscribed events, defaults to push events only
+    events: Option<i32>
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct CreateWebhookJsonResponse {
+    id: i32,
+    secret: String
+}
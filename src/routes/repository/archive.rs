@@ -1,194 +1,349 @@
-use crate::git::utils::{read_raw_blob_content, repo_files_at_ref};
-use crate::privileges::privilege;
-use crate::repository::Repository;
-use crate::routes::repository::GitTreeRequest;
-use crate::user::{User, WebUser};
+use crate::routes::repository::{find_readable_repo, GitTreeRequest};
+use crate::user::WebUser;
+use crate::utils::{etag, glob, stream};
 use crate::{die, err};
 
-use std::borrow::Borrow;
-use std::io::{Cursor, Write};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
 use std::path::Path;
-use std::sync::Arc;
 
-use actix_web::http::header::CONTENT_DISPOSITION;
-use actix_web::{HttpResponse, Responder, web};
+use actix_web::http::header::{CONTENT_DISPOSITION, LOCATION};
+use actix_web::{Either, HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use async_compression::tokio::write::GzipEncoder;
-use async_recursion::async_recursion;
-use bstr::ByteSlice;
-use git_repository::objs::tree::EntryMode;
-use git_repository::objs::Tree;
-use git_repository::odb::pack::FindExt;
-use git_repository::odb::Store;
-use git_repository::refs::file::find::existing::Error as GitoxideFindError;
+use git2::{Oid, Repository as Git2Repository, Tree, TreeWalkMode, TreeWalkResult};
 use gitarena_macros::route;
+use log::warn;
+use serde::Deserialize;
 use sqlx::PgPool;
-use tokio_tar::{Builder as TarBuilder, Header as TarHeader};
+use tokio::io::AsyncWriteExt;
+use tokio_tar::{Builder as TarBuilder, EntryType, Header as TarHeader};
 use zip::write::FileOptions as ZipFileOptions;
 use zip::ZipWriter;
 
-#[route("/{username}/{repository}/tree/{tree:.*}/archive/targz", method = "GET", err = "html")]
-pub(crate) async fn tar_gz_file(uri: web::Path<GitTreeRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+#[route("/{username}/{repository}/archive/{archive:.*}", method = "GET", err = "html")]
+pub(crate) async fn archive(uri: web::Path<ArchiveRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let (reference, format) = if let Some(reference) = uri.archive.strip_suffix(".tar.gz") {
+        (reference, ArchiveFormat::TarGz)
+    } else if let Some(reference) = uri.archive.strip_suffix(".zip") {
+        (reference, ArchiveFormat::Zip)
+    } else {
+        die!(NOT_FOUND, "Unsupported archive format");
+    };
+
     let mut transaction = db_pool.begin().await?;
 
-    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
-    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = match find_readable_repo(uri.username.as_str(), uri.repository.as_str(), web_user, &request, &mut transaction).await? {
+        Either::Left(repo) => repo,
+        Either::Right(response) => return Ok(response)
+    };
 
-    if !privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
-        die!(NOT_FOUND, "Not found");
-    }
+    let git2_repo = repo.libgit2(&mut transaction).await?;
 
-    let gitoxide_repo = repo.gitoxide(&mut transaction).await?;
+    transaction.commit().await?;
 
-    let loose_ref = match gitoxide_repo.refs.find_loose(&uri.tree) {
-        Ok(loose_ref) => Ok(loose_ref),
-        Err(GitoxideFindError::Find(err)) => Err(err),
-        Err(GitoxideFindError::NotFound(_)) => die!(NOT_FOUND, "Not found")
-    }?; // Handle 404
+    let commit_oid = git2_repo.revparse_single(reference)
+        .and_then(|object| object.peel_to_commit())
+        .map(|commit| commit.id())
+        .map_err(|_| err!(NOT_FOUND, "Ref not found"))?;
 
-    let mut buffer = Vec::<u8>::new();
+    // The archive contains the commit id (prefix) and time (mtime), so the tree id alone is not enough to identify it
+    let entity_tag = etag::strong(commit_oid);
 
-    let store = gitoxide_repo.objects.clone();
+    if let Some(response) = etag::not_modified(&request, &repo, entity_tag.as_str()) {
+        return Ok(response);
+    }
 
-    let tree = repo_files_at_ref(&loose_ref, store.clone(), &gitoxide_repo, &mut buffer).await?;
-    let tree = Tree::from(tree);
+    // Matches the naming used by GitHub: `{repo}-{short sha}`
+    let prefix = format!("{}-{}", &repo.name, &commit_oid.to_string()[..7]);
+    let filename = format!("{}.{}", prefix, format.extension());
+
+    let response = match format {
+        ArchiveFormat::TarGz => {
+            let (writer, reader) = tokio::io::duplex(stream::CHUNK_SIZE);
+
+            // Reading blobs and compressing them blocks, so the archive gets written on the blocking thread pool while the
+            // response is being streamed. The pipe, encoder and tar builder do not depend on a runtime, so they can be driven there
+            actix_web::rt::spawn(async move {
+                match web::block(move || futures::executor::block_on(write_tar_gz(git2_repo, commit_oid, prefix, writer))).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => warn!("Failed to write tar.gz archive: {}", err),
+                    Err(err) => warn!("Failed to write tar.gz archive: {}", err)
+                }
+            });
 
-    let mut builder = TarBuilder::new(Vec::new());
-    write_directory_tar(store.clone(), tree, Path::new("."), &mut builder, &mut buffer).await?;
+            let mut builder = HttpResponse::Ok();
+            etag::apply(&mut builder, &repo, entity_tag.as_str());
 
-    let tar_data = builder.into_inner().await?;
+            builder.content_type("application/gzip")
+                .append_header((CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)))
+                .streaming(stream::read_stream(reader))
+        }
+        ArchiveFormat::Zip => {
+            // Zip files require seeking to write their central directory, so they get spooled to a temporary file instead of memory
+            let file = web::block(move || write_zip(&git2_repo, commit_oid, prefix.as_str())).await??;
 
-    let encoder = GzipEncoder::new(tar_data);
-    let gzip_data = encoder.into_inner();
+            let mut builder = HttpResponse::Ok();
+            etag::apply(&mut builder, &repo, entity_tag.as_str());
 
-    Ok(HttpResponse::Ok()
-        .append_header((CONTENT_DISPOSITION, format!("attachment; filename=\"{}.tar.gz\"", &repo.name)))
-        .body(gzip_data))
-}
+            builder.content_type("application/zip")
+                .append_header((CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)))
+                .streaming(stream::read_stream(tokio::fs::File::from_std(file)))
+        }
+    };
 
-#[async_recursion(?Send)]
-async fn write_directory_tar(store: Arc<Store>, tree: Tree, path: &Path, builder: &mut TarBuilder<Vec<u8>>, buffer: &mut Vec<u8>) -> Result<()> {
-    for entry in tree.entries {
-        let filename = entry.filename.to_str()?;
-        let path = path.join(filename);
+    Ok(response)
+}
 
-        match entry.mode {
-            EntryMode::Tree => {
-                let (tree_ref, _) = store.to_cache_arc().find_tree(entry.oid.as_ref(), buffer)?;
-                let tree = Tree::from(tree_ref);
+#[route("/{username}/{repository}/tree/{tree:.*}/archive/targz", method = "GET", err = "html")]
+pub(crate) async fn tar_gz_file(uri: web::Path<GitTreeRequest>) -> Result<impl Responder> {
+    Ok(HttpResponse::MovedPermanently()
+        .append_header((LOCATION, format!("/{}/{}/archive/{}.tar.gz", &uri.username, &uri.repository, &uri.tree)))
+        .finish())
+}
 
-                write_directory_tar(store.clone(), tree, path.as_path(), builder, buffer).await?;
-            }
-            EntryMode::Blob | EntryMode::BlobExecutable | EntryMode::Link => {
-                let content = read_raw_blob_content(entry.oid.as_ref(), store.clone()).await?;
+#[route("/{username}/{repository}/tree/{tree:.*}/archive/zip", method = "GET", err = "html")]
+pub(crate) async fn zip_file(uri: web::Path<GitTreeRequest>) -> Result<impl Responder> {
+    Ok(HttpResponse::MovedPermanently()
+        .append_header((LOCATION, format!("/{}/{}/archive/{}.zip", &uri.username, &uri.repository, &uri.tree)))
+        .finish())
+}
 
-                let mut header = TarHeader::new_gnu();
-                header.set_size(content.len() as u64);
+async fn write_tar_gz(repo: Git2Repository, commit_oid: Oid, prefix: String, writer: tokio::io::DuplexStream) -> Result<()> {
+    let commit = repo.find_commit(commit_oid)?;
+    let tree = commit.tree()?;
+    let mtime = commit.time().seconds().max(0) as u64;
 
-                header.set_mode(if matches!(entry.mode, EntryMode::BlobExecutable) {
-                    0o775
-                } else {
-                    0o664
-                });
+    let mut builder = TarBuilder::new(GzipEncoder::new(writer));
 
-                header.set_uid(0);
-                header.set_gid(0);
-                header.set_username("gitarena")?;
-                header.set_groupname("gitarena")?;
+    for entry in collect_entries(&repo, &tree)? {
+        let path = format!("{}/{}", prefix, entry.path);
 
-                header.set_device_major(0)?;
-                header.set_device_minor(0)?;
+        let mut header = TarHeader::new_gnu();
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("gitarena")?;
+        header.set_groupname("gitarena")?;
 
-                header.set_mtime(0); // TODO: Unix timestamp of last commit to this file
+        match entry.kind {
+            EntryKind::Directory => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o775);
+                header.set_size(0);
+                header.set_cksum();
 
-                if matches!(entry.mode, EntryMode::Link) {
-                    let cow = String::from_utf8_lossy(&content[..]);
-                    let borrow: &str = cow.borrow();
+                builder.append_data(&mut header, format!("{}/", path), tokio::io::empty()).await?;
+            }
+            EntryKind::File { executable } => {
+                let blob = repo.find_blob(entry.oid)?;
 
-                    header.set_link_name(Path::new(borrow))?;
-                }
+                header.set_mode(if executable { 0o775 } else { 0o664 });
+                header.set_size(blob.content().len() as u64);
+                header.set_cksum();
 
+                builder.append_data(&mut header, path, blob.content()).await?;
+            }
+            EntryKind::Symlink => {
+                let blob = repo.find_blob(entry.oid)?;
+                let target = String::from_utf8_lossy(blob.content()).into_owned();
+
+                header.set_entry_type(EntryType::Symlink);
+                header.set_mode(0o777);
+                header.set_size(0);
+                header.set_link_name(Path::new(target.as_str()))?;
                 header.set_cksum();
 
-                builder.append_data(&mut header, path.as_path(), &content[..]).await?;
+                builder.append_data(&mut header, path, tokio::io::empty()).await?;
             }
-            EntryMode::Commit => { /* TODO: implement submodules */ }
         }
     }
 
+    let mut encoder = builder.into_inner().await?;
+    encoder.shutdown().await?;
+
     Ok(())
 }
 
-#[route("/{username}/{repository}/tree/{tree:.*}/archive/zip", method = "GET", err = "html")]
-pub(crate) async fn zip_file(uri: web::Path<GitTreeRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
-    let mut transaction = db_pool.begin().await?;
+fn write_zip(repo: &Git2Repository, commit_oid: Oid, prefix: &str) -> Result<File> {
+    let commit = repo.find_commit(commit_oid)?;
+    let tree = commit.tree()?;
+
+    let mut writer = ZipWriter::new(tempfile::tempfile()?);
 
-    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
-    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    for entry in collect_entries(repo, &tree)? {
+        let path = format!("{}/{}", prefix, entry.path);
 
-    if !privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
-        die!(NOT_FOUND, "Not found");
+        match entry.kind {
+            EntryKind::Directory => writer.add_directory(path, ZipFileOptions::default())?,
+            EntryKind::File { executable } => {
+                let blob = repo.find_blob(entry.oid)?;
+                let content = blob.content();
+
+                let options = ZipFileOptions::default()
+                    .unix_permissions(if executable { 0o775 } else { 0o664 })
+                    .large_file(content.len() >= 4294967000); // 4 GiB
+
+                writer.start_file(path, options)?;
+                writer.write_all(content)?;
+            }
+            // zip 0.5 masks the unix mode to its permission bits, so entries can't be marked as symlinks. Writing them as regular
+            // files would extract the link target as file content, so they are left out of zip archives. tar.gz archives keep them
+            EntryKind::Symlink => {}
+        }
     }
 
-    let gitoxide_repo = repo.gitoxide(&mut transaction).await?;
+    let mut file = writer.finish()?;
+    file.seek(SeekFrom::Start(0))?;
 
-    let loose_ref = match gitoxide_repo.refs.find_loose(&uri.tree) {
-        Ok(loose_ref) => Ok(loose_ref),
-        Err(GitoxideFindError::Find(err)) => Err(err),
-        Err(GitoxideFindError::NotFound(_)) => die!(NOT_FOUND, "Not found")
-    }?; // Handle 404
+    Ok(file)
+}
 
-    let mut buffer = Vec::<u8>::new();
-    let store = gitoxide_repo.objects.clone();
+/// Collects all entries of `tree` recursively, skipping paths marked as `export-ignore` in the top-level `.gitattributes`.
+/// Only metadata is collected here, blob contents are read one at a time while writing the archive
+fn collect_entries(repo: &Git2Repository, tree: &Tree<'_>) -> Result<Vec<ArchiveEntry>> {
+    let ignored = export_ignore_patterns(repo, tree)?;
+    let mut entries = Vec::new();
+
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        let name = match entry.name() {
+            Some(name) => name,
+            None => return TreeWalkResult::Skip
+        };
+
+        let path = format!("{}{}", root, name);
+
+        let kind = match entry.filemode() {
+            0o040000 => EntryKind::Directory,
+            0o100644 => EntryKind::File { executable: false },
+            0o100755 => EntryKind::File { executable: true },
+            0o120000 => EntryKind::Symlink,
+            _ => return TreeWalkResult::Skip // Submodules
+        };
+
+        if is_export_ignored(ignored.as_slice(), path.as_str(), matches!(kind, EntryKind::Directory)) {
+            return TreeWalkResult::Skip;
+        }
 
-    let tree = repo_files_at_ref(&loose_ref, store.clone(), &gitoxide_repo, &mut buffer).await?;
-    let tree = Tree::from(tree);
+        entries.push(ArchiveEntry {
+            path,
+            oid: entry.id(),
+            kind
+        });
 
-    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
-    write_directory_zip(store.clone(), tree, Path::new(""), &mut writer, &mut buffer).await?;
+        TreeWalkResult::Ok
+    })?;
 
-    let cursor = writer.finish()?;
-    let data = cursor.into_inner();
+    Ok(entries)
+}
 
-    Ok(HttpResponse::Ok()
-        .append_header((CONTENT_DISPOSITION, format!("attachment; filename=\"{}.zip\"", &repo.name)))
-        .body(data))
+fn export_ignore_patterns(repo: &Git2Repository, tree: &Tree<'_>) -> Result<Vec<String>> {
+    let entry = match tree.get_name(".gitattributes") {
+        Some(entry) => entry,
+        None => return Ok(Vec::new())
+    };
+
+    let blob = match entry.to_object(repo)?.into_blob() {
+        Ok(blob) => blob,
+        Err(_) => return Ok(Vec::new())
+    };
+
+    Ok(String::from_utf8_lossy(blob.content())
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut split = line.split_whitespace();
+            let pattern = split.next()?;
+
+            split.any(|attribute| attribute == "export-ignore").then(|| pattern.to_owned())
+        })
+        .collect())
 }
 
-#[async_recursion(?Send)]
-async fn write_directory_zip(store: Arc<Store>, tree: Tree, path: &Path, writer: &mut ZipWriter<Cursor<Vec<u8>>>, buffer: &mut Vec<u8>) -> Result<()> {
-    for entry in tree.entries {
-        let filename = entry.filename.to_str()?;
-        let path_buffer = path.join(filename);
-        let path = path_buffer.as_path();
+/// Matches `path` against `.gitattributes` patterns: Patterns without a slash match the file name at any depth,
+/// patterns with a slash are relative to the repository root and patterns ending with a slash only match directories
+fn is_export_ignored(patterns: &[String], path: &str, is_directory: bool) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
 
-        match entry.mode {
-            EntryMode::Tree => {
-                let (tree_ref, _) = store.to_cache_arc().find_tree(entry.oid.as_ref(), buffer)?;
-                let tree = Tree::from(tree_ref);
+    patterns.iter().any(|pattern| {
+        let (pattern, directory_only) = match pattern.strip_suffix('/') {
+            Some(stripped) => (stripped, true),
+            None => (pattern.as_str(), false)
+        };
 
-                writer.add_directory(format!("{}", path.display()), ZipFileOptions::default())?;
+        if directory_only && !is_directory {
+            return false;
+        }
 
-                write_directory_zip(store.clone(), tree, path, writer, buffer).await?;
-            }
-            EntryMode::Blob | EntryMode::BlobExecutable => {
-                let content = read_raw_blob_content(entry.oid.as_ref(), store.clone()).await?;
+        if pattern.contains('/') {
+            glob::matches(pattern.trim_start_matches('/'), path)
+        } else {
+            glob::matches(pattern, name)
+        }
+    })
+}
 
-                let options = ZipFileOptions::default()
-                    .unix_permissions(if matches!(entry.mode, EntryMode::BlobExecutable) {
-                        0o775
-                    } else {
-                        0o664
-                    })
-                    .large_file(content.len() >= 4294967000); // 4 GiB
-                    //.last_modified_time(...) TODO: DateTime of last commit to this file
+#[derive(Deserialize)]
+pub(crate) struct ArchiveRequest {
+    username: String,
+    repository: String,
+    archive: String
+}
 
-                writer.start_file(format!("{}", path.display()), options)?;
-                writer.write_all(&content[..])?;
-            }
-            EntryMode::Link | EntryMode::Commit => { /* TODO: implement symlinks and submodules */ }
+enum ArchiveFormat {
+    TarGz,
+    Zip
+}
+
+impl ArchiveFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip"
         }
     }
+}
 
-    Ok(())
+struct ArchiveEntry {
+    path: String,
+    oid: Oid,
+    kind: EntryKind
+}
+
+enum EntryKind {
+    Directory,
+    File { executable: bool },
+    Symlink
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_zip;
+
+    use git2::{Repository as Git2Repository, Signature};
+    use zip::ZipArchive;
+
+    #[test]
+    fn zip_omits_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Git2Repository::init(dir.path()).unwrap();
+
+        let readme = repo.blob(b"Hello world").unwrap();
+        let target = repo.blob(b"README.md").unwrap();
+
+        let mut builder = repo.treebuilder(None).unwrap();
+        builder.insert("README.md", readme, 0o100644).unwrap();
+        builder.insert("link", target, 0o120000).unwrap();
+
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::now("GitArena", "test@gitarena.com").unwrap();
+        let commit_oid = repo.commit(None, &signature, &signature, "Initial commit", &tree, &[]).unwrap();
+
+        let mut archive = ZipArchive::new(write_zip(&repo, commit_oid, "test").unwrap()).unwrap();
+
+        assert!(archive.by_name("test/README.md").is_ok());
+        assert!(archive.by_name("test/link").is_err());
+        assert_eq!(archive.len(), 1);
+    }
 }
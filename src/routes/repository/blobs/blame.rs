@@ -0,0 +1,121 @@
+use crate::git::blame::blame;
+use crate::git::history::{all_branches, all_tags};
+use crate::prelude::{ContextExtensions, LibGit2SignatureExtensions, LibGit2TimeExtensions};
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::blobs::BlobRequest;
+use crate::templates::web::{BlameGroup, GitCommit};
+use crate::user::{User, WebUser};
+use crate::{die, err, render_template};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use actix_web::{Responder, web};
+use anyhow::Result;
+use git2::{ObjectType, Oid, Signature, Time};
+use gitarena_macros::route;
+use sqlx::PgPool;
+use tera::Context;
+
+#[route("/{username}/{repository}/blame/{tree}/{blob:.*}", method = "GET", err = "html")]
+pub(crate) async fn view_blame(uri: web::Path<BlobRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+    let repo = Repository::open(repo_owner, &uri.repository, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
+
+    if !privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
+        die!(NOT_FOUND, "Not found");
+    }
+
+    let libgit2_repo = repo.libgit2(&mut transaction).await?;
+
+    let commit = libgit2_repo.revparse_single(uri.tree.as_str())
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|_| err!(NOT_FOUND, "Not found"))?;
+
+    let entry = commit.tree()?.get_path(Path::new(uri.blob.as_str())).map_err(|_| err!(NOT_FOUND, "Not found"))?;
+
+    if entry.kind() != Some(ObjectType::Blob) {
+        die!(NOT_FOUND, "Not found");
+    }
+
+    let blob = libgit2_repo.find_blob(entry.id())?;
+
+    let mut context = Context::new();
+
+    // Blaming may take a while, so the transaction is not held open in the meantime
+    transaction.commit().await?;
+
+    // Same limit as for displaying files
+    let hunks = if blob.is_binary() || blob.size() >= 2_000_000 {
+        None
+    } else {
+        let hunks = blame(&repo, commit.id(), blob.id(), uri.blob.as_str(), db_pool.get_ref()).await?;
+        context.try_insert("pending", &hunks.is_none())?;
+
+        hunks
+    };
+
+    let mut transaction = db_pool.begin().await?;
+
+    let content = String::from_utf8_lossy(blob.content());
+    let lines = content.lines().collect::<Vec<_>>();
+
+    if let Some(hunks) = hunks {
+        let author_emails = hunks.iter().map(|hunk| hunk.author_email.as_str()).collect::<Vec<_>>();
+        let authors = User::find_using_emails(author_emails.as_slice(), &mut transaction).await?;
+
+        let mut messages = HashMap::<&str, String>::new();
+        let mut groups = Vec::<BlameGroup<'_>>::with_capacity(hunks.len());
+
+        for hunk in hunks.iter() {
+            let time = Time::new(hunk.time, hunk.offset);
+
+            let (author_name, author_uid, author_email) = Signature::new(hunk.author_name.as_str(), hunk.author_email.as_str(), &time)
+                .map(|signature| signature.disassemble_with(&authors))
+                .unwrap_or_else(|_| (hunk.author_name.clone(), None, hunk.author_email.clone()));
+
+            if !messages.contains_key(hunk.commit.as_str()) {
+                let message = libgit2_repo.find_commit(Oid::from_str(hunk.commit.as_str())?)?.message().unwrap_or_default().to_owned();
+                messages.insert(hunk.commit.as_str(), message);
+            }
+
+            let start = hunk.start_line.saturating_sub(1).min(lines.len());
+            let end = (start + hunk.lines).min(lines.len());
+
+            groups.push(BlameGroup {
+                commit: GitCommit {
+                    oid: hunk.commit.clone(),
+                    message: messages[hunk.commit.as_str()].clone(),
+                    time: hunk.time,
+                    date: Some(time.try_as_chrono()?),
+                    author_name,
+                    author_uid,
+                    author_email,
+                    verification: None,
+                    status: None
+                },
+                start_line: start + 1,
+                lines: &lines[start..end]
+            });
+        }
+
+        context.try_insert("groups", &groups)?;
+    }
+
+    context.insert_web_user(&web_user)?;
+    context.try_insert("repo_owner_name", uri.username.as_str())?;
+    context.try_insert("repo", &repo)?;
+
+    context.try_insert("tree", uri.tree.as_str())?;
+    context.try_insert("branches", &all_branches(&libgit2_repo).await?)?;
+    context.try_insert("tags", &all_tags(&libgit2_repo, None).await?)?;
+
+    context.try_insert("name", entry.name().unwrap_or_default())?;
+    context.try_insert("full_path", uri.blob.as_str())?;
+    context.try_insert("line_count", &lines.len())?;
+
+    render_template!("repo/blob/blame.html", context, transaction)
+}
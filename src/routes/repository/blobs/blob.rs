@@ -1,5 +1,7 @@
+use crate::config::get_setting;
 use crate::git::history::{all_branches, all_tags, last_commit_for_blob};
 use crate::git::utils::{read_blob_content, repo_files_at_ref};
+use crate::markdown::LinkTarget;
 use crate::prelude::{ContextExtensions, LibGit2SignatureExtensions};
 use crate::privileges::privilege;
 use crate::repository::Repository;
@@ -7,15 +9,17 @@ use crate::routes::repository::blobs::BlobRequest;
 use crate::templates::web::{GitCommit, RepoFile};
 use crate::user::{User, WebUser};
 use crate::utils::cookie_file::{CookieExtensions, FileType};
-use crate::{die, err, render_template};
+use crate::utils::etag;
+use crate::{die, err, highlight, markdown, render_template};
 
 use std::sync::Arc;
 
 use actix_web::http::header::CONTENT_TYPE;
-use actix_web::{HttpResponse, Responder, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use async_recursion::async_recursion;
 use bstr::ByteSlice;
+use git_repository::hash::ObjectId;
 use git_repository::objs::tree::EntryMode;
 use git_repository::objs::{Tree, TreeRef};
 use git_repository::odb::pack::FindExt;
@@ -25,12 +29,13 @@ use git_repository::refs::file::loose::Reference;
 use git_repository::Repository as GitoxideRepository;
 use gitarena_macros::route;
 use magic::Cookie;
+use serde::Deserialize;
 use sqlx::PgPool;
 use tera::Context;
 use tracing_unwrap::OptionExt;
 
 #[route("/{username}/{repository}/tree/{tree}/blob/{blob:.*}", method = "GET", err = "html")]
-pub(crate) async fn view_blob(uri: web::Path<BlobRequest>, web_user: WebUser, cookie: web::Data<Arc<Cookie>>, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+pub(crate) async fn view_blob(uri: web::Path<BlobRequest>, query: web::Query<BlobViewQuery>, web_user: WebUser, cookie: web::Data<Arc<Cookie>>, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let mut transaction = db_pool.begin().await?;
 
     let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
@@ -57,7 +62,7 @@ pub(crate) async fn view_blob(uri: web::Path<BlobRequest>, web_user: WebUser, co
     let store = gitoxide_repo.objects.clone();
 
     let tree_ref = repo_files_at_ref(&loose_ref, store.clone(), &gitoxide_repo, &mut buffer).await?;
-    let (name, content, mode) = recursively_visit_blob_content(&loose_ref, tree_ref, uri.blob.as_str(), &gitoxide_repo, store.clone(), &mut blob_buffer).await?;
+    let (name, content, mode, blob_oid) = recursively_visit_blob_content(&loose_ref, tree_ref, uri.blob.as_str(), &gitoxide_repo, store.clone(), &mut blob_buffer).await?;
 
     let oid = last_commit_for_blob(&libgit2_repo, full_tree_name, uri.blob.as_str()).await?.unwrap_or_log();
     let commit = libgit2_repo.find_commit(oid)?;
@@ -76,7 +81,9 @@ pub(crate) async fn view_blob(uri: web::Path<BlobRequest>, web_user: WebUser, co
             date: None,
             author_name,
             author_uid,
-            author_email
+            author_email,
+            verification: None,
+            status: None
         }
     })?;
 
@@ -86,9 +93,29 @@ pub(crate) async fn view_blob(uri: web::Path<BlobRequest>, web_user: WebUser, co
     context.try_insert("type", &file_type)?;
     context.try_insert("size", &size)?;
 
-    // We only display text files which are less than 2 MB
-    if matches!(file_type, FileType::Text) && size < 2_000_000 {
-        context.try_insert("content", content.as_str())?;
+    let max_size = get_setting::<i32, _>("repositories.highlight_max_size", &mut transaction).await?;
+    context.try_insert("max_size", &max_size)?;
+
+    // Larger files and binaries are not displayed at all, as highlighting them could take ages
+    if matches!(file_type, FileType::Text) && size <= max_size.max(0) as usize {
+        let is_markdown = markdown::is_markdown(name.as_str());
+        let show_source = query.view.as_deref() == Some("source");
+
+        context.try_insert("markdown", &is_markdown)?;
+
+        if is_markdown && !show_source {
+            let directory = uri.blob.rsplit_once('/').map_or("", |(directory, _)| directory);
+
+            context.try_insert("rendered", &markdown::render(content.as_str(), &LinkTarget {
+                username: uri.username.as_str(),
+                repository: uri.repository.as_str(),
+                tree: uri.tree.as_str(),
+                directory
+            }))?;
+        } else {
+            let lines = highlight::highlight(blob_oid, uri.blob.as_str(), content.as_str()).await?;
+            context.try_insert("lines", lines.as_slice())?;
+        }
     }
 
     context.insert_web_user(&web_user)?;
@@ -106,7 +133,7 @@ pub(crate) async fn view_blob(uri: web::Path<BlobRequest>, web_user: WebUser, co
 }
 
 #[route("/{username}/{repository}/tree/{tree}/~blob/{blob:.*}", method = "GET", err = "text")]
-pub(crate) async fn view_raw_blob(uri: web::Path<BlobRequest>, web_user: WebUser, cookie: web::Data<Arc<Cookie>>, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+pub(crate) async fn view_raw_blob(uri: web::Path<BlobRequest>, web_user: WebUser, cookie: web::Data<Arc<Cookie>>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let mut transaction = db_pool.begin().await?;
 
     let repo_owner = User::find_using_name(&uri.username, &mut transaction).await.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?;
@@ -130,7 +157,15 @@ pub(crate) async fn view_raw_blob(uri: web::Path<BlobRequest>, web_user: WebUser
     let store = gitoxide_repo.objects.clone();
 
     let tree_ref = repo_files_at_ref(&loose_ref, store.clone(), &gitoxide_repo, &mut buffer).await?;
-    let (_, content, _) = recursively_visit_blob_content(&loose_ref, tree_ref, uri.blob.as_str(), &gitoxide_repo, store.clone(), &mut blob_buffer).await?;
+    let (_, content, _, oid) = recursively_visit_blob_content(&loose_ref, tree_ref, uri.blob.as_str(), &gitoxide_repo, store.clone(), &mut blob_buffer).await?;
+
+    transaction.commit().await?;
+
+    let entity_tag = etag::strong(oid);
+
+    if let Some(response) = etag::not_modified(&request, &repo, entity_tag.as_str()) {
+        return Ok(response);
+    }
 
     let mime = if let Some(file_type) = infer::get(content.as_bytes()) {
         file_type.mime_type()
@@ -141,13 +176,14 @@ pub(crate) async fn view_raw_blob(uri: web::Path<BlobRequest>, web_user: WebUser
         }
     };
 
-    transaction.commit().await?;
+    let mut response = HttpResponse::Ok();
+    etag::apply(&mut response, &repo, entity_tag.as_str());
 
-    Ok(HttpResponse::Ok().insert_header((CONTENT_TYPE, mime)).body(content))
+    Ok(response.insert_header((CONTENT_TYPE, mime)).body(content))
 }
 
 #[async_recursion(?Send)]
-async fn recursively_visit_blob_content<'a>(reference: &Reference, tree_ref: TreeRef<'a>, path: &str, repo: &'a GitoxideRepository, store: Arc<Store>, buffer: &'a mut Vec<u8>) -> Result<(String, String, EntryMode)> {
+async fn recursively_visit_blob_content<'a>(reference: &Reference, tree_ref: TreeRef<'a>, path: &str, repo: &'a GitoxideRepository, store: Arc<Store>, buffer: &'a mut Vec<u8>) -> Result<(String, String, EntryMode, ObjectId)> {
     let tree = Tree::from(tree_ref);
     let (search, remaining) = path.split_once('/').map_or_else(|| (path, None), |(a, b)| (a, Some(b)));
 
@@ -174,7 +210,13 @@ async fn recursively_visit_blob_content<'a>(reference: &Reference, tree_ref: Tre
 
             let file_name = entry.filename.to_str().unwrap_or("Invalid file name");
 
-            Ok((file_name.to_owned(), read_blob_content(entry.oid.as_ref(), store).await?, entry.mode))
+            Ok((file_name.to_owned(), read_blob_content(entry.oid.as_ref(), store).await?, entry.mode, entry.oid))
         }
     }
 }
+
+#[derive(Deserialize)]
+pub(crate) struct BlobViewQuery {
+    /// `source` shows Markdown files as text instead of rendering them
+    view: Option<String>
+}
@@ -106,7 +106,9 @@ pub(crate) async fn view_dir(uri: web::Path<BlobRequest>, web_user: WebUser, db_
                 date: None,
                 author_name: String::new(), // Unused for file listing
                 author_uid: None, // Unused for file listing
-                author_email: String::new() // Unused for file listing
+                author_email: String::new(), // Unused for file listing
+                verification: None,
+                status: None
             }
         });
     }
@@ -147,7 +149,9 @@ pub(crate) async fn view_dir(uri: web::Path<BlobRequest>, web_user: WebUser, db_
         date: None,
         author_name,
         author_uid,
-        author_email
+        author_email,
+        verification: None,
+        status: None
     })?;
 
     render_template!("repo/blob/directory.html", context, transaction)
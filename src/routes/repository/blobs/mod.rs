@@ -1,13 +1,17 @@
 use actix_web::web::ServiceConfig;
 use serde::Deserialize;
 
+mod blame;
 mod blob;
 mod directory;
+mod raw;
 
 pub(crate) fn init(config: &mut ServiceConfig) {
+    config.service(blame::view_blame);
     config.service(blob::view_blob);
     config.service(blob::view_raw_blob);
     config.service(directory::view_dir);
+    config.service(raw::raw);
 }
 
 #[derive(Deserialize)]
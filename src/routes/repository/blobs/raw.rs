@@ -0,0 +1,111 @@
+use crate::config::get_setting;
+use crate::routes::repository::blobs::BlobRequest;
+use crate::routes::repository::find_readable_repo;
+use crate::user::WebUser;
+use crate::utils::{etag, stream};
+use crate::{die, err};
+
+use std::path::Path;
+use std::process::Stdio;
+
+use actix_files::file_extension_to_mime;
+use actix_web::http::header::{CONTENT_DISPOSITION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use actix_web::{Either, HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use git2::ObjectType;
+use gitarena_macros::route;
+use sqlx::PgPool;
+use tokio::process::Command;
+
+#[route("/{username}/{repository}/raw/{tree}/{blob:.*}", method = "GET", err = "text")]
+pub(crate) async fn raw(uri: web::Path<BlobRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = match find_readable_repo(uri.username.as_str(), uri.repository.as_str(), web_user, &request, &mut transaction).await? {
+        Either::Left(repo) => repo,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let stream_threshold = get_setting::<i32, _>("repositories.raw_stream_threshold", &mut transaction).await?;
+    let repo_path = repo.get_fs_path(&mut transaction).await?;
+    let git2_repo = repo.libgit2(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let tree = git2_repo.revparse_single(uri.tree.as_str())
+        .and_then(|object| object.peel_to_tree())
+        .map_err(|_| err!(NOT_FOUND, "Ref not found"))?;
+
+    let entry = tree.get_path(Path::new(uri.blob.as_str())).map_err(|_| err!(NOT_FOUND, "File not found"))?;
+
+    if entry.kind() != Some(ObjectType::Blob) {
+        die!(NOT_FOUND, "File not found");
+    }
+
+    // Blobs are content addressed, so their id is a strong validator
+    let entity_tag = etag::strong(entry.id());
+
+    if let Some(response) = etag::not_modified(&request, &repo, entity_tag.as_str()) {
+        return Ok(response);
+    }
+
+    let (size, _) = git2_repo.odb()?.read_header(entry.id())?;
+    let (content_type, inline) = content_type_for(uri.blob.as_str());
+    let file_name = entry.name().unwrap_or("file").replace('"', "");
+
+    let mut response = HttpResponse::Ok();
+    response.insert_header((CONTENT_TYPE, content_type))
+        .insert_header((X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .insert_header((CONTENT_SECURITY_POLICY, "default-src 'none'; style-src 'unsafe-inline'; sandbox"))
+        .insert_header((CONTENT_DISPOSITION, format!("{}; filename=\"{}\"", if inline { "inline" } else { "attachment" }, file_name)));
+
+    etag::apply(&mut response, &repo, entity_tag.as_str());
+
+    if size <= stream_threshold.max(0) as usize {
+        let blob = git2_repo.find_blob(entry.id())?;
+
+        return Ok(response.body(blob.content().to_vec()));
+    }
+
+    // libgit2 can not stream objects out of pack files, so large blobs are streamed from Git itself instead of being loaded into memory
+    let mut child = Command::new("git")
+        .args(&["cat-file", "blob", entry.id().to_string().as_str()])
+        .current_dir(repo_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdout = child.stdout.take().ok_or_else(|| err!(INTERNAL_SERVER_ERROR, "Failed to read blob"))?;
+
+    // Reap the process once it exited, the response keeps streaming from its stdout
+    actix_web::rt::spawn(async move {
+        let _ = child.wait().await;
+    });
+
+    Ok(response.streaming(stream::read_stream(stdout)))
+}
+
+/// Returns the content type for `path` based on its extension and whenever it can be safely displayed inline.
+/// Types which browsers would execute (such as HTML or SVG) are served as plain text instead
+fn content_type_for(path: &str) -> (String, bool) {
+    let extension = match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension,
+        None => return ("application/octet-stream".to_owned(), false)
+    };
+
+    let mime = file_extension_to_mime(extension);
+    let essence = mime.essence_str();
+
+    if essence == "text/html" || essence == "image/svg+xml" || essence.contains("javascript") || essence.ends_with("xml") {
+        return ("text/plain; charset=utf-8".to_owned(), true);
+    }
+
+    match mime.type_().as_str() {
+        "text" => (format!("{}; charset=utf-8", essence), true),
+        "image" | "audio" | "video" => (essence.to_owned(), true),
+        _ if essence == "application/pdf" || essence == "application/json" => (essence.to_owned(), true),
+        _ => (essence.to_owned(), false)
+    }
+}
@@ -1,17 +1,24 @@
+use crate::commit_status;
+use crate::config::{get_optional_setting, get_setting};
 use crate::git::history::{all_branches, all_commits, all_tags};
+use crate::git::signature;
 use crate::prelude::*;
 use crate::privileges::privilege;
 use crate::repository::Repository;
-use crate::routes::repository::GitTreeRequest;
+use crate::routes::repository::{find_readable_repo, GitTreeRequest};
+use crate::templates;
 use crate::templates::web::GitCommit;
 use crate::user::{User, WebUser};
 use crate::{die, err, render_template};
 
-use actix_web::{HttpRequest, Responder, web};
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::{Either, HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use bstr::ByteSlice;
+use git2::BranchType;
 use git_repository::refs::file::find::existing::Error as GitoxideFindError;
 use gitarena_macros::route;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use tera::Context;
 
@@ -56,9 +63,19 @@ pub(crate) async fn commits(uri: web::Path<GitTreeRequest>, web_user: WebUser, r
     let commit_ids = all_commits(&libgit2_repo, searching_ref, 20).await?;
     let mut commits = Vec::<GitCommit>::with_capacity(commit_ids.len());
 
-    for oid in commit_ids {
-        let commit = libgit2_repo.find_commit(oid)?;
-        let (name, uid, email) = commit.author().try_disassemble(&mut transaction).await;
+    let git2_commits = commit_ids.into_iter().map(|oid| libgit2_repo.find_commit(oid)).collect::<Result<Vec<_>, _>>()?;
+
+    // Resolve all authors at once instead of querying the database for every single commit
+    let author_emails = git2_commits.iter().filter_map(|commit| commit.author().email().map(str::to_owned)).collect::<Vec<_>>();
+    let authors = User::find_using_emails(author_emails.as_slice(), &mut transaction).await?;
+
+    let shas = git2_commits.iter().map(|commit| commit.id().to_string()).collect::<Vec<_>>();
+    let statuses = commit_status::combined_states(&repo, shas.as_slice(), &mut transaction).await?;
+
+    for commit in git2_commits {
+        let oid = commit.id();
+        let (name, uid, email) = commit.author().disassemble_with(&authors);
+        let verification = signature::verify_commit(&libgit2_repo, oid, &mut transaction).await?;
 
         let chrono_time = commit.time().try_as_chrono()?;
         let chrono_date = chrono_time.date();
@@ -71,7 +88,9 @@ pub(crate) async fn commits(uri: web::Path<GitTreeRequest>, web_user: WebUser, r
             date: Some(chrono_time_only_date),
             author_name: name,
             author_uid: uid,
-            author_email: email
+            author_email: email,
+            verification,
+            status: statuses.get(&oid.to_string()).copied()
         });
     }
 
@@ -94,3 +113,79 @@ pub(crate) async fn commits(uri: web::Path<GitTreeRequest>, web_user: WebUser, r
 
     render_template!("repo/commits.html", context, transaction)
 }
+
+/// Atom feed of the most recent commits on a branch. Private repositories can be subscribed to using an access token via Basic auth
+#[route("/{username}/{repository}/commits/{branch:.+}.atom", method = "GET", err = "text")]
+pub(crate) async fn commits_feed(uri: web::Path<FeedRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = match find_readable_repo(uri.username.as_str(), uri.repository.as_str(), web_user, &request, &mut transaction).await? {
+        Either::Left(repo) => repo,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let libgit2_repo = repo.libgit2(&mut transaction).await?;
+
+    let branch = libgit2_repo.find_branch(uri.branch.as_str(), BranchType::Local).map_err(|_| err!(NOT_FOUND, "Branch not found"))?;
+    let reference = branch.get().name().ok_or_else(|| err!(NOT_FOUND, "Branch not found"))?;
+
+    let max_entries = get_setting::<i32, _>("repositories.feed_max_entries", &mut transaction).await?;
+    let commit_ids = all_commits(&libgit2_repo, reference, max_entries.max(1) as usize).await?;
+
+    let git2_commits = commit_ids.into_iter().map(|oid| libgit2_repo.find_commit(oid)).collect::<Result<Vec<_>, _>>()?;
+
+    let author_emails = git2_commits.iter().filter_map(|commit| commit.author().email().map(str::to_owned)).collect::<Vec<_>>();
+    let authors = User::find_using_emails(author_emails.as_slice(), &mut transaction).await?;
+
+    let mut entries = Vec::<FeedEntry>::with_capacity(git2_commits.len());
+
+    for commit in git2_commits.iter() {
+        let (author_name, _, author_email) = commit.author().disassemble_with(&authors);
+        let message = commit.message().unwrap_or_default();
+
+        entries.push(FeedEntry {
+            oid: commit.id().to_string(),
+            title: message.lines().next().unwrap_or_default().to_owned(),
+            message: message.to_owned(),
+            author_name,
+            author_email,
+            updated: commit.time().try_as_chrono()?.to_rfc3339()
+        });
+    }
+
+    let domain = get_optional_setting::<String, _>("domain", &mut transaction).await?.unwrap_or_default();
+
+    let mut context = Context::new();
+
+    context.try_insert("domain", domain.trim_end_matches('/'))?;
+    context.try_insert("repo_owner_name", uri.username.as_str())?;
+    context.try_insert("repo", &repo)?;
+    context.try_insert("branch", uri.branch.as_str())?;
+    context.try_insert("updated", &entries.first().map(|entry| entry.updated.as_str()))?;
+    context.try_insert("entries", &entries)?;
+
+    let feed = templates::render("repo/commits_feed.xml", &context).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, "application/atom+xml; charset=utf-8"))
+        .body(feed))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FeedRequest {
+    username: String,
+    repository: String,
+    branch: String
+}
+
+#[derive(Serialize)]
+struct FeedEntry {
+    oid: String,
+    title: String,
+    message: String,
+    author_name: String,
+    author_email: String,
+    updated: String
+}
@@ -1,31 +1,31 @@
-use crate::die;
-use crate::git::hooks::post_update;
+use crate::access_token::TokenScopes;
+use crate::branch_protection;
+use crate::config::get_setting;
+use crate::{die, metrics};
 use crate::git::io::band::Band;
 use crate::git::io::reader::read_data_lines;
 use crate::git::io::writer::GitWriter;
 use crate::git::receive_pack::{process_create_update, process_delete};
 use crate::git::ref_update::{RefUpdate, RefUpdateType};
-use crate::git::{basic_auth, pack, ref_update};
+use crate::git::{basic_auth, pack, push, ref_update};
 use crate::prelude::*;
 use crate::privileges::privilege;
 use crate::repository::Repository;
 use crate::routes::repository::GitRequest;
-
-use std::path::Path;
+use crate::{quota, shutdown};
+use crate::webhook::PushedRef;
 
 use actix_web::http::header::CONTENT_TYPE;
 use actix_web::{Either, HttpRequest, HttpResponse, Responder, web};
-use anyhow::{Context, Result};
-use async_process::{Command, Stdio};
-use futures::StreamExt;
+use anyhow::Result;
 use git_repository::protocol::transport::packetline::{PacketLineRef, StreamingPeekableIter};
 use gitarena_macros::route;
 use log::warn;
-use memmem::{Searcher, TwoWaySearcher};
 use sqlx::PgPool;
+use tempfile::TempDir;
 
 #[route("/{username}/{repository}.git/git-receive-pack", method = "POST", err = "git")]
-pub(crate) async fn git_receive_pack(uri: web::Path<GitRequest>, mut body: web::Payload, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+pub(crate) async fn git_receive_pack(uri: web::Path<GitRequest>, body: web::Payload, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let content_type = request.get_header("content-type").unwrap_or_default();
     let accept_header = request.get_header("accept").unwrap_or_default();
 
@@ -51,7 +51,7 @@ pub(crate) async fn git_receive_pack(uri: web::Path<GitRequest>, mut body: web::
         .fetch_optional(&mut transaction)
         .await?;
 
-    let user = match basic_auth::login_flow(&request, &mut transaction, "application/x-git-receive-pack-result").await? {
+    let user = match basic_auth::login_flow(&request, &mut transaction, "application/x-git-receive-pack-result", TokenScopes::WRITE_REPOSITORY).await? {
         Either::Left(user) => user,
         Either::Right(response) => return Ok(response)
     };
@@ -74,17 +74,25 @@ pub(crate) async fn git_receive_pack(uri: web::Path<GitRequest>, mut body: web::
         die!(UNAUTHORIZED, "Repository is archived and thus read-only");
     }
 
-    let mut bytes = web::BytesMut::new();
-
-    while let Some(item) = body.next().await {
-        let item = item?;
-        bytes.extend_from_slice(&item);
+    if repo.mirrored_from.is_some() {
+        die!(UNAUTHORIZED, "Repository is a mirror and thus read-only");
     }
 
-    let frozen_bytes = bytes.freeze();
-    let vec = &frozen_bytes[..];
+    // Held until the response is ready, so a graceful shutdown doesn't interrupt writing into the repository
+    let _push = shutdown::track_push();
+    let _fs_lock = repo.lock_fs_shared().await;
 
-    let mut readable_iter = StreamingPeekableIter::new(vec, &[PacketLineRef::Flush]);
+    let max_push_size = get_setting::<i32, _>("repositories.max_push_size", &mut transaction).await?;
+    let push = pack::receive(body, max_push_size.max(0) as u64).await?;
+
+    // Reserved in a separate transaction like the `pre-receive` hook of SSH pushes does, so other pushes of the owner don't
+    // wait for this one (including garbage collection) to finish. `push::finish` replaces the reservation with the actual size
+    let quota_exceeded = match push.pack_path {
+        Some(_) => quota::reserve(&repo, push.size, db_pool.get_ref()).await?,
+        None => None
+    };
+
+    let mut readable_iter = StreamingPeekableIter::new(&push.commands[..], &[PacketLineRef::Flush]);
     readable_iter.fail_on_err_lines(true);
 
     let git_body = read_data_lines(&mut readable_iter).await?;
@@ -102,25 +110,33 @@ pub(crate) async fn git_receive_pack(uri: web::Path<GitRequest>, mut body: web::
             .finish());
     }
 
-    let gitoxide_repo = repo.gitoxide(&mut transaction).await?;
-    let store = gitoxide_repo.objects.clone();
-
     let mut output_writer = GitWriter::new();
 
-    let searcher = TwoWaySearcher::new(b"PACK");
+    if let Some(reason) = quota_exceeded {
+        output_writer.write_text_sideband(Band::Progress, format!("error: {}", reason)).await?;
+        output_writer.write_text_sideband_pktline(Band::Data, "unpack ok").await?;
 
-    match searcher.search_in(vec) {
-        Some(pos) => {
-            let (index_path, pack_path, _temp_dir) = pack::read(&vec[pos..], &repo, &mut transaction).await?;
+        for update in updates {
+            output_writer.write_text_sideband_pktline(Band::Data, format!("ng {} storage quota exceeded", update.target_ref)).await?;
+        }
 
-            output_writer.write_text_sideband_pktline(Band::Data, "unpack ok").await?;
+        output_writer.flush_sideband(Band::Data).await?;
+        output_writer.flush().await?;
 
-            for update in updates {
-                match RefUpdateType::determinate(&update.old, &update.new).await? {
-                    RefUpdateType::Create | RefUpdateType::Update => process_create_update(&update, &repo, store.clone(), &db_pool, &mut output_writer, index_path.as_ref(), pack_path.as_ref(), &vec[pos..]).await?,
-                    RefUpdateType::Delete => process_delete(&update, &repo, &mut transaction, &mut output_writer).await?
-                };
-            }
+        return Ok(HttpResponse::Ok()
+            .append_header((CONTENT_TYPE, accept_header))
+            .body(output_writer.serialize().await?));
+    }
+
+    let gitoxide_repo = repo.gitoxide(&mut transaction).await?;
+    let store = gitoxide_repo.objects.clone();
+
+    let mut pushed_refs = Vec::<PushedRef>::new();
+
+    let (index_path, pack_path, quarantine) = match push.pack_path.as_deref() {
+        Some(raw_pack) => {
+            let (index_path, pack_path, temp_dir) = pack::read(raw_pack, &repo, &mut transaction).await?;
+            (index_path, pack_path, Some(temp_dir))
         }
         None => {
             if !ref_update::is_only_deletions(updates.as_slice()).await? {
@@ -128,43 +144,50 @@ pub(crate) async fn git_receive_pack(uri: web::Path<GitRequest>, mut body: web::
                 die!(BAD_REQUEST, "No PACK payload was sent");
             }
 
-            // There wasn't actually something to unpack
-            output_writer.write_text_sideband_pktline(Band::Data, "unpack ok").await?;
+            (None, None, None)
+        }
+    };
 
-            for update in updates {
-                process_delete(&update, &repo, &mut transaction, &mut output_writer).await?;
-            }
+    output_writer.write_text_sideband_pktline(Band::Data, "unpack ok").await?;
+
+    // Every update is checked before anything is written, so objects of a rejected push never end up in the repository
+    let quarantine_path = quarantine.as_ref().filter(|_| index_path.is_some()).map(TempDir::path);
+    let mut accepted = Vec::<RefUpdate>::with_capacity(updates.len());
+
+    for update in updates {
+        match branch_protection::check_update(&update, &repo, Some(&user), quarantine_path, &mut transaction).await? {
+            Some(reason) => output_writer.write_text_sideband_pktline(Band::Data, format!("ng {} {}", update.target_ref, reason)).await?,
+            None => accepted.push(update)
         }
     }
 
-    let repo_dir_str = repo.get_fs_path(&mut transaction).await?;
-    let repo_dir = Path::new(&repo_dir_str);
+    for update in accepted {
+        let result = match (RefUpdateType::determinate(&update.old, &update.new).await?, push.pack_path.as_deref()) {
+            (RefUpdateType::Create | RefUpdateType::Update, Some(raw_pack)) => process_create_update(&update, &repo, store.clone(), &db_pool, &mut output_writer, index_path.as_ref(), pack_path.as_ref(), raw_pack).await,
+            (RefUpdateType::Create | RefUpdateType::Update, None) => die!(BAD_REQUEST, "No PACK payload was sent"),
+            (RefUpdateType::Delete, _) => process_delete(&update, &repo, &mut transaction, &mut output_writer).await
+        };
+
+        // Most likely the ref has been changed since the client fetched it, which git reports as stale info
+        if let Err(err) = result {
+            warn!("Failed to update {} in repo {}: {}", &update.target_ref, &repo.id, err);
 
-    // Let Git collect garbage to optimize repo size
-    match Command::new("git").args(&["gc", "--auto", "--quiet"]).current_dir(repo_dir).stdout(Stdio::null()).stderr(Stdio::null()).status().await {
-        Ok(status) => if !status.success() {
-            warn!("Git garbage collector exited with non-zero status: {}", status);
+            output_writer.write_text_sideband_pktline(Band::Data, format!("ng {} failed to update ref, fetch first", update.target_ref)).await?;
+            continue;
         }
-        Err(err) => warn!("Failed to execute Git garbage collector: {}", err)
+
+        pushed_refs.push(PushedRef::new(update.target_ref.as_str(), update.old.as_deref(), update.new.as_deref()));
     }
 
     output_writer.flush_sideband(Band::Data).await?;
     output_writer.flush().await?;
 
-    // Run post update hooks
-    post_update::run(store, &mut repo, &mut transaction)
-        .await
-        .with_context(|| format!("Failed to run post update hook for newest commit in {}/{}", &uri.username, repo.name))?;
-
-    sqlx::query("update repositories set license = $1 where id = $2")
-        .bind(&repo.license)
-        .bind(&repo.id)
-        .execute(&mut transaction)
-        .await?;
+    push::finish(&mut repo, uri.username.as_str(), Some(&user), pushed_refs, db_pool.get_ref(), transaction).await?;
 
-    transaction.commit().await?;
+    let output = output_writer.serialize().await?;
+    metrics::record_git_transfer("receive-pack", push.size as usize, output.len());
 
     Ok(HttpResponse::Ok()
         .append_header((CONTENT_TYPE, accept_header))
-        .body(output_writer.serialize().await?))
+        .body(output))
 }
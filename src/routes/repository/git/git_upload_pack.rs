@@ -1,6 +1,7 @@
-use crate::die;
+use crate::access_token::TokenScopes;
+use crate::{die, metrics};
 use crate::git::basic_auth;
-use crate::git::fetch::fetch;
+use crate::git::fetch::{fetch, fetch_v0};
 use crate::git::io::reader::{read_data_lines, read_until_command};
 use crate::git::ls_refs::ls_refs;
 use crate::prelude::*;
@@ -26,10 +27,7 @@ pub(crate) async fn git_upload_pack(uri: web::Path<GitRequest>, mut body: web::P
     }
 
     let git_protocol = request.get_header("git-protocol").unwrap_or_default();
-
-    if git_protocol != "version=2" {
-        die!(BAD_REQUEST, "Unsupported Git protocol version");
-    }
+    let protocol_v2 = git_protocol.split(':').any(|parameter| parameter == "version=2");
 
     let mut transaction = db_pool.begin().await?;
 
@@ -49,7 +47,7 @@ pub(crate) async fn git_upload_pack(uri: web::Path<GitRequest>, mut body: web::P
         .fetch_optional(&mut transaction)
         .await?;
 
-    let (user, repo) = match basic_auth::validate_repo_access(repo_option, "application/x-git-upload-pack-advertisement", &request, &mut transaction).await? {
+    let (user, repo) = match basic_auth::validate_repo_access(repo_option, "application/x-git-upload-pack-advertisement", TokenScopes::READ_REPOSITORY, &request, &mut transaction).await? {
         Either::Left(tuple) => tuple,
         Either::Right(response) => return Ok(response)
     };
@@ -58,6 +56,7 @@ pub(crate) async fn git_upload_pack(uri: web::Path<GitRequest>, mut body: web::P
         die!(NOT_FOUND);
     }
 
+    let _fs_lock = repo.lock_fs_shared().await;
     let git2repo = repo.libgit2(&mut transaction).await?;
 
     let mut bytes = web::BytesMut::new();
@@ -74,11 +73,29 @@ pub(crate) async fn git_upload_pack(uri: web::Path<GitRequest>, mut body: web::P
     readable_iter.fail_on_err_lines(true);
 
     let git_body = read_data_lines(&mut readable_iter).await?;
+
+    if !protocol_v2 {
+        // Legacy protocol: wants are followed by a flush, afterwards the haves and `done` follow
+        readable_iter.reset();
+        let haves = read_data_lines(&mut readable_iter).await?;
+
+        let output = fetch_v0(git_body, haves, &git2repo).await?;
+
+        transaction.commit().await?;
+
+        metrics::record_git_transfer("upload-pack", vec.len(), output.len());
+
+        return Ok(HttpResponse::Ok()
+            .append_header((CONTENT_TYPE, accept_header))
+            .body(output));
+    }
+
     let (command, body) = read_until_command(git_body).await?;
 
     let response = match command.as_str() {
         "ls-refs" => {
             let output = ls_refs(body, &git2repo).await?;
+            metrics::record_git_transfer("upload-pack", vec.len(), output.len());
 
             HttpResponse::Ok()
                 .append_header((CONTENT_TYPE, accept_header))
@@ -86,6 +103,7 @@ pub(crate) async fn git_upload_pack(uri: web::Path<GitRequest>, mut body: web::P
         }
         "fetch" => {
             let output = fetch(body, &git2repo).await?;
+            metrics::record_git_transfer("upload-pack", vec.len(), output.len());
 
             HttpResponse::Ok()
                 .append_header((CONTENT_TYPE, accept_header))
@@ -1,8 +1,10 @@
+use crate::access_token::TokenScopes;
 use crate::die;
 use crate::git::basic_auth;
 use crate::git::capabilities::capabilities;
-use crate::git::ls_refs::ls_refs_all;
+use crate::git::ls_refs::{ls_refs_all, ls_refs_upload_pack};
 use crate::prelude::*;
+use crate::privileges::privilege;
 use crate::repository::Repository;
 use crate::routes::repository::GitRequest;
 
@@ -10,7 +12,7 @@ use actix_web::http::header::CONTENT_TYPE;
 use actix_web::{Either, HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use gitarena_macros::route;
-use sqlx::{Executor, PgPool, Pool, Postgres};
+use sqlx::{PgPool, Pool, Postgres, Transaction};
 
 #[route("/{username}/{repository}.git/info/refs", method = "GET", err = "text")]
 pub(crate) async fn info_refs(uri: web::Path<GitRequest>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
@@ -39,6 +41,11 @@ pub(crate) async fn info_refs(uri: web::Path<GitRequest>, request: HttpRequest,
         .fetch_optional(&mut transaction)
         .await?;
 
+    let _fs_lock = match &repo_option {
+        Some(repo) => Some(repo.lock_fs_shared().await),
+        None => None
+    };
+
     match service {
         "git-upload-pack" => {
             let response = upload_pack_info_refs(repo_option, service, &request, &mut transaction).await?;
@@ -56,29 +63,35 @@ pub(crate) async fn info_refs(uri: web::Path<GitRequest>, request: HttpRequest,
     }
 }
 
-async fn upload_pack_info_refs<'e, E>(repo_option: Option<Repository>, service: &str, request: &HttpRequest, executor: E) -> Result<HttpResponse>
-    where E: Executor<'e, Database = Postgres>
-{
-    let git_protocol = request.get_header("git-protocol").unwrap_or_default();
+async fn upload_pack_info_refs(repo_option: Option<Repository>, service: &str, request: &HttpRequest, transaction: &mut Transaction<'_, Postgres>) -> Result<HttpResponse> {
+    let (user, repo) = match basic_auth::validate_repo_access(repo_option, "application/x-git-upload-pack-advertisement", TokenScopes::READ_REPOSITORY, request, transaction).await? {
+        Either::Left(tuple) => tuple,
+        Either::Right(response) => return Ok(response)
+    };
 
-    if git_protocol != "version=2" {
-        die!(BAD_REQUEST, "Unsupported Git protocol version");
+    if !privilege::check_access(&repo, user.as_ref(), &mut *transaction).await? {
+        die!(NOT_FOUND);
     }
 
-    let (_, _) = match basic_auth::validate_repo_access(repo_option, "application/x-git-upload-pack-advertisement", request, executor).await? {
-        Either::Left(tuple) => tuple,
-        Either::Right(response) => return Ok(response)
+    // Clients opt into protocol v2 using this header, everyone else gets the legacy ref advertisement
+    let git_protocol = request.get_header("git-protocol").unwrap_or_default();
+
+    let output = if git_protocol.split(':').any(|parameter| parameter == "version=2") {
+        capabilities(service).await?
+    } else {
+        let git2repo = repo.libgit2(&mut *transaction).await?;
+        ls_refs_upload_pack(&git2repo).await?
     };
 
     Ok(HttpResponse::Ok()
         .append_header((CONTENT_TYPE, "application/x-git-upload-pack-advertisement"))
-        .body(capabilities(service).await?))
+        .body(output))
 }
 
 async fn receive_pack_info_refs(repo_option: Option<Repository>, request: &HttpRequest, db_pool: &Pool<Postgres>) -> Result<HttpResponse> {
     let mut transaction = db_pool.begin().await?;
 
-    let _user = match basic_auth::login_flow(request, &mut transaction, "application/x-git-receive-pack-advertisement").await? {
+    let _user = match basic_auth::login_flow(request, &mut transaction, "application/x-git-receive-pack-advertisement", TokenScopes::WRITE_REPOSITORY).await? {
         Either::Left(user) => user,
         Either::Right(response) => return Ok(response)
     };
@@ -0,0 +1,693 @@
+use crate::access_token::TokenScopes;
+use crate::git::basic_auth;
+use crate::git::lfs::{self, LfsLock, ACTION_AUTH_SCHEME, ACTION_TOKEN_TTL, LFS_CONTENT_TYPE};
+use crate::prelude::*;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::routes::repository::GitRequest;
+use crate::user::User;
+use crate::{config, die, err, quota};
+
+use std::collections::HashMap;
+
+use actix_files::NamedFile;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::{Either, HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use gitarena_macros::route;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+
+// https://github.com/git-lfs/git-lfs/blob/main/docs/api/batch.md
+#[route("/{username}/{repository}.git/info/lfs/objects/batch", method = "POST", err = "json")]
+pub(crate) async fn lfs_batch(uri: web::Path<GitRequest>, body: web::Bytes, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let accept_header = request.get_header("accept").unwrap_or_default();
+
+    if !accept_header.contains(LFS_CONTENT_TYPE) {
+        die!(NOT_ACCEPTABLE, "Accept header must be {}", LFS_CONTENT_TYPE);
+    }
+
+    // The Git LFS client sends its own content type which `web::Json` would reject
+    let batch_request: BatchRequest = serde_json::from_slice(&body).map_err(|err| err!(BAD_REQUEST, "Invalid batch request: {}", err))?;
+
+    if let Some(transfers) = &batch_request.transfers {
+        if !transfers.iter().any(|transfer| transfer == "basic") {
+            die!(UNPROCESSABLE_ENTITY, "Only the basic transfer adapter is supported");
+        }
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_option = find_repo(&uri, &mut transaction).await?;
+
+    let upload = match batch_request.operation.as_str() {
+        "download" => false,
+        "upload" => true,
+        _ => die!(UNPROCESSABLE_ENTITY, "Unknown operation")
+    };
+
+    let (user, repo) = if upload {
+        match authorize_upload(repo_option, &request, &mut transaction).await? {
+            Either::Left((user, repo)) => (Some(user), repo),
+            Either::Right(response) => return Ok(response)
+        }
+    } else {
+        match authorize_download(repo_option, &request, &mut transaction).await? {
+            Either::Left(tuple) => tuple,
+            Either::Right(response) => return Ok(response)
+        }
+    };
+
+    let domain = config::get_setting::<String, _>("domain", &mut transaction).await?;
+    let base_url = format!("{}/{}/{}.git/info/lfs/objects", domain.trim_end_matches('/'), uri.username, uri.repository);
+
+    // Actions are authorized using a short-lived token instead of the credentials of this request, which would otherwise
+    // end up in responses, logs and caches. Anonymous downloads of public repositories don't need one
+    let mut header = HashMap::<&str, String>::new();
+
+    if let Some(user) = &user {
+        header.insert("Authorization", format!("{} {}", ACTION_AUTH_SCHEME, lfs::action_token(user, &repo, upload)));
+    }
+
+    let mut objects = Vec::<BatchObjectResponse>::with_capacity(batch_request.objects.len());
+
+    for object in &batch_request.objects {
+        if !lfs::is_valid_oid(object.oid.as_str()) || object.size < 0 {
+            objects.push(BatchObjectResponse::error(object, 422, "Invalid object id or size"));
+            continue;
+        }
+
+        let path = lfs::object_path(&repo, object.oid.as_str(), &mut transaction).await?;
+        let stored_size = lfs::object_size(path.as_path()).await;
+
+        let href = format!("{}/{}", base_url, object.oid);
+
+        let actions = if batch_request.operation == "download" {
+            match stored_size {
+                Some(size) if size == object.size as u64 => Some(BatchActions {
+                    download: Some(BatchAction::new(href, &header)),
+                    upload: None,
+                    verify: None
+                }),
+                _ => {
+                    objects.push(BatchObjectResponse::error(object, 404, "Object does not exist"));
+                    continue;
+                }
+            }
+        } else {
+            match stored_size {
+                // Object has already been uploaded, the client does not need to do anything
+                Some(size) if size == object.size as u64 => None,
+                _ => Some(BatchActions {
+                    download: None,
+                    upload: Some(BatchAction::new(href, &header)),
+                    verify: Some(BatchAction::new(format!("{}/verify", base_url), &header))
+                })
+            }
+        };
+
+        objects.push(BatchObjectResponse {
+            oid: object.oid.as_str(),
+            size: object.size,
+            authenticated: Some(true),
+            actions,
+            error: None
+        });
+    }
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, LFS_CONTENT_TYPE))
+        .json(BatchResponse {
+            transfer: "basic",
+            objects
+        }))
+}
+
+#[route("/{username}/{repository}.git/info/lfs/objects/{oid}", method = "GET", err = "json")]
+pub(crate) async fn lfs_download(uri: web::Path<LfsObjectRequest>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    if !lfs::is_valid_oid(uri.oid.as_str()) {
+        die!(NOT_FOUND, "Object does not exist");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_option = find_repo(&uri.git_request(), &mut transaction).await?;
+
+    let (_, repo) = match authorize_download(repo_option, &request, &mut transaction).await? {
+        Either::Left(tuple) => tuple,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let path = lfs::object_path(&repo, uri.oid.as_str(), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    if lfs::object_size(path.as_path()).await.is_none() {
+        die!(NOT_FOUND, "Object does not exist");
+    }
+
+    Ok(NamedFile::open_async(path).await?.into_response(&request))
+}
+
+#[route("/{username}/{repository}.git/info/lfs/objects/{oid}", method = "PUT", err = "json")]
+pub(crate) async fn lfs_upload(uri: web::Path<LfsObjectRequest>, body: web::Payload, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    if !lfs::is_valid_oid(uri.oid.as_str()) {
+        die!(UNPROCESSABLE_ENTITY, "Invalid object id");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_option = find_repo(&uri.git_request(), &mut transaction).await?;
+
+    let (user, mut repo) = match authorize_upload(repo_option, &request, &mut transaction).await? {
+        Either::Left(tuple) => tuple,
+        Either::Right(response) => return Ok(response)
+    };
+
+    // Git LFS always declares the size of the object, more than that is never read from the body
+    let length = request.get_header("content-length")
+        .and_then(|length| length.parse::<u64>().ok())
+        .ok_or_else(|| err!(LENGTH_REQUIRED, "Content-Length header is required"))?;
+
+    let max_size = config::get_setting::<i32, _>("repositories.max_push_size", &mut transaction).await?.max(0) as u64;
+
+    if length > max_size {
+        die!(PAYLOAD_TOO_LARGE, "Object may only be up to {} bytes large", max_size);
+    }
+
+    quota::check_push(&repo, length, &mut transaction).await?;
+
+    let path = lfs::object_path(&repo, uri.oid.as_str(), &mut transaction).await?;
+
+    if !lfs::store_object(uri.oid.as_str(), path.as_path(), length, body).await? {
+        die!(UNPROCESSABLE_ENTITY, "Uploaded data does not match object id");
+    }
+
+    repo.update_size(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("{} (id {}) uploaded LFS object {} to repository {} (id {})", &user.username, &user.id, &uri.oid, &repo.name, &repo.id);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Called by the client once an upload completed. Deletes the object if it does not have the size the client expects
+#[route("/{username}/{repository}.git/info/lfs/objects/verify", method = "POST", err = "json")]
+pub(crate) async fn lfs_verify(uri: web::Path<GitRequest>, body: web::Bytes, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let object: BatchObjectRequest = serde_json::from_slice(&body).map_err(|err| err!(BAD_REQUEST, "Invalid verify request: {}", err))?;
+
+    if !lfs::is_valid_oid(object.oid.as_str()) || object.size < 0 {
+        die!(UNPROCESSABLE_ENTITY, "Invalid object id or size");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_option = find_repo(&uri, &mut transaction).await?;
+
+    let (_, repo) = match authorize_upload(repo_option, &request, &mut transaction).await? {
+        Either::Left(tuple) => tuple,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let path = lfs::object_path(&repo, object.oid.as_str(), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    match lfs::object_size(path.as_path()).await {
+        Some(size) if size == object.size as u64 => Ok(HttpResponse::Ok()
+            .append_header((CONTENT_TYPE, LFS_CONTENT_TYPE))
+            .finish()),
+        Some(_) => {
+            tokio::fs::remove_file(path.as_path()).await?;
+
+            die!(UNPROCESSABLE_ENTITY, "Object size does not match");
+        }
+        None => die!(NOT_FOUND, "Object does not exist")
+    }
+}
+
+// https://github.com/git-lfs/git-lfs/blob/main/docs/api/locking.md
+#[route("/{username}/{repository}.git/info/lfs/locks", method = "POST", err = "json")]
+pub(crate) async fn lfs_create_lock(uri: web::Path<GitRequest>, body: web::Bytes, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let lock_request: CreateLockRequest = serde_json::from_slice(&body).map_err(|err| err!(BAD_REQUEST, "Invalid lock request: {}", err))?;
+    let path = lock_request.path.trim_start_matches('/');
+
+    if path.is_empty() || path.len() > 1024 {
+        die!(UNPROCESSABLE_ENTITY, "Invalid path");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_option = find_repo(&uri, &mut transaction).await?;
+
+    let (user, repo) = match authorize_upload(repo_option, &request, &mut transaction).await? {
+        Either::Left(tuple) => tuple,
+        Either::Right(response) => return Ok(response)
+    };
+
+    if let Some(existing) = LfsLock::find_by_path(&repo, path, &mut transaction).await? {
+        return Ok(HttpResponse::Conflict()
+            .append_header((CONTENT_TYPE, LFS_CONTENT_TYPE))
+            .json(LockConflictResponse {
+                lock: LockResponse::from(&existing),
+                message: "Path is already locked"
+            }));
+    }
+
+    let (id,): (i32,) = sqlx::query_as("insert into lfs_locks (repo, path, owner) values ($1, $2, $3) returning id")
+        .bind(&repo.id)
+        .bind(path)
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    let lock = LfsLock::find(&repo, id, &mut transaction).await?.ok_or_else(|| err!(INTERNAL_SERVER_ERROR, "Failed to create lock"))?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) locked {} in repository {} (id {})", &user.username, &user.id, &lock.path, &repo.name, &repo.id);
+
+    Ok(HttpResponse::Created()
+        .append_header((CONTENT_TYPE, LFS_CONTENT_TYPE))
+        .json(LockJsonResponse {
+            lock: LockResponse::from(&lock)
+        }))
+}
+
+#[route("/{username}/{repository}.git/info/lfs/locks", method = "GET", err = "json")]
+pub(crate) async fn lfs_list_locks(uri: web::Path<GitRequest>, query: web::Query<ListLocksQuery>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_option = find_repo(&uri, &mut transaction).await?;
+
+    let repo = match authorize_download(repo_option, &request, &mut transaction).await? {
+        Either::Left((_, repo)) => repo,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let (after, limit) = parse_cursor(query.cursor.as_deref(), query.limit)?;
+
+    let mut locks = match query.id.as_deref() {
+        Some(id) => {
+            let id = id.parse::<i32>().map_err(|_| err!(BAD_REQUEST, "Invalid lock id"))?;
+
+            LfsLock::find(&repo, id, &mut transaction).await?
+                .into_iter()
+                .filter(|lock| query.path.as_deref().map_or(true, |path| path == lock.path))
+                .collect::<Vec<_>>()
+        }
+        None => LfsLock::list(&repo, query.path.as_deref(), None, after, limit + 1, &mut transaction).await?
+    };
+
+    transaction.commit().await?;
+
+    let next_cursor = next_cursor(&mut locks, limit);
+
+    Ok(HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, LFS_CONTENT_TYPE))
+        .json(ListLocksResponse {
+            locks: locks.iter().map(LockResponse::from).collect(),
+            next_cursor
+        }))
+}
+
+#[route("/{username}/{repository}.git/info/lfs/locks/verify", method = "POST", err = "json")]
+pub(crate) async fn lfs_verify_locks(uri: web::Path<GitRequest>, body: web::Bytes, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let verify_request: VerifyLocksRequest = serde_json::from_slice(&body).map_err(|err| err!(BAD_REQUEST, "Invalid verify request: {}", err))?;
+
+    let mut transaction = db_pool.begin().await?;
+
+    let repo_option = find_repo(&uri, &mut transaction).await?;
+
+    let (user, repo) = match authorize_upload(repo_option, &request, &mut transaction).await? {
+        Either::Left(tuple) => tuple,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let (after, limit) = parse_cursor(verify_request.cursor.as_deref(), verify_request.limit)?;
+
+    let mut locks = LfsLock::list(&repo, None, None, after, limit + 1, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let next_cursor = next_cursor(&mut locks, limit);
+    let (ours, theirs): (Vec<_>, Vec<_>) = locks.iter().partition(|lock| lock.owner == user.id);
+
+    Ok(HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, LFS_CONTENT_TYPE))
+        .json(VerifyLocksResponse {
+            ours: ours.into_iter().map(LockResponse::from).collect(),
+            theirs: theirs.into_iter().map(LockResponse::from).collect(),
+            next_cursor
+        }))
+}
+
+/// Removes a lock. Locks owned by other users can only be removed by repository admins using `force`
+#[route("/{username}/{repository}.git/info/lfs/locks/{id}/unlock", method = "POST", err = "json")]
+pub(crate) async fn lfs_unlock(uri: web::Path<LfsLockRequest>, body: web::Bytes, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let unlock_request: UnlockRequest = if body.is_empty() {
+        UnlockRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|err| err!(BAD_REQUEST, "Invalid unlock request: {}", err))?
+    };
+
+    let mut transaction = db_pool.begin().await?;
+
+    let git_request = GitRequest {
+        username: uri.username.clone(),
+        repository: uri.repository.clone()
+    };
+    let repo_option = find_repo(&git_request, &mut transaction).await?;
+
+    let (user, repo) = match authorize_upload(repo_option, &request, &mut transaction).await? {
+        Either::Left(tuple) => tuple,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let lock = LfsLock::find(&repo, uri.id, &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Lock does not exist"))?;
+
+    if lock.owner != user.id {
+        if !unlock_request.force {
+            die!(FORBIDDEN, "Lock is owned by a different user");
+        }
+
+        if !privilege::check_admin(&repo, Some(&user), &mut transaction).await? {
+            die!(FORBIDDEN, "Only repository admins may force unlock locks of other users");
+        }
+    }
+
+    sqlx::query("delete from lfs_locks where id = $1")
+        .bind(&lock.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) unlocked {} in repository {} (id {})", &user.username, &user.id, &lock.path, &repo.name, &repo.id);
+
+    Ok(HttpResponse::Ok()
+        .append_header((CONTENT_TYPE, LFS_CONTENT_TYPE))
+        .json(LockJsonResponse {
+            lock: LockResponse::from(&lock)
+        }))
+}
+
+/// Parses the opaque pagination cursor (the id of the last lock of the previous page) and clamps the limit
+fn parse_cursor(cursor: Option<&str>, limit: Option<i64>) -> Result<(i32, i64)> {
+    let after = match cursor {
+        Some(cursor) if !cursor.is_empty() => cursor.parse::<i32>().map_err(|_| err!(BAD_REQUEST, "Invalid cursor"))?,
+        _ => 0
+    };
+
+    Ok((after, limit.unwrap_or(100).clamp(1, 100)))
+}
+
+/// Truncates `locks` (which was fetched with `limit + 1`) to `limit` and returns the cursor for the next page if there is one
+fn next_cursor(locks: &mut Vec<LfsLock>, limit: i64) -> Option<String> {
+    if locks.len() as i64 <= limit {
+        return None;
+    }
+
+    locks.truncate(limit as usize);
+    locks.last().map(|lock| lock.id.to_string())
+}
+
+async fn find_repo(uri: &GitRequest, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<Repository>> {
+    let user_option: Option<(i32,)> = sqlx::query_as("select id from users where lower(username) = lower($1) limit 1")
+        .bind(&uri.username)
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+    let (user_id,) = match user_option {
+        Some(user_id) => user_id,
+        None => die!(NOT_FOUND)
+    };
+
+    Ok(sqlx::query_as::<_, Repository>("select * from repositories where owner = $1 and lower(name) = lower($2) limit 1")
+        .bind(user_id)
+        .bind(&uri.repository)
+        .fetch_optional(&mut *transaction)
+        .await?)
+}
+
+/// Returns the action token sent by the client, see [action_token](lfs::action_token)
+fn action_token(request: &HttpRequest) -> Option<&str> {
+    let (scheme, token) = request.get_header("authorization")?.split_once(' ')?;
+
+    (scheme == ACTION_AUTH_SCHEME).then(|| token.trim())
+}
+
+async fn action_user(token: &str, repo: &Repository, upload: bool, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    let user_id = lfs::verify_action_token(token, repo, upload).ok_or_else(|| err!(UNAUTHORIZED, "Invalid or expired action token"))?;
+
+    let user = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+        .bind(&user_id)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or_else(|| err!(UNAUTHORIZED, "Invalid or expired action token"))?;
+
+    if let Some(message) = user.blocked_message() {
+        die!(FORBIDDEN, "{}", message);
+    }
+
+    Ok(user)
+}
+
+async fn authorize_download(repo_option: Option<Repository>, request: &HttpRequest, transaction: &mut Transaction<'_, Postgres>) -> Result<Either<(Option<User>, Repository), HttpResponse>> {
+    let (user, repo) = match (action_token(request), repo_option) {
+        (Some(token), Some(repo)) => (Some(action_user(token, &repo, false, transaction).await?), repo),
+        (Some(_), None) => die!(NOT_FOUND),
+        (None, repo_option) => match basic_auth::validate_repo_access(repo_option, LFS_CONTENT_TYPE, TokenScopes::READ_REPOSITORY, request, transaction).await? {
+            Either::Left(tuple) => tuple,
+            Either::Right(response) => return Ok(Either::Right(response))
+        }
+    };
+
+    // If the user doesn't have access return 404 Not found to not leak existence of internal/private repositories
+    if !privilege::check_access(&repo, user.as_ref(), &mut *transaction).await? {
+        die!(NOT_FOUND);
+    }
+
+    Ok(Either::Left((user, repo)))
+}
+
+async fn authorize_upload(repo_option: Option<Repository>, request: &HttpRequest, transaction: &mut Transaction<'_, Postgres>) -> Result<Either<(User, Repository), HttpResponse>> {
+    let (user, repo) = match (action_token(request), repo_option) {
+        (Some(token), Some(repo)) => (action_user(token, &repo, true, transaction).await?, repo),
+        (Some(_), None) => die!(NOT_FOUND),
+        (None, repo_option) => {
+            let user = match basic_auth::login_flow(request, transaction, LFS_CONTENT_TYPE, TokenScopes::WRITE_REPOSITORY).await? {
+                Either::Left(user) => user,
+                Either::Right(response) => return Ok(Either::Right(response))
+            };
+
+            match repo_option {
+                Some(repo) => (user, repo),
+                None => die!(NOT_FOUND)
+            }
+        }
+    };
+
+    if !privilege::check_access(&repo, Some(&user), &mut *transaction).await? {
+        die!(NOT_FOUND);
+    }
+
+    if !privilege::check_push(&repo, Some(&user), &mut *transaction).await? {
+        die!(FORBIDDEN, "No permission to push into this repo");
+    }
+
+    if repo.archived {
+        die!(FORBIDDEN, "Repository is archived and thus read-only");
+    }
+
+    if repo.mirrored_from.is_some() {
+        die!(FORBIDDEN, "Repository is a mirror and thus read-only");
+    }
+
+    Ok(Either::Left((user, repo)))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LfsObjectRequest {
+    username: String,
+    repository: String,
+    oid: String
+}
+
+impl LfsObjectRequest {
+    fn git_request(&self) -> GitRequest {
+        GitRequest {
+            username: self.username.clone(),
+            repository: self.repository.clone()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LfsLockRequest {
+    username: String,
+    repository: String,
+    id: i32
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ListLocksQuery {
+    path: Option<String>,
+    id: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i64>
+}
+
+#[derive(Deserialize)]
+struct CreateLockRequest {
+    path: String
+}
+
+#[derive(Deserialize)]
+struct VerifyLocksRequest {
+    cursor: Option<String>,
+    limit: Option<i64>
+}
+
+#[derive(Deserialize, Default)]
+struct UnlockRequest {
+    #[serde(default)]
+    force: bool
+}
+
+#[derive(Serialize)]
+struct LockResponse<'a> {
+    id: String,
+    path: &'a str,
+    locked_at: DateTime<Local>,
+    owner: LockOwnerResponse<'a>
+}
+
+impl<'a> From<&'a LfsLock> for LockResponse<'a> {
+    fn from(lock: &'a LfsLock) -> LockResponse<'a> {
+        LockResponse {
+            id: lock.id.to_string(),
+            path: lock.path.as_str(),
+            locked_at: lock.created_at,
+            owner: LockOwnerResponse {
+                name: lock.owner_name.as_str()
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LockOwnerResponse<'a> {
+    name: &'a str
+}
+
+#[derive(Serialize)]
+struct LockJsonResponse<'a> {
+    lock: LockResponse<'a>
+}
+
+#[derive(Serialize)]
+struct LockConflictResponse<'a> {
+    lock: LockResponse<'a>,
+    message: &'static str
+}
+
+#[derive(Serialize)]
+struct ListLocksResponse<'a> {
+    locks: Vec<LockResponse<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>
+}
+
+#[derive(Serialize)]
+struct VerifyLocksResponse<'a> {
+    ours: Vec<LockResponse<'a>>,
+    theirs: Vec<LockResponse<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    operation: String,
+    transfers: Option<Vec<String>>,
+    objects: Vec<BatchObjectRequest>
+}
+
+#[derive(Deserialize)]
+struct BatchObjectRequest {
+    oid: String,
+    size: i64
+}
+
+#[derive(Serialize)]
+struct BatchResponse<'a> {
+    transfer: &'static str,
+    objects: Vec<BatchObjectResponse<'a>>
+}
+
+#[derive(Serialize)]
+struct BatchObjectResponse<'a> {
+    oid: &'a str,
+    size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authenticated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actions: Option<BatchActions<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<BatchObjectError>
+}
+
+impl<'a> BatchObjectResponse<'a> {
+    fn error(object: &'a BatchObjectRequest, code: u16, message: &'static str) -> BatchObjectResponse<'a> {
+        BatchObjectResponse {
+            oid: object.oid.as_str(),
+            size: object.size,
+            authenticated: None,
+            actions: None,
+            error: Some(BatchObjectError {
+                code,
+                message
+            })
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchActions<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download: Option<BatchAction<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload: Option<BatchAction<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verify: Option<BatchAction<'a>>
+}
+
+#[derive(Serialize)]
+struct BatchAction<'a> {
+    href: String,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    header: HashMap<&'a str, String>,
+    expires_in: i64
+}
+
+impl<'a> BatchAction<'a> {
+    fn new(href: String, header: &HashMap<&'a str, String>) -> BatchAction<'a> {
+        BatchAction {
+            href,
+            header: header.clone(),
+            expires_in: ACTION_TOKEN_TTL
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchObjectError {
+    code: u16,
+    message: &'static str
+}
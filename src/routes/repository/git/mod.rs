@@ -3,9 +3,19 @@ use actix_web::web::ServiceConfig;
 mod git_receive_pack;
 mod git_upload_pack;
 mod info_refs;
+mod lfs;
 
 pub(crate) fn init(config: &mut ServiceConfig) {
     config.service(git_receive_pack::git_receive_pack); // git push
     config.service(git_upload_pack::git_upload_pack); // git pull
     config.service(info_refs::info_refs);
+
+    config.service(lfs::lfs_batch);
+    config.service(lfs::lfs_verify); // Needs to be registered before the object routes
+    config.service(lfs::lfs_download);
+    config.service(lfs::lfs_upload);
+    config.service(lfs::lfs_create_lock);
+    config.service(lfs::lfs_list_locks);
+    config.service(lfs::lfs_verify_locks);
+    config.service(lfs::lfs_unlock);
 }
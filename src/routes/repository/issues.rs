@@ -42,12 +42,15 @@ pub(crate) async fn all_issues(uri: web::Path<GitRequest>, web_user: WebUser, db
     let mut usernames = HashMap::new();
 
     for issue in issues.iter() {
-        let (username,): (String,) = sqlx::query_as("select username from users where id = $1 limit 1")
-            .bind(&issue.author)
-            .fetch_one(&mut transaction)
-            .await?;
+        // Authors who deleted their account are shown as ghost by the template
+        if let Some(author) = issue.author {
+            let (username,): (String,) = sqlx::query_as("select username from users where id = $1 limit 1")
+                .bind(&author)
+                .fetch_one(&mut transaction)
+                .await?;
 
-        usernames.insert(format!("u{}", issue.author), username);
+            usernames.insert(format!("u{}", author), username);
+        }
 
         // TODO: Serialize milestone and label strings here as well
         if !issue.assignees.is_empty() {
@@ -1,15 +1,27 @@
+use crate::access_token::TokenScopes;
+use crate::{die, err};
+use crate::git::basic_auth;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::{User, WebUser};
+
 use actix_web::web::ServiceConfig;
+use actix_web::{Either, HttpRequest, HttpResponse};
+use anyhow::Result;
 use serde::Deserialize;
+use sqlx::{Postgres, Transaction};
 
-mod api;
+pub(crate) mod api;
 mod archive;
 mod blobs;
 mod commits;
 mod import;
 mod git;
 mod issues;
+mod releases;
 mod repo_create;
 mod repo_view;
+mod search;
 
 pub(crate) fn init(config: &mut ServiceConfig) {
     api::init(config);
@@ -17,11 +29,15 @@ pub(crate) fn init(config: &mut ServiceConfig) {
     git::init(config); // Git smart protocol v2 routes
 
     config.service(commits::commits);
+    config.service(commits::commits_feed);
+    config.service(archive::archive);
     config.service(archive::tar_gz_file);
     config.service(archive::zip_file);
     config.service(issues::all_issues);
+    config.service(releases::download_asset);
     config.service(import::import_repo);
     config.service(repo_create::new_repo);
+    config.service(search::search_code);
     config.service(repo_view::view_repo);
     config.service(repo_view::view_repo_tree); // Always needs to be last in this list
 }
@@ -38,3 +54,26 @@ pub(crate) struct GitTreeRequest {
     pub(crate) repository: String,
     pub(crate) tree: String
 }
+
+/// Finds a repository for downloads which should also work outside the browser (e.g. archives or raw files).
+/// Logged in users are checked using their session, everyone else may authenticate using Basic auth (e.g. access tokens for `curl`)
+pub(crate) async fn find_readable_repo(username: &str, repository: &str, web_user: WebUser, request: &HttpRequest, transaction: &mut Transaction<'_, Postgres>) -> Result<Either<Repository, HttpResponse>> {
+    let repo_option = match User::find_using_name(username, &mut *transaction).await {
+        Some(repo_owner) => Repository::open(repo_owner, repository, &mut *transaction).await,
+        None => None
+    };
+
+    let (user, repo) = match web_user {
+        WebUser::Authenticated(user) => (Some(user), repo_option.ok_or_else(|| err!(NOT_FOUND, "Repository not found"))?),
+        WebUser::Anonymous => match basic_auth::validate_repo_access(repo_option, "application/octet-stream", TokenScopes::READ_REPOSITORY, request, &mut *transaction).await? {
+            Either::Left(tuple) => tuple,
+            Either::Right(response) => return Ok(Either::Right(response))
+        }
+    };
+
+    if !privilege::check_access(&repo, user.as_ref(), &mut *transaction).await? {
+        die!(NOT_FOUND, "Repository not found");
+    }
+
+    Ok(Either::Left(repo))
+}
@@ -0,0 +1,74 @@
+use crate::privileges::privilege;
+use crate::release::{self, Release};
+use crate::routes::repository::find_readable_repo;
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_files::NamedFile;
+use actix_web::http::header::{CONTENT_TYPE, ContentDisposition, DispositionParam, DispositionType, HeaderValue, X_CONTENT_TYPE_OPTIONS};
+use actix_web::{Either, HttpRequest, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use serde::Deserialize;
+use sqlx::PgPool;
+
+/// Downloads a release asset. Assets are always served as attachment, as their content type is chosen by the uploader
+#[route("/{username}/{repository}/releases/download/{tag:.+}/{name}", method = "GET", err = "text")]
+pub(crate) async fn download_asset(uri: web::Path<AssetRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let user_id = web_user.as_ref().map(|user| user.id);
+
+    let repo = match find_readable_repo(uri.username.as_str(), uri.repository.as_str(), web_user, &request, &mut transaction).await? {
+        Either::Left(repo) => repo,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let release = Release::find(&repo, uri.tag.as_str(), &mut transaction).await?.ok_or_else(|| err!(NOT_FOUND, "Release not found"))?;
+
+    // `find_readable_repo` takes ownership of the user, so it needs to be loaded again to check whenever it can see drafts
+    if release.draft {
+        let user = match user_id {
+            Some(id) => sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+                .bind(&id)
+                .fetch_optional(&mut transaction)
+                .await?,
+            None => None
+        };
+
+        if !privilege::check_push(&repo, user.as_ref(), &mut transaction).await? {
+            die!(NOT_FOUND, "Release not found");
+        }
+    }
+
+    let asset = release.assets(&mut transaction).await?
+        .into_iter()
+        .find(|asset| asset.name == uri.name)
+        .ok_or_else(|| err!(NOT_FOUND, "Asset not found"))?;
+
+    let repo_path = repo.get_fs_path(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let file = NamedFile::open_async(release::asset_path(repo_path.as_str(), release.id, asset.id)).await?
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(asset.name.clone())]
+        });
+
+    let mut response = file.into_response(&request);
+    let headers = response.headers_mut();
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_str(asset.content_type.as_str()).unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")));
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AssetRequest {
+    username: String,
+    repository: String,
+    tag: String,
+    name: String
+}
@@ -1,4 +1,5 @@
 use crate::prelude::ContextExtensions;
+use crate::templates::init;
 use crate::{Ipc, render_template};
 use crate::user::WebUser;
 
@@ -18,6 +19,8 @@ pub(crate) async fn new_repo(web_user: WebUser, ipc: web::Data<RwLock<Ipc>>, db_
     context.insert_user(&user)?;
 
     context.try_insert("ipc_enabled", &ipc.read().await.is_connected())?;
+    context.try_insert("gitignore_templates", &init::gitignore_names().await?)?;
+    context.try_insert("licenses", &init::license_names().await?)?;
 
     render_template!("repo/create.html", context, transaction)
 }
@@ -1,11 +1,14 @@
 use crate::git::GIT_HASH_KIND;
 use crate::git::history::{all_branches, all_commits, all_tags, last_commit_for_blob, last_commit_for_ref};
+use crate::git::languages::languages;
 use crate::git::utils::{read_blob_content, repo_files_at_ref};
+use crate::mirror::MirrorSettings;
 use crate::prelude::{ContextExtensions, LibGit2SignatureExtensions};
 use crate::privileges::privilege;
 use crate::repository::Repository;
 use crate::routes::repository::{GitRequest, GitTreeRequest};
 use crate::templates::web::{GitCommit, RepoFile};
+use crate::topics;
 use crate::user::{User, WebUser};
 use crate::{die, err, render_template};
 
@@ -48,9 +51,16 @@ async fn render(tree_option: Option<&str>, repo: Repository, username: &str, web
     context.try_insert("tree", tree_name)?;
     context.try_insert("branches", &all_branches(&libgit2_repo).await?)?;
     context.try_insert("tags", &all_tags(&libgit2_repo, None).await?)?;
-    context.try_insert("repo_size", &repo.repo_size(&mut transaction).await?)?;
+    context.try_insert("repo_size", &repo.repo_size_bytes)?;
+    context.try_insert("topics", &topics::for_repo(&repo, &mut transaction).await?)?;
     context.insert_web_user(&web_user)?;
 
+    if repo.mirrored_from.is_some() {
+        if let Some(mirror) = MirrorSettings::find(&repo, &mut transaction).await? {
+            context.try_insert("mirror", &mirror)?;
+        }
+    }
+
     let loose_ref = match gitoxide_repo.refs.find_loose(tree_name) {
         Ok(loose_ref) => Ok(loose_ref),
         Err(GitoxideFindError::Find(err)) => Err(err),
@@ -101,7 +111,9 @@ async fn render(tree_option: Option<&str>, repo: Repository, username: &str, web
                 date: None,
                 author_name: String::new(), // Unused for file listing
                 author_uid: None, // Unused for file listing
-                author_email: String::new() // Unused for file listing
+                author_email: String::new(), // Unused for file listing
+                verification: None,
+                status: None
             }
         });
     }
@@ -143,6 +155,7 @@ async fn render(tree_option: Option<&str>, repo: Repository, username: &str, web
     }
 
     context.try_insert("files", &files)?;
+    context.try_insert("languages", &languages(&repo, &mut transaction).await?)?;
     context.try_insert("commits_count", &all_commits(&libgit2_repo, full_tree_name, 0).await?.len())?;
 
     let last_commit_oid = last_commit_for_ref(&libgit2_repo, full_tree_name).await?.ok_or_else(|| err!(OK, "Repository is empty"))?;
@@ -158,7 +171,9 @@ async fn render(tree_option: Option<&str>, repo: Repository, username: &str, web
         date: None,
         author_name,
         author_uid,
-        author_email
+        author_email,
+        verification: None,
+        status: None
     })?;
 
     render_template!("repo/index.html", context, transaction)
@@ -0,0 +1,52 @@
+use crate::git::search;
+use crate::prelude::*;
+use crate::routes::repository::{find_readable_repo, GitRequest};
+use crate::user::WebUser;
+use crate::render_template;
+
+use std::path::Path;
+
+use actix_web::{Either, HttpRequest, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use sqlx::PgPool;
+use tera::Context;
+
+/// Searches the file contents of the default branch. Searches are literal unless `regex=true` is passed
+#[route("/{username}/{repository}/search", method = "GET", err = "html")]
+pub(crate) async fn search_code(uri: web::Path<GitRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+    let mut context = Context::new();
+
+    // `find_readable_repo` takes ownership of the user, so it needs to be inserted beforehand
+    context.insert_web_user(&web_user)?;
+
+    let repo = match find_readable_repo(uri.username.as_str(), uri.repository.as_str(), web_user, &request, &mut transaction).await? {
+        Either::Left(repo) => repo,
+        Either::Right(response) => return Ok(response)
+    };
+
+    let query_string = request.q_string();
+    let query = query_string.get("q").map(str::trim).unwrap_or_default();
+    let regex = query_string.get("regex") == Some("true");
+    let ignore_case = query_string.get("case") != Some("true");
+
+    context.try_insert("repo_owner_name", uri.username.as_str())?;
+    context.try_insert("repo", &repo)?;
+    context.try_insert("query", query)?;
+    context.try_insert("regex", &regex)?;
+    context.try_insert("case_sensitive", &!ignore_case)?;
+
+    if !query.is_empty() {
+        let repo_path = repo.get_fs_path(&mut transaction).await?;
+        let tree = format!("refs/heads/{}", &repo.default_branch);
+
+        let _fs_lock = repo.lock_fs_shared().await;
+        let results = search::search(Path::new(repo_path.as_str()), tree.as_str(), query, regex, ignore_case).await?;
+
+        context.try_insert("results", &results)?;
+        context.try_insert("max_results", &search::MAX_RESULTS)?;
+    }
+
+    render_template!("repo/search.html", context, transaction)
+}
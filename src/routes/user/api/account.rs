@@ -0,0 +1,214 @@
+use crate::audit::{self, AuditEvent};
+use crate::organization::{self, OrganizationRole};
+use crate::repository::Repository;
+use crate::user::WebUser;
+use crate::{die, err, session, totp};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use actix_identity::Identity;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::{Context, Result};
+use gitarena_macros::route;
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use utoipa::Component;
+
+/// Deletes the account of the current user. Sessions, keys, emails and other account data are removed through the foreign keys of the schema.
+/// Issues, pull requests and comments in repositories of others are kept and shown as written by [Ghost](crate::user::GHOST_USERNAME).
+/// Repositories of the user are either deleted as well or transferred to an organization the user owns
+#[utoipa::path(
+    delete,
+    path = "/api/user",
+    request_body = DeleteAccountJsonRequest,
+    responses(
+        (status = 204, description = "Account has been deleted"),
+        (status = 401, description = "Not logged in, incorrect password or two-factor authentication code or SSO login is not recent"),
+        (status = 409, description = "User is the only owner of an organization")
+    ),
+    tag = "user"
+)]
+#[route("/api/user", method = "DELETE", err = "json")]
+pub(crate) async fn delete_account(body: web::Json<DeleteAccountJsonRequest>, web_user: WebUser, id: Identity, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    let mut transaction = db_pool.begin().await?;
+
+    session::reauthenticate(&user, body.password.as_str(), id.identity(), &mut transaction).await?;
+
+    if user.totp_secret.is_some() {
+        let code = body.code.as_deref().ok_or_else(|| err!(UNAUTHORIZED, "Two-factor authentication code is required"))?;
+
+        if !totp::check_code(&user, code, &mut transaction).await? {
+            die!(UNAUTHORIZED, "Invalid two-factor authentication code");
+        }
+    }
+
+    let (sole_owner,): (bool,) = sqlx::query_as("select exists(select 1 from organization_members as owner where owner.member = $1 and owner.role = 'owner' \
+        and not exists(select 1 from organization_members as other where other.organization = owner.organization and other.member != $1 and other.role = 'owner'))")
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if sole_owner {
+        die!(CONFLICT, "You are the only owner of an organization. Add another owner or delete the organization first");
+    }
+
+    let repositories: Vec<Repository> = sqlx::query_as::<_, Repository>("select * from repositories where owner = $1 order by id")
+        .bind(&user.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    // Running Git operations need to finish before the directories are moved or removed. Locked in id order so concurrent requests cannot deadlock
+    let mut _fs_locks = Vec::with_capacity(repositories.len());
+
+    for repo in repositories.iter() {
+        _fs_locks.push(repo.lock_fs().await);
+    }
+
+    let mut old_paths = Vec::with_capacity(repositories.len());
+
+    for repo in repositories.iter() {
+        old_paths.push(repo.get_fs_path(&mut transaction).await?);
+    }
+
+    let target = match body.transfer_to.as_deref() {
+        Some(target_name) => {
+            // Moving repositories onto somebody else needs their consent, which the transfer flow asks for.
+            // Only organizations the user owns can be picked here, as the user already manages them
+            let target = organization::find_organization(target_name, &mut transaction).await?
+                .ok_or_else(|| err!(NOT_FOUND, "Repositories can only be transferred to organizations. Transfer them to other users individually before deleting your account"))?;
+
+            if organization::find_role(&target, &user, &mut transaction).await? != Some(OrganizationRole::Owner) {
+                die!(FORBIDDEN, "Repositories can only be transferred to organizations you are an owner of");
+            }
+
+            if target.disabled {
+                die!(BAD_REQUEST, "Repositories cannot be transferred to a disabled organization");
+            }
+
+            let (conflict,): (Option<String>,) = sqlx::query_as("select min(name) from repositories where owner = $1 and lower(name) in (select lower(name) from repositories where owner = $2)")
+                .bind(&target.id)
+                .bind(&user.id)
+                .fetch_one(&mut transaction)
+                .await?;
+
+            if let Some(name) = conflict {
+                die!(CONFLICT, "{} already has a repository named {}", &target.username, name);
+            }
+
+            sqlx::query("delete from repository_transfers where repo in (select id from repositories where owner = $1)")
+                .bind(&user.id)
+                .execute(&mut transaction)
+                .await?;
+
+            sqlx::query("update repositories set owner = $1 where owner = $2")
+                .bind(&target.id)
+                .bind(&user.id)
+                .execute(&mut transaction)
+                .await?;
+
+            Some(target)
+        }
+        None => {
+            // Forks only reference the repository they were forked from by id without a foreign key
+            sqlx::query("update repositories set forked_from = null where forked_from in (select id from repositories where owner = $1)")
+                .bind(&user.id)
+                .execute(&mut transaction)
+                .await?;
+
+            // Deleted through the foreign key of the repositories table, the directories are removed once the transaction has been committed
+            None
+        }
+    };
+
+    sqlx::query("delete from users where id = $1")
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    match &target {
+        Some(target) => {
+            // Repositories are stored by owner name, so the directories need to move as well. They're moved back if anything fails
+            let mut moved = Vec::with_capacity(repositories.len());
+
+            for (repo, old_path) in repositories.iter().zip(old_paths.iter()) {
+                match move_repository(old_path.as_str(), target.username.as_str(), repo.name.as_str()) {
+                    Ok(new_path) => moved.push((old_path.as_str(), new_path)),
+                    Err(err) => {
+                        move_back(moved.as_slice());
+                        return Err(err);
+                    }
+                }
+            }
+
+            if let Err(err) = transaction.commit().await {
+                move_back(moved.as_slice());
+                return Err(err.into());
+            }
+
+            info!("Transferred {} repositories of {} (id {}) to {} (id {})", repositories.len(), &user.username, &user.id, &target.username, &target.id);
+        }
+        None => {
+            transaction.commit().await?;
+
+            for path in old_paths {
+                if let Err(err) = fs::remove_dir_all(path.as_str()) {
+                    warn!("Failed to remove repository directory {} of deleted user {} (id {}): {}", &path, &user.username, &user.id, err);
+                }
+            }
+        }
+    }
+
+    audit::record(&request, AuditEvent::AccountDeleted, None, Some(format!("user:{}", &user.username)), json!({
+        "id": &user.id,
+        "repositories": repositories.len(),
+        "transferred_to": target.as_ref().map(|target| target.username.as_str())
+    })).await;
+
+    id.forget();
+
+    info!("{} (id {}) deleted their account", &user.username, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Moves the directory of repository `name` at `old_path` into the directory of `owner` and returns its new path
+fn move_repository(old_path: &str, owner: &str, name: &str) -> Result<PathBuf> {
+    let target_dir = Path::new(old_path)
+        .parent()
+        .and_then(Path::parent)
+        .ok_or_else(|| err!(INTERNAL_SERVER_ERROR, "Repository path {} has no base directory", old_path))?
+        .join(owner);
+
+    fs::create_dir_all(&target_dir)?;
+
+    let new_path = target_dir.join(name);
+    fs::rename(old_path, &new_path).with_context(|| format!("Failed to move repository {} to {}", old_path, new_path.display()))?;
+
+    Ok(new_path)
+}
+
+fn move_back(moved: &[(&str, PathBuf)]) {
+    for (old_path, new_path) in moved {
+        if let Err(err) = fs::rename(new_path, old_path) {
+            error!("Failed to move repository {} back to {}: {}", new_path.display(), old_path, err);
+        }
+    }
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct DeleteAccountJsonRequest {
+    /// Not required for users registered using SSO, who need to have signed in recently instead
+    #[serde(default)]
+    password: String,
+    /// Required if two-factor authentication is enabled
+    #[serde(default)]
+    code: Option<String>,
+    /// Name of an organization owned by the user repositories should be transferred to. If not set, repositories are deleted
+    #[serde(default)]
+    transfer_to: Option<String>
+}
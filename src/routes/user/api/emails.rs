@@ -0,0 +1,219 @@
+use crate::mail::Email;
+use crate::user::WebUser;
+use crate::utils::identifiers::is_valid_email;
+use crate::utils::is_unique_violation;
+use crate::verification::send_verification_mail;
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::Component;
+
+#[utoipa::path(
+    get,
+    path = "/api/user/emails",
+    responses(
+        (status = 200, description = "Email addresses of the current user, primary first", body = [Email]),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/emails", method = "GET", err = "json")]
+pub(crate) async fn list_emails(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let emails: Vec<Email> = sqlx::query_as::<_, Email>("select * from emails where owner = $1 order by \"primary\" desc, created_at")
+        .bind(&user.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(emails))
+}
+
+/// Adds an email address to the current user and sends a verification mail to it
+#[utoipa::path(
+    post,
+    path = "/api/user/emails",
+    request_body = AddEmailJsonRequest,
+    responses(
+        (status = 201, description = "Email address has been added", body = Email),
+        (status = 400, description = "Invalid email address"),
+        (status = 401, description = "Not logged in"),
+        (status = 409, description = "Email address is already in use")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/emails", method = "POST", err = "json")]
+pub(crate) async fn add_email(body: web::Json<AddEmailJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let address = body.email.trim();
+
+    if !is_valid_email(address) {
+        die!(BAD_REQUEST, "Invalid email address");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let email: Email = match sqlx::query_as::<_, Email>("insert into emails (owner, email) values ($1, $2) returning *")
+        .bind(&user.id)
+        .bind(address)
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(email) => email,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "Email already in use"),
+        Err(err) => return Err(err.into())
+    };
+
+    send_verification_mail(&user, &email, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("{} (id {}) added email address {} (id {})", &user.username, &user.id, &email.email, &email.id);
+
+    Ok(HttpResponse::Created().json(email))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/user/emails/{id}/primary",
+    params(
+        ("id" = i32, path, description = "Id of the email address")
+    ),
+    responses(
+        (status = 204, description = "Email address is now the primary email address"),
+        (status = 400, description = "Email address has not been verified yet"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Email address not found")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/emails/{id}/primary", method = "PUT", err = "json")]
+pub(crate) async fn set_primary_email(id: web::Path<i32>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let email: Email = sqlx::query_as::<_, Email>("select * from emails where id = $1 and owner = $2 limit 1")
+        .bind(id.into_inner())
+        .bind(&user.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Email address not found"))?;
+
+    if email.verified_at.is_none() {
+        die!(BAD_REQUEST, "Only verified email addresses can be made primary");
+    }
+
+    sqlx::query("update emails set \"primary\" = (id = $1) where owner = $2")
+        .bind(&email.id)
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/user/emails/{id}",
+    params(
+        ("id" = i32, path, description = "Id of the email address")
+    ),
+    responses(
+        (status = 204, description = "Email address has been deleted"),
+        (status = 400, description = "Email address is the primary email address"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Email address not found")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/emails/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_email(id: web::Path<i32>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let email: Email = sqlx::query_as::<_, Email>("select * from emails where id = $1 and owner = $2 limit 1")
+        .bind(id.into_inner())
+        .bind(&user.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Email address not found"))?;
+
+    if email.primary {
+        die!(BAD_REQUEST, "The primary email address cannot be deleted");
+    }
+
+    // Every user has exactly one commit, notification and public email, so these roles fall back to the primary email
+    sqlx::query("update emails set commit = commit or $2, notification = notification or $3, public = public or $4 where owner = $1 and \"primary\"")
+        .bind(&user.id)
+        .bind(&email.commit)
+        .bind(&email.notification)
+        .bind(&email.public)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query("delete from emails where id = $1")
+        .bind(&email.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("{} (id {}) deleted email address {} (id {})", &user.username, &user.id, &email.email, &email.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Toggles whenever commits shown and authored by GitArena use the noreply alias instead of the actual email address
+#[utoipa::path(
+    put,
+    path = "/api/user/emails/privacy",
+    request_body = EmailPrivacyJsonRequest,
+    responses(
+        (status = 200, description = "Email privacy has been updated", body = EmailPrivacyJsonResponse),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/emails/privacy", method = "PUT", err = "json")]
+pub(crate) async fn set_email_privacy(body: web::Json<EmailPrivacyJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    sqlx::query("update users set private_email = $1 where id = $2")
+        .bind(&body.private)
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(EmailPrivacyJsonResponse {
+        private: body.private,
+        noreply_email: user.noreply_email()
+    }))
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct AddEmailJsonRequest {
+    email: String
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct EmailPrivacyJsonRequest {
+    private: bool
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct EmailPrivacyJsonResponse {
+    private: bool,
+    noreply_email: String
+}
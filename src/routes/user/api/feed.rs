@@ -0,0 +1,33 @@
+use crate::activity;
+use crate::die;
+use crate::user::WebUser;
+use crate::utils::pagination::{Page, PageQuery};
+
+use actix_web::{HttpRequest, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use sqlx::PgPool;
+
+/// Recent pushes, releases and new repositories of users followed by the current user, newest first.
+/// Paginated using the id of the last activity as cursor
+#[route("/api/feed", method = "GET", err = "json")]
+pub(crate) async fn get_feed(page: web::Query<PageQuery>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    let after = match page.after.as_deref().map(str::parse::<i32>) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => die!(BAD_REQUEST, "Invalid cursor"),
+        None => None
+    };
+
+    let mut transaction = db_pool.begin().await?;
+
+    let limit = page.limit(&mut transaction).await?;
+    let entries = activity::feed(&user, after, limit as i64 + 1, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let page = Page::new(entries, limit, |entry| entry.id.to_string());
+
+    Ok(page.respond(&request, limit))
+}
@@ -0,0 +1,190 @@
+use crate::user::{User, WebUser};
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use utoipa::Component;
+
+/// Maximum amount of users returned by [list_followers] and [list_following] at once
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[utoipa::path(
+    put,
+    path = "/api/user/{username}/follow",
+    params(
+        ("username" = String, path, description = "User to follow")
+    ),
+    responses(
+        (status = 204, description = "Following the user"),
+        (status = 400, description = "Users cannot follow themselves"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "User not found")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/{username}/follow", method = "PUT", err = "json")]
+pub(crate) async fn follow(username: web::Path<String>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_user(username.as_str(), &mut transaction).await?;
+
+    if target.id == user.id {
+        die!(BAD_REQUEST, "Users cannot follow themselves");
+    }
+
+    // Following twice is not an error, so the request can safely be retried
+    sqlx::query("insert into follows (follower, followee) values ($1, $2) on conflict do nothing")
+        .bind(&user.id)
+        .bind(&target.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("{} (id {}) followed {} (id {})", &user.username, &user.id, &target.username, &target.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/user/{username}/follow",
+    params(
+        ("username" = String, path, description = "User to unfollow")
+    ),
+    responses(
+        (status = 204, description = "No longer following the user"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "User not found or not followed")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/{username}/follow", method = "DELETE", err = "json")]
+pub(crate) async fn unfollow(username: web::Path<String>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_user(username.as_str(), &mut transaction).await?;
+
+    let result = sqlx::query("delete from follows where follower = $1 and followee = $2")
+        .bind(&user.id)
+        .bind(&target.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    if result.rows_affected() == 0 {
+        die!(NOT_FOUND, "Not following {}", &target.username);
+    }
+
+    debug!("{} (id {}) unfollowed {} (id {})", &user.username, &user.id, &target.username, &target.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Lists the users following `username`, most recent first. Paginated using `offset` and `limit` query parameters
+#[utoipa::path(
+    get,
+    path = "/api/user/{username}/followers",
+    params(
+        ("username" = String, path, description = "Name of the user"),
+        ("offset" = Option<i64>, query, description = "Amount of users to skip"),
+        ("limit" = Option<i64>, query, description = "Maximum amount of users to return, up to 100")
+    ),
+    responses(
+        (status = 200, description = "Users following this user", body = [FollowJsonEntry]),
+        (status = 404, description = "User not found")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/{username}/followers", method = "GET", err = "json")]
+pub(crate) async fn list_followers(username: web::Path<String>, query: web::Query<FollowListQuery>, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_user(username.as_str(), &mut transaction).await?;
+
+    let users: Vec<FollowJsonEntry> = sqlx::query_as::<_, FollowJsonEntry>("select users.id, users.username, follows.created_at as since from follows \
+        inner join users on users.id = follows.follower where follows.followee = $1 and not users.disabled \
+        order by follows.created_at desc offset $2 limit $3")
+        .bind(&target.id)
+        .bind(&query.offset())
+        .bind(&query.limit())
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Lists the users `username` follows, most recent first. Paginated using `offset` and `limit` query parameters
+#[utoipa::path(
+    get,
+    path = "/api/user/{username}/following",
+    params(
+        ("username" = String, path, description = "Name of the user"),
+        ("offset" = Option<i64>, query, description = "Amount of users to skip"),
+        ("limit" = Option<i64>, query, description = "Maximum amount of users to return, up to 100")
+    ),
+    responses(
+        (status = 200, description = "Users this user follows", body = [FollowJsonEntry]),
+        (status = 404, description = "User not found")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/{username}/following", method = "GET", err = "json")]
+pub(crate) async fn list_following(username: web::Path<String>, query: web::Query<FollowListQuery>, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let target = find_user(username.as_str(), &mut transaction).await?;
+
+    let users: Vec<FollowJsonEntry> = sqlx::query_as::<_, FollowJsonEntry>("select users.id, users.username, follows.created_at as since from follows \
+        inner join users on users.id = follows.followee where follows.follower = $1 and not users.disabled \
+        order by follows.created_at desc offset $2 limit $3")
+        .bind(&target.id)
+        .bind(&query.offset())
+        .bind(&query.limit())
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(users))
+}
+
+async fn find_user(username: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    User::find_using_name(username, &mut *transaction)
+        .await
+        .filter(|user| !user.disabled)
+        .ok_or_else(|| err!(NOT_FOUND, "User not found").into())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FollowListQuery {
+    offset: Option<i64>,
+    limit: Option<i64>
+}
+
+impl FollowListQuery {
+    fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(20).clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(FromRow, Serialize, Component)]
+pub(crate) struct FollowJsonEntry {
+    id: i32,
+    username: String,
+    since: DateTime<Utc>
+}
@@ -0,0 +1,134 @@
+use crate::gpg::{self, GpgKey};
+use crate::user::WebUser;
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::debug;
+use pgp::types::KeyTrait;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::Component;
+
+#[utoipa::path(
+    post,
+    path = "/api/user/gpg_keys",
+    request_body = AddGpgKeyJsonRequest,
+    responses(
+        (status = 201, description = "GPG key has been added", body = AddGpgKeyJsonResponse),
+        (status = 400, description = "Invalid GPG public key"),
+        (status = 401, description = "Not logged in"),
+        (status = 409, description = "GPG key already exists")
+    ),
+    tag = "keys"
+)]
+#[route("/api/user/gpg_keys", method = "POST", err = "json")]
+pub(crate) async fn add_gpg_key(body: web::Json<AddGpgKeyJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    if body.key.trim().is_empty() {
+        die!(BAD_REQUEST, "Key is not a valid argument");
+    }
+
+    let public_key = gpg::parse_public_key(body.key.trim()).map_err(|err| err!(BAD_REQUEST, "Failed to parse GPG public key: {}", err))?;
+
+    let key_id = gpg::format_key_id(&public_key.key_id());
+    let subkey_ids = gpg::subkey_ids(&public_key);
+
+    let mut transaction = db_pool.begin().await?;
+
+    let (exists,): (bool,) = sqlx::query_as("select exists(select 1 from gpg_keys where key_id = $1 limit 1)")
+        .bind(key_id.as_str())
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if exists {
+        die!(CONFLICT, "GPG key already exists");
+    }
+
+    let gpg_key = sqlx::query_as::<_, GpgKey>("insert into gpg_keys (owner, key_id, subkey_ids, armored) values ($1, $2, $3, $4) returning *")
+        .bind(&user.id)
+        .bind(key_id.as_str())
+        .bind(&subkey_ids)
+        .bind(body.key.trim())
+        .fetch_one(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    debug!("New GPG key added for user {}: {} (id {})", &user.id, &gpg_key.key_id, &gpg_key.id);
+
+    Ok(HttpResponse::Created().json(AddGpgKeyJsonResponse {
+        id: gpg_key.id,
+        key_id: gpg_key.key_id
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/user/gpg_keys",
+    responses(
+        (status = 200, description = "GPG keys of the current user, newest first"),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "keys"
+)]
+#[route("/api/user/gpg_keys", method = "GET", err = "json")]
+pub(crate) async fn list_gpg_keys(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let gpg_keys: Vec<GpgKey> = sqlx::query_as::<_, GpgKey>("select * from gpg_keys where owner = $1 order by created_at desc")
+        .bind(&user.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(gpg_keys))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/user/gpg_keys/{id}",
+    params(
+        ("id" = i32, path, description = "Id of the GPG key")
+    ),
+    responses(
+        (status = 204, description = "GPG key has been removed"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "GPG key not found")
+    ),
+    tag = "keys"
+)]
+#[route("/api/user/gpg_keys/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_gpg_key(id: web::Path<i32>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let (deleted_id,): (i32,) = sqlx::query_as("delete from gpg_keys where id = $1 and owner = $2 returning id")
+        .bind(id.into_inner())
+        .bind(&user.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "GPG key not found"))?;
+
+    transaction.commit().await?;
+
+    debug!("GPG key {} removed by user {}", deleted_id, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct AddGpgKeyJsonRequest {
+    /// ASCII armored public key
+    key: String
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct AddGpgKeyJsonResponse {
+    id: i32,
+    key_id: String
+}
@@ -1,7 +1,61 @@
 use actix_web::web::ServiceConfig;
 
-mod add_key;
+pub(crate) mod account;
+pub(crate) mod emails;
+mod feed;
+pub(crate) mod follows;
+pub(crate) mod gpg_keys;
+mod notifications;
+mod password;
+pub(crate) mod profile;
+pub(crate) mod sessions;
+pub(crate) mod ssh_keys;
+mod starred;
+pub(crate) mod tokens;
+pub(crate) mod two_factor;
 
 pub(crate) fn init(config: &mut ServiceConfig) {
-    config.service(add_key::put_ssh_key);
+    config.service(account::delete_account);
+
+    config.service(emails::list_emails);
+    config.service(emails::add_email);
+    config.service(emails::set_email_privacy);
+    config.service(emails::set_primary_email);
+    config.service(emails::delete_email);
+
+    config.service(feed::get_feed);
+
+    config.service(follows::follow);
+    config.service(follows::unfollow);
+    config.service(follows::list_followers);
+    config.service(follows::list_following);
+
+    config.service(gpg_keys::add_gpg_key);
+    config.service(gpg_keys::list_gpg_keys);
+    config.service(gpg_keys::delete_gpg_key);
+
+    config.service(notifications::list_notifications);
+    config.service(notifications::mark_all_notifications_read);
+    config.service(notifications::mark_notification_read);
+    config.service(notifications::get_preferences);
+    config.service(notifications::put_preferences);
+
+    config.service(password::password_strength);
+
+    config.service(profile::get_profile);
+
+    config.service(sessions::delete_sessions);
+
+    config.service(ssh_keys::add_ssh_key);
+    config.service(ssh_keys::list_ssh_keys);
+    config.service(ssh_keys::delete_ssh_key);
+
+    config.service(starred::list_starred);
+
+    config.service(tokens::create_token);
+    config.service(tokens::list_tokens);
+    config.service(tokens::delete_token);
+
+    config.service(two_factor::enroll);
+    config.service(two_factor::verify);
 }
@@ -0,0 +1,102 @@
+use crate::notification;
+use crate::user::WebUser;
+use crate::utils::pagination::{Page, PageQuery};
+use crate::die;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+/// Notifications of the current user, newest first. Only unread notifications are returned if `unread` is set.
+/// Paginated using the id of the last notification as cursor
+#[route("/api/notifications", method = "GET", err = "json")]
+pub(crate) async fn list_notifications(page: web::Query<PageQuery>, filter: web::Query<NotificationFilter>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    let after = match page.after.as_deref().map(str::parse::<i32>) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => die!(BAD_REQUEST, "Invalid cursor"),
+        None => None
+    };
+
+    let mut transaction = db_pool.begin().await?;
+
+    let limit = page.limit(&mut transaction).await?;
+    let notifications = notification::list(&user, filter.unread, after, limit as i64 + 1, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    let page = Page::new(notifications, limit, |notification| notification.id.to_string());
+
+    Ok(page.respond(&request, limit))
+}
+
+#[route("/api/notifications/{id}/read", method = "PUT", err = "json")]
+pub(crate) async fn mark_notification_read(id: web::Path<i32>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    if !notification::mark_read(&user, id.into_inner(), &mut transaction).await? {
+        die!(NOT_FOUND, "Notification not found");
+    }
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[route("/api/notifications/read", method = "PUT", err = "json")]
+pub(crate) async fn mark_all_notifications_read(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let marked = notification::mark_all_read(&user, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "marked": marked
+    })))
+}
+
+#[route("/api/notifications/preferences", method = "GET", err = "json")]
+pub(crate) async fn get_preferences(web_user: WebUser) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    Ok(HttpResponse::Ok().json(NotificationPreferences {
+        login_emails: user.login_notifications
+    }))
+}
+
+/// Updates the notification preferences of the current user. Login emails can still be disabled instance-wide using
+/// the `users.login_notifications` setting
+#[route("/api/notifications/preferences", method = "PUT", err = "json")]
+pub(crate) async fn put_preferences(body: web::Json<NotificationPreferences>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    sqlx::query("update users set login_notifications = $1 where id = $2")
+        .bind(&body.login_emails)
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(body.into_inner()))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct NotificationFilter {
+    #[serde(default)]
+    unread: bool
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) struct NotificationPreferences {
+    /// Email about logins from new ip addresses
+    login_emails: bool
+}
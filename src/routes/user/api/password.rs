@@ -0,0 +1,73 @@
+use crate::utils::rate_limit::IpRateLimiter;
+use crate::{die, password, session};
+
+use std::time::Duration;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+/// Estimating gets slow for long inputs, no real password or username comes close to this
+const MAX_INPUT_LENGTH: usize = 256;
+
+/// The frontend checks while typing, so this is generous while still preventing the endpoint from being used to burn CPU
+const MAX_REQUESTS: usize = 120;
+const REQUESTS_WINDOW: Duration = Duration::from_secs(60);
+
+static REQUESTS: Lazy<IpRateLimiter> = Lazy::new(|| IpRateLimiter::new(MAX_REQUESTS, REQUESTS_WINDOW, false));
+
+/// Estimates the strength of a password so the frontend can display a strength meter.
+/// Does not require authentication as it is used on the registration page
+#[route("/api/user/password/strength", method = "POST", err = "json")]
+pub(crate) async fn password_strength(body: web::Json<PasswordStrengthJsonRequest>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let (ip_address, _) = session::extract_ip_and_ua(&request);
+
+    if REQUESTS.check(ip_address.ip()) {
+        die!(TOO_MANY_REQUESTS, "Too many requests. Please try again later.");
+    }
+
+    let inputs = [Some(body.password.as_str()), body.username.as_deref(), body.email.as_deref()];
+
+    if inputs.into_iter().flatten().any(|input| input.len() > MAX_INPUT_LENGTH) {
+        die!(BAD_REQUEST, "Inputs may only be up to {} characters long", MAX_INPUT_LENGTH);
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let policy_result = password::validate_password(body.password.as_str(), &mut transaction).await;
+
+    transaction.commit().await?;
+
+    let user_inputs = [body.username.as_deref(), body.email.as_deref()].into_iter().flatten().collect::<Vec<_>>();
+    let entropy = password::estimate_strength(body.password.as_str(), user_inputs.as_slice());
+    let feedback = entropy.as_ref().and_then(|entropy| entropy.feedback().as_ref());
+
+    Ok(HttpResponse::Ok().json(PasswordStrengthJsonResponse {
+        score: entropy.as_ref().map_or(0, |entropy| entropy.score()),
+        valid: policy_result.is_ok(),
+        error: policy_result.err().map(|err| err.to_string()),
+        warning: feedback.and_then(|feedback| feedback.warning()).map(|warning| warning.to_string()),
+        suggestions: feedback.map(|feedback| feedback.suggestions().iter().map(|suggestion| suggestion.to_string()).collect()).unwrap_or_default()
+    }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PasswordStrengthJsonRequest {
+    password: String,
+    username: Option<String>,
+    email: Option<String>
+}
+
+#[derive(Serialize)]
+pub(crate) struct PasswordStrengthJsonResponse {
+    /// zxcvbn score from 0 (too guessable) to 4 (very unguessable)
+    score: u8,
+    /// Whether the password satisfies the password policy of this instance
+    valid: bool,
+    error: Option<String>,
+    warning: Option<String>,
+    suggestions: Vec<String>
+}
@@ -0,0 +1,47 @@
+use crate::avatar;
+use crate::user::WebUser;
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use serde::Serialize;
+use sqlx::PgPool;
+use utoipa::Component;
+
+/// Returns the profile of the currently logged in user
+#[utoipa::path(
+    get,
+    path = "/api/user",
+    responses(
+        (status = 200, description = "Profile of the current user", body = ProfileJsonResponse),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "user"
+)]
+#[route("/api/user", method = "GET", err = "json")]
+pub(crate) async fn get_profile(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let avatar_url = avatar::resolve_url(&user, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(ProfileJsonResponse {
+        id: user.id,
+        username: user.username,
+        avatar_url,
+        admin: user.admin,
+        created_at: user.created_at
+    }))
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct ProfileJsonResponse {
+    id: i32,
+    username: String,
+    avatar_url: String,
+    admin: bool,
+    created_at: DateTime<Utc>
+}
@@ -0,0 +1,69 @@
+use crate::session::Session;
+use crate::user::WebUser;
+
+use actix_identity::Identity;
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::Component;
+
+/// Logs the user out everywhere by destroying all their sessions. Git over HTTPS is not affected as it does not use sessions
+#[utoipa::path(
+    delete,
+    path = "/api/user/sessions",
+    params(
+        ("keep_current" = Option<bool>, query, description = "Keeps the session the request has been made with")
+    ),
+    responses(
+        (status = 200, description = "Sessions have been revoked", body = DeleteSessionsJsonResponse),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/sessions", method = "DELETE", err = "json")]
+pub(crate) async fn delete_sessions(query: web::Query<DeleteSessionsQuery>, web_user: WebUser, id: Identity, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let current_session = Session::from_identity(id.identity(), &mut transaction).await?;
+
+    let result = match current_session {
+        Some(session) if query.keep_current => {
+            sqlx::query("delete from sessions where user_id = $1 and hash <> $2")
+                .bind(&user.id)
+                .bind(session.hash.as_str())
+                .execute(&mut transaction)
+                .await?
+        }
+        _ => {
+            id.forget();
+
+            sqlx::query("delete from sessions where user_id = $1")
+                .bind(&user.id)
+                .execute(&mut transaction)
+                .await?
+        }
+    };
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) revoked {} sessions", &user.username, &user.id, result.rows_affected());
+
+    Ok(HttpResponse::Ok().json(DeleteSessionsJsonResponse {
+        revoked: result.rows_affected()
+    }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeleteSessionsQuery {
+    #[serde(default)]
+    keep_current: bool
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct DeleteSessionsJsonResponse {
+    revoked: u64
+}
@@ -0,0 +1,135 @@
+use crate::ssh::{self, ParsedKey, SshKey};
+use crate::user::WebUser;
+use crate::utils::is_unique_violation;
+use crate::{die, err};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::serde::ts_seconds_option;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::Component;
+
+#[utoipa::path(
+    post,
+    path = "/api/user/ssh_keys",
+    request_body = AddKeyJsonRequest,
+    responses(
+        (status = 201, description = "SSH key has been added", body = AddKeyJsonResponse),
+        (status = 400, description = "Invalid SSH public key"),
+        (status = 401, description = "Not logged in"),
+        (status = 409, description = "SSH key already exists")
+    ),
+    tag = "keys"
+)]
+#[route("/api/user/ssh_keys", method = "POST", err = "json")]
+pub(crate) async fn add_ssh_key(body: web::Json<AddKeyJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    let parsed = ParsedKey::parse(body.key.as_str(), body.title.as_str())?;
+
+    let mut transaction = db_pool.begin().await?;
+
+    if ssh::key_in_use(parsed.fingerprint.as_str(), &mut transaction).await? {
+        die!(CONFLICT, "SSH key already exists");
+    }
+
+    let key = match sqlx::query_as::<_, SshKey>("insert into ssh_keys (owner, title, fingerprint, algorithm, key, expires_at) values ($1, $2, $3, $4, $5, $6) returning *")
+        .bind(&user.id)
+        .bind(parsed.title.as_str())
+        .bind(parsed.fingerprint.as_str())
+        .bind(parsed.algorithm)
+        .bind(parsed.key.as_slice())
+        .bind(&body.expiration_date)
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(key) => key,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "SSH key already exists"),
+        Err(err) => return Err(err.into())
+    };
+
+    transaction.commit().await?;
+
+    debug!("New SSH key added for user {}: {} (fingerprint: {} id {})", &user.id, &parsed.title, &parsed.fingerprint, &key.id);
+
+    Ok(HttpResponse::Created().json(AddKeyJsonResponse {
+        id: key.id,
+        fingerprint: parsed.fingerprint
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/user/ssh_keys",
+    responses(
+        (status = 200, description = "SSH keys of the current user, newest first"),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "keys"
+)]
+#[route("/api/user/ssh_keys", method = "GET", err = "json")]
+pub(crate) async fn list_ssh_keys(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let ssh_keys: Vec<SshKey> = sqlx::query_as::<_, SshKey>("select * from ssh_keys where owner = $1 order by created_at desc")
+        .bind(&user.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(ssh_keys))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/user/ssh_keys/{id}",
+    params(
+        ("id" = i32, path, description = "Id of the SSH key")
+    ),
+    responses(
+        (status = 204, description = "SSH key has been removed"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "SSH key not found")
+    ),
+    tag = "keys"
+)]
+#[route("/api/user/ssh_keys/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_ssh_key(id: web::Path<i32>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let (deleted_id,): (i32,) = sqlx::query_as("delete from ssh_keys where id = $1 and owner = $2 returning id")
+        .bind(id.into_inner())
+        .bind(&user.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "SSH key not found"))?;
+
+    transaction.commit().await?;
+
+    debug!("SSH key {} removed by user {}", deleted_id, &user.id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct AddKeyJsonRequest {
+    #[serde(default)]
+    title: String,
+    /// Public key in OpenSSH format
+    key: String,
+    /// Unix timestamp in seconds. Keys without expiration date are valid until removed
+    #[serde(default, with = "ts_seconds_option")]
+    expiration_date: Option<DateTime<Utc>>
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct AddKeyJsonResponse {
+    id: i32,
+    fingerprint: String
+}
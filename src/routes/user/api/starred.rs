@@ -0,0 +1,90 @@
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::{User, WebUser};
+use crate::utils::pagination::{Page, PageQuery};
+use crate::{die, err};
+
+use actix_web::{HttpRequest, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use gitarena_macros::route;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+
+/// Lists the repositories starred by `username` which the current user has access to, most recently starred first.
+/// Paginated using the id of the last star as cursor
+#[route("/api/user/{username}/starred", method = "GET", err = "json")]
+pub(crate) async fn list_starred(username: web::Path<String>, page: web::Query<PageQuery>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let after = match page.after.as_deref().map(str::parse::<i32>) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => die!(BAD_REQUEST, "Invalid cursor"),
+        None => None
+    };
+
+    let mut transaction = db_pool.begin().await?;
+
+    let stargazer = User::find_using_name(username.as_str(), &mut transaction)
+        .await
+        .filter(|user| !user.disabled)
+        .ok_or_else(|| err!(NOT_FOUND, "User not found"))?;
+
+    let limit = page.limit(&mut transaction).await?;
+    let fetch_limit = limit as i64 + 1;
+
+    let mut entries = Vec::<StarredRepository>::with_capacity(limit + 1);
+    let mut cursor = after;
+
+    // Stars of repositories the viewer has no access to are skipped, so more rows are fetched until the page is full
+    loop {
+        let batch: Vec<StarredRepository> = sqlx::query_as::<_, StarredRepository>("select stars.id as star_id, stars.created_at as starred_at, \
+            repositories.id, owners.username as owner, repositories.name, repositories.description from stars \
+            inner join repositories on repositories.id = stars.repo \
+            inner join users owners on owners.id = repositories.owner \
+            where stars.stargazer = $1 and not repositories.disabled and ($2::integer is null or stars.id < $2) \
+            order by stars.id desc limit $3")
+            .bind(&stargazer.id)
+            .bind(&cursor)
+            .bind(&fetch_limit)
+            .fetch_all(&mut transaction)
+            .await?;
+
+        let exhausted = (batch.len() as i64) < fetch_limit;
+        cursor = batch.last().map(|entry| entry.star_id).or(cursor);
+
+        for entry in batch {
+            if entries.len() as i64 >= fetch_limit {
+                break;
+            }
+
+            let repo: Repository = sqlx::query_as::<_, Repository>("select * from repositories where id = $1 limit 1")
+                .bind(&entry.id)
+                .fetch_one(&mut transaction)
+                .await?;
+
+            if privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
+                entries.push(entry);
+            }
+        }
+
+        if exhausted || entries.len() as i64 >= fetch_limit {
+            break;
+        }
+    }
+
+    transaction.commit().await?;
+
+    let page = Page::new(entries, limit, |entry| entry.star_id.to_string());
+
+    Ok(page.respond(&request, limit))
+}
+
+#[derive(FromRow, Serialize)]
+struct StarredRepository {
+    #[serde(skip_serializing)]
+    star_id: i32,
+    id: i32,
+    owner: String,
+    name: String,
+    description: String,
+    starred_at: DateTime<Utc>
+}
@@ -0,0 +1,162 @@
+use crate::access_token::{PersonalAccessToken, TokenScopes, TOKEN_PREFIX};
+use crate::audit::{self, AuditEvent};
+use crate::user::WebUser;
+use crate::{die, err};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::serde::ts_seconds_option;
+use chrono::{DateTime, Local, Utc};
+use gitarena_macros::route;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use utoipa::Component;
+
+/// Creates a personal access token. The token itself is only returned once and can't be retrieved afterwards
+#[utoipa::path(
+    post,
+    path = "/api/user/tokens",
+    request_body = CreateTokenJsonRequest,
+    responses(
+        (status = 201, description = "Token has been created", body = CreateTokenJsonResponse),
+        (status = 400, description = "Invalid name, scopes or expiration date"),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "tokens"
+)]
+#[route("/api/user/tokens", method = "POST", err = "json")]
+pub(crate) async fn create_token(body: web::Json<CreateTokenJsonRequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let name = body.name.trim();
+
+    if name.is_empty() || name.len() > 64 {
+        die!(BAD_REQUEST, "Name must be between 1 and 64 characters long");
+    }
+
+    let scopes = TokenScopes::from_bits(body.scopes);
+
+    if scopes.bits() == 0 {
+        die!(BAD_REQUEST, "At least one scope is required");
+    }
+
+    let expires_at = body.expiration_date.map(|date| date.with_timezone(&Local));
+
+    if matches!(expires_at, Some(date) if date < Local::now()) {
+        die!(BAD_REQUEST, "Expiration date cannot be in the past");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let (access_token, token) = PersonalAccessToken::create(&user, name, scopes, expires_at, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    debug!("New access token created for user {}: {} (id {})", &user.id, &access_token.name, &access_token.id);
+    audit::record(&request, AuditEvent::TokenCreated, Some(&user), Some(format!("token:{}", &access_token.id)), json!({
+        "name": &access_token.name,
+        "scopes": scopes.bits()
+    })).await;
+
+    Ok(HttpResponse::Created().json(CreateTokenJsonResponse {
+        id: access_token.id,
+        token
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/user/tokens",
+    responses(
+        (status = 200, description = "Tokens of the current user, newest first", body = [TokenJsonResponse]),
+        (status = 401, description = "Not logged in")
+    ),
+    tag = "tokens"
+)]
+#[route("/api/user/tokens", method = "GET", err = "json")]
+pub(crate) async fn list_tokens(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let access_tokens: Vec<PersonalAccessToken> = sqlx::query_as::<_, PersonalAccessToken>("select * from access_tokens where owner = $1 order by created_at desc")
+        .bind(&user.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    let tokens = access_tokens.iter()
+        .map(|access_token| TokenJsonResponse {
+            id: access_token.id,
+            name: access_token.name.clone(),
+            prefix: format!("{}{}", TOKEN_PREFIX, access_token.prefix),
+            scopes: access_token.scopes,
+            created_at: access_token.created_at,
+            expires_at: access_token.expires_at,
+            expired: access_token.is_expired()
+        })
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/user/tokens/{id}",
+    params(
+        ("id" = i32, path, description = "Id of the token")
+    ),
+    responses(
+        (status = 204, description = "Token has been revoked"),
+        (status = 401, description = "Not logged in"),
+        (status = 404, description = "Token not found")
+    ),
+    tag = "tokens"
+)]
+#[route("/api/user/tokens/{id}", method = "DELETE", err = "json")]
+pub(crate) async fn delete_token(id: web::Path<i32>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    let (deleted_id,): (i32,) = sqlx::query_as("delete from access_tokens where id = $1 and owner = $2 returning id")
+        .bind(id.into_inner())
+        .bind(&user.id)
+        .fetch_optional(&mut transaction)
+        .await?
+        .ok_or_else(|| err!(NOT_FOUND, "Access token not found"))?;
+
+    transaction.commit().await?;
+
+    debug!("Access token {} revoked by user {}", deleted_id, &user.id);
+    audit::record(&request, AuditEvent::TokenRevoked, Some(&user), Some(format!("token:{}", deleted_id)), json!({})).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct CreateTokenJsonRequest {
+    name: String,
+    /// Bitflags of the granted scopes
+    scopes: i32,
+    /// Unix timestamp in seconds. Tokens without expiration date are valid until revoked
+    #[serde(default, with = "ts_seconds_option")]
+    expiration_date: Option<DateTime<Utc>>
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct CreateTokenJsonResponse {
+    id: i32,
+    token: String
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct TokenJsonResponse {
+    id: i32,
+    name: String,
+    prefix: String,
+    scopes: i32,
+    created_at: DateTime<Local>,
+    expires_at: Option<DateTime<Local>>,
+    expired: bool
+}
@@ -0,0 +1,112 @@
+use crate::user::WebUser;
+use crate::{config, die, err, totp};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::Component;
+
+/// Generates a new secret for two-factor authentication. It is only enabled once a code generated from it has been verified
+#[utoipa::path(
+    post,
+    path = "/api/user/2fa/enroll",
+    responses(
+        (status = 200, description = "Secret and otpauth uri to show as QR code", body = EnrollJsonResponse),
+        (status = 401, description = "Not logged in"),
+        (status = 409, description = "Two-factor authentication is already enabled")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/2fa/enroll", method = "POST", err = "json")]
+pub(crate) async fn enroll(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    if user.totp_secret.is_some() {
+        die!(CONFLICT, "Two-factor authentication is already enabled");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let domain = config::get_optional_setting::<String, _>("domain", &mut transaction).await?.unwrap_or_default();
+    let issuer = domain.trim_start_matches("https://").trim_start_matches("http://");
+
+    transaction.commit().await?;
+
+    // The secret is only persisted once the user confirmed it using `/api/user/2fa/verify`
+    let secret = totp::generate_secret();
+    let uri = totp::otpauth_uri(secret.as_str(), user.username.as_str(), if issuer.is_empty() { "GitArena" } else { issuer });
+
+    Ok(HttpResponse::Ok().json(EnrollJsonResponse {
+        secret,
+        qr_payload: uri
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/user/2fa/verify",
+    request_body = VerifyJsonRequest,
+    responses(
+        (status = 200, description = "Two-factor authentication has been enabled", body = VerifyJsonResponse),
+        (status = 400, description = "Invalid secret or code"),
+        (status = 401, description = "Not logged in"),
+        (status = 409, description = "Two-factor authentication is already enabled")
+    ),
+    tag = "user"
+)]
+#[route("/api/user/2fa/verify", method = "POST", err = "json")]
+pub(crate) async fn verify(body: web::Json<VerifyJsonRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+
+    if user.totp_secret.is_some() {
+        die!(CONFLICT, "Two-factor authentication is already enabled");
+    }
+
+    // 160 bit secrets are exactly 32 characters long when base32 encoded
+    if body.secret.len() != 32 {
+        die!(BAD_REQUEST, "Invalid secret");
+    }
+
+    let step = totp::verify(body.secret.as_str(), body.code.as_str())?.ok_or_else(|| err!(BAD_REQUEST, "Invalid code"))?;
+
+    let mut transaction = db_pool.begin().await?;
+
+    // The code used for enrollment cannot be used to log in afterwards
+    sqlx::query("update users set totp_secret = $1, totp_last_step = $2 where id = $3")
+        .bind(body.secret.as_str())
+        .bind(&(step as i64))
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    let recovery_codes = totp::generate_recovery_codes(&user, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) enabled two-factor authentication", &user.username, &user.id);
+
+    Ok(HttpResponse::Ok().json(VerifyJsonResponse {
+        recovery_codes
+    }))
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct EnrollJsonResponse {
+    secret: String,
+    qr_payload: String
+}
+
+#[derive(Deserialize, Component)]
+pub(crate) struct VerifyJsonRequest {
+    /// Secret returned by `/api/user/2fa/enroll`
+    secret: String,
+    code: String
+}
+
+#[derive(Serialize, Component)]
+pub(crate) struct VerifyJsonResponse {
+    recovery_codes: Vec<String>
+}
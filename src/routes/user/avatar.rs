@@ -1,7 +1,7 @@
 use crate::mail::Email;
 use crate::prelude::{AwcExtensions, HttpRequestExtensions};
 use crate::user::WebUser;
-use crate::{die, err};
+use crate::{avatar, die, err};
 
 use std::fs;
 use std::io::Cursor;
@@ -9,7 +9,7 @@ use std::path::Path;
 use std::time::SystemTime;
 
 use actix_multipart::Multipart;
-use actix_web::http::header::{CACHE_CONTROL, LAST_MODIFIED};
+use actix_web::http::header::{CACHE_CONTROL, ETAG, LAST_MODIFIED};
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::{Context, Result};
 use awc::Client;
@@ -17,8 +17,11 @@ use awc::http::header::IF_MODIFIED_SINCE;
 use chrono::{Duration, NaiveDateTime};
 use futures::TryStreamExt;
 use gitarena_macros::{from_config, route};
-use image::ImageFormat;
-use serde::Deserialize;
+use image::imageops::FilterType;
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, ImageFormat};
+use log::info;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
 #[route("/api/avatar/{user_id}", method = "GET", err = "text")]
@@ -30,14 +33,10 @@ pub(crate) async fn get_avatar(avatar_request: web::Path<AvatarRequest>, request
 
     let query_string = request.q_string();
 
-    if !query_string.has("override") {
-        let path_str = format!("{}/{}.jpg", avatars_dir, avatar_request.user_id);
-        let path = Path::new(path_str.as_str());
-
-        // User has set an avatar, return it
-        if path.is_file() {
-            return send_image(path, &request).await.context("Failed to read local image file");
-        }
+    // User has set an avatar, return it
+    if !query_string.has("override") && avatar::has_upload(avatars_dir.as_str(), avatar_request.user_id) {
+        let path = avatar::upload_path(avatars_dir.as_str(), avatar_request.user_id, avatar::UPLOAD_SIZES[avatar::UPLOAD_SIZES.len() - 1]);
+        return send_image(path, &request).await.context("Failed to read local image file");
     }
 
     // User has not set an avatar, so if Gravatar integration is enabled return it
@@ -53,31 +52,60 @@ pub(crate) async fn get_avatar(avatar_request: web::Path<AvatarRequest>, request
                 .email
         };
 
-        return send_gravatar(email.as_str(), &request).await.context("Failed to request Gravatar image");
+        transaction.commit().await?;
+
+        let url = avatar::gravatar_url(email.as_str(), 500);
+
+        return send_gravatar(url.as_str(), &request).await.context("Failed to request Gravatar image");
     }
 
-    // Gravatar integration is not enabled, return fallback icon
-    // TODO: Maybe generate own identicons? -> There are crates for this
+    // Gravatar integration is not enabled, so generate an identicon without any third party involved.
+    // Commit authors without an account are identified by their email, users by their id so the icon stays the same if they change it
+    let seed = match query_string.get("override") {
+        Some(email) => email.trim().to_lowercase(),
+        None => avatar_request.user_id.to_string()
+    };
 
-    let path_str = format!("{}/default.jpg", avatars_dir);
-    let path = Path::new(path_str.as_str());
+    let png = web::block(move || avatar::identicon(seed.as_bytes())).await.context("Failed to generate identicon")??;
 
-    Ok(send_image(path, &request).await.context("Failed to read default avatar file")?)
+    Ok(HttpResponse::Ok()
+        .content_type("image/png")
+        .append_header((CACHE_CONTROL, "public, max-age=86400"))
+        .body(png))
 }
 
-#[route("/api/avatar", method = "PUT", err = "text")]
-pub(crate) async fn put_avatar(web_user: WebUser, mut payload: Multipart, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
-    if matches!(web_user, WebUser::Anonymous) {
-        die!(UNAUTHORIZED, "No logged in");
+/// Serves uploaded avatars. The size can be picked using `?s=<pixels>`, which is rounded up to the next stored size
+#[route("/avatars/{user_id}", method = "GET", err = "text")]
+pub(crate) async fn get_uploaded_avatar(avatar_request: web::Path<AvatarRequest>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let avatars_dir: String = from_config!("avatars.dir" => String);
+
+    if !avatar::has_upload(avatars_dir.as_str(), avatar_request.user_id) {
+        die!(NOT_FOUND, "User has not uploaded an avatar");
     }
 
+    let requested_size = request.q_string().get("s").and_then(|size| size.parse::<u32>().ok()).unwrap_or(u32::MAX);
+    let size = avatar::UPLOAD_SIZES.iter()
+        .find(|size| **size >= requested_size)
+        .unwrap_or(&avatar::UPLOAD_SIZES[avatar::UPLOAD_SIZES.len() - 1]);
+
+    let path = avatar::upload_path(avatars_dir.as_str(), avatar_request.user_id, *size);
+
+    send_image(path, &request).await.context("Failed to read uploaded avatar")
+}
+
+#[route("/api/user/avatar", method = "POST", err = "json")]
+pub(crate) async fn post_avatar(web_user: WebUser, mut payload: Multipart, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let user = web_user.into_user()?;
 
     if user.disabled {
         die!(FORBIDDEN, "User is disabled");
     }
 
-    let avatars_dir: String = from_config!("avatars.dir" => String);
+    let (avatars_dir, max_upload_size): (String, i32) = from_config!(
+        "avatars.dir" => String,
+        "avatars.max_upload_size" => i32
+    );
+    let max_upload_size = max_upload_size.max(0) as usize;
 
     let mut field = match payload.try_next().await {
         Ok(Some(field)) => field,
@@ -85,37 +113,58 @@ pub(crate) async fn put_avatar(web_user: WebUser, mut payload: Multipart, db_poo
         Err(err) => return Err(err.into())
     };
 
-    let content_disposition = field.content_disposition();
-    let file_name = content_disposition.get_filename().ok_or_else(|| err!(BAD_REQUEST, "No file name"))?;
-    let extension = file_name.rsplit_once('.')
-        .map(|(_, ext)| ext.to_owned())
-        .ok_or_else(|| err!(BAD_REQUEST, "Invalid file name"))?;
-
     let mut bytes = web::BytesMut::new();
 
     while let Some(chunk) = field.try_next().await.context("Failed to read multipart data chunk")? {
+        if bytes.len() + chunk.len() > max_upload_size {
+            die!(PAYLOAD_TOO_LARGE, "Avatar may only be up to {} bytes large", max_upload_size);
+        }
+
         bytes.extend_from_slice(chunk.as_ref());
     }
 
     let frozen_bytes = bytes.freeze();
+    let user_id = user.id;
 
     web::block(move || -> Result<()> {
-        let format = ImageFormat::from_extension(extension).ok_or_else(|| err!(BAD_REQUEST, "Unsupported image format"))?;
+        // The format is detected using the content as both file name and content type are chosen by the client.
+        // SVGs are never detected and thus rejected, as they may contain scripts
+        let format = image::guess_format(frozen_bytes.as_ref())
+            .ok()
+            .filter(|format| matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP))
+            .ok_or_else(|| err!(UNSUPPORTED_MEDIA_TYPE, "Unsupported image format, only PNG, JPEG and WebP are supported"))?;
+
+        // Only reads the header, so the size of the decoded image is known before allocating it
+        let (width, height) = ImageReader::with_format(Cursor::new(frozen_bytes.as_ref()), format)
+            .into_dimensions()
+            .map_err(|_| err!(BAD_REQUEST, "Image could not be decoded"))?;
+
+        if width > avatar::MAX_UPLOAD_DIMENSION || height > avatar::MAX_UPLOAD_DIMENSION {
+            die!(PAYLOAD_TOO_LARGE, "Avatar may only be up to {0}x{0} pixels large", avatar::MAX_UPLOAD_DIMENSION);
+        }
 
-        let mut cursor = Cursor::new(frozen_bytes.as_ref());
+        let img = image::load_from_memory_with_format(frozen_bytes.as_ref(), format).map_err(|_| err!(BAD_REQUEST, "Image could not be decoded"))?;
 
-        let mut img = image::load(&mut cursor, format)?;
-        img = img.thumbnail_exact(500, 500); // TODO: Check whenever this removes metadata such as location (If not remove metadata)
+        // Re-encoding the image also drops all metadata such as the location the picture was taken at
+        for size in avatar::UPLOAD_SIZES {
+            let path = avatar::upload_path(avatars_dir.as_str(), user_id, size);
 
-        let path_str = format!("{}/{}.jpg", avatars_dir, user.id);
-        let path = Path::new(path_str.as_str());
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
 
-        img.save_with_format(path, ImageFormat::Jpeg)?;
+            let resized = img.resize_to_fill(size, size, FilterType::Lanczos3);
+            DynamicImage::ImageRgb8(resized.to_rgb8()).save_with_format(path, ImageFormat::Jpeg)?;
+        }
 
         Ok(())
-    }).await.context("Failed to save image")?.context("Failed to save image")?;
+    }).await.context("Failed to save image")??;
 
-    Ok(HttpResponse::Created().finish())
+    info!("{} (id {}) uploaded a new avatar", &user.username, &user.id);
+
+    Ok(HttpResponse::Created().json(UploadAvatarJsonResponse {
+        avatar_url: avatar::upload_url(user.id)
+    }))
 }
 
 async fn send_image<P: AsRef<Path>>(path: P, request: &HttpRequest) -> Result<HttpResponse> {
@@ -123,11 +172,22 @@ async fn send_image<P: AsRef<Path>>(path: P, request: &HttpRequest) -> Result<Ht
 
     let mut response = HttpResponse::Ok();
     response.content_type("image/jpeg");
+    response.append_header((CACHE_CONTROL, "public, max-age=3600"));
 
     let meta_data = fs::metadata(path)?;
 
     if let Ok(modified_system_time) = meta_data.modified() {
         let modified_unix_time = modified_system_time.duration_since(SystemTime::UNIX_EPOCH)?;
+
+        // Avatars are replaced as a whole, so modification time and size identify its content well enough
+        let etag = format!("\"{:x}-{:x}\"", modified_unix_time.as_secs(), meta_data.len());
+
+        if request.get_header("if-none-match").map_or(false, |if_none_match| if_none_match.split(',').any(|tag| tag.trim() == etag)) {
+            return Ok(HttpResponse::NotModified().append_header((ETAG, etag)).finish());
+        }
+
+        response.append_header((ETAG, etag));
+
         let naive_date_time = NaiveDateTime::from_timestamp(modified_unix_time.as_secs() as i64, modified_unix_time.subsec_nanos());
 
         // TODO: Convert time zone from local machine to GMT properly
@@ -153,11 +213,7 @@ async fn send_image<P: AsRef<Path>>(path: P, request: &HttpRequest) -> Result<Ht
 }
 
 /// Returns a streaming HttpResponse with the gravatar image
-async fn send_gravatar(email: &str, request: &HttpRequest) -> Result<HttpResponse> {
-    let md5hash = md5::compute(email);
-
-    let url = format!("https://www.gravatar.com/avatar/{:x}?s=500&r=pg&d=identicon", md5hash);
-
+async fn send_gravatar(url: &str, request: &HttpRequest) -> Result<HttpResponse> {
     let mut client = Client::gitarena().get(url);
 
     if let Some(header_value) = request.get_header("if-modified-since") {
@@ -184,3 +240,8 @@ async fn send_gravatar(email: &str, request: &HttpRequest) -> Result<HttpRespons
 pub(crate) struct AvatarRequest {
     user_id: i32
 }
+
+#[derive(Serialize)]
+pub(crate) struct UploadAvatarJsonResponse {
+    avatar_url: String
+}
@@ -1,11 +1,16 @@
 use actix_web::web::ServiceConfig;
 
-mod api;
+pub(crate) mod api;
 mod avatar;
+pub(crate) mod profile;
 mod sso;
-mod user_create;
+mod user_2fa;
+pub(crate) mod user_create;
+mod user_email_change;
 mod user_login;
+pub(crate) mod user_login_revoke;
 mod user_logout;
+mod user_password_reset;
 mod user_verify;
 
 pub(crate) fn init(config: &mut ServiceConfig) {
@@ -13,15 +18,34 @@ pub(crate) fn init(config: &mut ServiceConfig) {
 
     config.service(user_create::get_register);
     config.service(user_create::post_register);
+    config.service(user_create::get_registration_mode);
+
+    config.service(user_email_change::request_email_change);
+    config.service(user_email_change::confirm_email_change);
+    config.service(user_email_change::revoke_email_change);
 
     config.service(user_login::get_login);
     config.service(user_login::post_login);
 
+    config.service(user_login_revoke::revoke);
+
+    config.service(user_2fa::get_2fa);
+    config.service(user_2fa::post_2fa);
+
     config.service(user_logout::logout);
+
+    config.service(user_password_reset::get_password_reset);
+    config.service(user_password_reset::request_password_reset);
+    config.service(user_password_reset::confirm_password_reset);
+
+    config.service(user_verify::resend);
     config.service(user_verify::verify);
 
+    config.service(profile::get_contributions);
+
     config.service(avatar::get_avatar);
-    config.service(avatar::put_avatar);
+    config.service(avatar::get_uploaded_avatar);
+    config.service(avatar::post_avatar);
 
     config.service(sso::initiate_sso);
     config.service(sso::sso_callback);
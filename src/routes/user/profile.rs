@@ -0,0 +1,67 @@
+use crate::contributions;
+use crate::prelude::ContextExtensions;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::{User, WebUser};
+use crate::{err, render_template};
+
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use sqlx::{PgPool, Postgres, Transaction};
+use tera::Context;
+
+/// Profile page listing the repositories of an user and their contribution calendar.
+/// Needs to be registered after every other route consisting of a single segment, as it matches all of them
+#[route("/{username}", method = "GET", err = "html")]
+pub(crate) async fn view_profile(username: web::Path<String>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+    let profile_user = find_profile_user(username.as_str(), &mut transaction).await?;
+
+    let owned: Vec<Repository> = sqlx::query_as::<_, Repository>("select * from repositories where owner = $1 order by lower(name)")
+        .bind(&profile_user.id)
+        .fetch_all(&mut transaction)
+        .await?;
+
+    let mut repositories = Vec::with_capacity(owned.len());
+
+    for repo in owned {
+        if privilege::check_access(&repo, web_user.as_ref(), &mut transaction).await? {
+            repositories.push(repo);
+        }
+    }
+
+    let contributions = contributions::for_viewer(&profile_user, web_user.as_ref(), &mut transaction).await?;
+    let total = contributions.values().sum::<i64>();
+
+    let mut context = Context::new();
+
+    context.insert_web_user(&web_user)?;
+    context.try_insert("profile_user", &profile_user)?;
+    context.try_insert("repositories", &repositories)?;
+    context.try_insert("calendar", &contributions::calendar(&contributions))?;
+    context.try_insert("contributions_total", &total)?;
+
+    render_template!("user/profile.html", context, transaction)
+}
+
+/// Returns the amount of commits per day (`YYYY-MM-DD`) of the trailing year. Contributions to repositories the
+/// current user can't see are not counted
+#[route("/api/user/{username}/contributions", method = "GET", err = "json")]
+pub(crate) async fn get_contributions(username: web::Path<String>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+    let profile_user = find_profile_user(username.as_str(), &mut transaction).await?;
+
+    let contributions = contributions::for_viewer(&profile_user, web_user.as_ref(), &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(contributions))
+}
+
+async fn find_profile_user(username: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<User> {
+    User::find_using_name(username, &mut *transaction)
+        .await
+        .filter(|user| !user.disabled)
+        .ok_or_else(|| err!(NOT_FOUND, "User not found").into())
+}
@@ -1,16 +1,20 @@
+use crate::audit::{self, AuditEvent};
 use crate::mail::Email;
 use crate::prelude::HttpRequestExtensions;
+use crate::registration::RegistrationMode;
 use crate::session::Session;
 use crate::sso::SSO;
 use crate::sso::sso_provider::SSOProvider;
 use crate::sso::sso_provider_type::SSOProviderType;
 use crate::user::{User, WebUser};
-use crate::{die, err};
+use crate::utils::safe_redirect::{encode_redirect, safe_redirect};
+use crate::{crypto, die, err, known_login};
 
 use std::ops::Deref;
 use std::str::FromStr;
 
 use actix_identity::Identity;
+use actix_web::cookie::{Cookie, SameSite};
 use actix_web::http::header::LOCATION;
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::{Context, Result};
@@ -18,10 +22,16 @@ use gitarena_macros::route;
 use log::debug;
 use oauth2::TokenResponse;
 use serde::Deserialize;
+use serde_json::json;
 use sqlx::PgPool;
+use time::Duration as TimeDuration;
+
+const REDIRECT_COOKIE: &str = "gitarena-sso-redirect";
+/// Holds `state` and `nonce` of the authorization request, separated by a dot, until the provider redirects back
+const STATE_COOKIE: &str = "gitarena-sso-state";
 
 #[route("/sso/{service}", method = "GET", err = "html")]
-pub(crate) async fn initiate_sso(sso_request: web::Path<SSORequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+pub(crate) async fn initiate_sso(sso_request: web::Path<SSORequest>, web_user: WebUser, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     if matches!(web_user, WebUser::Authenticated(_)) {
         die!(UNAUTHORIZED, "Already logged in");
     }
@@ -30,10 +40,33 @@ pub(crate) async fn initiate_sso(sso_request: web::Path<SSORequest>, web_user: W
         .map_err(|_| err!(BAD_REQUEST, "Unknown service"))?;
     let provider_impl = provider.get_implementation();
 
-    // TODO: Save token in cache to check for CSRF
-    let (url, _token) = SSOProvider::generate_auth_url(provider_impl.deref(), &provider, &db_pool).await?;
+    let nonce = crypto::random_hex_string(32);
+    let (url, state) = SSOProvider::generate_auth_url(provider_impl.deref(), &provider, nonce.as_str(), &db_pool).await?;
 
-    Ok(HttpResponse::TemporaryRedirect().append_header((LOCATION, url.to_string())).finish())
+    // The provider only knows about our callback url, so the page to return to afterwards is kept in a cookie until then
+    let query_string = request.q_string();
+    let redirect = safe_redirect(query_string.get("redirect"));
+
+    let cookie = Cookie::build(REDIRECT_COOKIE, redirect.to_owned())
+        .path("/sso")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(TimeDuration::minutes(10))
+        .finish();
+
+    // The callback is only accepted in the browser which started the login, so other sites can't log users into their account
+    let state_cookie = Cookie::build(STATE_COOKIE, format!("{}.{}", state.secret(), nonce))
+        .path("/sso")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(TimeDuration::minutes(10))
+        .finish();
+
+    Ok(HttpResponse::TemporaryRedirect()
+        .append_header((LOCATION, url.to_string()))
+        .cookie(cookie)
+        .cookie(state_cookie)
+        .finish())
 }
 
 #[route("/sso/{service}/callback", method = "GET", err = "html")]
@@ -46,8 +79,17 @@ pub(crate) async fn sso_callback(sso_request: web::Path<SSORequest>, id: Identit
         .map_err(|_| err!(BAD_REQUEST, "Unknown service"))?;
     let provider_impl = provider.get_implementation();
 
+    // The cookie is client controlled, so it needs to be validated again
+    let redirect = safe_redirect(request.cookie(REDIRECT_COOKIE).as_ref().map(|cookie| cookie.value())).to_owned();
+    let redirect_cookie = Cookie::build(REDIRECT_COOKIE, "").path("/sso").finish();
+    let state_cookie = Cookie::build(STATE_COOKIE, "").path("/sso").finish();
+
+    let (state, nonce) = request.cookie(STATE_COOKIE)
+        .and_then(|cookie| cookie.value().split_once('.').map(|(state, nonce)| (state.to_owned(), nonce.to_owned())))
+        .ok_or_else(|| err!(BAD_REQUEST, "Sso login has expired, please try again"))?;
+
     let query_string = request.q_string();
-    let token_response = SSOProvider::exchange_response(provider_impl.deref(), &query_string, &provider, &db_pool).await?;
+    let token_response = SSOProvider::exchange_response(provider_impl.deref(), &query_string, state.as_str(), &provider, &db_pool).await?;
 
     if !SSOProvider::validate_scopes(provider_impl.deref(), token_response.scopes()) {
         die!(CONFLICT, "Not all required scopes have been granted");
@@ -58,7 +100,7 @@ pub(crate) async fn sso_callback(sso_request: web::Path<SSORequest>, id: Identit
 
     let mut transaction = db_pool.begin().await?;
 
-    let provider_id = SSOProvider::get_provider_id(provider_impl.deref(), token.as_str()).await?;
+    let provider_id = SSOProvider::get_provider_id(provider_impl.deref(), &token_response, nonce.as_str(), &db_pool).await?;
 
     let sso: Option<SSO> = sqlx::query_as::<_, SSO>("select * from sso where provider = $1 and provider_id = $2 limit 1")
         .bind(&provider)
@@ -66,19 +108,29 @@ pub(crate) async fn sso_callback(sso_request: web::Path<SSORequest>, id: Identit
         .fetch_optional(&mut transaction)
         .await?;
 
-    let user = match sso {
+    let (user, linked) = match sso {
         Some(sso) => {
             // User link already exists -> Login user
-            sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+            let user = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
                 .bind(&sso.user_id)
                 .fetch_one(&mut transaction)
-                .await?
+                .await?;
+
+            (user, false)
         },
         None => {
-            // User link does not exist -> Create new user
-            SSOProvider::create_user(provider_impl.deref(), token.as_str(), &db_pool)
+            // User link does not exist -> Create new user. There is no way to enter an invite code here, so only open instances allow it
+            if RegistrationMode::current(&mut transaction).await? != RegistrationMode::Open {
+                die!(FORBIDDEN, "User registrations are disabled");
+            }
+
+            let mut user = SSOProvider::create_user(provider_impl.deref(), token.as_str(), &db_pool)
                 .await
-                .context("Failed to create new user using sso")?
+                .context("Failed to create new user using sso")?;
+
+            user.promote_if_first(&mut transaction).await?;
+
+            (user, true)
         }
     };
 
@@ -86,24 +138,46 @@ pub(crate) async fn sso_callback(sso_request: web::Path<SSORequest>, id: Identit
         .await?
         .ok_or_else(|| err!(UNAUTHORIZED, "No primary email"))?;
 
-    if user.disabled || !primary_email.is_allowed_login() {
+    if let Some(message) = user.blocked_message() {
+        debug!("Received {} sso login request for disabled or banned user {} (id {})", &provider, &user.username, &user.id);
+        audit::record(&request, AuditEvent::LoginFailure, Some(&user), Some(format!("user:{}", &user.username)), json!({ "method": "sso", "provider": provider.to_string(), "reason": "blocked" })).await;
+
+        die!(FORBIDDEN, "{}", message);
+    }
+
+    if !primary_email.is_allowed_login() {
         debug!("Received {} sso login request for disabled user {} (id {})", &provider, &user.username, &user.id);
 
         die!(FORBIDDEN, "Account has been disabled. Please contact support.");
     }
 
-    // We're now doing something *very* illegal: We're changing state in a GET request
-    // For this reason we need additional protection in the form of CSRF tokens as "Same-Site: Lax" cookies
-    // don't protect in this case against cross-site request forgery.
+    // Logging in changes state in a GET request, which "Same-Site: Lax" cookies don't protect against.
+    // The `state` (and for OpenID Connect the `nonce`) checked above ties the callback to the browser which started the login
 
+    Session::destroy_identity(id.identity(), &mut transaction).await?;
     let session = Session::new(&request, &user, &mut transaction).await?;
     id.remember(session.to_string());
 
-    debug!("{} (id {}) logged in successfully using {} sso", &user.username, &user.id, &provider);
-
     transaction.commit().await?;
 
-    Ok(HttpResponse::Found().append_header((LOCATION, "/")).finish())
+    let target = format!("user:{}", &user.username);
+
+    if linked {
+        audit::record(&request, AuditEvent::SsoLinked, Some(&user), Some(target.as_str()), json!({ "provider": provider.to_string() })).await;
+    }
+
+    if session.pending_2fa {
+        debug!("{} (id {}) authenticated using {} sso, awaiting 2fa code", &user.username, &user.id, &provider);
+
+        let location = format!("/login/2fa?redirect={}", encode_redirect(redirect.as_str()));
+        return Ok(HttpResponse::Found().append_header((LOCATION, location)).del_cookie(&redirect_cookie).del_cookie(&state_cookie).finish());
+    }
+
+    debug!("{} (id {}) logged in successfully using {} sso", &user.username, &user.id, &provider);
+    audit::record(&request, AuditEvent::LoginSuccess, Some(&user), Some(target.as_str()), json!({ "method": "sso", "provider": provider.to_string() })).await;
+    known_login::check(&request, &user).await;
+
+    Ok(HttpResponse::Found().append_header((LOCATION, redirect.as_str())).del_cookie(&redirect_cookie).del_cookie(&state_cookie).finish())
 }
 
 #[derive(Deserialize)]
@@ -0,0 +1,118 @@
+use crate::audit::{self, AuditEvent};
+use crate::csrf;
+use crate::session::Session;
+use crate::user::User;
+use crate::utils::rate_limit::RateLimiter;
+use crate::utils::safe_redirect::safe_redirect;
+use crate::{die, err, known_login, render_template, totp};
+
+use std::time::Duration;
+
+use actix_identity::Identity;
+use actix_web::http::header::LOCATION;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use tera::Context;
+
+#[route("/login/2fa", method = "GET", err = "html")]
+pub(crate) async fn get_2fa(id: Identity, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let session = Session::from_identity(id.identity(), &mut transaction)
+        .await?
+        .ok_or_else(|| err!(UNAUTHORIZED, "Not logged in"))?;
+
+    if !session.pending_2fa {
+        die!(UNAUTHORIZED, "Already logged in");
+    }
+
+    let mut context = Context::new();
+    context.try_insert("csrf_token", &csrf::token(session.to_string().as_str()))?;
+
+    render_template!("user/2fa.html", context, transaction)
+}
+
+/// Failed 2fa codes allowed per user and per session within the window below. A six digit code has a million possible values,
+/// so this keeps the chance of guessing one negligible
+const MAX_FAILED_ATTEMPTS: usize = 5;
+const FAILED_ATTEMPTS_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+static FAILED_ATTEMPTS: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(MAX_FAILED_ATTEMPTS, FAILED_ATTEMPTS_WINDOW));
+
+#[route("/login/2fa", method = "POST", err = "html")]
+pub(crate) async fn post_2fa(body: web::Form<TwoFactorRequest>, id: Identity, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let redirect = safe_redirect(body.redirect.as_deref());
+
+    let mut transaction = db_pool.begin().await?;
+
+    let mut session = Session::from_identity(id.identity(), &mut transaction)
+        .await?
+        .ok_or_else(|| err!(UNAUTHORIZED, "Not logged in"))?;
+
+    if !session.pending_2fa {
+        return Ok(HttpResponse::Found().append_header((LOCATION, redirect)).finish());
+    }
+
+    let user: User = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+        .bind(&session.user_id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if user.totp_secret.is_none() {
+        die!(INTERNAL_SERVER_ERROR, "Session requires 2fa but user has no secret");
+    }
+
+    // Limited per user as well as per session, as an attacker knowing the password can create as many sessions as they like
+    let keys = [format!("user:{}", &user.id), format!("session:{}", &session.hash)];
+
+    if keys.iter().any(|key| FAILED_ATTEMPTS.is_limited(key.as_str())) {
+        warn!("Rejecting 2fa code for {} (id {}) due to too many failed attempts", &user.username, &user.id);
+        die!(TOO_MANY_REQUESTS, "Too many failed attempts. Please try again later.");
+    }
+
+    let valid = totp::check_code(&user, body.code.as_str(), &mut transaction).await?;
+
+    for key in keys.iter() {
+        if valid {
+            FAILED_ATTEMPTS.reset(key.as_str());
+        } else {
+            FAILED_ATTEMPTS.hit(key.as_str());
+        }
+    }
+
+    if !valid {
+        debug!("Received invalid 2fa code for {} (id {})", &user.username, &user.id);
+        audit::record(&request, AuditEvent::LoginFailure, Some(&user), Some(format!("user:{}", &user.username)), json!({ "method": "2fa", "reason": "invalid_code" })).await;
+
+        let mut context = Context::new();
+        context.try_insert("csrf_token", &csrf::token(session.to_string().as_str()))?;
+        context.try_insert("error", &true)?;
+        context.try_insert("code_error", "Invalid code")?;
+
+        return render_template!(StatusCode::UNAUTHORIZED, "user/2fa.html", context, transaction);
+    }
+
+    session.complete_2fa(&mut transaction).await?;
+
+    debug!("{} (id {}) completed 2fa successfully", &user.username, &user.id);
+
+    transaction.commit().await?;
+
+    audit::record(&request, AuditEvent::LoginSuccess, Some(&user), Some(format!("user:{}", &user.username)), json!({ "method": "2fa" })).await;
+    known_login::check(&request, &user).await;
+
+    Ok(HttpResponse::Found().append_header((LOCATION, redirect)).finish())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TwoFactorRequest {
+    code: String,
+    redirect: Option<String>
+}
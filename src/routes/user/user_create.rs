@@ -1,22 +1,27 @@
-use crate::config::{get_optional_setting, get_setting};
+use crate::config::get_setting;
+use crate::mail::Email;
 use crate::prelude::*;
-use crate::session::Session;
+use crate::registration::{InviteCode, RegistrationMode};
+use crate::session::{self, Session};
 use crate::user::{User, WebUser};
-use crate::utils::identifiers::{is_username_taken, validate_username};
+use crate::utils::identifiers::{is_username_taken, is_valid_email, validate_new_username};
+use crate::utils::{is_unique_violation, rate_limit};
 use crate::verification::send_verification_mail;
-use crate::{captcha, crypto, die, render_template};
+use crate::{captcha, crypto, die, password, render_template};
 
 use actix_identity::Identity;
 use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use gitarena_macros::route;
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::PgPool;
 use tera::Context;
+use utoipa::Component;
 
 #[route("/register", method = "GET", err = "html")]
-pub(crate) async fn get_register(web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+pub(crate) async fn get_register(query: web::Query<RegisterQuery>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let mut transaction = db_pool.begin().await?;
 
     if matches!(web_user, WebUser::Authenticated(_)) {
@@ -25,17 +30,39 @@ pub(crate) async fn get_register(web_user: WebUser, db_pool: web::Data<PgPool>)
 
     let mut context = Context::new();
 
-    if !get_setting::<bool, _>("allow_registrations", &mut transaction).await? {
+    let mode = RegistrationMode::current(&mut transaction).await?;
+
+    if mode == RegistrationMode::Closed {
         die!(FORBIDDEN, "User registrations are disabled");
     }
 
-    if let Some(site_key) = get_optional_setting::<String, _>("hcaptcha.site_key", &mut transaction).await? {
+    context.try_insert("registration_mode", &mode)?;
+
+    // Invite links point to `/register?invite=...`, so the code doesn't need to be entered manually
+    if let Some(invite) = &query.invite {
+        context.try_insert("invite_code", invite)?;
+    }
+
+    if captcha::is_enabled(&mut transaction).await? {
+        let site_key = get_setting::<String, _>("hcaptcha.site_key", &mut transaction).await?;
         context.try_insert("hcaptcha_site_key", &site_key)?;
     }
 
     render_template!("user/register.html", context, transaction)
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/user",
+    request_body = RegisterJsonRequest,
+    responses(
+        (status = 200, description = "Account has been created and the session cookie set", body = RegisterJsonResponse),
+        (status = 400, description = "Invalid username, email or password"),
+        (status = 403, description = "Registrations are disabled or the invite code is invalid"),
+        (status = 429, description = "Too many registrations from this network")
+    ),
+    tag = "user"
+)]
 #[route("/api/user", method = "POST", err = "htmx+html")]
 pub(crate) async fn post_register(body: web::Json<RegisterJsonRequest>, id: Identity, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     if id.identity().is_some() {
@@ -43,15 +70,24 @@ pub(crate) async fn post_register(body: web::Json<RegisterJsonRequest>, id: Iden
         die!(UNAUTHORIZED, "Already logged in");
     }
 
+    let (ip_address, _) = session::extract_ip_and_ua(&request);
+
+    if rate_limit::REGISTRATIONS.get().map_or(false, |limiter| limiter.check(ip_address.ip())) {
+        warn!("Rejecting registration from {} due to too many registrations", ip_address.ip());
+        die!(TOO_MANY_REQUESTS, "Too many accounts have been registered from your network. Please try again later.");
+    }
+
     let mut transaction = db_pool.begin().await?;
 
-    if !get_setting::<bool, _>("allow_registrations", &mut transaction).await? {
+    let mode = RegistrationMode::current(&mut transaction).await?;
+
+    if mode == RegistrationMode::Closed {
         die!(FORBIDDEN, "User registrations are disabled");
     }
 
     let username = &body.username;
 
-    validate_username(username.as_str())?;
+    validate_new_username(username.as_str(), &mut transaction).await?;
 
     if is_username_taken(username.as_str(), &mut transaction).await? {
         die!(CONFLICT, "Username already in use");
@@ -59,9 +95,7 @@ pub(crate) async fn post_register(body: web::Json<RegisterJsonRequest>, id: Iden
 
     let email = &body.email;
 
-    // This is not according to the spec of the IETF but trying to implement that is honestly out-of-bounds for this project
-    // Thus a best effort naive implementation. Checks for the presence of "@" and a "." in the domain name (after the last @)
-    if !email.contains('@') || !email.rsplit_once('@').map(|(_, x)| x).unwrap_or_default().contains('.') {
+    if !is_valid_email(email.as_str()) {
         die!(BAD_REQUEST, "Invalid email address");
     }
 
@@ -76,49 +110,65 @@ pub(crate) async fn post_register(body: web::Json<RegisterJsonRequest>, id: Iden
 
     let raw_password = &body.password;
 
-    // We don't implement any strict password rules according to NIST 2017 Guidelines
-    // TODO: Allow configuration of password rules
-    if raw_password.len() < 8 {
-        die!(BAD_REQUEST, "Password must be at least 8 characters");
-    }
+    password::validate_password(raw_password.as_str(), &mut transaction).await?;
+    password::check_breached(raw_password.as_str(), &mut transaction).await?;
 
     let password = crypto::hash_password(raw_password)?;
 
-    if get_optional_setting::<String, _>("hcaptcha.site_key", &mut transaction).await?.is_some() {
-        if let Some(h_captcha_response) = &body.h_captcha_response {
-            let captcha_success = captcha::verify_captcha(h_captcha_response, &mut transaction).await?;
+    // The captcha response is only required if captchas are enabled, API clients don't need to send a dummy value otherwise
+    if captcha::is_enabled(&mut transaction).await? {
+        let h_captcha_response = match &body.h_captcha_response {
+            Some(response) if !response.is_empty() => response,
+            _ => die!(BAD_REQUEST, "hCaptcha response was not provided")
+        };
 
-            if !captcha_success {
-                die!(UNPROCESSABLE_ENTITY, "Captcha verification failed");
-            }
-        } else {
-            die!(BAD_REQUEST, "HCaptcha response was not provided");
+        if !captcha::verify_captcha(h_captcha_response.as_str(), &mut transaction).await? {
+            die!(UNPROCESSABLE_ENTITY, "Captcha verification failed");
         }
     }
 
-    let user: User = sqlx::query_as::<_, User>("insert into users (username, password) values ($1, $2) returning *")
+    // The checks above only give a nice error message in the common case. Concurrent registrations may both pass them,
+    // so the unique indexes are the actual source of truth and violations are reported as conflicts as well
+    let mut user: User = match sqlx::query_as::<_, User>("insert into users (username, password) values ($1, $2) returning *")
         .bind(username)
         .bind(&password)
         .fetch_one(&mut transaction)
-        .await?;
+        .await {
+        Ok(user) => user,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "Username already in use"),
+        Err(err) => return Err(err.into())
+    };
+
+    user.promote_if_first(&mut transaction).await?;
+
+    // Redeemed last so the code is not used up if anything else fails, the transaction gets rolled back in that case anyway
+    if mode == RegistrationMode::InviteOnly {
+        match body.invite_code.as_deref() {
+            Some(code) if !code.trim().is_empty() => InviteCode::redeem(code, &mut transaction).await?,
+            _ => die!(FORBIDDEN, "An invite code is required to register")
+        }
+    }
 
-    sqlx::query("insert into emails (owner, email, \"primary\", commit, notification, public) values ($1, $2, true, true, true, true)")
+    let primary_email: Email = match sqlx::query_as::<_, Email>("insert into emails (owner, email, \"primary\", commit, notification, public) values ($1, $2, true, true, true, true) returning *")
         .bind(&user.id)
         .bind(email)
-        .execute(&mut transaction)
-        .await?;
-
-    // Close the transaction so the email gets committed (above) and then immediatly start a new one for `session` below
-    transaction.commit().await?;
-    let mut transaction = db_pool.begin().await?;
-
-    send_verification_mail(&user, &db_pool).await?;
+        .fetch_one(&mut transaction)
+        .await {
+        Ok(email) => email,
+        Err(err) if is_unique_violation(&err) => die!(CONFLICT, "Email already in use"),
+        Err(err) => return Err(err.into())
+    };
 
+    Session::destroy_identity(id.identity(), &mut transaction).await?;
     let session = Session::new(&request, &user, &mut transaction).await?;
-    id.remember(session.to_string());
+
+    // Only queued here, the mail is sent in the background once the account has been committed
+    send_verification_mail(&user, &primary_email, &mut transaction).await?;
 
     transaction.commit().await?;
 
+    id.remember(session.to_string());
+
     info!("New user registered: {} (id {})", &user.username, &user.id);
 
     Ok(if request.get_header("hx-request").is_some() {
@@ -131,17 +181,39 @@ pub(crate) async fn post_register(body: web::Json<RegisterJsonRequest>, id: Iden
     })
 }
 
-#[derive(Deserialize)]
+/// Returns whenever registrations are `open`, `invite_only` or `closed`, so clients know whenever to offer signing up
+#[route("/api/registration", method = "GET", err = "json")]
+pub(crate) async fn get_registration_mode(db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let mode = RegistrationMode::current(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "mode": mode
+    })))
+}
+
+#[derive(Deserialize, Component)]
 pub(crate) struct RegisterJsonRequest {
     username: String,
     email: String,
     password: String,
+    /// Required if hCaptcha is enabled on this instance
     #[serde(rename = "h-captcha-response")]
-    h_captcha_response: Option<String>
+    h_captcha_response: Option<String>,
+    /// Required if registrations are invite only
+    invite_code: Option<String>
 }
 
-#[derive(Serialize)]
-struct RegisterJsonResponse {
+#[derive(Serialize, Component)]
+pub(crate) struct RegisterJsonResponse {
     success: bool,
     id: i32
 }
+
+#[derive(Deserialize)]
+pub(crate) struct RegisterQuery {
+    invite: Option<String>
+}
@@ -0,0 +1,250 @@
+use crate::config::get_setting;
+use crate::mail::Email;
+use crate::prelude::ContextExtensions;
+use crate::templates::plain;
+use crate::user::{User, WebUser};
+use crate::utils::identifiers::is_valid_email;
+use crate::utils::is_unique_violation;
+use crate::{crypto, die, err, mail, render_template, session, template_context, totp};
+
+use actix_identity::Identity;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use gitarena_macros::route;
+use log::info;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use tera::Context;
+
+/// Length of the token sent to the new address. Only its hash gets stored in the database
+const TOKEN_LENGTH: usize = 64;
+
+/// Starts changing the primary email address of the current user. Nothing changes until the link sent to the new address
+/// has been clicked, while the current address gets notified with a link to cancel it. The user needs to [reauthenticate](session::reauthenticate)
+/// (and enter their two-factor code if enabled), so a hijacked session alone can't take over the account
+#[route("/api/user/email/change", method = "POST", err = "json")]
+pub(crate) async fn request_email_change(body: web::Json<EmailChangeJsonRequest>, web_user: WebUser, id: Identity, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let address = body.email.trim();
+
+    if !is_valid_email(address) {
+        die!(BAD_REQUEST, "Invalid email address");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    session::reauthenticate(&user, body.password.as_str(), id.identity(), &mut transaction).await?;
+
+    if user.totp_secret.is_some() {
+        let code = body.code.as_deref().ok_or_else(|| err!(UNAUTHORIZED, "Two-factor authentication code is required"))?;
+
+        if !totp::check_code(&user, code, &mut transaction).await? {
+            die!(UNAUTHORIZED, "Invalid two-factor authentication code");
+        }
+    }
+
+    let current = Email::find_primary_email(&user, &mut transaction)
+        .await?
+        .ok_or_else(|| err!(UNAUTHORIZED, "No primary email"))?;
+
+    if current.email.eq_ignore_ascii_case(address) {
+        die!(BAD_REQUEST, "This already is your primary email address");
+    }
+
+    let (taken,): (bool,) = sqlx::query_as("select exists(select 1 from emails where lower(email) = lower($1) and owner <> $2 limit 1)")
+        .bind(address)
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if taken {
+        die!(CONFLICT, "Email already in use");
+    }
+
+    let token = crypto::random_hex_string(TOKEN_LENGTH);
+    let revoke_token = crypto::random_hex_string(TOKEN_LENGTH);
+
+    // Only one change may be pending at a time, requesting another one invalidates the previous links
+    sqlx::query("insert into email_changes (user_id, email, hash, revoke_hash, expires) values ($1, $2, $3, $4, now() + interval '1 day') \
+        on conflict (user_id) do update set email = excluded.email, hash = excluded.hash, revoke_hash = excluded.revoke_hash, \
+        expires = excluded.expires, created_at = now()")
+        .bind(&user.id)
+        .bind(address)
+        .bind(crypto::hash_token(token.as_str()))
+        .bind(crypto::hash_token(revoke_token.as_str()))
+        .execute(&mut transaction)
+        .await?;
+
+    let domain = get_setting::<String, _>("domain", &mut transaction).await?;
+
+    let confirmation = template_context!([
+        ("username".to_owned(), user.username.to_owned()),
+        ("email".to_owned(), address.to_owned()),
+        ("link".to_owned(), format!("{}/api/user/email/change/{}", domain, token))
+    ]);
+
+    mail::send_template_to_address(&user, address, plain::EMAIL_CHANGE, &confirmation, &mut transaction).await?;
+
+    let notice = template_context!([
+        ("username".to_owned(), user.username.to_owned()),
+        ("email".to_owned(), address.to_owned()),
+        ("link".to_owned(), format!("{}/api/user/email/change/revoke/{}", domain, revoke_token))
+    ]);
+
+    mail::send_template_to(&user, &current, plain::EMAIL_CHANGE_NOTICE, &notice, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) requested changing their primary email", &user.username, &user.id);
+
+    Ok(HttpResponse::Accepted().json(json!({
+        "success": true
+    })))
+}
+
+/// Confirms a pending change. The new address becomes verified and takes over all roles (primary, commit, notification
+/// and public) of the previous primary address. The previous address stays on the account so old commits remain attributed
+#[route("/api/user/email/change/{token}", method = "GET", err = "html")]
+pub(crate) async fn confirm_email_change(token: web::Path<String>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let token = token.into_inner();
+
+    if token.len() != TOKEN_LENGTH || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        die!(BAD_REQUEST, "Token is illegal");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let mut context = Context::new();
+    context.insert_web_user(&web_user)?;
+
+    let option: Option<(i32, String, DateTime<Local>)> = sqlx::query_as("select user_id, email, expires from email_changes where hash = $1 limit 1 for update")
+        .bind(crypto::hash_token(token.as_str()))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let (user_id, address, expires) = match option {
+        Some(row) => row,
+        None => {
+            context.try_insert("state", "invalid")?;
+            return render_template!(StatusCode::NOT_FOUND, "user/verify.html", context, transaction);
+        }
+    };
+
+    if expires <= Local::now() {
+        context.try_insert("state", "expired")?;
+        return render_template!(StatusCode::GONE, "user/verify.html", context, transaction);
+    }
+
+    let user: User = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+        .bind(&user_id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    let previous = Email::find_primary_email(&user, &mut transaction)
+        .await?
+        .ok_or_else(|| err!(UNAUTHORIZED, "No primary email"))?;
+
+    let existing: Option<Email> = sqlx::query_as::<_, Email>("select * from emails where lower(email) = lower($1) limit 1")
+        .bind(address.as_str())
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let email = match existing {
+        Some(email) if email.owner == user.id => sqlx::query_as::<_, Email>("update emails set verified_at = coalesce(verified_at, now()) where id = $1 returning *")
+            .bind(&email.id)
+            .fetch_one(&mut transaction)
+            .await?,
+        Some(_) => die!(CONFLICT, "Email already in use"),
+        None => match sqlx::query_as::<_, Email>("insert into emails (owner, email, verified_at) values ($1, $2, now()) returning *")
+            .bind(&user.id)
+            .bind(address.as_str())
+            .fetch_one(&mut transaction)
+            .await {
+            Ok(email) => email,
+            Err(err) if is_unique_violation(&err) => die!(CONFLICT, "Email already in use"),
+            Err(err) => return Err(err.into())
+        }
+    };
+
+    // Roles are moved one by one as the previous primary address may have passed some of them on to other addresses already
+    sqlx::query("update emails set \"primary\" = (id = $1), \
+        commit = case when $3 then id = $1 else commit end, \
+        notification = case when $4 then id = $1 else notification end, \
+        public = case when $5 then id = $1 else public end \
+        where owner = $2")
+        .bind(&email.id)
+        .bind(&user.id)
+        .bind(&previous.commit)
+        .bind(&previous.notification)
+        .bind(&previous.public)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query("delete from email_changes where user_id = $1")
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    info!("{} (id {}) changed their primary email from id {} to id {}", &user.username, &user.id, &previous.id, &email.id);
+
+    context.try_insert("state", "verified")?;
+    render_template!("user/verify.html", context, transaction)
+}
+
+/// "This wasn't me" link of the email sent to the current address. Cancels the pending change and, as whoever requested it
+/// knew the password, destroys all sessions of the user
+#[route("/api/user/email/change/revoke/{token}", method = "GET", err = "html")]
+pub(crate) async fn revoke_email_change(token: web::Path<String>, web_user: WebUser, id: Identity, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let token = token.into_inner();
+
+    let mut context = Context::new();
+    context.insert_web_user(&web_user)?;
+
+    let mut transaction = db_pool.begin().await?;
+
+    if token.len() != TOKEN_LENGTH || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        context.try_insert("state", "invalid")?;
+        return render_template!(StatusCode::NOT_FOUND, "user/email_change_revoke.html", context, transaction);
+    }
+
+    let option: Option<(i32,)> = sqlx::query_as("delete from email_changes where revoke_hash = $1 returning user_id")
+        .bind(crypto::hash_token(token.as_str()))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let user_id = match option {
+        Some((user_id,)) => user_id,
+        None => {
+            context.try_insert("state", "invalid")?;
+            return render_template!(StatusCode::NOT_FOUND, "user/email_change_revoke.html", context, transaction);
+        }
+    };
+
+    let sessions = sqlx::query("delete from sessions where user_id = $1")
+        .bind(&user_id)
+        .execute(&mut transaction)
+        .await?;
+
+    if matches!(&web_user, WebUser::Authenticated(user) if user.id == user_id) {
+        id.forget();
+        context.remove("user");
+    }
+
+    info!("User id {} cancelled changing their primary email and revoked {} sessions", user_id, sessions.rows_affected());
+
+    context.try_insert("state", "revoked")?;
+    render_template!("user/email_change_revoke.html", context, transaction)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EmailChangeJsonRequest {
+    email: String,
+    /// Not required for users registered using SSO, who need to have signed in recently instead
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    code: Option<String>
+}
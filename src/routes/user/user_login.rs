@@ -1,8 +1,11 @@
+use crate::audit::{self, AuditEvent};
 use crate::mail::Email;
+use crate::registration::RegistrationMode;
 use crate::render_template;
 use crate::session::Session;
 use crate::user::{User, WebUser};
-use crate::{crypto, die, err};
+use crate::utils::safe_redirect::{encode_redirect, safe_redirect};
+use crate::{crypto, die, err, known_login, metrics};
 
 use actix_identity::Identity;
 use actix_web::http::header::LOCATION;
@@ -11,6 +14,7 @@ use actix_web::{HttpRequest, HttpResponse, Responder, web};
 use anyhow::Result;
 use gitarena_macros::{from_config, route};
 use serde::Deserialize;
+use serde_json::json;
 use sqlx::PgPool;
 use tera::Context;
 use tracing_unwrap::OptionExt;
@@ -22,26 +26,27 @@ pub(crate) async fn get_login(web_user: WebUser, db_pool: web::Data<PgPool>) ->
         die!(UNAUTHORIZED, "Already logged in");
     }
 
-    let (allow_registrations, bitbucket_sso_enabled, github_sso_enabled, gitlab_sso_enabled): (bool, bool, bool, bool) = from_config!(
-        "allow_registrations" => bool,
+    let (bitbucket_sso_enabled, github_sso_enabled, gitlab_sso_enabled, oidc_sso_enabled): (bool, bool, bool, bool) = from_config!(
         "sso.bitbucket.enabled" => bool,
         "sso.github.enabled" => bool,
-        "sso.gitlab.enabled" => bool
+        "sso.gitlab.enabled" => bool,
+        "sso.oidc.enabled" => bool
     );
 
     let mut context = Context::new();
 
-    context.try_insert("allow_registrations", &allow_registrations)?;
+    context.try_insert("registration_mode", &RegistrationMode::current(db_pool.get_ref()).await?)?;
     context.try_insert("sso_bitbucket", &bitbucket_sso_enabled)?;
     context.try_insert("sso_github", &github_sso_enabled)?;
     context.try_insert("sso_gitlab", &gitlab_sso_enabled)?;
+    context.try_insert("sso_oidc", &oidc_sso_enabled)?;
 
     render_template!("user/login.html", context)
 }
 
 #[route("/login", method = "POST", err = "html")]
 pub(crate) async fn post_login(body: web::Form<LoginRequest>, request: HttpRequest, id: Identity, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
-    let redirect = body.redirect.as_deref().unwrap_or("/");
+    let redirect = safe_redirect(body.redirect.as_deref());
 
     // User is already logged in
     if id.identity().is_some() {
@@ -80,6 +85,8 @@ pub(crate) async fn post_login(body: web::Form<LoginRequest>, request: HttpReque
 
     if option.is_none() {
         debug!("Received login request for non-existent user: {}", &username);
+        metrics::record_auth("web", false);
+        audit::record(&request, AuditEvent::LoginFailure, None, Some(format!("user:{}", username)), json!({ "method": "password", "reason": "unknown_user" })).await;
 
         context.try_insert("username_error", "Username does not exist")?;
         return render_template!(StatusCode::UNAUTHORIZED, "user/login.html", context, transaction);
@@ -96,6 +103,8 @@ pub(crate) async fn post_login(body: web::Form<LoginRequest>, request: HttpReque
 
     if !crypto::check_password(&user, password)? {
         debug!("Received login request with wrong password for {} (id {})", &user.username, &user.id);
+        metrics::record_auth("web", false);
+        audit::record(&request, AuditEvent::LoginFailure, Some(&user), Some(format!("user:{}", &user.username)), json!({ "method": "password", "reason": "wrong_password" })).await;
 
         context.try_insert("password_error", "Incorrect password")?;
         return render_template!(StatusCode::UNAUTHORIZED, "user/login.html", context, transaction);
@@ -105,20 +114,38 @@ pub(crate) async fn post_login(body: web::Form<LoginRequest>, request: HttpReque
         .await?
         .ok_or_else(|| err!(UNAUTHORIZED, "No primary email"))?;
 
-    if user.disabled || !primary_email.is_allowed_login() {
-        debug!("Received login request for disabled user {} (id {})", &user.username, &user.id);
+    let blocked = user.blocked_message().or_else(|| (!primary_email.is_allowed_login()).then(|| "Account has been disabled. Please contact support.".to_owned()));
 
-        context.try_insert("general_error", "Account has been disabled. Please contact support.")?;
+    if let Some(message) = blocked {
+        debug!("Received login request for disabled or banned user {} (id {})", &user.username, &user.id);
+        metrics::record_auth("web", false);
+        audit::record(&request, AuditEvent::LoginFailure, Some(&user), Some(format!("user:{}", &user.username)), json!({ "method": "password", "reason": "blocked" })).await;
+
+        context.try_insert("general_error", message.as_str())?;
         return render_template!(StatusCode::UNAUTHORIZED, "user/login.html", context, transaction);
     }
 
+    metrics::record_auth("web", true);
+
+    crypto::upgrade_password_hash(&user, password, &mut transaction).await?;
+
+    Session::destroy_identity(id.identity(), &mut transaction).await?;
     let session = Session::new(&request, &user, &mut transaction).await?;
     id.remember(session.to_string());
 
-    debug!("{} (id {}) logged in successfully", &user.username, &user.id);
-
     transaction.commit().await?;
 
+    if session.pending_2fa {
+        debug!("{} (id {}) entered correct password, awaiting 2fa code", &user.username, &user.id);
+
+        let location = format!("/login/2fa?redirect={}", encode_redirect(redirect));
+        return Ok(HttpResponse::Found().append_header((LOCATION, location)).finish());
+    }
+
+    debug!("{} (id {}) logged in successfully", &user.username, &user.id);
+    audit::record(&request, AuditEvent::LoginSuccess, Some(&user), Some(format!("user:{}", &user.username)), json!({ "method": "password" })).await;
+    known_login::check(&request, &user).await;
+
     Ok(HttpResponse::Found().append_header((LOCATION, redirect)).finish())
 }
 
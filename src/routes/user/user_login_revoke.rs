@@ -0,0 +1,65 @@
+use crate::known_login::TOKEN_LENGTH;
+use crate::prelude::ContextExtensions;
+use crate::user::WebUser;
+use crate::{crypto, render_template};
+
+use actix_identity::Identity;
+use actix_web::http::StatusCode;
+use actix_web::{Responder, web};
+use anyhow::Result;
+use gitarena_macros::route;
+use log::info;
+use serde::Deserialize;
+use sqlx::PgPool;
+use tera::Context;
+
+/// "This wasn't me" link of the new login email. Destroys all sessions of the user, including the one of the suspicious login
+#[route("/login/revoke/{token}", method = "GET", err = "html")]
+pub(crate) async fn revoke(uri: web::Path<RevokeRequest>, web_user: WebUser, id: Identity, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let token = uri.token.as_str();
+
+    let mut context = Context::new();
+    context.insert_web_user(&web_user)?;
+
+    let mut transaction = db_pool.begin().await?;
+
+    if token.len() != TOKEN_LENGTH || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        context.try_insert("state", "invalid")?;
+        return render_template!(StatusCode::NOT_FOUND, "user/login_revoke.html", context, transaction);
+    }
+
+    // Links are single use and only valid for a week, afterwards the sessions page has to be used
+    let option: Option<(i32,)> = sqlx::query_as("update known_logins set revoke_hash = null where revoke_hash = $1 \
+        and first_seen > now() - interval '7 days' returning user_id")
+        .bind(crypto::hash_token(token))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let user_id = match option {
+        Some((user_id,)) => user_id,
+        None => {
+            context.try_insert("state", "invalid")?;
+            return render_template!(StatusCode::NOT_FOUND, "user/login_revoke.html", context, transaction);
+        }
+    };
+
+    let sessions = sqlx::query("delete from sessions where user_id = $1")
+        .bind(&user_id)
+        .execute(&mut transaction)
+        .await?;
+
+    if matches!(&web_user, WebUser::Authenticated(user) if user.id == user_id) {
+        id.forget();
+        context.remove("user");
+    }
+
+    info!("User id {} revoked {} sessions using the link of a new login email", user_id, sessions.rows_affected());
+
+    context.try_insert("state", "revoked")?;
+    render_template!("user/login_revoke.html", context, transaction)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RevokeRequest {
+    token: String
+}
@@ -0,0 +1,173 @@
+use crate::config::get_setting;
+use crate::mail::Email;
+use crate::prelude::{ContextExtensions, HttpRequestExtensions};
+use crate::templates::plain;
+use crate::user::{User, WebUser};
+use crate::{crypto, die, mail, password, render_template, template_context};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local};
+use chrono_humanize::{Accuracy, HumanTime, Tense};
+use gitarena_macros::route;
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgPool;
+use tera::Context;
+
+/// Length of the token sent to the user. Only its hash gets stored in the database
+const TOKEN_LENGTH: usize = 64;
+
+#[route("/login/reset", method = "GET", err = "html")]
+pub(crate) async fn get_password_reset(query: web::Query<PasswordResetQuery>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let transaction = db_pool.begin().await?;
+
+    let mut context = Context::new();
+    context.insert_web_user(&web_user)?;
+
+    if let Some(token) = &query.token {
+        context.try_insert("token", token.as_str())?;
+    }
+
+    render_template!("user/password_reset.html", context, transaction)
+}
+
+/// Sends a password reset link to `email` if it's the primary address of an account.
+/// Always responds with success, so this can't be used to find out whenever an address is registered
+#[route("/api/user/password/reset/request", method = "POST", err = "json")]
+pub(crate) async fn request_password_reset(body: web::Json<PasswordResetRequest>, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let mut transaction = db_pool.begin().await?;
+
+    let email: Option<Email> = sqlx::query_as::<_, Email>("select * from emails where lower(email) = lower($1) and \"primary\" = true limit 1")
+        .bind(body.email.trim())
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let user = match email.as_ref() {
+        Some(email) => sqlx::query_as::<_, User>("select * from users where id = $1 and disabled = false limit 1")
+            .bind(&email.owner)
+            .fetch_optional(&mut transaction)
+            .await?,
+        None => None
+    };
+
+    if let (Some(email), Some(user)) = (email, user) {
+        let (recently_sent,): (bool,) = sqlx::query_as("select exists(select 1 from password_resets where user_id = $1 and created_at > now() - interval '5 minutes')")
+            .bind(&user.id)
+            .fetch_one(&mut transaction)
+            .await?;
+
+        if recently_sent {
+            debug!("Not sending another password reset to {} (id {}) as one has been sent recently", &user.username, &user.id);
+        } else {
+            let expiry = get_setting::<i32, _>("passwords.reset_expiry", &mut transaction).await?.max(60);
+            let token = crypto::random_hex_string(TOKEN_LENGTH);
+
+            sqlx::query("insert into password_resets (user_id, hash, expires) values ($1, $2, now() + make_interval(secs => $3))")
+                .bind(&user.id)
+                .bind(crypto::hash_token(token.as_str()))
+                .bind(expiry as f64)
+                .execute(&mut transaction)
+                .await?;
+
+            let domain = get_setting::<String, _>("domain", &mut transaction).await?;
+            let expires = HumanTime::from(Duration::seconds(expiry as i64)).to_text_en(Accuracy::Precise, Tense::Present);
+
+            let context = template_context!([
+                ("username".to_owned(), user.username.to_owned()),
+                ("link".to_owned(), format!("{}/login/reset?token={}", domain, token)),
+                ("expires".to_owned(), expires)
+            ]);
+
+            mail::send_template_to(&user, &email, plain::PASSWORD_RESET, &context, &mut transaction).await?;
+
+            info!("Password reset requested for {} (id {})", &user.username, &user.id);
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "success": true
+    })))
+}
+
+/// Sets a new password using a token sent by [request_password_reset]. All sessions of the user are destroyed afterwards
+#[route("/api/user/password/reset/confirm", method = "POST", err = "json")]
+pub(crate) async fn confirm_password_reset(body: web::Json<PasswordResetConfirmRequest>, request: HttpRequest, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let token = body.token.as_str();
+
+    if token.len() != TOKEN_LENGTH || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+        die!(BAD_REQUEST, "Invalid or expired password reset link");
+    }
+
+    let mut transaction = db_pool.begin().await?;
+
+    let option: Option<(i32, i32, DateTime<Local>)> = sqlx::query_as("select id, user_id, expires from password_resets where hash = $1 limit 1 for update")
+        .bind(crypto::hash_token(token))
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let (row_id, user_id) = match option {
+        Some((row_id, user_id, expires)) if expires > Local::now() => (row_id, user_id),
+        _ => die!(BAD_REQUEST, "Invalid or expired password reset link")
+    };
+
+    password::validate_password(body.password.as_str(), &mut transaction).await?;
+    password::check_breached(body.password.as_str(), &mut transaction).await?;
+
+    let user: User = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+        .bind(&user_id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if user.disabled {
+        die!(BAD_REQUEST, "Invalid or expired password reset link");
+    }
+
+    sqlx::query("update users set password = $1 where id = $2")
+        .bind(crypto::hash_password(body.password.as_str())?)
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    // The token is single use, other outstanding links become useless as well now that the password changed
+    sqlx::query("delete from password_resets where user_id = $1")
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    let sessions = sqlx::query("delete from sessions where user_id = $1")
+        .bind(&user.id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    info!("{} (id {}) reset their password using reset {}, revoking {} sessions", &user.username, &user.id, row_id, sessions.rows_affected());
+
+    Ok(if request.get_header("hx-request").is_some() {
+        HttpResponse::Ok().append_header(("hx-redirect", "/login")).finish()
+    } else {
+        HttpResponse::Ok().json(json!({
+            "success": true
+        }))
+    })
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PasswordResetQuery {
+    token: Option<String>
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PasswordResetRequest {
+    email: String
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PasswordResetConfirmRequest {
+    token: String,
+    password: String
+}
@@ -1,37 +1,52 @@
-use crate::die;
+use crate::mail::Email;
+use crate::prelude::ContextExtensions;
+use crate::user::WebUser;
+use crate::verification::{send_verification_mail, TOKEN_LENGTH};
+use crate::{crypto, die, err, render_template};
 
-use actix_web::{Responder, web};
+use actix_web::{HttpResponse, Responder, web};
 use anyhow::Result;
+use chrono::{DateTime, Local};
 use gitarena_macros::route;
 use log::info;
 use serde::Deserialize;
 use serde_json::json;
 use sqlx::PgPool;
-use tracing_unwrap::OptionExt;
+use tera::Context;
 
-#[route("/api/verify/{token}", method = "GET", err = "html")]
-pub(crate) async fn verify(verify_request: web::Path<VerifyRequest>, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+#[route("/api/user/verify/{token}", method = "GET", err = "html")]
+pub(crate) async fn verify(verify_request: web::Path<VerifyRequest>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
     let token = &verify_request.token;
 
-    if token.len() != 32 || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+    if token.len() != TOKEN_LENGTH || !token.chars().all(|c| c.is_ascii_hexdigit()) {
         die!(BAD_REQUEST, "Token is illegal");
     }
 
     let mut transaction = db_pool.begin().await?;
 
-    let option: Option<(i32, i32)> = sqlx::query_as("select id, user_id from user_verifications where hash = $1 and expires > now() limit 1")
-        .bind(&token)
+    let option: Option<(i32, i32, i32, DateTime<Local>)> = sqlx::query_as("select id, user_id, email_id, expires from user_verifications where hash = $1 limit 1")
+        .bind(crypto::hash_token(token.as_str()))
         .fetch_optional(&mut transaction)
         .await?;
 
-    if option.is_none() {
-        die!(FORBIDDEN, "Token does not exist or has expired");
-    }
+    let mut context = Context::new();
+    context.insert_web_user(&web_user)?;
+
+    let (row_id, user_id, email_id, expires) = match option {
+        Some(row) => row,
+        None => {
+            context.try_insert("state", "invalid")?;
+            return render_template!(actix_web::http::StatusCode::NOT_FOUND, "user/verify.html", context, transaction);
+        }
+    };
 
-    let (row_id, user_id) = option.unwrap_or_log();
+    if expires <= Local::now() {
+        context.try_insert("state", "expired")?;
+        return render_template!(actix_web::http::StatusCode::GONE, "user/verify.html", context, transaction);
+    }
 
-    sqlx::query("update emails set verified_at = current_timestamp where owner = $1")
-        .bind(&user_id)
+    sqlx::query("update emails set verified_at = current_timestamp where id = $1")
+        .bind(&email_id)
         .execute(&mut transaction)
         .await?;
 
@@ -40,12 +55,47 @@ pub(crate) async fn verify(verify_request: web::Path<VerifyRequest>, db_pool: we
         .execute(&mut transaction)
         .await?;
 
+    info!("User id {} verified their e-mail", user_id);
+
+    context.try_insert("state", "verified")?;
+    render_template!("user/verify.html", context, transaction)
+}
+
+#[route("/api/user/verify/resend", method = "POST", err = "json")]
+pub(crate) async fn resend(query: web::Query<ResendQuery>, web_user: WebUser, db_pool: web::Data<PgPool>) -> Result<impl Responder> {
+    let user = web_user.into_user()?;
+    let mut transaction = db_pool.begin().await?;
+
+    // Defaults to the primary email, other addresses can be specified using `?email=<id>`
+    let email = match query.email {
+        Some(id) => sqlx::query_as::<_, Email>("select * from emails where id = $1 and owner = $2 limit 1")
+            .bind(&id)
+            .bind(&user.id)
+            .fetch_optional(&mut transaction)
+            .await?,
+        None => Email::find_primary_email(&user, &mut transaction).await?
+    }.ok_or_else(|| err!(NOT_FOUND, "Email address not found"))?;
+
+    if email.verified_at.is_some() {
+        die!(BAD_REQUEST, "Email address has already been verified");
+    }
+
+    let (recently_sent,): (bool,) = sqlx::query_as("select exists(select 1 from user_verifications where user_id = $1 and sent_at > now() - interval '5 minutes')")
+        .bind(&user.id)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    if recently_sent {
+        die!(TOO_MANY_REQUESTS, "A verification email has been sent recently, please wait a few minutes before requesting another one");
+    }
+
+    send_verification_mail(&user, &email, &mut transaction).await?;
+
     transaction.commit().await?;
 
-    info!("User id {} verified their e-mail", user_id);
+    info!("Resent verification email to {} (id {})", &user.username, &user.id);
 
-    // TODO: Show html success page instead of json
-    Ok(web::Json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "success": true
     })))
 }
@@ -54,3 +104,8 @@ pub(crate) async fn verify(verify_request: web::Path<VerifyRequest>, db_pool: we
 pub(crate) struct VerifyRequest {
     token: String
 }
+
+#[derive(Deserialize)]
+pub(crate) struct ResendQuery {
+    email: Option<i32>
+}
@@ -1,20 +1,26 @@
 use crate::prelude::HttpRequestExtensions;
 use crate::user::User;
+use crate::{crypto, die};
 
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::net::Ipv6Addr;
+use std::net::{IpAddr, Ipv6Addr};
 use std::str::FromStr;
 
 use actix_web::HttpRequest;
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use chrono::{DateTime, Local};
-use ipnetwork::{IpNetwork, Ipv6Network};
+use ipnetwork::{IpNetwork, IpNetworkError, Ipv6Network};
 use log::warn;
+use once_cell::sync::OnceCell;
 use serde::Serialize;
 use sqlx::{Executor, FromRow, Postgres};
 use tracing_unwrap::ResultExt;
 
+/// Users registered using SSO have no password to confirm sensitive actions with (see [reauthenticate]).
+/// Instead, they need to have signed in through their provider within this many seconds
+const SSO_REAUTHENTICATION_MAX_AGE: i64 = 10 * 60;
+
 #[derive(FromRow, Debug, Serialize)]
 pub(crate) struct Session {
     pub(crate) user_id: i32,
@@ -22,6 +28,7 @@ pub(crate) struct Session {
     pub(crate) hash: String,
     pub(crate) ip_address: IpNetwork,
     pub(crate) user_agent: String, // TODO: Move this to a dedicated table to prevent duplicates
+    pub(crate) pending_2fa: bool,
     created_at: DateTime<Local>,
     pub(crate) updated_at: DateTime<Local>
 }
@@ -39,23 +46,29 @@ impl Session {
         // Limit user agent to 256 characters: https://stackoverflow.com/questions/654921/how-big-can-a-user-agent-string-get/654992#comment106798172_654992
         let user_agent = user_agent.chars().take(256).collect::<String>();
 
-        let repo: Session = sqlx::query_as::<_, Session>("insert into sessions (user_id, ip_address, user_agent) values ($1, $2, $3) returning *")
+        // Users with two-factor authentication enabled need to enter their code before the session becomes valid
+        let pending_2fa = user.totp_secret.is_some();
+
+        let repo: Session = sqlx::query_as::<_, Session>("insert into sessions (user_id, ip_address, user_agent, pending_2fa) values ($1, $2, $3, $4) returning *")
             .bind(&user.id)
             .bind(&ip_address)
             .bind(&user_agent)
+            .bind(&pending_2fa)
             .fetch_one(executor)
             .await?;
 
         Ok(repo)
     }
 
-    /// Finds existing session from Identity (Display of Session)
+    /// Finds existing session from Identity (Display of Session).
+    /// Returns `None` without querying the database if the identity can't be parsed, database errors are passed on
     pub(crate) async fn from_identity<'e, E: Executor<'e, Database = Postgres>>(identity: Option<String>, executor: E) -> Result<Option<Session>> {
-        match identity {
-            Some(identity) => {
-                let (user_id_str, hash) = identity.split_once('$').ok_or_else(|| anyhow!("Unable to parse identity"))?;
-                let user_id = user_id_str.parse::<i32>()?;
+        let parsed = identity.as_deref()
+            .and_then(|identity| identity.split_once('$'))
+            .and_then(|(user_id_str, hash)| user_id_str.parse::<i32>().ok().map(|user_id| (user_id, hash)));
 
+        match parsed {
+            Some((user_id, hash)) => {
                 let option: Option<Session> = sqlx::query_as::<_, Session>("select * from sessions where user_id = $1 and hash = $2 limit 1")
                     .bind(user_id)
                     .bind(hash)
@@ -68,6 +81,26 @@ impl Session {
         }
     }
 
+    /// Destroys the session referenced by `identity`, if there is one.
+    /// Called before logging in, so a session identity planted by an attacker is never carried over (session fixation)
+    pub(crate) async fn destroy_identity<'e, E: Executor<'e, Database = Postgres>>(identity: Option<String>, executor: E) -> Result<()> {
+        let (user_id, hash) = match identity.as_deref().and_then(|identity| identity.split_once('$')) {
+            Some((user_id_str, hash)) => match user_id_str.parse::<i32>() {
+                Ok(user_id) => (user_id, hash),
+                Err(_) => return Ok(())
+            },
+            None => return Ok(())
+        };
+
+        sqlx::query("delete from sessions where user_id = $1 and hash = $2")
+            .bind(&user_id)
+            .bind(hash)
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
     pub(crate) async fn update_explicit<'e, E: Executor<'e, Database = Postgres>>(&self, ip_address: &IpNetwork, user_agent: &str, executor: E) -> Result<()> {
         let now = Local::now();
 
@@ -93,6 +126,37 @@ impl Session {
         self.update_explicit(&ip_address, user_agent, executor).await
     }
 
+    /// Returns true if the session has not been used for longer than `max_age` seconds.
+    /// As `updated_at` gets refreshed while the session is in use (see [needs_refresh](Session::needs_refresh)), active sessions never expire
+    pub(crate) fn is_expired(&self, max_age: i64) -> bool {
+        Local::now().signed_duration_since(self.updated_at).num_seconds() > max_age
+    }
+
+    /// Returns true once half of `max_age` has passed since `updated_at` was last written. Refreshing only then instead of on
+    /// every request keeps active sessions alive without writing to the database for every single page view
+    pub(crate) fn needs_refresh(&self, max_age: i64) -> bool {
+        Local::now().signed_duration_since(self.updated_at).num_seconds() > max_age / 2
+    }
+
+    /// Returns true if the session has been created, meaning the user signed in, within the last `max_age` seconds.
+    /// Unlike [is_expired](Session::is_expired), using the session does not reset this
+    pub(crate) fn is_recent_login(&self, max_age: i64) -> bool {
+        Local::now().signed_duration_since(self.created_at).num_seconds() <= max_age
+    }
+
+    /// Marks the second factor as entered, turning this session into a fully authenticated one
+    pub(crate) async fn complete_2fa<'e, E: Executor<'e, Database = Postgres>>(&mut self, executor: E) -> Result<()> {
+        sqlx::query("update sessions set pending_2fa = false where user_id = $1 and hash = $2")
+            .bind(&self.user_id)
+            .bind(self.hash.as_str())
+            .execute(executor)
+            .await?;
+
+        self.pending_2fa = false;
+
+        Ok(())
+    }
+
     /// Consumes the current session and destroys it
     pub(crate) async fn destroy<'e, E: Executor<'e, Database = Postgres>>(self, executor: E) -> Result<()> {
         sqlx::query("delete from sessions where user_id = $1 and hash = $2")
@@ -105,6 +169,23 @@ impl Session {
     }
 }
 
+/// Confirms the user is actually present before sensitive actions such as changing their email address or deleting their account,
+/// so a hijacked session alone is not enough. Users with a password need to enter it, while users registered using SSO need
+/// the session `identity` refers to be a recent login, which requires signing in through their provider again
+pub(crate) async fn reauthenticate<'e, E: Executor<'e, Database = Postgres>>(user: &User, password: &str, identity: Option<String>, executor: E) -> Result<()> {
+    if user.password == "sso-login" {
+        let session = Session::from_identity(identity, executor).await?;
+
+        if !session.map_or(false, |session| session.user_id == user.id && session.is_recent_login(SSO_REAUTHENTICATION_MAX_AGE)) {
+            die!(UNAUTHORIZED, "Please sign in again using your SSO provider to confirm this action");
+        }
+    } else if !crypto::check_password(user, password)? {
+        die!(UNAUTHORIZED, "Password is incorrect");
+    }
+
+    Ok(())
+}
+
 pub(crate) fn extract_ip_and_ua(request: &HttpRequest) -> (IpNetwork, &str) {
     let ip_address = extract_ip(request);
     let user_agent = request.get_header("user-agent").unwrap_or_default();
@@ -119,21 +200,56 @@ pub(crate) fn extract_ip_and_ua_owned(request: HttpRequest) -> (IpNetwork, Strin
     (ip_address, user_agent.to_owned())
 }
 
+static TRUSTED_PROXIES: OnceCell<Vec<IpNetwork>> = OnceCell::new();
+
+/// Sets the networks of reverse proxies whose forwarded headers are honoured, a comma separated list of addresses or CIDR ranges
+pub(crate) fn init_trusted_proxies(proxies: Option<&str>) {
+    let proxies = proxies.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|proxy| !proxy.is_empty())
+        .filter_map(|proxy| IpNetwork::from_str(proxy).map_err(|err| warn!("Ignoring invalid trusted proxy {}: {}", proxy, err)).ok())
+        .collect();
+
+    let _ = TRUSTED_PROXIES.set(proxies);
+}
+
+fn is_trusted_proxy(ip: IpAddr) -> bool {
+    TRUSTED_PROXIES.get().map_or(false, |proxies| proxies.iter().any(|proxy| proxy.contains(ip)))
+}
+
+/// Returns the address of the client. Forwarded headers can be set by anyone, so they're only honoured if the connection
+/// comes from a trusted proxy. Proxies append to `X-Forwarded-For`, so the right-most address which isn't a trusted proxy
+/// is the client, everything left of it could have been sent by the client itself
 fn extract_ip(request: &HttpRequest) -> IpNetwork {
-    let connection_info = request.connection_info();
-    let ip_str = connection_info.realip_remote_addr().unwrap_or("No user agent sent");
-
-    match IpNetwork::from_str(ip_str) {
-        Ok(ip_network) => ip_network,
-        Err(err) => {
-            // If we got the local address, it includes the port so try again but with port stripped
-            if let Some((ip, _)) = ip_str.split_once(':') {
-                IpNetwork::from_str(ip).unwrap_or_else(|err| default_ip_address(Some(err)))
-            } else {
-                default_ip_address(Some(err))
+    let peer = match request.peer_addr() {
+        Some(address) => address.ip(),
+        None => return default_ip_address::<IpNetworkError>(None)
+    };
+
+    if !is_trusted_proxy(peer) {
+        return IpNetwork::from(peer);
+    }
+
+    let forwarded = request.headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    for address in forwarded.into_iter().rev() {
+        match IpAddr::from_str(address) {
+            Ok(ip) if is_trusted_proxy(ip) => continue,
+            Ok(ip) => return IpNetwork::from(ip),
+            Err(err) => {
+                warn!("Unable to parse forwarded ip address {}: {}", address, err);
+                break;
             }
         }
     }
+
+    IpNetwork::from(peer)
 }
 
 fn default_ip_address<E: Error>(err: Option<E>) -> IpNetwork {
@@ -0,0 +1,102 @@
+//! Graceful shutdown on SIGTERM or Ctrl+C.
+//!
+//! Once a signal is received, the server stops accepting new connections and waits for in-flight pushes to finish,
+//! as cancelling a receive-pack halfway through could leave the repository in an inconsistent state. Afterwards the
+//! remaining requests are given the same deadline to finish. If it passes, the process exits forcefully so a stuck
+//! connection can't block a deploy forever.
+
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use actix_web::dev::ServerHandle;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use tokio::signal;
+use tokio::sync::Notify;
+use tokio::time;
+
+static IN_FLIGHT_PUSHES: AtomicUsize = AtomicUsize::new(0);
+static PUSHES_DRAINED: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Marks a receive-pack as in-flight until dropped
+pub(crate) struct PushGuard(());
+
+impl Drop for PushGuard {
+    fn drop(&mut self) {
+        if IN_FLIGHT_PUSHES.fetch_sub(1, Ordering::SeqCst) == 1 {
+            PUSHES_DRAINED.notify_waiters();
+        }
+    }
+}
+
+/// Tracks a receive-pack operation. Shutdown waits until the returned guard has been dropped
+pub(crate) fn track_push() -> PushGuard {
+    IN_FLIGHT_PUSHES.fetch_add(1, Ordering::SeqCst);
+    PushGuard(())
+}
+
+async fn pushes_drained() {
+    loop {
+        // Created before checking the counter so a notification sent in between isn't missed
+        let notified = PUSHES_DRAINED.notified();
+
+        if IN_FLIGHT_PUSHES.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        notified.await;
+    }
+}
+
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        let mut sigterm = match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(err) => {
+                error!("Failed to listen for SIGTERM, only Ctrl+C will shut down gracefully: {}", err);
+
+                let _ = signal::ctrl_c().await;
+                return "Ctrl+C";
+            }
+        };
+
+        tokio::select! {
+            _ = sigterm.recv() => "SIGTERM",
+            _ = signal::ctrl_c() => "Ctrl+C"
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
+
+/// Waits for a shutdown signal and then stops the server. The server needs to be started with
+/// [disable_signals](actix_web::HttpServer::disable_signals) for this to be the only signal handler
+pub(crate) async fn listen(server: ServerHandle, timeout: Duration) {
+    let signal = shutdown_signal().await;
+
+    info!("Received {}, shutting down gracefully (waiting up to {} seconds)", signal, timeout.as_secs());
+
+    tokio::spawn(async move {
+        time::sleep(timeout).await;
+
+        error!("Graceful shutdown did not finish within {} seconds, exiting forcefully", timeout.as_secs());
+        process::exit(1);
+    });
+
+    server.pause().await;
+
+    let pushes = IN_FLIGHT_PUSHES.load(Ordering::SeqCst);
+
+    if pushes > 0 {
+        info!("Waiting for {} in-flight push(es) to finish", pushes);
+        pushes_drained().await;
+    }
+
+    server.stop(true).await;
+}
@@ -1,8 +1,12 @@
+use crate::{die, err};
+
+use anyhow::Result;
 use chrono::{DateTime, Utc};
 use derive_more::Display;
 use gitarena_common::database::models::KeyType;
+use openssh_keys::PublicKey;
 use serde::Serialize;
-use sqlx::FromRow;
+use sqlx::{Executor, FromRow, Postgres};
 
 #[derive(FromRow, Display, Debug, Serialize)]
 #[display(fmt = "{}", title)]
@@ -12,7 +16,74 @@ pub(crate) struct SshKey {
     pub(crate) title: String,
     pub(crate) fingerprint: String,
     pub(crate) algorithm: KeyType,
+    #[serde(skip_serializing)]
     key: Vec<u8>,
     pub(crate) created_at: DateTime<Utc>,
     pub(crate) expires_at: Option<DateTime<Utc>>
 }
+
+/// SSH key which only grants access to a single repository, commonly used by CI
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", title)]
+pub(crate) struct DeployKey {
+    pub(crate) id: i32,
+    pub(crate) repo: i32,
+    pub(crate) title: String,
+    pub(crate) fingerprint: String,
+    pub(crate) algorithm: KeyType,
+    #[serde(skip_serializing)]
+    key: Vec<u8>,
+    pub(crate) read_only: bool,
+    pub(crate) created_at: DateTime<Utc>
+}
+
+/// Public key submitted by an user, validated to be usable as an user or deploy key
+pub(crate) struct ParsedKey {
+    pub(crate) title: String,
+    pub(crate) fingerprint: String,
+    pub(crate) algorithm: KeyType,
+    pub(crate) key: Vec<u8>
+}
+
+impl ParsedKey {
+    /// Parses a public key in OpenSSH format. If `title` is empty, the comment of the key is used instead
+    pub(crate) fn parse(input: &str, title: &str) -> Result<ParsedKey> {
+        if input.trim().is_empty() {
+            die!(BAD_REQUEST, "Key is not a valid argument");
+        }
+
+        let public_key = PublicKey::parse(input.trim()).map_err(|err| err!(BAD_REQUEST, "Failed to parse SSH public key: {}", err))?;
+        let algorithm = KeyType::try_from(public_key.keytype()).map_err(|_| err!(BAD_REQUEST, "Invalid or unsupported key type, only ed25519, rsa and ecdsa keys are supported"))?;
+
+        let title = if !title.is_empty() {
+            title.to_owned()
+        } else if let Some(comment) = &public_key.comment {
+            comment.clone()
+        } else {
+            die!(BAD_REQUEST, "Key requires a title");
+        };
+
+        if title.len() > 64 {
+            die!(BAD_REQUEST, "Title may only be up to 64 characters long");
+        }
+
+        Ok(ParsedKey {
+            title,
+            // Same format as displayed by `ssh-keygen -l` and the OpenSSH client
+            fingerprint: format!("SHA256:{}", public_key.fingerprint()),
+            algorithm,
+            key: public_key.data()
+        })
+    }
+}
+
+/// Whether the key is already used as an user or deploy key. Keys may only be used once, as the SSH server authenticates
+/// using the first matching entry and therefore couldn't tell them apart
+pub(crate) async fn key_in_use<'e, E: Executor<'e, Database = Postgres>>(fingerprint: &str, executor: E) -> Result<bool> {
+    let (exists,): (bool,) = sqlx::query_as("select exists(select 1 from ssh_keys where fingerprint = $1) or exists(select 1 from deploy_keys where fingerprint = $1)")
+        .bind(fingerprint)
+        .fetch_one(executor)
+        .await?;
+
+    Ok(exists)
+}
@@ -0,0 +1,206 @@
+//! Unix socket `gitarena-ssh` calls into for Git operations over SSH.
+//!
+//! Git itself runs in the SSH process, which installs `pre-receive` and `post-receive` hooks calling back into this module.
+//! Pushes over SSH thus go through the same storage quota, branch protection and post push steps as pushes over smart HTTP.
+//! The socket is only accessible to the user GitArena runs as, the SSH process is expected to run as the same user.
+
+use crate::branch_protection;
+use crate::git::push;
+use crate::git::ref_update::RefUpdate;
+use crate::quota;
+use crate::repository::Repository;
+use crate::shutdown;
+use crate::user::User;
+use crate::utils::oid;
+use crate::webhook::PushedRef;
+
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::{fs, thread};
+
+use actix_web::rt::System;
+use anyhow::{anyhow, Context, Result};
+use fs_extra::dir;
+use gitarena_common::ipc::{hooks_ipc_path, read_packet, write_packet};
+use gitarena_common::packets::hooks::{GitHook, GitHookResult, HookRefUpdate, HookStage};
+use log::{error, info, warn};
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::io::AsyncReadExt;
+use tokio::net::{UnixListener, UnixStream};
+
+/// Listens for hooks on a dedicated thread, as webhooks are delivered using awc which requires an actix runtime
+pub(crate) fn spawn_listener(db_pool: PgPool) {
+    thread::spawn(move || {
+        System::new().block_on(async move {
+            if let Err(err) = listen(db_pool).await {
+                error!("Failed to listen for Git hooks, pushes over SSH will be rejected: {:?}", err);
+            }
+        })
+    });
+}
+
+async fn listen(db_pool: PgPool) -> Result<()> {
+    let path = hooks_ipc_path()?;
+
+    // Left behind if the previous process did not shut down gracefully
+    let _ = fs::remove_file(path);
+
+    let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind {}", path))?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+
+    info!("Listening for Git hooks at {}", path);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let db_pool = db_pool.clone();
+
+        actix_web::rt::spawn(async move {
+            if let Err(err) = handle(stream, &db_pool).await {
+                warn!("Failed to handle Git hook: {:?}", err);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: UnixStream, db_pool: &PgPool) -> Result<()> {
+    let hook: GitHook = read_packet(&mut stream).await?;
+
+    let result = match hook.stage {
+        HookStage::Session { push } => return hold_session(&hook, push, stream, db_pool).await,
+        HookStage::PreReceive => pre_receive(&hook, db_pool).await,
+        HookStage::PostReceive => post_receive(&hook, db_pool).await
+    };
+
+    let result = result.unwrap_or_else(|err| {
+        warn!("Failed to run {:?} hook for repo {}: {:?}", hook.stage, hook.repo, err);
+
+        GitHookResult {
+            accepted: false,
+            messages: vec!["error: Internal server error".to_owned()]
+        }
+    });
+
+    write_packet(&mut stream, result).await
+}
+
+/// Keeps the repository from being moved (and the server from shutting down during a push) until `gitarena-ssh` disconnects
+async fn hold_session(hook: &GitHook, push: bool, mut stream: UnixStream, db_pool: &PgPool) -> Result<()> {
+    let repo = find_repo(hook.repo, db_pool).await?;
+
+    let _push = push.then(shutdown::track_push);
+    let _fs_lock = repo.lock_fs_shared().await;
+
+    write_packet(&mut stream, GitHookResult {
+        accepted: true,
+        messages: Vec::new()
+    }).await?;
+
+    // Nothing else is sent on this connection, so reading only returns once the other side closed it
+    let mut buffer = [0_u8; 64];
+
+    while stream.read(&mut buffer).await? > 0 {}
+
+    Ok(())
+}
+
+/// Checks the updates of a push before Git applies them. Git rejects all updates if a single one is rejected
+async fn pre_receive(hook: &GitHook, db_pool: &PgPool) -> Result<GitHookResult> {
+    let mut transaction = db_pool.begin().await?;
+
+    let repo = find_repo(hook.repo, &mut transaction).await?;
+    let user = find_user(hook.user, &mut transaction).await?;
+    let quarantine = hook.quarantine.as_deref().map(Path::new);
+
+    let incoming = match quarantine {
+        Some(quarantine) if quarantine.is_dir() => dir::get_size(quarantine)?,
+        _ => 0
+    };
+
+    if let Some(reason) = quota::exceeded(&repo, incoming, &mut transaction).await? {
+        return Ok(GitHookResult {
+            accepted: false,
+            messages: vec![format!("error: {}", reason)]
+        });
+    }
+
+    let mut messages = Vec::new();
+
+    for update in hook.updates.iter().map(ref_update) {
+        if let Some(reason) = branch_protection::check_update(&update, &repo, user.as_ref(), quarantine, &mut transaction).await? {
+            messages.push(format!("error: {} {}", update.target_ref, reason));
+        }
+    }
+
+    if messages.is_empty() {
+        // The owner is only locked until this hook returns, so the incoming size is reserved right away.
+        // Concurrent pushes are checked against it and `post-receive` replaces it with the actual size
+        sqlx::query("update repositories set repo_size_bytes = repo_size_bytes + $1 where id = $2")
+            .bind(&(incoming as i64))
+            .bind(&repo.id)
+            .execute(&mut transaction)
+            .await?;
+
+        transaction.commit().await?;
+    }
+
+    Ok(GitHookResult {
+        accepted: messages.is_empty(),
+        messages
+    })
+}
+
+async fn post_receive(hook: &GitHook, db_pool: &PgPool) -> Result<GitHookResult> {
+    let mut transaction = db_pool.begin().await?;
+
+    let mut repo = find_repo(hook.repo, &mut transaction).await?;
+    let user = find_user(hook.user, &mut transaction).await?;
+
+    let (owner_name,): (String,) = sqlx::query_as("select username from users where id = $1 limit 1")
+        .bind(&repo.owner)
+        .fetch_one(&mut transaction)
+        .await?;
+
+    let pushed_refs = hook.updates.iter()
+        .map(|update| PushedRef::new(update.target_ref.as_str(), oid::normalize_str(Some(update.old.as_str())), oid::normalize_str(Some(update.new.as_str()))))
+        .collect();
+
+    push::finish(&mut repo, owner_name.as_str(), user.as_ref(), pushed_refs, db_pool, transaction).await?;
+
+    Ok(GitHookResult {
+        accepted: true,
+        messages: Vec::new()
+    })
+}
+
+fn ref_update(update: &HookRefUpdate) -> RefUpdate {
+    RefUpdate {
+        old: oid::normalize_str(Some(update.old.as_str())).map(str::to_owned),
+        new: oid::normalize_str(Some(update.new.as_str())).map(str::to_owned),
+        target_ref: update.target_ref.clone(),
+        ..Default::default()
+    }
+}
+
+async fn find_repo<'e, E: sqlx::Executor<'e, Database = Postgres>>(id: i32, executor: E) -> Result<Repository> {
+    sqlx::query_as::<_, Repository>("select * from repositories where id = $1 limit 1")
+        .bind(&id)
+        .fetch_optional(executor)
+        .await?
+        .ok_or_else(|| anyhow!("Repository {} does not exist", id))
+}
+
+async fn find_user(id: Option<i32>, transaction: &mut Transaction<'_, Postgres>) -> Result<Option<User>> {
+    let id = match id {
+        Some(id) => id,
+        None => return Ok(None)
+    };
+
+    let user = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
+        .bind(&id)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .ok_or_else(|| anyhow!("User {} does not exist", id))?;
+
+    Ok(Some(user))
+}
@@ -1,16 +1,16 @@
 use crate::prelude::AwcExtensions;
 use crate::sso::oauth_request::{OAuthRequest, SerdeMap};
-use crate::sso::sso_provider::{DatabaseSSOProvider, SSOProvider};
+use crate::sso::sso_provider::{DatabaseSSOProvider, SSOProvider, SSOTokenResponse};
 use crate::sso::sso_provider_type::SSOProviderType;
 use crate::user::User;
-use crate::utils::identifiers::{is_username_taken, validate_username};
+use crate::utils::identifiers::{is_configured_reserved_username, is_username_taken, sanitize_username, validate_username};
 use crate::{config, crypto, err};
 
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use awc::Client;
 use awc::http::header::ACCEPT;
-use oauth2::{AuthUrl, ClientId, ClientSecret, TokenUrl};
+use oauth2::{AuthUrl, ClientId, ClientSecret, TokenResponse, TokenUrl};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -74,7 +74,8 @@ impl SSOProvider for BitBucketSSO {
         ]
     }
 
-    async fn get_provider_id(&self, token: &str) -> Result<String> {
+    async fn get_provider_id(&self, token_response: &SSOTokenResponse, _nonce: &str, _db_pool: &PgPool) -> Result<String> {
+        let token = token_response.access_token().secret();
         let profile_data: SerdeMap = BitBucketSSO::request_data("user", token).await?;
 
         profile_data.get("account_id")
@@ -90,7 +91,7 @@ impl SSOProvider for BitBucketSSO {
 
         let profile_data: SerdeMap = BitBucketSSO::request_data("user", token).await?;
 
-        let mut username = profile_data.get("username")
+        let raw_username = profile_data.get("username")
             .and_then(|v| match v {
                 Value::String(s) => Some(s),
                 _ => None
@@ -98,7 +99,11 @@ impl SSOProvider for BitBucketSSO {
             .cloned()
             .ok_or_else(|| anyhow!("Failed to retrieve username from BitBucket API json response"))?;
 
-        while validate_username(username.as_str()).is_err() || is_username_taken(username.as_str(), &mut transaction).await? {
+        let mut username = sanitize_username(raw_username.as_str()).unwrap_or_default();
+
+        while validate_username(username.as_str()).is_err()
+            || is_configured_reserved_username(username.as_str(), &mut transaction).await?
+            || is_username_taken(username.as_str(), &mut transaction).await? {
             username = crypto::random_numeric_ascii_string(16);
         }
 
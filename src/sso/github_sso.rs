@@ -1,16 +1,16 @@
 use crate::prelude::AwcExtensions;
 use crate::sso::oauth_request::{OAuthRequest, SerdeMap};
-use crate::sso::sso_provider::{DatabaseSSOProvider, SSOProvider};
+use crate::sso::sso_provider::{DatabaseSSOProvider, SSOProvider, SSOTokenResponse};
 use crate::sso::sso_provider_type::SSOProviderType;
 use crate::user::User;
-use crate::utils::identifiers::{is_username_taken, validate_username};
+use crate::utils::identifiers::{is_configured_reserved_username, is_username_taken, sanitize_username, validate_username};
 use crate::{config, crypto, err};
 
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use awc::Client;
 use awc::http::header::{ACCEPT, AUTHORIZATION, USER_AGENT};
-use oauth2::{AuthUrl, ClientId, ClientSecret, Scope, TokenUrl};
+use oauth2::{AuthUrl, ClientId, ClientSecret, Scope, TokenResponse, TokenUrl};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -92,7 +92,8 @@ impl SSOProvider for GitHubSSO {
         granted_scopes.iter().all(|item| requested_scopes.contains(item))
     }
 
-    async fn get_provider_id(&self, token: &str) -> Result<String> {
+    async fn get_provider_id(&self, token_response: &SSOTokenResponse, _nonce: &str, _db_pool: &PgPool) -> Result<String> {
+        let token = token_response.access_token().secret();
         let profile_data: SerdeMap = GitHubSSO::request_data("user", token).await?;
 
         profile_data.get("id")
@@ -108,7 +109,7 @@ impl SSOProvider for GitHubSSO {
 
         let profile_data: SerdeMap = GitHubSSO::request_data("user", token).await?;
 
-        let mut username = profile_data.get("login")
+        let raw_username = profile_data.get("login")
             .and_then(|v| match v {
                 Value::String(s) => Some(s),
                 _ => None
@@ -116,7 +117,11 @@ impl SSOProvider for GitHubSSO {
             .cloned()
             .ok_or_else(|| anyhow!("Failed to retrieve username from GitHub API json response"))?;
 
-        while validate_username(username.as_str()).is_err() || is_username_taken(username.as_str(), &mut transaction).await? {
+        let mut username = sanitize_username(raw_username.as_str()).unwrap_or_default();
+
+        while validate_username(username.as_str()).is_err()
+            || is_configured_reserved_username(username.as_str(), &mut transaction).await?
+            || is_username_taken(username.as_str(), &mut transaction).await? {
             username = crypto::random_numeric_ascii_string(16);
         }
 
@@ -1,9 +1,9 @@
 use crate::prelude::AwcExtensions;
-use crate::sso::oauth_request::{OAuthRequest, SerdeMap};
-use crate::sso::sso_provider::{DatabaseSSOProvider, SSOProvider};
+use crate::sso::oauth_request::SerdeMap;
+use crate::sso::sso_provider::{DatabaseSSOProvider, SSOProvider, SSOTokenResponse};
 use crate::sso::sso_provider_type::SSOProviderType;
 use crate::user::User;
-use crate::utils::identifiers::{is_username_taken, validate_username};
+use crate::utils::identifiers::{is_configured_reserved_username, is_username_taken, sanitize_username, validate_username};
 use crate::{config, crypto, err};
 
 use std::sync::Once;
@@ -12,7 +12,7 @@ use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use awc::Client;
 use awc::http::header::{AUTHORIZATION, USER_AGENT};
-use oauth2::{AuthUrl, ClientId, ClientSecret, TokenUrl};
+use oauth2::{AuthUrl, ClientId, ClientSecret, TokenResponse, TokenUrl};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -21,12 +21,17 @@ use tracing_unwrap::ResultExt;
 
 pub(crate) struct GitLabSSO;
 
-#[async_trait(?Send)]
-impl<T: DeserializeOwned> OAuthRequest<T> for GitLabSSO {
-    async fn request_data(endpoint: &'static str, token: &str) -> Result<T> {
+impl GitLabSSO {
+    async fn get_base_url<'e, E: Executor<'e, Database = Postgres>>(executor: E) -> Result<String> {
+        let base_url = config::get_setting::<String, _>("sso.gitlab.base_url", executor).await?;
+
+        Ok(base_url.trim_end_matches('/').to_owned())
+    }
+
+    async fn request_data<T: DeserializeOwned>(base_url: &str, endpoint: &'static str, token: &str) -> Result<T> {
         let client = Client::gitarena();
 
-        Ok(client.get(format!("https://gitlab.com/api/v4/{}", endpoint).as_str())
+        Ok(client.get(format!("{}/api/v4/{}", base_url, endpoint).as_str())
             .append_header((AUTHORIZATION, format!("Bearer {}", token)))
             .append_header((USER_AGENT, concat!("GitArena ", env!("CARGO_PKG_VERSION"))))
             .send()
@@ -36,6 +41,15 @@ impl<T: DeserializeOwned> OAuthRequest<T> for GitLabSSO {
             .await
             .map_err(|err| err!(BAD_GATEWAY, "Failed to parse GitLab response as JSON: {}", err))?)
     }
+
+    fn extract_id(profile_data: &SerdeMap) -> Result<String> {
+        profile_data.get("id")
+            .and_then(|v| match v {
+                Value::Number(val) => val.as_i64().map_or_else(|| None, |v| Some(v.to_string())),
+                _ => None
+            })
+            .ok_or_else(|| anyhow!("Failed to retrieve id from GitLab API json response"))
+    }
 }
 
 #[async_trait]
@@ -69,29 +83,47 @@ impl SSOProvider for GitLabSSO {
         Some(TokenUrl::new("https://gitlab.com/oauth/token".to_owned()).unwrap_or_log())
     }
 
+    async fn get_endpoints(&self, db_pool: &PgPool) -> Result<(AuthUrl, Option<TokenUrl>)> {
+        let base_url = GitLabSSO::get_base_url(db_pool).await?;
+
+        let auth_url = AuthUrl::new(format!("{}/oauth/authorize", base_url))?;
+        let token_url = TokenUrl::new(format!("{}/oauth/token", base_url))?;
+
+        Ok((auth_url, Some(token_url)))
+    }
+
     fn get_scopes_as_str(&self) -> Vec<&'static str> {
         vec![
             "read_user"
         ]
     }
 
-    async fn get_provider_id(&self, token: &str) -> Result<String> {
-        let profile_data: SerdeMap = GitLabSSO::request_data("user", token).await?;
+    async fn get_provider_id(&self, token_response: &SSOTokenResponse, _nonce: &str, db_pool: &PgPool) -> Result<String> {
+        let token = token_response.access_token().secret();
+        let base_url = GitLabSSO::get_base_url(db_pool).await?;
+        let profile_data: SerdeMap = GitLabSSO::request_data(base_url.as_str(), "user", token).await?;
 
-        profile_data.get("id")
-            .and_then(|v| match v {
-                Value::Number(val) => val.as_i64().map_or_else(|| None, |v| Some(v.to_string())),
-                _ => None
-            })
-            .ok_or_else(|| anyhow!("Failed to retrieve id from GitLab API json response"))
+        GitLabSSO::extract_id(&profile_data)
     }
 
     async fn create_user(&self, token: &str, db_pool: &PgPool) -> Result<User> {
         let mut transaction = db_pool.begin().await?;
 
-        let profile_data: SerdeMap = GitLabSSO::request_data("user", token).await?;
+        let base_url = GitLabSSO::get_base_url(&mut transaction).await?;
+        let profile_data: SerdeMap = GitLabSSO::request_data(base_url.as_str(), "user", token).await?;
+        let gitlab_id = GitLabSSO::extract_id(&profile_data)?;
+
+        let (already_linked,): (bool,) = sqlx::query_as("select exists(select 1 from sso where provider = $1 and provider_id = $2 limit 1)")
+            .bind(&SSOProviderType::GitLab)
+            .bind(gitlab_id.as_str())
+            .fetch_one(&mut transaction)
+            .await?;
 
-        let mut username = profile_data.get("username")
+        if already_linked {
+            bail!("GitLab account is already linked to a different account");
+        }
+
+        let raw_username = profile_data.get("username")
             .and_then(|v| match v {
                 Value::String(s) => Some(s),
                 _ => None
@@ -99,7 +131,11 @@ impl SSOProvider for GitLabSSO {
             .cloned()
             .ok_or_else(|| anyhow!("Failed to retrieve username from GitLab API json response"))?;
 
-        while validate_username(username.as_str()).is_err() || is_username_taken(username.as_str(), &mut transaction).await? {
+        let mut username = sanitize_username(raw_username.as_str()).unwrap_or_default();
+
+        while validate_username(username.as_str()).is_err()
+            || is_configured_reserved_username(username.as_str(), &mut transaction).await?
+            || is_username_taken(username.as_str(), &mut transaction).await? {
             username = crypto::random_numeric_ascii_string(16);
         }
 
@@ -109,13 +145,6 @@ impl SSOProvider for GitLabSSO {
             .fetch_one(&mut transaction)
             .await?;
 
-        let gitlab_id = profile_data.get("id")
-            .and_then(|v| match v {
-                Value::Number(val) => val.as_i64().map_or_else(|| None, |v| Some(v.to_string())),
-                _ => None
-            })
-            .ok_or_else(|| anyhow!("Failed to retrieve id from GitLab API json response"))?;
-
         sqlx::query("insert into sso (user_id, provider, provider_id) values ($1, $2, $3)")
             .bind(&user.id)
             .bind(&SSOProviderType::GitLab)
@@ -125,7 +154,7 @@ impl SSOProvider for GitLabSSO {
 
         // TODO: Save avatar (profile data "avatar_url")
 
-        let emails: Vec<GitLabEmail> = GitLabSSO::request_data("user/emails", token).await?;
+        let emails: Vec<GitLabEmail> = GitLabSSO::request_data(base_url.as_str(), "user/emails", token).await?;
         let once = Once::new();
 
         // For some reason GitLab does not currently always provide the `verified_at` field even for verified email addresses
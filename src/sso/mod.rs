@@ -6,6 +6,7 @@ mod bitbucket_sso;
 mod github_sso;
 mod gitlab_sso;
 mod oauth2_awc_client;
+mod oidc_sso;
 pub(crate) mod oauth_request;
 pub(crate) mod sso_provider;
 pub(crate) mod sso_provider_type;
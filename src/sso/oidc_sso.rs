@@ -0,0 +1,249 @@
+use crate::mail::Email;
+use crate::prelude::AwcExtensions;
+use crate::sso::sso_provider::{DatabaseSSOProvider, SSOProvider, SSOTokenResponse};
+use crate::sso::sso_provider_type::SSOProviderType;
+use crate::user::User;
+use crate::utils::identifiers::{is_configured_reserved_username, is_username_taken, sanitize_username, validate_username};
+use crate::{config, crypto, err, verification};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use awc::Client;
+use awc::http::header::{ACCEPT, AUTHORIZATION};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{DecodingKey, Validation};
+use oauth2::{AuthUrl, ClientId, ClientSecret, TokenResponse, TokenUrl};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sqlx::{Executor, PgPool, Postgres};
+use tracing_unwrap::ResultExt;
+
+/// Generic OpenID Connect provider (Keycloak, Authentik, ...).
+/// All endpoints are discovered using the `.well-known/openid-configuration` document of the configured issuer
+pub(crate) struct OidcSSO;
+
+impl OidcSSO {
+    async fn get_issuer_url<'e, E: Executor<'e, Database = Postgres>>(executor: E) -> Result<String> {
+        let issuer_url = config::get_setting::<String, _>("sso.oidc.issuer_url", executor).await?;
+
+        Ok(issuer_url.trim_end_matches('/').to_owned())
+    }
+
+    async fn request_json<T: DeserializeOwned>(url: &str, token: Option<&str>) -> Result<T> {
+        let client = Client::gitarena();
+        let mut request = client.get(url).append_header((ACCEPT, "application/json"));
+
+        if let Some(token) = token {
+            request = request.append_header((AUTHORIZATION, format!("Bearer {}", token)));
+        }
+
+        Ok(request.send()
+            .await
+            .map_err(|err| err!(BAD_GATEWAY, "Failed to connect to OpenID Connect provider: {}", err))?
+            .json::<T>()
+            .await
+            .map_err(|err| err!(BAD_GATEWAY, "Failed to parse OpenID Connect provider response as JSON: {}", err))?)
+    }
+
+    async fn discover(db_pool: &PgPool) -> Result<DiscoveryDocument> {
+        let issuer_url = OidcSSO::get_issuer_url(db_pool).await?;
+        let document: DiscoveryDocument = OidcSSO::request_json(format!("{}/.well-known/openid-configuration", issuer_url).as_str(), None).await?;
+
+        // https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderConfigurationValidation
+        if document.issuer.trim_end_matches('/') != issuer_url {
+            bail!("OpenID Connect discovery document issuer does not match configured issuer url");
+        }
+
+        Ok(document)
+    }
+
+    /// Verifies the signature of `id_token` against the JWKS of the issuer as well as its `iss`, `aud` and `nonce` claims
+    async fn verify_id_token(id_token: &str, nonce: &str, document: &DiscoveryDocument, db_pool: &PgPool) -> Result<IdTokenClaims> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(|err| err!(UNAUTHORIZED, "Malformed id token: {}", err))?;
+        let kid = header.kid.ok_or_else(|| err!(UNAUTHORIZED, "Id token does not specify a key id"))?;
+
+        let jwks: JwkSet = OidcSSO::request_json(document.jwks_uri.as_str(), None).await?;
+        let jwk = jwks.find(kid.as_str()).ok_or_else(|| err!(UNAUTHORIZED, "Id token was signed with an unknown key"))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|err| err!(UNAUTHORIZED, "Unsupported JWK: {}", err))?;
+
+        let client_id = DatabaseSSOProvider::get_client_id(&OidcSSO, db_pool).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[client_id.as_str()]);
+        validation.set_issuer(&[document.issuer.as_str()]);
+
+        let data = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|err| err!(UNAUTHORIZED, "Failed to verify id token: {}", err))?;
+
+        // Binds the id token to the login flow started in this browser, so it can't be replayed
+        if data.claims.nonce.as_deref() != Some(nonce) {
+            bail!("OpenID Connect id token nonce does not match");
+        }
+
+        Ok(data.claims)
+    }
+}
+
+#[async_trait]
+impl DatabaseSSOProvider for OidcSSO {
+    async fn get_client_id<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<ClientId> {
+        let client_id = config::get_setting::<String, _>("sso.oidc.client_id", executor).await?;
+
+        Ok(ClientId::new(client_id))
+    }
+
+    async fn get_client_secret<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<Option<ClientSecret>> {
+        let client_secret = config::get_setting::<String, _>("sso.oidc.client_secret", executor).await?;
+
+        Ok(Some(ClientSecret::new(client_secret)))
+    }
+}
+
+#[async_trait(?Send)]
+impl SSOProvider for OidcSSO {
+    fn get_name(&self) -> &'static str {
+        "oidc"
+    }
+
+    fn get_auth_url(&self) -> AuthUrl {
+        // Never used as the endpoints are discovered in `get_endpoints`
+        // unwrap_or_log() is safe as we can guarantee that this is a valid url
+        AuthUrl::new("https://localhost/authorize".to_owned()).unwrap_or_log()
+    }
+
+    fn get_token_url(&self) -> Option<TokenUrl> {
+        None
+    }
+
+    async fn get_endpoints(&self, db_pool: &PgPool) -> Result<(AuthUrl, Option<TokenUrl>)> {
+        let document = OidcSSO::discover(db_pool).await?;
+
+        let auth_url = AuthUrl::new(document.authorization_endpoint)?;
+        let token_url = TokenUrl::new(document.token_endpoint)?;
+
+        Ok((auth_url, Some(token_url)))
+    }
+
+    fn uses_nonce(&self) -> bool {
+        true
+    }
+
+    fn get_scopes_as_str(&self) -> Vec<&'static str> {
+        vec![
+            "openid",
+            "profile",
+            "email"
+        ]
+    }
+
+    async fn get_provider_id(&self, token_response: &SSOTokenResponse, nonce: &str, db_pool: &PgPool) -> Result<String> {
+        let id_token = token_response.extra_fields()
+            .id_token
+            .as_deref()
+            .ok_or_else(|| err!(UNAUTHORIZED, "OpenID Connect provider did not return an id token"))?;
+
+        let document = OidcSSO::discover(db_pool).await?;
+        let claims = OidcSSO::verify_id_token(id_token, nonce, &document, db_pool).await?;
+
+        // The userinfo endpoint is required to return the same subject as the id token
+        let token = token_response.access_token().secret();
+        let user_info: UserInfo = OidcSSO::request_json(document.userinfo_endpoint.as_str(), Some(token)).await?;
+
+        if user_info.sub != claims.sub {
+            bail!("OpenID Connect userinfo subject does not match id token subject");
+        }
+
+        Ok(claims.sub)
+    }
+
+    async fn create_user(&self, token: &str, db_pool: &PgPool) -> Result<User> {
+        let document = OidcSSO::discover(db_pool).await?;
+        let user_info: UserInfo = OidcSSO::request_json(document.userinfo_endpoint.as_str(), Some(token)).await?;
+
+        let mut transaction = db_pool.begin().await?;
+
+        let raw_username = user_info.preferred_username
+            .clone()
+            .ok_or_else(|| anyhow!("Failed to retrieve username from OpenID Connect userinfo response"))?;
+
+        let mut username = sanitize_username(raw_username.as_str()).unwrap_or_default();
+
+        while validate_username(username.as_str()).is_err()
+            || is_configured_reserved_username(username.as_str(), &mut transaction).await?
+            || is_username_taken(username.as_str(), &mut transaction).await? {
+            username = crypto::random_numeric_ascii_string(16);
+        }
+
+        let user: User = sqlx::query_as::<_, User>("insert into users (username, password) values ($1, $2) returning *")
+            .bind(username.as_str())
+            .bind("sso-login")
+            .fetch_one(&mut transaction)
+            .await?;
+
+        sqlx::query("insert into sso (user_id, provider, provider_id) values ($1, $2, $3)")
+            .bind(&user.id)
+            .bind(&SSOProviderType::Oidc)
+            .bind(user_info.sub.as_str())
+            .execute(&mut transaction)
+            .await?;
+
+        let email = user_info.email
+            .as_deref()
+            .ok_or_else(|| anyhow!("Failed to retrieve email from OpenID Connect userinfo response"))?;
+
+        let (email_exists,): (bool,) = sqlx::query_as("select exists(select 1 from emails where lower(email) = lower($1) limit 1)")
+            .bind(email)
+            .fetch_one(&mut transaction)
+            .await?;
+
+        if email_exists {
+            bail!("Primary email is already assigned to a different account");
+        }
+
+        // Only trust the verification status if the provider explicitly tells us so
+        if user_info.email_verified.unwrap_or(false) {
+            sqlx::query("insert into emails (owner, email, \"primary\", commit, notification, public, verified_at) values ($1, $2, true, true, true, false, current_timestamp)")
+                .bind(&user.id)
+                .bind(email)
+                .execute(&mut transaction)
+                .await?;
+        } else {
+            let email = sqlx::query_as::<_, Email>("insert into emails (owner, email, \"primary\", commit, notification, public) values ($1, $2, true, true, true, false) returning *")
+                .bind(&user.id)
+                .bind(email)
+                .fetch_one(&mut transaction)
+                .await?;
+
+            verification::send_verification_mail(&user, &email, &mut transaction).await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(user)
+    }
+}
+
+// https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata
+#[derive(Deserialize, Debug)]
+struct DiscoveryDocument {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    jwks_uri: String
+}
+
+#[derive(Deserialize, Debug)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>
+}
+
+// https://openid.net/specs/openid-connect-core-1_0.html#StandardClaims
+#[derive(Deserialize, Debug)]
+struct UserInfo {
+    sub: String,
+    preferred_username: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>
+}
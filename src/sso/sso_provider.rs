@@ -1,19 +1,21 @@
-use crate::config;
 use crate::sso::bitbucket_sso::BitBucketSSO;
 use crate::sso::github_sso::GitHubSSO;
 use crate::sso::gitlab_sso::GitLabSSO;
 use crate::sso::oauth2_awc_client::async_http_client;
+use crate::sso::oidc_sso::OidcSSO;
 use crate::sso::sso_provider_type::SSOProviderType;
 use crate::user::User;
+use crate::{config, die};
 
 use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
-use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::basic::{BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse, BasicTokenType};
 use oauth2::url::Url;
-use oauth2::{AuthorizationCode, AuthUrl, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope, TokenUrl};
+use oauth2::{AuthorizationCode, AuthUrl, Client, ClientId, ClientSecret, CsrfToken, ExtraTokenFields, RedirectUrl, Scope, StandardRevocableToken, StandardTokenResponse, TokenUrl};
 use qstring::QString;
+use ring::constant_time;
+use serde::{Deserialize, Serialize};
 use sqlx::{Executor, PgPool, Postgres};
-use tracing_unwrap::OptionExt;
 
 #[async_trait(?Send)]
 pub(crate) trait SSOProvider {
@@ -22,7 +24,13 @@ pub(crate) trait SSOProvider {
     fn get_auth_url(&self) -> AuthUrl;
     fn get_token_url(&self) -> Option<TokenUrl>;
 
-    async fn build_client(&self, provider: &SSOProviderType, db_pool: &PgPool) -> Result<BasicClient> {
+    /// Returns the auth and token url used for building the oauth client.
+    /// Providers with configurable endpoints (such as self-hosted instances) override this.
+    async fn get_endpoints(&self, _db_pool: &PgPool) -> Result<(AuthUrl, Option<TokenUrl>)> {
+        Ok((self.get_auth_url(), self.get_token_url()))
+    }
+
+    async fn build_client(&self, provider: &SSOProviderType, db_pool: &PgPool) -> Result<SSOClient> {
         let mut transaction = db_pool.begin().await?;
 
         let (client_id, client_secret) = match provider {
@@ -37,21 +45,25 @@ pub(crate) trait SSOProvider {
             SSOProviderType::GitLab => (
                 DatabaseSSOProvider::get_client_id(&GitLabSSO, &mut transaction).await.context("Failed to get client id")?,
                 DatabaseSSOProvider::get_client_secret(&GitLabSSO, &mut transaction).await.context("Failed to get client secret")?
+            ),
+            SSOProviderType::Oidc => (
+                DatabaseSSOProvider::get_client_id(&OidcSSO, &mut transaction).await.context("Failed to get client id")?,
+                DatabaseSSOProvider::get_client_secret(&OidcSSO, &mut transaction).await.context("Failed to get client secret")?
             )
         };
 
-        let auth_url = self.get_auth_url();
-        let token_url = self.get_token_url();
+        let (auth_url, token_url) = self.get_endpoints(db_pool).await?;
 
         let redirect_url = match provider {
             SSOProviderType::BitBucket => DatabaseSSOProvider::get_redirect_url(&BitBucketSSO, &mut transaction).await.context("Failed to get redirect url")?,
             SSOProviderType::GitHub => DatabaseSSOProvider::get_redirect_url(&GitHubSSO, &mut transaction).await.context("Failed to get redirect url")?,
             SSOProviderType::GitLab => DatabaseSSOProvider::get_redirect_url(&GitLabSSO, &mut transaction).await.context("Failed to get redirect url")?,
+            SSOProviderType::Oidc => DatabaseSSOProvider::get_redirect_url(&OidcSSO, &mut transaction).await.context("Failed to get redirect url")?,
         };
 
         transaction.commit().await?;
 
-        Ok(BasicClient::new(client_id, client_secret, auth_url, token_url).set_redirect_uri(redirect_url))
+        Ok(SSOClient::new(client_id, client_secret, auth_url, token_url).set_redirect_uri(redirect_url))
     }
 
     fn get_scopes_as_str(&self) -> Vec<&'static str>;
@@ -63,7 +75,13 @@ pub(crate) trait SSOProvider {
             .collect()
     }
 
-    async fn generate_auth_url(&self, provider: &SSOProviderType, db_pool: &PgPool) -> Result<(Url, CsrfToken)> {
+    /// Whether the authorization request carries a `nonce` which the provider includes in the id token it issues
+    fn uses_nonce(&self) -> bool {
+        false
+    }
+
+    /// Returns the url to send the user to as well as the `state` of the request, which needs to be remembered until the callback
+    async fn generate_auth_url(&self, provider: &SSOProviderType, nonce: &str, db_pool: &PgPool) -> Result<(Url, CsrfToken)> {
         let client = self.build_client(provider, db_pool).await?;
         let mut request = client.authorize_url(CsrfToken::new_random);
 
@@ -71,24 +89,26 @@ pub(crate) trait SSOProvider {
             request = request.add_scope(scope);
         }
 
+        if self.uses_nonce() {
+            request = request.add_extra_param("nonce", nonce.to_owned());
+        }
+
         Ok(request.url())
     }
 
-    /// Exchanges a response (provide by `state` and `code` in `query_string`) into an oauth access token
-    async fn exchange_response(&self, query_string: &QString, provider: &SSOProviderType, db_pool: &PgPool) -> Result<BasicTokenResponse> {
-        let code_option = query_string.get("code");
-        let state_option = query_string.get("state");
+    /// Exchanges a response (provide by `state` and `code` in `query_string`) into an oauth access token.
+    /// `expected_state` is the state returned by [generate_auth_url](SSOProvider::generate_auth_url) for this user
+    async fn exchange_response(&self, query_string: &QString, expected_state: &str, provider: &SSOProviderType, db_pool: &PgPool) -> Result<SSOTokenResponse> {
+        let (code_str, state_str) = match (query_string.get("code"), query_string.get("state")) {
+            (Some(code), Some(state)) => (code, state),
+            _ => bail!("Received {} sso callback request without `code` and/or `state` in query string", self.get_name())
+        };
 
-        if state_option.is_none() || state_option.is_none() {
-            bail!("Received GitHub sso callback request without `code` and/or `state` in query string");
+        if constant_time::verify_slices_are_equal(state_str.as_bytes(), expected_state.as_bytes()).is_err() {
+            die!(BAD_REQUEST, "State of the sso callback does not match, please try again");
         }
 
-        let code_str = code_option.unwrap_or_log();
         let code = AuthorizationCode::new(code_str.to_owned());
-
-        let state_str = state_option.unwrap_or_log();
-        let _state = CsrfToken::new(state_str.to_owned()); // TODO: Verify CSRF token
-
         let client = self.build_client(provider, db_pool).await?;
 
         Ok(client.exchange_code(code)
@@ -108,7 +128,9 @@ pub(crate) trait SSOProvider {
         granted_scopes.iter().all(|item| requested_scopes.contains(item))
     }
 
-    async fn get_provider_id(&self, token: &str) -> Result<String>;
+    /// Returns the user id on the provider end.
+    /// `token_response` is passed in full so providers which issue an `id_token` can verify it (and its `nonce`) before trusting any claims
+    async fn get_provider_id(&self, token_response: &SSOTokenResponse, nonce: &str, db_pool: &PgPool) -> Result<String>;
 
     async fn create_user(&self, token: &str, db_pool: &PgPool) -> Result<User>;
 }
@@ -125,3 +147,15 @@ pub(crate) trait DatabaseSSOProvider: SSOProvider {
     async fn get_client_id<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<ClientId>;
     async fn get_client_secret<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<Option<ClientSecret>>;
 }
+
+/// Additional token response fields sent by OpenID Connect providers. Plain OAuth2 providers leave these empty
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct IdTokenFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) id_token: Option<String>
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+pub(crate) type SSOTokenResponse = StandardTokenResponse<IdTokenFields, BasicTokenType>;
+pub(crate) type SSOClient = Client<BasicErrorResponse, SSOTokenResponse, BasicTokenType, BasicTokenIntrospectionResponse, StandardRevocableToken, BasicRevocationErrorResponse>;
@@ -1,6 +1,7 @@
 use crate::sso::bitbucket_sso::BitBucketSSO;
 use crate::sso::github_sso::GitHubSSO;
 use crate::sso::gitlab_sso::GitLabSSO;
+use crate::sso::oidc_sso::OidcSSO;
 use crate::sso::sso_provider::SSOProvider;
 
 use std::result::Result as StdResult;
@@ -16,7 +17,8 @@ use sqlx::Type;
 pub(crate) enum SSOProviderType {
     BitBucket,
     GitHub,
-    GitLab
+    GitLab,
+    Oidc
 }
 
 impl SSOProviderType {
@@ -24,7 +26,8 @@ impl SSOProviderType {
         match self {
             SSOProviderType::BitBucket => Box::new(BitBucketSSO),
             SSOProviderType::GitHub => Box::new(GitHubSSO),
-            SSOProviderType::GitLab => Box::new(GitLabSSO)
+            SSOProviderType::GitLab => Box::new(GitLabSSO),
+            SSOProviderType::Oidc => Box::new(OidcSSO)
         }
     }
 }
@@ -39,6 +42,7 @@ impl FromStr for SSOProviderType {
             "bitbucket" => Ok(SSOProviderType::BitBucket),
             "github" => Ok(SSOProviderType::GitHub),
             "gitlab" => Ok(SSOProviderType::GitLab),
+            "oidc" => Ok(SSOProviderType::Oidc),
             _ => Err(())
         }
     }
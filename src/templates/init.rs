@@ -0,0 +1,70 @@
+//! Files new repositories can be initialized with when they're created through the web interface.
+//!
+//! `.gitignore` templates are located at `templates/init/gitignore/<name>.gitignore` and license texts at
+//! `templates/init/license/<SPDX identifier>.txt`. License texts may contain the `{{year}}` and `{{fullname}}` placeholders.
+//! New templates can be added by placing files into these directories, they're picked up without a restart.
+
+use crate::utils::identifiers::is_path_component;
+
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{Datelike, Utc};
+use tokio::fs;
+
+const GITIGNORE_DIRECTORY: &str = "templates/init/gitignore";
+const LICENSE_DIRECTORY: &str = "templates/init/license";
+
+/// Returns the names of all available `.gitignore` templates in alphabetical order
+pub(crate) async fn gitignore_names() -> Result<Vec<String>> {
+    list(GITIGNORE_DIRECTORY, ".gitignore").await
+}
+
+/// Returns the SPDX identifiers of all available licenses in alphabetical order
+pub(crate) async fn license_names() -> Result<Vec<String>> {
+    list(LICENSE_DIRECTORY, ".txt").await
+}
+
+/// Returns the `.gitignore` template called `name` or `None` if it does not exist
+pub(crate) async fn gitignore(name: &str) -> Result<Option<String>> {
+    read(GITIGNORE_DIRECTORY, name, ".gitignore").await
+}
+
+/// Returns the text of the license `spdx_id` with its placeholders filled in or `None` if it does not exist
+pub(crate) async fn license(spdx_id: &str, fullname: &str) -> Result<Option<String>> {
+    Ok(read(LICENSE_DIRECTORY, spdx_id, ".txt").await?.map(|text| {
+        text.replace("{{year}}", Utc::now().year().to_string().as_str())
+            .replace("{{fullname}}", fullname)
+    }))
+}
+
+async fn list(directory: &str, extension: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut entries = fs::read_dir(directory).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str().and_then(|file_name| file_name.strip_suffix(extension)) {
+            names.push(name.to_owned());
+        }
+    }
+
+    names.sort_unstable_by_key(|name| name.to_lowercase());
+
+    Ok(names)
+}
+
+async fn read(directory: &str, name: &str, extension: &str) -> Result<Option<String>> {
+    // The name comes from the client, so it must not be able to escape the template directory
+    if !is_path_component(name) {
+        return Ok(None);
+    }
+
+    let path = Path::new(directory).join(format!("{}{}", name, extension));
+
+    match fs::read_to_string(path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into())
+    }
+}
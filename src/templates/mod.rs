@@ -1,4 +1,3 @@
-use crate::templates::plain::Template;
 use crate::utils::time_function;
 
 use anyhow::Result;
@@ -10,6 +9,7 @@ use tracing_unwrap::{OptionExt, ResultExt};
 mod filters;
 mod tests;
 
+pub(crate) mod init;
 pub(crate) mod plain;
 pub(crate) mod web;
 
@@ -25,15 +25,14 @@ type GlobalTera = Tera;
 #[cfg(not(debug_assertions))]
 type TemplateInitResult = ();
 
-pub(crate) static VERIFY_EMAIL: OnceCell<Template> = OnceCell::new();
 static TERA: OnceCell<GlobalTera> = OnceCell::new();
 
-pub(crate) async fn init() -> Result<TemplateInitResult> {
+pub(crate) async fn init(email_override_dir: Option<&str>) -> Result<TemplateInitResult> {
     info!("Loading templates. This may take a few seconds.");
 
-    let elapsed = time_function(|| async {
-        VERIFY_EMAIL.set(parse_template("email/user/verify_email.txt".to_owned())).expect_or_log("Verify email template should only be initialized once");
+    plain::init(email_override_dir)?;
 
+    let elapsed = time_function(|| async {
         // This additionally checks the templates for errors
         TERA.set(init_tera()).expect_or_log("Tera should only be initialized once");
     }).await;
@@ -100,13 +99,6 @@ pub(crate) async fn init() -> Result<TemplateInitResult> {
     Ok(())
 }
 
-fn parse_template(template_path: String) -> Template {
-    match plain::parse(template_path) {
-        Ok(template) => template,
-        Err(err) => panic!("Failed to parse template: {}", err)
-    }
-}
-
 pub(crate) async fn render(template: &str, context: &Context) -> Result<String> {
     #[cfg(debug_assertions)]
     return Ok(tera().read().await.render(template, context)?);
@@ -142,7 +134,7 @@ pub(crate) fn tera() -> &'static GlobalTera {
 #[macro_export]
 macro_rules! template_context {
     ($input:expr) => {
-        Some($input.iter().cloned().collect())
+        $input.iter().cloned().collect::<$crate::templates::plain::TemplateContext>()
     }
 }
 
@@ -159,17 +151,21 @@ macro_rules! render_template {
         render_template!(actix_web::http::StatusCode::OK, $template_name, $context, $transaction)
     }};
     ($status:expr, $template_name:literal, $context:expr) => {{
+        $context.try_insert("csp_nonce", &$crate::utils::security_headers::nonce())?;
+
         if cfg!(debug_assertions) {
             $context.try_insert("debug", &true)?;
         }
 
         let template = $crate::templates::render($template_name, &$context).await?;
-        Ok(actix_web::HttpResponseBuilder::new($status).body(template))
+        Ok(actix_web::HttpResponseBuilder::new($status).content_type("text/html; charset=utf-8").body(template))
     }};
     ($status:expr, $template_name:literal, $context:expr, $transaction:expr) => {{
         let domain = $crate::config::get_optional_setting::<String, _>("domain", &mut $transaction).await?.unwrap_or_default();
         $context.try_insert("domain", &domain)?;
 
+        $context.try_insert("csp_nonce", &$crate::utils::security_headers::nonce())?;
+
         if cfg!(debug_assertions) {
             $context.try_insert("debug", &true)?;
         }
@@ -178,6 +174,6 @@ macro_rules! render_template {
 
         $transaction.commit().await?;
 
-        Ok(actix_web::HttpResponseBuilder::new($status).body(template))
+        Ok(actix_web::HttpResponseBuilder::new($status).content_type("text/html; charset=utf-8").body(template))
     }};
 }
@@ -1,51 +1,182 @@
-use anyhow::{anyhow, Result};
+//! Templates for transactional emails.
+//!
+//! Every template consists of a plain text part `templates/email/<name>.txt` and an optional HTML part `<name>.html` next to it.
+//! The plain text part starts with a header containing at least the `subject`, followed by a `---` line:
+//!
+//! ```text
+//! subject: Reset your password
+//! ---
+//!
+//! Hi {{username}}
+//! ```
+//!
+//! Placeholders look like `{{name}}` and may be used in the subject and both parts. Rendering fails if a placeholder
+//! has no value, so a template never goes out with a blank where a link should be. Values are HTML escaped in the HTML part.
+//!
+//! Operators can override built-in templates or add new ones by placing files with the same layout into the directory
+//! configured as `email.templates_dir`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info};
+use once_cell::sync::OnceCell;
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use tracing_unwrap::OptionExt;
 
-pub(crate) type Template = (String, HashMap<String, String>);
 pub(crate) type TemplateContext = HashMap<String, String>;
 
-pub(crate) fn parse(template_path: String) -> Result<Template> {
-    let template_dir = Path::new("templates/");
-    let path = template_dir.join(&template_path);
+const BUILT_IN_DIRECTORY: &str = "templates/email";
+
+pub(crate) const VERIFY_EMAIL: &str = "user/verify_email";
+pub(crate) const PASSWORD_RESET: &str = "user/password_reset";
+pub(crate) const EMAIL_CHANGE: &str = "user/email_change";
+pub(crate) const EMAIL_CHANGE_NOTICE: &str = "user/email_change_notice";
+pub(crate) const NEW_LOGIN: &str = "user/new_login";
+pub(crate) const REPO_TRANSFER: &str = "repo/transfer";
+
+static EMAIL_TEMPLATES: OnceCell<HashMap<String, EmailTemplate>> = OnceCell::new();
+
+#[derive(Debug)]
+pub(crate) struct EmailTemplate {
+    pub(crate) name: String,
+    subject: String,
+    text: String,
+    html: Option<String>
+}
+
+#[derive(Debug)]
+pub(crate) struct RenderedEmail {
+    pub(crate) subject: String,
+    pub(crate) text: String,
+    pub(crate) html: Option<String>
+}
+
+/// Loads the built-in templates and afterwards the ones in `override_dir`, replacing built-in templates of the same name
+pub(crate) fn init(override_dir: Option<&str>) -> Result<()> {
+    let mut templates = HashMap::new();
+
+    load_directory(Path::new(BUILT_IN_DIRECTORY), &mut templates)?;
+
+    if let Some(directory) = override_dir.filter(|directory| !directory.is_empty()) {
+        load_directory(Path::new(directory), &mut templates).with_context(|| format!("Failed to load email templates from {}", directory))?;
+        info!("Loaded email templates from {}", directory);
+    }
+
+    for name in [VERIFY_EMAIL, PASSWORD_RESET, EMAIL_CHANGE, EMAIL_CHANGE_NOTICE, NEW_LOGIN, REPO_TRANSFER] {
+        if !templates.contains_key(name) {
+            bail!("Email template `{}` is missing", name);
+        }
+    }
+
+    EMAIL_TEMPLATES.set(templates).map_err(|_| anyhow!("Email templates should only be initialized once"))
+}
+
+/// Returns the email template called `name`, such as [VERIFY_EMAIL]
+pub(crate) fn get(name: &str) -> Result<&'static EmailTemplate> {
+    EMAIL_TEMPLATES.get()
+        .unwrap_or_log()
+        .get(name)
+        .ok_or_else(|| anyhow!("Unknown email template `{}`", name))
+}
+
+fn load_directory(root: &Path, templates: &mut HashMap<String, EmailTemplate>) -> Result<()> {
+    let mut directories = vec![root.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                directories.push(path);
+                continue;
+            }
 
-    let content = fs::read_to_string(path)?;
-    let mut skip_lines = 0;
+            if path.extension().map_or(true, |extension| extension != "txt") {
+                continue;
+            }
 
-    let mut tags = HashMap::new();
-    tags.insert("template_name".to_owned(), template_path.clone());
+            let name = path.strip_prefix(root)?
+                .with_extension("")
+                .to_string_lossy()
+                .replace('\\', "/");
 
-    for (index, line) in content.lines().enumerate() {
-        if line == "---" {
-            skip_lines = index + 2;
-            break;
+            let template = parse(name.as_str(), path.as_path())?;
+
+            debug!("Loaded email template {} from {}", &name, path.display());
+            templates.insert(name, template);
         }
+    }
+
+    Ok(())
+}
+
+fn parse(name: &str, text_path: &Path) -> Result<EmailTemplate> {
+    let content = fs::read_to_string(text_path)?;
+    let (header, body) = content.split_once("\n---\n").ok_or_else(|| anyhow!("Template `{}` is missing the `---` line after its header", name))?;
+
+    let mut subject = None;
 
-        let mut splitter = line.splitn(2, ": ");
-        let key = splitter.next().unwrap_or_default();
-        let value = splitter.next().unwrap_or_default();
+    for line in header.lines() {
+        let (key, value) = line.split_once(": ").unwrap_or_default();
 
         if key.is_empty() || value.is_empty() {
-            return Err(anyhow!("Template `{}` meta data contains empty values", template_path));
+            bail!("Template `{}` meta data contains empty values", name);
         }
 
-        tags.insert(key.to_owned(), value.to_owned());
+        if key == "subject" {
+            subject = Some(value.to_owned());
+        }
     }
 
-    let vec: Vec<&str> = content.lines().skip(skip_lines).collect();
+    let html_path = text_path.with_extension("html");
+    let html = if html_path.is_file() {
+        Some(fs::read_to_string(html_path)?)
+    } else {
+        None
+    };
 
-    Ok((vec.join("\n"), tags))
+    Ok(EmailTemplate {
+        name: name.to_owned(),
+        subject: subject.ok_or_else(|| anyhow!("Template `{}` has no subject", name))?,
+        text: body.trim_start_matches('\n').to_owned(),
+        html
+    })
 }
 
-pub(crate) fn render(template_content: String, context_option: Option<TemplateContext>) -> String {
-    let mut result = template_content;
+impl EmailTemplate {
+    pub(crate) fn render(&self, context: &TemplateContext) -> Result<RenderedEmail> {
+        Ok(RenderedEmail {
+            subject: substitute(self, self.subject.as_str(), context, false)?,
+            text: substitute(self, self.text.as_str(), context, false)?,
+            html: match &self.html {
+                Some(html) => Some(substitute(self, html.as_str(), context, true)?),
+                None => None
+            }
+        })
+    }
+}
 
-    if let Some(context) = context_option {
-        for (key, value) in context {
-            result = result.replace(format!("{{{{{}}}}}", key).as_str(), value.as_str());
-        }
+fn substitute(template: &EmailTemplate, input: &str, context: &TemplateContext, escape: bool) -> Result<String> {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break
+        };
+
+        let key = rest[start + 2..end].trim();
+        let value = context.get(key).ok_or_else(|| anyhow!("Email template `{}` requires `{}` which has not been provided", template.name, key))?;
+
+        result.push_str(&rest[..start]);
+        result.push_str(if escape { tera::escape_html(value) } else { value.to_owned() }.as_str());
+
+        rest = &rest[end + 2..];
     }
 
-    result
+    result.push_str(rest);
+
+    Ok(result)
 }
@@ -1,3 +1,6 @@
+use crate::commit_status::CommitState;
+use crate::git::signature::VerificationStatus;
+
 use chrono::{DateTime, FixedOffset};
 use serde::Serialize;
 
@@ -26,5 +29,18 @@ pub(crate) struct GitCommit {
 
     pub(crate) author_name: String,
     pub(crate) author_uid: Option<i32>,
-    pub(crate) author_email: String
+    pub(crate) author_email: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) verification: Option<VerificationStatus>,
+    /// Combined state of the statuses reported for this commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) status: Option<CommitState>
+}
+
+#[derive(Serialize)]
+pub(crate) struct BlameGroup<'a> {
+    pub(crate) commit: GitCommit,
+    pub(crate) start_line: usize,
+    pub(crate) lines: &'a [&'a str]
 }
@@ -0,0 +1,142 @@
+//! Topics are short tags (e.g. `rust` or `cli`) attached to repositories to make them discoverable.
+//!
+//! Topics are stored lowercase and consist of [identifier characters](is_valid), so they can be used in urls as is.
+
+use crate::config::get_setting;
+use crate::die;
+use crate::privileges::privilege;
+use crate::repository::Repository;
+use crate::user::User;
+use crate::utils::identifiers::is_valid;
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{Executor, Postgres, Transaction};
+
+/// Maximum length of a single topic, matching the column in `repo_topics`
+const MAX_TOPIC_LENGTH: usize = 35;
+
+/// Checks if the string is a valid topic: a lowercase identifier of up to 35 characters which starts with a letter or digit
+pub(crate) fn is_valid_topic(input: &str) -> bool {
+    !input.is_empty()
+        && input.len() <= MAX_TOPIC_LENGTH
+        && input.chars().all(|c| is_valid(&c) && !c.is_ascii_uppercase())
+        && input.starts_with(|c: char| c.is_ascii_alphanumeric())
+}
+
+/// Lowercases and validates `input`, removing duplicates while keeping the order.
+/// Returns an error with a user-facing message if a topic is invalid or more than `repositories.max_topics` are given
+pub(crate) async fn normalize<'e, E: Executor<'e, Database = Postgres>>(input: &[String], executor: E) -> Result<Vec<String>> {
+    let max_topics = get_setting::<i32, _>("repositories.max_topics", executor).await?.max(0) as usize;
+    let mut topics = Vec::<String>::with_capacity(input.len());
+
+    for topic in input {
+        let topic = topic.trim().to_lowercase();
+
+        if !is_valid_topic(topic.as_str()) {
+            die!(BAD_REQUEST, "Topic `{}` must be between 1 and 35 characters long, start with a letter or digit and may only contain a-z, 0-9, _ or -", topic);
+        }
+
+        if !topics.contains(&topic) {
+            topics.push(topic);
+        }
+    }
+
+    if topics.len() > max_topics {
+        die!(BAD_REQUEST, "Repositories may only have up to {} topics", max_topics);
+    }
+
+    Ok(topics)
+}
+
+/// Returns the topics of the repository in alphabetical order
+pub(crate) async fn for_repo<'e, E: Executor<'e, Database = Postgres>>(repo: &Repository, executor: E) -> Result<Vec<String>> {
+    let topics: Vec<(String,)> = sqlx::query_as("select topic from repo_topics where repo = $1 order by topic")
+        .bind(&repo.id)
+        .fetch_all(executor)
+        .await?;
+
+    Ok(topics.into_iter().map(|(topic,)| topic).collect())
+}
+
+/// Replaces the topics of the repository with `topics`, which need to be [normalized](normalize) already
+pub(crate) async fn set(repo: &Repository, topics: &[String], transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+    sqlx::query("delete from repo_topics where repo = $1")
+        .bind(&repo.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    for topic in topics {
+        sqlx::query("insert into repo_topics (repo, topic) values ($1, $2)")
+            .bind(&repo.id)
+            .bind(topic)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TopicRepository {
+    pub(crate) id: i32,
+    pub(crate) owner: String,
+    pub(crate) name: String,
+    pub(crate) description: String
+}
+
+/// Returns up to `limit` repositories tagged with `topic` which `viewer` has access to, newest first.
+/// If `after` is set, only repositories with a lower id are returned. Like the activity feed, repositories the viewer
+/// has no access to are skipped and more rows are fetched until the page is full
+pub(crate) async fn repositories(topic: &str, viewer: Option<&User>, after: Option<i32>, limit: i64, transaction: &mut Transaction<'_, Postgres>) -> Result<Vec<TopicRepository>> {
+    let mut entries = Vec::with_capacity(limit as usize);
+    let mut owners = HashMap::<i32, String>::new();
+    let mut cursor = after;
+
+    loop {
+        let batch: Vec<Repository> = sqlx::query_as::<_, Repository>("select repositories.* from repositories \
+            inner join repo_topics on repo_topics.repo = repositories.id and repo_topics.topic = $1 \
+            where not repositories.disabled and ($2::integer is null or repositories.id < $2) \
+            order by repositories.id desc limit $3")
+            .bind(topic)
+            .bind(&cursor)
+            .bind(&limit)
+            .fetch_all(&mut *transaction)
+            .await?;
+
+        let exhausted = (batch.len() as i64) < limit;
+        cursor = batch.last().map(|repo| repo.id).or(cursor);
+
+        for repo in batch {
+            if (entries.len() as i64) >= limit || !privilege::check_access(&repo, viewer, &mut *transaction).await? {
+                continue;
+            }
+
+            let owner = match owners.get(&repo.owner) {
+                Some(owner) => owner.clone(),
+                None => {
+                    let (owner,): (String,) = sqlx::query_as("select username from users where id = $1")
+                        .bind(&repo.owner)
+                        .fetch_one(&mut *transaction)
+                        .await?;
+
+                    owners.insert(repo.owner, owner.clone());
+                    owner
+                }
+            };
+
+            entries.push(TopicRepository {
+                id: repo.id,
+                owner,
+                name: repo.name,
+                description: repo.description
+            });
+        }
+
+        if exhausted || entries.len() as i64 >= limit {
+            return Ok(entries);
+        }
+    }
+}
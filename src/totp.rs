@@ -0,0 +1,142 @@
+//! Time-based one-time passwords (RFC 6238) used as optional second factor upon login.
+//!
+//! Secrets are 160 bit long and stored base32 encoded, as that is the format authenticator apps expect in `otpauth://` URIs.
+
+use crate::crypto;
+use crate::user::User;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use base32::Alphabet;
+use rand::RngCore;
+use sqlx::{Postgres, Transaction};
+use totp_lite::{totp_custom, Sha1};
+
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+
+/// Amount of steps before and after the current one which are still accepted to account for clock drift
+const ALLOWED_DRIFT: u64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+pub(crate) fn generate_secret() -> String {
+    let mut bytes = [0_u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    base32::encode(Alphabet::RFC4648 { padding: false }, &bytes)
+}
+
+/// Returns the `otpauth://` URI which gets encoded into the QR code scanned by authenticator apps
+pub(crate) fn otpauth_uri(secret: &str, username: &str, issuer: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = urlencode(issuer),
+        username = urlencode(username),
+        secret = secret,
+        digits = DIGITS,
+        period = STEP
+    )
+}
+
+/// Returns the time step `code` is valid for or `None` if it isn't valid for the current time (including drift)
+pub(crate) fn verify(secret: &str, code: &str) -> Result<Option<u64>> {
+    let key = base32::decode(Alphabet::RFC4648 { padding: false }, secret).ok_or_else(|| anyhow!("TOTP secret is not valid base32"))?;
+    let code = code.trim();
+
+    if code.len() != DIGITS as usize {
+        return Ok(None);
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    Ok((0..=ALLOWED_DRIFT * 2).map(|offset| (now + offset * STEP).saturating_sub(ALLOWED_DRIFT * STEP)).find_map(|time| {
+        (totp_custom::<Sha1>(STEP, DIGITS, key.as_slice(), time) == code).then(|| time / STEP)
+    }))
+}
+
+/// Checks `code` of `user`, which is either a code of their authenticator app or one of their recovery codes.
+///
+/// Every time step is only accepted once, so a code that has been observed (e.g. shoulder surfed or phished) cannot be
+/// replayed while it is still valid. Recovery codes are marked as used
+pub(crate) async fn check_code(user: &User, code: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<bool> {
+    let secret = user.totp_secret.as_deref().ok_or_else(|| anyhow!("User has no two-factor authentication secret"))?;
+    let code = code.trim();
+
+    if code.len() != DIGITS as usize {
+        return use_recovery_code(user, code, transaction).await;
+    }
+
+    let step = match verify(secret, code)? {
+        Some(step) => step as i64,
+        None => return Ok(false)
+    };
+
+    // Only updates if no code of this or a later step has been used yet, which also guards against concurrent requests
+    let result = sqlx::query("update users set totp_last_step = $1 where id = $2 and (totp_last_step is null or totp_last_step < $1)")
+        .bind(&step)
+        .bind(&user.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Replaces all recovery codes of `user` with newly generated ones and returns them in plain text.
+/// Recovery codes are only stored hashed and thus cannot be shown again afterwards
+pub(crate) async fn generate_recovery_codes(user: &User, transaction: &mut Transaction<'_, Postgres>) -> Result<Vec<String>> {
+    sqlx::query("delete from recovery_codes where owner = $1")
+        .bind(&user.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    let mut codes = Vec::<String>::with_capacity(RECOVERY_CODE_COUNT);
+
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = crypto::random_numeric_ascii_string(RECOVERY_CODE_LENGTH).to_lowercase();
+        let hash = crypto::hash_password(code.as_str())?;
+
+        sqlx::query("insert into recovery_codes (owner, hash) values ($1, $2)")
+            .bind(&user.id)
+            .bind(hash.as_str())
+            .execute(&mut *transaction)
+            .await?;
+
+        codes.push(code);
+    }
+
+    Ok(codes)
+}
+
+/// Checks `code` against the unused recovery codes of `user`. If it matches, the code gets marked as used
+pub(crate) async fn use_recovery_code(user: &User, code: &str, transaction: &mut Transaction<'_, Postgres>) -> Result<bool> {
+    let code = code.trim().to_lowercase();
+
+    if code.len() != RECOVERY_CODE_LENGTH {
+        return Ok(false);
+    }
+
+    let candidates: Vec<(i32, String)> = sqlx::query_as("select id, hash from recovery_codes where owner = $1 and used_at is null")
+        .bind(&user.id)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    for (id, hash) in candidates {
+        if argon2::verify_encoded(hash.as_str(), code.as_bytes())? {
+            sqlx::query("update recovery_codes set used_at = now() where id = $1")
+                .bind(&id)
+                .execute(&mut *transaction)
+                .await?;
+
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn urlencode(input: &str) -> String {
+    url::form_urlencoded::byte_serialize(input.as_bytes()).collect()
+}
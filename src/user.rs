@@ -1,8 +1,10 @@
 use crate::error::{ErrorDisplayType, GitArenaError};
 use crate::session::Session;
-use crate::{die, err, session};
+use crate::{config, die, err, session};
 
+use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::ops::Deref;
 use std::pin::Pin;
 use std::sync::Arc;
 
@@ -15,8 +17,11 @@ use chrono::{DateTime, Utc};
 use derive_more::Display;
 use futures::Future;
 use ipnetwork::IpNetwork;
+use log::{debug, info, warn};
+use once_cell::sync::OnceCell;
 use serde::Serialize;
-use sqlx::{Executor, FromRow, PgPool, Postgres};
+use sqlx::{Executor, FromRow, PgPool, Postgres, Row, Transaction};
+use tracing::Span;
 
 #[derive(FromRow, Display, Debug, Serialize)]
 #[display(fmt = "{}", username)]
@@ -26,11 +31,100 @@ pub(crate) struct User {
     #[serde(skip_serializing)]
     pub(crate) password: String,
     pub(crate) disabled: bool,
+    /// Account can't be used until this point in time, set by admins using [ban](crate::routes::admin::users::ban_user)
+    pub(crate) banned_until: Option<DateTime<Utc>>,
+    /// Overrides the `quotas.user_max_size` setting in bytes if set, zero allows unlimited storage. See [quota](crate::quota)
+    pub(crate) storage_quota: Option<i64>,
     pub(crate) admin: bool,
+    #[serde(skip_serializing)]
+    pub(crate) totp_secret: Option<String>,
+    /// Show the noreply alias instead of the actual email address in commits displayed and authored by GitArena
+    pub(crate) private_email: bool,
+    /// Email the user about logins from ip addresses they haven't used before, see [known_login](crate::known_login)
+    #[serde(skip_serializing)]
+    pub(crate) login_notifications: bool,
+    /// Organizations are owners of repositories which users can't log into, see [organization](crate::organization)
+    pub(crate) organization: bool,
     pub(crate) created_at: DateTime<Utc>
 }
 
+/// Host of the instance used in noreply email addresses. Set on startup from the `domain` setting
+/// Shown instead of the username for issues, pull requests and comments whose author deleted their account
+pub(crate) const GHOST_USERNAME: &str = "Ghost";
+
+static NOREPLY_HOST: OnceCell<String> = OnceCell::new();
+
+pub(crate) fn init_noreply_host(domain: Option<&str>) {
+    let host = domain.unwrap_or_default()
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split(&['/', ':'][..])
+        .next()
+        .filter(|host| !host.is_empty())
+        .unwrap_or("localhost");
+
+    let _ = NOREPLY_HOST.set(host.to_lowercase());
+}
+
+/// Extracts the user id from a noreply address as returned by [User::noreply_email]
+fn noreply_user_id(email: &str) -> Option<i32> {
+    let host = NOREPLY_HOST.get().map(String::as_str).unwrap_or("localhost");
+    let (local, domain) = email.rsplit_once('@')?;
+
+    if domain.strip_prefix("users.noreply.")? != host {
+        return None;
+    }
+
+    let (id, _) = local.split_once('+')?;
+
+    id.parse().ok()
+}
+
 impl User {
+    /// Alias of the form `{id}+{username}@users.noreply.{host}` which is shown instead of the actual email address if
+    /// [private_email](User::private_email) is enabled. Resolves back to this user using [find_using_emails](User::find_using_emails)
+    pub(crate) fn noreply_email(&self) -> String {
+        let host = NOREPLY_HOST.get().map(String::as_str).unwrap_or("localhost");
+
+        format!("{}+{}@users.noreply.{}", self.id, self.username.to_lowercase(), host)
+    }
+
+    pub(crate) fn is_banned(&self) -> bool {
+        self.banned_until.map_or(false, |until| until > Utc::now())
+    }
+
+    /// Returns why this account can't be used right now, or `None` if it may log in
+    pub(crate) fn blocked_message(&self) -> Option<String> {
+        if self.disabled {
+            Some("Account has been disabled. Please contact support.".to_owned())
+        } else if let Some(until) = self.banned_until.filter(|_| self.is_banned()) {
+            Some(format!("Account has been banned until {}. Please contact support.", until.format("%Y-%m-%d %H:%M UTC")))
+        } else {
+            None
+        }
+    }
+
+    /// Makes this user an admin if it's the only user on the instance and `users.promote_first_user` is enabled,
+    /// so the first admin of a fresh instance doesn't need to be created by hand in the database
+    pub(crate) async fn promote_if_first(&mut self, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
+        if self.admin || self.organization || !config::get_setting::<bool, _>("users.promote_first_user", &mut *transaction).await? {
+            return Ok(());
+        }
+
+        let promoted: Option<(i32,)> = sqlx::query_as("update users set admin = true where id = $1 and \
+            not exists(select 1 from users where id <> $1 and organization = false) returning id")
+            .bind(&self.id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+        if promoted.is_some() {
+            info!("Promoted {} (id {}) to admin as they're the first user of this instance", &self.username, &self.id);
+            self.admin = true;
+        }
+
+        Ok(())
+    }
+
     pub(crate) async fn find_using_name<'e, E, S>(name: S, executor: E) -> Option<User>
         where E: Executor<'e, Database = Postgres>,
               S: AsRef<str>
@@ -47,20 +141,63 @@ impl User {
         user
     }
 
-    pub(crate) async fn find_using_email<'e, E, S>(email: S, executor: E) -> Option<User>
+    /// Returns the username of the user with the id `id` or [GHOST_USERNAME] if their account has been deleted
+    pub(crate) async fn username_or_ghost<'e, E: Executor<'e, Database = Postgres>>(id: Option<i32>, executor: E) -> Result<String> {
+        let username: Option<(String,)> = match id {
+            Some(id) => sqlx::query_as("select username from users where id = $1 limit 1")
+                .bind(&id)
+                .fetch_optional(executor)
+                .await?,
+            None => None
+        };
+
+        Ok(username.map_or_else(|| GHOST_USERNAME.to_owned(), |(username,)| username))
+    }
+
+    /// Resolves multiple emails using a single query. Every verified address of an user is considered, not only their primary one.
+    /// Returns a map of lower case email to the user owning it. Emails not belonging to any user or which have not been verified are absent from the map
+    pub(crate) async fn find_using_emails<'e, E, S>(emails: &[S], executor: E) -> Result<HashMap<String, User>>
         where E: Executor<'e, Database = Postgres>,
               S: AsRef<str>
     {
-        let email = email.as_ref();
+        let emails = emails.iter().map(|email| email.as_ref().to_lowercase()).collect::<Vec<_>>();
 
-        let user = sqlx::query_as::<_, User>("select * from users where id = (select owner from emails where lower(email) = lower($1) limit 1) limit 1")
-            .bind(email)
-            .fetch_optional(executor)
-            .await
-            .ok()
-            .flatten();
+        if emails.is_empty() {
+            return Ok(HashMap::new());
+        }
 
-        user
+        // Noreply aliases are not stored in the database but contain the user id
+        let noreply_ids = emails.iter().filter_map(|email| noreply_user_id(email.as_str())).collect::<Vec<_>>();
+
+        let rows = sqlx::query("select users.*, lower(emails.email) as matched_email from emails inner join users on users.id = emails.owner \
+            where lower(emails.email) = any($1) and emails.verified_at is not null \
+            union all select users.*, null as matched_email from users where id = any($2)")
+            .bind(&emails)
+            .bind(&noreply_ids)
+            .fetch_all(executor)
+            .await?;
+
+        let mut users = HashMap::with_capacity(rows.len());
+
+        for row in rows {
+            let user = User::from_row(&row)?;
+
+            match row.try_get::<Option<String>, _>("matched_email")? {
+                Some(email) => {
+                    users.insert(email, user);
+                }
+                None => {
+                    // The username is part of the alias, so it only resolves as long as the user has not been renamed
+                    let alias = user.noreply_email();
+
+                    if emails.contains(&alias) {
+                        users.insert(alias, user);
+                    }
+                }
+            }
+        }
+
+        Ok(users)
     }
 }
 
@@ -125,9 +262,19 @@ impl FromRequest for WebUser {
                 let db_pool = db_pool.clone();
 
                 Box::pin(async move {
-                    extract_from_request(db_pool, id_future, ip_network, user_agent).await.map_err(|err| GitArenaError {
-                        source: Arc::new(err),
-                        display_type: ErrorDisplayType::Html // TODO: Check whenever route is err = "html|json|git" etc...
+                    extract_from_request(db_pool, id_future, ip_network, user_agent).await.map_err(|err| {
+                        // Treating the request as anonymous would silently log out everyone while the database is unreachable
+                        let err = if err.chain().any(|cause| cause.is::<sqlx::Error>()) {
+                            warn!("Failed to look up session, database unavailable: {:#}", err);
+                            err!(SERVICE_UNAVAILABLE, "Database is currently unavailable, please try again later").into()
+                        } else {
+                            err
+                        };
+
+                        GitArenaError {
+                            source: Arc::new(err),
+                            display_type: ErrorDisplayType::Html // TODO: Check whenever route is err = "html|json|git" etc...
+                        }
                     })
                 })
             }
@@ -141,6 +288,50 @@ impl FromRequest for WebUser {
     }
 }
 
+/// Extractor for admin only routes. Anonymous requests are rejected with 401 and other users with 403 before the handler runs
+#[derive(Debug)]
+pub(crate) struct AdminUser(User);
+
+impl AdminUser {
+    pub(crate) fn into_user(self) -> User {
+        self.0
+    }
+}
+
+impl Deref for AdminUser {
+    type Target = User;
+
+    fn deref(&self) -> &User {
+        &self.0
+    }
+}
+
+impl FromRequest for AdminUser {
+    type Error = GitArenaError;
+    type Future = Pin<Box<dyn Future<Output = Result<AdminUser, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let web_user_future = WebUser::from_request(req, payload);
+
+        Box::pin(async move {
+            match web_user_future.await? {
+                WebUser::Authenticated(user) if user.admin => Ok(AdminUser(user)),
+                web_user => {
+                    let error: Error = match web_user {
+                        WebUser::Anonymous => err!(UNAUTHORIZED, "Not authenticated").into(),
+                        WebUser::Authenticated(_) => err!(FORBIDDEN, "Not allowed").into()
+                    };
+
+                    Err(GitArenaError {
+                        source: Arc::new(error),
+                        display_type: ErrorDisplayType::Html // TODO: Check whenever route is err = "html|json|git" etc...
+                    })
+                }
+            }
+        })
+    }
+}
+
 async fn extract_from_request<F: Future<Output = actix_web::Result<Identity>>>(db_pool: Data<PgPool>, id_future: F, ip_network: IpNetwork, user_agent: String) -> Result<WebUser> {
     let id = id_future.await.map_err(|_| anyhow!("Failed to build identity"))?;
 
@@ -148,15 +339,45 @@ async fn extract_from_request<F: Future<Output = actix_web::Result<Identity>>>(d
         Some(identity) => {
             let mut transaction = db_pool.begin().await?;
 
-            let result = match Session::from_identity(Some(identity), &mut transaction).await? {
+            let max_age = config::get_setting::<i64, _>("sessions.max_age", &mut transaction).await?;
+
+            let result = match Session::from_identity(Some(identity.clone()), &mut transaction).await? {
+                Some(session) if session.is_expired(max_age) => {
+                    debug!("Session for user id {} expired, logging out", &session.user_id);
+
+                    session.destroy(&mut transaction).await?;
+                    id.forget();
+
+                    WebUser::Anonymous
+                }
+                // Session is only fully authenticated once the second factor has been entered
+                Some(session) if session.pending_2fa => WebUser::Anonymous,
                 Some(session) => {
-                    session.update_explicit(&ip_network, user_agent.as_str(), &mut transaction).await?;
+                    // The cookie is issued again as well, as it would otherwise still expire `max_age` after logging in
+                    if session.needs_refresh(max_age) {
+                        session.update_explicit(&ip_network, user_agent.as_str(), &mut transaction).await?;
+                        id.remember(identity);
+                    }
 
                     let user: Option<User> = sqlx::query_as::<_, User>("select * from users where id = $1 limit 1")
                         .bind(&session.user_id)
                         .fetch_optional(&mut transaction)
                         .await?;
 
+                    if let Some(message) = user.as_ref().and_then(User::blocked_message) {
+                        debug!("Session for user id {} belongs to a disabled or banned account, logging out", &session.user_id);
+
+                        session.destroy(&mut transaction).await?;
+                        transaction.commit().await?;
+                        id.forget();
+
+                        die!(FORBIDDEN, "{}", message);
+                    }
+
+                    if let Some(user) = user.as_ref() {
+                        Span::current().record("user_id", &user.id);
+                    }
+
                     user.map_or_else(|| WebUser::Anonymous, WebUser::Authenticated)
                 }
                 None => {
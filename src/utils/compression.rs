@@ -0,0 +1,46 @@
+use std::future::Future;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderValue};
+use actix_web::Error as ActixError;
+use actix_web::Result as ActixResult;
+
+/// Content types worth compressing. Everything else (such as Git pack data, archives and images) is usually already
+/// compressed, so compressing it again only costs CPU time
+const COMPRESSIBLE_TYPES: [&str; 6] = [
+    "application/atom+xml",
+    "application/javascript",
+    "application/json",
+    "application/xml",
+    "image/svg+xml",
+    "text/"
+];
+
+/// Middleware opting responses out of [Compress](actix_web::middleware::Compress) unless their content type is
+/// [compressible](COMPRESSIBLE_TYPES). Needs to be wrapped *inside* of `Compress`, which skips responses that already
+/// have a `Content-Encoding` header set
+pub(crate) fn compression_middleware<S, B>(request: ServiceRequest, service: &S) -> impl Future<Output = ActixResult<ServiceResponse<B>>> + 'static
+    where S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+          S::Future: 'static,
+          B: 'static
+{
+    let future = service.call(request);
+
+    async {
+        let mut response = future.await?;
+
+        let compressible = response.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |content_type| {
+                // Server-sent events need to be flushed as they happen, which the encoder wouldn't do
+                !content_type.starts_with("text/event-stream") && COMPRESSIBLE_TYPES.iter().any(|prefix| content_type.starts_with(prefix))
+            });
+
+        if !compressible && !response.headers().contains_key(CONTENT_ENCODING) {
+            response.headers_mut().insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,47 @@
+//! Conditional GET for content addressed responses such as blobs and archives.
+//!
+//! The urls of these responses usually contain a ref which may move at any time, so clients are told to revalidate
+//! every time (`no-cache`). Revalidation is cheap as the object id is known before the content has to be read.
+//! Callers need to check access to the repository before calling [not_modified], so a 304 is never sent to somebody
+//! who is not allowed to read the content.
+
+use crate::prelude::HttpRequestExtensions;
+use crate::privileges::repo_visibility::RepoVisibility;
+use crate::repository::Repository;
+
+use std::fmt::Display;
+
+use actix_web::http::header::{CACHE_CONTROL, ETAG};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+
+/// Returns the strong entity tag for the object `id`
+pub(crate) fn strong<D: Display>(id: D) -> String {
+    format!("\"{}\"", id)
+}
+
+/// Returns a 304 response if the `If-None-Match` header of `request` matches `etag`
+pub(crate) fn not_modified(request: &HttpRequest, repo: &Repository, etag: &str) -> Option<HttpResponse> {
+    let if_none_match = request.get_header("if-none-match")?;
+
+    // If-None-Match uses the weak comparison, so `W/` prefixes are ignored
+    let matches = if_none_match.split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+
+    matches.then(|| {
+        let mut response = HttpResponse::NotModified();
+        apply(&mut response, repo, etag);
+        response.finish()
+    })
+}
+
+/// Adds the `ETag` and `Cache-Control` headers. Content of non-public repositories may only be cached by the client itself
+pub(crate) fn apply(response: &mut HttpResponseBuilder, repo: &Repository, etag: &str) {
+    let cache_control = if repo.visibility == RepoVisibility::Public {
+        "no-cache"
+    } else {
+        "private, no-cache"
+    };
+
+    response.insert_header((ETAG, etag)).insert_header((CACHE_CONTROL, cache_control));
+}
@@ -0,0 +1,30 @@
+/// Checks if `input` matches `pattern`. The only supported wildcard is `*`, which matches any amount of characters (including none).
+///
+/// Never takes more than `pattern.len() * input.len()` steps: when a later part of the pattern fails to match, only the most
+/// recent `*` needs to consume one more character, as every earlier `*` could absorb the same characters as well.
+/// Matching works on bytes, which is fine for UTF-8 as `*` is ASCII and literal characters still need to match in full
+pub(crate) fn matches(pattern: &str, input: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let input = input.as_bytes();
+
+    let (mut p, mut i) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < input.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, i));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == input[i] {
+            p += 1;
+            i += 1;
+        } else if let Some((star, start)) = backtrack {
+            p = star + 1;
+            i = start + 1;
+            backtrack = Some((star, i));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
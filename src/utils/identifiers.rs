@@ -1,3 +1,4 @@
+use crate::config::get_setting;
 use crate::die;
 
 use anyhow::Result;
@@ -23,6 +24,32 @@ pub(crate) fn is_valid(c: &char) -> bool {
     c.is_ascii_alphanumeric() || c == &'-' || c == &'_'
 }
 
+/// Checks if the string is a valid single component of a file path (e.g. `README.md` or `.gitignore`).
+///
+/// Unlike [identifiers](is_valid), path components may contain dots. `.` and `..` are always rejected to prevent path traversal,
+/// as are components which are [not legal on Windows](is_fs_legal) such as ones ending in a dot.
+/// Leading dots are allowed as they are commonly used for hidden files.
+///
+/// # Example
+///
+/// ```
+/// use crate::utils::identifiers::is_path_component;
+///
+/// assert!(is_path_component("README.md"));
+/// assert!(is_path_component(".gitignore"));
+/// assert!(!is_path_component(".."));
+/// assert!(!is_path_component("trailing."));
+/// assert!(!is_path_component("a/b"));
+/// ```
+pub(crate) fn is_path_component(input: &str) -> bool {
+    // Explicitly checked as both would otherwise consist of only valid characters
+    if input.is_empty() || input == "." || input == ".." {
+        return false;
+    }
+
+    input.chars().all(|c| is_valid(&c) || c == '.') && is_fs_legal(input)
+}
+
 /// Checks if the string is a reserved username.
 ///
 /// This method checks the input string against the list of hardcoded, reserved usernames.
@@ -54,17 +81,40 @@ pub(crate) fn is_reserved_username(input: &str) -> bool {
     ILLEGAL_USERNAMES.contains(&lower_case.as_str())
 }
 
+/// Checks if the string is contained in the `users.reserved_names` setting, a comma separated list of names.
+///
+/// Unlike [is_reserved_username], this list can be configured by the instance administrator, for example to reserve
+/// names of additional pages served by a reverse proxy in front of GitArena. The comparison is case-insensitive.
+/// `input` _should_ already be [validated](validate_username).
+pub(crate) async fn is_configured_reserved_username<'e, E: Executor<'e, Database = Postgres>>(input: &str, executor: E) -> Result<bool> {
+    let reserved_names = get_setting::<String, _>("users.reserved_names", executor).await?;
+
+    Ok(reserved_names.split(',')
+        .map(str::trim)
+        .any(|name| name.eq_ignore_ascii_case(input)))
+}
+
+/// Checks if the string is a valid username which is not [reserved by the instance configuration](is_configured_reserved_username).
+/// Returns `Ok` on success and an error with a user-facing message on failure.
+pub(crate) async fn validate_new_username<'e, E: Executor<'e, Database = Postgres>>(input: &str, executor: E) -> Result<()> {
+    validate_username(input)?;
+
+    if is_configured_reserved_username(input, executor).await? {
+        die!(CONFLICT, "Username is reserved and cannot be registered");
+    }
+
+    Ok(())
+}
+
 /// Checks if the string is a valid username.
-/// Returns `Ok` on success and [HttpError][0] with error string on failure.
+/// Returns `Ok` on success and an error with a user-facing message on failure.
 ///
 /// This method checks if the username is:
 /// - At least 3 characters long
 /// - At max 32 characters long
 /// - [A valid identifier](is_valid)
 /// - [Not a reserved username](is_reserved_username)
-/// - [Legal for the current OS filesystem](is_fs_legal)
-///
-/// [0]: crate::error::GAErrors::HttpError
+/// - [Legal as a file name on all major operating systems](is_fs_legal)
 pub(crate) fn validate_username(input: &str) -> Result<()> {
     if input.len() < 3 || input.len() > 32 || !input.chars().all(|c| is_valid(&c)) {
         die!(BAD_REQUEST, "Username must be between 3 and 32 characters long and may only contain a-z, 0-9, _ or -");
@@ -81,6 +131,34 @@ pub(crate) fn validate_username(input: &str) -> Result<()> {
     Ok(())
 }
 
+/// Converts an arbitrary string (such as a display name or an username from a SSO provider) into a valid username.
+/// Returns the sanitized username on success and an error with a user-facing message if nothing usable remains.
+///
+/// Whitespace is replaced with dashes and all other characters which are not [a valid identifier](is_valid) are dropped.
+/// The result is truncated to 32 characters and afterwards [validated](validate_username).
+///
+/// # Example
+///
+/// ```
+/// use crate::utils::identifiers::sanitize_username;
+///
+/// assert_eq!(sanitize_username("mellow").unwrap(), "mellow");
+/// assert_eq!(sanitize_username("Mellow Agäin").unwrap(), "Mellow-Agin");
+/// assert!(sanitize_username("äöü").is_err());
+/// ```
+pub(crate) fn sanitize_username(input: &str) -> Result<String> {
+    let sanitized = input.trim()
+        .chars()
+        .map(|c| if c.is_whitespace() { '-' } else { c })
+        .filter(is_valid)
+        .take(32)
+        .collect::<String>();
+
+    validate_username(sanitized.as_str())?;
+
+    Ok(sanitized)
+}
+
 /// Checks if the string is already a taken username.
 ///
 /// This method requires a database connection as it will check the provided input against the user table.
@@ -127,44 +205,45 @@ pub(crate) fn is_reserved_repo_name(input: &str) -> bool {
     ILLEGAL_REPO_NAMES.contains(&lower_case.as_str())
 }
 
-/// Checks if the string is a legal name for this operating system.
+/// Checks if the string is a legal file name on all major operating systems.
 ///
-/// On Windows, this checks the input against a list of hardcoded, illegal file names.
-/// On other operating systems, this function will always return `true`.
+/// Repositories are cloned onto the machines of their users, so names need to be legal on Windows even if
+/// GitArena itself runs on another operating system. This rejects:
+/// - Reserved device names (such as `CON` or `COM1`), regardless of case and extension
+/// - Names ending in a dot or a space
 ///
 /// # Example
 ///
-/// ```no_run
+/// ```
 /// use crate::utils::identifiers::is_fs_legal;
 ///
-/// // This will fail on Windows but pass on other operating systems
-/// assert!(is_fs_legal("COM0"));
+/// assert!(is_fs_legal("gitarena"));
+/// assert!(!is_fs_legal("Com1"));
+/// assert!(!is_fs_legal("con.txt"));
+/// assert!(!is_fs_legal("gitarena."));
 /// ```
 pub(crate) fn is_fs_legal(input: &str) -> bool {
-    // The actual implementation is in private functions to prevent having to write the doc twice
-    internal_is_fs_legal(input)
-}
-
-#[cfg(windows)]
-fn internal_is_fs_legal(input: &str) -> bool {
     const ILLEGAL_FILENAMES: [&str; 25] = [
-        "con", "prn", "aux", "nul", "lst",
-        "com0", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
-        "lpt0", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9"
+        "CON", "PRN", "AUX", "NUL", "LST", "CONIN$", "CONOUT$",
+        "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"
     ];
 
-    // Strip the extension if one exists (as Windows ignores them as well)
-    let lowercase = if let Some((file_name, _)) = input.rsplit_once('.') {
-        file_name
-    } else {
-        input
-    }.to_lowercase(); // These invalid file names are valid for both cases
+    if input.ends_with('.') || input.ends_with(' ') {
+        return false;
+    }
+
+    // Windows ignores everything after the first dot (and trailing spaces before it) when checking for device names
+    let file_name = input.split('.').next().unwrap_or(input).trim_end_matches(' ');
+    let uppercase = file_name.to_uppercase();
 
-    !ILLEGAL_FILENAMES.contains(&lowercase.as_str())
+    !ILLEGAL_FILENAMES.contains(&uppercase.as_str())
 }
 
-#[cfg(not(windows))]
-#[inline]
-fn internal_is_fs_legal(_: &str) -> bool {
-    true
+/// Checks if the string looks like an email address.
+///
+/// This is not according to the spec of the IETF but trying to implement that is honestly out-of-bounds for this project.
+/// Thus a best effort naive implementation. Checks for the presence of "@" and a "." in the domain name (after the last @).
+pub(crate) fn is_valid_email(input: &str) -> bool {
+    input.len() <= 256 && input.rsplit_once('@').map_or(false, |(local, domain)| !local.is_empty() && domain.contains('.'))
 }
@@ -1,11 +1,25 @@
 use std::future::Future;
 use std::time::Instant;
 
+use sqlx::postgres::PgDatabaseError;
+use sqlx::Error as SqlxError;
+
 pub(crate) mod admin_panel_layer;
+pub(crate) mod compression;
 pub(crate) mod cookie_file;
+pub(crate) mod etag;
 pub(crate) mod filesystem;
+pub(crate) mod glob;
 pub(crate) mod identifiers;
 pub(crate) mod oid;
+pub(crate) mod outbound_url;
+pub(crate) mod pagination;
+pub(crate) mod rate_limit;
+pub(crate) mod repo_redirect;
+pub(crate) mod request_span;
+pub(crate) mod safe_redirect;
+pub(crate) mod security_headers;
+pub(crate) mod stream;
 
 /// Counts the amount of seconds the provided [Future][future] took to execute.
 /// The [Future][future] _should_ not return a output, as it will be discarded and not returned.
@@ -35,3 +49,12 @@ pub(crate) async fn time_function<T: Future, F: FnOnce() -> T>(func: F) -> u64 {
 
     start.elapsed().as_secs()
 }
+
+/// Returns whenever `err` has been caused by violating an unique constraint.
+/// Useful to turn races between an existence check and an insert into a proper conflict response
+pub(crate) fn is_unique_violation(err: &SqlxError) -> bool {
+    // 23505: unique_violation
+    err.as_database_error()
+        .and_then(|db_err| db_err.try_downcast_ref::<PgDatabaseError>())
+        .map_or(false, |pg_err| pg_err.code() == "23505")
+}
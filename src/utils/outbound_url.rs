@@ -0,0 +1,15 @@
+//! Guards requests GitArena makes to user supplied urls against server-side request forgery, see
+//! [gitarena_common::outbound_url] for details. Errors are turned into responses with status `400 Bad Request`
+
+use crate::err;
+
+use anyhow::Result;
+use gitarena_common::outbound_url;
+use url::Url;
+
+/// Errors if the host of `url` resolves to an address which is not publicly routable. The scheme needs to be checked by the caller
+pub(crate) async fn check(url: &Url) -> Result<()> {
+    outbound_url::check(url).await.map_err(|err| err!(BAD_REQUEST, "{}", err))?;
+
+    Ok(())
+}
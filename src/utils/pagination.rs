@@ -0,0 +1,88 @@
+//! Cursor based pagination used by list endpoints.
+//!
+//! Clients pass `limit` and `after` as query parameters. Responses contain the items as well as `next_cursor`, which
+//! is passed as `after` to request the following page and is `null` on the last one. The url of the next page is also
+//! sent in a `Link` header (`rel="next"`). What a cursor looks like depends on the endpoint, e.g. commit lists use SHAs.
+
+use crate::config::get_setting;
+use crate::die;
+
+use actix_web::http::header::LINK;
+use actix_web::{HttpRequest, HttpResponse};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Postgres};
+use url::form_urlencoded::{self, Serializer};
+
+/// Page size used if the client did not specify `limit`, unless `api.max_page_size` is lower
+const DEFAULT_LIMIT: usize = 30;
+
+#[derive(Deserialize)]
+pub(crate) struct PageQuery {
+    pub(crate) limit: Option<usize>,
+    pub(crate) after: Option<String>
+}
+
+impl PageQuery {
+    /// Returns the requested page size, capped at `api.max_page_size`
+    pub(crate) async fn limit<'e, E: Executor<'e, Database = Postgres>>(&self, executor: E) -> Result<usize> {
+        let max = get_setting::<i32, _>("api.max_page_size", executor).await?.max(1) as usize;
+
+        match self.limit {
+            Some(0) => die!(BAD_REQUEST, "Limit needs to be at least 1"),
+            Some(limit) => Ok(limit.min(max)),
+            None => Ok(DEFAULT_LIMIT.min(max))
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct Page<T: Serialize> {
+    pub(crate) items: Vec<T>,
+    pub(crate) next_cursor: Option<String>
+}
+
+impl<T: Serialize> Page<T> {
+    /// Builds a page out of `items`, which should contain up to `limit + 1` elements. The additional element is only
+    /// used to find out whenever there is a next page and gets removed. `cursor` returns the cursor pointing after an item
+    pub(crate) fn new<F: Fn(&T) -> String>(mut items: Vec<T>, limit: usize, cursor: F) -> Page<T> {
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(cursor)
+        } else {
+            None
+        };
+
+        Page {
+            items,
+            next_cursor
+        }
+    }
+
+    /// Serializes the page as json and links the next page (if any) using the `Link` header
+    pub(crate) fn respond(&self, request: &HttpRequest, limit: usize) -> HttpResponse {
+        let mut builder = HttpResponse::Ok();
+
+        if let Some(cursor) = self.next_cursor.as_deref() {
+            builder.insert_header((LINK, format!("<{}>; rel=\"next\"", next_url(request, cursor, limit))));
+        }
+
+        builder.json(self)
+    }
+}
+
+/// Url of the current request with `after` and `limit` replaced, keeping all other query parameters as is
+fn next_url(request: &HttpRequest, cursor: &str, limit: usize) -> String {
+    let mut serializer = Serializer::new(String::new());
+
+    for (key, value) in form_urlencoded::parse(request.query_string().as_bytes()) {
+        if key != "after" && key != "limit" {
+            serializer.append_pair(key.as_ref(), value.as_ref());
+        }
+    }
+
+    serializer.append_pair("limit", limit.to_string().as_str());
+    serializer.append_pair("after", cursor);
+
+    format!("{}?{}", request.path(), serializer.finish())
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use once_cell::sync::OnceCell;
+
+/// Amount of keys after which stale entries get purged from the map on the next write
+const PURGE_THRESHOLD: usize = 10_000;
+
+/// In-memory sliding window counter. Keys are arbitrary strings such as `ip:127.0.0.1` or `user:admin`.
+/// A key is limited once it accumulated `max` hits within the last `window`; hits older than `window` decay automatically.
+///
+/// As the state lives in memory it is not shared between multiple GitArena instances and is reset upon restart.
+pub(crate) struct RateLimiter {
+    max: usize,
+    window: Duration,
+    entries: Mutex<HashMap<String, VecDeque<Instant>>>
+}
+
+impl RateLimiter {
+    pub(crate) fn new(max: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            max,
+            window,
+            entries: Mutex::new(HashMap::new())
+        }
+    }
+
+    pub(crate) fn is_limited(&self, key: &str) -> bool {
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("Rate limiter lock has been poisoned: {}", err);
+                return false;
+            }
+        };
+
+        match entries.get_mut(key) {
+            Some(hits) => {
+                Self::prune(hits, self.window);
+                hits.len() >= self.max
+            }
+            None => false
+        }
+    }
+
+    pub(crate) fn hit(&self, key: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= PURGE_THRESHOLD {
+                let window = self.window;
+
+                entries.retain(|_, hits| {
+                    Self::prune(hits, window);
+                    !hits.is_empty()
+                });
+            }
+
+            let hits = entries.entry(key.to_owned()).or_insert_with(VecDeque::new);
+
+            Self::prune(hits, self.window);
+            hits.push_back(Instant::now());
+        }
+    }
+
+    pub(crate) fn reset(&self, key: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.remove(key);
+        }
+    }
+
+    fn prune(hits: &mut VecDeque<Instant>, window: Duration) {
+        while matches!(hits.front(), Some(instant) if instant.elapsed() > window) {
+            hits.pop_front();
+        }
+    }
+}
+
+/// Limits account registrations per ip address. Set on startup from the `registrations.rate_limit.*` settings,
+/// so it can be checked before a request touches the database
+pub(crate) static REGISTRATIONS: OnceCell<IpRateLimiter> = OnceCell::new();
+
+/// [RateLimiter] keyed by ip address which optionally exempts loopback addresses (e.g. for integration tests)
+pub(crate) struct IpRateLimiter {
+    limiter: RateLimiter,
+    exempt_loopback: bool
+}
+
+impl IpRateLimiter {
+    pub(crate) fn new(max: usize, window: Duration, exempt_loopback: bool) -> IpRateLimiter {
+        IpRateLimiter {
+            limiter: RateLimiter::new(max, window),
+            exempt_loopback
+        }
+    }
+
+    /// Records a hit for `ip_address` unless it is already limited. Returns `true` if the request should be rejected
+    pub(crate) fn check(&self, ip_address: IpAddr) -> bool {
+        if self.exempt_loopback && ip_address.is_loopback() {
+            return false;
+        }
+
+        let key = ip_address.to_string();
+
+        if self.limiter.is_limited(key.as_str()) {
+            return true;
+        }
+
+        self.limiter.hit(key.as_str());
+        false
+    }
+}
@@ -0,0 +1,143 @@
+use std::future::Future;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::LOCATION;
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::Data;
+use actix_web::Error as ActixError;
+use actix_web::HttpResponse;
+use actix_web::Result as ActixResult;
+use anyhow::Result;
+use log::warn;
+use sqlx::{Executor, PgPool, Postgres};
+
+/// Remembers the current owner and name of `repo` as its previous location, so requests to it get redirected.
+/// Needs to be called *before* the repository gets renamed or transferred
+pub(crate) async fn add_redirect<'e, E: Executor<'e, Database = Postgres>>(repo: i32, executor: E) -> Result<()> {
+    sqlx::query("insert into repository_redirects (owner, name, repo) select owner, name, id from repositories where id = $1 \
+        on conflict (owner, lower(name)) do update set repo = excluded.repo, created_at = now()")
+        .bind(&repo)
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Middleware redirecting requests which resulted in a 404 to the new location of renamed or transferred repositories.
+///
+/// Needs to be wrapped *inside* of the [error renderer](crate::error::error_renderer_middleware), as Git errors get
+/// rendered with status 200 which would hide the 404 from this middleware
+pub(crate) fn repo_redirect_middleware<S, B>(request: ServiceRequest, service: &S) -> impl Future<Output = ActixResult<ServiceResponse<EitherBody<B>>>> + 'static
+    where S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+          S::Future: 'static,
+          B: MessageBody + 'static
+{
+    let db_pool = request.app_data::<Data<PgPool>>().cloned();
+    let future = service.call(request);
+
+    async move {
+        let response = future.await?;
+
+        if response.status() != StatusCode::NOT_FOUND {
+            return Ok(response.map_into_left_body());
+        }
+
+        let (db_pool, location) = match (db_pool, RepoPath::parse(response.request().path())) {
+            (Some(db_pool), Some(location)) => (db_pool, location),
+            _ => return Ok(response.map_into_left_body())
+        };
+
+        let target = match location.resolve(&db_pool).await {
+            Ok(Some(target)) => target,
+            Ok(None) => return Ok(response.map_into_left_body()),
+            Err(err) => {
+                warn!("Failed to look up repository redirect: {}", err);
+                return Ok(response.map_into_left_body());
+            }
+        };
+
+        let query_string = response.request().query_string();
+        let target = if query_string.is_empty() {
+            target
+        } else {
+            format!("{}?{}", target, query_string)
+        };
+
+        // Only GET and HEAD requests may be turned into GET requests by a 301, everything else needs to keep its method and body
+        let status = match *response.request().method() {
+            Method::GET | Method::HEAD => StatusCode::MOVED_PERMANENTLY,
+            _ => StatusCode::PERMANENT_REDIRECT
+        };
+
+        let redirect = HttpResponse::build(status).insert_header((LOCATION, target)).finish();
+
+        Ok(response.into_response(redirect).map_into_right_body())
+    }
+}
+
+/// Repository referenced in a request path, either `/{owner}/{name}[.git]/...` or `/api/repo/{owner}/{name}/...`
+struct RepoPath<'a> {
+    prefix: &'static str,
+    owner: &'a str,
+    name: &'a str,
+    git_suffix: bool,
+    rest: &'a str
+}
+
+impl<'a> RepoPath<'a> {
+    fn parse(path: &'a str) -> Option<RepoPath<'a>> {
+        let (prefix, path) = match path.strip_prefix("/api/repo/") {
+            Some(path) => ("/api/repo/", path),
+            None => ("/", path.strip_prefix('/')?)
+        };
+
+        let mut segments = path.splitn(3, '/');
+        let owner = segments.next().filter(|owner| !owner.is_empty())?;
+        let name = segments.next().filter(|name| !name.is_empty())?;
+        let rest = segments.next().unwrap_or_default();
+
+        let (name, git_suffix) = match name.strip_suffix(".git") {
+            Some(name) => (name, true),
+            None => (name, false)
+        };
+
+        Some(RepoPath {
+            prefix,
+            owner,
+            name,
+            git_suffix,
+            rest
+        })
+    }
+
+    /// Returns the current path of the repository if a redirect exists for this location
+    async fn resolve(&self, db_pool: &PgPool) -> Result<Option<String>> {
+        let target: Option<(String, String)> = sqlx::query_as("select users.username, repositories.name from repository_redirects \
+            inner join repositories on repositories.id = repository_redirects.repo \
+            inner join users on users.id = repositories.owner \
+            where repository_redirects.owner = (select id from users where lower(username) = lower($1) limit 1) \
+            and lower(repository_redirects.name) = lower($2) \
+            and not exists (select 1 from repositories existing where existing.owner = repository_redirects.owner and lower(existing.name) = lower($2)) \
+            limit 1")
+            .bind(self.owner)
+            .bind(self.name)
+            .fetch_optional(db_pool)
+            .await?;
+
+        Ok(target.map(|(owner, name)| {
+            let mut path = format!("{}{}/{}", self.prefix, owner, name);
+
+            if self.git_suffix {
+                path.push_str(".git");
+            }
+
+            if !self.rest.is_empty() {
+                path.push('/');
+                path.push_str(self.rest);
+            }
+
+            path
+        }))
+    }
+}
@@ -0,0 +1,92 @@
+use crate::crypto;
+
+use std::future::Future;
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error as ActixError;
+use actix_web::Result as ActixResult;
+use gitarena_common::log::json_stdout;
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, Instrument};
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Correlation id of the current request, available in the request extensions.
+/// Either taken from the `X-Request-Id` header sent by the client (or a reverse proxy in front of GitArena) or generated
+#[derive(Clone, Debug)]
+pub(crate) struct RequestId(pub(crate) String);
+
+impl RequestId {
+    fn from_request(request: &ServiceRequest) -> RequestId {
+        let inbound = request.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| is_valid(value));
+
+        match inbound {
+            Some(id) => RequestId(id.to_owned()),
+            None => RequestId(crypto::random_hex_string(16))
+        }
+    }
+}
+
+/// Inbound ids end up in logs and response headers, so only short ids consisting of a safe set of characters are accepted
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Middleware wrapping every request in a `request` span, so everything logged while handling it carries the request id,
+/// method, route and (once [WebUser](crate::user::WebUser) has been extracted) user id.
+///
+/// The request id is echoed back in the `X-Request-Id` response header. As Git clients send multiple requests per operation
+/// (such as `info/refs` followed by `git-upload-pack`), a reverse proxy can set the header to correlate them.
+///
+/// Once the response is ready, an event containing status and latency is emitted. It is logged as info if logs are written
+/// as JSON (as those are usually ingested somewhere) and as debug otherwise, to not clutter the console during development
+pub(crate) fn request_span_middleware<S, B>(request: ServiceRequest, service: &S) -> impl Future<Output = ActixResult<ServiceResponse<B>>> + 'static
+    where S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+          S::Future: 'static,
+          B: 'static
+{
+    let request_id = RequestId::from_request(&request);
+
+    let span = info_span!(
+        "request",
+        request_id = request_id.0.as_str(),
+        method = request.method().as_str(),
+        path = request.path(),
+        route = Empty,
+        user_id = Empty
+    );
+
+    // Only contains characters valid in a header value, either checked by `is_valid` or generated as hex
+    let header_value = HeaderValue::from_str(request_id.0.as_str()).ok();
+    request.extensions_mut().insert(request_id);
+
+    let start = Instant::now();
+    let future = service.call(request).instrument(span.clone());
+
+    async move {
+        let mut response = future.await?;
+
+        let route = response.request().match_pattern().unwrap_or_else(|| "unmatched".to_owned());
+        let status = response.status().as_u16();
+        let latency_ms = start.elapsed().as_millis() as u64;
+
+        span.record("route", &route.as_str());
+
+        span.in_scope(|| if json_stdout() {
+            info!(status, latency_ms, "Request completed");
+        } else {
+            debug!(status, latency_ms, "Request completed");
+        });
+
+        if let Some(value) = header_value {
+            response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+
+        Ok(response)
+    }
+}
@@ -0,0 +1,27 @@
+/// Where users end up after logging in if no (valid) redirect target has been provided
+pub(crate) const DEFAULT_REDIRECT: &str = "/";
+
+/// Returns `target` if it's safe to redirect to after logging in, otherwise [DEFAULT_REDIRECT].
+///
+/// Only relative paths on this instance are allowed. Absolute urls (`https://evil.com`) and protocol-relative ones
+/// (`//evil.com`, and `/\evil.com` as browsers treat backslashes like slashes) are rejected to prevent open redirects.
+pub(crate) fn safe_redirect(target: Option<&str>) -> &str {
+    match target {
+        Some(target) if is_safe_redirect(target) => target,
+        _ => DEFAULT_REDIRECT
+    }
+}
+
+fn is_safe_redirect(target: &str) -> bool {
+    if !target.starts_with('/') || target.starts_with("//") {
+        return false;
+    }
+
+    // Browsers strip tabs and newlines from urls, so `/\t/evil.com` would turn into `//evil.com`
+    !target.chars().any(|c| c == '\\' || c.is_control())
+}
+
+/// Turns a redirect target into the form expected by the `redirect` query parameter of the login pages (without leading slash)
+pub(crate) fn encode_redirect(target: &str) -> String {
+    url::form_urlencoded::byte_serialize(target.trim_start_matches('/').as_bytes()).collect()
+}
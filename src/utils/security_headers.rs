@@ -0,0 +1,99 @@
+use crate::crypto;
+
+use std::future::Future;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{CONTENT_SECURITY_POLICY, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS};
+use actix_web::Error as ActixError;
+use actix_web::Result as ActixResult;
+use log::warn;
+use once_cell::sync::OnceCell;
+
+/// Placeholder in the `security.csp` setting which gets replaced with the nonce of the current request
+const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+static HEADERS: OnceCell<SecurityHeaders> = OnceCell::new();
+
+tokio::task_local! {
+    static NONCE: String;
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SecurityHeaders {
+    /// Content security policy, may contain [NONCE_PLACEHOLDER]. Not sent if empty
+    pub(crate) csp: String,
+    /// Not sent if empty, for example to allow embedding GitArena in an iframe
+    pub(crate) frame_options: String,
+    /// Not sent if empty
+    pub(crate) referrer_policy: String,
+    /// `max-age` of the `Strict-Transport-Security` header in seconds. Not sent if zero
+    pub(crate) hsts_max_age: i32
+}
+
+pub(crate) fn init(headers: SecurityHeaders) {
+    let _ = HEADERS.set(headers);
+}
+
+/// Returns the nonce inline scripts of the current request need to carry (`<script nonce="{{ csp_nonce }}">`).
+/// Empty if called outside of [security_headers_middleware]
+pub(crate) fn nonce() -> String {
+    NONCE.try_with(|nonce| nonce.clone()).unwrap_or_default()
+}
+
+/// Middleware adding `Content-Security-Policy`, `X-Content-Type-Options`, `X-Frame-Options`, `Referrer-Policy` and
+/// (if enabled) `Strict-Transport-Security` to HTML responses. Other responses, such as Git smart HTTP or JSON, are left alone.
+///
+/// Every request gets its own nonce which is available to templates while the request is being handled.
+/// This needs to wrap the error renderer, so error pages rendered by it get the nonce as well
+pub(crate) fn security_headers_middleware<S, B>(request: ServiceRequest, service: &S) -> impl Future<Output = ActixResult<ServiceResponse<B>>> + 'static
+    where S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+          S::Future: 'static,
+          B: 'static
+{
+    let nonce = crypto::random_hex_string(32);
+    let future = NONCE.scope(nonce.clone(), service.call(request));
+
+    async move {
+        let mut response = future.await?;
+
+        let is_html = response.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.starts_with("text/html"));
+
+        if !is_html {
+            return Ok(response);
+        }
+
+        let settings = match HEADERS.get() {
+            Some(settings) => settings,
+            None => return Ok(response)
+        };
+
+        let headers = response.headers_mut();
+
+        headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+        let csp = settings.csp.replace(NONCE_PLACEHOLDER, nonce.as_str());
+        insert_header(headers, CONTENT_SECURITY_POLICY, csp.as_str());
+        insert_header(headers, X_FRAME_OPTIONS, settings.frame_options.as_str());
+        insert_header(headers, REFERRER_POLICY, settings.referrer_policy.as_str());
+
+        if settings.hsts_max_age > 0 {
+            insert_header(headers, STRICT_TRANSPORT_SECURITY, format!("max-age={}", settings.hsts_max_age).as_str());
+        }
+
+        Ok(response)
+    }
+}
+
+fn insert_header(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+
+    match HeaderValue::from_str(value) {
+        Ok(value) => headers.insert(name, value),
+        Err(err) => warn!("Invalid value for {} header: {}", name, err)
+    }
+}
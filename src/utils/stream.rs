@@ -0,0 +1,23 @@
+use std::io;
+
+use actix_web::web::{Bytes, BytesMut};
+use futures::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Size of the chunks read by [read_stream]
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Converts an [AsyncRead] into a [Stream] of [Bytes], suitable for streaming response bodies.
+/// The stream ends after the reader reached EOF or returned an error
+pub(crate) fn read_stream<R: AsyncRead + Unpin + 'static>(reader: R) -> impl Stream<Item = Result<Bytes, io::Error>> {
+    futures::stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut buffer = BytesMut::with_capacity(CHUNK_SIZE);
+
+        match reader.read_buf(&mut buffer).await {
+            Ok(0) => None,
+            Ok(_) => Some((Ok(buffer.freeze()), Some(reader))),
+            Err(err) => Some((Err(err), None))
+        }
+    })
+}
@@ -1,40 +1,38 @@
 use crate::config::get_setting;
-use crate::templates::plain::render;
+use crate::mail::Email;
+use crate::templates::plain;
 use crate::user::User;
-use crate::{crypto, mail, template_context, templates};
+use crate::{crypto, mail, template_context};
 
-use anyhow::{Context, Result};
-use sqlx::{Pool, Postgres};
-use tracing_unwrap::OptionExt;
+use anyhow::Result;
+use sqlx::{Postgres, Transaction};
 
-pub(crate) async fn send_verification_mail(user: &User, db_pool: &Pool<Postgres>) -> Result<()> {
+/// Length of the token sent to the user. Only its hash gets stored in the database
+pub(crate) const TOKEN_LENGTH: usize = 64;
+
+/// Queues a verification mail for `email` of `user`, invalidating previously sent links for that address.
+/// The mail is only sent once `transaction` has been committed
+pub(crate) async fn send_verification_mail(user: &User, email: &Email, transaction: &mut Transaction<'_, Postgres>) -> Result<()> {
     assert!(user.id >= 0);
+    assert_eq!(user.id, email.owner);
 
-    let hash = crypto::random_hex_string(32);
-    let mut transaction = db_pool.begin().await?;
+    let token = crypto::random_hex_string(TOKEN_LENGTH);
 
-    sqlx::query("insert into user_verifications (user_id, hash, expires) values ($1, $2, now() + interval '1 day')")
+    sqlx::query("insert into user_verifications (user_id, email_id, hash, expires) values ($1, $2, $3, now() + interval '1 day') \
+        on conflict (email_id) do update set hash = excluded.hash, expires = excluded.expires, sent_at = now()")
         .bind(&user.id)
-        .bind(&hash)
-        .execute(&mut transaction)
+        .bind(&email.id)
+        .bind(crypto::hash_token(token.as_str()))
+        .execute(&mut *transaction)
         .await?;
 
-    let domain = get_setting::<String, _>("domain", &mut transaction).await?;
-    let url = format!("{}/api/verify/{}", domain, hash);
-
-    let template = &templates::VERIFY_EMAIL.get().unwrap_or_log();
-    let body = &template.0;
-    let tags = &template.1;
+    let domain = get_setting::<String, _>("domain", &mut *transaction).await?;
+    let url = format!("{}/api/user/verify/{}", domain, token);
 
-    let subject = tags.get("subject").context("Template does not contain subject")?;
-    let email_body = render(body.to_string(), template_context!([
+    let context = template_context!([
         ("username".to_owned(), user.username.to_owned()),
         ("link".to_owned(), url)
-    ]));
-
-    mail::send_user_mail(user, subject, email_body, db_pool).await?;
-
-    transaction.commit().await?;
+    ]);
 
-    Ok(())
+    mail::send_template_to(user, email, plain::VERIFY_EMAIL, &context, &mut *transaction).await
 }
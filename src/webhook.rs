@@ -0,0 +1,220 @@
+//! Webhooks notify external services about events happening in a repository.
+//!
+//! Payloads are signed using HMAC-SHA256 with the secret of the webhook and the signature is sent in the
+//! `X-GitArena-Signature` header (`sha256=<hex>`) so receivers can verify the request originates from this instance.
+
+use crate::prelude::USER_AGENT_STR;
+use crate::repository::Repository;
+use crate::user::User;
+use crate::utils::outbound_url;
+
+use std::time::Duration;
+
+use anyhow::Result;
+use awc::ClientBuilder;
+use awc::http::header::{CONTENT_TYPE, USER_AGENT};
+use chrono::{DateTime, Local};
+use derive_more::Display;
+use log::{debug, warn};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, FromRow, PgPool, Postgres};
+use url::Url;
+
+const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", url)]
+pub(crate) struct Webhook {
+    pub(crate) id: i32,
+    pub(crate) repo: i32,
+    pub(crate) url: String,
+    #[serde(skip_serializing)]
+    pub(crate) secret: String,
+    pub(crate) events: i32,
+    pub(crate) created_at: DateTime<Local>
+}
+
+impl Webhook {
+    /// Returns all webhooks of `repo` which are subscribed to `event`
+    pub(crate) async fn all_for_event<'e, E: Executor<'e, Database = Postgres>>(repo_id: i32, event: WebhookEvents, executor: E) -> Result<Vec<Webhook>> {
+        Ok(sqlx::query_as::<_, Webhook>("select * from webhooks where repo = $1 and events & $2 = $2")
+            .bind(&repo_id)
+            .bind(event.bits())
+            .fetch_all(executor)
+            .await?)
+    }
+}
+
+#[derive(FromRow, Display, Debug, Serialize)]
+#[display(fmt = "{}", id)]
+pub(crate) struct WebhookDelivery {
+    pub(crate) id: i32,
+    pub(crate) webhook: i32,
+    pub(crate) event: String,
+    pub(crate) status_code: Option<i32>,
+    pub(crate) success: bool,
+    pub(crate) error: Option<String>,
+    pub(crate) delivered_at: DateTime<Local>
+}
+
+/// Bitflag of events a webhook is subscribed to
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub(crate) struct WebhookEvents(i32);
+
+impl WebhookEvents {
+    pub(crate) const PUSH: WebhookEvents = WebhookEvents(1 << 0);
+
+    pub(crate) const ALL: WebhookEvents = WebhookEvents((1 << 1) - 1);
+
+    /// Creates events from raw bits, dropping any unknown bits
+    pub(crate) fn from_bits(bits: i32) -> WebhookEvents {
+        WebhookEvents(bits & WebhookEvents::ALL.0)
+    }
+
+    pub(crate) fn bits(&self) -> i32 {
+        self.0
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match *self {
+            WebhookEvents::PUSH => "push",
+            _ => "unknown"
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct PushPayload {
+    pub(crate) repository: PayloadRepository,
+    /// `None` if the push used a deploy key
+    pub(crate) pusher: Option<PayloadUser>,
+    pub(crate) refs: Vec<PushedRef>
+}
+
+impl PushPayload {
+    pub(crate) fn new(repo: &Repository, owner: &str, pusher: Option<&User>, refs: Vec<PushedRef>) -> PushPayload {
+        PushPayload {
+            repository: PayloadRepository {
+                id: repo.id,
+                owner: owner.to_owned(),
+                name: repo.name.clone()
+            },
+            pusher: pusher.map(|pusher| PayloadUser {
+                id: pusher.id,
+                username: pusher.username.clone()
+            }),
+            refs
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct PayloadRepository {
+    id: i32,
+    owner: String,
+    name: String
+}
+
+#[derive(Serialize)]
+pub(crate) struct PayloadUser {
+    id: i32,
+    username: String
+}
+
+#[derive(Serialize)]
+pub(crate) struct PushedRef {
+    #[serde(rename = "ref")]
+    target_ref: String,
+    before: String,
+    after: String
+}
+
+impl PushedRef {
+    /// Creates a pushed ref. Missing object ids (created or deleted refs) are represented as the null oid like Git does
+    pub(crate) fn new(target_ref: &str, before: Option<&str>, after: Option<&str>) -> PushedRef {
+        PushedRef {
+            target_ref: target_ref.to_owned(),
+            before: before.unwrap_or(NULL_OID).to_owned(),
+            after: after.unwrap_or(NULL_OID).to_owned()
+        }
+    }
+}
+
+/// Computes the value of the `X-GitArena-Signature` header for `body`
+pub(crate) fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, body);
+
+    format!("sha256={}", hex::encode(tag.as_ref()))
+}
+
+/// Delivers `payload` to all webhooks of `repo_id` subscribed to `event` in a background task, so slow receivers do not delay the request
+pub(crate) fn deliver_in_background<T: Serialize>(repo_id: i32, event: WebhookEvents, payload: &T, db_pool: PgPool) {
+    let body = match serde_json::to_vec(payload) {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("Failed to serialize {} webhook payload for repo {}: {}", event.name(), repo_id, err);
+            return;
+        }
+    };
+
+    // awc clients are not `Send` and thus need to be spawned on the current actix runtime
+    actix_web::rt::spawn(async move {
+        if let Err(err) = deliver(repo_id, event, body, &db_pool).await {
+            warn!("Failed to deliver {} webhooks for repo {}: {}", event.name(), repo_id, err);
+        }
+    });
+}
+
+async fn check_url(url: &str) -> Result<()> {
+    outbound_url::check(&Url::parse(url)?).await
+}
+
+async fn deliver(repo_id: i32, event: WebhookEvents, body: Vec<u8>, db_pool: &PgPool) -> Result<()> {
+    let webhooks = Webhook::all_for_event(repo_id, event, db_pool).await?;
+
+    // Redirects are not followed, as their target has not been checked to be a public address
+    let client = ClientBuilder::new()
+        .add_default_header((USER_AGENT, USER_AGENT_STR))
+        .disable_redirects()
+        .finish();
+
+    for webhook in webhooks {
+        // The host may resolve to a different address than when the webhook was created
+        let (status_code, success, error) = match check_url(webhook.url.as_str()).await {
+            Ok(()) => {
+                let result = client.post(webhook.url.as_str())
+                    .timeout(Duration::from_secs(10))
+                    .insert_header((CONTENT_TYPE, "application/json"))
+                    .insert_header(("X-GitArena-Event", event.name()))
+                    .insert_header(("X-GitArena-Signature", sign(webhook.secret.as_str(), body.as_slice())))
+                    .send_body(body.clone())
+                    .await;
+
+                match result {
+                    Ok(response) => {
+                        let status = response.status();
+                        (Some(status.as_u16() as i32), status.is_success(), None)
+                    }
+                    Err(err) => (None, false, Some(err.to_string()))
+                }
+            }
+            Err(err) => (None, false, Some(err.to_string()))
+        };
+
+        sqlx::query("insert into webhook_deliveries (webhook, event, status_code, success, error) values ($1, $2, $3, $4, $5)")
+            .bind(&webhook.id)
+            .bind(event.name())
+            .bind(&status_code)
+            .bind(&success)
+            .bind(&error)
+            .execute(db_pool)
+            .await?;
+
+        debug!("Delivered {} webhook {} for repo {} (success: {})", event.name(), &webhook.id, repo_id, success);
+    }
+
+    Ok(())
+}
@@ -58,9 +58,21 @@ document.addEventListener("DOMContentLoaded", () => {
         });
 
         $("#user-popup").dropdown();
+
+        // Plain forms can't send headers, so they submit the CSRF token as form field instead
+        $("input[name='csrf_token']").val(getCookie("gitarena-csrf"));
     });
 });
 
+// Requests made using the session need to prove they originate from GitArena itself
+document.addEventListener("htmx:configRequest", (event) => {
+    const token = getCookie("gitarena-csrf");
+
+    if (token) {
+        event.detail.headers["X-CSRF-Token"] = token;
+    }
+});
+
 function displayHtmxError(event) {
     sendNotification("error", "Error occurred while sending request");
     console.error(event);
@@ -96,10 +108,8 @@ function getCookie(name) {
         }
 
         if (c.indexOf(nameEQ) === 0) {
-            return;
+            return decodeURIComponent(c.substring(nameEQ.length, c.length));
         }
-
-        c.substring(nameEQ.length,c.length);
     }
 
     return null;
@@ -1,51 +1,11 @@
-function proxyAttribute(url) {
-    if (/^data:image\//.test(url)) {
-        return url;
-    } else {
-        let hexUrl = url.split("").map(c => c.charCodeAt(0).toString(16).padStart(2, "0")).join("");
-        return `/api/proxy/${hexUrl}`;
-    }
-}
-
-function renderMarkdown(content, element) {
-    insertScript("/static/js/third_party/purify.min.js");
-
-    const HEADINGS = ["h1", "h2", "h3", "h4", "h5"];
-    let containsCode = false;
-
-    DOMPurify.addHook("afterSanitizeAttributes", (node) => {
-        if (node.tagName === "IMG") {
-            node.classList.add("ui");
-            node.classList.add("image");
-
-            if (node.hasAttribute("src")) {
-                node.setAttribute("src", proxyAttribute(node.getAttribute("src")));
-                node.setAttribute("loading", "lazy");
-            }
-        }
-
-        if (HEADINGS.includes(node.tagName.toLowerCase())) {
-            node.classList.add("ui");
-            node.classList.add("header");
-        }
+function renderMarkdown(html, element) {
+    // The server already sanitized the rendered Markdown, so it only needs to be styled here
+    element.html(html);
 
-        if (node.tagName === "CODE" && !containsCode) {
-            containsCode = true;
-        }
-    });
+    element.find("img").addClass("ui image").attr("loading", "lazy");
+    element.find("h1, h2, h3, h4, h5, h6").addClass("ui header");
 
-    insertScript("/static/js/third_party/marked.min.js");
-
-    let renderedContent = marked.parse(content);
-
-    renderedContent = DOMPurify.sanitize(renderedContent, {
-        ALLOWED_TAGS: HEADINGS.concat(["p", "ul", "ol", "li", "em", "strong", "italic", "code", "a", "blockquote", "pre", "hr", "img"]),
-        KEEP_CONTENT: true
-    });
-
-    element.html(renderedContent);
-
-    if (containsCode) {
+    if (element.find("code").length > 0) {
         insertScript("/static/js/third_party/highlight.min.js");
         insertStyleSheet("/static/css/third_party/highlight.min.css");
 
@@ -57,21 +17,18 @@ function loadReadme(username, repo, tree) {
     $.getJSON(`/api/repo/${username}/${repo}/tree/${tree}/readme`)
         .done((json) => {
             let fileName = json.file_name;
-            const loweredFileName = fileName.toLowerCase();
-            const isMarkdown = loweredFileName.endsWith(".md") || loweredFileName.endsWith(".markdown");
-
-            let content = json.content;
             let readmeElement = $("#readme");
 
-            if (isMarkdown) {
-                renderMarkdown(content, readmeElement);
-            } else {
-                insertScript("/static/js/third_party/purify.min.js");
+            insertScript("/static/js/third_party/purify.min.js");
 
-                readmeElement.html(DOMPurify.sanitize(content, {
+            if (json.html !== null) {
+                renderMarkdown(json.html, readmeElement);
+            } else {
+                readmeElement.html(DOMPurify.sanitize(json.content, {
                     ALLOWED_TAGS: [],
                     KEEP_CONTENT: true
                 }));
+                readmeElement.wrapInner("<pre></pre>");
             }
 
             $("#readme-file-name").html(DOMPurify.sanitize(fileName, {